@@ -0,0 +1,374 @@
+//! Local control socket for talking to a running daemon.
+//!
+//! The protocol is one JSON request per connection, terminated by a newline,
+//! answered by one JSON response line:
+//!
+//! ```text
+//! -> {"command":"status"}
+//! <- {"ok":true,"data":{...}}
+//! ```
+
+use crate::config::{Config, IpVersion};
+use crate::detector::ServiceHealth;
+use crate::error::{DdnsError, Result};
+use crate::lock::runtime_path;
+use crate::state::{ProviderState, State};
+use crate::updater::DetectedIps;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// How long clients wait for the daemon to answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long clients wait for the results of a [`Request::RunCycle`].
+const CYCLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A request sent to the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Report the daemon's view of addresses and providers.
+    Status,
+    /// Run an update cycle immediately.
+    TriggerUpdate,
+    /// Run an update cycle immediately and answer with its results once it
+    /// has finished.
+    RunCycle,
+    /// Suspend the cycles run every check interval and on network changes.
+    /// Cycles requested over the socket still run.
+    Pause,
+    /// Resume the cycles suspended by [`Request::Pause`].
+    Resume,
+    /// Change the time between cycles, without editing the config file.
+    SetInterval {
+        /// Seconds between cycles, within
+        /// [`check_interval_bounds`](crate::config::check_interval_bounds).
+        secs: u64,
+    },
+    /// Reload the configuration file.
+    Reload,
+    /// Return recent history entries, newest first.
+    History {
+        /// Maximum number of entries (default: 10).
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+}
+
+/// The daemon's answer to a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    /// Whether the request succeeded.
+    pub ok: bool,
+
+    /// Response payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+
+    /// Error message if the request failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    /// Create a successful response.
+    pub fn ok(data: serde_json::Value) -> Self {
+        Self {
+            ok: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    /// Create an error response.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            data: None,
+            error: Some(message.into()),
+        }
+    }
+}
+
+/// Daemon status returned by the `status` command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    /// Daemon process ID.
+    pub pid: u32,
+    /// When the daemon started.
+    pub started: DateTime<Utc>,
+    /// Last detected public IPv4 address.
+    pub ipv4: Option<IpAddr>,
+    /// Last detected public IPv6 address.
+    pub ipv6: Option<IpAddr>,
+    /// Configured providers.
+    pub providers: Vec<ProviderStatus>,
+    /// Health of the IP detection services.
+    #[serde(default)]
+    pub ip_services: BTreeMap<String, ServiceHealth>,
+    /// Whether automatic cycles are paused ([`Request::Pause`]).
+    #[serde(default)]
+    pub paused: bool,
+    /// Seconds between cycles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+}
+
+/// Provider status as seen by the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderStatus {
+    /// Provider label (its key in persistent state).
+    pub label: String,
+    /// Provider name.
+    pub name: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Whether the provider is enabled.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Address families the provider is configured for.
+    #[serde(default)]
+    pub ip_version: IpVersion,
+    /// Address of the AAAA record, for entries with `ipv6_suffix`: composed
+    /// from the last IPv6 prefix instead of the detected address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<IpAddr>,
+    /// Persistent state of the provider.
+    #[serde(flatten)]
+    pub state: ProviderState,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl DaemonStatus {
+    /// Build the status from config and persistent state.
+    pub fn new(config: &Config, state: &State, started: DateTime<Utc>) -> Self {
+        let ips = DetectedIps {
+            ipv6: state.last_ipv6,
+            ipv6_prefix: state.last_ipv6_prefix,
+            ..Default::default()
+        };
+        let providers = config
+            .providers
+            .iter()
+            .map(|p| ProviderStatus {
+                label: p.label(),
+                name: p.name().to_string(),
+                domain: p.display_name(),
+                enabled: p.enabled,
+                ip_version: p.ip_version(config.ip_version),
+                ipv6: p.ipv6_suffix.and(
+                    ips.with_suffix(p.ipv6_suffix, config.ipv6_prefix_length)
+                        .ipv6,
+                ),
+                state: state.providers.get(&p.label()).cloned().unwrap_or_default(),
+            })
+            .collect();
+
+        Self {
+            pid: std::process::id(),
+            started,
+            ipv4: state.last_ipv4,
+            ipv6: state.last_ipv6,
+            providers,
+            ip_services: state.ip_services.clone(),
+            paused: false,
+            interval_secs: Some(config.check_interval_secs),
+        }
+    }
+}
+
+/// Get the control socket path for a config.
+pub fn socket_path(config: &Config) -> Result<PathBuf> {
+    match &config.daemon.control_socket {
+        Some(path) => Ok(path.clone()),
+        None => Ok(runtime_path("rusty-dns.sock", &config.state_dir()?)),
+    }
+}
+
+/// A request received by the server, awaiting the daemon's reply.
+pub struct Command {
+    /// The request.
+    pub request: Request,
+    reply: oneshot::Sender<Response>,
+}
+
+impl Command {
+    /// Send the response back to the client.
+    pub fn reply(self, response: Response) {
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Listening control socket. The socket file is removed when dropped.
+pub struct ControlServer {
+    path: PathBuf,
+    commands: mpsc::Receiver<Command>,
+}
+
+impl ControlServer {
+    /// Bind the socket and start accepting connections.
+    ///
+    /// The caller must hold the daemon's PID file, so any existing socket
+    /// file is stale and gets replaced.
+    pub fn bind(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+
+        // Only the daemon's user may talk to it
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+        let (tx, rx) = mpsc::channel(8);
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve(stream, tx).await {
+                                tracing::debug!("Control connection failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        tracing::warn!("Control socket error: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            commands: rx,
+        })
+    }
+
+    /// Wait for the next command.
+    pub async fn recv(&mut self) -> Option<Command> {
+        self.commands.recv().await
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Handle a single client connection.
+async fn serve(stream: UnixStream, commands: mpsc::Sender<Command>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let (reply, rx) = oneshot::channel();
+            if commands.send(Command { request, reply }).await.is_err() {
+                Response::error("Daemon is shutting down")
+            } else {
+                rx.await
+                    .unwrap_or_else(|_| Response::error("Daemon dropped the request"))
+            }
+        }
+        Err(e) => Response::error(format!("Invalid request: {}", e)),
+    };
+
+    let mut out = serde_json::to_string(&response)?;
+    out.push('\n');
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Send a request to a running daemon.
+///
+/// Fails with `DdnsError::Network` if no daemon is listening.
+pub async fn request(path: &Path, request: &Request) -> Result<Response> {
+    let exchange = async {
+        let stream = UnixStream::connect(path).await?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut out = serde_json::to_string(request)?;
+        out.push('\n');
+        writer.write_all(out.as_bytes()).await?;
+
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        let response: Response = serde_json::from_str(&line)?;
+        Ok::<_, DdnsError>(response)
+    };
+
+    let timeout = match request {
+        Request::RunCycle => CYCLE_TIMEOUT,
+        _ => CLIENT_TIMEOUT,
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| DdnsError::Network("Timed out waiting for the daemon".to_string()))?
+        .map_err(|e| DdnsError::Network(format!("No running daemon at {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_format() {
+        assert_eq!(
+            serde_json::to_string(&Request::TriggerUpdate).unwrap(),
+            r#"{"command":"trigger-update"}"#
+        );
+
+        let request: Request = serde_json::from_str(r#"{"command":"history"}"#).unwrap();
+        assert_eq!(request, Request::History { limit: None });
+
+        assert_eq!(
+            serde_json::to_string(&Request::SetInterval { secs: 600 }).unwrap(),
+            r#"{"command":"set-interval","secs":600}"#
+        );
+        let request: Request = serde_json::from_str(r#"{"command":"run-cycle"}"#).unwrap();
+        assert_eq!(request, Request::RunCycle);
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let path = std::env::temp_dir().join(format!("rusty-dns-control-{}", std::process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+
+        tokio::spawn(async move {
+            while let Some(command) = server.recv().await {
+                let response = match command.request {
+                    Request::Status => Response::ok(serde_json::json!({"pid": 1})),
+                    _ => Response::error("unsupported"),
+                };
+                command.reply(response);
+            }
+        });
+
+        let response = request(&path, &Request::Status).await.unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap()["pid"], 1);
+
+        let response = request(&path, &Request::Reload).await.unwrap();
+        assert!(!response.ok);
+        assert_eq!(response.error.as_deref(), Some("unsupported"));
+    }
+}
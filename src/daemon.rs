@@ -0,0 +1,863 @@
+//! Daemon loop: update cycles every check interval and on network changes or
+//! control socket commands, until cancelled.
+
+use crate::config::{self, Config};
+use crate::detector::{IpDetector, NetworkSituation};
+use crate::error::Result;
+use crate::history;
+use crate::lock::PidFile;
+use crate::metrics::{self, Metrics};
+use crate::notify::{CycleSummary, Notifications};
+use crate::report::{CycleReport, UpdateReport};
+use crate::state::State;
+use crate::stats::CycleRecord;
+use crate::systemd;
+use crate::updater::{DetectedIps, ProviderUpdate, Stagger, UpdateOutcome, UpdatePolicy, Updater};
+use crate::upgrade::{self, BinaryWatch, UpgradeCheck};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+pub use tokio_util::sync::CancellationToken;
+
+/// Time between prunes of the history.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Something the daemon did or ran into, reported to its event handler.
+#[derive(Debug, Clone)]
+pub enum DaemonEvent {
+    /// The daemon started, running a cycle every `interval`.
+    Started { interval: Duration },
+    /// Network changes are watched (`daemon.watch_network`).
+    WatchingNetwork,
+    /// The control socket is listening at this path.
+    ControlSocket(PathBuf),
+    /// A network change triggered a cycle.
+    NetworkChanged,
+    /// A cycle was requested over the control socket.
+    UpdateTriggered,
+    /// The config file at this path was reloaded.
+    ConfigReloaded(PathBuf),
+    /// Automatic cycles were paused over the control socket.
+    Paused,
+    /// Automatic cycles were resumed over the control socket.
+    Resumed,
+    /// The time between cycles was changed over the control socket.
+    IntervalChanged(Duration),
+    /// Whether the network is behind CGNAT or double NAT, checked once
+    /// after the first cycle.
+    NetworkSituation(NetworkSituation),
+    /// No address could be detected, so the cycle updated nothing.
+    DetectionFailed(String),
+    /// A detected address differs from the last one of its family.
+    IpChanged {
+        previous: Option<IpAddr>,
+        current: IpAddr,
+    },
+    /// A record was sent to its provider (see [`ProviderUpdate::error`]).
+    RecordUpdated(Box<ProviderUpdate>),
+    /// The failure streak of the provider entry with this label ended.
+    Recovered(String),
+    /// The daemon's binary at this path was upgraded, and is executed in
+    /// place of the running daemon (see [`Daemon::with_upgrade_watch`]).
+    Upgrading(PathBuf),
+    /// A problem the daemon carries on despite.
+    Warning(String),
+    /// The daemon stopped after being cancelled.
+    Stopped,
+}
+
+type EventHandler = Box<dyn Fn(&DaemonEvent) + Send + Sync>;
+
+/// Keeps the configured records up to date until cancelled.
+pub struct Daemon {
+    config: Config,
+    config_path: Option<PathBuf>,
+    detector: IpDetector,
+    cancel: CancellationToken,
+    interval: Duration,
+    paused: bool,
+    on_event: EventHandler,
+    metrics: Mutex<Metrics>,
+    upgrade: Option<BinaryWatch>,
+}
+
+/// What the daemon does after a control command.
+enum Next {
+    /// Keep waiting for the next cycle.
+    Wait,
+    /// Run a cycle now, answering the command with its results if given.
+    RunCycle(Option<ControlCommand>),
+}
+
+impl Daemon {
+    /// Create a daemon updating the providers of `config` with the
+    /// addresses found by `detector`, until `cancel` is cancelled.
+    pub fn new(config: Config, detector: IpDetector, cancel: CancellationToken) -> Self {
+        Self {
+            interval: config.check_interval(None),
+            config,
+            config_path: None,
+            detector,
+            cancel,
+            paused: false,
+            on_event: Box::new(|_| {}),
+            metrics: Mutex::new(Metrics::new()),
+            upgrade: None,
+        }
+    }
+
+    /// Set the time between cycles (default: `check_interval_secs`).
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the config file loaded again by the control socket's reload
+    /// command, which fails without one.
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Watch the binary of `watch` between cycles and, once it is upgraded,
+    /// execute it with the same arguments in place of this process (see
+    /// [`upgrade`]). On Windows, only warn that a restart is required.
+    pub fn with_upgrade_watch(mut self, watch: BinaryWatch) -> Self {
+        self.upgrade = Some(watch);
+        self
+    }
+
+    /// Report events to `handler` (default: ignore them).
+    pub fn on_event(mut self, handler: impl Fn(&DaemonEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Box::new(handler);
+        self
+    }
+
+    /// Run cycles until cancelled, starting with one right away. A cycle in
+    /// progress is finished first.
+    ///
+    /// Fails if another daemon holds the PID file of the config.
+    pub async fn run(mut self) -> Result<()> {
+        let started = chrono::Utc::now();
+        let pid_path = match self.config.daemon.pid_file.clone() {
+            Some(path) => path,
+            None => PidFile::default_path(&self.config.state_dir()?),
+        };
+        let _pid_file = PidFile::acquire(&pid_path)?;
+
+        // Not borrowing `self` while waiting, for the command handlers
+        let cancel = self.cancel.clone();
+
+        self.emit(DaemonEvent::Started {
+            interval: self.interval,
+        });
+        let mut network_events = self.watch_network();
+        let mut control = self.start_control_server();
+        let mut notifications = Notifications::from_config(&self.config);
+        notifications.started().await;
+
+        let mut last_prune: Option<tokio::time::Instant> = None;
+        // Command waiting for the results of the next cycle
+        let mut waiting: Option<ControlCommand> = None;
+        let mut network_checked = !self.config.ip_version.includes_v4();
+        let mut upgrade_polls = self.upgrade.is_some().then(|| {
+            // Polls missed during a cycle aren't made up at once, which
+            // would leave no time for a binary being written to settle
+            let mut polls = tokio::time::interval(upgrade::POLL_INTERVAL);
+            polls.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            polls
+        });
+        loop {
+            let report = self.run_cycle(&notifications, waiting.is_none()).await;
+            if let Some(command) = waiting.take() {
+                reply_with_report(command, &report);
+            }
+            if !network_checked {
+                // Only once, as the router may be searched for
+                network_checked = true;
+                let situation = self.detector.network_situation(report.ipv4, true).await;
+                self.emit(DaemonEvent::NetworkSituation(situation));
+            }
+            self.write_metrics();
+            if last_prune.map_or(true, |at| at.elapsed() >= PRUNE_INTERVAL) {
+                self.prune_history();
+                last_prune = Some(tokio::time::Instant::now());
+            }
+
+            let finished = tokio::time::Instant::now();
+            loop {
+                // Recomputed as commands pause, resume or retime the daemon
+                let deadline = (!self.paused).then(|| finished + self.interval);
+                tokio::select! {
+                    _ = sleep_until(deadline) => break,
+                    Some(()) = next_network_event(&mut network_events) => {
+                        if !self.paused {
+                            self.emit(DaemonEvent::NetworkChanged);
+                            break;
+                        }
+                    }
+                    Some(command) = next_command(&mut control) => {
+                        match self.handle_command(command, &mut notifications, started) {
+                            Next::Wait => {}
+                            Next::RunCycle(reply) => {
+                                waiting = reply;
+                                break;
+                            }
+                        }
+                    }
+                    _ = next_poll(&mut upgrade_polls) => self.check_upgrade(),
+                    _ = cancel.cancelled() => {
+                        self.emit(DaemonEvent::Stopped);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn emit(&self, event: DaemonEvent) {
+        (self.on_event)(&event);
+    }
+
+    fn warn(&self, message: String) {
+        self.emit(DaemonEvent::Warning(message));
+    }
+
+    fn metrics(&self) -> std::sync::MutexGuard<'_, Metrics> {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write the metrics to `daemon.metrics_textfile`, if set.
+    fn write_metrics(&self) {
+        let Some(path) = &self.config.daemon.metrics_textfile else {
+            return;
+        };
+        if let Err(e) = metrics::write_textfile(path, &self.metrics()) {
+            self.warn(format!(
+                "Failed to write metrics to {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Check whether the binary was upgraded, and if so execute it in place
+    /// of this process. Only called between cycles, once the state of the
+    /// last one is saved.
+    fn check_upgrade(&mut self) {
+        let Some(watch) = &mut self.upgrade else {
+            return;
+        };
+        let path = watch.path().to_path_buf();
+        match watch.poll() {
+            UpgradeCheck::Unchanged | UpgradeCheck::Settling => {}
+            UpgradeCheck::Deferred(until) => tracing::debug!(
+                "{} was upgraded, restarting in {}s",
+                path.display(),
+                until
+                    .duration_since(std::time::SystemTime::now())
+                    .unwrap_or_default()
+                    .as_secs()
+            ),
+            UpgradeCheck::Reexec => {
+                #[cfg(unix)]
+                {
+                    self.emit(DaemonEvent::Upgrading(path.clone()));
+                    let e = upgrade::reexec(&path);
+                    self.warn(format!(
+                        "Failed to restart into {}, carrying on: {}",
+                        path.display(),
+                        e
+                    ));
+                }
+                #[cfg(not(unix))]
+                self.warn(format!(
+                    "{} was upgraded; restart rusty-dns to run the new version",
+                    path.display()
+                ));
+            }
+        }
+    }
+
+    /// Drop history beyond `history.max_entries` and
+    /// `history.retention_days`.
+    fn prune_history(&self) {
+        match history::open(&self.config).and_then(|store| store.prune()) {
+            Ok(0) => {}
+            Ok(dropped) => tracing::info!("Pruned {} old history entries", dropped),
+            Err(e) => self.warn(format!("Failed to prune history: {}", e)),
+        }
+    }
+
+    /// Keep the aggregates of a cycle in the state, for trends in `status
+    /// --verbose` (`history.max_cycles`).
+    fn record_cycle(&self, cycle: CycleRecord) {
+        let max = self.config.history.max_cycles;
+        let recorded = history::open(&self.config)
+            .and_then(|store| store.update_state(|state| state.record_cycle(cycle, max)));
+        if let Err(e) = recorded {
+            self.warn(format!("Failed to record cycle statistics: {}", e));
+        }
+    }
+
+    /// Run a single detection + update cycle, shared by the timer and
+    /// network events.
+    ///
+    /// Each address family is tracked independently: a provider is updated
+    /// when the detected address differs from the one it last published,
+    /// which also retries records whose last update failed.
+    ///
+    /// Updates are spaced out (`daemon.stagger_secs`) only if `staggered`,
+    /// as someone waits for the results of the other cycles.
+    async fn run_cycle(&self, notifications: &Notifications, staggered: bool) -> CycleReport {
+        let progress = |update: &ProviderUpdate| {
+            if matches!(update.outcome, UpdateOutcome::Attempted(_)) {
+                self.emit(DaemonEvent::RecordUpdated(Box::new(update.clone())));
+            }
+        };
+        let mut updater = Updater::new(&self.config, &self.detector)
+            .with_notifications(notifications)
+            .with_progress(&progress)
+            .with_consensus(true);
+        if let Some(spacing) = self.config.daemon.stagger().filter(|_| staggered) {
+            // Leaving the other half of the interval for the requests
+            updater = updater.with_stagger(Stagger {
+                spacing,
+                within: self.interval / 2,
+            });
+        }
+
+        let detection_started = Instant::now();
+        let detected = updater.detect().await;
+        let detection = detection_started.elapsed();
+        self.metrics().observe_detection(detection);
+        if let Err(e) = self.detector.save_health() {
+            self.warn(format!("Failed to save IP service health: {}", e));
+        }
+        let ips = match detected {
+            Ok(ips) => ips,
+            Err(e) => {
+                let error = e.to_string();
+                self.emit(DaemonEvent::DetectionFailed(error.clone()));
+                self.record_cycle(CycleRecord::new(&DetectedIps::default(), detection, &[]));
+                notifications.cycle(&CycleSummary::failed(&error)).await;
+                return CycleReport::failed(error);
+            }
+        };
+
+        let state = history::open(&self.config)
+            .and_then(|store| store.load_state())
+            .unwrap_or_else(|e| {
+                self.warn(format!("Failed to load state, starting fresh: {}", e));
+                State::default()
+            });
+
+        for ip in [ips.ipv4, ips.ipv6].into_iter().flatten() {
+            let previous = state.last_ip(&ip);
+            if previous != Some(ip) {
+                self.emit(DaemonEvent::IpChanged {
+                    previous,
+                    current: ip,
+                });
+            }
+        }
+
+        let policy = self.config.daemon.refresh_interval().map_or(
+            UpdatePolicy::OnlyIfChanged,
+            UpdatePolicy::RefreshIfOlderThan,
+        );
+        let updates = updater.update_changed(&ips, &state, policy).await;
+
+        self.metrics().record_updates(&updates);
+        self.record_cycle(CycleRecord::new(&ips, detection, &updates));
+        let mut report = CycleReport {
+            ipv4: ips.ipv4,
+            ipv6: ips.ipv6,
+            error: None,
+            updates: updates.iter().map(UpdateReport::from).collect(),
+        };
+
+        let summary = match updater.record(&ips, &updates).await {
+            Ok(recorded) => {
+                self.metrics()
+                    .record_state(&self.config, &ips, &recorded.state);
+                for label in recorded.recovered {
+                    self.emit(DaemonEvent::Recovered(label));
+                }
+                systemd::notify_status(&daemon_status(&recorded.state));
+                let (providers, groups) = updater.outcomes(&updates);
+                CycleSummary {
+                    ipv4: ips.ipv4,
+                    ipv6: ips.ipv6,
+                    providers,
+                    groups,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                let error = format!("Failed to save state: {}", e);
+                self.warn(error.clone());
+                report.error = Some(error.clone());
+                CycleSummary::failed(error)
+            }
+        };
+        notifications.cycle(&summary).await;
+        report
+    }
+
+    /// Start the network change watcher if enabled in config.
+    #[cfg(all(target_os = "linux", feature = "netlink"))]
+    fn watch_network(&self) -> Option<mpsc::Receiver<()>> {
+        if !self.config.daemon.watch_network {
+            return None;
+        }
+
+        match crate::netwatch::spawn_watcher(crate::netwatch::DEFAULT_DEBOUNCE) {
+            Ok(rx) => {
+                self.emit(DaemonEvent::WatchingNetwork);
+                Some(rx)
+            }
+            Err(e) => {
+                self.warn(format!("Network watch unavailable, polling only: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Start the network change watcher if enabled in config.
+    #[cfg(not(all(target_os = "linux", feature = "netlink")))]
+    fn watch_network(&self) -> Option<mpsc::Receiver<()>> {
+        if self.config.daemon.watch_network {
+            self.warn(
+                "daemon.watch_network requires Linux and the `netlink` feature, polling only"
+                    .to_string(),
+            );
+        }
+        None
+    }
+
+    /// Start the control socket, continuing without it on failure.
+    #[cfg(unix)]
+    fn start_control_server(&self) -> Option<crate::control::ControlServer> {
+        let path = match crate::control::socket_path(&self.config) {
+            Ok(path) => path,
+            Err(e) => {
+                self.warn(format!("Control socket unavailable: {}", e));
+                return None;
+            }
+        };
+
+        match crate::control::ControlServer::bind(&path) {
+            Ok(server) => {
+                self.emit(DaemonEvent::ControlSocket(path));
+                Some(server)
+            }
+            Err(e) => {
+                self.warn(format!(
+                    "Control socket unavailable at {}: {}",
+                    path.display(),
+                    e
+                ));
+                None
+            }
+        }
+    }
+
+    /// Start the control socket (not available on this platform).
+    #[cfg(not(unix))]
+    fn start_control_server(&self) -> Option<()> {
+        None
+    }
+
+    /// Handle a control command.
+    #[cfg(unix)]
+    fn handle_command(
+        &mut self,
+        command: ControlCommand,
+        notifications: &mut Notifications,
+        started: chrono::DateTime<chrono::Utc>,
+    ) -> Next {
+        use crate::control::{DaemonStatus, Request, Response};
+
+        match command.request.clone() {
+            Request::Status => {
+                let status = DaemonStatus {
+                    paused: self.paused,
+                    interval_secs: Some(self.interval.as_secs()),
+                    ..DaemonStatus::new(&self.config, &load_state(&self.config), started)
+                };
+                command.reply(Response::ok(
+                    serde_json::to_value(status).unwrap_or_default(),
+                ));
+                Next::Wait
+            }
+            Request::TriggerUpdate => {
+                self.emit(DaemonEvent::UpdateTriggered);
+                command.reply(Response::ok(serde_json::json!({ "triggered": true })));
+                Next::RunCycle(None)
+            }
+            Request::RunCycle => {
+                self.emit(DaemonEvent::UpdateTriggered);
+                Next::RunCycle(Some(command))
+            }
+            Request::Pause | Request::Resume => {
+                let paused = command.request == Request::Pause;
+                let changed = self.paused != paused;
+                self.paused = paused;
+                if changed {
+                    self.emit(if paused {
+                        DaemonEvent::Paused
+                    } else {
+                        DaemonEvent::Resumed
+                    });
+                }
+                command.reply(Response::ok(
+                    serde_json::json!({ "paused": paused, "changed": changed }),
+                ));
+                Next::Wait
+            }
+            Request::SetInterval { secs } => {
+                match config::check_interval_bounds(secs) {
+                    Ok(interval) => {
+                        self.interval = interval;
+                        self.emit(DaemonEvent::IntervalChanged(interval));
+                        command.reply(Response::ok(serde_json::json!({ "interval_secs": secs })));
+                    }
+                    Err(e) => command.reply(Response::error(e.to_string())),
+                }
+                Next::Wait
+            }
+            Request::Reload => {
+                let Some(path) = self.config_path.clone() else {
+                    command.reply(Response::error("No config file to reload".to_string()));
+                    return Next::Wait;
+                };
+                match Config::load_with_profile(&path, self.config.profile.as_deref()) {
+                    Ok(config) => {
+                        // Rebuilt so changed services and network settings apply
+                        self.detector = IpDetector::from_config(&config);
+                        *notifications = Notifications::from_config(&config);
+                        self.config = config;
+                        self.emit(DaemonEvent::ConfigReloaded(path));
+                        command.reply(Response::ok(serde_json::json!({ "reloaded": true })));
+                        // Staying paused, if so, with the new config
+                        if self.paused {
+                            Next::Wait
+                        } else {
+                            Next::RunCycle(None)
+                        }
+                    }
+                    Err(e) => {
+                        self.warn(format!("Failed to reload configuration: {}", e));
+                        command.reply(Response::error(e.to_string()));
+                        Next::Wait
+                    }
+                }
+            }
+            Request::History { limit } => {
+                let entries: Vec<_> = history::open(&self.config)
+                    .and_then(|store| store.entries())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .rev()
+                    .take(limit.unwrap_or(10))
+                    .collect();
+                command.reply(Response::ok(
+                    serde_json::to_value(entries).unwrap_or_default(),
+                ));
+                Next::Wait
+            }
+        }
+    }
+
+    /// Handle a control command (never called on this platform).
+    #[cfg(not(unix))]
+    fn handle_command(
+        &mut self,
+        command: ControlCommand,
+        _notifications: &mut Notifications,
+        _started: chrono::DateTime<chrono::Utc>,
+    ) -> Next {
+        match command {}
+    }
+}
+
+#[cfg(unix)]
+type ControlCommand = crate::control::Command;
+
+#[cfg(not(unix))]
+type ControlCommand = std::convert::Infallible;
+
+/// Answer a command waiting for the results of a cycle.
+#[cfg(unix)]
+fn reply_with_report(command: ControlCommand, report: &CycleReport) {
+    command.reply(crate::control::Response::ok(
+        serde_json::to_value(report).unwrap_or_default(),
+    ));
+}
+
+/// Answer a command waiting for the results of a cycle (never called on
+/// this platform).
+#[cfg(not(unix))]
+fn reply_with_report(command: ControlCommand, _report: &CycleReport) {
+    match command {}
+}
+
+/// Sleep until `deadline`, or forever without one.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next network event, or forever if watching is disabled.
+async fn next_network_event(events: &mut Option<mpsc::Receiver<()>>) -> Option<()> {
+    match events {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next tick of `polls`, or forever without one.
+async fn next_poll(polls: &mut Option<tokio::time::Interval>) {
+    match polls {
+        Some(polls) => {
+            polls.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next control command, or forever if there is no socket.
+#[cfg(unix)]
+async fn next_command(
+    control: &mut Option<crate::control::ControlServer>,
+) -> Option<ControlCommand> {
+    match control {
+        Some(server) => server.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next control command (never resolves on this platform).
+#[cfg(not(unix))]
+async fn next_command(_control: &mut Option<()>) -> Option<ControlCommand> {
+    std::future::pending().await
+}
+
+/// Build the systemd status line from provider state.
+fn daemon_status(state: &State) -> String {
+    let alerted: Vec<_> = state
+        .providers
+        .iter()
+        .filter(|(_, p)| p.alerted)
+        .map(|(key, p)| format!("{} ({} failures)", key, p.consecutive_failures))
+        .collect();
+
+    if alerted.is_empty() {
+        "Running".to_string()
+    } else {
+        format!("Failing: {}", alerted.join(", "))
+    }
+}
+
+/// Load persistent state, falling back to empty state on error.
+#[cfg(unix)]
+fn load_state(config: &Config) -> State {
+    history::open(config)
+        .and_then(|store| store.load_state())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockProvider;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_run_until_cancelled() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7"))
+            .mount(&server)
+            .await;
+        let mock = MockProvider::new("home.example");
+
+        let dir = std::env::temp_dir().join(format!("rusty-dns-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config: Config = toml::from_str(&format!(
+            r#"
+            ip_services = ["{}"]
+            min_agreement = 1
+            allow_private_ip = true
+            state_dir = "{}"
+
+            [daemon]
+            control_socket = "{}"
+            metrics_textfile = "{}"
+            "#,
+            server.uri(),
+            dir.display(),
+            dir.join("daemon.sock").display(),
+            dir.join("rusty_dns.prom").display()
+        ))
+        .unwrap();
+        config.providers = vec![mock.register("daemon-test")];
+
+        let (events, mut received) = mpsc::unbounded_channel();
+        let cancel = CancellationToken::new();
+        let daemon = Daemon::new(
+            config.clone(),
+            IpDetector::from_config(&config),
+            cancel.clone(),
+        )
+        .on_event(move |event| {
+            let _ = events.send(event.clone());
+        });
+        let running = tokio::spawn(daemon.run());
+
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        loop {
+            match received.recv().await.unwrap() {
+                DaemonEvent::RecordUpdated(update) => {
+                    assert_eq!(update.ip, ip);
+                    assert_eq!(update.error(), None);
+                    break;
+                }
+                DaemonEvent::Warning(message) | DaemonEvent::DetectionFailed(message) => {
+                    panic!("{}", message)
+                }
+                _ => {}
+            }
+        }
+
+        // A second daemon for the same config is refused
+        let second = Daemon::new(config.clone(), IpDetector::new(), CancellationToken::new());
+        assert!(second.run().await.is_err());
+
+        cancel.cancel();
+        running.await.unwrap().unwrap();
+        let mut last = None;
+        while let Ok(event) = received.try_recv() {
+            last = Some(event);
+        }
+        assert!(matches!(last, Some(DaemonEvent::Stopped)), "{:?}", last);
+
+        assert_eq!(mock.record(), Some(ip));
+        let state = State::load_from(&State::path_in(&dir)).unwrap();
+        assert_eq!(state.last_ip(&ip), Some(ip));
+        assert_eq!(state.cycles.len(), 1);
+        assert_eq!(state.cycles[0].ipv4, Some(ip));
+        assert_eq!(
+            state.cycles[0].providers["daemon-test-home.example"],
+            crate::stats::CycleOutcome::Updated
+        );
+        let metrics = std::fs::read_to_string(dir.join("rusty_dns.prom")).unwrap();
+        assert!(metrics.contains(
+            r#"rusty_dns_updates_total{label="daemon-test-home.example",provider="mock",result="success"} 1"#
+        ));
+        assert!(metrics
+            .contains(r#"rusty_dns_in_sync{label="daemon-test-home.example",provider="mock"} 1"#));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_control_commands() {
+        use crate::control::{request, Request};
+        use crate::report::CycleReport;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7"))
+            .mount(&server)
+            .await;
+        let mock = MockProvider::new("control.example");
+
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-daemon-control-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("daemon.sock");
+        let mut config: Config = toml::from_str(&format!(
+            "ip_services = [\"{}\"]\nmin_agreement = 1\nallow_private_ip = true\n\
+             state_dir = \"{}\"\n[daemon]\ncontrol_socket = \"{}\"\n",
+            server.uri(),
+            dir.display(),
+            socket.display()
+        ))
+        .unwrap();
+        config.providers = vec![mock.register("daemon-control-test")];
+        let config_path = dir.join("config.toml");
+        config.save_to(&config_path).unwrap();
+
+        let (events, mut received) = mpsc::unbounded_channel();
+        let cancel = CancellationToken::new();
+        let daemon = Daemon::new(
+            config.clone(),
+            IpDetector::from_config(&config),
+            cancel.clone(),
+        )
+        .with_interval(Duration::from_secs(3600))
+        .with_config_path(config_path)
+        .on_event(move |event| {
+            let _ = events.send(event.clone());
+        });
+        let running = tokio::spawn(daemon.run());
+        while !matches!(
+            received.recv().await.unwrap(),
+            DaemonEvent::RecordUpdated(_)
+        ) {}
+
+        let send = |request_: Request| {
+            let socket = socket.clone();
+            async move { request(&socket, &request_).await.unwrap() }
+        };
+        let status = || async { send(Request::Status).await.data.unwrap() };
+
+        let paused = send(Request::Pause).await;
+        assert_eq!(paused.data.unwrap()["changed"], true);
+        assert_eq!(status().await["paused"], true);
+        assert_eq!(status().await["interval_secs"], 3600);
+
+        let rejected = send(Request::SetInterval { secs: 5 }).await;
+        assert!(!rejected.ok);
+        assert!(rejected.error.unwrap().contains("between 30s and 86400s"));
+        assert!(send(Request::SetInterval { secs: 60 }).await.ok);
+        assert_eq!(status().await["interval_secs"], 60);
+
+        // Reloading the config keeps the daemon paused
+        assert!(send(Request::Reload).await.ok);
+        assert_eq!(status().await["paused"], true);
+
+        // Requested cycles still run, answering with their results
+        let cycle: CycleReport =
+            serde_json::from_value(send(Request::RunCycle).await.data.unwrap()).unwrap();
+        assert_eq!(cycle.ipv4, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(cycle.error, None);
+        // The record already holds the address
+        assert!(cycle.updates.is_empty());
+
+        let resumed = send(Request::Resume).await;
+        assert_eq!(resumed.data.unwrap()["changed"], true);
+        assert_eq!(status().await["paused"], false);
+
+        cancel.cancel();
+        running.await.unwrap().unwrap();
+        let mut seen = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            seen.push(event);
+        }
+        assert!(seen.iter().any(|e| matches!(e, DaemonEvent::Paused)));
+        assert!(seen
+            .iter()
+            .any(|e| matches!(e, DaemonEvent::IntervalChanged(i) if i.as_secs() == 60)));
+        assert!(seen.iter().any(|e| matches!(e, DaemonEvent::Resumed)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
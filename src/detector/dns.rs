@@ -0,0 +1,211 @@
+//! Public IP detection through DNS "whoami" queries.
+
+use super::DnsQueryType;
+use crate::error::{DdnsError, Result};
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Time allowed for a DNS server to answer.
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Ask a DNS server for the address it sees this host querying from.
+pub(super) async fn query(
+    server: &str,
+    qname: &str,
+    qtype: DnsQueryType,
+    ipv6: bool,
+) -> Result<IpAddr> {
+    tokio::time::timeout(DNS_TIMEOUT, exchange(server, qname, qtype, ipv6))
+        .await
+        .map_err(|_| DdnsError::IpDetection(format!("DNS query to {} timed out", server)))?
+}
+
+async fn exchange(server: &str, qname: &str, qtype: DnsQueryType, ipv6: bool) -> Result<IpAddr> {
+    let addr = server_addr(server, ipv6).await?;
+    let local: SocketAddr = if addr.is_ipv6() {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+
+    let request = request(query_id(), qname, qtype, ipv6)?;
+    socket.send(&encode(&request)?).await?;
+
+    let mut buffer = [0u8; 4096];
+    let len = socket.recv(&mut buffer).await?;
+    let response = Message::from_vec(&buffer[..len])
+        .map_err(|e| DdnsError::IpDetection(format!("Invalid DNS response: {}", e)))?;
+    if response.id() != request.id() {
+        return Err(DdnsError::IpDetection(
+            "DNS response doesn't match the query".to_string(),
+        ));
+    }
+    answer(&response)
+}
+
+/// Address of a DNS server (`host`, `ip` or `host:port`), preferring one of
+/// the detected family so the server sees the right source address.
+async fn server_addr(server: &str, ipv6: bool) -> Result<SocketAddr> {
+    let resolved = match server.parse::<IpAddr>() {
+        Ok(ip) => Ok(vec![SocketAddr::new(ip, 53)]),
+        Err(_) if server.contains(':') => {
+            tokio::net::lookup_host(server).await.map(Iterator::collect)
+        }
+        Err(_) => tokio::net::lookup_host((server, 53))
+            .await
+            .map(Iterator::collect),
+    };
+    let addrs: Vec<SocketAddr> =
+        resolved.map_err(|e| DdnsError::Network(format!("Cannot resolve {}: {}", server, e)))?;
+
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv6() == ipv6)
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(|| DdnsError::Network(format!("Cannot resolve {}", server)))
+}
+
+/// Build the query for a detection source.
+fn request(id: u16, qname: &str, qtype: DnsQueryType, ipv6: bool) -> Result<Message> {
+    let name = Name::from_ascii(qname)
+        .map_err(|e| DdnsError::IpDetection(format!("Invalid DNS name {}: {}", qname, e)))?;
+    let query = match qtype {
+        DnsQueryType::Address if ipv6 => Query::query(name, RecordType::AAAA),
+        DnsQueryType::Address => Query::query(name, RecordType::A),
+        DnsQueryType::ChaosTxt => {
+            let mut query = Query::query(name, RecordType::TXT);
+            query.set_query_class(DNSClass::CH);
+            query
+        }
+    };
+
+    let mut message = Message::new();
+    message
+        .set_id(id)
+        .set_recursion_desired(true)
+        .add_query(query);
+    Ok(message)
+}
+
+fn encode(message: &Message) -> Result<Vec<u8>> {
+    message
+        .to_vec()
+        .map_err(|e| DdnsError::IpDetection(format!("Cannot encode DNS query: {}", e)))
+}
+
+/// Extract the address from the first A, AAAA or TXT answer.
+fn answer(response: &Message) -> Result<IpAddr> {
+    if response.response_code() != ResponseCode::NoError {
+        return Err(DdnsError::IpDetection(format!(
+            "DNS server answered {}",
+            response.response_code()
+        )));
+    }
+
+    for record in response.answers() {
+        match record.data() {
+            Some(RData::A(a)) => return Ok(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => return Ok(IpAddr::V6(aaaa.0)),
+            Some(RData::TXT(txt)) => {
+                let text: String = txt.iter().map(|s| String::from_utf8_lossy(s)).collect();
+                return text.trim().parse().map_err(|_| {
+                    DdnsError::IpDetection(format!("Invalid IP in DNS answer: {}", text))
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Err(DdnsError::IpDetection(
+        "No address in DNS answer".to_string(),
+    ))
+}
+
+/// Unpredictable query ID, so stray packets aren't taken for the answer.
+fn query_id() -> u16 {
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish() as u16
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use hickory_proto::op::MessageType;
+    use hickory_proto::rr::rdata::{A, AAAA, TXT};
+    use hickory_proto::rr::Record;
+
+    /// Start a DNS server on localhost answering `myip.test A` with
+    /// 1.2.3.4, `myip.test AAAA` with 2a01:4f8::1 and `whoami.test TXT CH`
+    /// with 5.6.7.8.
+    pub(in crate::detector) async fn whoami_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+                let request = Message::from_vec(&buffer[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let name = query.name().clone();
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_query(query.clone());
+                match (
+                    name.to_ascii().as_str(),
+                    query.query_type(),
+                    query.query_class(),
+                ) {
+                    ("myip.test.", RecordType::A, DNSClass::IN) => {
+                        let a = RData::A(A("1.2.3.4".parse().unwrap()));
+                        response.add_answer(Record::from_rdata(name, 0, a));
+                    }
+                    ("myip.test.", RecordType::AAAA, DNSClass::IN) => {
+                        let aaaa = RData::AAAA(AAAA("2a01:4f8::1".parse().unwrap()));
+                        response.add_answer(Record::from_rdata(name, 0, aaaa));
+                    }
+                    ("whoami.test.", RecordType::TXT, DNSClass::CH) => {
+                        let txt = RData::TXT(TXT::new(vec!["5.6.7.8".to_string()]));
+                        response.add_answer(Record::from_rdata(name, 0, txt));
+                    }
+                    _ => {
+                        response.set_response_code(ResponseCode::NXDomain);
+                    }
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let server = whoami_server().await.to_string();
+
+        let ip = query(&server, "myip.test", DnsQueryType::Address, false)
+            .await
+            .unwrap();
+        assert_eq!(ip, "1.2.3.4".parse::<IpAddr>().unwrap());
+
+        let ip = query(&server, "whoami.test", DnsQueryType::ChaosTxt, false)
+            .await
+            .unwrap();
+        assert_eq!(ip, "5.6.7.8".parse::<IpAddr>().unwrap());
+
+        let err = query(&server, "unknown.test", DnsQueryType::Address, false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Non-Existent Domain"), "{}", err);
+    }
+}
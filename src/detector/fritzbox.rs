@@ -0,0 +1,200 @@
+//! WAN address detection by asking a FRITZ!Box over its unauthenticated
+//! IGD/TR-064 SOAP endpoint.
+
+use super::upnp::{element, soap_call};
+use super::Ipv6Prefix;
+use crate::error::{DdnsError, Result};
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Host queried when the source doesn't name one.
+pub(super) const DEFAULT_HOST: &str = "fritz.box";
+
+/// Port of the IGD endpoint.
+const PORT: u16 = 49000;
+
+/// Control path of the WAN IP connection service.
+const CONTROL_PATH: &str = "/igdupnp/control/WANIPConn1";
+
+const SERVICE: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+
+/// Ask a FRITZ!Box for its WAN address of a family and, for IPv6, the prefix
+/// delegated to the network (if it has one).
+pub(super) async fn query(
+    client: &reqwest::Client,
+    host: &str,
+    ipv6: bool,
+) -> Result<(IpAddr, Option<Ipv6Prefix>)> {
+    let url = control_url(host)?;
+
+    if !ipv6 {
+        let response = soap_call(client, url, SERVICE, "GetExternalIPAddress").await?;
+        return Ok((address(&response, "NewExternalIPAddress")?, None));
+    }
+
+    let response = soap_call(
+        client,
+        url.clone(),
+        SERVICE,
+        "X_AVM_DE_GetExternalIPv6Address",
+    )
+    .await?;
+    let ip = address(&response, "NewExternalIPv6Address")?;
+
+    // Older firmware and connections without delegation have no prefix
+    let prefix = match soap_call(client, url, SERVICE, "X_AVM_DE_GetIPv6Prefix").await {
+        Ok(response) => prefix(&response),
+        Err(e) => {
+            tracing::debug!("FRITZ!Box at {} reported no IPv6 prefix: {}", host, e);
+            None
+        }
+    };
+    Ok((ip, prefix))
+}
+
+/// Control URL of a FRITZ!Box given as a host, IP address or `host:port`.
+fn control_url(host: &str) -> Result<reqwest::Url> {
+    let authority = if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, PORT)
+    } else if host.rsplit_once(':').is_some_and(|(name, port)| {
+        port.parse::<u16>().is_ok() && (!name.contains(':') || name.ends_with(']'))
+    }) {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, PORT)
+    };
+    reqwest::Url::parse(&format!("http://{}{}", authority, CONTROL_PATH))
+        .map_err(|e| DdnsError::Config(format!("Invalid FRITZ!Box host {}: {}", host, e)))
+}
+
+/// Parse the address in an element of a SOAP response.
+fn address(response: &str, name: &str) -> Result<IpAddr> {
+    let text = element(response, name).unwrap_or_default().trim();
+    if text.is_empty() {
+        return Err(DdnsError::IpDetection(
+            "FRITZ!Box has no WAN address of this family".to_string(),
+        ));
+    }
+    text.parse().map_err(|_| {
+        DdnsError::IpDetection(format!("FRITZ!Box returned an invalid address: {}", text))
+    })
+}
+
+/// Parse the delegated prefix of an `X_AVM_DE_GetIPv6Prefix` response.
+fn prefix(response: &str) -> Option<Ipv6Prefix> {
+    let address: Ipv6Addr = element(response, "NewIPv6Prefix")?.trim().parse().ok()?;
+    let length = element(response, "NewPrefixLength")?.trim().parse().ok()?;
+    (length <= 128 && !address.is_unspecified()).then_some(Ipv6Prefix { address, length })
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Start a FRITZ!Box on localhost answering each action with the given
+    /// response arguments.
+    pub(in crate::detector) async fn fritzbox(actions: &[(&str, &str)]) -> MockServer {
+        let server = MockServer::start().await;
+        for (action, arguments) in actions {
+            Mock::given(method("POST"))
+                .and(path(CONTROL_PATH))
+                .and(header(
+                    "SOAPAction",
+                    format!("\"{}#{}\"", SERVICE, action).as_str(),
+                ))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    "<s:Envelope><s:Body><u:{action}Response xmlns:u=\"{}\">{}\
+                     </u:{action}Response></s:Body></s:Envelope>",
+                    SERVICE,
+                    arguments,
+                    action = action
+                )))
+                .mount(&server)
+                .await;
+        }
+        server
+    }
+
+    fn host(server: &MockServer) -> String {
+        server.address().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let server = fritzbox(&[
+            (
+                "GetExternalIPAddress",
+                "<NewExternalIPAddress>203.0.114.7</NewExternalIPAddress>",
+            ),
+            (
+                "X_AVM_DE_GetExternalIPv6Address",
+                "<NewExternalIPv6Address>2a01:4f8::1</NewExternalIPv6Address>\
+                 <NewPrefixLength>64</NewPrefixLength>",
+            ),
+            (
+                "X_AVM_DE_GetIPv6Prefix",
+                "<NewIPv6Prefix>2a01:4f8:1200::</NewIPv6Prefix>\
+                 <NewPrefixLength>56</NewPrefixLength>",
+            ),
+        ])
+        .await;
+        let client = reqwest::Client::new();
+
+        let (ip, prefix) = query(&client, &host(&server), false).await.unwrap();
+        assert_eq!(ip, "203.0.114.7".parse::<IpAddr>().unwrap());
+        assert_eq!(prefix, None);
+
+        let (ip, prefix) = query(&client, &host(&server), true).await.unwrap();
+        assert_eq!(ip, "2a01:4f8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(prefix.unwrap().to_string(), "2a01:4f8:1200::/56");
+    }
+
+    #[tokio::test]
+    async fn test_query_without_ipv6() {
+        // No IPv6 connection, and firmware without the prefix action
+        let server = fritzbox(&[(
+            "X_AVM_DE_GetExternalIPv6Address",
+            "<NewExternalIPv6Address></NewExternalIPv6Address>",
+        )])
+        .await;
+        let err = query(&reqwest::Client::new(), &host(&server), true)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no WAN address"), "{}", err);
+
+        let server = fritzbox(&[(
+            "X_AVM_DE_GetExternalIPv6Address",
+            "<NewExternalIPv6Address>2a01:4f8::1</NewExternalIPv6Address>",
+        )])
+        .await;
+        let (_, prefix) = query(&reqwest::Client::new(), &host(&server), true)
+            .await
+            .unwrap();
+        assert_eq!(prefix, None);
+    }
+
+    #[test]
+    fn test_control_url() {
+        for (host, url) in [
+            (
+                "fritz.box",
+                "http://fritz.box:49000/igdupnp/control/WANIPConn1",
+            ),
+            (
+                "192.168.178.1:8080",
+                "http://192.168.178.1:8080/igdupnp/control/WANIPConn1",
+            ),
+            (
+                "fd00::1",
+                "http://[fd00::1]:49000/igdupnp/control/WANIPConn1",
+            ),
+            (
+                "[fd00::1]:80",
+                "http://[fd00::1]/igdupnp/control/WANIPConn1",
+            ),
+        ] {
+            assert_eq!(control_url(host).unwrap().as_str(), url);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Health of detection services, used to order and quarantine them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Consecutive failures after which a service is quarantined.
+pub const QUARANTINE_AFTER: u32 = 3;
+
+/// How long a quarantined service is skipped before it's tried again.
+pub const QUARANTINE_MINUTES: i64 = 30;
+
+/// Weight of the latest outcome in the rolling averages.
+const SMOOTHING: f64 = 0.2;
+
+/// Rolling success rate and latency of one detection service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceHealth {
+    /// Number of times the service was queried.
+    pub attempts: u64,
+    /// Rolling share of successful answers, from 0 to 1 (1 until tried).
+    pub success_rate: f64,
+    /// Rolling average time of successful answers, in milliseconds.
+    pub latency_ms: u64,
+    /// Number of failures since the last success.
+    pub consecutive_failures: u32,
+    /// Until when the service is skipped after repeated failures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantined_until: Option<DateTime<Utc>>,
+}
+
+impl Default for ServiceHealth {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            success_rate: 1.0,
+            latency_ms: 0,
+            consecutive_failures: 0,
+            quarantined_until: None,
+        }
+    }
+}
+
+impl ServiceHealth {
+    /// Record the outcome of a query.
+    ///
+    /// A service failing [`QUARANTINE_AFTER`] times in a row is quarantined
+    /// for [`QUARANTINE_MINUTES`]; once that expires it is tried again, and
+    /// quarantined again right away if that attempt fails too.
+    pub fn record(&mut self, success: bool, latency_ms: u64, now: DateTime<Utc>) {
+        let outcome = if success { 1.0 } else { 0.0 };
+        self.success_rate = if self.attempts == 0 {
+            outcome
+        } else {
+            self.success_rate + SMOOTHING * (outcome - self.success_rate)
+        };
+
+        if success {
+            self.latency_ms = if self.latency_ms == 0 {
+                latency_ms
+            } else {
+                (self.latency_ms as f64 + SMOOTHING * (latency_ms as f64 - self.latency_ms as f64))
+                    .round() as u64
+            };
+            self.consecutive_failures = 0;
+            self.quarantined_until = None;
+        } else {
+            self.consecutive_failures += 1;
+            if self.consecutive_failures >= QUARANTINE_AFTER {
+                self.quarantined_until = Some(now + chrono::Duration::minutes(QUARANTINE_MINUTES));
+            }
+        }
+        self.attempts += 1;
+    }
+
+    /// Whether the service is skipped at `now`.
+    pub fn is_quarantined(&self, now: DateTime<Utc>) -> bool {
+        self.quarantined_until.is_some_and(|until| until > now)
+    }
+
+    /// Describe the health, e.g. `98% success over 52 attempts, 120 ms`.
+    pub fn summary(&self, now: DateTime<Utc>) -> String {
+        let mut summary = format!(
+            "{:.0}% success over {} attempt(s), {} ms",
+            self.success_rate * 100.0,
+            self.attempts,
+            self.latency_ms
+        );
+        if let Some(until) = self.quarantined_until.filter(|_| self.is_quarantined(now)) {
+            summary.push_str(&format!(
+                ", quarantined until {}",
+                until.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        summary
+    }
+
+    /// Success rate rounded to 10%, so services with similar rates are
+    /// ordered by latency.
+    fn rate_bucket(&self) -> u32 {
+        (self.success_rate * 10.0).round() as u32
+    }
+}
+
+/// Order services by health: quarantined ones are left out, the others are
+/// sorted by success rate (in steps of 10%), then latency, then their
+/// configured order. Services never tried count as healthy.
+///
+/// If every service is quarantined, all of them are returned in configured
+/// order so detection can still succeed.
+pub fn order<'a>(
+    services: &[&'a str],
+    health: &BTreeMap<String, ServiceHealth>,
+    now: DateTime<Utc>,
+) -> Vec<&'a str> {
+    let default = ServiceHealth::default();
+    let health_of = |service: &str| health.get(service).unwrap_or(&default);
+
+    let mut available: Vec<&str> = services
+        .iter()
+        .copied()
+        .filter(|s| !health_of(s).is_quarantined(now))
+        .collect();
+    if available.is_empty() {
+        return services.to_vec();
+    }
+
+    available.sort_by_key(|s| {
+        let health = health_of(s);
+        (std::cmp::Reverse(health.rate_bucket()), health.latency_ms)
+    });
+    available
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn test_record() {
+        let mut health = ServiceHealth::default();
+        health.record(true, 100, at(0));
+        assert_eq!(health.success_rate, 1.0);
+        assert_eq!(health.latency_ms, 100);
+
+        health.record(true, 200, at(1));
+        assert_eq!(health.latency_ms, 120);
+
+        health.record(false, 10_000, at(2));
+        assert_eq!(health.attempts, 3);
+        assert!((health.success_rate - 0.8).abs() < 1e-9);
+        assert_eq!(health.latency_ms, 120);
+        assert_eq!(health.consecutive_failures, 1);
+        assert_eq!(
+            health.summary(at(2)),
+            "80% success over 3 attempt(s), 120 ms"
+        );
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let mut health = ServiceHealth::default();
+        for minute in 0..QUARANTINE_AFTER as i64 {
+            assert!(!health.is_quarantined(at(minute)));
+            health.record(false, 10_000, at(minute));
+        }
+        assert!(health.is_quarantined(at(3)));
+        assert!(health.summary(at(3)).contains("quarantined until"));
+
+        // Re-probed once the quarantine expires, and quarantined again if it
+        // still fails
+        assert!(!health.is_quarantined(at(2 + QUARANTINE_MINUTES)));
+        health.record(false, 10_000, at(2 + QUARANTINE_MINUTES));
+        assert!(health.is_quarantined(at(3 + QUARANTINE_MINUTES)));
+
+        // A success lifts it
+        health.record(true, 80, at(2 * QUARANTINE_MINUTES + 3));
+        assert!(!health.is_quarantined(at(2 * QUARANTINE_MINUTES + 3)));
+        assert_eq!(health.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_order() {
+        let services = ["slow", "flaky", "fast", "new", "dead"];
+        let mut health = BTreeMap::new();
+        let mut record = |service: &str, outcomes: &[(bool, u64)]| {
+            let entry: &mut ServiceHealth = health.entry(service.to_string()).or_default();
+            for (minute, (success, latency)) in outcomes.iter().enumerate() {
+                entry.record(*success, *latency, at(minute as i64));
+            }
+        };
+        record("slow", &[(true, 900), (true, 900)]);
+        record("flaky", &[(true, 50), (false, 10_000), (false, 10_000)]);
+        record("fast", &[(true, 40), (true, 60)]);
+        record("dead", &[(false, 10_000); QUARANTINE_AFTER as usize]);
+
+        assert_eq!(
+            order(&services, &health, at(5)),
+            ["new", "fast", "slow", "flaky"]
+        );
+
+        // Quarantined services come back once it expires
+        assert_eq!(
+            order(&services, &health, at(QUARANTINE_MINUTES + 5)).last(),
+            Some(&"dead")
+        );
+
+        // All are tried if all are quarantined
+        assert_eq!(order(&["dead"], &health, at(5)), ["dead"]);
+    }
+}
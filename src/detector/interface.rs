@@ -0,0 +1,249 @@
+//! Public IP detection from the addresses of a local network interface.
+
+use super::is_public;
+use crate::error::{DdnsError, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Address assigned to an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct InterfaceAddr {
+    ip: IpAddr,
+    /// IPv6 privacy address, which changes over time.
+    temporary: bool,
+}
+
+/// Get the first public address of a family assigned to an interface, or,
+/// if `allow_private` is set, its first non-local one.
+pub(super) fn detect(interface: &str, ipv6: bool, allow_private: bool) -> Result<IpAddr> {
+    let addrs = addresses(interface)?;
+    if addrs.is_empty() {
+        return Err(DdnsError::IpDetection(format!(
+            "Interface {} not found or has no address",
+            interface
+        )));
+    }
+
+    pick(&addrs, ipv6, allow_private).ok_or_else(|| {
+        DdnsError::IpDetection(format!(
+            "No public {} address on {}",
+            if ipv6 { "IPv6" } else { "IPv4" },
+            interface
+        ))
+    })
+}
+
+/// Pick the first public address of a family, then, if `allow_private` is
+/// set, the first one that isn't loopback or link-local, preferring stable
+/// IPv6 addresses to temporary ones.
+fn pick(addrs: &[InterfaceAddr], ipv6: bool, allow_private: bool) -> Option<IpAddr> {
+    let stable_first = |filter: &dyn Fn(IpAddr) -> bool| {
+        let mut candidates = addrs
+            .iter()
+            .filter(|addr| addr.ip.is_ipv6() == ipv6 && filter(addr.ip));
+        let first = candidates.clone().next()?;
+        candidates
+            .find(|addr| !addr.temporary)
+            .or(Some(first))
+            .map(|addr| addr.ip)
+    };
+
+    stable_first(&is_public).or_else(|| {
+        allow_private
+            .then(|| stable_first(&|ip| !is_local(ip)))
+            .flatten()
+    })
+}
+
+/// Whether an address is only meaningful on this host or link.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_unspecified() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            ip.is_unspecified() || ip.is_loopback() || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// List the addresses assigned to an interface.
+fn addresses(interface: &str) -> Result<Vec<InterfaceAddr>> {
+    let temporary = temporary_addresses(interface);
+    Ok(local_addresses()?
+        .into_iter()
+        .filter(|(name, _)| name == interface)
+        .map(|(_, ip)| InterfaceAddr {
+            ip,
+            temporary: temporary.contains(&ip),
+        })
+        .collect())
+}
+
+/// List the addresses assigned to this host, with the name of their
+/// interface.
+#[cfg(unix)]
+pub fn local_addresses() -> Result<Vec<(String, IpAddr)>> {
+    let mut addrs = Vec::new();
+
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs allocates the list and stores it in head, which is
+    // freed below with freeifaddrs.
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let mut entry = head;
+    while !entry.is_null() {
+        // SAFETY: entry is a non-null element of the list returned by
+        // getifaddrs, whose name is a C string and whose address, if any,
+        // is a sockaddr of the family it states.
+        unsafe {
+            let ifa = &*entry;
+            entry = ifa.ifa_next;
+
+            if ifa.ifa_addr.is_null() {
+                continue;
+            }
+            let name = std::ffi::CStr::from_ptr(ifa.ifa_name);
+
+            let ip = match i32::from((*ifa.ifa_addr).sa_family) {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            addrs.push((name.to_string_lossy().into_owned(), ip));
+        }
+    }
+
+    // SAFETY: head was allocated by getifaddrs and isn't used afterwards.
+    unsafe { libc::freeifaddrs(head) };
+    Ok(addrs)
+}
+
+#[cfg(not(unix))]
+pub fn local_addresses() -> Result<Vec<(String, IpAddr)>> {
+    Err(DdnsError::IpDetection(
+        "Listing interface addresses is not supported on this platform".to_string(),
+    ))
+}
+
+/// IPv6 privacy addresses of an interface, from `/proc/net/if_inet6`.
+#[cfg(target_os = "linux")]
+fn temporary_addresses(interface: &str) -> Vec<IpAddr> {
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .map(|content| parse_if_inet6(&content, interface))
+        .unwrap_or_default()
+}
+
+/// Address flags aren't exposed by getifaddrs on this platform.
+#[cfg(not(target_os = "linux"))]
+fn temporary_addresses(_interface: &str) -> Vec<IpAddr> {
+    Vec::new()
+}
+
+/// Parse the temporary addresses of an interface from `/proc/net/if_inet6`
+/// lines (`address index prefix scope flags name`).
+#[cfg(any(target_os = "linux", test))]
+fn parse_if_inet6(content: &str, interface: &str) -> Vec<IpAddr> {
+    /// IFA_F_TEMPORARY
+    const TEMPORARY: u32 = 0x01;
+
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [address, _, _, _, flags, name] = fields[..] else {
+                return None;
+            };
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if name != interface || flags & TEMPORARY == 0 {
+                return None;
+            }
+            u128::from_str_radix(address, 16)
+                .ok()
+                .map(|bits| IpAddr::V6(Ipv6Addr::from(bits)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(ip: &str, temporary: bool) -> InterfaceAddr {
+        InterfaceAddr {
+            ip: ip.parse().unwrap(),
+            temporary,
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let addrs = [
+            addr("192.168.1.10", false),
+            addr("100.64.0.7", false),
+            addr("203.0.114.7", false),
+            addr("fe80::1", false),
+            addr("2a01:4f8::1234", true),
+            addr("2a01:4f8::1", false),
+        ];
+        assert_eq!(
+            pick(&addrs, false, false),
+            Some("203.0.114.7".parse().unwrap())
+        );
+        assert_eq!(
+            pick(&addrs, true, false),
+            Some("2a01:4f8::1".parse().unwrap())
+        );
+
+        // A temporary address is used if it's the only public one
+        assert_eq!(
+            pick(&addrs[..5], true, false),
+            Some("2a01:4f8::1234".parse().unwrap())
+        );
+        assert_eq!(pick(&addrs[..2], false, false), None);
+
+        // Private addresses are only picked when allowed, never link-local
+        assert_eq!(
+            pick(&addrs[..2], false, true),
+            Some("192.168.1.10".parse().unwrap())
+        );
+        assert_eq!(pick(&addrs[3..4], true, true), None);
+    }
+
+    #[test]
+    fn test_parse_if_inet6() {
+        let content = "\
+2a0104f8000000000000000000001234 02 40 00 01     eth0
+2a0104f8000000000000000000000001 02 40 00 00     eth0
+fe800000000000000000000000000001 02 40 20 80     eth0
+2a0104f8000000000000000000005678 03 40 00 01     wlan0
+";
+        assert_eq!(
+            parse_if_inet6(content, "eth0"),
+            vec!["2a01:4f8::1234".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_detect_loopback() {
+        // Loopback has addresses, but none of them is public
+        let name = if cfg!(target_os = "linux") {
+            "lo"
+        } else {
+            "lo0"
+        };
+        let err = detect(name, false, false).unwrap_err().to_string();
+        assert!(err.contains("No public IPv4 address"), "{}", err);
+
+        let err = detect("rusty-dns-missing0", false, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not found"), "{}", err);
+    }
+}
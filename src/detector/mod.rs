@@ -0,0 +1,1563 @@
+//! Public IP detection.
+
+use crate::config::{Config, HistoryConfig, IpVersion};
+use crate::error::{DdnsError, Result};
+use crate::history::{self, HistoryStore, JsonStore};
+use crate::http::ClientOptions;
+use crate::state::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+mod dns;
+mod fritzbox;
+mod health;
+mod interface;
+mod nat;
+mod prefix;
+mod upnp;
+
+pub use health::{ServiceHealth, QUARANTINE_AFTER, QUARANTINE_MINUTES};
+pub use interface::local_addresses;
+pub use nat::{NatKind, NetworkSituation};
+pub use prefix::{suffix_fits, Ipv6Prefix};
+#[cfg(test)]
+pub(crate) use upnp::tests::gateway as upnp_gateway;
+
+/// IP detector with multiple fallback services.
+pub struct IpDetector {
+    /// HTTP clients bound to IPv4 and IPv6, so each family is detected over
+    /// a connection of that family.
+    clients: [reqwest::Client; 2],
+    services: Vec<String>,
+    ipv6_services: Vec<String>,
+    min_agreement: usize,
+    allow_private: bool,
+    cache_ttl: Option<Duration>,
+    /// Last detection of each family (IPv4, IPv6) and when it was made.
+    cache: [Mutex<Option<(Instant, Detection)>>; 2],
+    /// Where the persistent state is kept.
+    store: Option<Arc<dyn HistoryStore>>,
+    /// Health of each service, used to order and quarantine them.
+    health: std::sync::Mutex<BTreeMap<String, ServiceHealth>>,
+}
+
+/// Create HTTP clients bound to IPv4 and IPv6 (the options' local address
+/// for its family), with `options`.
+fn clients(options: &ClientOptions) -> Result<[reqwest::Client; 2]> {
+    let client = |unspecified: IpAddr| {
+        let local = options
+            .local_address
+            .filter(|address| address.is_ipv4() == unspecified.is_ipv4())
+            .unwrap_or(unspecified);
+        options
+            .client_builder()?
+            .timeout(Duration::from_secs(10))
+            .local_address(local)
+            .build()
+            .map_err(|e| DdnsError::Network(format!("Failed to create HTTP client: {}", e)))
+    };
+    Ok([
+        client(Ipv4Addr::UNSPECIFIED.into())?,
+        client(Ipv6Addr::UNSPECIFIED.into())?,
+    ])
+}
+
+/// Number of services that must agree on an address by default.
+pub const DEFAULT_MIN_AGREEMENT: usize = 2;
+
+/// How long a cached detection is reused by default.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+impl IpDetector {
+    /// Create a new IP detector with default services.
+    pub fn new() -> Self {
+        Self::with_services(vec![
+            "https://api.ipify.org".to_string(),
+            "https://icanhazip.com".to_string(),
+            "https://ifconfig.me/ip".to_string(),
+            "https://ipecho.net/plain".to_string(),
+            "dns:opendns".to_string(),
+            "dns:cloudflare".to_string(),
+        ])
+    }
+
+    /// Create a new IP detector with custom IPv4 services (and default IPv6
+    /// services).
+    pub fn with_services(services: Vec<String>) -> Self {
+        Self {
+            clients: clients(&ClientOptions::default()).expect("Failed to create HTTP client"),
+            services,
+            ipv6_services: DEFAULT_IPV6_SERVICES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            min_agreement: DEFAULT_MIN_AGREEMENT,
+            allow_private: false,
+            cache_ttl: None,
+            cache: Default::default(),
+            store: None,
+            health: Default::default(),
+        }
+    }
+
+    /// Create an IP detector with the services, agreement and state
+    /// directory of a config (default services for a family it lists none
+    /// for), starting from the service health saved in the state file.
+    pub fn from_config(config: &Config) -> Self {
+        let mut detector = if config.ip_services.is_empty() {
+            Self::new()
+        } else {
+            Self::with_services(config.ip_services.clone())
+        };
+        if !config.ipv6_services.is_empty() {
+            detector = detector.with_ipv6_services(config.ipv6_services.clone());
+        }
+        detector.store = history::open(config).ok();
+        if let Some(state) = detector
+            .store
+            .as_ref()
+            .and_then(|store| store.load_state().ok())
+        {
+            let health = state
+                .ip_services
+                .into_iter()
+                .filter(|(service, _)| detector.knows(service))
+                .collect();
+            detector = detector.with_health(health);
+        }
+        match config
+            .network
+            .client_options(None)
+            .and_then(|options| clients(&options))
+        {
+            Ok(clients) => detector.clients = clients,
+            Err(e) => tracing::warn!("Detecting the IP without the network settings: {}", e),
+        }
+        detector
+            .with_min_agreement(config.min_agreement)
+            .with_allow_private(config.allow_private_ip)
+    }
+
+    /// Send HTTP requests (to IP services and routers) with `options`.
+    pub fn with_client_options(mut self, options: &ClientOptions) -> Result<Self> {
+        self.clients = clients(options)?;
+        Ok(self)
+    }
+
+    /// Set the services used to detect the public IPv6 address.
+    pub fn with_ipv6_services(mut self, services: Vec<String>) -> Self {
+        self.ipv6_services = services;
+        self
+    }
+
+    /// Set how many services must agree on an address in consensus mode.
+    pub fn with_min_agreement(mut self, min_agreement: usize) -> Self {
+        self.min_agreement = min_agreement;
+        self
+    }
+
+    /// Set whether private, shared (CGNAT) and other non-public addresses
+    /// are accepted from services instead of being rejected.
+    pub fn with_allow_private(mut self, allow_private: bool) -> Self {
+        self.allow_private = allow_private;
+        self
+    }
+
+    /// Reuse detections younger than `ttl` in [`IpDetector::detect_detailed`]
+    /// (and the methods built on it), so repeated calls in a short time don't
+    /// query the services again.
+    pub fn with_cache(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Set the state directory read by [`IpDetector::last_known`] and
+    /// written by [`IpDetector::save_health`], holding JSON files.
+    pub fn with_state_dir(self, state_dir: PathBuf) -> Self {
+        self.with_store(Arc::new(JsonStore::new(
+            state_dir,
+            &HistoryConfig::default(),
+        )))
+    }
+
+    /// Set the store read by [`IpDetector::last_known`] and written by
+    /// [`IpDetector::save_health`].
+    pub fn with_store(mut self, store: Arc<dyn HistoryStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Start from previously recorded service health.
+    pub fn with_health(self, health: BTreeMap<String, ServiceHealth>) -> Self {
+        *self.health.lock().unwrap() = health;
+        self
+    }
+
+    /// Get the health of the services queried so far (or loaded with
+    /// [`IpDetector::with_health`]), by service.
+    pub fn health(&self) -> BTreeMap<String, ServiceHealth> {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Save the service health in the state file, so the next detector
+    /// created by [`IpDetector::from_config`] starts from it.
+    pub fn save_health(&self) -> Result<()> {
+        let Some(store) = self.store.as_ref() else {
+            return Ok(());
+        };
+        let health = self.health();
+        store.update_state(|state| {
+            state.ip_services.retain(|service, _| self.knows(service));
+            state.ip_services.extend(health);
+        })
+    }
+
+    /// Detect public IPv4 address.
+    pub async fn detect_ipv4(&self) -> Result<IpAddr> {
+        self.detect_detailed(false).await.map(|d| d.ip)
+    }
+
+    /// Detect public IPv6 address.
+    pub async fn detect_ipv6(&self) -> Result<IpAddr> {
+        self.detect_detailed(true).await.map(|d| d.ip)
+    }
+
+    /// Detect public IPv4 address, querying all services concurrently and
+    /// returning the first address confirmed by enough of them.
+    pub async fn detect_ipv4_consensus(&self) -> Result<IpAddr> {
+        self.detect_consensus(false).await.map(|d| d.ip)
+    }
+
+    /// Detect public IPv6 address, querying all services concurrently and
+    /// returning the first address confirmed by enough of them.
+    pub async fn detect_ipv6_consensus(&self) -> Result<IpAddr> {
+        self.detect_consensus(true).await.map(|d| d.ip)
+    }
+
+    /// Detect public IPv4 address, reusing the previous detection if it's
+    /// younger than `ttl` (see [`Detection::cached`]).
+    pub async fn detect_ipv4_cached(&self, ttl: Duration) -> Result<Detection> {
+        self.detect_cached(false, ttl).await
+    }
+
+    /// Detect public IPv6 address, reusing the previous detection if it's
+    /// younger than `ttl` (see [`Detection::cached`]).
+    pub async fn detect_ipv6_cached(&self, ttl: Duration) -> Result<Detection> {
+        self.detect_cached(true, ttl).await
+    }
+
+    /// Detect the public address of a family, reusing the previous detection
+    /// if it's younger than `ttl`.
+    ///
+    /// Concurrent calls wait for a single detection instead of each querying
+    /// the services. Failures aren't cached.
+    pub async fn detect_cached(&self, ipv6: bool, ttl: Duration) -> Result<Detection> {
+        let mut cache = self.cache[usize::from(ipv6)].lock().await;
+        if let Some((at, detection)) = cache.as_ref() {
+            if at.elapsed() < ttl {
+                return Ok(Detection {
+                    cached: true,
+                    ..detection.clone()
+                });
+            }
+        }
+
+        let detection = self.detect_sequential(ipv6).await?;
+        *cache = Some((Instant::now(), detection.clone()));
+        Ok(detection)
+    }
+
+    /// Get the address of a family last confirmed by the daemon or an
+    /// update, from the persisted state, e.g. to show while detection fails.
+    pub fn last_known(&self, ipv6: bool) -> Option<LastKnownIp> {
+        let state = self.store.as_ref()?.load_state().ok()?;
+        LastKnownIp::from_state(&state, ipv6)
+    }
+
+    /// Services queried for an address family.
+    fn services(&self, ipv6: bool) -> Vec<&str> {
+        let services = if ipv6 {
+            &self.ipv6_services
+        } else {
+            &self.services
+        };
+        services.iter().map(String::as_str).collect()
+    }
+
+    /// Services of an address family in the order they should be tried,
+    /// leaving out quarantined ones (see [`health::order`]).
+    fn ordered_services(&self, ipv6: bool) -> Vec<&str> {
+        health::order(
+            &self.services(ipv6),
+            &self.health.lock().unwrap(),
+            Utc::now(),
+        )
+    }
+
+    /// Whether a service is configured for either family.
+    fn knows(&self, service: &str) -> bool {
+        self.services
+            .iter()
+            .chain(&self.ipv6_services)
+            .any(|s| s == service)
+    }
+
+    /// Update the health of the service that answered or failed a probe.
+    fn record(&self, probe: &ServiceProbe) {
+        self.health
+            .lock()
+            .unwrap()
+            .entry(probe.service.clone())
+            .or_default()
+            .record(probe.ip.is_some(), probe.latency_ms, Utc::now());
+    }
+
+    /// HTTP client connecting over an address family.
+    fn client(&self, ipv6: bool) -> &reqwest::Client {
+        &self.clients[usize::from(ipv6)]
+    }
+
+    /// Detect the public address of a family, trying each service in turn,
+    /// healthiest first, and report which service answered.
+    ///
+    /// Uses the cache if enabled with [`IpDetector::with_cache`].
+    pub async fn detect_detailed(&self, ipv6: bool) -> Result<Detection> {
+        match self.cache_ttl {
+            Some(ttl) => self.detect_cached(ipv6, ttl).await,
+            None => self.detect_sequential(ipv6).await,
+        }
+    }
+
+    async fn detect_sequential(&self, ipv6: bool) -> Result<Detection> {
+        let family = if ipv6 { "IPv6" } else { "IPv4" };
+        let mut rejected = 0;
+
+        for service in self.ordered_services(ipv6) {
+            let probe = probe(&self.clients, service, ipv6, self.allow_private).await;
+            self.record(&probe);
+            rejected += usize::from(probe.rejected.is_some());
+            match (probe.ip, probe.error) {
+                (Some(ip), _) => {
+                    tracing::debug!("Detected {} {} from {}", family, ip, service);
+                    return Ok(Detection {
+                        ip,
+                        service: probe.service,
+                        latency_ms: probe.latency_ms,
+                        cached: false,
+                        prefix: probe.prefix,
+                    });
+                }
+                (None, error) => {
+                    tracing::warn!(
+                        "{} service {} failed: {}",
+                        family,
+                        service,
+                        error.unwrap_or_default()
+                    );
+                }
+            }
+        }
+
+        if rejected > 0 {
+            return Err(DdnsError::IpDetection(format!(
+                "All {} detection services failed ({} answer(s) rejected as non-public addresses; \
+                 set allow_private_ip = true to accept them)",
+                family, rejected
+            )));
+        }
+        Err(DdnsError::IpDetection(format!(
+            "All {} detection services failed",
+            family
+        )))
+    }
+
+    /// Detect the public address of a family, querying every service
+    /// concurrently and returning as soon as `min_agreement` services (or
+    /// all of them, if fewer are configured) returned the same address.
+    ///
+    /// A single slow service doesn't delay the answer, and a minority of
+    /// services returning another address is outvoted. The detection
+    /// reports the first service that returned the agreed address.
+    /// Quarantined services aren't queried.
+    pub async fn detect_consensus(&self, ipv6: bool) -> Result<Detection> {
+        let family = if ipv6 { "IPv6" } else { "IPv4" };
+        let services = self.ordered_services(ipv6);
+        let required = self.min_agreement.clamp(1, services.len().max(1));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for service in services {
+            let clients = self.clients.clone();
+            let service = service.to_string();
+            let allow_private = self.allow_private;
+            tasks.spawn(async move { probe(&clients, &service, ipv6, allow_private).await });
+        }
+
+        let mut votes: HashMap<IpAddr, (usize, ServiceProbe)> = HashMap::new();
+        let mut answers = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            let Ok(probe) = result else { continue };
+            self.record(&probe);
+            let Some(ip) = probe.ip else {
+                tracing::warn!(
+                    "{} service {} failed: {}",
+                    family,
+                    probe.service,
+                    probe.error.as_deref().unwrap_or_default()
+                );
+                answers.push(match probe.rejected {
+                    Some(rejected) => format!("{} from {} rejected", rejected, probe.service),
+                    None => format!("{} failed", probe.service),
+                });
+                continue;
+            };
+
+            answers.push(format!("{} from {}", ip, probe.service));
+            let (count, first) = votes.entry(ip).or_insert_with(|| (0, probe.clone()));
+            *count += 1;
+            if *count >= required {
+                tracing::debug!(
+                    "Detected {} {} confirmed by {} service(s)",
+                    family,
+                    ip,
+                    count
+                );
+                return Ok(Detection {
+                    ip,
+                    service: first.service.clone(),
+                    latency_ms: first.latency_ms,
+                    cached: false,
+                    prefix: first.prefix,
+                });
+            }
+        }
+
+        if answers.is_empty() {
+            return Err(DdnsError::IpDetection(format!(
+                "No {} detection services configured",
+                family
+            )));
+        }
+        let hint = if answers.iter().any(|a| a.ends_with(" rejected")) {
+            "; non-public addresses are rejected unless allow_private_ip = true"
+        } else {
+            ""
+        };
+        Err(DdnsError::IpDetection(format!(
+            "No {} address confirmed by {} services ({}{})",
+            family,
+            required,
+            answers.join(", "),
+            hint
+        )))
+    }
+
+    /// Query every service of an address family concurrently, including
+    /// quarantined ones, returning their answers in configured order.
+    pub async fn probe_all(&self, ipv6: bool) -> Vec<ServiceProbe> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, service) in self.services(ipv6).into_iter().enumerate() {
+            let clients = self.clients.clone();
+            let service = service.to_string();
+            let allow_private = self.allow_private;
+            tasks.spawn(
+                async move { (index, probe(&clients, &service, ipv6, allow_private).await) },
+            );
+        }
+
+        let mut probes = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(probe) = result {
+                self.record(&probe.1);
+                probes.push(probe);
+            }
+        }
+        probes.sort_by_key(|(index, _)| *index);
+        probes.into_iter().map(|(_, probe)| probe).collect()
+    }
+
+    /// Ask the router for its WAN address, whether or not it's public,
+    /// using the first `upnp` or `fritzbox` IPv4 service or else discovering
+    /// the router over UPnP.
+    pub async fn query_router(&self) -> Result<IpAddr> {
+        match self.router_service() {
+            Some(DetectionSource::FritzBox(host)) => {
+                fritzbox::query(self.client(false), &host, false)
+                    .await
+                    .map(|(ip, _)| ip)
+            }
+            Some(DetectionSource::Upnp(location)) => {
+                upnp::query(self.client(false), location.as_deref()).await
+            }
+            _ => upnp::query(self.client(false), None).await,
+        }
+    }
+
+    /// Get the first `upnp` or `fritzbox` IPv4 service.
+    fn router_service(&self) -> Option<DetectionSource> {
+        self.services.iter().find_map(|s| match s.parse() {
+            Ok(source @ (DetectionSource::Upnp(_) | DetectionSource::FritzBox(_))) => Some(source),
+            _ => None,
+        })
+    }
+
+    /// Tell whether the network is behind CGNAT or double NAT (see
+    /// [`NetworkSituation::classify`]) from the detected public IPv4
+    /// address, the router's WAN address and the local interfaces.
+    ///
+    /// The router is asked if an `upnp` or `fritzbox` service is
+    /// configured, or else if `discover` is set, searching for it over UPnP
+    /// for up to two seconds.
+    pub async fn network_situation(
+        &self,
+        public: Option<IpAddr>,
+        discover: bool,
+    ) -> NetworkSituation {
+        let router = if discover || self.router_service().is_some() {
+            self.query_router().await.ok()
+        } else {
+            None
+        };
+        let interfaces = local_addresses().unwrap_or_default();
+        NetworkSituation::classify(public, router, &interfaces)
+    }
+
+    /// Detect the public IPv4 and IPv6 addresses concurrently, trying each
+    /// family's services in turn.
+    pub async fn detect_both(&self) -> DetectionReport {
+        self.detect_version(IpVersion::Both, false).await
+    }
+
+    /// Detect the public addresses of the families in `version`
+    /// concurrently, with [`IpDetector::detect_consensus`] if `consensus` is
+    /// set and [`IpDetector::detect_detailed`] otherwise.
+    ///
+    /// A family that can't be detected is reported in
+    /// [`DetectionReport::errors`] without failing the other one, so an
+    /// IPv4-only network still yields its IPv4 address.
+    pub async fn detect_version(&self, version: IpVersion, consensus: bool) -> DetectionReport {
+        let detect = |ipv6: bool| async move {
+            if ipv6 && !version.includes_v6() || !ipv6 && !version.includes_v4() {
+                None
+            } else if consensus {
+                Some(self.detect_consensus(ipv6).await)
+            } else {
+                Some(self.detect_detailed(ipv6).await)
+            }
+        };
+        let (ipv4, ipv6) = tokio::join!(detect(false), detect(true));
+
+        let mut report = DetectionReport::default();
+        for result in [ipv4, ipv6].into_iter().flatten() {
+            match result {
+                Ok(detection) => {
+                    if detection.ip.is_ipv6() {
+                        report.ipv6 = Some(detection.ip);
+                        report.ipv6_prefix = detection.prefix;
+                    } else {
+                        report.ipv4 = Some(detection.ip);
+                    }
+                    report.sources.push(detection);
+                }
+                Err(DdnsError::IpDetection(message)) => report.errors.push(message),
+                Err(e) => report.errors.push(e.to_string()),
+            }
+        }
+        report
+    }
+}
+
+/// Public addresses of both families, detected concurrently.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionReport {
+    /// Public IPv4 address, if detected.
+    pub ipv4: Option<IpAddr>,
+    /// Public IPv6 address, if detected.
+    pub ipv6: Option<IpAddr>,
+    /// Detection of each detected family, IPv4 first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<Detection>,
+    /// IPv6 prefix delegated to the network, if the IPv6 service reports it
+    /// (see [`DetectionSource::FritzBox`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix: Option<Ipv6Prefix>,
+    /// Why a requested family couldn't be detected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl DetectionReport {
+    /// Get the detection of a family.
+    pub fn source(&self, ipv6: bool) -> Option<&Detection> {
+        self.sources.iter().find(|d| d.ip.is_ipv6() == ipv6)
+    }
+
+    /// Whether any family was detected. Missing only one family, such as
+    /// IPv6 on an IPv4-only network, isn't a failure.
+    pub fn is_detected(&self) -> bool {
+        self.ipv4.is_some() || self.ipv6.is_some()
+    }
+}
+
+/// Services used to detect the public IPv6 address by default.
+pub const DEFAULT_IPV6_SERVICES: &[&str] = &[
+    "https://api6.ipify.org",
+    "https://v6.ident.me",
+    "https://ipv6.icanhazip.com",
+];
+
+/// Where a detection service gets the public address from, parsed from an
+/// `ip_services` entry.
+///
+/// `dns:opendns` and `dns:cloudflare` ask those resolvers which address the
+/// query came from, `dns:<name>@<server>` asks any server for the A/AAAA
+/// record of `<name>`, `interface:<name>` reads the public address assigned
+/// to a local interface, `upnp` asks the router for its WAN address (or
+/// `upnp:<url>` the router whose device description is at `<url>`),
+/// `fritzbox` (or `fritzbox:<host>`) asks a FRITZ!Box for it, and anything
+/// else is an HTTP(S) URL returning the address as plain text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectionSource {
+    /// HTTP(S) endpoint returning the address as plain text.
+    Https(String),
+    /// DNS query answered with the address it was sent from.
+    Dns {
+        /// Server queried (`host`, `ip` or `host:port`).
+        server: String,
+        /// Name queried.
+        qname: String,
+        /// Kind of query.
+        qtype: DnsQueryType,
+    },
+    /// Public address assigned to a local network interface.
+    Interface(String),
+    /// WAN address of a FRITZ!Box (host, IP address or `host:port`), and
+    /// the IPv6 prefix it delegates.
+    FritzBox(String),
+    /// WAN address of the router, asked over UPnP IGD, with the URL of its
+    /// device description if it isn't discovered.
+    Upnp(Option<String>),
+}
+
+/// Kind of DNS query used to detect an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsQueryType {
+    /// A record (AAAA when detecting IPv6), class IN.
+    Address,
+    /// TXT record, class CHAOS.
+    ChaosTxt,
+}
+
+impl FromStr for DetectionSource {
+    type Err = DdnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(interface) = s.strip_prefix("interface:") {
+            if interface.is_empty() {
+                return Err(DdnsError::Config(format!(
+                    "Missing interface name in IP service `{}`",
+                    s
+                )));
+            }
+            return Ok(Self::Interface(interface.to_string()));
+        }
+        if s == "fritzbox" {
+            return Ok(Self::FritzBox(fritzbox::DEFAULT_HOST.to_string()));
+        }
+        if let Some(host) = s.strip_prefix("fritzbox:") {
+            if host.is_empty() {
+                return Err(DdnsError::Config(format!(
+                    "Missing FRITZ!Box host in IP service `{}`",
+                    s
+                )));
+            }
+            return Ok(Self::FritzBox(host.to_string()));
+        }
+        if s == "upnp" {
+            return Ok(Self::Upnp(None));
+        }
+        if let Some(location) = s.strip_prefix("upnp:") {
+            return Ok(Self::Upnp(Some(location.to_string())));
+        }
+        let Some(dns) = s.strip_prefix("dns:") else {
+            return Ok(Self::Https(s.to_string()));
+        };
+
+        let (server, qname, qtype) = match dns {
+            "opendns" => (
+                "resolver1.opendns.com",
+                "myip.opendns.com",
+                DnsQueryType::Address,
+            ),
+            "cloudflare" => ("1.1.1.1", "whoami.cloudflare", DnsQueryType::ChaosTxt),
+            _ => match dns.split_once('@') {
+                Some((qname, server)) if !qname.is_empty() && !server.is_empty() => {
+                    (server, qname, DnsQueryType::Address)
+                }
+                _ => {
+                    return Err(DdnsError::Config(format!(
+                        "Unknown IP service `{}` (expected dns:opendns, dns:cloudflare or dns:<name>@<server>)",
+                        s
+                    )))
+                }
+            },
+        };
+        Ok(Self::Dns {
+            server: server.to_string(),
+            qname: qname.to_string(),
+            qtype,
+        })
+    }
+}
+
+/// Public address detected by a service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Detection {
+    /// Detected address.
+    pub ip: IpAddr,
+    /// Service that answered.
+    pub service: String,
+    /// Time the service took to answer, in milliseconds.
+    pub latency_ms: u64,
+    /// Whether this is an earlier detection reused from the cache.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// IPv6 prefix delegated to the network, if the service reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<Ipv6Prefix>,
+}
+
+/// Address last confirmed by the daemon or an update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastKnownIp {
+    /// Confirmed address.
+    pub ip: IpAddr,
+    /// When it was last confirmed (unknown for state written by older
+    /// versions).
+    pub confirmed: Option<DateTime<Utc>>,
+}
+
+impl LastKnownIp {
+    /// Get the last confirmed address of a family from persisted state.
+    pub fn from_state(state: &State, ipv6: bool) -> Option<Self> {
+        let (ip, confirmed) = if ipv6 {
+            (state.last_ipv6, state.last_ipv6_confirmed)
+        } else {
+            (state.last_ipv4, state.last_ipv4_confirmed)
+        };
+        Some(Self { ip: ip?, confirmed })
+    }
+}
+
+/// Answer of one detection service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceProbe {
+    /// Service URL.
+    pub service: String,
+    /// Address returned, if valid for the family.
+    pub ip: Option<IpAddr>,
+    /// Why the service failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Non-public address returned by the service and rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<IpAddr>,
+    /// Time the service took to answer or fail, in milliseconds.
+    pub latency_ms: u64,
+    /// IPv6 prefix delegated to the network, if the service reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<Ipv6Prefix>,
+}
+
+/// Query a single IP detection service with the client of the family
+/// (IPv4, IPv6), rejecting non-public addresses unless `allow_private` is
+/// set.
+async fn probe(
+    clients: &[reqwest::Client; 2],
+    service: &str,
+    ipv6: bool,
+    allow_private: bool,
+) -> ServiceProbe {
+    let started = Instant::now();
+    let client = &clients[usize::from(ipv6)];
+    let source = service.parse();
+    let router = matches!(
+        source,
+        Ok(DetectionSource::Upnp(_) | DetectionSource::FritzBox(_))
+    );
+    let mut prefix = None;
+    let result =
+        match source {
+            Ok(DetectionSource::Https(url)) => query(client, &url).await,
+            Ok(DetectionSource::Dns {
+                server,
+                qname,
+                qtype,
+            }) => dns::query(&server, &qname, qtype, ipv6).await,
+            Ok(DetectionSource::Interface(name)) => interface::detect(&name, ipv6, allow_private),
+            Ok(DetectionSource::Upnp(_)) if ipv6 => Err(DdnsError::IpDetection(
+                "UPnP gateways only report an IPv4 address".to_string(),
+            )),
+            Ok(DetectionSource::Upnp(location)) => upnp::query(client, location.as_deref()).await,
+            // The router reports both families however it's reached
+            Ok(DetectionSource::FritzBox(host)) => fritzbox::query(&clients[0], &host, ipv6)
+                .await
+                .map(|(ip, delegated)| {
+                    prefix = delegated;
+                    ip
+                }),
+            Err(e) => Err(e),
+        };
+    let rejected = result
+        .as_ref()
+        .ok()
+        .filter(|ip| ip.is_ipv6() == ipv6 && !allow_private && !is_public(**ip))
+        .copied();
+    let result = match result {
+        Ok(ip) if ip.is_ipv6() != ipv6 => Err(DdnsError::IpDetection(format!(
+            "Expected an {} address, got {}",
+            if ipv6 { "IPv6" } else { "IPv4" },
+            ip
+        ))),
+        Ok(ip) if rejected.is_some() && router && is_cgnat(ip) => {
+            Err(DdnsError::IpDetection(format!(
+                "Behind CGNAT: the router's WAN address {} is in the shared range \
+                 100.64.0.0/10, so the ISP's address can't be published",
+                ip
+            )))
+        }
+        Ok(ip) if rejected.is_some() && router => Err(DdnsError::IpDetection(format!(
+            "The router's WAN address {} isn't public (double NAT)",
+            ip
+        ))),
+        Ok(ip) if rejected.is_some() => Err(DdnsError::IpDetection(format!(
+            "Rejected non-public address {}",
+            ip
+        ))),
+        result => result,
+    };
+
+    ServiceProbe {
+        service: service.to_string(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        ip: result.as_ref().ok().copied(),
+        prefix: prefix.filter(|_| result.is_ok()),
+        error: result.err().map(|e| e.to_string()),
+        rejected,
+    }
+}
+
+/// Whether an address is globally routable, excluding private, shared
+/// (CGNAT), link-local, loopback, documentation and multicast ranges.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// Whether an address is in the shared address space carrier-grade NAT
+/// uses (100.64.0.0/10), meaning the ISP shares its public address.
+pub fn is_cgnat(ip: IpAddr) -> bool {
+    matches!(ip, IpAddr::V4(ip) if ip.octets()[0] == 100 && (64..128).contains(&ip.octets()[1]))
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240
+        // Shared address space (CGNAT), 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (18..20).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Link-local, fe80::/10
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Unique local, fc00::/7
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Documentation, 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // IPv4-mapped, ::ffff:0:0/96
+        || ip.to_ipv4_mapped().is_some())
+}
+
+async fn query(client: &reqwest::Client, url: &str) -> Result<IpAddr> {
+    let response = client.get(url).send().await?;
+
+    if !response.status().is_success() {
+        return Err(DdnsError::IpDetection(format!(
+            "HTTP {} from {}",
+            response.status(),
+            url
+        )));
+    }
+
+    let text = response.text().await?;
+    let ip_str = text.trim();
+
+    ip_str
+        .parse()
+        .map_err(|_| DdnsError::IpDetection(format!("Invalid IP response: {}", ip_str)))
+}
+
+impl Default for IpDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_services() {
+        let detector = IpDetector::new();
+        assert!(!detector.services.is_empty());
+    }
+
+    #[test]
+    fn test_custom_services() {
+        let detector = IpDetector::with_services(vec!["https://example.com".to_string()]);
+        assert_eq!(detector.services.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_detect_detailed_and_probe_all() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (route, response) in [
+            ("/down", ResponseTemplate::new(503)),
+            (
+                "/v6",
+                ResponseTemplate::new(200).set_body_string("2001:db8::1"),
+            ),
+            (
+                "/ok",
+                ResponseTemplate::new(200).set_body_string("1.2.3.4\n"),
+            ),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(response)
+                .mount(&server)
+                .await;
+        }
+
+        let detector = IpDetector::with_services(
+            ["/down", "/v6", "/ok"]
+                .iter()
+                .map(|route| format!("{}{}", server.uri(), route))
+                .collect(),
+        );
+
+        // Failing services and answers of the wrong family are skipped
+        let detection = detector.detect_detailed(false).await.unwrap();
+        assert_eq!(detection.ip, "1.2.3.4".parse::<IpAddr>().unwrap());
+        assert_eq!(detection.service, format!("{}/ok", server.uri()));
+
+        let probes = detector.probe_all(false).await;
+        assert_eq!(probes.len(), 3);
+        assert!(probes[0].error.as_deref().unwrap().contains("503"));
+        assert!(probes[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Expected an IPv4 address"));
+        assert_eq!(probes[1].ip, None);
+        assert_eq!(probes[2].ip, Some(detection.ip));
+        assert_eq!(probes[2].error, None);
+    }
+
+    #[tokio::test]
+    async fn test_service_health() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (route, response) in [
+            ("/down", ResponseTemplate::new(503)),
+            (
+                "/ok",
+                ResponseTemplate::new(200).set_body_string("1.2.3.4\n"),
+            ),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(response)
+                .mount(&server)
+                .await;
+        }
+        let down = format!("{}/down", server.uri());
+        let ok = format!("{}/ok", server.uri());
+
+        let detector = IpDetector::with_services(vec![down.clone(), ok.clone()]);
+        detector.detect_detailed(false).await.unwrap();
+        let health = detector.health();
+        assert_eq!(health[&down].consecutive_failures, 1);
+        assert_eq!(health[&ok].attempts, 1);
+        assert_eq!(health[&ok].success_rate, 1.0);
+
+        // The failing service is now tried last, so it isn't queried again
+        let detection = detector.detect_detailed(false).await.unwrap();
+        assert_eq!(detection.service, ok);
+        assert_eq!(detector.health()[&down].attempts, 1);
+
+        // Both services must agree, unless the failing one is quarantined
+        let err = detector.detect_consensus(false).await.unwrap_err();
+        assert!(
+            err.to_string().contains("confirmed by 2 services"),
+            "{}",
+            err
+        );
+
+        let mut quarantined = ServiceHealth::default();
+        for _ in 0..QUARANTINE_AFTER {
+            quarantined.record(false, 10_000, Utc::now());
+        }
+        let detector = IpDetector::with_services(vec![down.clone(), ok.clone()])
+            .with_health(BTreeMap::from([(down.clone(), quarantined)]));
+        let detection = detector.detect_consensus(false).await.unwrap();
+        assert_eq!(detection.service, ok);
+        assert_eq!(detector.health()[&down].attempts, 3);
+
+        // probe_all still queries quarantined services
+        detector.probe_all(false).await;
+        assert_eq!(detector.health()[&down].attempts, 4);
+
+        // The health is saved in the state file
+        let dir = std::env::temp_dir().join(format!("rusty-dns-health-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let detector = detector.with_state_dir(dir.clone());
+        detector.save_health().unwrap();
+        let state = State::load_from(&State::path_in(&dir)).unwrap();
+        assert_eq!(state.ip_services, detector.health());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detection_source_from_str() {
+        assert_eq!(
+            "https://api.ipify.org".parse::<DetectionSource>().unwrap(),
+            DetectionSource::Https("https://api.ipify.org".to_string())
+        );
+        assert_eq!(
+            "dns:opendns".parse::<DetectionSource>().unwrap(),
+            DetectionSource::Dns {
+                server: "resolver1.opendns.com".to_string(),
+                qname: "myip.opendns.com".to_string(),
+                qtype: DnsQueryType::Address,
+            }
+        );
+        assert_eq!(
+            "dns:cloudflare".parse::<DetectionSource>().unwrap(),
+            DetectionSource::Dns {
+                server: "1.1.1.1".to_string(),
+                qname: "whoami.cloudflare".to_string(),
+                qtype: DnsQueryType::ChaosTxt,
+            }
+        );
+        assert_eq!(
+            "dns:myip.test@127.0.0.1:5353"
+                .parse::<DetectionSource>()
+                .unwrap(),
+            DetectionSource::Dns {
+                server: "127.0.0.1:5353".to_string(),
+                qname: "myip.test".to_string(),
+                qtype: DnsQueryType::Address,
+            }
+        );
+        assert_eq!(
+            "interface:eth0".parse::<DetectionSource>().unwrap(),
+            DetectionSource::Interface("eth0".to_string())
+        );
+        for invalid in [
+            "dns:google",
+            "dns:myip.test@",
+            "dns:@127.0.0.1",
+            "interface:",
+            "fritzbox:",
+        ] {
+            let err = invalid.parse::<DetectionSource>().unwrap_err();
+            assert!(err.to_string().contains(invalid), "{}", err);
+        }
+        assert_eq!(
+            "upnp".parse::<DetectionSource>().unwrap(),
+            DetectionSource::Upnp(None)
+        );
+        assert_eq!(
+            "fritzbox".parse::<DetectionSource>().unwrap(),
+            DetectionSource::FritzBox("fritz.box".to_string())
+        );
+        assert_eq!(
+            "fritzbox:192.168.178.1".parse::<DetectionSource>().unwrap(),
+            DetectionSource::FritzBox("192.168.178.1".to_string())
+        );
+        assert_eq!(
+            "upnp:http://192.168.1.1:5000/rootDesc.xml"
+                .parse::<DetectionSource>()
+                .unwrap(),
+            DetectionSource::Upnp(Some("http://192.168.1.1:5000/rootDesc.xml".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_with_fritzbox_source() {
+        let server = fritzbox::tests::fritzbox(&[
+            (
+                "GetExternalIPAddress",
+                "<NewExternalIPAddress>203.0.114.7</NewExternalIPAddress>",
+            ),
+            (
+                "X_AVM_DE_GetExternalIPv6Address",
+                "<NewExternalIPv6Address>2a01:4f8::1</NewExternalIPv6Address>",
+            ),
+            (
+                "X_AVM_DE_GetIPv6Prefix",
+                "<NewIPv6Prefix>2a01:4f8:1200::</NewIPv6Prefix>\
+                 <NewPrefixLength>56</NewPrefixLength>",
+            ),
+        ])
+        .await;
+        let service = format!("fritzbox:{}", server.address());
+        let detector =
+            IpDetector::with_services(vec![service.clone()]).with_ipv6_services(vec![service]);
+
+        let report = detector.detect_both().await;
+        assert_eq!(report.ipv4, Some("203.0.114.7".parse().unwrap()));
+        assert_eq!(report.ipv6, Some("2a01:4f8::1".parse().unwrap()));
+        assert_eq!(
+            report.ipv6_prefix,
+            Some(Ipv6Prefix {
+                address: "2a01:4f8:1200::".parse().unwrap(),
+                length: 56,
+            })
+        );
+        assert_eq!(report.source(false).unwrap().prefix, None);
+    }
+
+    #[tokio::test]
+    async fn test_detect_with_upnp_source() {
+        let (_server, location) = upnp::tests::gateway("203.0.114.7").await;
+        let detector = IpDetector::with_services(vec![format!("upnp:{}", location)]);
+        let detection = detector.detect_detailed(false).await.unwrap();
+        assert_eq!(detection.ip, "203.0.114.7".parse::<IpAddr>().unwrap());
+        assert_eq!(detector.query_router().await.unwrap(), detection.ip);
+
+        let answer = probe(&detector.clients, &detection.service, true, false).await;
+        assert!(answer.error.unwrap().contains("only report an IPv4"));
+
+        // A shared or private WAN address is reported as such
+        let (_server, location) = upnp::tests::gateway("100.72.1.2").await;
+        let service = format!("upnp:{}", location);
+        let answer = probe(&detector.clients, &service, false, false).await;
+        assert_eq!(answer.rejected, Some("100.72.1.2".parse().unwrap()));
+        assert!(answer
+            .error
+            .unwrap()
+            .starts_with("IP detection failed: Behind CGNAT"));
+
+        let (_server, location) = upnp::tests::gateway("192.168.0.2").await;
+        let service = format!("upnp:{}", location);
+        let answer = probe(&detector.clients, &service, false, false).await;
+        assert!(answer.error.unwrap().contains("double NAT"));
+    }
+
+    #[test]
+    fn test_ipv6_services() {
+        let detector = IpDetector::new();
+        assert_eq!(detector.services(true), DEFAULT_IPV6_SERVICES);
+
+        let detector = IpDetector::with_services(vec!["https://api.ipify.org".to_string()])
+            .with_ipv6_services(vec!["interface:eth0".to_string()]);
+        assert_eq!(detector.services(false), ["https://api.ipify.org"]);
+        assert_eq!(detector.services(true), ["interface:eth0"]);
+
+        let config = Config {
+            ip_services: Vec::new(),
+            ipv6_services: vec!["https://v6.example".to_string()],
+            ..Config::default()
+        };
+        let detector = IpDetector::from_config(&config);
+        assert_eq!(detector.services(false), IpDetector::new().services(false));
+        assert_eq!(detector.services(true), ["https://v6.example"]);
+    }
+
+    #[tokio::test]
+    async fn test_detection_connects_over_family() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // The server only listens on IPv4 and echoes an IPv6 address, so
+        // only an IPv6 detection over IPv4 could get an answer
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2a01:4f8::1"))
+            .mount(&server)
+            .await;
+
+        let detector = IpDetector::with_services(Vec::new())
+            .with_ipv6_services(vec![format!("{}/ip", server.uri())]);
+        let probes = detector.probe_all(true).await;
+        assert_eq!(probes[0].ip, None);
+        assert!(probes[0].error.is_some());
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        use crate::http::{ClientOptions, DEFAULT_USER_AGENT};
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (user_agent, ip) in [
+            (DEFAULT_USER_AGENT, "203.0.114.7"),
+            ("custom", "203.0.114.8"),
+        ] {
+            Mock::given(method("GET"))
+                .and(header("user-agent", user_agent))
+                .respond_with(ResponseTemplate::new(200).set_body_string(ip))
+                .mount(&server)
+                .await;
+        }
+
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+        assert_eq!(
+            detector.detect_ipv4().await.unwrap().to_string(),
+            "203.0.114.7"
+        );
+
+        let detector = detector
+            .with_client_options(&ClientOptions {
+                user_agent: Some("custom".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            detector.detect_ipv4().await.unwrap().to_string(),
+            "203.0.114.8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_both() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+        let http = format!("{}/ip", server.uri());
+        let dns = format!("dns:myip.test@{}", dns::tests::whoami_server().await);
+
+        // Only IPv4 is available: the IPv6 service can't be reached over IPv6
+        let detector =
+            IpDetector::with_services(vec![http.clone()]).with_ipv6_services(vec![http.clone()]);
+        let report = detector.detect_both().await;
+        assert!(report.is_detected());
+        assert_eq!(report.ipv4, Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(report.ipv6, None);
+        assert_eq!(report.sources.len(), 1);
+        assert_eq!(report.source(false).unwrap().service, http);
+        assert_eq!(report.errors, ["All IPv6 detection services failed"]);
+
+        // Each family is reported with the service that answered for it, even
+        // when they answer for different networks
+        let detector = IpDetector::with_services(vec![http.clone()])
+            .with_ipv6_services(vec![http.clone(), dns.clone()]);
+        let report = detector.detect_both().await;
+        assert_eq!(report.ipv4, Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(report.ipv6, Some("2a01:4f8::1".parse().unwrap()));
+        assert_eq!(report.source(false).unwrap().service, http);
+        assert_eq!(report.source(true).unwrap().service, dns);
+        assert!(report.errors.is_empty());
+
+        // Only the requested families are detected, and a missing one is
+        // reported when it's the only one
+        let report = detector.detect_version(IpVersion::V4, true).await;
+        assert_eq!(report.ipv6, None);
+        assert!(report.errors.is_empty());
+        let detector = IpDetector::with_services(Vec::new()).with_ipv6_services(vec![http]);
+        let report = detector.detect_version(IpVersion::V6, false).await;
+        assert!(!report.is_detected());
+        assert_eq!(report.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_detect_with_dns_source() {
+        let server = dns::tests::whoami_server().await;
+        let detector = IpDetector::with_services(vec![
+            "dns:nothing.test@127.0.0.1:1".to_string(),
+            format!("dns:unknown.test@{}", server),
+            format!("dns:myip.test@{}", server),
+        ]);
+
+        let detection = detector.detect_detailed(false).await.unwrap();
+        assert_eq!(detection.ip, "1.2.3.4".parse::<IpAddr>().unwrap());
+        assert_eq!(detection.service, format!("dns:myip.test@{}", server));
+
+        let probes = detector.probe_all(false).await;
+        assert_eq!(probes.len(), 3);
+        assert!(probes[0].error.is_some());
+        assert!(probes[1].error.is_some());
+        assert_eq!(probes[2].ip, Some(detection.ip));
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["203.0.114.1", "8.8.8.8", "100.128.0.1", "2a01:4f8::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.10",
+            "100.64.0.1",
+            "100.127.255.254",
+            "169.254.1.1",
+            "127.0.0.1",
+            "0.0.0.0",
+            "192.0.2.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "fe80::1",
+            "fd00::1",
+            "::1",
+            "2001:db8::1",
+            "::ffff:8.8.8.8",
+            "ff02::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_non_public() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (route, ip) in [
+            ("/proxy", "10.1.2.3"),
+            ("/cgnat", "100.64.0.9"),
+            ("/ok", "1.2.3.4"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(ResponseTemplate::new(200).set_body_string(ip))
+                .mount(&server)
+                .await;
+        }
+        let detector = |routes: &[&str]| {
+            IpDetector::with_services(
+                routes
+                    .iter()
+                    .map(|route| format!("{}{}", server.uri(), route))
+                    .collect(),
+            )
+        };
+
+        // Rejected answers are skipped
+        let detection = detector(&["/proxy", "/ok"])
+            .detect_detailed(false)
+            .await
+            .unwrap();
+        assert_eq!(detection.ip, "1.2.3.4".parse::<IpAddr>().unwrap());
+
+        let probes = detector(&["/proxy", "/ok"]).probe_all(false).await;
+        assert_eq!(probes[0].ip, None);
+        assert_eq!(probes[0].rejected, Some("10.1.2.3".parse().unwrap()));
+        assert!(probes[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Rejected non-public address 10.1.2.3"));
+        assert_eq!(probes[1].rejected, None);
+
+        let err = detector(&["/proxy", "/cgnat"])
+            .detect_ipv4()
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("2 answer(s) rejected"), "{}", err);
+
+        let err = detector(&["/proxy", "/cgnat"])
+            .detect_ipv4_consensus()
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("10.1.2.3 from"), "{}", err);
+        assert!(err.contains("allow_private_ip"), "{}", err);
+
+        // Unless private addresses are allowed
+        assert_eq!(
+            detector(&["/proxy", "/ok"])
+                .with_allow_private(true)
+                .detect_ipv4()
+                .await
+                .unwrap(),
+            "10.1.2.3".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_cached() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1.2.3.4")
+                    .set_delay(Duration::from_millis(50)),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        // Concurrent calls share one detection
+        let ttl = Duration::from_secs(60);
+        let (first, second) = tokio::join!(
+            detector.detect_ipv4_cached(ttl),
+            detector.detect_ipv4_cached(ttl)
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_ne!(first.cached, second.cached);
+        assert_eq!(first.ip, second.ip);
+
+        // Expired entries are detected again
+        let detection = detector.detect_ipv4_cached(Duration::ZERO).await.unwrap();
+        assert!(!detection.cached);
+
+        // The cache applies to detect_detailed when enabled
+        let detector = detector.with_cache(ttl);
+        assert!(detector.detect_detailed(false).await.unwrap().cached);
+        assert!(detector.detect_ipv4_cached(ttl).await.unwrap().cached);
+    }
+
+    #[test]
+    fn test_last_known() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-last-known-{}", std::process::id()));
+        let detector = IpDetector::new().with_state_dir(dir.clone());
+        assert_eq!(detector.last_known(false), None);
+
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let mut state = State::default();
+        state.set_last_ip(ip);
+        state.save_to(&State::path_in(&dir)).unwrap();
+
+        let last = detector.last_known(false).unwrap();
+        assert_eq!(last.ip, ip);
+        assert!(last.confirmed.is_some());
+        assert_eq!(detector.last_known(true), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_detect_consensus() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let answer = |ip: &str, delay_ms: u64| {
+            ResponseTemplate::new(200)
+                .set_body_string(ip)
+                .set_delay(Duration::from_millis(delay_ms))
+        };
+        for (route, response) in [
+            ("/edge", answer("9.9.9.9", 0)),
+            ("/a", answer("1.2.3.4", 100)),
+            ("/b", answer("1.2.3.4", 100)),
+            ("/hung", answer("1.2.3.4", 10_000)),
+            ("/down", ResponseTemplate::new(503)),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(response)
+                .mount(&server)
+                .await;
+        }
+        let detector = |routes: &[&str]| {
+            IpDetector::with_services(
+                routes
+                    .iter()
+                    .map(|route| format!("{}{}", server.uri(), route))
+                    .collect(),
+            )
+        };
+        let expected: IpAddr = "1.2.3.4".parse().unwrap();
+
+        // The first answer is trusted by the sequential method, but outvoted
+        // here, without waiting for the hung service
+        let detector_all = detector(&["/hung", "/down", "/edge", "/a", "/b"]);
+        let started = Instant::now();
+        let detection = detector_all.detect_consensus(false).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(detection.ip, expected);
+        assert!(detection.service.ends_with("/a") || detection.service.ends_with("/b"));
+        assert_eq!(
+            detector(&["/edge", "/a", "/b"])
+                .detect_ipv4()
+                .await
+                .unwrap(),
+            "9.9.9.9".parse::<IpAddr>().unwrap()
+        );
+
+        // A single service is trusted on its own
+        assert_eq!(
+            detector(&["/a"]).detect_ipv4_consensus().await.unwrap(),
+            expected
+        );
+        assert_eq!(
+            detector(&["/edge", "/a"])
+                .with_min_agreement(1)
+                .detect_ipv4_consensus()
+                .await
+                .unwrap(),
+            "9.9.9.9".parse::<IpAddr>().unwrap()
+        );
+
+        let err = detector(&["/edge", "/a", "/down"])
+            .detect_ipv4_consensus()
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("No IPv4 address confirmed by 2 services"),
+            "{}",
+            err
+        );
+        assert!(err.contains(&format!("9.9.9.9 from {}/edge", server.uri())));
+        assert!(err.contains(&format!("{}/down failed", server.uri())));
+    }
+}
@@ -0,0 +1,277 @@
+//! Telling CGNAT and double NAT apart from a network that inbound
+//! connections can reach, by checking the address ranges of the router's
+//! WAN address or of the local interfaces, and comparing them with the
+//! detected public address.
+
+use super::{is_cgnat, is_public};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// How the network reaches the Internet over IPv4, as far as records
+/// pointing to the public address go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatKind {
+    /// The public address is the router's or this host's own, so records
+    /// pointing to it reach the network (through forwarded ports behind a
+    /// router).
+    Reachable,
+    /// Carrier-grade NAT: the address the ISP gave is in 100.64.0.0/10, so
+    /// the public address is shared with other customers.
+    Cgnat,
+    /// The router's WAN address is private, so another router sits between
+    /// it and the Internet.
+    DoubleNat,
+    /// The router's or this host's public address differs from the
+    /// detected one, e.g. as traffic leaves through a VPN or proxy.
+    Mismatch,
+    /// No router reported its WAN address and no interface holds a telling
+    /// address.
+    Unknown,
+}
+
+/// CGNAT or double NAT check, see [`NetworkSituation::classify`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSituation {
+    /// What was found.
+    pub kind: NatKind,
+    /// Address compared with the public one: the router's WAN address, or
+    /// the address of [`NetworkSituation::interface`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    /// Local interface holding `address`, if it isn't the router's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Detected public IPv4 address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public: Option<IpAddr>,
+}
+
+impl NetworkSituation {
+    /// Classify the network from the detected public IPv4 address, the WAN
+    /// address the router reported (if any) and the addresses of the local
+    /// interfaces.
+    ///
+    /// The router's WAN address tells the most, so it's used whenever
+    /// known. Otherwise an interface holding the public address (this host
+    /// is the router), a shared address (e.g. a mobile connection), or
+    /// another public address (a VPN) is looked for. A host behind a
+    /// router that didn't answer can't tell, as its own address is private
+    /// either way.
+    pub fn classify(
+        public: Option<IpAddr>,
+        router: Option<IpAddr>,
+        interfaces: &[(String, IpAddr)],
+    ) -> Self {
+        let public = public.filter(IpAddr::is_ipv4);
+        let found = |kind, address, interface: Option<&String>| Self {
+            kind,
+            address: Some(address),
+            interface: interface.cloned(),
+            public,
+        };
+
+        if let Some(wan) = router.filter(IpAddr::is_ipv4) {
+            let kind = if is_cgnat(wan) {
+                NatKind::Cgnat
+            } else if !is_public(wan) {
+                NatKind::DoubleNat
+            } else if public.is_some_and(|ip| ip != wan) {
+                NatKind::Mismatch
+            } else {
+                NatKind::Reachable
+            };
+            return found(kind, wan, None);
+        }
+
+        let addresses: Vec<_> = interfaces.iter().filter(|(_, ip)| ip.is_ipv4()).collect();
+        if let Some((name, ip)) = addresses.iter().find(|(_, ip)| Some(*ip) == public) {
+            return found(NatKind::Reachable, *ip, Some(name));
+        }
+        if let Some((name, ip)) = addresses.iter().find(|(_, ip)| is_cgnat(*ip)) {
+            return found(NatKind::Cgnat, *ip, Some(name));
+        }
+        if let Some((name, ip)) = addresses.iter().find(|(_, ip)| is_public(*ip)) {
+            if public.is_some() {
+                return found(NatKind::Mismatch, *ip, Some(name));
+            }
+        }
+
+        Self {
+            kind: NatKind::Unknown,
+            address: None,
+            interface: None,
+            public,
+        }
+    }
+
+    /// Whether records pointing to the public address may not reach this
+    /// network.
+    pub fn is_problem(&self) -> bool {
+        matches!(
+            self.kind,
+            NatKind::Cgnat | NatKind::DoubleNat | NatKind::Mismatch
+        )
+    }
+
+    /// Suggested next step for a problem.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self.kind {
+            NatKind::Cgnat => Some(
+                "Ask the ISP for a public IPv4 address, or publish IPv6 only with `ip_version = \"v6\"`",
+            ),
+            NatKind::DoubleNat => {
+                Some("Forward the ports on the upstream router too, or put it in bridge mode")
+            }
+            NatKind::Mismatch => Some("Check whether traffic leaves through a VPN or proxy"),
+            NatKind::Reachable | NatKind::Unknown => None,
+        }
+    }
+
+    /// Describe the compared address, e.g. "the router's WAN address
+    /// 100.72.1.2".
+    fn subject(&self) -> String {
+        let address = self.address.map(|ip| ip.to_string()).unwrap_or_default();
+        match &self.interface {
+            Some(interface) => format!("the address {} of {}", address, interface),
+            None => format!("the router's WAN address {}", address),
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkSituation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            NatKind::Reachable => write!(f, "{} is public", capitalize(&self.subject())),
+            NatKind::Cgnat => write!(
+                f,
+                "Behind CGNAT: {} is in 100.64.0.0/10, so the public address is shared with \
+                 other customers and records pointing to it can't reach this network",
+                self.subject()
+            ),
+            NatKind::DoubleNat => write!(
+                f,
+                "Behind double NAT: {} isn't public, so connections to the public address \
+                 stop at the upstream router",
+                self.subject()
+            ),
+            NatKind::Mismatch => write!(
+                f,
+                "{} differs from the detected public IP {}",
+                capitalize(&self.subject()),
+                self.public.map(|ip| ip.to_string()).unwrap_or_default()
+            ),
+            NatKind::Unknown => f.write_str(
+                "The router didn't report its WAN address, so CGNAT or double NAT can't be \
+                 ruled out",
+            ),
+        }
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn interfaces(addresses: &[(&str, &str)]) -> Vec<(String, IpAddr)> {
+        addresses
+            .iter()
+            .map(|(name, address)| (name.to_string(), ip(address)))
+            .collect()
+    }
+
+    #[test]
+    fn test_classify_router() {
+        let lan = interfaces(&[("lo", "127.0.0.1"), ("eth0", "192.168.1.20")]);
+        let public = Some(ip("1.2.3.4"));
+        let kind = |router: &str| NetworkSituation::classify(public, Some(ip(router)), &lan).kind;
+
+        assert_eq!(kind("1.2.3.4"), NatKind::Reachable);
+        assert_eq!(kind("100.64.0.1"), NatKind::Cgnat);
+        assert_eq!(kind("100.127.255.254"), NatKind::Cgnat);
+        assert_eq!(kind("100.128.0.1"), NatKind::Mismatch);
+        assert_eq!(kind("10.0.0.2"), NatKind::DoubleNat);
+        assert_eq!(kind("192.168.0.2"), NatKind::DoubleNat);
+        assert_eq!(kind("5.6.7.8"), NatKind::Mismatch);
+
+        let cgnat = NetworkSituation::classify(public, Some(ip("100.72.1.2")), &lan);
+        assert!(cgnat.is_problem());
+        assert_eq!(cgnat.address, Some(ip("100.72.1.2")));
+        assert_eq!(cgnat.interface, None);
+        assert!(cgnat
+            .to_string()
+            .starts_with("Behind CGNAT: the router's WAN address 100.72.1.2 is in"));
+        assert!(cgnat.suggestion().unwrap().contains("public IPv4"));
+
+        // Without a detected address the router's public WAN address is
+        // taken as reachable
+        let wan = NetworkSituation::classify(None, Some(ip("1.2.3.4")), &lan);
+        assert_eq!(wan.kind, NatKind::Reachable);
+        assert_eq!(
+            wan.to_string(),
+            "The router's WAN address 1.2.3.4 is public"
+        );
+    }
+
+    #[test]
+    fn test_classify_interfaces() {
+        let public = Some(ip("1.2.3.4"));
+        let classify = |addresses: &[(&str, &str)]| {
+            NetworkSituation::classify(public, None, &interfaces(addresses))
+        };
+
+        // This host is the router
+        let direct = classify(&[("eth0", "192.168.1.1"), ("ppp0", "1.2.3.4")]);
+        assert_eq!(direct.kind, NatKind::Reachable);
+        assert_eq!(direct.interface.as_deref(), Some("ppp0"));
+        assert!(!direct.is_problem());
+
+        // A mobile connection handing out a shared address
+        let mobile = classify(&[("wwan0", "100.99.3.4")]);
+        assert_eq!(mobile.kind, NatKind::Cgnat);
+        assert_eq!(
+            mobile.to_string(),
+            "Behind CGNAT: the address 100.99.3.4 of wwan0 is in 100.64.0.0/10, so the public \
+             address is shared with other customers and records pointing to it can't reach \
+             this network"
+        );
+
+        // A public address that isn't the one traffic leaves from
+        let vpn = classify(&[("eth0", "5.6.7.8"), ("tun0", "10.8.0.2")]);
+        assert_eq!(vpn.kind, NatKind::Mismatch);
+        assert_eq!(
+            vpn.to_string(),
+            "The address 5.6.7.8 of eth0 differs from the detected public IP 1.2.3.4"
+        );
+
+        // Private addresses alone can't tell a single NAT from a double one
+        let lan = classify(&[("lo", "127.0.0.1"), ("eth0", "192.168.1.20")]);
+        assert_eq!(lan.kind, NatKind::Unknown);
+        assert!(!lan.is_problem());
+        assert_eq!(
+            NetworkSituation::classify(None, None, &interfaces(&[("eth0", "5.6.7.8")])).kind,
+            NatKind::Unknown
+        );
+
+        // IPv6 addresses don't take part
+        let v6 = NetworkSituation::classify(
+            Some(ip("2001:db8::1")),
+            None,
+            &interfaces(&[("eth0", "2001:db8::1")]),
+        );
+        assert_eq!(v6.kind, NatKind::Unknown);
+        assert_eq!(v6.public, None);
+    }
+}
@@ -0,0 +1,147 @@
+//! IPv6 prefixes, and the addresses of LAN hosts composed from the
+//! network's current prefix and a stable host suffix, for records that
+//! follow a rotating delegated prefix.
+
+use serde::{Deserialize, Serialize};
+use std::net::Ipv6Addr;
+
+/// IPv6 prefix delegated to the network, e.g. `2001:db8:1200::/56`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ipv6Prefix {
+    /// First address of the prefix.
+    pub address: Ipv6Addr,
+    /// Prefix length in bits.
+    pub length: u8,
+}
+
+impl Ipv6Prefix {
+    /// Get the prefix of `length` bits (at most 128) holding `address`,
+    /// e.g. `2001:db8:1200::/56` for `2001:db8:1200:10::1` and 56.
+    pub fn of(address: Ipv6Addr, length: u8) -> Self {
+        let length = length.min(128);
+        Self {
+            address: Ipv6Addr::from(u128::from(address) & mask(length)),
+            length,
+        }
+    }
+
+    /// Whether `address` is within the prefix.
+    pub fn contains(&self, address: Ipv6Addr) -> bool {
+        (u128::from(address) ^ u128::from(self.address)) & mask(self.length) == 0
+    }
+
+    /// Compose the address of a host: the prefix followed by the bits of
+    /// `suffix` past the prefix length, e.g. `2001:db8:1200::1234:5678:9abc:def0`
+    /// for `2001:db8:1200::/56` and `::1234:5678:9abc:def0`.
+    ///
+    /// Bits of `suffix` within the prefix are ignored (see [`suffix_fits`]).
+    pub fn with_suffix(&self, suffix: Ipv6Addr) -> Ipv6Addr {
+        let mask = mask(self.length);
+        Ipv6Addr::from(u128::from(self.address) & mask | u128::from(suffix) & !mask)
+    }
+}
+
+impl std::fmt::Display for Ipv6Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)
+    }
+}
+
+/// Get the mask selecting the first `length` bits of an address (all of
+/// them from 128 on).
+pub fn mask(length: u8) -> u128 {
+    match length {
+        0 => 0,
+        // Shifting a u128 by 128 overflows
+        1..=127 => u128::MAX << (128 - u32::from(length)),
+        _ => u128::MAX,
+    }
+}
+
+/// Whether `suffix` only sets bits past the first `length`, so none of it
+/// is replaced by a prefix of that length.
+pub fn suffix_fits(suffix: Ipv6Addr, length: u8) -> bool {
+    u128::from(suffix) & mask(length) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask(0), 0);
+        assert_eq!(mask(1), 1 << 127);
+        assert_eq!(mask(64), 0xffff_ffff_ffff_ffff_0000_0000_0000_0000);
+        assert_eq!(mask(56), 0xffff_ffff_ffff_ff00_0000_0000_0000_0000);
+        assert_eq!(mask(127), u128::MAX - 1);
+        assert_eq!(mask(128), u128::MAX);
+        assert_eq!(mask(200), u128::MAX);
+    }
+
+    #[test]
+    fn test_prefix_of() {
+        let prefix = Ipv6Prefix::of(ip("2001:db8:1200:10:aaaa::1"), 56);
+        assert_eq!(prefix.to_string(), "2001:db8:1200::/56");
+        assert!(prefix.contains(ip("2001:db8:1200:ff:ffff::1")));
+        assert!(!prefix.contains(ip("2001:db8:1201::1")));
+
+        // A length that isn't a multiple of 4 cuts through a hex digit
+        let odd = Ipv6Prefix::of(ip("2001:db8:ffff::"), 37);
+        assert_eq!(odd.address, ip("2001:db8:f800::"));
+
+        assert_eq!(
+            Ipv6Prefix::of(ip("2001:db8::1"), 0).address,
+            Ipv6Addr::UNSPECIFIED
+        );
+        let host = Ipv6Prefix::of(ip("2001:db8::1"), 255);
+        assert_eq!((host.address, host.length), (ip("2001:db8::1"), 128));
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        let suffix = ip("::1234:5678:9abc:def0");
+        let prefix = Ipv6Prefix::of(ip("2001:db8:1200::"), 56);
+        assert_eq!(
+            prefix.with_suffix(suffix),
+            ip("2001:db8:1200:0:1234:5678:9abc:def0")
+        );
+        // The suffix may pick a subnet of the delegated prefix
+        assert_eq!(
+            prefix.with_suffix(ip("::2a:1234:5678:9abc:def0")),
+            ip("2001:db8:1200:2a:1234:5678:9abc:def0")
+        );
+        // Bits within the prefix come from the prefix
+        assert_eq!(prefix.with_suffix(ip("ffff::1")), ip("2001:db8:1200::1"));
+
+        let lan = Ipv6Prefix::of(ip("2001:db8:1200:10:aaaa::1"), 64);
+        assert_eq!(
+            lan.with_suffix(suffix),
+            ip("2001:db8:1200:10:1234:5678:9abc:def0")
+        );
+        assert_eq!(
+            Ipv6Prefix::of(ip("2001:db8::1"), 0).with_suffix(suffix),
+            suffix
+        );
+        assert_eq!(
+            Ipv6Prefix::of(ip("2001:db8::1"), 128).with_suffix(suffix),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn test_suffix_fits() {
+        assert!(suffix_fits(ip("::1234:5678:9abc:def0"), 64));
+        assert!(suffix_fits(ip("::2a:1234:5678:9abc:def0"), 56));
+        assert!(!suffix_fits(ip("::2a:1234:5678:9abc:def0"), 64));
+        assert!(!suffix_fits(ip("8000::1"), 1));
+        assert!(suffix_fits(ip("2001:db8::1"), 0));
+        assert!(suffix_fits(Ipv6Addr::UNSPECIFIED, 128));
+    }
+}
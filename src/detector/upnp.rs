@@ -0,0 +1,231 @@
+//! WAN address detection by asking the router over UPnP IGD.
+
+use crate::error::{DdnsError, Result};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// SSDP multicast address gateways listen on.
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+
+/// Time allowed for a gateway to answer the SSDP search.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Devices searched for.
+const SEARCH_TARGETS: [&str; 2] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+];
+
+/// Services of a gateway device that can report the WAN address.
+const WAN_SERVICES: [&str; 2] = ["WANIPConnection", "WANPPPConnection"];
+
+/// Ask the router for its WAN address, finding it with an SSDP search
+/// unless the URL of its device description is given.
+pub(super) async fn query(client: &reqwest::Client, location: Option<&str>) -> Result<IpAddr> {
+    let location = match location {
+        Some(location) => location.to_string(),
+        None => discover().await?,
+    };
+
+    let description = fetch(client.get(&location)).await?;
+    let (service_type, control_url) = wan_service(&description).ok_or_else(|| {
+        DdnsError::IpDetection(format!(
+            "UPnP gateway at {} has no WAN connection service",
+            location
+        ))
+    })?;
+    let base = element(&description, "URLBase").unwrap_or(&location);
+    let control_url = reqwest::Url::parse(base)
+        .and_then(|base| base.join(control_url))
+        .map_err(|e| DdnsError::IpDetection(format!("Invalid UPnP control URL: {}", e)))?;
+
+    let response = soap_call(client, control_url, service_type, "GetExternalIPAddress").await?;
+    let address = element(&response, "NewExternalIPAddress").unwrap_or_default();
+    address.trim().parse().map_err(|_| {
+        DdnsError::IpDetection(format!(
+            "UPnP gateway returned no valid WAN address: {:?}",
+            address
+        ))
+    })
+}
+
+/// Find a gateway with an SSDP search, returning the URL of its device
+/// description.
+async fn discover() -> Result<String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    for target in SEARCH_TARGETS {
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_ADDR, target
+        );
+        socket.send_to(request.as_bytes(), SSDP_ADDR).await?;
+    }
+
+    let mut buffer = [0u8; 2048];
+    let search = async {
+        loop {
+            let (len, _) = socket.recv_from(&mut buffer).await?;
+            if let Some(location) = location(&String::from_utf8_lossy(&buffer[..len])) {
+                return Ok(location);
+            }
+        }
+    };
+    tokio::time::timeout(DISCOVERY_TIMEOUT, search)
+        .await
+        .map_err(|_| DdnsError::IpDetection("No UPnP gateway answered".to_string()))?
+}
+
+/// Call an action without arguments of a UPnP service, returning the SOAP
+/// response.
+pub(super) async fn soap_call(
+    client: &reqwest::Client,
+    control_url: reqwest::Url,
+    service_type: &str,
+    action: &str,
+) -> Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{} xmlns:u=\"{}\"/></s:Body></s:Envelope>",
+        action, service_type
+    );
+    fetch(
+        client
+            .post(control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#{}\"", service_type, action))
+            .body(body),
+    )
+    .await
+}
+
+async fn fetch(request: reqwest::RequestBuilder) -> Result<String> {
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| DdnsError::Network(e.to_string()))?
+        .text()
+        .await
+        .map_err(|e| DdnsError::Network(e.to_string()))
+}
+
+/// Get the `LOCATION` header of an SSDP response.
+fn location(response: &str) -> Option<String> {
+    response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("location")
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Get the type and control URL of the first WAN connection service of a
+/// device description.
+fn wan_service(description: &str) -> Option<(&str, &str)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service_type = element(service, "serviceType")?;
+        if !WAN_SERVICES.iter().any(|wan| service_type.contains(wan)) {
+            return None;
+        }
+        Some((service_type.trim(), element(service, "controlURL")?.trim()))
+    })
+}
+
+/// Get the text of the first `<name>` element of an XML document.
+pub(super) fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..start + end])
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Start a gateway on localhost whose WAN address is `wan_ip`, returning
+    /// the URL of its device description.
+    pub(crate) async fn gateway(wan_ip: &str) -> (MockServer, String) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rootDesc.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<root><device><serviceList>\
+                 <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+                 <controlURL>/ctl/L3F</controlURL></service>\
+                 <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+                 <controlURL>/ctl/IPConn</controlURL></service>\
+                 </serviceList></device></root>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/ctl/IPConn"))
+            .and(header(
+                "SOAPAction",
+                "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+                 <NewExternalIPAddress>{}</NewExternalIPAddress>\
+                 </u:GetExternalIPAddressResponse></s:Body></s:Envelope>",
+                wan_ip
+            )))
+            .mount(&server)
+            .await;
+
+        let location = format!("{}/rootDesc.xml", server.uri());
+        (server, location)
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let (_server, location) = gateway("203.0.114.7").await;
+        let ip = query(&reqwest::Client::new(), Some(&location))
+            .await
+            .unwrap();
+        assert_eq!(ip, "203.0.114.7".parse::<IpAddr>().unwrap());
+
+        let err = query(
+            &reqwest::Client::new(),
+            Some(&location.replace("rootDesc", "x")),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+    }
+
+    #[test]
+    fn test_location() {
+        let response = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\n\
+                        Location: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(
+            location(response).as_deref(),
+            Some("http://192.168.1.1:5000/rootDesc.xml")
+        );
+        assert_eq!(location("HTTP/1.1 200 OK\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_wan_service() {
+        let description = "<URLBase>http://10.0.0.1:49000</URLBase>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>\
+            <controlURL> /upnp/control/WANPPPConn1 </controlURL></service>";
+        assert_eq!(
+            wan_service(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANPPPConnection:1",
+                "/upnp/control/WANPPPConn1"
+            ))
+        );
+        assert_eq!(
+            element(description, "URLBase"),
+            Some("http://10.0.0.1:49000")
+        );
+        assert_eq!(wan_service("<service></service>"), None);
+    }
+}
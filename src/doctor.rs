@@ -0,0 +1,755 @@
+//! End-to-end diagnostics for `rusty-dns doctor`.
+//!
+//! Each check yields a [`Finding`] with a suggested fix when it doesn't
+//! pass. Checks that depend on an earlier failure (such as reading a record
+//! with rejected credentials) are skipped.
+
+use crate::config::{read_secret, Config};
+use crate::detector::{local_addresses, IpDetector, NatKind, NetworkSituation};
+use crate::error::DdnsError;
+use crate::notify::Notifications;
+use crate::providers::{create_provider, DdnsProvider};
+use crate::secret::KEYRING_PREFIX;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Everything is fine.
+    Pass,
+    /// Works, but deserves attention.
+    Warn,
+    /// Broken.
+    Fail,
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "PASS",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+        })
+    }
+}
+
+/// Result of one check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// Group of checks, e.g. "IP detection".
+    pub section: &'static str,
+    /// What was checked (a file, service, provider or channel).
+    pub subject: String,
+    /// Outcome.
+    pub level: Level,
+    /// What was found.
+    pub message: String,
+    /// Suggested next step if the check didn't pass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+/// Findings of a doctor run, in check order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DoctorReport {
+    /// All findings.
+    pub findings: Vec<Finding>,
+}
+
+impl DoctorReport {
+    fn push(
+        &mut self,
+        section: &'static str,
+        subject: impl Into<String>,
+        level: Level,
+        message: impl Into<String>,
+        suggestion: Option<String>,
+    ) {
+        self.findings.push(Finding {
+            section,
+            subject: subject.into(),
+            level,
+            message: message.into(),
+            suggestion,
+        });
+    }
+
+    fn pass(
+        &mut self,
+        section: &'static str,
+        subject: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.push(section, subject, Level::Pass, message, None);
+    }
+
+    /// Count the findings at a level.
+    pub fn count(&self, level: Level) -> usize {
+        self.findings.iter().filter(|f| f.level == level).count()
+    }
+
+    /// Get the worst level found.
+    pub fn level(&self) -> Level {
+        self.findings
+            .iter()
+            .map(|f| f.level)
+            .max()
+            .unwrap_or(Level::Pass)
+    }
+
+    /// Get the process exit code: 1 if any check failed.
+    pub fn exit_code(&self) -> i32 {
+        match self.level() {
+            Level::Fail => 1,
+            _ => 0,
+        }
+    }
+}
+
+const CONFIG: &str = "Configuration";
+const SECRETS: &str = "Secrets";
+const DETECTION: &str = "IP detection";
+const PROVIDERS: &str = "Providers";
+const RECORDS: &str = "Records";
+const NOTIFICATIONS: &str = "Notifications";
+
+/// Diagnose the config file at `path` and everything it refers to.
+///
+/// Test messages are only sent to the notification channels if
+/// `send_test_notifications` is set.
+pub async fn run(
+    path: &Path,
+    profile: Option<&str>,
+    send_test_notifications: bool,
+) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    let subject = path.display().to_string();
+
+    if path.exists() {
+        report.pass(CONFIG, &subject, "Config file found");
+    } else {
+        report.push(
+            CONFIG,
+            &subject,
+            Level::Warn,
+            "No config file; using defaults and RUSTY_DNS_* environment variables",
+            Some("Create one with `rusty-dns config init`".to_string()),
+        );
+    }
+
+    let config = match Config::load_unchecked(path, false, profile) {
+        Ok(config) => config,
+        Err(e) => {
+            let message = e.to_string();
+            let suggestion = config_suggestion(&message);
+            report.push(CONFIG, &subject, Level::Fail, message, Some(suggestion));
+            return report;
+        }
+    };
+    report.pass(
+        CONFIG,
+        &subject,
+        format!(
+            "Parsed ({} provider(s), {} notification channel(s))",
+            config.providers.len(),
+            config.notifications.len()
+        ),
+    );
+
+    let providers: Vec<Box<dyn DdnsProvider>> = config
+        .providers
+        .iter()
+        .map(|p| create_provider(p, config.default_ttl, &config.network))
+        .collect();
+    let detector = IpDetector::from_config(&config);
+
+    diagnose(
+        &mut report,
+        &config,
+        &detector,
+        &providers,
+        send_test_notifications,
+    )
+    .await;
+    report
+}
+
+/// Run the checks that follow loading the config, with one already created
+/// provider per entry of `config.providers`.
+pub async fn diagnose(
+    report: &mut DoctorReport,
+    config: &Config,
+    detector: &IpDetector,
+    providers: &[Box<dyn DdnsProvider>],
+    send_test_notifications: bool,
+) {
+    match config.validate() {
+        Ok(warnings) => {
+            for warning in warnings {
+                report.push(
+                    CONFIG,
+                    warning.label,
+                    Level::Warn,
+                    warning.message,
+                    Some("Review this setting in the config file".to_string()),
+                );
+            }
+        }
+        Err(e) => report.push(
+            CONFIG,
+            "providers",
+            Level::Fail,
+            e.to_string(),
+            Some("Fix the settings listed above; `rusty-dns validate` shows them too".to_string()),
+        ),
+    }
+    if config.providers.is_empty() {
+        report.push(
+            CONFIG,
+            "providers",
+            Level::Warn,
+            "No providers configured",
+            Some("Add one with `rusty-dns config add-provider <type>`".to_string()),
+        );
+    }
+
+    let unresolved = check_secrets(report, config);
+    let detected = check_detection(report, config, detector).await;
+    if config.ip_version.includes_v4() {
+        check_router(report, config, detector, &detected).await;
+    }
+    check_providers(report, config, providers, &unresolved, &detected).await;
+    check_notifications(report, config, send_test_notifications).await;
+}
+
+/// Check that every credential resolves, returning the labels of the
+/// providers with unresolved credentials.
+fn check_secrets(report: &mut DoctorReport, config: &Config) -> Vec<String> {
+    let mut unresolved = Vec::new();
+    for provider in config.providers.iter().filter(|p| p.enabled) {
+        let label = provider.label();
+        for (field, value, file) in provider.credentials() {
+            let source = match file {
+                Some(file) => format!("file {}", file.display()),
+                None if value.starts_with(KEYRING_PREFIX) => format!("keyring entry {}", value),
+                None => "inline value".to_string(),
+            };
+
+            match read_secret(value, file) {
+                Ok(secret) if !secret.trim().is_empty() => report.pass(
+                    SECRETS,
+                    &label,
+                    format!("`{}` resolves ({})", field, source),
+                ),
+                Ok(_) => {
+                    unresolved.push(label.clone());
+                    report.push(
+                        SECRETS,
+                        &label,
+                        Level::Fail,
+                        format!("`{}` is empty ({})", field, source),
+                        Some(format!("Set `{}` or `{}_file`", field, field)),
+                    )
+                }
+                Err(e) => {
+                    unresolved.push(label.clone());
+                    report.push(
+                        SECRETS,
+                        &label,
+                        Level::Fail,
+                        format!("`{}` doesn't resolve: {}", field, e),
+                        Some(match file {
+                            Some(file) => format!(
+                            "Check that {} exists and is readable by the user running rusty-dns",
+                            file.display()
+                        ),
+                            None => {
+                                "Store the secret with `rusty-dns secret set <entry>`".to_string()
+                            }
+                        }),
+                    )
+                }
+            }
+        }
+    }
+    unresolved
+}
+
+/// Probe every detection service, returning the detected addresses.
+async fn check_detection(
+    report: &mut DoctorReport,
+    config: &Config,
+    detector: &IpDetector,
+) -> Vec<IpAddr> {
+    let mut detected = Vec::new();
+
+    for (included, ipv6) in [
+        (config.ip_version.includes_v4(), false),
+        (config.ip_version.includes_v6(), true),
+    ] {
+        if !included {
+            continue;
+        }
+
+        let probes = detector.probe_all(ipv6).await;
+        let health = detector.health();
+        let now = chrono::Utc::now();
+        let history = |service: &str| {
+            health
+                .get(service)
+                .map(|h| format!(" ({})", h.summary(now)))
+                .unwrap_or_default()
+        };
+        let mut answers: Vec<(IpAddr, Vec<&str>)> = Vec::new();
+        for probe in &probes {
+            match (probe.ip, &probe.error) {
+                (Some(ip), _) => {
+                    report.pass(
+                        DETECTION,
+                        &probe.service,
+                        format!(
+                            "Answered {} in {} ms{}",
+                            ip,
+                            probe.latency_ms,
+                            history(&probe.service)
+                        ),
+                    );
+                    match answers.iter_mut().find(|(answer, _)| *answer == ip) {
+                        Some((_, services)) => services.push(&probe.service),
+                        None => answers.push((ip, vec![&probe.service])),
+                    }
+                }
+                (None, error) => report.push(
+                    DETECTION,
+                    &probe.service,
+                    Level::Warn,
+                    format!(
+                        "Failed after {} ms: {}{}",
+                        probe.latency_ms,
+                        error.as_deref().unwrap_or_default(),
+                        history(&probe.service)
+                    ),
+                    Some("Remove the service from `ip_services` if it keeps failing".to_string()),
+                ),
+            }
+        }
+
+        let family = if ipv6 { "IPv6" } else { "IPv4" };
+        match answers.as_slice() {
+            [] => report.push(
+                DETECTION,
+                family,
+                Level::Fail,
+                "No detection service answered",
+                Some(if ipv6 {
+                    "Check that this host has IPv6 connectivity, or set `ip_version = \"v4\"`"
+                        .to_string()
+                } else {
+                    "Check outbound HTTPS access (firewall, proxy, DNS resolution)".to_string()
+                }),
+            ),
+            [(ip, _)] => report.pass(DETECTION, family, format!("All services agree on {}", ip)),
+            [..] => {
+                let summary: Vec<String> = answers
+                    .iter()
+                    .map(|(ip, services)| format!("{} ({})", ip, services.join(", ")))
+                    .collect();
+                report.push(
+                    DETECTION,
+                    family,
+                    Level::Warn,
+                    format!("Services disagree: {}", summary.join("; ")),
+                    Some(
+                        "Remove services that return a different address from `ip_services`"
+                            .to_string(),
+                    ),
+                );
+            }
+        }
+
+        // The first answer in service order is the one an update would use
+        detected.extend(probes.iter().find_map(|p| p.ip));
+    }
+
+    detected
+}
+
+/// Ask the router for its WAN address, or else look at the local
+/// interfaces, to tell apart a public address from CGNAT or double NAT,
+/// which no DNS record can reach (see [`NetworkSituation::classify`]).
+async fn check_router(
+    report: &mut DoctorReport,
+    config: &Config,
+    detector: &IpDetector,
+    detected: &[IpAddr],
+) {
+    const ROUTER: &str = "Router";
+
+    let (router, error) = match detector.query_router().await {
+        Ok(wan) => (Some(wan), None),
+        Err(DdnsError::IpDetection(message)) => (None, Some(message)),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let public = detected.iter().copied().find(IpAddr::is_ipv4);
+    let situation =
+        NetworkSituation::classify(public, router, &local_addresses().unwrap_or_default());
+    let subject = match &situation.interface {
+        Some(interface) => format!("Interface {}", interface),
+        None => ROUTER.to_string(),
+    };
+    let suggestion = situation.suggestion().map(str::to_string);
+
+    match situation.kind {
+        NatKind::Reachable => report.pass(DETECTION, subject, situation.to_string()),
+        NatKind::Cgnat => report.push(
+            DETECTION,
+            subject,
+            Level::Fail,
+            situation.to_string(),
+            suggestion,
+        ),
+        NatKind::DoubleNat | NatKind::Mismatch => report.push(
+            DETECTION,
+            subject,
+            Level::Warn,
+            situation.to_string(),
+            suggestion,
+        ),
+        NatKind::Unknown => {
+            let e = error.unwrap_or_default();
+            let configured = config
+                .ip_services
+                .iter()
+                .any(|s| s == "upnp" || s.starts_with("upnp:") || s.starts_with("fritzbox"));
+            if configured {
+                report.push(
+                    DETECTION,
+                    ROUTER,
+                    Level::Warn,
+                    e,
+                    Some(
+                        "Enable UPnP (TR-064 on a FRITZ!Box) on the router, or remove it from \
+                         `ip_services`"
+                            .to_string(),
+                    ),
+                );
+            } else {
+                report.pass(DETECTION, ROUTER, format!("Skipped: {}", e));
+            }
+        }
+    }
+}
+
+async fn check_providers(
+    report: &mut DoctorReport,
+    config: &Config,
+    providers: &[Box<dyn DdnsProvider>],
+    unresolved: &[String],
+    detected: &[IpAddr],
+) {
+    for (provider_config, provider) in config.providers.iter().zip(providers) {
+        let label = provider_config.label();
+        if !provider_config.enabled {
+            report.pass(PROVIDERS, &label, "Disabled, not checked");
+            continue;
+        }
+        if unresolved.contains(&label) {
+            continue;
+        }
+
+        let validated = match provider.prepare().await {
+            Ok(()) => provider.validate().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = validated {
+            report.push(
+                PROVIDERS,
+                &label,
+                Level::Fail,
+                format!("Credentials rejected: {}", e),
+                Some(provider_suggestion(provider_config.name()).to_string()),
+            );
+            continue;
+        }
+        report.pass(PROVIDERS, &label, "Credentials accepted");
+
+        let subject = format!("{} ({})", label, provider.domain());
+        let record = if provider.capabilities().can_read_record {
+            provider.get_current_ip().await
+        } else {
+            Ok(None)
+        };
+        match record {
+            Ok(Some(ip)) => match detected.iter().find(|d| d.is_ipv4() == ip.is_ipv4()) {
+                Some(public) if *public == ip => {
+                    report.pass(RECORDS, subject, format!("Record holds {}", ip))
+                }
+                Some(public) => report.push(
+                    RECORDS,
+                    subject,
+                    Level::Warn,
+                    format!("Record holds {}, but the public IP is {}", ip, public),
+                    Some("Run `rusty-dns update` to publish the current address".to_string()),
+                ),
+                None => report.push(
+                    RECORDS,
+                    subject,
+                    Level::Warn,
+                    format!(
+                        "Record holds {}; no public address of that family was detected",
+                        ip
+                    ),
+                    None,
+                ),
+            },
+            Ok(None) => report.push(
+                RECORDS,
+                subject,
+                Level::Warn,
+                format!("{} can't report the current record", provider.name()),
+                None,
+            ),
+            Err(e) => report.push(
+                RECORDS,
+                subject,
+                Level::Fail,
+                format!("Record can't be read: {}", e),
+                Some("Check that the record exists and the name is spelled correctly".to_string()),
+            ),
+        }
+    }
+}
+
+async fn check_notifications(report: &mut DoctorReport, config: &Config, send_test: bool) {
+    if config.notifications.is_empty() {
+        return;
+    }
+
+    if !send_test {
+        report.pass(
+            NOTIFICATIONS,
+            "channels",
+            format!(
+                "{} channel(s) configured; use --send-test-notifications to send a test message",
+                config.notifications.len()
+            ),
+        );
+        return;
+    }
+
+    for (name, result) in Notifications::from_config(config).test().await {
+        match result {
+            Ok(()) => report.pass(NOTIFICATIONS, name, "Test message accepted"),
+            Err(e) => report.push(
+                NOTIFICATIONS,
+                name,
+                Level::Fail,
+                format!("Test message failed: {}", e),
+                Some(format!(
+                    "Check the {} settings (URL, credentials) in `notifications`",
+                    name
+                )),
+            ),
+        }
+    }
+}
+
+/// Suggest a fix for a config loading error.
+fn config_suggestion(message: &str) -> String {
+    if message.contains("Environment variable") {
+        "Export the variable in the environment rusty-dns runs in (e.g. the systemd unit's \
+         EnvironmentFile), or use a `_file` setting instead"
+            .to_string()
+    } else if message.contains("Unknown profile") {
+        "Use one of the listed profiles, or unset RUSTY_DNS_PROFILE".to_string()
+    } else {
+        "Fix the config file; `rusty-dns config show` prints what was understood".to_string()
+    }
+}
+
+/// Suggest a fix for rejected provider credentials.
+fn provider_suggestion(provider: &str) -> &'static str {
+    match provider {
+        "cloudflare" => {
+            "Check that the API token has Zone:DNS:Edit permission and `zone_id` is the zone's ID"
+        }
+        "namecheap" => {
+            "Check that Dynamic DNS is enabled for the domain and `password` is its Dynamic DNS password"
+        }
+        "duckdns" => "Check the token shown on duckdns.org and that the subdomain is yours",
+        "godaddy" => "Check that the API key and secret are production keys with API access",
+        "vultr" => "Check the API key and that this host's address is in its access control list",
+        "dynurl" => "Check that the dynamic URL is current; regenerating it at the registrar replaces it",
+        "dnsomatic" => {
+            "Check the DNS-O-Matic username and password, and that `hostname` is one of its services"
+        }
+        "yandexcloud" => {
+            "Check that `auth_type` matches the credential and it may edit the zone (dns.editor role)"
+        }
+        _ => "Check the provider credentials",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::CloudflareProvider;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_diagnose() {
+        let server = MockServer::start().await;
+        for (route, ip) in [("/a", "1.2.3.4"), ("/b", "1.2.3.4"), ("/c", "9.9.9.9")] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(ResponseTemplate::new(200).set_body_string(ip))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/zone/dns_records"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":[{"id":"record-123","content":"5.6.7.8"}],"errors":[]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let config: Config = toml::from_str(
+            r#"
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+
+            [[providers]]
+            type = "duckdns"
+            domains = "home"
+            token_file = "/nonexistent/rusty-dns-token"
+            "#,
+        )
+        .unwrap();
+        let providers: Vec<Box<dyn DdnsProvider>> = vec![
+            Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                "zone".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                server.uri(),
+            )),
+            create_provider(&config.providers[1], None, &config.network),
+        ];
+        let detector = IpDetector::with_services(
+            ["/a", "/b", "/c"]
+                .iter()
+                .map(|route| format!("{}{}", server.uri(), route))
+                .collect(),
+        );
+
+        let mut report = DoctorReport::default();
+        diagnose(&mut report, &config, &detector, &providers, false).await;
+        let find = |section: &str, subject: &str| {
+            report
+                .findings
+                .iter()
+                .find(|f| f.section == section && f.subject.starts_with(subject))
+                .unwrap_or_else(|| panic!("no {} finding for {}", section, subject))
+        };
+
+        let secret = find(SECRETS, "duckdns-home.duckdns.org");
+        assert_eq!(secret.level, Level::Fail);
+        assert!(secret
+            .suggestion
+            .as_deref()
+            .unwrap()
+            .contains("/nonexistent"));
+
+        let agreement = find(DETECTION, "IPv4");
+        assert_eq!(agreement.level, Level::Warn);
+        assert!(agreement.message.contains("9.9.9.9"));
+
+        let record = find(RECORDS, "cloudflare-vpn.example.com");
+        assert_eq!(record.level, Level::Warn);
+        assert!(record
+            .message
+            .contains("holds 5.6.7.8, but the public IP is 1.2.3.4"));
+        assert!(record
+            .suggestion
+            .as_deref()
+            .unwrap()
+            .contains("rusty-dns update"));
+
+        // Providers with unresolved secrets aren't contacted
+        assert!(!report
+            .findings
+            .iter()
+            .any(|f| f.section == PROVIDERS && f.subject.starts_with("duckdns")));
+        assert_eq!(find(PROVIDERS, "cloudflare").level, Level::Pass);
+
+        assert_eq!(report.level(), Level::Fail);
+        assert_eq!(report.exit_code(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_cgnat() {
+        let (server, location) = crate::detector::upnp_gateway("100.72.1.2").await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+
+        let config = Config {
+            ip_services: vec![format!("upnp:{}", location), format!("{}/ip", server.uri())],
+            ..Config::default()
+        };
+        let detector = IpDetector::from_config(&config);
+
+        let mut report = DoctorReport::default();
+        diagnose(&mut report, &config, &detector, &[], false).await;
+        let router = report
+            .findings
+            .iter()
+            .find(|f| f.subject == "Router")
+            .unwrap();
+        assert_eq!(router.level, Level::Fail);
+        assert!(
+            router.message.starts_with("Behind CGNAT"),
+            "{}",
+            router.message
+        );
+        assert!(router
+            .suggestion
+            .as_deref()
+            .unwrap()
+            .contains("public IPv4"));
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_unset_variable() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(
+            &path,
+            "[[providers]]\ntype = \"duckdns\"\ndomains = \"home\"\ntoken = \"${RUSTY_DNS_DOCTOR_UNSET}\"\n",
+        )
+        .unwrap();
+
+        let report = run(&path, None, false).await;
+        let last = report.findings.last().unwrap();
+        assert_eq!(last.level, Level::Fail);
+        assert!(last.message.contains("RUSTY_DNS_DOCTOR_UNSET"));
+        assert!(last
+            .suggestion
+            .as_deref()
+            .unwrap()
+            .contains("Export the variable"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! Edits to config files that keep comments and formatting.
+//!
+//! TOML files are edited in place, so everything outside the changed entry
+//! stays byte-identical. YAML and JSON files are re-serialized.
+
+use crate::config::{Config, ConfigFormat, ProviderConfig};
+use crate::error::{DdnsError, Result};
+use std::path::Path;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Value};
+
+/// Append a provider entry to a config file, creating it if needed.
+pub fn append_provider(path: &Path, provider: &ProviderConfig) -> Result<()> {
+    if ConfigFormat::from_path(path) != ConfigFormat::Toml {
+        let mut config = Config::load_file(path)?;
+        config.providers.push(provider.clone());
+        return config.save_to(path);
+    }
+
+    let mut document = read_document(path)?;
+    let mut table = toml_edit::ser::to_document(provider)
+        .map_err(|e| DdnsError::Serialization(e.to_string()))?
+        .as_table()
+        .clone();
+    // Separate the new entry from what comes before it
+    if !document.is_empty() {
+        table.decor_mut().set_prefix("\n");
+    }
+
+    let providers = document
+        .entry("providers")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()));
+    match providers {
+        Item::ArrayOfTables(providers) => providers.push(table),
+        Item::Value(Value::Array(providers)) => providers.push(table.into_inline_table()),
+        _ => return Err(not_a_list(path)),
+    }
+
+    write_document(path, &document)
+}
+
+/// Remove the provider entry at `index` from a config file.
+pub fn remove_provider(path: &Path, index: usize) -> Result<()> {
+    if ConfigFormat::from_path(path) != ConfigFormat::Toml {
+        let mut config = Config::load_file(path)?;
+        if index >= config.providers.len() {
+            return Err(changed(path));
+        }
+        config.providers.remove(index);
+        return config.save_to(path);
+    }
+
+    let mut document = read_document(path)?;
+    match document.get_mut("providers") {
+        Some(Item::ArrayOfTables(providers)) if index < providers.len() => providers.remove(index),
+        Some(Item::Value(Value::Array(providers))) if index < providers.len() => {
+            providers.remove(index);
+        }
+        Some(Item::ArrayOfTables(_) | Item::Value(Value::Array(_))) | None => {
+            return Err(changed(path))
+        }
+        Some(_) => return Err(not_a_list(path)),
+    }
+
+    write_document(path, &document)
+}
+
+/// Set `check_interval_secs` in a config file, creating it if needed.
+pub fn set_check_interval(path: &Path, secs: u64) -> Result<()> {
+    if ConfigFormat::from_path(path) != ConfigFormat::Toml {
+        let mut config = Config::load_file(path)?;
+        config.check_interval_secs = secs;
+        return config.save_to(path);
+    }
+
+    let mut document = read_document(path)?;
+    let secs = i64::try_from(secs)
+        .map_err(|_| DdnsError::Config(format!("check interval too large: {}s", secs)))?;
+    match document.get_mut("check_interval_secs") {
+        // Replaced in place, keeping the comment after it
+        Some(Item::Value(value)) => {
+            let decor = value.decor().clone();
+            *value = Value::from(secs);
+            *value.decor_mut() = decor;
+        }
+        _ => {
+            document.insert("check_interval_secs", toml_edit::value(secs));
+        }
+    }
+
+    write_document(path, &document)
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    std::fs::read_to_string(path)?
+        .parse()
+        .map_err(|e| DdnsError::Config(format!("{}: {}", path.display(), e)))
+}
+
+fn write_document(path: &Path, document: &DocumentMut) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(path, document.to_string())?;
+    Ok(())
+}
+
+fn not_a_list(path: &Path) -> DdnsError {
+    DdnsError::Config(format!("{}: `providers` is not a list", path.display()))
+}
+
+fn changed(path: &Path) -> DdnsError {
+    DdnsError::Config(format!("{}: changed while editing", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderKind;
+
+    /// Hand-written config with comments, unusual spacing and key order.
+    const FIXTURE: &str = r#"# rusty-dns configuration
+# Maintained by hand, please keep the comments!
+
+check_interval_secs   =  120   # two minutes
+
+ip_services = [
+    "https://api.ipify.org",  # primary
+    # "https://icanhazip.com",
+]
+
+# --- Home router ---
+[[providers]]
+type = "duckdns"   # free
+token = "${DUCKDNS_TOKEN}"
+domains = "home"
+
+# --- Office, managed by IT ---
+[[providers]]
+type = "cloudflare"
+record_name = "vpn.example.com"
+zone_id = "zone"  # from the dashboard
+api_token_file = "/run/secrets/cf"
+
+[daemon]
+# Restart if the network changes
+watch_network = true
+"#;
+
+    /// The office provider block, as removed from the fixture.
+    const OFFICE: &str = r#"
+# --- Office, managed by IT ---
+[[providers]]
+type = "cloudflare"
+record_name = "vpn.example.com"
+zone_id = "zone"  # from the dashboard
+api_token_file = "/run/secrets/cf"
+"#;
+
+    fn fixture(name: &str, content: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-edit-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn cabin() -> ProviderConfig {
+        ProviderConfig::new(ProviderKind::DuckDns {
+            domains: "cabin".to_string(),
+            token: "keyring:rusty-dns/duckdns".into(),
+            token_file: None,
+        })
+    }
+
+    #[test]
+    fn test_append_keeps_formatting() {
+        let path = fixture("append", FIXTURE);
+        append_provider(&path, &cabin()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+
+        // The entry lands after the existing providers; the rest is untouched
+        let (before, after) = FIXTURE.split_at(FIXTURE.find("\n[daemon]").unwrap());
+        let added = "\n[[providers]]\n\
+                     enabled = true\n\
+                     type = \"duckdns\"\n\
+                     domains = \"cabin\"\n\
+                     token = \"keyring:rusty-dns/duckdns\"\n";
+        assert_eq!(content, format!("{}{}{}", before, added, after));
+
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.providers.len(), 3);
+        assert_eq!(config.providers[2].display_name(), "cabin.duckdns.org");
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_remove_keeps_formatting() {
+        let path = fixture("remove", FIXTURE);
+        remove_provider(&path, 1).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, FIXTURE.replacen(OFFICE, "", 1));
+
+        assert!(remove_provider(&path, 1).is_err());
+        remove_provider(&path, 0).unwrap();
+        let config = Config::load_file(&path).unwrap();
+        assert!(config.providers.is_empty());
+        assert_eq!(config.check_interval_secs, 120);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_set_check_interval_keeps_formatting() {
+        let path = fixture("interval", FIXTURE);
+        set_check_interval(&path, 600).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            FIXTURE.replacen(
+                "check_interval_secs   =  120   # two minutes",
+                "check_interval_secs   =  600   # two minutes",
+                1
+            )
+        );
+
+        // A file without the key gets it ahead of its tables
+        std::fs::write(&path, "[daemon]\nwatch_network = true\n").unwrap();
+        set_check_interval(&path, 90).unwrap();
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.check_interval_secs, 90);
+        assert!(config.daemon.watch_network);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_new_and_inline_lists() {
+        // A missing file gets just the new entry
+        let path = fixture("new", "");
+        std::fs::remove_file(&path).unwrap();
+        append_provider(&path, &cabin()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("[[providers]]\n"));
+
+        // Inline arrays are extended in place
+        let inline = "# inline\nproviders = [\n  { type = \"duckdns\", domains = \"home\", token = \"t\" },\n]\n";
+        std::fs::write(&path, inline).unwrap();
+        append_provider(&path, &cabin()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(
+            "# inline\nproviders = [\n  { type = \"duckdns\", domains = \"home\", token = \"t\" },"
+        ));
+        assert_eq!(Config::load_file(&path).unwrap().providers.len(), 2);
+        remove_provider(&path, 1).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), inline);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -20,6 +20,10 @@ pub enum DdnsError {
     #[error("Provider error ({provider}): {message}")]
     Provider { provider: String, message: String },
 
+    /// Credentials rejected by the provider.
+    #[error("Authentication failed ({provider}): {message}")]
+    Auth { provider: String, message: String },
+
     /// IP detection error.
     #[error("IP detection failed: {0}")]
     IpDetection(String),
@@ -31,6 +35,10 @@ pub enum DdnsError {
     /// Serialization error.
     #[error("Serialization error: {0}")]
     Serialization(String),
+
+    /// Another instance holds the lock.
+    #[error("Another rusty-dns instance is running (PID {pid}, lock file {path})")]
+    AlreadyRunning { pid: String, path: String },
 }
 
 impl From<reqwest::Error> for DdnsError {
@@ -56,3 +64,20 @@ impl From<serde_json::Error> for DdnsError {
         DdnsError::Serialization(e.to_string())
     }
 }
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for DdnsError {
+    fn from(e: rusqlite::Error) -> Self {
+        DdnsError::Io(std::io::Error::other(e))
+    }
+}
+
+#[cfg(all(windows, feature = "windows-service"))]
+impl From<windows_service::Error> for DdnsError {
+    fn from(e: windows_service::Error) -> Self {
+        match e {
+            windows_service::Error::Winapi(e) => DdnsError::Io(e),
+            e => DdnsError::Config(e.to_string()),
+        }
+    }
+}
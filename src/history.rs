@@ -0,0 +1,862 @@
+//! Persistent update history shared by the daemon, CLI, and MCP server.
+//!
+//! History, the IP change log and the persistent state are kept by a
+//! [`HistoryStore`]: JSON files by default, or a SQLite database with the
+//! `sqlite` feature and `history.backend = "sqlite"`.
+
+use crate::config::{Config, HistoryBackend, HistoryConfig};
+use crate::error::{DdnsError, Result};
+use crate::providers::{RecordType, UpdateResult};
+use crate::state::State;
+use crate::storage;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File name of the history file inside the state directory.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// File name of the IP change log inside the state directory.
+pub const IP_CHANGES_FILE: &str = "ip_changes.jsonl";
+
+/// Kind named in the history file's header.
+const HISTORY_KIND: &str = "history";
+
+/// Kind named in the IP change log's header.
+const IP_CHANGES_KIND: &str = "ip_changes";
+
+/// Why an update was sent to a provider, or why it wasn't (see
+/// [`UpdateReason::is_skip`]).
+///
+/// Entries written before skips were recorded have no reason and read as
+/// [`UpdateReason::Changed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateReason {
+    /// The detected address differs from the published one.
+    #[default]
+    Changed,
+    /// The user forced the update.
+    Forced,
+    /// Scheduled refresh to keep the hostname from expiring.
+    Refresh,
+    /// The record was changed outside rusty-dns and is set back to the
+    /// address last published.
+    Drift,
+    /// An address published before is given again (`update --ip`).
+    Rollback,
+    /// Not sent: the record already holds the address.
+    Unchanged,
+    /// Not sent: the provider is disabled.
+    Disabled,
+    /// Not sent: the provider asked not to be sent updates for a while
+    /// after rate limiting them.
+    NotEligibleInterval,
+    /// Not sent: the record was updated to the same address less than
+    /// `min_update_interval_secs` ago.
+    RateGuard,
+}
+
+impl UpdateReason {
+    /// Get the reason as a string.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Changed => "changed",
+            Self::Forced => "forced",
+            Self::Refresh => "refresh",
+            Self::Drift => "drift",
+            Self::Rollback => "rollback",
+            Self::Unchanged => "unchanged",
+            Self::Disabled => "disabled",
+            Self::NotEligibleInterval => "not_eligible_interval",
+            Self::RateGuard => "rate_guard",
+        }
+    }
+
+    /// Whether the update wasn't sent for this reason.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Self::Unchanged | Self::Disabled | Self::NotEligibleInterval | Self::RateGuard
+        )
+    }
+}
+
+impl std::fmt::Display for UpdateReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where the published address came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpSource {
+    /// Detected public address.
+    #[default]
+    Detected,
+    /// Address given by the user (`update --ip`).
+    Manual,
+}
+
+impl IpSource {
+    /// Get the source as a string.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Detected => "detected",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+impl std::fmt::Display for IpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A recorded update, or a skipped one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Result returned by the provider. For skips, a successful result with
+    /// the address that wasn't sent.
+    #[serde(flatten)]
+    pub result: UpdateResult,
+
+    /// Why the update was sent or skipped.
+    #[serde(default)]
+    pub reason: UpdateReason,
+
+    /// Where the published address came from.
+    #[serde(default)]
+    pub source: IpSource,
+}
+
+impl HistoryEntry {
+    /// Whether the update wasn't sent (see [`UpdateReason::is_skip`]).
+    pub fn is_skip(&self) -> bool {
+        self.reason.is_skip()
+    }
+
+    /// Describe the outcome, e.g. `OK (forced)`, `SKIPPED (unchanged)` or
+    /// `FAILED: <error>`.
+    pub fn outcome(&self) -> String {
+        let r = &self.result;
+        if self.is_skip() {
+            return format!("SKIPPED ({})", self.reason);
+        }
+        match &r.error {
+            None if r.success => match self.source {
+                IpSource::Detected => format!("OK ({})", self.reason),
+                IpSource::Manual => format!("OK ({}, manual)", self.reason),
+            },
+            error => format!("FAILED: {}", error.as_deref().unwrap_or_default()),
+        }
+    }
+
+    /// Whether `selector` names the provider of this entry: its label,
+    /// domain or provider type.
+    pub fn matches_provider(&self, selector: &str) -> bool {
+        let r = &self.result;
+        r.label.as_deref() == Some(selector) || r.domain == selector || r.provider == selector
+    }
+}
+
+/// A change of the detected public address, logged whatever the provider
+/// outcomes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpChange {
+    /// When the change was detected.
+    pub timestamp: DateTime<Utc>,
+    /// Address family, as the record type holding it.
+    pub record_type: RecordType,
+    /// Previously detected address.
+    pub old_ip: Option<IpAddr>,
+    /// Newly detected address.
+    pub new_ip: IpAddr,
+    /// Detection service that reported the new address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+}
+
+/// Criteria for selecting history entries, shared by the CLI and the MCP
+/// server.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    /// Maximum number of entries to return.
+    pub limit: Option<usize>,
+    /// Only entries of these providers (see [`HistoryEntry::matches_provider`]).
+    pub providers: Vec<String>,
+    /// Only failed updates.
+    pub failed_only: bool,
+    /// Only entries recorded at or after this time.
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    /// Whether an entry passes the filter (ignoring the limit).
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        (self.providers.is_empty() || self.providers.iter().any(|p| entry.matches_provider(p)))
+            && !(self.failed_only && entry.result.success)
+            && self
+                .since
+                .map_or(true, |since| entry.result.timestamp >= since)
+    }
+
+    /// Select the matching entries, most recent first.
+    pub fn apply<'a>(&self, entries: &'a [HistoryEntry]) -> Vec<&'a HistoryEntry> {
+        entries
+            .iter()
+            .rev()
+            .filter(|entry| self.matches(entry))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Parse a point in time: an RFC 3339 timestamp, a date (midnight UTC) or an
+/// age relative to `now` such as `30m`, `12h` or `7d`.
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+
+    let age = value
+        .char_indices()
+        .last()
+        .and_then(|(i, unit)| {
+            let amount: i64 = value[..i].parse().ok()?;
+            match unit {
+                's' => chrono::Duration::try_seconds(amount),
+                'm' => chrono::Duration::try_minutes(amount),
+                'h' => chrono::Duration::try_hours(amount),
+                'd' => chrono::Duration::try_days(amount),
+                'w' => chrono::Duration::try_weeks(amount),
+                _ => None,
+            }
+        })
+        .filter(|age| *age >= chrono::Duration::zero());
+
+    age.and_then(|age| now.checked_sub_signed(age)).ok_or_else(|| {
+        DdnsError::Config(format!(
+            "Invalid time \"{}\" (expected a timestamp like 2024-05-01T12:00:00Z, a date like 2024-05-01, or an age like 30m, 12h or 7d)",
+            value
+        ))
+    })
+}
+
+/// Get the history file path inside a state directory.
+pub fn path_in(dir: &Path) -> PathBuf {
+    dir.join(HISTORY_FILE)
+}
+
+/// Get the IP change log path inside a state directory.
+pub fn ip_changes_path_in(dir: &Path) -> PathBuf {
+    dir.join(IP_CHANGES_FILE)
+}
+
+/// Load history entries, oldest first (missing file yields no entries).
+///
+/// Lines that fail to parse are skipped.
+pub fn load_from(path: &Path) -> Result<Vec<HistoryEntry>> {
+    storage::load_lines(path, HISTORY_KIND)
+}
+
+/// Load IP changes, oldest first (missing file yields no changes).
+///
+/// Lines that fail to parse are skipped.
+pub fn load_ip_changes(path: &Path) -> Result<Vec<IpChange>> {
+    storage::load_lines(path, IP_CHANGES_KIND)
+}
+
+/// Append IP changes to the log, which is never truncated.
+pub fn append_ip_changes(path: &Path, changes: &[IpChange]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    storage::update_lines(path, IP_CHANGES_KIND, |all| {
+        all.extend_from_slice(changes);
+    })
+}
+
+/// Storage of the update history, the IP change log and persistent state,
+/// selected by `history.backend`, so callers don't depend on the backend.
+pub trait HistoryStore: Send + Sync {
+    /// Get the backend.
+    fn backend(&self) -> HistoryBackend;
+
+    /// Load history entries, oldest first.
+    fn entries(&self) -> Result<Vec<HistoryEntry>>;
+
+    /// Append history entries.
+    fn append(&self, entries: &[HistoryEntry]) -> Result<()>;
+
+    /// Load IP changes, oldest first.
+    fn ip_changes(&self) -> Result<Vec<IpChange>>;
+
+    /// Append IP changes.
+    fn append_ip_changes(&self, changes: &[IpChange]) -> Result<()>;
+
+    /// Load the persistent state (empty if there is none yet).
+    fn load_state(&self) -> Result<State>;
+
+    /// Atomically read, modify, and write the persistent state, so
+    /// concurrent processes don't lose writes. `update_state` wraps it for
+    /// closures returning a value.
+    fn modify_state(&self, f: &mut dyn FnMut(&mut State)) -> Result<()>;
+
+    /// Drop history entries beyond `history.max_entries`, and entries and
+    /// IP changes older than `history.retention_days`.
+    ///
+    /// Returns the number of entries and changes dropped.
+    fn prune(&self) -> Result<usize>;
+}
+
+impl dyn HistoryStore {
+    /// Atomically read, modify, and write the persistent state, returning
+    /// what `f` returns.
+    pub fn update_state<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        let mut f = Some(f);
+        let mut value = None;
+        self.modify_state(&mut |state| {
+            if let Some(f) = f.take() {
+                value = Some(f(state));
+            }
+        })?;
+        Ok(value.expect("state is modified once"))
+    }
+}
+
+/// Open the history store selected by `history.backend` in the state
+/// directory of a config.
+pub fn open(config: &Config) -> Result<Arc<dyn HistoryStore>> {
+    open_in(&config.state_dir()?, &config.history)
+}
+
+/// Open the history store selected by `config.backend` in `dir`.
+pub fn open_in(dir: &Path, config: &HistoryConfig) -> Result<Arc<dyn HistoryStore>> {
+    match config.backend {
+        HistoryBackend::Json => Ok(Arc::new(JsonStore::new(dir.to_path_buf(), config))),
+        #[cfg(feature = "sqlite")]
+        HistoryBackend::Sqlite => Ok(Arc::new(crate::sqlite::SqliteStore::open(dir, config)?)),
+        #[cfg(not(feature = "sqlite"))]
+        HistoryBackend::Sqlite => Err(DdnsError::Config(
+            "history.backend = \"sqlite\" requires rusty-dns built with the `sqlite` feature"
+                .to_string(),
+        )),
+    }
+}
+
+/// What [`migrate`] copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Migration {
+    /// History entries copied.
+    pub entries: usize,
+    /// IP changes copied.
+    pub ip_changes: usize,
+    /// Provider states copied.
+    pub providers: usize,
+}
+
+/// Copy the history, IP change log and state kept in `dir` by the other
+/// backend into the `to` backend.
+///
+/// Fails if `to` already holds history or IP changes, so nothing is
+/// imported twice. The source is left untouched.
+pub fn migrate(dir: &Path, config: &HistoryConfig, to: HistoryBackend) -> Result<Migration> {
+    let from = match to {
+        HistoryBackend::Json => HistoryBackend::Sqlite,
+        HistoryBackend::Sqlite => HistoryBackend::Json,
+    };
+    let source = open_in(
+        dir,
+        &HistoryConfig {
+            backend: from,
+            ..config.clone()
+        },
+    )?;
+    let target = open_in(
+        dir,
+        &HistoryConfig {
+            backend: to,
+            ..config.clone()
+        },
+    )?;
+
+    let held = target.entries()?.len() + target.ip_changes()?.len();
+    if held > 0 {
+        return Err(DdnsError::Config(format!(
+            "The {} backend already holds {} entries; not importing over them",
+            to, held
+        )));
+    }
+
+    let entries = source.entries()?;
+    let ip_changes = source.ip_changes()?;
+    let state = source.load_state()?;
+    let migration = Migration {
+        entries: entries.len(),
+        ip_changes: ip_changes.len(),
+        providers: state.providers.len(),
+    };
+
+    target.append(&entries)?;
+    target.append_ip_changes(&ip_changes)?;
+    target.update_state(|target| *target = state)?;
+    Ok(migration)
+}
+
+/// History kept in JSON files in the state directory: `history.jsonl`,
+/// `ip_changes.jsonl` and `state.json`, written through [`storage`].
+pub struct JsonStore {
+    dir: PathBuf,
+    max_entries: usize,
+    retention: Option<chrono::Duration>,
+}
+
+impl JsonStore {
+    /// Create a store of the files in `dir`, trimmed as set in `config`.
+    pub fn new(dir: PathBuf, config: &HistoryConfig) -> Self {
+        Self {
+            dir,
+            max_entries: config.max_entries,
+            retention: config.retention(),
+        }
+    }
+}
+
+impl HistoryStore for JsonStore {
+    fn backend(&self) -> HistoryBackend {
+        HistoryBackend::Json
+    }
+
+    fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        load_from(&path_in(&self.dir))
+    }
+
+    fn append(&self, entries: &[HistoryEntry]) -> Result<()> {
+        append(&path_in(&self.dir), entries, self.max_entries)
+    }
+
+    fn ip_changes(&self) -> Result<Vec<IpChange>> {
+        load_ip_changes(&ip_changes_path_in(&self.dir))
+    }
+
+    fn append_ip_changes(&self, changes: &[IpChange]) -> Result<()> {
+        append_ip_changes(&ip_changes_path_in(&self.dir), changes)
+    }
+
+    fn load_state(&self) -> Result<State> {
+        State::load_from(&State::path_in(&self.dir))
+    }
+
+    fn modify_state(&self, f: &mut dyn FnMut(&mut State)) -> Result<()> {
+        State::update(&State::path_in(&self.dir), f)
+    }
+
+    fn prune(&self) -> Result<usize> {
+        let cutoff = self.retention.map(|retention| Utc::now() - retention);
+        let max_entries = self.max_entries;
+        let mut dropped = 0;
+
+        let path = path_in(&self.dir);
+        if path.exists() {
+            dropped +=
+                storage::update_lines(&path, HISTORY_KIND, |all: &mut Vec<HistoryEntry>| {
+                    let before = all.len();
+                    if let Some(cutoff) = cutoff {
+                        all.retain(|entry| entry.result.timestamp >= cutoff);
+                    }
+                    if all.len() > max_entries {
+                        all.drain(..all.len() - max_entries);
+                    }
+                    before - all.len()
+                })?;
+        }
+
+        let path = ip_changes_path_in(&self.dir);
+        if let (Some(cutoff), true) = (cutoff, path.exists()) {
+            dropped += storage::update_lines(&path, IP_CHANGES_KIND, |all: &mut Vec<IpChange>| {
+                let before = all.len();
+                all.retain(|change| change.timestamp >= cutoff);
+                before - all.len()
+            })?;
+        }
+
+        Ok(dropped)
+    }
+}
+
+/// Header of [`updates_csv`].
+const UPDATES_CSV_HEADER: &str =
+    "timestamp,label,provider,domain,record_type,previous_ip,ip,success,reason,error,source,error_kind";
+
+/// Header of [`ip_changes_csv`].
+const IP_CHANGES_CSV_HEADER: &str = "timestamp,record_type,old_ip,new_ip,service";
+
+/// Format history entries as CSV, with a header row.
+pub fn updates_csv<'a>(entries: impl IntoIterator<Item = &'a HistoryEntry>) -> String {
+    let rows = entries.into_iter().map(|entry| {
+        let r = &entry.result;
+        vec![
+            r.timestamp.to_rfc3339(),
+            r.label.clone().unwrap_or_default(),
+            r.provider.clone(),
+            r.domain.clone(),
+            r.record_type.to_string(),
+            r.previous_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            r.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            r.success.to_string(),
+            entry.reason.to_string(),
+            r.error.clone().unwrap_or_default(),
+            entry.source.to_string(),
+            r.error_kind
+                .map(|kind| kind.to_string())
+                .unwrap_or_default(),
+        ]
+    });
+    csv(UPDATES_CSV_HEADER, rows)
+}
+
+/// Format IP changes as CSV, with a header row.
+pub fn ip_changes_csv<'a>(changes: impl IntoIterator<Item = &'a IpChange>) -> String {
+    let rows = changes.into_iter().map(|change| {
+        vec![
+            change.timestamp.to_rfc3339(),
+            change.record_type.to_string(),
+            change.old_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            change.new_ip.to_string(),
+            change.service.clone().unwrap_or_default(),
+        ]
+    });
+    csv(IP_CHANGES_CSV_HEADER, rows)
+}
+
+fn csv(header: &str, rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut out = format!("{}\n", header);
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Append entries, keeping at most `max_entries` of the most recent ones.
+pub fn append(path: &Path, entries: &[HistoryEntry], max_entries: usize) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    storage::update_lines(path, HISTORY_KIND, |all| {
+        all.extend_from_slice(entries);
+        if all.len() > max_entries {
+            all.drain(..all.len() - max_entries);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordType;
+    use crate::providers::UpdateErrorKind;
+
+    fn entry(domain: &str, reason: UpdateReason) -> HistoryEntry {
+        HistoryEntry {
+            result: UpdateResult {
+                provider: "duckdns".to_string(),
+                label: None,
+                domain: domain.to_string(),
+                record_type: RecordType::A,
+                success: true,
+                ip: Some("1.2.3.4".parse().unwrap()),
+                previous_ip: None,
+                error: None,
+                error_kind: None,
+                timestamp: chrono::Utc::now(),
+            },
+            reason,
+            source: IpSource::Detected,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let now = chrono::Utc::now();
+        let mut entries = vec![
+            entry("a.duckdns.org", UpdateReason::Changed),
+            entry("b.duckdns.org", UpdateReason::Changed),
+            entry("c.example.com", UpdateReason::Forced),
+        ];
+        entries[0].result.timestamp = now - chrono::Duration::days(3);
+        entries[1].result.success = false;
+        entries[2].result.provider = "cloudflare".to_string();
+        entries[2].result.label = Some("home".to_string());
+
+        let domains = |filter: &HistoryFilter| -> Vec<String> {
+            filter
+                .apply(&entries)
+                .iter()
+                .map(|e| e.result.domain.clone())
+                .collect()
+        };
+
+        // Most recent first
+        assert_eq!(
+            domains(&HistoryFilter::default()),
+            ["c.example.com", "b.duckdns.org", "a.duckdns.org"]
+        );
+        assert_eq!(
+            domains(&HistoryFilter {
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ["c.example.com"]
+        );
+        assert_eq!(
+            domains(&HistoryFilter {
+                providers: vec!["duckdns".to_string(), "home".to_string()],
+                failed_only: true,
+                ..Default::default()
+            }),
+            ["b.duckdns.org"]
+        );
+        assert_eq!(
+            domains(&HistoryFilter {
+                providers: vec!["a.duckdns.org".to_string()],
+                ..Default::default()
+            }),
+            ["a.duckdns.org"]
+        );
+        assert_eq!(
+            domains(&HistoryFilter {
+                since: Some(parse_since("1d", now).unwrap()),
+                ..Default::default()
+            }),
+            ["c.example.com", "b.duckdns.org"]
+        );
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = "2024-05-10T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        assert_eq!(
+            parse_since("2024-05-01T08:30:00+02:00", now)
+                .unwrap()
+                .to_rfc3339(),
+            "2024-05-01T06:30:00+00:00"
+        );
+        assert_eq!(
+            parse_since("2024-05-01", now).unwrap().to_rfc3339(),
+            "2024-05-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_since("12h", now).unwrap().to_rfc3339(),
+            "2024-05-10T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_since("2w", now).unwrap().to_rfc3339(),
+            "2024-04-26T12:00:00+00:00"
+        );
+
+        for invalid in ["", "h", "12x", "-3d", "yesterday"] {
+            assert!(parse_since(invalid, now).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_append_trims_oldest() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = path_in(&dir);
+
+        append(&path, &[entry("a", UpdateReason::Changed)], 2).unwrap();
+        append(
+            &path,
+            &[
+                entry("b", UpdateReason::Forced),
+                entry("c", UpdateReason::Refresh),
+            ],
+            2,
+        )
+        .unwrap();
+
+        let entries = load_from(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].result.domain, "b");
+        assert_eq!(entries[1].reason, UpdateReason::Refresh);
+
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(raw.contains(r#""reason":"refresh""#));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ip_changes_log() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-ip-changes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = ip_changes_path_in(&dir);
+
+        let change = |old: Option<&str>, new: &str| IpChange {
+            timestamp: chrono::Utc::now(),
+            record_type: RecordType::A,
+            old_ip: old.map(|ip| ip.parse().unwrap()),
+            new_ip: new.parse().unwrap(),
+            service: Some("https://api.ipify.org".to_string()),
+        };
+        let changes = vec![change(None, "1.2.3.4"), change(Some("1.2.3.4"), "5.6.7.8")];
+        append_ip_changes(&path, &changes[..1]).unwrap();
+        append_ip_changes(&path, &changes[1..]).unwrap();
+        assert_eq!(load_ip_changes(&path).unwrap(), changes);
+
+        let csv = ip_changes_csv(&changes);
+        let rows = parse_csv(&csv);
+        assert_eq!(rows[0].join(","), IP_CHANGES_CSV_HEADER);
+        assert_eq!(
+            rows[2][1..],
+            ["A", "1.2.3.4", "5.6.7.8", "https://api.ipify.org"]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_store_prune() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-prune-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = HistoryConfig {
+            max_entries: 2,
+            retention_days: 30,
+            ..HistoryConfig::default()
+        };
+        let store = JsonStore::new(dir.clone(), &config);
+        // Nothing to prune before anything is written
+        assert_eq!(store.prune().unwrap(), 0);
+        assert!(!path_in(&dir).exists());
+
+        let aged = |domain: &str, days: i64| {
+            let mut entry = entry(domain, UpdateReason::Changed);
+            entry.result.timestamp -= chrono::Duration::days(days);
+            entry
+        };
+        // Written by an older version without a retention limit
+        append(
+            &path_in(&dir),
+            &[aged("old", 60), aged("x", 2), aged("a", 1), aged("b", 0)],
+            10,
+        )
+        .unwrap();
+        store
+            .append_ip_changes(&[IpChange {
+                timestamp: chrono::Utc::now() - chrono::Duration::days(60),
+                record_type: RecordType::A,
+                old_ip: None,
+                new_ip: "1.2.3.4".parse().unwrap(),
+                service: None,
+            }])
+            .unwrap();
+
+        assert_eq!(store.prune().unwrap(), 3);
+        let domains: Vec<_> = store
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.result.domain)
+            .collect();
+        assert_eq!(domains, ["a", "b"]);
+        assert!(store.ip_changes().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reads_entries_without_reason() {
+        // Written before reasons and sources were recorded
+        let line = r#"{"provider":"duckdns","domain":"home.duckdns.org","record_type":"A","success":true,"ip":"1.2.3.4","previous_ip":null,"error":null,"timestamp":"2024-05-01T12:00:00Z"}"#;
+        let entry: HistoryEntry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.reason, UpdateReason::Changed);
+        assert_eq!(entry.source, IpSource::Detected);
+        assert_eq!(entry.outcome(), "OK (changed)");
+
+        let mut skipped = entry.clone();
+        skipped.reason = UpdateReason::NotEligibleInterval;
+        let line = serde_json::to_string(&skipped).unwrap();
+        assert!(line.contains(r#""reason":"not_eligible_interval""#));
+        assert!(serde_json::from_str::<HistoryEntry>(&line)
+            .unwrap()
+            .is_skip());
+    }
+
+    #[test]
+    fn test_updates_csv_round_trip() {
+        let mut failed = entry("a.duckdns.org", UpdateReason::Forced);
+        failed.result.success = false;
+        failed.result.error = Some("HTTP 400: \"bad, request\"\nretry later".to_string());
+        failed.result.error_kind = Some(UpdateErrorKind::ProviderRejected);
+        failed.source = IpSource::Manual;
+        let entries = [entry("b.duckdns.org", UpdateReason::Changed), failed];
+
+        let csv = updates_csv(&entries);
+        assert!(csv.contains(r#""HTTP 400: ""bad, request"""#));
+
+        let rows = parse_csv(&csv);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].join(","), UPDATES_CSV_HEADER);
+        for (row, entry) in rows[1..].iter().zip(&entries) {
+            assert_eq!(row.len(), 12);
+            assert_eq!(row[0], entry.result.timestamp.to_rfc3339());
+            assert_eq!(row[3], entry.result.domain);
+            assert_eq!(row[7], entry.result.success.to_string());
+            assert_eq!(row[8], entry.reason.as_str());
+            assert_eq!(row[9], entry.result.error.clone().unwrap_or_default());
+            assert_eq!(row[10], entry.source.as_str());
+        }
+        assert_eq!(rows[2][11], "provider_rejected");
+    }
+
+    /// Parse CSV with quoted fields (RFC 4180).
+    fn parse_csv(content: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = content.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                ('"', _) => quoted = !quoted,
+                (',', false) => row.push(std::mem::take(&mut field)),
+                ('\n', false) => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                (c, _) => field.push(c),
+            }
+        }
+        rows
+    }
+}
@@ -4,11 +4,12 @@
 //!
 //! ## Features
 //!
-//! - Multiple DDNS providers: Cloudflare, Namecheap, DuckDNS, GoDaddy
+//! - Multiple DDNS providers: Cloudflare, Namecheap, DuckDNS, GoDaddy, Vultr,
+//!   Yandex Cloud DNS, DNS-O-Matic, and any registrar's dynamic update URL
 //! - Automatic IP change detection
 //! - MCP server for AI assistant integration (Claude Code, etc.)
 //! - Daemon mode with configurable check interval
-//! - SSH tunnel support for remote management
+//! - Reverse SSH tunnel for remote management behind NAT
 //!
 //! ## Usage
 //!
@@ -27,11 +28,44 @@
 //! ```
 
 pub mod config;
+#[cfg(unix)]
+pub mod control;
+pub mod daemon;
 pub mod detector;
+pub mod doctor;
+pub mod edit;
 pub mod error;
+pub mod history;
+pub mod http;
+pub mod lock;
+pub mod logfile;
 pub mod mcp;
+pub mod metrics;
+pub mod names;
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+pub mod netwatch;
+pub mod notify;
 pub mod providers;
+pub mod report;
+pub mod schema;
+pub mod secret;
+pub mod service;
+pub mod setup;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod summary;
+pub mod systemd;
+pub mod table;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod tunnel;
+pub mod updater;
+pub mod upgrade;
 
 pub use config::Config;
 pub use detector::IpDetector;
 pub use error::{DdnsError, Result};
+pub use state::State;
@@ -0,0 +1,230 @@
+//! Advisory file locking and PID files.
+
+use crate::error::{DdnsError, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An exclusive advisory lock held on a file until dropped.
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Acquire an exclusive lock, blocking until it is available.
+    pub fn exclusive(path: &Path) -> Result<Self> {
+        let file = open_lock_file(path)?;
+        flock(&file, true)?;
+        Ok(Self { file })
+    }
+
+    /// Try to acquire an exclusive lock without blocking.
+    ///
+    /// Returns `Ok(None)` if another process holds the lock.
+    pub fn try_exclusive(path: &Path) -> Result<Option<Self>> {
+        let file = open_lock_file(path)?;
+        match flock(&file, false) {
+            Ok(()) => Ok(Some(Self { file })),
+            Err(DdnsError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the locked file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+#[cfg(unix)]
+fn flock(file: &File, blocking: bool) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut operation = libc::LOCK_EX;
+    if !blocking {
+        operation |= libc::LOCK_NB;
+    }
+
+    loop {
+        // SAFETY: the file descriptor is valid for the lifetime of `file`.
+        let ret = unsafe { libc::flock(file.as_raw_fd(), operation) };
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn flock(_file: &File, _blocking: bool) -> Result<()> {
+    // Advisory locking is not available on this platform
+    Ok(())
+}
+
+/// A PID file holding an exclusive lock for the lifetime of the process.
+///
+/// The file is removed when dropped. A file left behind by a crashed
+/// process is not locked and gets reclaimed by the next instance.
+pub struct PidFile {
+    lock: FileLock,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Acquire the PID file, failing if another instance holds it.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let Some(lock) = FileLock::try_exclusive(path)? else {
+            let mut content = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut content))
+                .ok();
+
+            return Err(DdnsError::AlreadyRunning {
+                pid: content.trim().to_string(),
+                path: path.display().to_string(),
+            });
+        };
+
+        let mut file = lock.file();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+
+        Ok(Self {
+            lock,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Get the PID written to the PID file at `path`, if a running process
+    /// holds it.
+    pub fn holder(path: &Path) -> Option<String> {
+        let mut file = File::open(path).ok()?;
+        // Taking the lock means nobody holds it; it's released on return
+        if flock(&file, false).is_ok() {
+            return None;
+        }
+
+        let mut content = String::new();
+        file.read_to_string(&mut content).ok()?;
+        Some(content.trim().to_string())
+    }
+
+    /// Get the default PID file path.
+    ///
+    /// `$XDG_RUNTIME_DIR/rusty-dns.pid`, or `/run/rusty-dns.pid` when running
+    /// as root, otherwise inside `fallback_dir`.
+    pub fn default_path(fallback_dir: &Path) -> PathBuf {
+        runtime_path("rusty-dns.pid", fallback_dir)
+    }
+}
+
+/// Get the path of a runtime file (PID file, control socket).
+///
+/// `$XDG_RUNTIME_DIR/<name>`, or `/run/<name>` when running as root,
+/// otherwise inside `fallback_dir`.
+pub fn runtime_path(name: &str, fallback_dir: &Path) -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join(name);
+    }
+
+    if is_root() {
+        return Path::new("/run").join(name);
+    }
+
+    fallback_dir.join(name)
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Remove while still holding the lock so no other instance sees a
+        // half-removed file
+        let _ = self.lock.file().set_len(0);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_root() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rusty-dns-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_pid_file_prevents_second_instance() {
+        let path = temp_path("pid-concurrent");
+
+        let pid_file = PidFile::acquire(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.trim(), std::process::id().to_string());
+
+        match PidFile::acquire(&path) {
+            Err(DdnsError::AlreadyRunning { pid, .. }) => {
+                assert_eq!(pid, std::process::id().to_string())
+            }
+            _ => panic!("expected AlreadyRunning"),
+        }
+
+        assert_eq!(PidFile::holder(&path), Some(std::process::id().to_string()));
+
+        drop(pid_file);
+        assert!(!path.exists());
+        assert_eq!(PidFile::holder(&path), None);
+    }
+
+    #[test]
+    fn test_stale_pid_file_is_reclaimed() {
+        let path = temp_path("pid-stale");
+        std::fs::write(&path, "999999\n").unwrap();
+
+        assert_eq!(PidFile::holder(&path), None);
+
+        let pid_file = PidFile::acquire(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.trim(), std::process::id().to_string());
+        drop(pid_file);
+    }
+
+    #[test]
+    fn test_file_lock_released_on_drop() {
+        let path = temp_path("lock");
+
+        let lock = FileLock::exclusive(&path).unwrap();
+        assert!(FileLock::try_exclusive(&path).unwrap().is_none());
+        drop(lock);
+        assert!(FileLock::try_exclusive(&path).unwrap().is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
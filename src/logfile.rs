@@ -0,0 +1,304 @@
+//! Log file of the daemon, rotated by size, for systems without journald.
+
+use crate::config::{DaemonConfig, LogFormat};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// A file rotated once a write would grow it past a size limit:
+/// `daemon.log` is renamed to `daemon.log.1`, `daemon.log.1` to
+/// `daemon.log.2` and so on, dropping the oldest beyond the number kept.
+///
+/// A write is never split across two files, so lines written at once (as
+/// the tracing formatters do) stay whole.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    keep: usize,
+    file: File,
+    size: u64,
+    /// Whether a write failed halfway, possibly leaving a partial line.
+    interrupted: bool,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it and its directory if
+    /// missing, to be rotated past `max_size` bytes (`None` = never) with
+    /// `keep` rotated files.
+    ///
+    /// A partial last line, e.g. left by a crash during a write, is ended
+    /// so the next line starts on its own.
+    pub fn open(path: &Path, max_size: Option<u64>, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = Self {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file: open_append(path)?,
+            size: 0,
+            interrupted: false,
+        };
+        file.end_partial_line()?;
+        Ok(file)
+    }
+
+    /// Get the path of the `index`th rotated file, e.g. `daemon.log.2`.
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    /// Read the size of the file from disk and end its last line if it
+    /// is partial.
+    fn end_partial_line(&mut self) -> io::Result<()> {
+        self.size = self.file.metadata()?.len();
+        if self.size == 0 {
+            return Ok(());
+        }
+        let mut last = [0];
+        self.file.seek(SeekFrom::End(-1))?;
+        self.file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            self.file.write_all(b"\n")?;
+            self.size += 1;
+        }
+        Ok(())
+    }
+
+    /// Shift the rotated files by one, move the file to `.1` and start an
+    /// empty one. The file is synced first, so no line is lost to a crash
+    /// during the renames.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(self.rotated_path(self.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for index in (1..self.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Prepare writing `len` bytes: recover from an interrupted write and
+    /// rotate if they wouldn't fit.
+    fn prepare(&mut self, len: usize) -> io::Result<()> {
+        if self.interrupted {
+            self.end_partial_line()?;
+            self.interrupted = false;
+        }
+        let full = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len as u64 > max);
+        if full {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.prepare(buf.len())?;
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.prepare(buf.len())?;
+        match self.file.write_all(buf) {
+            Ok(()) => {
+                self.size += buf.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.interrupted = true;
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Build a tracing layer writing to the daemon's `log_file`, if set.
+pub fn layer<S>(config: &DaemonConfig) -> io::Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(path) = &config.log_file else {
+        return Ok(None);
+    };
+    let file = RotatingFile::open(path, config.log_max_size(), config.log_keep_files)?;
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(Mutex::new(file));
+    Ok(Some(match config.log_format {
+        LogFormat::Plain => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-logfile-rotation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("logs/daemon.log");
+        let mut file = RotatingFile::open(&path, Some(10), 2).unwrap();
+
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        assert_eq!(read(&path), "second\n");
+        assert_eq!(read(&file.rotated_path(1)), "first\n");
+
+        file.write_all(b"third\n").unwrap();
+        file.write_all(b"fourth\n").unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&file.rotated_path(1)), "third\n");
+        assert_eq!(read(&file.rotated_path(2)), "second\n");
+        assert!(!file.rotated_path(3).exists());
+
+        // A line longer than the limit goes to a file of its own, whole
+        file.write_all(b"a much longer line\n").unwrap();
+        file.write_all(b"fifth\n").unwrap();
+        assert_eq!(read(&file.rotated_path(1)), "a much longer line\n");
+        assert_eq!(read(&path), "fifth\n");
+
+        // Reopening picks up the size of the existing file
+        let mut file = RotatingFile::open(&path, Some(10), 2).unwrap();
+        file.write_all(b"sixth\n").unwrap();
+        assert_eq!(read(&path), "sixth\n");
+        assert_eq!(read(&file.rotated_path(1)), "fifth\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_limits() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-logfile-limits-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("daemon.log");
+
+        // Nothing kept: the file starts over
+        let mut file = RotatingFile::open(&path, Some(8), 0).unwrap();
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        assert_eq!(read(&path), "second\n");
+        assert!(!file.rotated_path(1).exists());
+
+        // No limit: never rotated
+        let mut file = RotatingFile::open(&path, None, 2).unwrap();
+        for _ in 0..10 {
+            file.write_all(b"line\n").unwrap();
+        }
+        assert_eq!(read(&path).lines().count(), 11);
+        assert!(!file.rotated_path(1).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_line_recovery() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-logfile-partial-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("daemon.log");
+        fs::write(&path, "complete\ncut off mid-wr").unwrap();
+
+        let mut file = RotatingFile::open(&path, Some(1024), 1).unwrap();
+        file.write_all(b"next\n").unwrap();
+        assert_eq!(read(&path), "complete\ncut off mid-wr\nnext\n");
+
+        // After a failed write, the next line starts on its own too
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"half")
+            .unwrap();
+        file.interrupted = true;
+        file.write_all(b"after\n").unwrap();
+        assert_eq!(read(&path), "complete\ncut off mid-wr\nnext\nhalf\nafter\n");
+        assert_eq!(file.size, read(&path).len() as u64);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_layer_formats() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-logfile-layer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (format, expected) in [
+            (
+                LogFormat::Plain,
+                " INFO rusty_dns::logfile::tests: cycle done",
+            ),
+            (LogFormat::Json, r#""message":"cycle done""#),
+        ] {
+            let path = dir.join(format!("{:?}.log", format));
+            let config = DaemonConfig {
+                log_file: Some(path.clone()),
+                log_format: format,
+                ..Default::default()
+            };
+            let subscriber = tracing_subscriber::registry().with(layer(&config).unwrap());
+            tracing::subscriber::with_default(subscriber, || tracing::info!("cycle done"));
+            let log = read(&path);
+            assert!(log.contains(expected), "{:?}: {}", format, log);
+            assert!(log.ends_with('\n'));
+        }
+
+        assert!(
+            layer::<tracing_subscriber::Registry>(&DaemonConfig::default())
+                .unwrap()
+                .is_none()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
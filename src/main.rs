@@ -1,12 +1,37 @@
 //! rusty-dns - Dynamic DNS client with MCP support.
 
-use clap::{Parser, Subcommand};
-use rusty_dns::config::Config;
+use clap::{Parser, Subcommand, ValueEnum};
+use rusty_dns::config::{Config, ConfigFormat, HistoryBackend, IpVersion, ProviderConfig};
+use rusty_dns::daemon::{CancellationToken, Daemon, DaemonEvent};
 use rusty_dns::detector::IpDetector;
+use rusty_dns::history::{self, HistoryFilter, IpSource, UpdateReason};
+use rusty_dns::logfile;
 use rusty_dns::mcp::McpServer;
-use rusty_dns::providers::create_provider;
-use std::path::PathBuf;
-use std::time::Duration;
+use rusty_dns::notify::Notifications;
+use rusty_dns::providers::{create_provider, DdnsProvider, ProviderCapabilities, RecordType};
+use rusty_dns::report::{
+    query_records, CheckStatus, DiffReport, Health, IpReport, LiveCheck, RecordReport,
+    RecordSource, StatusReport, SyncStatus, UpdateReport, UpdateStatus, ValidationReport, Verdict,
+    TIMING_SAMPLES,
+};
+use rusty_dns::service::{self, ServiceCommand};
+use rusty_dns::setup::{self, SecretStorage, SetupOptions};
+use rusty_dns::stats::{self, CycleStats};
+use rusty_dns::table::{Cell, Color, Table};
+use rusty_dns::tunnel::TunnelStatus;
+use rusty_dns::updater::{DetectedIps, UpdatePolicy, Updater};
+use rusty_dns::upgrade::BinaryWatch;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+/// Layer writing to the daemon's log file, set once its config is loaded.
+type LogFileLayer = Option<Box<dyn Layer<Registry> + Send + Sync>>;
 
 #[derive(Parser)]
 #[command(name = "rusty-dns")]
@@ -17,34 +42,422 @@ struct Cli {
     #[arg(short, long, global = true)]
     config: Option<PathBuf>,
 
+    /// Config profile to apply (default: $RUSTY_DNS_PROFILE)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Output format of `status`, `update` and `validate`
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Output format of command results.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// A JSON document on stdout
+    Json,
+    /// Comma-separated values (`history` only)
+    Csv,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Show current DDNS status
-    Status,
+    Status {
+        /// Only show this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+
+        /// Keep refreshing the status until interrupted
+        #[arg(short, long)]
+        watch: bool,
+
+        /// Also show trends of the daemon's recent cycles: address changes,
+        /// detection latency and failure rates
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Seconds between refreshes with --watch (provider records are
+        /// queried at most once a minute)
+        #[arg(
+            short,
+            long,
+            default_value_t = 10,
+            requires = "watch",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        interval: u64,
+    },
+
+    /// Print the public IP address, without touching any provider
+    Ip {
+        /// Detect the IPv6 address instead of the IPv4 address
+        #[arg(long, conflicts_with = "both")]
+        ipv6: bool,
+
+        /// Detect the IPv4 and the IPv6 address concurrently (succeeds if
+        /// either is found)
+        #[arg(long)]
+        both: bool,
+
+        /// Query every detection service and print each answer and latency
+        #[arg(long)]
+        all_services: bool,
+    },
+
+    /// Check that every record holds the public IP (exit code 0 when it
+    /// does, 1 on a mismatch, 2 if detection failed, 3 if a provider is
+    /// unhealthy)
+    Check {
+        /// Only check this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+    },
+
+    /// Compare each live record with the public IP without updating
+    /// anything (exit code 1 when a record would change)
+    Diff {
+        /// Only compare this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+    },
 
     /// Force update DNS records
     Update {
         /// Update even if IP hasn't changed
         #[arg(short, long)]
         force: bool,
+
+        /// With --force, also update records updated to the same address
+        /// less than `min_update_interval_secs` ago
+        #[arg(long, requires = "force")]
+        yes_really: bool,
+
+        /// Only update this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+
+        /// Publish this address instead of the detected public IP
+        #[arg(long, value_name = "ADDR")]
+        ip: Option<IpAddr>,
+    },
+
+    /// Show recent updates from the persistent history
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+
+        /// Only show this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+
+        /// Only show failed updates
+        #[arg(long)]
+        failed_only: bool,
+
+        /// Only show entries since a timestamp, a date (2024-05-01) or an
+        /// age (30m, 12h, 7d)
+        #[arg(long)]
+        since: Option<String>,
     },
 
     /// Run as daemon (background service)
     Daemon {
-        /// Check interval in seconds
-        #[arg(short, long, default_value = "300")]
-        interval: u64,
+        /// Check interval in seconds (default: `check_interval_secs` from
+        /// the config)
+        #[arg(short, long)]
+        interval: Option<u64>,
+
+        /// Restart into the new binary, with the same arguments, when the
+        /// rusty-dns binary is upgraded (Unix; Windows only warns)
+        #[arg(long)]
+        reexec_on_upgrade: bool,
+
+        /// Never restart on upgrades, even with --reexec-on-upgrade
+        #[arg(long)]
+        no_reexec: bool,
     },
 
+    /// Install or control the daemon as a system service (launchd on macOS,
+    /// Windows service on Windows)
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+
+    /// Trigger an immediate update cycle in the running daemon
+    Trigger,
+
     /// Run MCP server over stdio
     Mcp,
 
+    /// Keep a reverse SSH tunnel to the `[tunnel]` host open, exposing the
+    /// MCP server or the daemon's control socket there
+    Tunnel,
+
+    /// Diagnose the config, secrets, IP detection, providers and
+    /// notifications end to end
+    Doctor {
+        /// Send a test message through every notification channel
+        #[arg(long)]
+        send_test_notifications: bool,
+    },
+
     /// Validate configuration
-    Validate,
+    Validate {
+        /// Only check this provider (label, domain or provider type;
+        /// repeatable)
+        #[arg(short, long = "provider", value_name = "PROVIDER")]
+        providers: Vec<String>,
+
+        /// Also read each record and write it back unchanged, to check the
+        /// credentials can update it
+        #[arg(long)]
+        live: bool,
+
+        /// Check each provider's credentials 3 times and show the fastest
+        /// and average round trip
+        #[arg(long)]
+        timing: bool,
+
+        /// Only check the file against the config schema, without reading
+        /// secrets or contacting providers (e.g. in CI)
+        #[arg(long, conflicts_with_all = ["providers", "live", "timing"])]
+        schema_only: bool,
+
+        /// File checked with --schema-only (default: the config file)
+        #[arg(requires = "schema_only")]
+        file: Option<PathBuf>,
+    },
+
+    /// Set up the config step by step: detect the public IP, add providers
+    /// and keep their credentials out of the file
+    Setup {
+        /// Provider type to add, with --non-interactive
+        #[arg(long = "type", value_parser = ProviderConfig::TYPES, requires = "non_interactive")]
+        r#type: Option<String>,
+
+        /// Provider setting (repeatable) with --non-interactive, e.g.
+        /// `--field zone_id=abc123`
+        #[arg(
+            long = "field",
+            value_name = "KEY=VALUE",
+            value_parser = parse_field,
+            requires = "non_interactive"
+        )]
+        fields: Vec<(String, String)>,
+
+        /// Where to keep credentials (default: ask, or the config file with
+        /// --non-interactive)
+        #[arg(long, value_enum)]
+        secrets: Option<SecretsArg>,
+
+        /// Test the credentials against the provider before saving
+        #[arg(long)]
+        check: bool,
+
+        /// Show what the first update would change
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Don't prompt: add the provider given by --type and --field
+        #[arg(long, requires = "type")]
+        non_interactive: bool,
+    },
+
+    /// Create, inspect and edit the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Print the JSON Schema of the config file, for editors and CI
+    Schema,
+
+    /// Manage credentials stored in the OS keyring
+    #[cfg(feature = "keyring")]
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServiceAction {
+    /// Register the daemon with the service manager and start it
+    Install,
+    /// Stop the daemon and remove it from the service manager
+    Uninstall,
+    /// Start the installed service
+    Start,
+    /// Stop the installed service
+    Stop,
+    /// Run the daemon as the Windows service (started by the service
+    /// manager)
+    #[command(hide = true)]
+    Run,
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Dump the update history or the IP change log
+    Export {
+        /// File format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// Which log to export
+        #[arg(long, value_enum, default_value_t = ExportKind::Updates)]
+        kind: ExportKind,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Copy the history and state into another backend
+    Migrate {
+        /// Backend to copy into, from the other one
+        #[arg(long, value_enum)]
+        to: BackendArg,
+    },
+}
+
+/// Backend named by `history migrate --to`.
+#[derive(Clone, Copy, ValueEnum)]
+enum BackendArg {
+    /// JSON files in the state directory
+    Json,
+    /// A SQLite database in the state directory
+    Sqlite,
+}
+
+impl From<BackendArg> for HistoryBackend {
+    fn from(backend: BackendArg) -> Self {
+        match backend {
+            BackendArg::Json => Self::Json,
+            BackendArg::Sqlite => Self::Sqlite,
+        }
+    }
+}
+
+/// Credential storage named by `setup --secrets`.
+#[derive(Clone, Copy, ValueEnum)]
+enum SecretsArg {
+    /// Environment variables, referenced as `$NAME`
+    Env,
+    /// The OS keyring (requires the `keyring` feature)
+    Keyring,
+    /// The config file, in plain text
+    Plaintext,
+}
+
+impl From<SecretsArg> for SecretStorage {
+    fn from(secrets: SecretsArg) -> Self {
+        match secrets {
+            SecretsArg::Env => Self::Env,
+            SecretsArg::Keyring => Self::Keyring,
+            SecretsArg::Plaintext => Self::Plaintext,
+        }
+    }
+}
+
+/// File format of `history export`.
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// A JSON array
+    Json,
+}
+
+/// Log dumped by `history export`.
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportKind {
+    /// Updates sent to providers
+    Updates,
+    /// Changes of the detected public IP
+    IpChanges,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Write an example config file
+    Init {
+        /// Overwrite an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the effective configuration, with credentials redacted
+    Show,
+
+    /// Add a provider entry, prompting for its settings
+    AddProvider {
+        /// Provider type
+        #[arg(value_parser = ProviderConfig::TYPES)]
+        r#type: String,
+
+        /// Provider setting (repeatable), e.g. `--field zone_id=abc123`
+        #[arg(long = "field", value_name = "KEY=VALUE", value_parser = parse_field)]
+        fields: Vec<(String, String)>,
+
+        /// Don't prompt for settings missing from `--field`
+        #[arg(long)]
+        non_interactive: bool,
+
+        /// Test the credentials against the provider before saving
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Remove a provider entry
+    RemoveProvider {
+        /// Provider label or domain
+        name: String,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+fn parse_field(field: &str) -> Result<(String, String), String> {
+    field
+        .split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got \"{}\"", field))
+}
+
+#[cfg(feature = "keyring")]
+#[derive(Subcommand)]
+enum SecretAction {
+    /// Store a secret (read from the terminal, or from stdin when piped)
+    Set {
+        /// Entry name ("<entry>" or "<service>/<entry>"), referenced in the
+        /// config as "keyring:rusty-dns/<entry>"
+        name: String,
+    },
+
+    /// Delete a stored secret
+    Rm {
+        /// Entry name ("<entry>" or "<service>/<entry>")
+        name: String,
+    },
 }
 
 fn get_config_path(cli_path: Option<PathBuf>) -> PathBuf {
@@ -52,16 +465,19 @@ fn get_config_path(cli_path: Option<PathBuf>) -> PathBuf {
         return path;
     }
 
-    // Default locations
-    let candidates = [
-        dirs::config_dir().map(|p| p.join("rusty-dns/config.toml")),
-        Some(PathBuf::from("/etc/rusty-dns/config.toml")),
-        Some(PathBuf::from("config.toml")),
+    // Default locations, each probed for every supported format
+    let dirs = [
+        dirs::config_dir().map(|p| p.join("rusty-dns")),
+        Some(PathBuf::from("/etc/rusty-dns")),
+        Some(PathBuf::new()),
     ];
 
-    for candidate in candidates.into_iter().flatten() {
-        if candidate.exists() {
-            return candidate;
+    for dir in dirs.into_iter().flatten() {
+        for name in ConfigFormat::FILE_NAMES {
+            let candidate = dir.join(name);
+            if candidate.exists() {
+                return candidate;
+            }
         }
     }
 
@@ -73,204 +489,1328 @@ fn get_config_path(cli_path: Option<PathBuf>) -> PathBuf {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let (log_file, log_file_handle) = reload::Layer::new(LogFileLayer::None);
+    tracing_subscriber::registry()
+        .with(log_file)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        )
+        .init();
+
     let cli = Cli::parse();
     let config_path = get_config_path(cli.config);
+    let profile = cli.profile.as_deref();
+    let output = cli.output;
+
+    if output == OutputFormat::Csv && !matches!(cli.command, Commands::History { .. }) {
+        anyhow::bail!("--output csv is only supported by `history`");
+    }
 
     match cli.command {
-        Commands::Status => {
-            let config = Config::load_from(&config_path)?;
-            cmd_status(config).await?;
+        Commands::Status {
+            providers,
+            watch,
+            verbose,
+            interval,
+        } => {
+            let mut config = Config::load_with_profile(&config_path, profile)?;
+            config.filter_providers(&providers)?;
+            if watch {
+                if output != OutputFormat::Text {
+                    anyhow::bail!("--watch only supports text output");
+                }
+                cmd_status_watch(config, Duration::from_secs(interval), verbose).await?;
+            } else {
+                cmd_status(config, output, verbose).await?;
+            }
         }
-        Commands::Update { force } => {
-            let config = Config::load_from(&config_path)?;
-            cmd_update(config, force).await?;
+        Commands::Ip {
+            ipv6,
+            both,
+            all_services,
+        } => {
+            let version = match (ipv6, both) {
+                (_, true) => IpVersion::Both,
+                (true, _) => IpVersion::V6,
+                _ => IpVersion::V4,
+            };
+            cmd_ip(&config_path, profile, version, all_services, output).await?;
         }
-        Commands::Daemon { interval } => {
-            let config = Config::load_from(&config_path)?;
-            cmd_daemon(config, interval).await?;
+        Commands::Check { providers } => {
+            let mut config = Config::load_with_profile(&config_path, profile)?;
+            config.filter_providers(&providers)?;
+            cmd_check(config, output).await?;
+        }
+        Commands::Diff { providers } => {
+            let mut config = Config::load_with_profile(&config_path, profile)?;
+            config.filter_providers(&providers)?;
+            cmd_diff(config, output).await?;
+        }
+        Commands::Update {
+            force,
+            yes_really,
+            providers,
+            ip,
+        } => {
+            let mut config = Config::load_with_profile(&config_path, profile)?;
+            config.filter_providers(&providers)?;
+            let bypass_guard = force && yes_really;
+            cmd_update(
+                config,
+                force,
+                bypass_guard,
+                !providers.is_empty(),
+                ip,
+                output,
+            )
+            .await?;
+        }
+        Commands::History {
+            action: Some(HistoryAction::Export { format, kind, out }),
+            ..
+        } => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            cmd_history_export(config, format, kind, out)?;
+        }
+        Commands::History {
+            action: Some(HistoryAction::Migrate { to }),
+            ..
+        } => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            cmd_history_migrate(config, to.into())?;
+        }
+        Commands::History {
+            action: None,
+            limit,
+            providers,
+            failed_only,
+            since,
+        } => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            let filter = HistoryFilter {
+                limit: Some(limit),
+                providers,
+                failed_only,
+                since: since
+                    .map(|since| history::parse_since(&since, chrono::Utc::now()))
+                    .transpose()?,
+            };
+            cmd_history(config, filter, output)?;
+        }
+        Commands::Daemon {
+            interval,
+            reexec_on_upgrade,
+            no_reexec,
+        } => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            if let Some(path) = &config.daemon.log_file {
+                let layer = logfile::layer(&config.daemon).map_err(|e| {
+                    anyhow::anyhow!("Failed to open log file {}: {}", path.display(), e)
+                })?;
+                log_file_handle.reload(layer)?;
+            }
+            let cancel = cancel_on_shutdown()?;
+            let mut daemon = daemon(&config_path, config, interval, cancel);
+            if reexec_on_upgrade && !no_reexec {
+                daemon = daemon.with_upgrade_watch(BinaryWatch::current()?);
+            }
+            daemon.run().await?;
+        }
+        Commands::Service { action } => cmd_service(action, &config_path, profile).await?,
+        Commands::Trigger => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            cmd_trigger(config).await?;
         }
         Commands::Mcp => {
-            let config = Config::load_from(&config_path)?;
-            cmd_mcp(config).await?;
+            let config = Config::load_with_profile(&config_path, profile)?;
+            cmd_mcp(config, &config_path).await?;
+        }
+        Commands::Tunnel => {
+            let config = Config::load_with_profile(&config_path, profile)?;
+            cmd_tunnel(&config_path, config).await?;
+        }
+        Commands::Validate {
+            schema_only: true,
+            file,
+            ..
+        } => cmd_validate_schema(file.as_deref().unwrap_or(&config_path), output)?,
+        Commands::Validate {
+            providers,
+            live,
+            timing,
+            ..
+        } => {
+            let mut config = Config::load_unchecked(&config_path, true, profile)?;
+            config.filter_providers(&providers)?;
+            cmd_validate(config, live, timing, output).await?;
         }
-        Commands::Validate => {
-            let config = Config::load_from(&config_path)?;
-            cmd_validate(config).await?;
+        Commands::Doctor {
+            send_test_notifications,
+        } => cmd_doctor(&config_path, profile, send_test_notifications, output).await?,
+        Commands::Setup {
+            r#type,
+            fields,
+            secrets,
+            check,
+            dry_run,
+            non_interactive,
+        } => {
+            // Flags answer the questions; without prompts, unset ones are off
+            let options = SetupOptions {
+                secrets: secrets.map(Into::into),
+                check: (check || non_interactive).then_some(check),
+                dry_run: (dry_run || non_interactive).then_some(dry_run),
+            };
+            let preset = r#type.map(|kind| (kind, fields.into_iter().collect()));
+            cmd_setup(&config_path, profile, preset, options).await?;
         }
+        Commands::Config { action } => cmd_config(action, &config_path, profile).await?,
+        Commands::Schema => println!(
+            "{}",
+            serde_json::to_string_pretty(&rusty_dns::schema::config_schema())?
+        ),
+        #[cfg(feature = "keyring")]
+        Commands::Secret { action } => cmd_secret(action)?,
     }
 
     Ok(())
 }
 
-async fn cmd_status(config: Config) -> anyhow::Result<()> {
-    let detector = IpDetector::new();
-
-    println!("rusty-dns Status");
-    println!("================\n");
+async fn cmd_status(config: Config, output: OutputFormat, verbose: bool) -> anyhow::Result<()> {
+    // Prefer the running daemon's view over querying providers again
+    let mut report = match daemon_report(&config).await {
+        Some(report) => report,
+        None => StatusReport::collect(&config, &IpDetector::from_config(&config)).await,
+    };
+    if verbose {
+        report = report.with_stats(&config);
+    }
 
-    // Detect current IP
-    match detector.detect_ipv4().await {
-        Ok(ip) => println!("Current Public IP: {}", ip),
-        Err(e) => println!("Failed to detect IP: {}", e),
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_status(&report);
     }
 
-    println!("\nProviders:");
-    println!("---------");
+    Ok(())
+}
+
+/// Minimum time between two queries of the provider APIs in `status --watch`.
+const WATCH_RECORD_INTERVAL: Duration = Duration::from_secs(60);
 
-    for provider_config in &config.providers {
-        let provider = create_provider(provider_config);
+async fn cmd_status_watch(config: Config, interval: Duration, verbose: bool) -> anyhow::Result<()> {
+    // Created once so their HTTP clients are reused across refreshes
+    let detector = IpDetector::from_config(&config);
+    let providers: Vec<Box<dyn DdnsProvider>> = config
+        .providers
+        .iter()
+        .map(|p| create_provider(p, config.default_ttl, &config.network))
+        .collect();
+    let mut records = Vec::new();
+    let mut queried: Option<Instant> = None;
 
-        print!("  {} ({}): ", provider.name(), provider.domain());
+    let terminal = std::io::stdout().is_terminal();
+    let shutdown = shutdown_signal()?;
+    tokio::pin!(shutdown);
 
-        match provider.get_current_ip().await {
-            Ok(Some(ip)) => println!("{}", ip),
-            Ok(None) => println!("(no record)"),
-            Err(e) => println!("error: {}", e),
+    loop {
+        let refresh = async {
+            if let Some(report) = daemon_report(&config).await {
+                return report;
+            }
+            if queried.map_or(true, |at| at.elapsed() >= WATCH_RECORD_INTERVAL) {
+                records = query_records(&config, &providers).await;
+                queried = Some(Instant::now());
+            }
+            StatusReport::with_records(&config, &detector, &records).await
+        };
+        let mut report = tokio::select! {
+            _ = &mut shutdown => break,
+            report = refresh => report,
+        };
+        if verbose {
+            report = report.with_stats(&config);
+        }
+
+        if terminal {
+            // Clear the screen and move the cursor to the top left
+            print!("\x1b[2J\x1b[H");
+        } else {
+            println!();
+        }
+        print_status(&report);
+        println!(
+            "\nRefreshed at {} every {}s{}; press Ctrl-C to exit",
+            chrono::Local::now().format("%H:%M:%S"),
+            interval.as_secs(),
+            match report.daemon {
+                Some(_) => " from the daemon",
+                None => " (records every 60s)",
+            }
+        );
+        std::io::stdout().flush()?;
+
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(interval) => {}
         }
     }
 
     Ok(())
 }
 
-async fn cmd_update(config: Config, force: bool) -> anyhow::Result<()> {
-    let detector = IpDetector::new();
-    let current_ip = detector.detect_ipv4().await?;
+/// Get the running daemon's status of the configured providers, if a daemon
+/// answers on the control socket.
+async fn daemon_report(config: &Config) -> Option<StatusReport> {
+    #[cfg(unix)]
+    {
+        let mut status = query_daemon_status(config).await?;
+        status
+            .providers
+            .retain(|p| config.providers.iter().any(|c| c.label() == p.label));
+        status.tunnel = TunnelStatus::load(config);
+        status = status.with_groups(config);
+        if config.ip_version.includes_v4() {
+            let detector = IpDetector::from_config(config);
+            status.network_situation = Some(detector.network_situation(status.ipv4, false).await);
+        }
+        Some(status)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = config;
+        None
+    }
+}
+
+fn print_status(report: &StatusReport) {
+    match &report.daemon {
+        Some(daemon) => println!(
+            "rusty-dns Status (daemon PID {}, running since {}{})",
+            daemon.pid,
+            daemon.started.format("%Y-%m-%d %H:%M:%S"),
+            if daemon.paused {
+                ", automatic updates paused"
+            } else {
+                ""
+            }
+        ),
+        None => println!("rusty-dns Status"),
+    }
+    println!("================\n");
+
+    if let Some(error) = &report.detection_error {
+        println!("Failed to detect IP: {}", error);
+    }
+    for last in &report.last_known {
+        match last.confirmed {
+            Some(confirmed) => println!(
+                "Last Confirmed IP: {} ({} ago)",
+                last.ip,
+                format_age(chrono::Utc::now() - confirmed)
+            ),
+            None => println!("Last Confirmed IP: {}", last.ip),
+        }
+    }
+    if let Some(ip) = report.ipv4 {
+        println!("Current Public IP: {}", ip);
+    }
+    if let Some(ip) = report.ipv6 {
+        println!("Current Public IPv6: {}", ip);
+    }
+    if let Some(situation) = report.network_situation.as_ref().filter(|s| s.is_problem()) {
+        println!("\nWARNING: {}", situation);
+        if let Some(suggestion) = situation.suggestion() {
+            println!("  {}", suggestion);
+        }
+    }
+    if let Some(tunnel) = &report.tunnel {
+        print_tunnel_status(tunnel);
+    }
 
-    println!("Current IP: {}", current_ip);
     println!();
+    let now = chrono::Utc::now();
+    let mut table = Table::for_stdout(&[
+        "PROVIDER",
+        "DOMAIN",
+        "RECORDS",
+        "SYNC",
+        "LAST UPDATE",
+        "VERIFIED",
+        "STATUS",
+    ]);
+    for provider in &report.providers {
+        let status = if !provider.enabled {
+            Cell::colored("disabled", Color::Yellow)
+        } else if let Some(error) = &provider.error {
+            Cell::colored(format!("error: {}", error), Color::Red)
+        } else if provider.consecutive_failures > 0 {
+            Cell::colored(
+                format!("{} consecutive failures", provider.consecutive_failures),
+                Color::Red,
+            )
+        } else {
+            Cell::colored("OK", Color::Green)
+        };
 
-    for provider_config in &config.providers {
-        let provider = create_provider(provider_config);
+        table.push([
+            Cell::from(format!("{} ({})", provider.label, provider.name)),
+            Cell::from(format!("{} [{}]", provider.domain, provider.ip_version)),
+            Cell::from(if provider.enabled && provider.error.is_none() {
+                published_families(&provider.records, provider.capabilities)
+            } else {
+                String::new()
+            }),
+            match provider.sync {
+                _ if !provider.enabled => Cell::from(""),
+                SyncStatus::InSync => Cell::colored(provider.sync.to_string(), Color::Green),
+                SyncStatus::Stale | SyncStatus::Error => {
+                    Cell::colored(provider.sync.to_string(), Color::Red)
+                }
+                SyncStatus::Unknown => Cell::colored(provider.sync.to_string(), Color::Yellow),
+            },
+            Cell::from(
+                provider
+                    .last_updated
+                    .map(|at| format!("{} ago", format_age(now - at)))
+                    .unwrap_or_default(),
+            ),
+            match provider.last_verified_at {
+                Some(at) => Cell::from(format!("{} ago", format_age(now - at))),
+                None if provider.enabled => Cell::colored("never", Color::Yellow),
+                None => Cell::from(""),
+            },
+            status,
+        ]);
+    }
+    print!("{}", table);
 
-        print!("Updating {} ({})... ", provider.name(), provider.domain());
+    if !report.groups.is_empty() {
+        println!();
+    }
+    for group in &report.groups {
+        let members: Vec<String> = group
+            .members
+            .iter()
+            .map(|label| match &group.primary {
+                Some(primary) if primary == label => format!("{} (primary)", label),
+                _ => label.clone(),
+            })
+            .collect();
+        println!(
+            "Group {} ({}): {} - {}",
+            group.name,
+            group.policy,
+            if group.healthy { "OK" } else { "UNHEALTHY" },
+            members.join(", ")
+        );
+    }
+
+    print_stale_caches(report.providers.iter().flat_map(|provider| {
+        provider
+            .records
+            .iter()
+            .filter_map(|r| Some((provider.domain.as_str(), r.record_type, r.stale_until?)))
+    }));
+
+    if let Some(stats) = &report.stats {
+        print_stats(stats);
+    }
+}
+
+/// Print the trends of the daemon's recent cycles (`status --verbose`).
+fn print_stats(stats: &CycleStats) {
+    println!();
+    let Some(since) = stats.since else {
+        println!("Trends: no cycles recorded yet (the daemon records them)");
+        return;
+    };
+    println!(
+        "Trends: last {} of {} cycles recorded since {}",
+        stats.recent_cycles,
+        stats.cycles,
+        since.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+    );
+    println!(
+        "  IP changes in the last {} days: {}",
+        stats::IP_CHANGE_DAYS,
+        stats.ip_changes_7d
+    );
+    if let Some(ms) = stats.avg_detection_ms {
+        println!("  Average detection latency: {}ms", ms);
+    }
+    println!(
+        "  Detection failures: {} of {} cycles",
+        stats.detection_failures, stats.recent_cycles
+    );
+    if stats.providers.is_empty() {
+        return;
+    }
 
-        // Check if update needed
-        if !force {
-            if let Ok(Some(existing)) = provider.get_current_ip().await {
-                if existing == current_ip {
-                    println!("skipped (IP unchanged)");
-                    continue;
+    println!();
+    let mut table = Table::for_stdout(&["PROVIDER", "CYCLES", "UPDATES", "FAILED", "FAILURE RATE"]);
+    for provider in &stats.providers {
+        table.push([
+            Cell::from(provider.label.as_str()),
+            Cell::from(provider.cycles.to_string()),
+            Cell::from(provider.updates.to_string()),
+            Cell::from(provider.failures.to_string()),
+            match provider.failure_rate {
+                Some(rate) if rate > 0.0 => {
+                    Cell::colored(format!("{:.0}%", rate * 100.0), Color::Red)
                 }
+                Some(rate) => Cell::from(format!("{:.0}%", rate * 100.0)),
+                None => Cell::from("-"),
+            },
+        ]);
+    }
+    print!("{}", table);
+}
+
+/// Print until when resolvers may still answer with the old address of
+/// each recently changed `(domain, record type, until)`.
+fn print_stale_caches<'a>(
+    records: impl Iterator<Item = (&'a str, RecordType, chrono::DateTime<chrono::Utc>)>,
+) {
+    let now = chrono::Utc::now();
+    let mut records = records.peekable();
+    if records.peek().is_some() {
+        println!();
+    }
+    for (domain, record_type, until) in records {
+        println!(
+            "{} ({}): stale caches possible until {} (in {})",
+            domain,
+            record_type,
+            until.with_timezone(&chrono::Local).format("%H:%M:%S"),
+            format_age(until - now)
+        );
+    }
+}
+
+/// Format a TTL, e.g. `300s`, or `unknown` for providers not reporting it.
+fn format_ttl(ttl: Option<u32>) -> String {
+    ttl.map_or_else(|| "unknown".to_string(), |ttl| format!("{}s", ttl))
+}
+
+fn print_tunnel_status(tunnel: &TunnelStatus) {
+    let now = chrono::Utc::now();
+    let state = match (tunnel.connected_since, &tunnel.last_error) {
+        (Some(since), _) => format!("connected for {}", format_age(now - since)),
+        (None, Some(error)) => format!("reconnecting ({})", error),
+        (None, None) => "connecting".to_string(),
+    };
+    let reconnects = match tunnel.last_reconnect {
+        Some(last) => format!(
+            ", {} reconnects, last {} ago",
+            tunnel.reconnects,
+            format_age(now - last)
+        ),
+        None => String::new(),
+    };
+    println!(
+        "SSH Tunnel: {} exposed at {}, {}{}",
+        tunnel.exposes, tunnel.remote, state, reconnects
+    );
+}
+
+/// Format an age as its two largest units, e.g. `3h 12m`.
+fn format_age(age: chrono::Duration) -> String {
+    let secs = age.num_seconds().max(0);
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+async fn cmd_ip(
+    config_path: &Path,
+    profile: Option<&str>,
+    version: IpVersion,
+    all_services: bool,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    // Only the detection settings are taken from the config, so a missing or
+    // broken config still works
+    let detector = match Config::load_unchecked(config_path, false, profile) {
+        Ok(config) => IpDetector::from_config(&config),
+        Err(e) => {
+            tracing::warn!("Using the default IP services: {}", e);
+            IpDetector::new()
+        }
+    };
+
+    let report = IpReport::collect(&detector, version, all_services).await;
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        if !report.services.is_empty() {
+            let width = report.services.iter().map(|p| p.service.len()).max();
+            for probe in &report.services {
+                let answer = match (&probe.ip, &probe.error) {
+                    (Some(ip), _) => ip.to_string(),
+                    (None, error) => format!("error: {}", error.as_deref().unwrap_or_default()),
+                };
+                println!(
+                    "{:width$}  {:>6} ms  {}",
+                    probe.service,
+                    probe.latency_ms,
+                    answer,
+                    width = width.unwrap_or_default()
+                );
+            }
+            println!();
+        }
+        for detection in [&report.ipv4, &report.ipv6].into_iter().flatten() {
+            println!("{}", detection.ip);
+        }
+        for error in &report.errors {
+            eprintln!("{}", error);
+        }
+    }
+
+    // With --both, a network without IPv6 still succeeds
+    if report.ipv4.is_none() && report.ipv6.is_none() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn cmd_check(config: Config, output: OutputFormat) -> anyhow::Result<()> {
+    // Always query the providers: the daemon only knows what it last published
+    let report = StatusReport::collect(&config, &IpDetector::from_config(&config)).await;
+    let health = report.health();
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        std::process::exit(health.exit_code());
+    }
+
+    let enabled: Vec<_> = report.providers.iter().filter(|p| p.enabled).collect();
+    match (&report.detection_error, health) {
+        (Some(error), _) => println!("{} - {}", health, error),
+        (None, Health::Ok) => println!("{} - {} provider(s) up to date", health, enabled.len()),
+        (None, _) => println!("{}", health),
+    }
+
+    for provider in enabled {
+        if let Some(error) = &provider.error {
+            println!("  {} ({}): {}", provider.label, provider.domain, error);
+        } else if provider.consecutive_failures > 0 {
+            println!(
+                "  {} ({}): {} consecutive failures",
+                provider.label, provider.domain, provider.consecutive_failures
+            );
+        }
+
+        let mut mismatches = provider
+            .records
+            .iter()
+            .filter(|r| r.is_mismatch())
+            .peekable();
+        if provider.error.is_none() && !provider.in_sync && mismatches.peek().is_none() {
+            println!(
+                "  {} ({}): {}",
+                provider.label, provider.domain, provider.sync
+            );
+        }
+        for record in mismatches {
+            println!(
+                "  {} ({}): {} {}, detected {}",
+                provider.label,
+                provider.domain,
+                record.record_type,
+                record
+                    .record_ip
+                    .map_or_else(|| "not published".to_string(), |ip| ip.to_string()),
+                record
+                    .detected_ip
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    std::process::exit(health.exit_code());
+}
+
+async fn cmd_diff(config: Config, output: OutputFormat) -> anyhow::Result<()> {
+    let report = DiffReport::collect(&config, &IpDetector::from_config(&config)).await;
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        if let Some(error) = &report.detection_error {
+            println!("Failed to detect IP: {}\n", error);
+        }
+
+        let mut table =
+            Table::for_stdout(&["PROVIDER", "DOMAIN", "RECORD", "TTL", "DETECTED", "RESULT"]);
+        for r in &report.records {
+            table.push([
+                Cell::from(format!("{} ({})", r.label, r.provider)),
+                Cell::from(format!("{} ({})", r.domain, r.record_type)),
+                Cell::from(match (r.record_ip, r.source) {
+                    (Some(ip), Some(RecordSource::Dns)) => format!("{} (dns)", ip),
+                    (Some(ip), _) => ip.to_string(),
+                    (None, _) => "-".to_string(),
+                }),
+                Cell::from(match r.record_ip {
+                    // Resolved through DNS, which doesn't tell the record's TTL
+                    Some(_)
+                        if !ProviderCapabilities::of_type(&r.provider)
+                            .map_or(true, |c| c.can_read_record) =>
+                    {
+                        "n/a".to_string()
+                    }
+                    Some(_) => format_ttl(r.ttl),
+                    None => "-".to_string(),
+                }),
+                Cell::from(
+                    r.detected_ip
+                        .map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+                ),
+                match (&r.error, r.verdict) {
+                    (Some(error), verdict) => {
+                        Cell::colored(format!("{}: {}", verdict, error), Color::Red)
+                    }
+                    (None, Verdict::Ok) => Cell::colored("OK", Color::Green),
+                    (None, verdict) => Cell::colored(verdict.to_string(), Color::Yellow),
+                },
+            ]);
+        }
+        print!("{}", table);
+
+        print_stale_caches(
+            report
+                .records
+                .iter()
+                .filter_map(|r| Some((r.domain.as_str(), r.record_type, r.stale_until?))),
+        );
+    }
+
+    if report.has_changes() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn cmd_update(
+    config: Config,
+    force: bool,
+    bypass_guard: bool,
+    filtered: bool,
+    manual_ip: Option<IpAddr>,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    // Selecting only disabled providers is most likely a mistake
+    if filtered && config.providers.iter().all(|p| !p.enabled) {
+        let labels: Vec<String> = config.providers.iter().map(|p| p.label()).collect();
+        anyhow::bail!("Provider {} is disabled", labels.join(", "));
+    }
+
+    let detector = IpDetector::from_config(&config);
+    let notifications = Notifications::from_config(&config);
+    let mut updater = Updater::new(&config, &detector)
+        .with_notifications(&notifications)
+        .with_guard_bypass(bypass_guard);
+    let text = output == OutputFormat::Text;
+
+    let ips = match manual_ip {
+        Some(ip) => {
+            // Every selected provider must publish the given family
+            let family = if ip.is_ipv4() { "IPv4" } else { "IPv6" };
+            let mismatched: Vec<String> = config
+                .enabled_providers()
+                .filter(|p| {
+                    let version = p.ip_version(config.ip_version);
+                    !(ip.is_ipv4() && version.includes_v4()
+                        || ip.is_ipv6() && version.includes_v6())
+                })
+                .map(|p| p.label())
+                .collect();
+            if !mismatched.is_empty() {
+                anyhow::bail!(
+                    "{} is an {} address, but {} {} addresses (select providers with --provider)",
+                    ip,
+                    family,
+                    match mismatched.as_slice() {
+                        [label] => format!("provider {} doesn't publish", label),
+                        labels => format!("providers {} don't publish", labels.join(", ")),
+                    },
+                    family
+                );
+            }
+
+            updater = updater.with_source(IpSource::Manual);
+            DetectedIps {
+                ipv4: Some(ip).filter(IpAddr::is_ipv4),
+                ipv6: Some(ip).filter(IpAddr::is_ipv6),
+                ..Default::default()
             }
         }
+        None => updater.detect().await?,
+    };
 
-        match provider.update_ip(current_ip).await {
-            Ok(result) => {
-                if result.success {
-                    if let Some(prev) = result.previous_ip {
-                        println!("OK ({} -> {})", prev, current_ip);
-                    } else {
-                        println!("OK ({})", current_ip);
+    if text {
+        let label = if manual_ip.is_some() {
+            "Given"
+        } else {
+            "Current"
+        };
+        if let Some(ip) = ips.ipv4 {
+            println!("{} IP: {}", label, ip);
+        }
+        if let Some(ip) = ips.ipv6 {
+            println!("{} IPv6: {}", label, ip);
+        }
+        println!();
+    }
+
+    let policy = if force {
+        UpdatePolicy::Force
+    } else {
+        UpdatePolicy::DriftCorrect
+    };
+    let updates = updater.update_all(&ips, policy).await;
+    let reports: Vec<UpdateReport> = updates.iter().map(UpdateReport::from).collect();
+
+    if text {
+        let mut table = Table::for_stdout(&["PROVIDER", "DOMAIN", "IP", "RESULT"]);
+        for report in &reports {
+            table.push([
+                Cell::from(format!("{} ({})", report.label, report.provider)),
+                Cell::from(format!("{} ({})", report.domain, report.record_type)),
+                Cell::from(match report.previous_ip {
+                    Some(prev) if report.status == UpdateStatus::Updated => {
+                        format!("{} -> {}", prev, report.ip)
                     }
-                } else {
-                    println!("FAILED: {}", result.error.unwrap_or_default());
-                }
+                    _ => report.ip.to_string(),
+                }),
+                match report.status {
+                    UpdateStatus::Updated => Cell::colored("OK", Color::Green),
+                    UpdateStatus::Unchanged => {
+                        Cell::colored("skipped (IP unchanged)", Color::Yellow)
+                    }
+                    UpdateStatus::Unsupported => Cell::colored("unsupported", Color::Yellow),
+                    UpdateStatus::Skipped => {
+                        Cell::colored(format!("skipped ({})", report.reason), Color::Yellow)
+                    }
+                    UpdateStatus::Failed => Cell::colored(
+                        format!("FAILED: {}", report.error.as_deref().unwrap_or_default()),
+                        Color::Red,
+                    ),
+                },
+            ]);
+        }
+        print!("{}", table);
+        if reports.iter().any(|r| r.reason == UpdateReason::RateGuard) {
+            println!(
+                "\nRecords updated to the same address less than min_update_interval_secs ago \
+                 were skipped; use --force --yes-really to send them anyway"
+            );
+        }
+    }
+
+    match updater.record(&ips, &updates).await {
+        Ok(recorded) if text => print_recovered(&recorded.recovered),
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to save state: {}", e),
+    }
+
+    if !text {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+
+    Ok(())
+}
+
+fn cmd_history(config: Config, filter: HistoryFilter, output: OutputFormat) -> anyhow::Result<()> {
+    let store = history::open(&config)?;
+    let entries = store.entries()?;
+    let selected = filter.apply(&entries);
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&selected)?),
+        OutputFormat::Csv => print!("{}", history::updates_csv(selected)),
+        OutputFormat::Text if entries.is_empty() => {
+            println!(
+                "No history yet ({} history in {} has no entries)",
+                store.backend(),
+                config.state_dir()?.display()
+            );
+        }
+        OutputFormat::Text if selected.is_empty() => println!("No matching history entries"),
+        OutputFormat::Text => {
+            let mut table = Table::for_stdout(&["TIME", "PROVIDER", "DOMAIN", "IP", "RESULT"]);
+            for entry in &selected {
+                let r = &entry.result;
+                let ip = r.ip.map(|ip| ip.to_string()).unwrap_or_default();
+                table.push([
+                    Cell::from(
+                        r.timestamp
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string(),
+                    ),
+                    Cell::from(match &r.label {
+                        Some(label) => format!("{} ({})", label, r.provider),
+                        None => r.provider.clone(),
+                    }),
+                    Cell::from(format!("{} ({})", r.domain, r.record_type)),
+                    Cell::from(match r.previous_ip {
+                        Some(prev) => format!("{} -> {}", prev, ip),
+                        None => ip,
+                    }),
+                    Cell::colored(
+                        entry.outcome(),
+                        if entry.is_skip() {
+                            Color::Yellow
+                        } else if r.success {
+                            Color::Green
+                        } else {
+                            Color::Red
+                        },
+                    ),
+                ]);
             }
-            Err(e) => println!("ERROR: {}", e),
+            print!("{}", table);
         }
     }
 
     Ok(())
 }
 
-async fn cmd_daemon(config: Config, interval: u64) -> anyhow::Result<()> {
-    let detector = IpDetector::new();
-    let interval = Duration::from_secs(interval);
+fn cmd_history_export(
+    config: Config,
+    format: ExportFormat,
+    kind: ExportKind,
+    out: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let store = history::open(&config)?;
+    let content = match kind {
+        ExportKind::Updates => {
+            let entries = store.entries()?;
+            match format {
+                ExportFormat::Csv => history::updates_csv(&entries),
+                ExportFormat::Json => serde_json::to_string_pretty(&entries)? + "\n",
+            }
+        }
+        ExportKind::IpChanges => {
+            let changes = store.ip_changes()?;
+            match format {
+                ExportFormat::Csv => history::ip_changes_csv(&changes),
+                ExportFormat::Json => serde_json::to_string_pretty(&changes)? + "\n",
+            }
+        }
+    };
 
+    match out {
+        Some(path) => std::fs::write(&path, content)
+            .map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))?,
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+fn cmd_history_migrate(config: Config, to: HistoryBackend) -> anyhow::Result<()> {
+    let migration = history::migrate(&config.state_dir()?, &config.history, to)?;
     println!(
-        "Starting rusty-dns daemon (interval: {}s)",
-        interval.as_secs()
+        "Copied {} history entries, {} IP changes and the state of {} providers into the {} backend",
+        migration.entries, migration.ip_changes, migration.providers, to
     );
+    if config.history.backend != to {
+        println!("Set `backend = \"{}\"` under [history] to use it", to);
+    }
+    Ok(())
+}
 
-    let mut last_ip = None;
+/// Set up the daemon for `config`, stopping when `cancel` is cancelled.
+fn daemon(
+    config_path: &Path,
+    config: Config,
+    interval: Option<u64>,
+    cancel: CancellationToken,
+) -> Daemon {
+    let detector = IpDetector::from_config(&config);
+    Daemon::new(config.clone(), detector, cancel)
+        .with_interval(config.check_interval(interval))
+        .with_config_path(config_path.to_path_buf())
+        .on_event(print_daemon_event)
+}
 
-    loop {
-        match detector.detect_ipv4().await {
-            Ok(current_ip) => {
-                let ip_changed = last_ip != Some(current_ip);
-
-                if ip_changed {
-                    println!(
-                        "[{}] IP changed: {:?} -> {}",
-                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
-                        last_ip,
-                        current_ip
-                    );
-
-                    for provider_config in &config.providers {
-                        let provider = create_provider(provider_config);
-                        match provider.update_ip(current_ip).await {
-                            Ok(result) => {
-                                if result.success {
-                                    println!(
-                                        "  {} ({}): updated",
-                                        provider.name(),
-                                        provider.domain()
-                                    );
-                                } else {
-                                    eprintln!(
-                                        "  {} ({}): failed - {}",
-                                        provider.name(),
-                                        provider.domain(),
-                                        result.error.unwrap_or_default()
-                                    );
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "  {} ({}): error - {}",
-                                    provider.name(),
-                                    provider.domain(),
-                                    e
-                                );
-                            }
-                        }
-                    }
+async fn cmd_service(
+    action: ServiceAction,
+    config_path: &Path,
+    profile: Option<&str>,
+) -> anyhow::Result<()> {
+    match action {
+        ServiceAction::Install => {
+            // Fail now rather than in a service that keeps restarting
+            Config::load_with_profile(config_path, profile)?;
+            service::install(&ServiceCommand::current(config_path, profile)?)?;
+            println!("Service installed and started");
+        }
+        ServiceAction::Uninstall => {
+            service::uninstall()?;
+            println!("Service uninstalled");
+        }
+        ServiceAction::Start => {
+            service::start()?;
+            println!("Service started");
+        }
+        ServiceAction::Stop => {
+            service::stop()?;
+            println!("Service stopped");
+        }
+        ServiceAction::Run => {
+            let config = Config::load_with_profile(config_path, profile)?;
+            let config_path = config_path.to_path_buf();
+            let runtime = tokio::runtime::Handle::current();
+            // The service manager calls back on a thread of its own, which
+            // runs the daemon on this runtime
+            tokio::task::spawn_blocking(move || {
+                service::run(move |cancel| {
+                    runtime.block_on(daemon(&config_path, config, None, cancel).run())
+                })
+            })
+            .await??;
+        }
+    }
+    Ok(())
+}
 
-                    last_ip = Some(current_ip);
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "[{}] Failed to detect IP: {}",
-                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
-                    e
-                );
+/// Print what the daemon does: progress to stdout, problems to stderr.
+fn print_daemon_event(event: &DaemonEvent) {
+    let now = || chrono::Utc::now().format("%Y-%m-%d %H:%M:%S");
+    match event {
+        DaemonEvent::Started { interval } => println!(
+            "Starting rusty-dns daemon (interval: {}s)",
+            interval.as_secs()
+        ),
+        DaemonEvent::WatchingNetwork => println!("Watching for network changes"),
+        DaemonEvent::ControlSocket(path) => println!("Control socket: {}", path.display()),
+        DaemonEvent::NetworkChanged => println!("[{}] Network change detected", now()),
+        DaemonEvent::UpdateTriggered => {
+            println!("[{}] Update triggered via control socket", now())
+        }
+        DaemonEvent::ConfigReloaded(path) => {
+            println!("Configuration reloaded from {}", path.display())
+        }
+        DaemonEvent::Paused => println!("[{}] Automatic updates paused", now()),
+        DaemonEvent::Resumed => println!("[{}] Automatic updates resumed", now()),
+        DaemonEvent::IntervalChanged(interval) => {
+            println!("[{}] Check interval set to {}s", now(), interval.as_secs())
+        }
+        DaemonEvent::NetworkSituation(situation) if situation.is_problem() => {
+            eprintln!("WARNING: {}", situation);
+            if let Some(suggestion) = situation.suggestion() {
+                eprintln!("  {}", suggestion);
             }
         }
+        DaemonEvent::NetworkSituation(situation) => println!("Network: {}", situation),
+        DaemonEvent::DetectionFailed(e) => eprintln!("[{}] Failed to detect IP: {}", now(), e),
+        DaemonEvent::IpChanged { previous, current } => {
+            println!("[{}] IP changed: {:?} -> {}", now(), previous, current)
+        }
+        DaemonEvent::RecordUpdated(update) => match update.error() {
+            None => println!(
+                "  {} ({}, {}): updated ({})",
+                update.label,
+                update.domain,
+                update.record_type(),
+                update.reason
+            ),
+            Some(error) => eprintln!(
+                "  {} ({}, {}): failed - {}",
+                update.label,
+                update.domain,
+                update.record_type(),
+                error
+            ),
+        },
+        DaemonEvent::Recovered(label) => print_recovered(std::slice::from_ref(label)),
+        DaemonEvent::Upgrading(path) => println!(
+            "[{}] {} was upgraded, restarting into the new version",
+            now(),
+            path.display()
+        ),
+        DaemonEvent::Warning(message) => eprintln!("{}", message),
+        DaemonEvent::Stopped => println!("Shutting down"),
+    }
+}
+
+/// Report providers whose failure streak ended.
+fn print_recovered(recovered: &[String]) {
+    for key in recovered {
+        println!("  {}: recovered", key);
+    }
+}
+
+/// Get a token cancelled on SIGINT/SIGTERM, so a daemon or tunnel run with
+/// it shuts down gracefully (like a stopped service, see
+/// [`rusty_dns::service`]).
+fn cancel_on_shutdown() -> std::io::Result<CancellationToken> {
+    let cancel = CancellationToken::new();
+    let shutdown = shutdown_signal()?;
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            shutdown.await;
+            cancel.cancel();
+        }
+    });
+    Ok(cancel)
+}
+
+/// Register handlers for SIGINT/SIGTERM, resolving when either arrives.
+fn shutdown_signal() -> std::io::Result<impl std::future::Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        Ok(async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        })
+    }
+
+    #[cfg(not(unix))]
+    {
+        Ok(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+    }
+}
+
+/// Ask a running daemon for its status over the control socket.
+#[cfg(unix)]
+async fn query_daemon_status(config: &Config) -> Option<StatusReport> {
+    use rusty_dns::control::{self, DaemonStatus, Request};
+
+    let path = control::socket_path(config).ok()?;
+    let response = control::request(&path, &Request::Status).await.ok()?;
+    let status: DaemonStatus = serde_json::from_value(response.data?).ok()?;
+    Some(StatusReport::from(&status))
+}
+
+/// Format the address and TTL of each configured family, e.g.
+/// "A 1.2.3.4 (TTL 300s), AAAA (not yet published)". The TTL is "n/a" for
+/// providers that can't read their records.
+fn published_families(records: &[RecordReport], capabilities: ProviderCapabilities) -> String {
+    records
+        .iter()
+        .map(|record| match record.record_ip {
+            Some(ip) if !capabilities.can_read_record => {
+                format!("{} {} (TTL n/a)", record.record_type, ip)
+            }
+            Some(ip) => format!(
+                "{} {} (TTL {})",
+                record.record_type,
+                ip,
+                format_ttl(record.ttl)
+            ),
+            None => format!("{} (not yet published)", record.record_type),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-        tokio::time::sleep(interval).await;
+#[cfg(unix)]
+async fn cmd_trigger(config: Config) -> anyhow::Result<()> {
+    use rusty_dns::control::{self, Request};
+
+    let path = control::socket_path(&config)?;
+    let response = control::request(&path, &Request::TriggerUpdate).await?;
+
+    if !response.ok {
+        anyhow::bail!(response.error.unwrap_or_default());
     }
+
+    println!("Update cycle triggered");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn cmd_trigger(_config: Config) -> anyhow::Result<()> {
+    anyhow::bail!("The control socket is only available on Unix")
+}
+
+async fn cmd_mcp(config: Config, config_path: &Path) -> anyhow::Result<()> {
+    let server = McpServer::new(config).with_config_path(config_path.to_path_buf());
+    eprintln!("rusty-dns MCP server started");
+    server.run_stdio().await?;
+    Ok(())
 }
 
-async fn cmd_mcp(config: Config) -> anyhow::Result<()> {
-    let server = McpServer::new(config);
-    server.run().await?;
+#[cfg(unix)]
+async fn cmd_tunnel(config_path: &Path, config: Config) -> anyhow::Result<()> {
+    use rusty_dns::tunnel::Tunnel;
+
+    Tunnel::new(config, cancel_on_shutdown()?)
+        .with_config_path(config_path.to_path_buf())
+        .on_event(print_tunnel_event)
+        .run()
+        .await?;
     Ok(())
 }
 
-async fn cmd_validate(config: Config) -> anyhow::Result<()> {
-    println!("Validating configuration...\n");
+#[cfg(not(unix))]
+async fn cmd_tunnel(_config_path: &Path, _config: Config) -> anyhow::Result<()> {
+    anyhow::bail!("SSH tunnels are only available on Unix")
+}
 
-    let mut all_valid = true;
+/// Print what the tunnel does: progress to stdout, problems to stderr.
+#[cfg(unix)]
+fn print_tunnel_event(event: &rusty_dns::tunnel::TunnelEvent) {
+    use rusty_dns::tunnel::TunnelEvent;
 
-    for provider_config in &config.providers {
-        let provider = create_provider(provider_config);
+    let now = || chrono::Utc::now().format("%Y-%m-%d %H:%M:%S");
+    match event {
+        TunnelEvent::Forwarding(path) => println!("Forwarding {}", path.display()),
+        TunnelEvent::Connecting { attempt: 1 } => println!("[{}] Connecting", now()),
+        TunnelEvent::Connecting { attempt } => {
+            println!("[{}] Reconnecting (attempt {})", now(), attempt)
+        }
+        TunnelEvent::Connected(remote) => println!("[{}] Tunnel open at {}", now(), remote),
+        TunnelEvent::Disconnected { error, retry_in } => eprintln!(
+            "[{}] Tunnel closed: {} (retrying in {}s)",
+            now(),
+            error,
+            retry_in.as_secs()
+        ),
+        TunnelEvent::Warning(message) => eprintln!("{}", message),
+        TunnelEvent::Stopped => println!("Shutting down"),
+    }
+}
 
-        print!("  {} ({}): ", provider.name(), provider.domain());
+/// Round trip past which a provider is shown as slow.
+const SLOW_PROVIDER: Duration = Duration::from_secs(2);
 
-        match provider.validate().await {
-            Ok(()) => println!("OK"),
-            Err(e) => {
-                println!("FAILED - {}", e);
-                all_valid = false;
+fn cmd_validate_schema(path: &Path, output: OutputFormat) -> anyhow::Result<()> {
+    let report = rusty_dns::schema::check_file(path)?;
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for warning in &report.warnings {
+            println!("  warning: {}", warning);
+        }
+        if report.valid {
+            println!("{} matches the config schema", path.display());
+        } else {
+            println!("{} doesn't match the config schema:", path.display());
+            for error in &report.errors {
+                println!("  {}", error);
             }
         }
     }
 
+    if !report.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn cmd_validate(
+    config: Config,
+    live: bool,
+    timing: bool,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    if output == OutputFormat::Text {
+        println!("Validating configuration...\n");
+    }
+
+    let samples = if timing { TIMING_SAMPLES } else { 1 };
+    let report = ValidationReport::collect_with(&config, live, samples).await;
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.valid {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(error) = &report.error {
+        println!("{}\n", error);
+        println!("Fix the configuration before checking providers.");
+        std::process::exit(1);
+    }
+
+    for warning in &report.warnings {
+        println!("  warning: {}", warning);
+    }
+    if !report.warnings.is_empty() {
+        println!();
+    }
+
+    let mut header = vec!["PROVIDER", "DOMAIN", "SOURCE", "TTL", "RESULT", "LATENCY"];
+    if live {
+        header.extend(["READ", "WRITE"]);
+    }
+    let mut table = Table::for_stdout(&header);
+    for (provider_config, provider) in config.providers.iter().zip(&report.providers) {
+        let source = provider
+            .source
+            .as_deref()
+            .map_or_else(|| "environment".to_string(), |p| p.display().to_string());
+        let ttl = match provider.ttl {
+            Some(ttl) => format!("{}s", ttl),
+            None if provider_config.capabilities().supports_ttl => "unchanged".to_string(),
+            None => "set by provider".to_string(),
+        };
+
+        let mut row = vec![
+            Cell::from(format!("{} ({})", provider.label, provider.name)),
+            Cell::from(provider.domain.as_str()),
+            Cell::from(source),
+            Cell::from(ttl),
+            match provider.status {
+                CheckStatus::Passed => Cell::colored("OK", Color::Green),
+                CheckStatus::Skipped => Cell::colored("skipped (disabled)", Color::Yellow),
+                CheckStatus::Failed | CheckStatus::Unsupported => Cell::colored(
+                    format!(
+                        "FAILED: {}",
+                        provider.message.as_deref().unwrap_or_default()
+                    ),
+                    Color::Red,
+                ),
+            },
+            match provider.latency {
+                Some(latency) if Duration::from_millis(latency.avg_ms) >= SLOW_PROVIDER => {
+                    Cell::colored(format!("{} (slow)", latency), Color::Yellow)
+                }
+                Some(latency) => Cell::from(latency.to_string()),
+                None => Cell::from("-"),
+            },
+        ];
+        if live {
+            row.extend([live_cell(&provider.read), live_cell(&provider.write)]);
+        }
+        table.push(row);
+    }
+    print!("{}", table);
+
     println!();
 
-    if all_valid {
+    if report.valid {
         println!("All providers validated successfully.");
     } else {
         println!("Some providers failed validation.");
@@ -279,3 +1819,265 @@ async fn cmd_validate(config: Config) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Cell of a `validate --live` check, empty if it wasn't made.
+fn live_cell(check: &Option<LiveCheck>) -> Cell {
+    let Some(check) = check else {
+        return Cell::from("-");
+    };
+    let message = check.message.as_deref().unwrap_or_default();
+    match check.status {
+        CheckStatus::Passed => Cell::colored(format!("OK: {}", message), Color::Green),
+        CheckStatus::Failed => Cell::colored(format!("FAILED: {}", message), Color::Red),
+        CheckStatus::Skipped | CheckStatus::Unsupported => {
+            Cell::colored(format!("not checked: {}", message), Color::Yellow)
+        }
+    }
+}
+
+async fn cmd_doctor(
+    config_path: &Path,
+    profile: Option<&str>,
+    send_test_notifications: bool,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    use rusty_dns::doctor::{self, Level};
+
+    let report = doctor::run(config_path, profile, send_test_notifications).await;
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        std::process::exit(report.exit_code());
+    }
+
+    let mut section = "";
+    for finding in &report.findings {
+        if finding.section != section {
+            section = finding.section;
+            println!("\n{}", section);
+        }
+        println!(
+            "  [{}] {}: {}",
+            finding.level, finding.subject, finding.message
+        );
+    }
+
+    println!(
+        "\n{} passed, {} warning(s), {} failed",
+        report.count(Level::Pass),
+        report.count(Level::Warn),
+        report.count(Level::Fail)
+    );
+
+    let steps: Vec<_> = report
+        .findings
+        .iter()
+        .filter(|f| f.level != Level::Pass)
+        .filter_map(|f| Some((f, f.suggestion.as_deref()?)))
+        .collect();
+    if !steps.is_empty() {
+        println!("\nNext steps:");
+        for (finding, suggestion) in steps {
+            println!("  - {}: {}", finding.subject, suggestion);
+        }
+    }
+
+    std::process::exit(report.exit_code());
+}
+
+async fn cmd_setup(
+    config_path: &Path,
+    profile: Option<&str>,
+    preset: Option<(String, BTreeMap<String, String>)>,
+    options: SetupOptions,
+) -> anyhow::Result<()> {
+    // Detection through the proxy of the existing config, if any
+    let detector = Config::load_unchecked(config_path, false, profile)
+        .map(|config| IpDetector::from_config(&config))
+        .unwrap_or_default();
+    let mut terminal = setup::Terminal;
+    let outcome = match preset {
+        Some((kind, fields)) => {
+            setup::run_non_interactive(
+                &mut terminal,
+                config_path,
+                &detector,
+                &kind,
+                fields,
+                options,
+            )
+            .await?
+        }
+        None => setup::run(&mut terminal, config_path, &detector, options).await?,
+    };
+
+    if outcome.added.is_empty() {
+        println!("No provider added.");
+        return Ok(());
+    }
+    if !outcome.env_vars.is_empty() {
+        println!("\nSet these environment variables wherever rusty-dns runs (e.g. the");
+        println!("service's environment), with the credentials you entered:");
+        for name in &outcome.env_vars {
+            println!("  {}", name);
+        }
+    }
+    println!("\nNext steps:");
+    println!("  rusty-dns validate        # check the config");
+    println!("  rusty-dns update          # point the records at this machine now");
+    println!("  rusty-dns daemon          # keep them up to date");
+    Ok(())
+}
+
+async fn cmd_config(
+    action: ConfigAction,
+    config_path: &Path,
+    profile: Option<&str>,
+) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    match action {
+        ConfigAction::Init { force } => {
+            if config_path.exists() && !force {
+                anyhow::bail!(
+                    "{} already exists (use --force to overwrite)",
+                    config_path.display()
+                );
+            }
+            Config::example().save_to(config_path)?;
+            println!("Wrote example config to {}", config_path.display());
+        }
+        ConfigAction::Show => {
+            let config = Config::load_unchecked(config_path, false, profile)?;
+            if config_path.exists() {
+                println!("# Source: {}", config_path.display());
+            } else {
+                println!("# Source: {} (not found)", config_path.display());
+            }
+            if let Some(profile) = &config.profile {
+                println!("# Profile: {}", profile);
+            }
+            let mut drop_ins: Vec<&Path> = config
+                .providers
+                .iter()
+                .filter_map(|p| p.source.as_deref())
+                .filter(|source| *source != config_path)
+                .collect();
+            drop_ins.dedup();
+            for drop_in in drop_ins {
+                println!("# Drop-in: {}", drop_in.display());
+            }
+            println!();
+            print!(
+                "{}",
+                ConfigFormat::from_path(config_path).serialize(&config.redacted())?
+            );
+        }
+        ConfigAction::AddProvider {
+            r#type,
+            fields,
+            non_interactive,
+            check,
+        } => {
+            let mut fields: BTreeMap<String, String> = fields.into_iter().collect();
+            if !non_interactive {
+                println!("New {} provider (leave empty to skip a setting)", r#type);
+                for field in ProviderConfig::fields(&r#type) {
+                    if fields.contains_key(field) {
+                        continue;
+                    }
+                    print!("  {}: ", field);
+                    std::io::stdout().flush()?;
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    let value = line.trim();
+                    if !value.is_empty() {
+                        fields.insert(field.to_string(), value.to_string());
+                    }
+                }
+            }
+
+            let provider = ProviderConfig::from_fields(&r#type, &fields)?;
+            if check {
+                // Through the proxy of the existing config, if any
+                let network = Config::load_unchecked(config_path, false, profile)
+                    .map(|config| config.network)
+                    .unwrap_or_default();
+                create_provider(&provider.interpolated()?, None, &network)
+                    .validate()
+                    .await?;
+                println!("Credentials OK");
+            }
+
+            let label = provider.label();
+            for warning in Config::add_provider_to(config_path, provider)? {
+                println!("warning: {}", warning);
+            }
+            println!("Added {} to {}", label, config_path.display());
+        }
+        ConfigAction::RemoveProvider { name, yes } => {
+            let config = Config::load_unchecked(config_path, false, profile)?;
+            let provider = config
+                .find_provider(&name)
+                .ok_or_else(|| anyhow::anyhow!("No provider matching \"{}\"", name))?;
+
+            if !yes {
+                print!(
+                    "Remove {} ({}) from {}? [y/N] ",
+                    provider.label(),
+                    provider.display_name(),
+                    config_path.display()
+                );
+                std::io::stdout().flush()?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    println!("Cancelled");
+                    return Ok(());
+                }
+            }
+
+            let removed = Config::remove_provider_from(config_path, &name)?;
+            println!("Removed {} from {}", removed.label(), config_path.display());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "keyring")]
+fn cmd_secret(action: SecretAction) -> anyhow::Result<()> {
+    use rusty_dns::secret::{KeyringRef, KEYRING_PREFIX};
+    use std::io::IsTerminal;
+
+    match action {
+        SecretAction::Set { name } => {
+            let reference = KeyringRef::from_name(&name)?;
+            let secret = if std::io::stdin().is_terminal() {
+                rpassword::prompt_password(format!("Secret for {}: ", reference))?
+            } else {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line)?;
+                line
+            };
+            let secret = secret.trim();
+            if secret.is_empty() {
+                anyhow::bail!("Refusing to store an empty secret");
+            }
+
+            reference.set(secret)?;
+            println!("Stored {}", reference);
+            println!(
+                "Reference it in the config as \"{}{}\"",
+                KEYRING_PREFIX, reference
+            );
+        }
+        SecretAction::Rm { name } => {
+            let reference = KeyringRef::from_name(&name)?;
+            reference.delete()?;
+            println!("Deleted {}", reference);
+        }
+    }
+
+    Ok(())
+}
@@ -1,19 +1,28 @@
-//! MCP JSON-RPC 2.0 server over stdio.
+//! MCP JSON-RPC 2.0 server over stdio or any other byte stream.
 
-use crate::config::Config;
-use crate::detector::IpDetector;
+use crate::config::{self, Config, ProviderConfig};
+use crate::detector::{IpDetector, DEFAULT_CACHE_TTL};
 use crate::error::Result;
-use crate::providers::{create_provider, UpdateResult};
+use crate::history::{self, HistoryEntry, HistoryFilter};
+use crate::notify::Notifications;
+use crate::providers::create_provider;
+use crate::report::{
+    timed_validate, DaemonInfo, IpReport, StatusReport, UpdateReport, TIMING_SAMPLES,
+};
+use crate::stats;
+use crate::tunnel::TunnelStatus;
+use crate::updater::{UpdatePolicy, Updater};
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, Write};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 /// MCP Server for AI assistant integration.
 pub struct McpServer {
     config: Config,
+    config_path: Option<PathBuf>,
     detector: IpDetector,
-    history: Arc<Mutex<Vec<UpdateResult>>>,
+    notifications: Notifications,
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,43 +53,44 @@ struct JsonRpcError {
     data: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
-struct StatusResponse {
-    current_ip: Option<String>,
-    providers: Vec<ProviderStatus>,
-    last_update: Option<String>,
-}
-
-#[derive(Debug, Serialize)]
-struct ProviderStatus {
-    name: String,
-    domain: String,
-    current_ip: Option<String>,
-    healthy: bool,
-}
-
 impl McpServer {
     /// Create a new MCP server.
     pub fn new(config: Config) -> Self {
         Self {
+            notifications: Notifications::from_config(&config),
+            // Tool calls made in quick succession share one detection
+            detector: IpDetector::from_config(&config).with_cache(DEFAULT_CACHE_TTL),
             config,
-            detector: IpDetector::new(),
-            history: Arc::new(Mutex::new(Vec::new())),
+            config_path: None,
         }
     }
 
-    /// Run the MCP server over stdio.
-    pub async fn run(&self) -> Result<()> {
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
+    /// Set the config file edited by the add/remove provider tools.
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
 
-        eprintln!("rusty-dns MCP server started");
+    /// Run the MCP server over stdio.
+    pub async fn run_stdio(&self) -> Result<()> {
+        self.run(tokio::io::stdin(), tokio::io::stdout()).await
+    }
 
-        for line in stdin.lock().lines() {
-            let line = match line {
-                Ok(l) => l,
+    /// Run the MCP server over a transport, one JSON-RPC message per line,
+    /// until `input` ends.
+    pub async fn run<R, W>(&self, input: R, mut output: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(input).lines();
+
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
                 Err(e) => {
-                    eprintln!("Error reading stdin: {}", e);
+                    tracing::warn!("Error reading MCP request: {}", e);
                     continue;
                 }
             };
@@ -89,28 +99,23 @@ impl McpServer {
                 continue;
             }
 
-            let request: JsonRpcRequest = match serde_json::from_str(&line) {
-                Ok(r) => r,
-                Err(e) => {
-                    let error_response = JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: None,
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32700,
-                            message: format!("Parse error: {}", e),
-                            data: None,
-                        }),
-                    };
-                    writeln!(stdout, "{}", serde_json::to_string(&error_response)?)?;
-                    stdout.flush()?;
-                    continue;
-                }
+            let response = match serde_json::from_str::<JsonRpcRequest>(&line) {
+                Ok(request) => self.handle_request(request).await,
+                Err(e) => JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: None,
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32700,
+                        message: format!("Parse error: {}", e),
+                        data: None,
+                    }),
+                },
             };
-
-            let response = self.handle_request(request).await;
-            writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
-            stdout.flush()?;
+            let mut message = serde_json::to_string(&response)?;
+            message.push('\n');
+            output.write_all(message.as_bytes()).await?;
+            output.flush().await?;
         }
 
         Ok(())
@@ -184,9 +189,16 @@ impl McpServer {
             "ddns_status" => self.tool_status().await,
             "ddns_update" => self.tool_update(arguments).await,
             "ddns_history" => self.tool_history(arguments).await,
+            "ddns_stats" => self.tool_stats().await,
             "ddns_test_provider" => self.tool_test_provider(arguments).await,
+            "ddns_test_ip_services" => self.tool_test_ip_services(arguments).await,
             "ddns_add_provider" => self.tool_add_provider(arguments).await,
             "ddns_remove_provider" => self.tool_remove_provider(arguments).await,
+            "ddns_tunnel_status" => self.tool_tunnel_status().await,
+            "ddns_set_check_interval" => self.tool_set_check_interval(arguments).await,
+            "ddns_pause" => self.tool_pause(true).await,
+            "ddns_resume" => self.tool_pause(false).await,
+            "ddns_trigger_cycle" => self.tool_trigger_cycle().await,
             _ => Err(JsonRpcError {
                 code: -32602,
                 message: format!("Unknown tool: {}", name),
@@ -196,34 +208,13 @@ impl McpServer {
     }
 
     async fn tool_status(&self) -> std::result::Result<serde_json::Value, JsonRpcError> {
-        let current_ip = self.detector.detect_ipv4().await.ok();
-
-        let mut providers = Vec::new();
-        for provider_config in &self.config.providers {
-            let provider = create_provider(provider_config);
-
-            let current = provider.get_current_ip().await.ok().flatten();
-            let healthy = provider.validate().await.is_ok();
-
-            providers.push(ProviderStatus {
-                name: provider.name().to_string(),
-                domain: provider.domain(),
-                current_ip: current.map(|ip| ip.to_string()),
-                healthy,
-            });
-        }
-
-        let history = self.history.lock().await;
-        let last_update = history.last().map(|r| r.timestamp.to_rfc3339());
+        let mut report = StatusReport::collect(&self.config, &self.detector).await;
+        report.daemon = self.daemon_info().await;
 
         Ok(serde_json::json!({
             "content": [{
                 "type": "text",
-                "text": serde_json::to_string_pretty(&StatusResponse {
-                    current_ip: current_ip.map(|ip| ip.to_string()),
-                    providers,
-                    last_update,
-                }).unwrap()
+                "text": serde_json::to_string_pretty(&report).unwrap()
             }]
         }))
     }
@@ -236,58 +227,32 @@ impl McpServer {
             .get("force")
             .and_then(|v| v.as_bool())
             .unwrap_or(false);
+        let bypass_guard = arguments
+            .get("bypass_guard")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
-        let current_ip = self
-            .detector
-            .detect_ipv4()
-            .await
-            .map_err(|e| JsonRpcError {
-                code: -32000,
-                message: format!("Failed to detect IP: {}", e),
-                data: None,
-            })?;
-
-        let mut results = Vec::new();
-        for provider_config in &self.config.providers {
-            let provider = create_provider(provider_config);
-
-            // Check if update is needed
-            if !force {
-                if let Ok(Some(existing)) = provider.get_current_ip().await {
-                    if existing == current_ip {
-                        results.push(serde_json::json!({
-                            "provider": provider.name(),
-                            "domain": provider.domain(),
-                            "skipped": true,
-                            "reason": "IP unchanged"
-                        }));
-                        continue;
-                    }
-                }
-            }
-
-            let result = provider
-                .update_ip(current_ip)
-                .await
-                .map_err(|e| JsonRpcError {
-                    code: -32000,
-                    message: e.to_string(),
-                    data: None,
-                })?;
-
-            // Store in history
-            self.history.lock().await.push(result.clone());
-
-            results.push(serde_json::json!({
-                "provider": result.provider,
-                "domain": result.domain,
-                "success": result.success,
-                "ip": result.ip.map(|ip| ip.to_string()),
-                "previous_ip": result.previous_ip.map(|ip| ip.to_string()),
-                "error": result.error
-            }));
+        let updater = Updater::new(&self.config, &self.detector)
+            .with_notifications(&self.notifications)
+            .with_guard_bypass(bypass_guard);
+        let ips = updater.detect().await.map_err(|e| JsonRpcError {
+            code: -32000,
+            message: format!("Failed to detect IP: {}", e),
+            data: None,
+        })?;
+
+        let policy = if force {
+            UpdatePolicy::Force
+        } else {
+            UpdatePolicy::DriftCorrect
+        };
+        let updates = updater.update_all(&ips, policy).await;
+        if let Err(e) = updater.record(&ips, &updates).await {
+            tracing::warn!("Failed to save state: {}", e);
         }
 
+        let results: Vec<UpdateReport> = updates.iter().map(UpdateReport::from).collect();
+
         Ok(serde_json::json!({
             "content": [{
                 "type": "text",
@@ -296,32 +261,49 @@ impl McpServer {
         }))
     }
 
+    /// Load the persistent update history (empty if unavailable).
+    fn load_history(&self) -> Vec<HistoryEntry> {
+        history::open(&self.config)
+            .and_then(|store| store.entries())
+            .unwrap_or_default()
+    }
+
     async fn tool_history(
         &self,
         arguments: serde_json::Value,
     ) -> std::result::Result<serde_json::Value, JsonRpcError> {
-        let limit = arguments
-            .get("limit")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(10) as usize;
-
-        let history = self.history.lock().await;
-        let recent: Vec<_> = history.iter().rev().take(limit).collect();
-
-        let entries: Vec<_> = recent
-            .iter()
-            .map(|r| {
-                serde_json::json!({
-                    "provider": r.provider,
-                    "domain": r.domain,
-                    "success": r.success,
-                    "ip": r.ip.map(|ip| ip.to_string()),
-                    "previous_ip": r.previous_ip.map(|ip| ip.to_string()),
-                    "error": r.error,
-                    "timestamp": r.timestamp.to_rfc3339()
-                })
-            })
-            .collect();
+        let invalid = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+            data: None,
+        };
+
+        let filter = HistoryFilter {
+            limit: Some(
+                arguments
+                    .get("limit")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(10) as usize,
+            ),
+            providers: arguments
+                .get("provider")
+                .and_then(|v| v.as_str())
+                .map(|p| vec![p.to_string()])
+                .unwrap_or_default(),
+            failed_only: arguments
+                .get("failed_only")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            since: arguments
+                .get("since")
+                .and_then(|v| v.as_str())
+                .map(|since| history::parse_since(since, chrono::Utc::now()))
+                .transpose()
+                .map_err(|e| invalid(e.to_string()))?,
+        };
+
+        let history = self.load_history();
+        let entries = filter.apply(&history);
 
         Ok(serde_json::json!({
             "content": [{
@@ -331,6 +313,21 @@ impl McpServer {
         }))
     }
 
+    async fn tool_stats(&self) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        let stats = stats::load(&self.config).map_err(|e| JsonRpcError {
+            code: -32603,
+            message: format!("Failed to load state: {}", e),
+            data: None,
+        })?;
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&stats).unwrap()
+            }]
+        }))
+    }
+
     async fn tool_test_provider(
         &self,
         arguments: serde_json::Value,
@@ -346,67 +343,463 @@ impl McpServer {
 
         let provider_config = self
             .config
-            .providers
-            .iter()
-            .find(|p| {
-                let name = match p {
-                    crate::config::ProviderConfig::Cloudflare { .. } => "cloudflare",
-                    crate::config::ProviderConfig::Namecheap { .. } => "namecheap",
-                    crate::config::ProviderConfig::DuckDns { .. } => "duckdns",
-                    crate::config::ProviderConfig::GoDaddy { .. } => "godaddy",
-                };
-                name == provider_name
-            })
+            .select_provider(provider_name)
+            .filter(|p| p.enabled)
             .ok_or_else(|| JsonRpcError {
                 code: -32602,
-                message: format!("Provider not configured: {}", provider_name),
+                message: format!("Provider not configured or disabled: {}", provider_name),
                 data: None,
             })?;
 
-        let provider = create_provider(provider_config);
+        let provider = create_provider(
+            provider_config,
+            self.config.default_ttl,
+            &self.config.network,
+        );
 
-        let validation = provider.validate().await;
+        let timing = arguments
+            .get("timing")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let samples = if timing { TIMING_SAMPLES } else { 1 };
+        let (validation, latency) = timed_validate(provider.as_ref(), samples).await;
         let current_ip = provider.get_current_ip().await.ok().flatten();
 
         Ok(serde_json::json!({
             "content": [{
                 "type": "text",
                 "text": serde_json::to_string_pretty(&serde_json::json!({
+                    "label": provider_config.label(),
                     "provider": provider.name(),
                     "domain": provider.domain(),
                     "valid": validation.is_ok(),
                     "error": validation.err().map(|e| e.to_string()),
+                    "latency": latency,
                     "current_ip": current_ip.map(|ip| ip.to_string())
                 })).unwrap()
             }]
         }))
     }
 
+    async fn tool_test_ip_services(
+        &self,
+        arguments: serde_json::Value,
+    ) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        let version = match arguments.get("ip_version") {
+            Some(version) => serde_json::from_value(version.clone()).map_err(|_| JsonRpcError {
+                code: -32602,
+                message: format!("Invalid ip_version: {} (expected v4, v6 or both)", version),
+                data: None,
+            })?,
+            None => self.config.ip_version,
+        };
+
+        let report = IpReport::collect(&self.detector, version, true).await;
+        if let Err(e) = self.detector.save_health() {
+            tracing::warn!("Failed to save IP service health: {}", e);
+        }
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&report).unwrap()
+            }]
+        }))
+    }
+
+    fn config_path(&self) -> std::result::Result<&PathBuf, JsonRpcError> {
+        self.config_path.as_ref().ok_or_else(|| JsonRpcError {
+            code: -32603,
+            message: "No config file to edit".to_string(),
+            data: None,
+        })
+    }
+
     async fn tool_add_provider(
         &self,
-        _arguments: serde_json::Value,
+        arguments: serde_json::Value,
     ) -> std::result::Result<serde_json::Value, JsonRpcError> {
-        // This would require modifying the config file
-        // For now, return instructions
+        let invalid_params = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+            data: None,
+        };
+
+        let kind = arguments
+            .get("provider")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid_params("Missing provider type".to_string()))?;
+        let fields: BTreeMap<String, String> = arguments
+            .get("fields")
+            .and_then(|v| v.as_object())
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| {
+                let value = value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), str::to_string);
+                (key.clone(), value)
+            })
+            .collect();
+
+        let provider = ProviderConfig::from_fields(kind, &fields)
+            .map_err(|e| invalid_params(e.to_string()))?;
+        if arguments.get("check").and_then(|v| v.as_bool()) == Some(true) {
+            let interpolated = provider
+                .interpolated()
+                .map_err(|e| invalid_params(e.to_string()))?;
+            create_provider(&interpolated, self.config.default_ttl, &self.config.network)
+                .validate()
+                .await
+                .map_err(|e| invalid_params(format!("Credential check failed: {}", e)))?;
+        }
+
+        let path = self.config_path()?;
+        let label = provider.label();
+        let warnings =
+            Config::add_provider_to(path, provider).map_err(|e| invalid_params(e.to_string()))?;
+
         Ok(serde_json::json!({
             "content": [{
                 "type": "text",
-                "text": "To add a provider, edit the config file at ~/.config/rusty-dns/config.toml\n\nExample:\n\n[[providers]]\ntype = \"cloudflare\"\napi_token = \"your-token\"\nzone_id = \"your-zone-id\"\nrecord_name = \"home.example.com\"\nproxied = false"
+                "text": serde_json::to_string_pretty(&serde_json::json!({
+                    "added": label,
+                    "config_path": path,
+                    "warnings": warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+                    "note": "Reload the daemon to start updating this provider"
+                })).unwrap()
             }]
         }))
     }
 
     async fn tool_remove_provider(
         &self,
-        _arguments: serde_json::Value,
+        arguments: serde_json::Value,
+    ) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        let invalid_params = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+            data: None,
+        };
+
+        let selector = arguments
+            .get("provider")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid_params("Missing provider name".to_string()))?;
+
+        let path = self.config_path()?;
+        let removed = Config::remove_provider_from(path, selector)
+            .map_err(|e| invalid_params(e.to_string()))?;
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&serde_json::json!({
+                    "removed": removed.label(),
+                    "domain": removed.display_name(),
+                    "config_path": path,
+                    "note": "Reload the daemon to stop updating this provider"
+                })).unwrap()
+            }]
+        }))
+    }
+
+    async fn tool_tunnel_status(&self) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        let tunnel = self.config.tunnel.as_ref();
+        let status = TunnelStatus::load(&self.config);
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&serde_json::json!({
+                    "configured": tunnel.is_some(),
+                    "remote": tunnel.map(|t| t.remote_endpoint()),
+                    "exposes": tunnel.map(|t| t.expose),
+                    "running": status.is_some(),
+                    "status": status
+                })).unwrap()
+            }]
+        }))
+    }
+
+    /// Send a request to the running daemon over its control socket,
+    /// returning the data it answers with.
+    #[cfg(unix)]
+    async fn daemon_request(
+        &self,
+        request: &crate::control::Request,
     ) -> std::result::Result<serde_json::Value, JsonRpcError> {
-        // This would require modifying the config file
-        // For now, return instructions
+        use crate::control;
+
+        let failed = |message: String| JsonRpcError {
+            code: -32000,
+            message,
+            data: None,
+        };
+        let path = control::socket_path(&self.config).map_err(|e| failed(e.to_string()))?;
+        let response = control::request(&path, request)
+            .await
+            .map_err(|e| failed(e.to_string()))?;
+        if !response.ok {
+            return Err(failed(response.error.unwrap_or_default()));
+        }
+        Ok(response.data.unwrap_or_default())
+    }
+
+    /// Get the running daemon's process and pause state, if one answers.
+    async fn daemon_info(&self) -> Option<DaemonInfo> {
+        #[cfg(unix)]
+        {
+            let data = self
+                .daemon_request(&crate::control::Request::Status)
+                .await
+                .ok()?;
+            let status: crate::control::DaemonStatus = serde_json::from_value(data).ok()?;
+            StatusReport::from(&status).daemon
+        }
+
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    async fn tool_set_check_interval(
+        &self,
+        arguments: serde_json::Value,
+    ) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        let invalid_params = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+            data: None,
+        };
+
+        let secs = arguments
+            .get("seconds")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| invalid_params("Missing seconds".to_string()))?;
+        config::check_interval_bounds(secs).map_err(|e| invalid_params(e.to_string()))?;
+
+        let path = self.config_path()?;
+        Config::set_check_interval_in(path, secs).map_err(|e| invalid_params(e.to_string()))?;
+
+        #[cfg(unix)]
+        let applied = self
+            .daemon_request(&crate::control::Request::SetInterval { secs })
+            .await
+            .map(|_| ())
+            .map_err(|e| e.message);
+        #[cfg(not(unix))]
+        let applied: std::result::Result<(), String> = Err(unix_only().message);
+
         Ok(serde_json::json!({
             "content": [{
                 "type": "text",
-                "text": "To remove a provider, edit the config file at ~/.config/rusty-dns/config.toml and remove the [[providers]] section for that provider."
+                "text": serde_json::to_string_pretty(&serde_json::json!({
+                    "interval_secs": secs,
+                    "config_path": path,
+                    "applied": applied.is_ok(),
+                    "note": applied.err().map(|e| format!(
+                        "Saved, but not applied to a running daemon ({}); it applies when the daemon starts",
+                        e
+                    ))
+                })).unwrap()
+            }]
+        }))
+    }
+
+    async fn tool_pause(
+        &self,
+        paused: bool,
+    ) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        #[cfg(unix)]
+        let result = {
+            use crate::control::Request;
+            let request = if paused {
+                Request::Pause
+            } else {
+                Request::Resume
+            };
+            self.daemon_request(&request).await?
+        };
+        #[cfg(not(unix))]
+        let result = {
+            let _ = paused;
+            Err::<serde_json::Value, _>(unix_only())?
+        };
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&result).unwrap()
+            }]
+        }))
+    }
+
+    async fn tool_trigger_cycle(&self) -> std::result::Result<serde_json::Value, JsonRpcError> {
+        #[cfg(unix)]
+        let report = self
+            .daemon_request(&crate::control::Request::RunCycle)
+            .await?;
+        #[cfg(not(unix))]
+        let report = Err::<serde_json::Value, _>(unix_only())?;
+
+        Ok(serde_json::json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&report).unwrap()
             }]
         }))
     }
 }
+
+/// Error of the daemon tools where there is no control socket.
+#[cfg(not(unix))]
+fn unix_only() -> JsonRpcError {
+    JsonRpcError {
+        code: -32000,
+        message: "The control socket is only available on Unix".to_string(),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_run() {
+        let server = McpServer::new(Config::default());
+        let (mut client, transport) = tokio::io::duplex(64 * 1024);
+        let (input, output) = tokio::io::split(transport);
+
+        client
+            .write_all(
+                b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\"}\n\
+                  \n\
+                  not json\n\
+                  {\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"tools/list\"}\n",
+            )
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        server.run(input, output).await.unwrap();
+
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).await.unwrap();
+        let responses: Vec<serde_json::Value> = responses
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"]["serverInfo"]["name"], "rusty-dns");
+        assert_eq!(responses[1]["error"]["code"], -32700);
+        assert_eq!(responses[2]["id"], 2);
+        assert!(responses[2]["result"]["tools"].as_array().unwrap().len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_status() {
+        let text = |result: serde_json::Value| -> serde_json::Value {
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap()
+        };
+
+        let server = McpServer::new(Config::default());
+        let status = text(server.tool_tunnel_status().await.unwrap());
+        assert_eq!(status["configured"], false);
+        assert_eq!(status["running"], false);
+
+        let dir = std::env::temp_dir().join(format!("rusty-dns-mcp-tunnel-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!(
+            "state_dir = {:?}\n[tunnel]\nhost = \"vps.example.com\"\nremote_bind = \"7000\"\n",
+            dir
+        ))
+        .unwrap();
+        let status = text(McpServer::new(config).tool_tunnel_status().await.unwrap());
+        assert_eq!(status["configured"], true);
+        assert_eq!(status["remote"], "vps.example.com:7000");
+        assert_eq!(status["exposes"], "mcp");
+        assert_eq!(status["running"], false);
+        assert!(status["status"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        use crate::stats::{CycleOutcome, CycleRecord};
+
+        let dir = std::env::temp_dir().join(format!("rusty-dns-mcp-stats-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!("state_dir = {:?}\n", dir)).unwrap();
+        let cycle = |ip: &str, outcome| CycleRecord {
+            timestamp: chrono::Utc::now(),
+            ipv4: Some(ip.parse().unwrap()),
+            ipv6: None,
+            detection_ms: 100,
+            providers: BTreeMap::from([("home".to_string(), outcome)]),
+        };
+        history::open(&config)
+            .unwrap()
+            .update_state(|state| {
+                state.record_cycle(cycle("192.0.2.1", CycleOutcome::Updated), 10);
+                state.record_cycle(cycle("192.0.2.2", CycleOutcome::Failed), 10);
+            })
+            .unwrap();
+
+        let result = McpServer::new(config).tool_stats().await.unwrap();
+        let stats: serde_json::Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(stats["cycles"], 2);
+        assert_eq!(stats["ip_changes_7d"], 1);
+        assert_eq!(stats["avg_detection_ms"], 100);
+        assert_eq!(stats["providers"][0]["label"], "home");
+        assert_eq!(stats["providers"][0]["failure_rate"], 0.5);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_check_interval() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-mcp-interval-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "# tuned by hand\ncheck_interval_secs = 300\n").unwrap();
+        let config: Config = toml::from_str(&format!(
+            "state_dir = {:?}\n[daemon]\ncontrol_socket = {:?}\n",
+            dir,
+            dir.join("missing.sock")
+        ))
+        .unwrap();
+        let server = McpServer::new(config).with_config_path(path.clone());
+
+        for secs in [0, 29, 86401] {
+            let err = server
+                .tool_set_check_interval(serde_json::json!({ "seconds": secs }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code, -32602);
+        }
+        assert!(std::fs::read_to_string(&path).unwrap().contains("= 300"));
+
+        // Saved even with no daemon to apply it to
+        let result = server
+            .tool_set_check_interval(serde_json::json!({ "seconds": 900 }))
+            .await
+            .unwrap();
+        let result: serde_json::Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(result["interval_secs"], 900);
+        assert_eq!(result["applied"], false);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# tuned by hand\ncheck_interval_secs = 900\n"
+        );
+
+        // Pausing needs a daemon
+        let err = server.tool_pause(true).await.unwrap_err();
+        assert_eq!(err.code, -32000);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 //! MCP tool definitions.
 
+use crate::config::ProviderConfig;
+use crate::providers::ProviderCapabilities;
 use serde::Serialize;
 use serde_json::json;
 
@@ -11,12 +13,25 @@ pub struct ToolDefinition {
     pub input_schema: serde_json::Value,
 }
 
+/// Describe the capabilities of each provider type, e.g. "namecheap
+/// (update only)".
+fn type_capabilities() -> String {
+    ProviderConfig::TYPES
+        .iter()
+        .map(|kind| {
+            let capabilities = ProviderCapabilities::of_type(kind).unwrap_or_default();
+            format!("{} ({})", kind, capabilities.describe())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// Get all available MCP tools.
 pub fn get_tools() -> Vec<ToolDefinition> {
     vec![
         ToolDefinition {
             name: "ddns_status".to_string(),
-            description: "Get current DDNS status including detected public IP, provider status, and last update time.".to_string(),
+            description: "Get current DDNS status including detected public IP, provider status, and last update time. Each provider has a sync verdict (in_sync, stale, unknown or error), in_sync: true when its records hold the detected addresses, and when it was last updated. capabilities tells what the provider can do: when can_read_record is false, its records are the last published addresses rather than read from the provider. network_situation tells whether the network is behind CGNAT or double NAT (kind cgnat or double_nat), where DNS records can't reach it. When a daemon is running, daemon has its PID, whether its automatic updates are paused and its check interval.".to_string(),
             input_schema: json!({
                 "type": "object",
                 "properties": {},
@@ -25,7 +40,7 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         },
         ToolDefinition {
             name: "ddns_update".to_string(),
-            description: "Force update DNS records for all configured providers. Use force=true to update even if IP hasn't changed.".to_string(),
+            description: "Force update DNS records for all configured providers. Use force=true to update even if IP hasn't changed. Records updated to the same address less than min_update_interval_secs (default 60) ago are skipped with reason rate_guard unless bypass_guard=true.".to_string(),
             input_schema: json!({
                 "type": "object",
                 "properties": {
@@ -33,6 +48,11 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                         "type": "boolean",
                         "description": "Force update even if IP hasn't changed",
                         "default": false
+                    },
+                    "bypass_guard": {
+                        "type": "boolean",
+                        "description": "Send updates even to records updated to the same address within min_update_interval_secs",
+                        "default": false
                     }
                 },
                 "required": []
@@ -40,7 +60,7 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         },
         ToolDefinition {
             name: "ddns_history".to_string(),
-            description: "Get history of recent DNS updates.".to_string(),
+            description: "Get history of recent DNS updates, most recent first. Each entry has a reason: why it was sent (changed, forced, refresh, drift, rollback) or skipped (unchanged, disabled, not_eligible_interval, rate_guard); skipped entries are successful results for the address that wasn't sent.".to_string(),
             input_schema: json!({
                 "type": "object",
                 "properties": {
@@ -48,54 +68,159 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                         "type": "integer",
                         "description": "Maximum number of history entries to return",
                         "default": 10
+                    },
+                    "provider": {
+                        "type": "string",
+                        "description": "Only entries of this provider (label, domain or provider type)"
+                    },
+                    "failed_only": {
+                        "type": "boolean",
+                        "description": "Only failed updates",
+                        "default": false
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Only entries since this time: an RFC 3339 timestamp, a date (2024-05-01) or an age (30m, 12h, 7d)"
                     }
                 },
                 "required": []
             }),
         },
+        ToolDefinition {
+            name: "ddns_stats".to_string(),
+            description: "Get trends of the daemon's recent update cycles: how often the public IP changed in the last 7 days, the average time to detect it and how many detections failed over the last 100 cycles, and per provider how many of those cycles sent updates and what share of them failed (failure_rate, 0 to 1).".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": []
+            }),
+        },
         ToolDefinition {
             name: "ddns_test_provider".to_string(),
-            description: "Test connectivity and credentials for a specific DDNS provider.".to_string(),
+            description: "Test connectivity and credentials for a specific DDNS provider, with the round-trip latency of the check (latency.avg_ms and min_ms).".to_string(),
             input_schema: json!({
                 "type": "object",
                 "properties": {
                     "provider": {
                         "type": "string",
-                        "description": "Provider name (cloudflare, namecheap, duckdns, godaddy)",
-                        "enum": ["cloudflare", "namecheap", "duckdns", "godaddy"]
+                        "description": "Provider label (as shown by ddns_status) or domain, or provider type (cloudflare, namecheap, duckdns, godaddy, vultr, yandexcloud, dynurl, dnsomatic) to select the first entry of that type"
+                    },
+                    "timing": {
+                        "type": "boolean",
+                        "description": "Check the credentials 3 times for the fastest and average latency",
+                        "default": false
                     }
                 },
                 "required": ["provider"]
             }),
         },
+        ToolDefinition {
+            name: "ddns_test_ip_services".to_string(),
+            description: "Query every IP detection service and report its answer and latency, with each service's rolling success rate, average latency and quarantine.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ip_version": {
+                        "type": "string",
+                        "enum": ["v4", "v6", "both"],
+                        "description": "Address families to test (defaults to the configured ip_version)"
+                    }
+                },
+                "required": []
+            }),
+        },
         ToolDefinition {
             name: "ddns_add_provider".to_string(),
-            description: "Get instructions for adding a new DDNS provider to the configuration.".to_string(),
+            description: format!(
+                "Add a DDNS provider to the config file. The entry is validated before saving; credentials can be ${{VAR}} references, files or keyring references. What each type can do: {}.",
+                type_capabilities()
+            ),
             input_schema: json!({
                 "type": "object",
                 "properties": {
                     "provider": {
                         "type": "string",
                         "description": "Provider type to add",
-                        "enum": ["cloudflare", "namecheap", "duckdns", "godaddy"]
+                        "enum": ["cloudflare", "namecheap", "duckdns", "godaddy", "vultr", "yandexcloud", "dynurl", "dnsomatic"]
+                    },
+                    "fields": {
+                        "type": "object",
+                        "description": "Provider settings, e.g. {\"zone_id\": \"...\", \"record_name\": \"home.example.com\", \"api_token\": \"${CF_API_TOKEN}\"} for cloudflare; also accepts label, enabled and ip_version"
+                    },
+                    "check": {
+                        "type": "boolean",
+                        "description": "Test the credentials against the provider before saving",
+                        "default": false
                     }
                 },
-                "required": ["provider"]
+                "required": ["provider", "fields"]
             }),
         },
         ToolDefinition {
             name: "ddns_remove_provider".to_string(),
-            description: "Get instructions for removing a DDNS provider from the configuration.".to_string(),
+            description: "Remove a DDNS provider from the config file.".to_string(),
             input_schema: json!({
                 "type": "object",
                 "properties": {
                     "provider": {
                         "type": "string",
-                        "description": "Provider name to remove"
+                        "description": "Label (as shown by ddns_status) or domain of the provider to remove"
                     }
                 },
                 "required": ["provider"]
             }),
         },
+        ToolDefinition {
+            name: "ddns_tunnel_status".to_string(),
+            description: "Get the status of the reverse SSH tunnel (rusty-dns tunnel): whether it is configured and running, the remote endpoint, whether it is connected, and when it last reconnected.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": []
+            }),
+        },
+        ToolDefinition {
+            name: "ddns_set_check_interval".to_string(),
+            description: "Set the time between update cycles (check_interval_secs), between 30 seconds and 24 hours. The interval is saved to the config file and applied to the running daemon, if any.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "seconds": {
+                        "type": "integer",
+                        "description": "Seconds between update cycles",
+                        "minimum": 30,
+                        "maximum": 86400
+                    }
+                },
+                "required": ["seconds"]
+            }),
+        },
+        ToolDefinition {
+            name: "ddns_pause".to_string(),
+            description: "Pause the running daemon's automatic updates (every check interval and on network changes) without stopping it. Cycles triggered with ddns_trigger_cycle still run. The daemon stays paused across config reloads, until ddns_resume or a restart.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": []
+            }),
+        },
+        ToolDefinition {
+            name: "ddns_resume".to_string(),
+            description: "Resume the running daemon's automatic updates paused by ddns_pause. A cycle runs right away if one was due while paused.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": []
+            }),
+        },
+        ToolDefinition {
+            name: "ddns_trigger_cycle".to_string(),
+            description: "Make the running daemon detect the public addresses and update the providers whose records differ right away, and return the detected addresses and each update's result.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": []
+            }),
+        },
     ]
 }
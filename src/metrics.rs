@@ -0,0 +1,420 @@
+//! Prometheus metrics of the daemon, in the text exposition format.
+//!
+//! Rendered by [`Metrics::render`] for every exporter (the node_exporter
+//! textfile written by [`write_textfile`] after each daemon cycle), so the
+//! names below are the same wherever the metrics are read. They are part of
+//! the interface: dashboards and alerts depend on them, so they must not be
+//! renamed.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::state::State;
+use crate::storage;
+use crate::updater::{DetectedIps, ProviderUpdate, UpdateOutcome};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Gauge set to 1, labelled with the running `version`.
+pub const BUILD_INFO: &str = "rusty_dns_build_info";
+
+/// Counter of updates sent to a provider since the daemon started, labelled
+/// with `label`, `provider` and `result` (`success` or `failure`).
+pub const UPDATES_TOTAL: &str = "rusty_dns_updates_total";
+
+/// Gauge of the Unix time of a provider's last successful update, labelled
+/// with `label` and `provider`.
+pub const LAST_UPDATE_TIMESTAMP: &str = "rusty_dns_last_update_timestamp_seconds";
+
+/// Gauge of whether every record of a provider holds the detected address
+/// (1) or not (0), labelled with `label` and `provider`.
+pub const IN_SYNC: &str = "rusty_dns_in_sync";
+
+/// Gauge of the seconds since a record of a provider was last read and
+/// found to hold the detected address, `+Inf` if it never was, labelled
+/// with `label` and `provider`.
+pub const SECONDS_SINCE_VERIFIED: &str = "rusty_dns_seconds_since_verified";
+
+/// Histogram of the time taken to detect the public addresses, in seconds.
+pub const DETECTION_DURATION: &str = "rusty_dns_ip_detection_duration_seconds";
+
+/// Upper bounds of the [`DETECTION_DURATION`] buckets, in seconds.
+pub const DETECTION_BUCKETS: [f64; 6] = [0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Metrics of one provider entry.
+#[derive(Debug, Clone, Default)]
+struct ProviderMetrics {
+    provider: String,
+    successes: u64,
+    failures: u64,
+    last_update: Option<DateTime<Utc>>,
+    last_verified: Option<DateTime<Utc>>,
+    in_sync: Option<bool>,
+}
+
+/// Metrics collected by the daemon over its cycles.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// By provider entry label.
+    providers: BTreeMap<String, ProviderMetrics>,
+    /// Detections per bucket of [`DETECTION_BUCKETS`] (not cumulative).
+    detection_buckets: [u64; DETECTION_BUCKETS.len()],
+    detection_count: u64,
+    detection_sum: f64,
+}
+
+impl Metrics {
+    /// Create empty metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a detection of the public addresses that took `elapsed`,
+    /// whether or not it succeeded.
+    pub fn observe_detection(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = DETECTION_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.detection_buckets[bucket] += 1;
+        }
+        self.detection_count += 1;
+        self.detection_sum += seconds;
+    }
+
+    /// Count the updates sent in a cycle.
+    pub fn record_updates(&mut self, updates: &[ProviderUpdate]) {
+        for update in updates {
+            let UpdateOutcome::Attempted(result) = &update.outcome else {
+                continue;
+            };
+            let metrics = self.provider(&update.label, update.provider);
+            if result.success {
+                metrics.successes += 1;
+            } else {
+                metrics.failures += 1;
+            }
+        }
+    }
+
+    /// Take the last update and verification times and sync status of
+    /// every enabled provider from the state saved after a cycle that
+    /// detected `ips`.
+    pub fn record_state(&mut self, config: &Config, ips: &DetectedIps, state: &State) {
+        for provider_config in config.enabled_providers() {
+            let label = provider_config.label();
+            let published = state.providers.get(&label).cloned().unwrap_or_default();
+            let detected = ips
+                .with_suffix(provider_config.ipv6_suffix, config.ipv6_prefix_length)
+                .for_version(provider_config.ip_version(config.ip_version));
+
+            let metrics = self.provider(&label, provider_config.name());
+            metrics.last_update = published.last_success;
+            metrics.last_verified = published.last_verified_at;
+            metrics.in_sync = (!detected.is_empty()).then(|| {
+                detected
+                    .iter()
+                    .all(|ip| published.published(ip) == Some(*ip))
+            });
+        }
+    }
+
+    fn provider(&mut self, label: &str, provider: &str) -> &mut ProviderMetrics {
+        self.providers
+            .entry(label.to_string())
+            .or_insert_with(|| ProviderMetrics {
+                provider: provider.to_string(),
+                ..Default::default()
+            })
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        self.render_at(Utc::now())
+    }
+
+    /// Render the metrics as of `now`.
+    fn render_at(&self, now: DateTime<Utc>) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            BUILD_INFO,
+            "gauge",
+            "Version of rusty-dns running.",
+        );
+        let _ = writeln!(
+            out,
+            "{}{{version=\"{}\"}} 1",
+            BUILD_INFO,
+            escape(env!("CARGO_PKG_VERSION"))
+        );
+
+        header(
+            &mut out,
+            UPDATES_TOTAL,
+            "counter",
+            "Updates sent to providers since the daemon started, by result.",
+        );
+        for (label, metrics) in &self.providers {
+            for (result, count) in [
+                ("success", metrics.successes),
+                ("failure", metrics.failures),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "{}{{{},result=\"{}\"}} {}",
+                    UPDATES_TOTAL,
+                    labels(label, metrics),
+                    result,
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            LAST_UPDATE_TIMESTAMP,
+            "gauge",
+            "Unix time of the last successful update of a provider.",
+        );
+        for (label, metrics) in &self.providers {
+            if let Some(at) = metrics.last_update {
+                let _ = writeln!(
+                    out,
+                    "{}{{{}}} {}",
+                    LAST_UPDATE_TIMESTAMP,
+                    labels(label, metrics),
+                    at.timestamp()
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            SECONDS_SINCE_VERIFIED,
+            "gauge",
+            "Seconds since a record of a provider was last found to hold the detected address.",
+        );
+        for (label, metrics) in &self.providers {
+            let seconds = match metrics.last_verified {
+                Some(at) => (now - at).num_seconds().max(0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}{{{}}} {}",
+                SECONDS_SINCE_VERIFIED,
+                labels(label, metrics),
+                seconds
+            );
+        }
+
+        header(
+            &mut out,
+            IN_SYNC,
+            "gauge",
+            "Whether every record of a provider holds the detected address.",
+        );
+        for (label, metrics) in &self.providers {
+            if let Some(in_sync) = metrics.in_sync {
+                let _ = writeln!(
+                    out,
+                    "{}{{{}}} {}",
+                    IN_SYNC,
+                    labels(label, metrics),
+                    u8::from(in_sync)
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            DETECTION_DURATION,
+            "histogram",
+            "Time taken to detect the public addresses.",
+        );
+        let mut cumulative = 0;
+        for (le, count) in DETECTION_BUCKETS.iter().zip(self.detection_buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                DETECTION_DURATION, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            DETECTION_DURATION, self.detection_count
+        );
+        let _ = writeln!(out, "{}_sum {}", DETECTION_DURATION, self.detection_sum);
+        let _ = writeln!(out, "{}_count {}", DETECTION_DURATION, self.detection_count);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn labels(label: &str, metrics: &ProviderMetrics) -> String {
+    format!(
+        "label=\"{}\",provider=\"{}\"",
+        escape(label),
+        escape(&metrics.provider)
+    )
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Write the metrics to `path` for node_exporter's textfile collector,
+/// atomically so it never reads a partial file.
+pub fn write_textfile(path: &Path, metrics: &Metrics) -> Result<()> {
+    storage::write_atomic(path, metrics.render().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::UpdateReason;
+    use crate::providers::{RecordType, UpdateResult};
+
+    fn update(label: &str, success: bool) -> ProviderUpdate {
+        ProviderUpdate {
+            label: label.to_string(),
+            provider: "cloudflare",
+            domain: "home.example.com".to_string(),
+            ip: "1.2.3.4".parse().unwrap(),
+            reason: UpdateReason::Changed,
+            outcome: UpdateOutcome::Attempted(UpdateResult {
+                provider: "cloudflare".to_string(),
+                label: Some(label.to_string()),
+                domain: "home.example.com".to_string(),
+                record_type: RecordType::A,
+                success,
+                ip: Some("1.2.3.4".parse().unwrap()),
+                previous_ip: None,
+                error: (!success).then(|| "KO".to_string()),
+                error_kind: None,
+                timestamp: Utc::now(),
+            }),
+            verified: false,
+        }
+    }
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            [[providers]]
+            type = "cloudflare"
+            label = "home"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "home.example.com"
+
+            [[providers]]
+            type = "duckdns"
+            label = "say \"hi\""
+            token = "token"
+            domains = "myhome"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::new();
+        metrics.observe_detection(Duration::from_millis(300));
+        metrics.observe_detection(Duration::from_millis(800));
+        metrics.observe_detection(Duration::from_secs(30));
+        metrics.record_updates(&[update("home", true), update("home", false)]);
+        metrics.record_updates(&[update("home", true)]);
+
+        let ips = DetectedIps {
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        let home = state.provider_mut("home");
+        home.last_ipv4 = ips.ipv4;
+        home.last_success = Some("2024-05-01T12:00:00Z".parse().unwrap());
+        home.last_verified_at = Some("2024-05-01T12:30:00Z".parse().unwrap());
+        metrics.record_state(&config(), &ips, &state);
+
+        let text = metrics.render_at("2024-05-01T13:00:00Z".parse().unwrap());
+        let lines: Vec<_> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        let version = format!(
+            "rusty_dns_build_info{{version=\"{}\"}} 1",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            lines,
+            [
+                version.as_str(),
+                r#"rusty_dns_updates_total{label="home",provider="cloudflare",result="success"} 2"#,
+                r#"rusty_dns_updates_total{label="home",provider="cloudflare",result="failure"} 1"#,
+                r#"rusty_dns_updates_total{label="say \"hi\"",provider="duckdns",result="success"} 0"#,
+                r#"rusty_dns_updates_total{label="say \"hi\"",provider="duckdns",result="failure"} 0"#,
+                r#"rusty_dns_last_update_timestamp_seconds{label="home",provider="cloudflare"} 1714564800"#,
+                r#"rusty_dns_seconds_since_verified{label="home",provider="cloudflare"} 1800"#,
+                r#"rusty_dns_seconds_since_verified{label="say \"hi\"",provider="duckdns"} +Inf"#,
+                r#"rusty_dns_in_sync{label="home",provider="cloudflare"} 1"#,
+                r#"rusty_dns_in_sync{label="say \"hi\"",provider="duckdns"} 0"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="0.25"} 0"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="0.5"} 1"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="1"} 2"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="2.5"} 2"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="5"} 2"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="10"} 2"#,
+                r#"rusty_dns_ip_detection_duration_seconds_bucket{le="+Inf"} 3"#,
+                r#"rusty_dns_ip_detection_duration_seconds_sum 31.1"#,
+                r#"rusty_dns_ip_detection_duration_seconds_count 3"#,
+            ]
+        );
+        for name in [
+            BUILD_INFO,
+            UPDATES_TOTAL,
+            LAST_UPDATE_TIMESTAMP,
+            SECONDS_SINCE_VERIFIED,
+            IN_SYNC,
+            DETECTION_DURATION,
+        ] {
+            assert!(text.contains(&format!("# TYPE {} ", name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_write_textfile() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-metrics-{}", std::process::id()));
+        let path = dir.join("rusty_dns.prom");
+
+        let mut metrics = Metrics::new();
+        write_textfile(&path, &metrics).unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&format!("{}{{", UPDATES_TOTAL)));
+
+        metrics.record_updates(&[update("home", true)]);
+        write_textfile(&path, &metrics).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), metrics.render());
+
+        // Replaced in place, with no temporary file left for the collector
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["rusty_dns.prom"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
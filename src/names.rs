@@ -0,0 +1,277 @@
+//! Domain and record names, checked when the config is read.
+//!
+//! Parsing lowercases names, converts internationalized ones to their ASCII
+//! (punycode) form and drops a trailing dot, so `Bücher.Example.` and
+//! `xn--bcher-kva.example` are the same name.
+
+use crate::error::{DdnsError, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest name, without the trailing dot.
+const MAX_NAME_LEN: usize = 253;
+
+/// Longest label.
+const MAX_LABEL_LEN: usize = 63;
+
+/// Fully qualified domain name, e.g. the zone holding a record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DomainName(String);
+
+impl DomainName {
+    /// Parse and normalize a domain name.
+    pub fn parse(name: &str) -> Result<Self> {
+        normalize(name, false)
+            .map(Self)
+            .map_err(|e| DdnsError::Config(format!("invalid domain name \"{}\": {}", name, e)))
+    }
+
+    /// Get the normalized name.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Name of a DNS record: `@` for the apex of a zone, or a name whose first
+/// label may be the wildcard `*`.
+///
+/// Whether it is relative to a zone depends on the provider: Namecheap's
+/// `host` is, Cloudflare's `record_name` is the full name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordName(String);
+
+impl RecordName {
+    /// Name of the apex record.
+    const APEX: &'static str = "@";
+
+    /// Get the name of the apex record, `@`.
+    pub fn apex() -> Self {
+        Self(Self::APEX.to_string())
+    }
+
+    /// Parse and normalize a record name.
+    pub fn parse(name: &str) -> Result<Self> {
+        if name == Self::APEX {
+            return Ok(Self::apex());
+        }
+        normalize(name, true)
+            .map(Self)
+            .map_err(|e| DdnsError::Config(format!("invalid record name \"{}\": {}", name, e)))
+    }
+
+    /// Whether this is the apex record, `@`.
+    pub fn is_apex(&self) -> bool {
+        self.0 == Self::APEX
+    }
+
+    /// Whether this is a wildcard record, e.g. `*.example.com`.
+    pub fn is_wildcard(&self) -> bool {
+        self.0 == "*" || self.0.starts_with("*.")
+    }
+
+    /// Get the normalized name, `@` for the apex.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the full name of the record in `zone`, e.g. `vpn.example.com`
+    /// for `vpn` in `example.com`, or `example.com` for `@`.
+    pub fn full_domain(&self, zone: &DomainName) -> String {
+        if self.is_apex() {
+            zone.to_string()
+        } else {
+            format!("{}.{}", self, zone)
+        }
+    }
+}
+
+/// Lowercase `name`, convert it to ASCII and check its labels, returning
+/// what is wrong with it otherwise.
+fn normalize(name: &str, allow_wildcard: bool) -> std::result::Result<String, String> {
+    if name.is_empty() {
+        return Err("is empty".to_string());
+    }
+    if name.chars().any(char::is_whitespace) {
+        return Err("contains whitespace".to_string());
+    }
+    // Fully qualified form, as in zone files
+    let relative = name.strip_suffix('.').unwrap_or(name);
+    if relative.is_empty() || relative.ends_with('.') {
+        return Err("has an empty label".to_string());
+    }
+
+    let ascii = idna::domain_to_ascii(relative)
+        .map_err(|_| "is not a valid internationalized name".to_string())?;
+    if ascii.len() > MAX_NAME_LEN {
+        return Err(format!("is longer than {} characters", MAX_NAME_LEN));
+    }
+
+    for (i, label) in ascii.split('.').enumerate() {
+        if i == 0 && allow_wildcard && label == "*" {
+            continue;
+        }
+        if label.is_empty() {
+            return Err("has an empty label".to_string());
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(format!(
+                "label \"{}\" is longer than {} characters",
+                label, MAX_LABEL_LEN
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label \"{}\" starts or ends with a hyphen", label));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(match c {
+                '*' => format!("label \"{}\" has a wildcard not in first place", label),
+                c => format!("label \"{}\" contains '{}'", label, c),
+            });
+        }
+    }
+
+    Ok(ascii)
+}
+
+impl FromStr for DomainName {
+    type Err = DdnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl FromStr for RecordName {
+    type Err = DdnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for RecordName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for DomainName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl Serialize for RecordName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DomainName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::parse(&name).map_err(de_error)
+    }
+}
+
+impl<'de> Deserialize<'de> for RecordName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::parse(&name).map_err(de_error)
+    }
+}
+
+// Plain strings in the config schema, checked as the config is read
+impl schemars::JsonSchema for DomainName {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "DomainName".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl schemars::JsonSchema for RecordName {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "RecordName".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Report a parse error without the "Configuration error" prefix, as the
+/// config loader adds its own context.
+fn de_error<E: serde::de::Error>(e: DdnsError) -> E {
+    match e {
+        DdnsError::Config(message) => E::custom(message),
+        e => E::custom(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_name() {
+        let parse = |name: &str| DomainName::parse(name).map(|n| n.to_string());
+        assert_eq!(parse("Example.COM.").unwrap(), "example.com");
+        assert_eq!(parse("bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(parse("_dmarc.example.com").unwrap(), "_dmarc.example.com");
+
+        let err = |name: &str| parse(name).unwrap_err().to_string();
+        assert!(err("").contains("is empty"));
+        assert!(err("example .com").contains("contains whitespace"));
+        assert!(err("example..com").contains("has an empty label"));
+        assert!(err("example.com..").contains("has an empty label"));
+        assert!(err("*.example.com").contains("label \"*\" has a wildcard"));
+        assert!(err("-vpn.example.com").contains("label \"-vpn\" starts or ends with a hyphen"));
+        assert!(err("vpn!.example.com").contains("label \"vpn!\" contains '!'"));
+        assert!(err(&format!("{}.com", "a".repeat(64))).contains("is longer than 63 characters"));
+        assert!(err(&["a"; 128].join(".")).contains("is longer than 253 characters"));
+        assert!(err("@").contains("invalid domain name \"@\""));
+    }
+
+    #[test]
+    fn test_record_name() {
+        let zone = DomainName::parse("example.com").unwrap();
+        let full = |name: &str| RecordName::parse(name).unwrap().full_domain(&zone);
+        assert_eq!(full("@"), "example.com");
+        assert_eq!(full("VPN"), "vpn.example.com");
+        assert_eq!(full("*.home"), "*.home.example.com");
+
+        assert!(RecordName::parse("@").unwrap().is_apex());
+        assert!(RecordName::parse("*").unwrap().is_wildcard());
+        assert!(RecordName::parse("vpn.*").is_err());
+        assert!(RecordName::parse("vpn.*.example.com").is_err());
+
+        // Names read from a config are normalized the same way
+        let name: RecordName = serde_json::from_str("\"Vpn.Example.com.\"").unwrap();
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"vpn.example.com\"");
+        let err = serde_json::from_str::<DomainName>("\"a..b\"").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("invalid domain name \"a..b\": has an empty label"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Network change detection via Linux netlink.
+//!
+//! Subscribes to rtnetlink address and route notifications so the daemon can
+//! run an update cycle as soon as the WAN address changes instead of waiting
+//! for the next poll.
+
+use crate::error::{DdnsError, Result};
+use netlink_sys::protocols::NETLINK_ROUTE;
+use netlink_sys::{AsyncSocket, AsyncSocketExt, SocketAddr, TokioSocket};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Default quiet period before a burst of events triggers a cycle.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+const NLMSG_HDRLEN: usize = 16;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_TABLE_MAIN: u8 = 254;
+
+/// Spawn a background task watching for network changes.
+///
+/// The returned receiver yields one message per debounced burst of relevant
+/// events (global address or default route added/removed).
+pub fn spawn_watcher(debounce: Duration) -> Result<mpsc::Receiver<()>> {
+    let mut socket = TokioSocket::new(NETLINK_ROUTE)
+        .map_err(|e| DdnsError::Network(format!("Failed to open netlink socket: {}", e)))?;
+
+    let groups = RTMGRP_IPV4_IFADDR | RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_IFADDR | RTMGRP_IPV6_ROUTE;
+    socket
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))
+        .map_err(|e| DdnsError::Network(format!("Failed to bind netlink socket: {}", e)))?;
+
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        loop {
+            if !next_relevant_event(&socket).await {
+                return;
+            }
+
+            // Wait until events stop arriving for the debounce window
+            while let Ok(alive) = tokio::time::timeout(debounce, next_relevant_event(&socket)).await
+            {
+                if !alive {
+                    return;
+                }
+            }
+
+            tracing::debug!("Network change detected");
+            if tx.try_send(()).is_err() && tx.is_closed() {
+                return;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Wait for the next relevant netlink event. Returns false if the socket failed.
+async fn next_relevant_event(socket: &TokioSocket) -> bool {
+    loop {
+        match socket.recv_from_full().await {
+            Ok((buf, _)) => {
+                if is_relevant_change(&buf) {
+                    return true;
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Netlink socket error, network watch disabled: {}", e);
+                return false;
+            }
+        }
+    }
+}
+
+/// Check whether a netlink datagram contains a global address or default route change.
+fn is_relevant_change(buf: &[u8]) -> bool {
+    let mut offset = 0;
+
+    while offset + NLMSG_HDRLEN <= buf.len() {
+        let len = u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        let msg_type = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+
+        let payload = &buf[offset + NLMSG_HDRLEN..offset + len];
+
+        let relevant = match msg_type {
+            // struct ifaddrmsg { family, prefixlen, flags, scope, index }
+            RTM_NEWADDR | RTM_DELADDR => payload.len() >= 4 && payload[3] == RT_SCOPE_UNIVERSE,
+            // struct rtmsg { family, dst_len, src_len, tos, table, ... }
+            RTM_NEWROUTE | RTM_DELROUTE => {
+                payload.len() >= 5 && payload[1] == 0 && payload[4] == RT_TABLE_MAIN
+            }
+            _ => false,
+        };
+
+        if relevant {
+            return true;
+        }
+
+        // Messages are 4-byte aligned
+        offset += (len + 3) & !3;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+        let len = (NLMSG_HDRLEN + payload.len()) as u32;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&len.to_ne_bytes());
+        buf.extend_from_slice(&msg_type.to_ne_bytes());
+        buf.extend_from_slice(&[0u8; 10]);
+        buf.extend_from_slice(payload);
+        while buf.len() % 4 != 0 {
+            buf.push(0);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_global_address_is_relevant() {
+        let buf = message(RTM_NEWADDR, &[2, 24, 0, RT_SCOPE_UNIVERSE, 2, 0, 0, 0]);
+        assert!(is_relevant_change(&buf));
+    }
+
+    #[test]
+    fn test_link_local_address_is_ignored() {
+        // RT_SCOPE_LINK = 253
+        let buf = message(RTM_NEWADDR, &[10, 64, 0, 253, 2, 0, 0, 0]);
+        assert!(!is_relevant_change(&buf));
+    }
+
+    #[test]
+    fn test_default_route_is_relevant() {
+        let buf = message(RTM_DELROUTE, &[2, 0, 0, 0, RT_TABLE_MAIN, 3, 0, 1]);
+        assert!(is_relevant_change(&buf));
+    }
+
+    #[test]
+    fn test_subnet_route_is_ignored() {
+        let buf = message(RTM_NEWROUTE, &[2, 24, 0, 0, RT_TABLE_MAIN, 3, 0, 1]);
+        assert!(!is_relevant_change(&buf));
+    }
+
+    #[test]
+    fn test_multiple_messages() {
+        let mut buf = message(RTM_NEWROUTE, &[2, 24, 0, 0, RT_TABLE_MAIN, 3, 0, 1]);
+        buf.extend(message(
+            RTM_NEWADDR,
+            &[2, 24, 0, RT_SCOPE_UNIVERSE, 2, 0, 0, 0],
+        ));
+        assert!(is_relevant_change(&buf));
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let mut buf = message(RTM_NEWADDR, &[2, 24, 0, RT_SCOPE_UNIVERSE, 2, 0, 0, 0]);
+        buf.truncate(10);
+        assert!(!is_relevant_change(&buf));
+    }
+}
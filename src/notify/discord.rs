@@ -0,0 +1,141 @@
+//! Discord webhook notifier.
+
+use super::{ip_or_unknown, Event, EventKind, Notifier};
+use crate::error::{DdnsError, Result};
+use crate::history::UpdateReason;
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Embed sidebar color for successful events.
+const COLOR_SUCCESS: u32 = 0x2ecc71;
+
+/// Embed sidebar color for failures.
+const COLOR_FAILURE: u32 = 0xe74c3c;
+
+/// Longest rate-limit delay worth waiting for before dropping the message.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Posts events as embeds to a Discord webhook.
+pub struct DiscordNotifier {
+    client: crate::http::Client,
+    url: String,
+}
+
+impl DiscordNotifier {
+    /// Create a new Discord notifier.
+    pub fn new(url: String) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            url,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn post(&self, payload: &serde_json::Value) -> Result<reqwest::Response> {
+        Ok(self.client.post(&self.url).json(payload).send().await?)
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let payload = embed(event);
+        let mut response = self.post(&payload).await?;
+
+        // Rate limited: retry once after the delay Discord asks for
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let body: serde_json::Value = response.json().await.unwrap_or_default();
+            let retry_after = body["retry_after"].as_f64().unwrap_or(1.0);
+            let delay = Duration::from_secs_f64(retry_after.max(0.0));
+
+            if delay > MAX_RETRY_AFTER {
+                return Err(DdnsError::Network(format!(
+                    "Discord rate limited for {:.1}s, dropping message",
+                    retry_after
+                )));
+            }
+
+            tokio::time::sleep(delay).await;
+            response = self.post(&payload).await?;
+        }
+
+        if !response.status().is_success() {
+            return Err(DdnsError::Network(format!(
+                "Discord returned HTTP {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Build the webhook payload for an event.
+fn embed(event: &Event) -> serde_json::Value {
+    let (title, failed, description) = match event.event {
+        EventKind::IpChanged => {
+            let lines: Vec<_> = event
+                .providers
+                .iter()
+                .map(|p| {
+                    if p.success && p.reason == UpdateReason::Changed {
+                        format!("✅ **{}** ({})", p.provider, p.domain)
+                    } else if p.success {
+                        format!("✅ **{}** ({}, {})", p.provider, p.domain, p.reason)
+                    } else {
+                        format!(
+                            "❌ **{}** ({}): {}",
+                            p.provider,
+                            p.domain,
+                            p.error.as_deref().unwrap_or("failed")
+                        )
+                    }
+                })
+                .chain(event.groups.iter().map(|g| g.describe()))
+                .collect();
+            ("IP changed", !event.success(), lines.join("\n"))
+        }
+        EventKind::UpdateFailed => (
+            "Update failed",
+            true,
+            format!(
+                "❌ **{}** ({}): {}",
+                event.provider.as_deref().unwrap_or_default(),
+                event.domain.as_deref().unwrap_or_default(),
+                event.error.as_deref().unwrap_or("unknown error")
+            ),
+        ),
+        EventKind::UpdateRecovered => (
+            "Update recovered",
+            false,
+            format!(
+                "✅ **{}** ({})",
+                event.provider.as_deref().unwrap_or_default(),
+                event.domain.as_deref().unwrap_or_default()
+            ),
+        ),
+        EventKind::Test => ("Test notification", false, event.message()),
+    };
+
+    serde_json::json!({
+        "embeds": [{
+            "title": title,
+            "description": description,
+            "color": if failed { COLOR_FAILURE } else { COLOR_SUCCESS },
+            "fields": [
+                { "name": "Old IP", "value": ip_or_unknown(event.old_ip), "inline": true },
+                { "name": "New IP", "value": ip_or_unknown(event.new_ip), "inline": true }
+            ],
+            "timestamp": event.timestamp.to_rfc3339()
+        }]
+    })
+}
@@ -0,0 +1,271 @@
+//! Email (SMTP) notifier.
+
+use super::{Event, Notifier};
+use crate::error::{DdnsError, Result};
+use crate::history::HistoryStore;
+use crate::secret::Secret;
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of history entries included in each message.
+const HISTORY_ENTRIES: usize = 5;
+
+/// First delay after a delivery failure; doubles on each further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Longest delay between delivery attempts while the server is failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// SMTP connection timeout.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SMTP connection settings.
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    /// SMTP server host.
+    pub host: String,
+    /// SMTP server port.
+    pub port: u16,
+    /// Login username.
+    pub username: Option<String>,
+    /// Login password.
+    pub password: Option<Secret>,
+    /// Upgrade the connection with STARTTLS (otherwise implicit TLS on
+    /// port 465, plain text elsewhere).
+    pub use_starttls: bool,
+}
+
+/// Backoff state after delivery failures.
+#[derive(Debug, Default)]
+struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// Sends events by email.
+pub struct EmailNotifier {
+    smtp: SmtpSettings,
+    from: String,
+    to: Vec<String>,
+    history: Option<Arc<dyn HistoryStore>>,
+    backoff: Mutex<Backoff>,
+}
+
+impl EmailNotifier {
+    /// Create a new email notifier.
+    ///
+    /// Recent entries from `history` are appended to each message.
+    pub fn new(
+        smtp: SmtpSettings,
+        from: String,
+        to: Vec<String>,
+        history: Option<Arc<dyn HistoryStore>>,
+    ) -> Self {
+        Self {
+            smtp,
+            from,
+            to,
+            history,
+            backoff: Mutex::new(Backoff::default()),
+        }
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let smtp_error = |e: lettre::transport::smtp::Error| DdnsError::Network(e.to_string());
+
+        let builder = if self.smtp.use_starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.smtp.host)
+                .map_err(smtp_error)?
+        } else if self.smtp.port == 465 {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&self.smtp.host).map_err(smtp_error)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.smtp.host)
+        };
+
+        let mut builder = builder.port(self.smtp.port).timeout(Some(SMTP_TIMEOUT));
+        if let Some(username) = &self.smtp.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                self.smtp
+                    .password
+                    .as_ref()
+                    .map(|p| p.expose().to_string())
+                    .unwrap_or_default(),
+            ));
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Build the message for an event.
+    pub(super) fn message(&self, event: &Event) -> Result<Message> {
+        let config_error = |e: lettre::address::AddressError| {
+            DdnsError::Config(format!("Invalid email address: {}", e))
+        };
+
+        let mut builder = Message::builder()
+            .from(self.from.parse::<Mailbox>().map_err(config_error)?)
+            .subject(format!("[rusty-dns] {}", event.title()));
+        for to in &self.to {
+            builder = builder.to(to.parse::<Mailbox>().map_err(config_error)?);
+        }
+
+        builder
+            .body(self.body(event))
+            .map_err(|e| DdnsError::Config(format!("Invalid email message: {}", e)))
+    }
+
+    fn body(&self, event: &Event) -> String {
+        let mut body = format!(
+            "{}\n\n{}\n",
+            event.message(),
+            event.timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+
+        let entries = self
+            .history
+            .as_ref()
+            .and_then(|store| store.entries().ok())
+            .unwrap_or_default();
+        if !entries.is_empty() {
+            body.push_str("\nRecent updates:\n");
+            for entry in entries.iter().rev().take(HISTORY_ENTRIES) {
+                let r = &entry.result;
+                body.push_str(&format!(
+                    "  {} {} ({}, {}) {} {}\n",
+                    r.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                    r.label.as_deref().unwrap_or(&r.provider),
+                    r.domain,
+                    r.record_type,
+                    r.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                    entry.outcome()
+                ));
+            }
+        }
+
+        body
+    }
+
+    /// Check whether a delivery attempt is allowed right now.
+    fn check_backoff(&self, now: Instant) -> Result<()> {
+        let backoff = self.backoff.lock().unwrap();
+        match backoff.retry_at {
+            Some(retry_at) if now < retry_at => Err(DdnsError::Network(format!(
+                "Email suppressed for {}s after {} failed deliveries",
+                (retry_at - now).as_secs(),
+                backoff.failures
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a delivery outcome, backing off exponentially after failures.
+    fn record_delivery(&self, success: bool, now: Instant) {
+        let mut backoff = self.backoff.lock().unwrap();
+        if success {
+            *backoff = Backoff::default();
+            return;
+        }
+
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(backoff.failures))
+            .min(MAX_BACKOFF);
+        backoff.failures += 1;
+        backoff.retry_at = Some(now + delay);
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        self.check_backoff(Instant::now())?;
+
+        let message = self.message(event)?;
+        let result = self.transport()?.send(message).await;
+        self.record_delivery(result.is_ok(), Instant::now());
+
+        result
+            .map(|_| ())
+            .map_err(|e| DdnsError::Network(format!("SMTP delivery failed: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::EventKind;
+
+    fn notifier() -> EmailNotifier {
+        EmailNotifier::new(
+            SmtpSettings {
+                host: "127.0.0.1".to_string(),
+                port: 1,
+                username: None,
+                password: None,
+                use_starttls: false,
+            },
+            "rusty-dns <ddns@example.com>".to_string(),
+            vec!["me@example.com".to_string(), "ops@example.com".to_string()],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_message() {
+        let event = Event::provider(
+            EventKind::UpdateFailed,
+            "cloudflare",
+            "vpn.example.com",
+            None,
+            "5.6.7.8".parse().unwrap(),
+            Some("401 Unauthorized".to_string()),
+        );
+
+        let message = notifier().message(&event).unwrap();
+        let raw = String::from_utf8(message.formatted()).unwrap();
+
+        assert!(raw.contains("Subject: [rusty-dns] Update failed: cloudflare"));
+        assert!(raw.contains("To: me@example.com, ops@example.com"));
+        assert!(raw.contains("cloudflare (vpn.example.com) failed to update to 5.6.7.8"));
+    }
+
+    #[test]
+    fn test_backoff_after_failures() {
+        let notifier = notifier();
+        let now = Instant::now();
+
+        assert!(notifier.check_backoff(now).is_ok());
+
+        notifier.record_delivery(false, now);
+        assert!(notifier.check_backoff(now).is_err());
+        assert!(notifier.check_backoff(now + INITIAL_BACKOFF).is_ok());
+
+        // Second failure doubles the delay
+        notifier.record_delivery(false, now);
+        assert!(notifier.check_backoff(now + INITIAL_BACKOFF).is_err());
+        assert!(notifier.check_backoff(now + INITIAL_BACKOFF * 2).is_ok());
+
+        notifier.record_delivery(true, now);
+        assert!(notifier.check_backoff(now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_suppressed() {
+        let notifier = notifier();
+        let event = Event::ip_changed(None, "5.6.7.8".parse().unwrap(), Vec::new());
+
+        let err = notifier.notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("SMTP delivery failed"));
+
+        let err = notifier.notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("suppressed"));
+    }
+}
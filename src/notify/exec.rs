@@ -0,0 +1,129 @@
+//! Command hook notifier.
+
+use super::{Event, Notifier};
+use crate::error::{DdnsError, Result};
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Maximum bytes of command output kept for logging.
+const MAX_OUTPUT: usize = 1024;
+
+/// Runs a command for each event, passing the event as JSON on stdin.
+pub struct ExecNotifier {
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl ExecNotifier {
+    /// Create a new exec notifier.
+    pub fn new(command: String, args: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            command,
+            args,
+            timeout,
+        }
+    }
+
+    fn env(event: &Event) -> [(&'static str, String); 5] {
+        let ip = |ip: Option<std::net::IpAddr>| ip.map(|ip| ip.to_string()).unwrap_or_default();
+        [
+            ("DDNS_EVENT", event.event.to_string()),
+            ("DDNS_OLD_IP", ip(event.old_ip)),
+            ("DDNS_NEW_IP", ip(event.new_ip)),
+            ("DDNS_PROVIDER", event.provider.clone().unwrap_or_default()),
+            ("DDNS_DOMAIN", event.domain.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+#[async_trait]
+impl Notifier for ExecNotifier {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let payload = serde_json::to_vec(event)?;
+
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .envs(Self::env(event))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The command may exit without reading its input
+            if let Err(e) = stdin.write_all(&payload).await {
+                tracing::debug!("Failed to write event to {}: {}", self.command, e);
+            }
+        }
+
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                DdnsError::Network(format!(
+                    "{} timed out after {}s",
+                    self.command,
+                    self.timeout.as_secs()
+                ))
+            })??;
+
+        let stdout = truncate_output(&output.stdout);
+        if !stdout.is_empty() {
+            tracing::info!("{}: {}", self.command, stdout);
+        }
+
+        if !output.status.success() {
+            return Err(DdnsError::Network(format!(
+                "{} exited with {}: {}",
+                self.command,
+                output.status,
+                truncate_output(&output.stderr)
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert command output to a trimmed string of at most `MAX_OUTPUT` bytes.
+fn truncate_output(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let output = output.trim();
+
+    if output.len() <= MAX_OUTPUT {
+        return output.to_string();
+    }
+
+    let mut end = MAX_OUTPUT;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!(
+        "{}… ({} bytes truncated)",
+        &output[..end],
+        output.len() - end
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output(b"  ok\n"), "ok");
+
+        let long = "é".repeat(MAX_OUTPUT);
+        let truncated = truncate_output(long.as_bytes());
+        assert!(truncated.starts_with(&"é".repeat(MAX_OUTPUT / 2)));
+        assert!(truncated.ends_with(&format!("({} bytes truncated)", MAX_OUTPUT)));
+    }
+}
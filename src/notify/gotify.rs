@@ -0,0 +1,55 @@
+//! Gotify push notifier.
+
+use super::{send_request, Event, Notifier};
+use crate::error::Result;
+use crate::secret::Secret;
+use async_trait::async_trait;
+
+/// Sends events as Gotify application messages.
+pub struct GotifyNotifier {
+    client: crate::http::Client,
+    server: String,
+    app_token: Secret,
+    priority: u8,
+}
+
+impl GotifyNotifier {
+    /// Create a new Gotify notifier.
+    pub fn new(server: String, app_token: impl Into<Secret>, priority: u8) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            server,
+            app_token: app_token.into(),
+            priority,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+#[async_trait]
+impl Notifier for GotifyNotifier {
+    fn name(&self) -> &'static str {
+        "gotify"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let url = format!("{}/message", self.server.trim_end_matches('/'));
+
+        let request = self
+            .client
+            .post(&url)
+            .header("X-Gotify-Key", self.app_token.expose())
+            .json(&serde_json::json!({
+                "title": event.title(),
+                "message": event.message(),
+                "priority": self.priority
+            }));
+
+        send_request(request, "Gotify").await
+    }
+}
@@ -0,0 +1,64 @@
+//! healthchecks.io-style dead man's switch.
+
+use super::{send_request, CycleSummary, Event, Notifier};
+use crate::error::Result;
+use async_trait::async_trait;
+
+/// Pings a check URL after every daemon cycle, so a missing ping means the
+/// daemon (or the machine it runs on) is down.
+pub struct HealthcheckNotifier {
+    client: crate::http::Client,
+    url: String,
+}
+
+impl HealthcheckNotifier {
+    /// Create a new healthcheck notifier.
+    pub fn new(url: String) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn ping(&self, suffix: &str, body: String) -> Result<()> {
+        let request = self
+            .client
+            .post(format!("{}{}", self.url, suffix))
+            .body(body);
+
+        send_request(request, "Healthcheck").await
+    }
+}
+
+#[async_trait]
+impl Notifier for HealthcheckNotifier {
+    fn name(&self) -> &'static str {
+        "healthcheck"
+    }
+
+    /// Events are ignored; only cycle outcomes are reported.
+    async fn notify(&self, _event: &Event) -> Result<()> {
+        Ok(())
+    }
+
+    async fn on_start(&self) -> Result<()> {
+        self.ping("/start", "rusty-dns daemon started".to_string())
+            .await
+    }
+
+    async fn on_cycle(&self, summary: &CycleSummary) -> Result<()> {
+        let suffix = if summary.success() { "" } else { "/fail" };
+        self.ping(suffix, summary.message()).await
+    }
+
+    /// Logs an event without changing the check's status.
+    async fn test(&self) -> Result<()> {
+        self.ping("/log", Event::test().message()).await
+    }
+}
@@ -0,0 +1,671 @@
+//! Notification channels for IP changes and update failures.
+
+mod discord;
+#[cfg(feature = "email")]
+mod email;
+mod exec;
+mod gotify;
+mod healthcheck;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod ntfy;
+mod telegram;
+#[cfg(test)]
+mod tests;
+mod webhook;
+
+pub use discord::DiscordNotifier;
+#[cfg(feature = "email")]
+pub use email::{EmailNotifier, SmtpSettings};
+pub use exec::ExecNotifier;
+pub use gotify::GotifyNotifier;
+pub use healthcheck::HealthcheckNotifier;
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttNotifier, MqttSettings};
+pub use ntfy::NtfyNotifier;
+pub use telegram::TelegramNotifier;
+pub use webhook::WebhookNotifier;
+
+use crate::config::{Config, GroupPolicy, NotificationChannel};
+use crate::error::{DdnsError, Result};
+use crate::history::{self, HistoryStore};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Kind of notification event.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The public IP address changed.
+    IpChanged,
+    /// A provider failed `daemon.max_consecutive_failures` updates in a row.
+    UpdateFailed,
+    /// A provider succeeded again after a reported failure.
+    UpdateRecovered,
+    /// Test message sent on request (can't be subscribed to).
+    #[serde(skip_deserializing)]
+    Test,
+}
+
+impl EventKind {
+    /// Get the event name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::IpChanged => "ip_changed",
+            EventKind::UpdateFailed => "update_failed",
+            EventKind::UpdateRecovered => "update_recovered",
+            EventKind::Test => "test",
+        }
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Outcome of a provider update included in an `ip_changed` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderOutcome {
+    /// Provider name.
+    pub provider: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Whether the update succeeded.
+    pub success: bool,
+    /// Error message if failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why the update was sent.
+    #[serde(default)]
+    pub reason: history::UpdateReason,
+    /// Group of the provider entry (see [`GroupOutcome`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl ProviderOutcome {
+    /// Describe the outcome, e.g. `OK`, `OK (forced)` or `FAILED - <error>`.
+    pub fn describe(&self) -> String {
+        match (self.success, self.reason) {
+            (true, history::UpdateReason::Changed) => "OK".to_string(),
+            (true, reason) => format!("OK ({})", reason),
+            (false, _) => format!(
+                "FAILED - {}",
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+/// Outcome of the provider updates of a group, included in `ip_changed`
+/// events and cycle summaries next to its members' outcomes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupOutcome {
+    /// Group name.
+    pub name: String,
+    /// Which members had to succeed.
+    pub policy: GroupPolicy,
+    /// Whether they did.
+    pub success: bool,
+}
+
+/// Whether every update succeeded, except for failures its group's policy
+/// allows.
+fn all_succeeded(providers: &[ProviderOutcome], groups: &[GroupOutcome]) -> bool {
+    providers
+        .iter()
+        .filter(|p| p.group.is_none())
+        .all(|p| p.success)
+        && groups.iter().all(|g| g.success)
+}
+
+impl GroupOutcome {
+    /// Describe the outcome, e.g. `Group home (primary-failover): OK`.
+    pub fn describe(&self) -> String {
+        format!(
+            "Group {} ({}): {}",
+            self.name,
+            self.policy,
+            if self.success { "OK" } else { "FAILED" }
+        )
+    }
+}
+
+/// A notification event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Event kind.
+    pub event: EventKind,
+    /// Previous IP address (if known).
+    pub old_ip: Option<IpAddr>,
+    /// New IP address.
+    pub new_ip: Option<IpAddr>,
+    /// Provider name (provider events only).
+    pub provider: Option<String>,
+    /// Domain (provider events only).
+    pub domain: Option<String>,
+    /// Error message (failure events only).
+    pub error: Option<String>,
+    /// Provider updates triggered by an IP change.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<ProviderOutcome>,
+    /// Groups of the providers in `providers`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupOutcome>,
+    /// When the event happened.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Event {
+    /// Create an `ip_changed` event.
+    pub fn ip_changed(
+        old_ip: Option<IpAddr>,
+        new_ip: IpAddr,
+        providers: Vec<ProviderOutcome>,
+    ) -> Self {
+        Self {
+            event: EventKind::IpChanged,
+            old_ip,
+            new_ip: Some(new_ip),
+            provider: None,
+            domain: None,
+            error: None,
+            providers,
+            groups: Vec::new(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Add the outcomes of the groups of the event's providers.
+    pub fn with_groups(mut self, groups: Vec<GroupOutcome>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Check whether the provider updates of an `ip_changed` event
+    /// succeeded, as far as their groups' policies require.
+    pub fn success(&self) -> bool {
+        all_succeeded(&self.providers, &self.groups)
+    }
+
+    /// Create a provider event (`update_failed` or `update_recovered`).
+    pub fn provider(
+        event: EventKind,
+        provider: &str,
+        domain: &str,
+        old_ip: Option<IpAddr>,
+        new_ip: IpAddr,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            event,
+            old_ip,
+            new_ip: Some(new_ip),
+            provider: Some(provider.to_string()),
+            domain: Some(domain.to_string()),
+            error,
+            providers: Vec::new(),
+            groups: Vec::new(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+impl Event {
+    /// Create a `test` event.
+    pub fn test() -> Self {
+        Self {
+            event: EventKind::Test,
+            old_ip: None,
+            new_ip: None,
+            provider: None,
+            domain: None,
+            error: None,
+            providers: Vec::new(),
+            groups: Vec::new(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Get a short plain-text title.
+    pub fn title(&self) -> String {
+        match self.event {
+            EventKind::IpChanged => "IP changed".to_string(),
+            EventKind::UpdateFailed => format!(
+                "Update failed: {}",
+                self.provider.as_deref().unwrap_or_default()
+            ),
+            EventKind::UpdateRecovered => format!(
+                "Update recovered: {}",
+                self.provider.as_deref().unwrap_or_default()
+            ),
+            EventKind::Test => "Test notification".to_string(),
+        }
+    }
+
+    /// Get a plain-text summary.
+    pub fn message(&self) -> String {
+        match self.event {
+            EventKind::IpChanged => {
+                let mut message = format!(
+                    "{} -> {}",
+                    ip_or_unknown(self.old_ip),
+                    ip_or_unknown(self.new_ip)
+                );
+                for p in &self.providers {
+                    message.push_str(&format!(
+                        "\n{} ({}): {}",
+                        p.provider,
+                        p.domain,
+                        p.describe()
+                    ));
+                }
+                for group in &self.groups {
+                    message.push_str(&format!("\n{}", group.describe()));
+                }
+                message
+            }
+            EventKind::UpdateFailed => format!(
+                "{} ({}) failed to update to {}: {}",
+                self.provider.as_deref().unwrap_or_default(),
+                self.domain.as_deref().unwrap_or_default(),
+                ip_or_unknown(self.new_ip),
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+            EventKind::UpdateRecovered => format!(
+                "{} ({}) updated to {}",
+                self.provider.as_deref().unwrap_or_default(),
+                self.domain.as_deref().unwrap_or_default(),
+                ip_or_unknown(self.new_ip)
+            ),
+            EventKind::Test => "rusty-dns can deliver notifications to this channel".to_string(),
+        }
+    }
+}
+
+/// Summary of a daemon update cycle, reported to every channel after each
+/// cycle whether or not anything changed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CycleSummary {
+    /// Detected IPv4 address.
+    pub ipv4: Option<IpAddr>,
+    /// Detected IPv6 address.
+    pub ipv6: Option<IpAddr>,
+    /// Provider updates attempted during the cycle.
+    pub providers: Vec<ProviderOutcome>,
+    /// Groups of the providers in `providers`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupOutcome>,
+    /// Error that aborted the cycle (detection or state failure).
+    pub error: Option<String>,
+}
+
+impl CycleSummary {
+    /// Create a summary for a cycle that failed outright.
+    pub fn failed(error: impl std::fmt::Display) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Check whether the cycle completed and every attempted update
+    /// succeeded, except for failures allowed by group policies.
+    pub fn success(&self) -> bool {
+        self.error.is_none() && all_succeeded(&self.providers, &self.groups)
+    }
+
+    /// Get a plain-text summary.
+    pub fn message(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("Cycle failed: {}", error);
+        }
+
+        let mut message = String::new();
+        for (family, ip) in [("IPv4", self.ipv4), ("IPv6", self.ipv6)] {
+            if let Some(ip) = ip {
+                message.push_str(&format!("{}: {}\n", family, ip));
+            }
+        }
+
+        if self.providers.is_empty() {
+            message.push_str("No updates needed");
+        }
+        for p in &self.providers {
+            message.push_str(&format!(
+                "{} ({}): {}\n",
+                p.provider,
+                p.domain,
+                p.describe()
+            ));
+        }
+        for group in &self.groups {
+            message.push_str(&format!("{}\n", group.describe()));
+        }
+
+        message.trim_end().to_string()
+    }
+}
+
+/// Send a notification request, failing on non-success HTTP status.
+async fn send_request(request: crate::http::RequestBuilder, channel: &str) -> Result<()> {
+    let response = request.send().await?;
+
+    if !response.status().is_success() {
+        return Err(DdnsError::Network(format!(
+            "{} returned HTTP {}",
+            channel,
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Format an optional address for display.
+fn ip_or_unknown(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Trait for notification channels.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Get the channel name.
+    fn name(&self) -> &'static str;
+
+    /// Deliver an event.
+    async fn notify(&self, event: &Event) -> Result<()>;
+
+    /// Report that the daemon started. Most channels ignore this.
+    async fn on_start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Report the outcome of a daemon cycle. Most channels ignore this.
+    async fn on_cycle(&self, _summary: &CycleSummary) -> Result<()> {
+        Ok(())
+    }
+
+    /// Deliver a test message.
+    async fn test(&self) -> Result<()> {
+        self.notify(&Event::test()).await
+    }
+}
+
+/// Create a notifier from channel configuration.
+///
+/// `history` is the update history, used by channels that include recent
+/// updates in their messages. HTTP channels send requests with
+/// `client`.
+pub fn create_notifier(
+    channel: &NotificationChannel,
+    history: Option<Arc<dyn HistoryStore>>,
+    client: &crate::http::Client,
+) -> Box<dyn Notifier> {
+    let client = client.clone();
+    match channel {
+        NotificationChannel::Webhook { url, headers } => Box::new(
+            WebhookNotifier::new(
+                url.clone(),
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+            )
+            .with_client(client),
+        ),
+        NotificationChannel::Telegram { bot_token, chat_id } => {
+            Box::new(TelegramNotifier::new(bot_token.clone(), chat_id.clone()).with_client(client))
+        }
+        NotificationChannel::Discord { url } => {
+            Box::new(DiscordNotifier::new(url.clone()).with_client(client))
+        }
+        NotificationChannel::Ntfy {
+            server,
+            topic,
+            token,
+            priorities,
+        } => Box::new(
+            NtfyNotifier::new(
+                server.clone(),
+                topic.clone(),
+                token.clone(),
+                priorities.clone(),
+            )
+            .with_client(client),
+        ),
+        NotificationChannel::Gotify {
+            server,
+            app_token,
+            priority,
+        } => Box::new(
+            GotifyNotifier::new(server.clone(), app_token.clone(), *priority).with_client(client),
+        ),
+        NotificationChannel::Exec {
+            command,
+            args,
+            timeout_secs,
+        } => Box::new(ExecNotifier::new(
+            command.clone(),
+            args.clone(),
+            std::time::Duration::from_secs(*timeout_secs),
+        )),
+        NotificationChannel::Healthcheck { url } => {
+            Box::new(HealthcheckNotifier::new(url.clone()).with_client(client))
+        }
+        #[cfg(feature = "email")]
+        NotificationChannel::Email {
+            smtp_host,
+            smtp_port,
+            username,
+            password,
+            from,
+            to,
+            use_starttls,
+        } => Box::new(EmailNotifier::new(
+            SmtpSettings {
+                host: smtp_host.clone(),
+                port: *smtp_port,
+                username: username.clone(),
+                password: password.clone(),
+                use_starttls: *use_starttls,
+            },
+            from.clone(),
+            to.clone(),
+            history,
+        )),
+        #[cfg(not(feature = "email"))]
+        NotificationChannel::Email { .. } => {
+            let _ = history;
+            Box::new(Unavailable {
+                name: "email",
+                feature: "email",
+            })
+        }
+        #[cfg(feature = "mqtt")]
+        NotificationChannel::Mqtt {
+            host,
+            port,
+            username,
+            password,
+            tls,
+            topic_prefix,
+            discovery,
+            discovery_prefix,
+        } => Box::new(MqttNotifier::new(MqttSettings {
+            host: host.clone(),
+            port: *port,
+            username: username.clone(),
+            password: password.clone(),
+            tls: *tls,
+            topic_prefix: topic_prefix.clone(),
+            discovery_prefix: discovery.then(|| discovery_prefix.clone()),
+        })),
+        #[cfg(not(feature = "mqtt"))]
+        NotificationChannel::Mqtt { .. } => Box::new(Unavailable {
+            name: "mqtt",
+            feature: "mqtt",
+        }),
+    }
+}
+
+/// Placeholder for a channel whose cargo feature isn't enabled.
+#[cfg(not(all(feature = "email", feature = "mqtt")))]
+struct Unavailable {
+    name: &'static str,
+    feature: &'static str,
+}
+
+#[cfg(not(all(feature = "email", feature = "mqtt")))]
+#[async_trait]
+impl Notifier for Unavailable {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn notify(&self, _event: &Event) -> Result<()> {
+        Err(DdnsError::Config(format!(
+            "The {} notification channel requires rusty-dns built with the `{}` feature",
+            self.name, self.feature
+        )))
+    }
+}
+
+/// A notification channel with its event filter.
+struct Channel {
+    notifier: Box<dyn Notifier>,
+    events: Vec<EventKind>,
+}
+
+impl Channel {
+    /// Check whether an event passes the channel's filter (empty = all).
+    fn wants(&self, event: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// All configured notification channels.
+#[derive(Default)]
+pub struct Notifications {
+    channels: Vec<Channel>,
+}
+
+impl Notifications {
+    /// Create from already constructed channels receiving all events.
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Self {
+            channels: notifiers
+                .into_iter()
+                .map(|notifier| Channel {
+                    notifier,
+                    events: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Create the channels listed in the config, sending requests with the
+    /// `[network]` proxy and TLS options.
+    pub fn from_config(config: &Config) -> Self {
+        let history = config
+            .history
+            .enabled
+            .then(|| history::open(config).ok())
+            .flatten();
+        let client = config
+            .network
+            .client_options(None)
+            .and_then(|options| options.client())
+            .unwrap_or_else(|e| {
+                tracing::warn!("Sending notifications without the network settings: {}", e);
+                crate::http::default_client()
+            });
+
+        Self {
+            channels: config
+                .notifications
+                .iter()
+                .map(|notification| Channel {
+                    notifier: create_notifier(&notification.channel, history.clone(), &client),
+                    events: notification.events.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Check whether no channels are configured.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Deliver events to every channel.
+    ///
+    /// Delivery failures are logged and never returned, so notifications
+    /// can't fail an update.
+    pub async fn send(&self, events: &[Event]) {
+        for event in events {
+            for channel in &self.channels {
+                if !channel.wants(event.event) {
+                    continue;
+                }
+
+                let notifier = &channel.notifier;
+                if let Err(e) = notifier.notify(event).await {
+                    tracing::warn!(
+                        "Failed to send {} notification via {}: {}",
+                        event.event,
+                        notifier.name(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Send a test message through every channel, ignoring event filters,
+    /// and return each channel's name and delivery result.
+    pub async fn test(&self) -> Vec<(&'static str, Result<()>)> {
+        let mut results = Vec::new();
+        for channel in &self.channels {
+            results.push((channel.notifier.name(), channel.notifier.test().await));
+        }
+        results
+    }
+
+    /// Report daemon startup to every channel.
+    pub async fn started(&self) {
+        for channel in &self.channels {
+            if let Err(e) = channel.notifier.on_start().await {
+                tracing::warn!(
+                    "Failed to report startup via {}: {}",
+                    channel.notifier.name(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Report a cycle summary to every channel.
+    ///
+    /// Event filters don't apply: cycle reports are sent every cycle.
+    pub async fn cycle(&self, summary: &CycleSummary) {
+        for channel in &self.channels {
+            if let Err(e) = channel.notifier.on_cycle(summary).await {
+                tracing::warn!(
+                    "Failed to report cycle via {}: {}",
+                    channel.notifier.name(),
+                    e
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! MQTT publisher with optional Home Assistant discovery.
+
+use super::{CycleSummary, Event, Notifier};
+use crate::error::{DdnsError, Result};
+use crate::secret::Secret;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Pending publishes buffered while the broker is unreachable.
+const QUEUE_CAPACITY: usize = 64;
+
+/// First delay before reconnecting; doubles on each further failure.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// MQTT connection and topic settings.
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    /// Broker host.
+    pub host: String,
+    /// Broker port.
+    pub port: u16,
+    /// Login username.
+    pub username: Option<String>,
+    /// Login password.
+    pub password: Option<Secret>,
+    /// Connect over TLS.
+    pub tls: bool,
+    /// Prefix for all published topics.
+    pub topic_prefix: String,
+    /// Home Assistant discovery prefix, if discovery is enabled.
+    pub discovery_prefix: Option<String>,
+}
+
+/// A message to publish (retained unless noted otherwise).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+impl Message {
+    fn retained(topic: String, payload: String) -> Self {
+        Self {
+            topic,
+            payload,
+            retain: true,
+        }
+    }
+}
+
+/// Topic layout and message formatting, shared with the connection task.
+#[derive(Debug)]
+pub(super) struct Topics {
+    settings: MqttSettings,
+    /// Providers seen so far (object ID to domain), announced to Home
+    /// Assistant on reconnect.
+    providers: Mutex<BTreeMap<String, String>>,
+}
+
+impl Topics {
+    pub(super) fn new(settings: MqttSettings) -> Self {
+        Self {
+            settings,
+            providers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.settings.topic_prefix, suffix)
+    }
+
+    fn availability_topic(&self) -> String {
+        self.topic("status")
+    }
+
+    /// Messages describing a cycle's outcome.
+    ///
+    /// Newly seen providers are also announced to Home Assistant.
+    pub(super) fn cycle_messages(&self, summary: &CycleSummary) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        if let Some(ip) = summary.ipv4.or(summary.ipv6) {
+            messages.push(Message::retained(self.topic("ip"), ip.to_string()));
+        }
+        if let Some(ip) = summary.ipv6 {
+            messages.push(Message::retained(self.topic("ipv6"), ip.to_string()));
+        }
+
+        let last_update = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "status": if summary.success() { "ok" } else { "failed" },
+            "error": summary.error,
+        });
+        messages.push(Message::retained(
+            self.topic("last_update"),
+            last_update.to_string(),
+        ));
+
+        for outcome in &summary.providers {
+            let id = object_id(&format!("{}-{}", outcome.provider, outcome.domain));
+            let status = if outcome.success { "ok" } else { "failed" };
+            messages.push(Message::retained(
+                self.topic(&format!("provider/{}/status", id)),
+                status.to_string(),
+            ));
+
+            let new = self
+                .providers
+                .lock()
+                .unwrap()
+                .insert(id.clone(), outcome.domain.clone())
+                .is_none();
+            if new {
+                messages.extend(self.provider_discovery(&id, &outcome.domain));
+            }
+        }
+
+        messages
+    }
+
+    /// Messages sent after every (re)connect: availability and discovery.
+    pub(super) fn connect_messages(&self) -> Vec<Message> {
+        let mut messages = vec![Message::retained(
+            self.availability_topic(),
+            "online".to_string(),
+        )];
+
+        messages.extend(self.discovery(
+            "sensor",
+            "ip",
+            serde_json::json!({
+                "name": "Public IP",
+                "state_topic": self.topic("ip"),
+                "icon": "mdi:ip-network",
+            }),
+        ));
+        messages.extend(self.discovery(
+            "sensor",
+            "ipv6",
+            serde_json::json!({
+                "name": "Public IPv6",
+                "state_topic": self.topic("ipv6"),
+                "icon": "mdi:ip-network",
+            }),
+        ));
+        messages.extend(self.discovery(
+            "sensor",
+            "last_update",
+            serde_json::json!({
+                "name": "Last update",
+                "state_topic": self.topic("last_update"),
+                "value_template": "{{ value_json.timestamp }}",
+                "json_attributes_topic": self.topic("last_update"),
+                "device_class": "timestamp",
+            }),
+        ));
+
+        let providers = self.providers.lock().unwrap().clone();
+        for (id, domain) in providers {
+            messages.extend(self.provider_discovery(&id, &domain));
+        }
+
+        messages
+    }
+
+    fn provider_discovery(&self, id: &str, name: &str) -> Option<Message> {
+        self.discovery(
+            "binary_sensor",
+            &format!("provider_{}", id),
+            serde_json::json!({
+                "name": format!("{} update", name),
+                "state_topic": self.topic(&format!("provider/{}/status", id)),
+                "payload_on": "failed",
+                "payload_off": "ok",
+                "device_class": "problem",
+            }),
+        )
+    }
+
+    /// Build a Home Assistant discovery message, if discovery is enabled.
+    fn discovery(
+        &self,
+        component: &str,
+        object: &str,
+        mut config: serde_json::Value,
+    ) -> Option<Message> {
+        let prefix = self.settings.discovery_prefix.as_deref()?;
+        let node = object_id(&self.settings.topic_prefix);
+        let unique_id = format!("{}_{}", node, object_id(object));
+
+        config["unique_id"] = unique_id.clone().into();
+        config["availability_topic"] = self.availability_topic().into();
+        config["device"] = serde_json::json!({
+            "identifiers": [node],
+            "name": "rusty-dns",
+            "manufacturer": "rusty-dns",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+
+        Some(Message::retained(
+            format!("{}/{}/{}/config", prefix, component, unique_id),
+            config.to_string(),
+        ))
+    }
+}
+
+/// Publishes IP and update status to an MQTT broker.
+///
+/// The connection runs in a background task that reconnects with backoff.
+/// Publishing only queues messages, so a broker outage never blocks an
+/// update cycle; messages that don't fit the queue are dropped.
+pub struct MqttNotifier {
+    client: AsyncClient,
+    topics: Arc<Topics>,
+    task: JoinHandle<()>,
+}
+
+impl MqttNotifier {
+    /// Create a new MQTT notifier and start its connection task.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(settings: MqttSettings) -> Self {
+        let mut options = MqttOptions::new(
+            format!("rusty-dns-{}", std::process::id()),
+            settings.host.clone(),
+            settings.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &settings.username {
+            options.set_credentials(
+                username.clone(),
+                settings
+                    .password
+                    .as_ref()
+                    .map(|p| p.expose().to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if settings.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+
+        let topics = Arc::new(Topics::new(settings));
+        options.set_last_will(LastWill::new(
+            topics.availability_topic(),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+
+        let (client, eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let task = tokio::spawn(run_connection(
+            eventloop,
+            client.clone(),
+            Arc::clone(&topics),
+        ));
+
+        Self {
+            client,
+            topics,
+            task,
+        }
+    }
+
+    fn publish(&self, messages: Vec<Message>) -> Result<()> {
+        publish(&self.client, messages)
+    }
+}
+
+impl Drop for MqttNotifier {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl Notifier for MqttNotifier {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        self.publish(vec![Message {
+            topic: self.topics.topic("event"),
+            payload: serde_json::to_string(event)?,
+            retain: false,
+        }])
+    }
+
+    async fn on_cycle(&self, summary: &CycleSummary) -> Result<()> {
+        self.publish(self.topics.cycle_messages(summary))
+    }
+}
+
+/// Queue messages without waiting for the broker.
+fn publish(client: &AsyncClient, messages: Vec<Message>) -> Result<()> {
+    for message in messages {
+        client
+            .try_publish(
+                message.topic,
+                QoS::AtLeastOnce,
+                message.retain,
+                message.payload,
+            )
+            .map_err(|e| DdnsError::Network(format!("MQTT publish queue full: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+/// Drive the MQTT connection, reconnecting with backoff after errors.
+async fn run_connection(mut eventloop: EventLoop, client: AsyncClient, topics: Arc<Topics>) {
+    let mut delay = INITIAL_RECONNECT_DELAY;
+
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("Connected to MQTT broker");
+                delay = INITIAL_RECONNECT_DELAY;
+                if let Err(e) = publish(&client, topics.connect_messages()) {
+                    tracing::warn!("Failed to publish MQTT discovery: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(
+                    "MQTT connection error: {} (retrying in {}s)",
+                    e,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Convert a name to a Home Assistant object ID (`[a-z0-9_]`).
+fn object_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::ProviderOutcome;
+
+    fn topics(discovery: bool) -> Topics {
+        Topics::new(MqttSettings {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            tls: false,
+            topic_prefix: "rusty-dns".to_string(),
+            discovery_prefix: discovery.then(|| "homeassistant".to_string()),
+        })
+    }
+
+    fn summary() -> CycleSummary {
+        CycleSummary {
+            ipv4: Some("5.6.7.8".parse().unwrap()),
+            ipv6: None,
+            providers: vec![ProviderOutcome {
+                provider: "cloudflare".to_string(),
+                domain: "vpn.example.com".to_string(),
+                success: false,
+                error: Some("401 Unauthorized".to_string()),
+                reason: Default::default(),
+                group: None,
+            }],
+            groups: Vec::new(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_cycle_messages() {
+        let messages = topics(false).cycle_messages(&summary());
+        let topic_names: Vec<_> = messages.iter().map(|m| m.topic.as_str()).collect();
+
+        assert_eq!(
+            topic_names,
+            [
+                "rusty-dns/ip",
+                "rusty-dns/last_update",
+                "rusty-dns/provider/cloudflare_vpn_example_com/status"
+            ]
+        );
+        assert!(messages.iter().all(|m| m.retain));
+        assert_eq!(messages[0].payload, "5.6.7.8");
+        assert!(messages[1].payload.contains(r#""status":"failed""#));
+        assert_eq!(messages[2].payload, "failed");
+    }
+
+    #[test]
+    fn test_discovery_messages() {
+        let topics = topics(true);
+
+        let connect = topics.connect_messages();
+        assert_eq!(connect[0].topic, "rusty-dns/status");
+        assert_eq!(connect[0].payload, "online");
+        assert!(connect
+            .iter()
+            .any(|m| m.topic == "homeassistant/sensor/rusty_dns_ip/config"));
+
+        // A provider is announced once, then again on every reconnect
+        let provider_topic =
+            "homeassistant/binary_sensor/rusty_dns_provider_cloudflare_vpn_example_com/config";
+        let first = topics.cycle_messages(&summary());
+        assert!(first.iter().any(|m| m.topic == provider_topic));
+        let second = topics.cycle_messages(&summary());
+        assert!(!second.iter().any(|m| m.topic == provider_topic));
+        assert!(topics
+            .connect_messages()
+            .iter()
+            .any(|m| m.topic == provider_topic));
+    }
+}
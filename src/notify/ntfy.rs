@@ -0,0 +1,73 @@
+//! ntfy push notifier.
+
+use super::{send_request, Event, EventKind, Notifier};
+use crate::error::Result;
+use crate::secret::Secret;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+/// Publishes events to an ntfy topic.
+pub struct NtfyNotifier {
+    client: crate::http::Client,
+    server: String,
+    topic: String,
+    token: Option<Secret>,
+    priorities: BTreeMap<EventKind, u8>,
+}
+
+impl NtfyNotifier {
+    /// Create a new ntfy notifier.
+    pub fn new(
+        server: String,
+        topic: String,
+        token: Option<Secret>,
+        priorities: BTreeMap<EventKind, u8>,
+    ) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            server,
+            topic,
+            token,
+            priorities,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Get the priority for an event (1 = min, 3 = default, 5 = urgent).
+    fn priority(&self, event: EventKind) -> u8 {
+        self.priorities.get(&event).copied().unwrap_or(match event {
+            EventKind::UpdateFailed => 4,
+            EventKind::IpChanged | EventKind::UpdateRecovered | EventKind::Test => 3,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let url = format!("{}/{}", self.server.trim_end_matches('/'), self.topic);
+
+        let mut request = self
+            .client
+            .post(&url)
+            .header("X-Title", event.title())
+            .header("X-Priority", self.priority(event.event).to_string())
+            .header("X-Tags", event.event.as_str())
+            .body(event.message());
+
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose());
+        }
+
+        send_request(request, "ntfy").await
+    }
+}
@@ -0,0 +1,182 @@
+//! Telegram bot notifier.
+
+use super::{ip_or_unknown, Event, EventKind, Notifier};
+use crate::error::{DdnsError, Result};
+use crate::history::UpdateReason;
+use crate::secret::Secret;
+use async_trait::async_trait;
+
+const DEFAULT_BASE_URL: &str = "https://api.telegram.org";
+
+/// Maximum message length accepted by Telegram.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Space kept free for the "and N more" suffix when truncating.
+const SUFFIX_RESERVE: usize = 32;
+
+/// Sends events as MarkdownV2 messages through a Telegram bot.
+pub struct TelegramNotifier {
+    client: crate::http::Client,
+    bot_token: Secret,
+    chat_id: String,
+    base_url: String,
+}
+
+impl TelegramNotifier {
+    /// Create a new Telegram notifier.
+    pub fn new(bot_token: impl Into<Secret>, chat_id: String) -> Self {
+        Self::with_base_url(bot_token, chat_id, DEFAULT_BASE_URL.to_string())
+    }
+
+    /// Create with custom base URL (for testing).
+    pub fn with_base_url(bot_token: impl Into<Secret>, chat_id: String, base_url: String) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            bot_token: bot_token.into(),
+            chat_id,
+            base_url,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let url = format!(
+            "{}/bot{}/sendMessage",
+            self.base_url,
+            self.bot_token.expose()
+        );
+
+        let response = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({
+                "chat_id": self.chat_id,
+                "text": format_message(event),
+                "parse_mode": "MarkdownV2"
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: serde_json::Value = response.json().await.unwrap_or_default();
+            let description = body["description"].as_str().unwrap_or("unknown error");
+            return Err(DdnsError::Network(format!(
+                "Telegram returned HTTP {}: {}",
+                status, description
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Characters that must be escaped in MarkdownV2 text.
+const MARKDOWN_SPECIAL: &str = "_*[]()~`>#+-=|{}.!\\";
+
+/// Escape text for Telegram MarkdownV2.
+pub(super) fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Format an event as a MarkdownV2 message within Telegram's length limit.
+pub(super) fn format_message(event: &Event) -> String {
+    let provider = escape_markdown(event.provider.as_deref().unwrap_or_default());
+    let domain = escape_markdown(event.domain.as_deref().unwrap_or_default());
+
+    match event.event {
+        EventKind::IpChanged => {
+            let mut message = format!(
+                "🏠 *IP changed* {} → {}",
+                escape_markdown(&ip_or_unknown(event.old_ip)),
+                escape_markdown(&ip_or_unknown(event.new_ip))
+            );
+            if event.providers.is_empty() {
+                return message;
+            }
+
+            message.push_str(" —");
+            let mut included = 0;
+            for outcome in &event.providers {
+                let part = if outcome.success && outcome.reason == UpdateReason::Changed {
+                    format!(" {} ✅", escape_markdown(&outcome.provider))
+                } else if outcome.success {
+                    format!(
+                        " {} ✅ \\({}\\)",
+                        escape_markdown(&outcome.provider),
+                        escape_markdown(outcome.reason.as_str())
+                    )
+                } else {
+                    format!(
+                        " {} ❌ \\({}\\)",
+                        escape_markdown(&outcome.provider),
+                        escape_markdown(outcome.error.as_deref().unwrap_or("failed"))
+                    )
+                };
+
+                // Keep room for the "and N more" suffix
+                if message.chars().count() + part.chars().count() + SUFFIX_RESERVE > MAX_MESSAGE_LEN
+                {
+                    break;
+                }
+                message.push_str(&part);
+                included += 1;
+            }
+
+            let omitted = event.providers.len() - included;
+            if omitted > 0 {
+                message.push_str(&escape_markdown(&format!(" … and {} more", omitted)));
+            }
+            message
+        }
+        EventKind::UpdateFailed => {
+            let error = escape_markdown(event.error.as_deref().unwrap_or("unknown error"));
+            let message = format!("❌ *{}* \\({}\\) is failing: {}", provider, domain, error);
+            truncate(message)
+        }
+        EventKind::UpdateRecovered => format!(
+            "✅ *{}* \\({}\\) recovered, now {}",
+            provider,
+            domain,
+            escape_markdown(&ip_or_unknown(event.new_ip))
+        ),
+        EventKind::Test => format!(
+            "🔔 *Test notification*: {}",
+            escape_markdown(&event.message())
+        ),
+    }
+}
+
+/// Cut a message to Telegram's limit without leaving a dangling escape.
+fn truncate(message: String) -> String {
+    if message.chars().count() <= MAX_MESSAGE_LEN {
+        return message;
+    }
+
+    let mut truncated: String = message.chars().take(MAX_MESSAGE_LEN - 1).collect();
+    let trailing_escapes = truncated.chars().rev().take_while(|&c| c == '\\').count();
+    if trailing_escapes % 2 == 1 {
+        truncated.pop();
+    }
+    truncated.push('…');
+    truncated
+}
@@ -0,0 +1,688 @@
+//! Notifier tests with HTTP mocking.
+
+use crate::notify::{Event, EventKind};
+
+/// A failed update of a Cloudflare record, as sent to every channel.
+fn failed_event() -> Event {
+    Event::provider(
+        EventKind::UpdateFailed,
+        "cloudflare",
+        "vpn.example.com",
+        Some("1.2.3.4".parse().unwrap()),
+        "5.6.7.8".parse().unwrap(),
+        Some("401 Unauthorized".to_string()),
+    )
+}
+
+#[cfg(test)]
+mod webhook_tests {
+    use crate::notify::{Event, EventKind, Notifier, ProviderOutcome, WebhookNotifier};
+    use std::net::IpAddr;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn old_ip() -> IpAddr {
+        "1.2.3.4".parse().unwrap()
+    }
+
+    fn new_ip() -> IpAddr {
+        "5.6.7.8".parse().unwrap()
+    }
+
+    async fn expect(mock_server: &MockServer, body: serde_json::Value) {
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(header("content-type", "application/json"))
+            .and(body_partial_json(body))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(mock_server)
+            .await;
+    }
+
+    fn notifier(mock_server: &MockServer) -> WebhookNotifier {
+        WebhookNotifier::new(format!("{}/hook", mock_server.uri()), Vec::new())
+    }
+
+    #[tokio::test]
+    async fn test_webhook_ip_changed() {
+        let mock_server = MockServer::start().await;
+        expect(
+            &mock_server,
+            serde_json::json!({
+                "event": "ip_changed",
+                "old_ip": "1.2.3.4",
+                "new_ip": "5.6.7.8",
+                "provider": null,
+                "providers": [{"provider": "duckdns", "domain": "home.duckdns.org", "success": true}]
+            }),
+        )
+        .await;
+
+        let event = Event::ip_changed(
+            Some(old_ip()),
+            new_ip(),
+            vec![ProviderOutcome {
+                provider: "duckdns".to_string(),
+                domain: "home.duckdns.org".to_string(),
+                success: true,
+                error: None,
+                reason: Default::default(),
+                group: None,
+            }],
+        );
+        notifier(&mock_server).notify(&event).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_update_failed() {
+        let mock_server = MockServer::start().await;
+        expect(
+            &mock_server,
+            serde_json::json!({
+                "event": "update_failed",
+                "old_ip": "1.2.3.4",
+                "new_ip": "5.6.7.8",
+                "provider": "cloudflare",
+                "domain": "vpn.example.com",
+                "error": "A: 401 Unauthorized"
+            }),
+        )
+        .await;
+
+        let event = Event::provider(
+            EventKind::UpdateFailed,
+            "cloudflare",
+            "vpn.example.com",
+            Some(old_ip()),
+            new_ip(),
+            Some("A: 401 Unauthorized".to_string()),
+        );
+        notifier(&mock_server).notify(&event).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_update_recovered() {
+        let mock_server = MockServer::start().await;
+        expect(
+            &mock_server,
+            serde_json::json!({
+                "event": "update_recovered",
+                "new_ip": "5.6.7.8",
+                "provider": "cloudflare",
+                "domain": "vpn.example.com",
+                "error": null
+            }),
+        )
+        .await;
+
+        let event = Event::provider(
+            EventKind::UpdateRecovered,
+            "cloudflare",
+            "vpn.example.com",
+            None,
+            new_ip(),
+            None,
+        );
+        notifier(&mock_server).notify(&event).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_custom_headers_and_errors() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let notifier = WebhookNotifier::new(
+            mock_server.uri(),
+            vec![("Authorization".to_string(), "Bearer secret".to_string())],
+        );
+        let event = Event::ip_changed(None, new_ip(), Vec::new());
+
+        let err = notifier.notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("500"));
+    }
+}
+
+#[cfg(test)]
+mod telegram_tests {
+    use crate::config::Config;
+    use crate::detector::IpDetector;
+    use crate::history;
+    use crate::notify::telegram::{escape_markdown, format_message};
+    use crate::notify::{Event, Notifications, Notifier, ProviderOutcome, TelegramNotifier};
+    use crate::providers::{RecordType, UpdateResult};
+    use crate::updater::{DetectedIps, ProviderUpdate, UpdateOutcome, Updater};
+    use std::net::IpAddr;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn outcome(provider: &str, error: Option<&str>) -> ProviderOutcome {
+        ProviderOutcome {
+            provider: provider.to_string(),
+            domain: "example.com".to_string(),
+            success: error.is_none(),
+            error: error.map(str::to_string),
+            reason: Default::default(),
+            group: None,
+        }
+    }
+
+    fn ip_changed(providers: Vec<ProviderOutcome>) -> Event {
+        Event::ip_changed(
+            Some("1.2.3.4".parse().unwrap()),
+            "5.6.7.8".parse().unwrap(),
+            providers,
+        )
+    }
+
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(escape_markdown("1.2.3.4"), r"1\.2\.3\.4");
+        assert_eq!(escape_markdown("a_b (c)!"), r"a\_b \(c\)\!");
+    }
+
+    #[test]
+    fn test_ip_changed_message() {
+        let message = format_message(&ip_changed(vec![
+            outcome("cloudflare", None),
+            outcome("godaddy", Some("401 Unauthorized")),
+        ]));
+        assert_eq!(
+            message,
+            r"🏠 *IP changed* 1\.2\.3\.4 → 5\.6\.7\.8 — cloudflare ✅ godaddy ❌ \(401 Unauthorized\)"
+        );
+    }
+
+    #[test]
+    fn test_ip_changed_message_reason() {
+        let forced = ProviderOutcome {
+            reason: history::UpdateReason::Forced,
+            ..outcome("cloudflare", None)
+        };
+        let event = ip_changed(vec![forced, outcome("duckdns", None)]);
+        assert_eq!(
+            format_message(&event),
+            r"🏠 *IP changed* 1\.2\.3\.4 → 5\.6\.7\.8 — cloudflare ✅ \(forced\) duckdns ✅"
+        );
+        assert!(event
+            .message()
+            .ends_with("cloudflare (example.com): OK (forced)\nduckdns (example.com): OK"));
+    }
+
+    #[test]
+    fn test_long_provider_list_is_truncated() {
+        let providers = (0..500)
+            .map(|i| outcome(&format!("provider{}", i), Some("connection refused")))
+            .collect();
+        let message = format_message(&ip_changed(providers));
+
+        assert!(message.chars().count() <= 4096);
+        assert!(message.ends_with(r" more"));
+    }
+
+    #[tokio::test]
+    async fn test_telegram_send_message() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTOKEN/sendMessage"))
+            .and(body_partial_json(serde_json::json!({
+                "chat_id": "42",
+                "parse_mode": "MarkdownV2",
+                "text": r"🏠 *IP changed* 1\.2\.3\.4 → 5\.6\.7\.8"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"ok": true})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = TelegramNotifier::with_base_url(
+            "TOKEN".to_string(),
+            "42".to_string(),
+            mock_server.uri(),
+        );
+        notifier.notify(&ip_changed(Vec::new())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_telegram_failure_does_not_affect_update() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "ok": false,
+                "description": "Unauthorized"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let notifier =
+            TelegramNotifier::with_base_url("BAD".to_string(), "42".to_string(), mock_server.uri());
+        let err = notifier.notify(&ip_changed(Vec::new())).await.unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+
+        // Through the updater, the failed delivery is only logged
+        let dir = std::env::temp_dir().join(format!("rusty-dns-telegram-{}", std::process::id()));
+        let config = Config {
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let notifications = Notifications::new(vec![Box::new(notifier)]);
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector).with_notifications(&notifications);
+
+        let ip: IpAddr = "5.6.7.8".parse().unwrap();
+        let result = UpdateResult {
+            provider: "duckdns".to_string(),
+            label: None,
+            domain: "home.duckdns.org".to_string(),
+            record_type: RecordType::A,
+            success: true,
+            ip: Some(ip),
+            previous_ip: None,
+            error: None,
+            error_kind: None,
+            timestamp: chrono::Utc::now(),
+        };
+        let update = ProviderUpdate {
+            label: "duckdns-home.duckdns.org".to_string(),
+            provider: "duckdns",
+            domain: "home.duckdns.org".to_string(),
+            ip,
+            reason: Default::default(),
+            outcome: UpdateOutcome::Attempted(result),
+            verified: false,
+        };
+        let ips = |ip: &str| DetectedIps {
+            ipv4: Some(ip.parse().unwrap()),
+            ipv6: None,
+            ..Default::default()
+        };
+
+        updater.record(&ips("1.2.3.4"), &[]).await.unwrap();
+        let recorded = updater
+            .record(&ips("5.6.7.8"), std::slice::from_ref(&update))
+            .await
+            .unwrap();
+        assert_eq!(recorded.events.len(), 1);
+
+        let entries = history::load_from(&history::path_in(&dir)).unwrap();
+        assert!(entries[0].result.success);
+        assert_eq!(entries[0].result.error, None);
+        assert_eq!(recorded.state.consecutive_failures(&update.label), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod discord_tests {
+    use super::failed_event;
+    use crate::config::{Config, NotificationChannel, NotificationConfig};
+    use crate::notify::{DiscordNotifier, Event, Notifications, Notifier};
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_discord_embed() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(serde_json::json!({
+                "embeds": [{
+                    "title": "Update failed",
+                    "color": 0xe74c3c,
+                    "description": "❌ **cloudflare** (vpn.example.com): 401 Unauthorized",
+                    "fields": [
+                        {"name": "Old IP", "value": "1.2.3.4", "inline": true},
+                        {"name": "New IP", "value": "5.6.7.8", "inline": true}
+                    ]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = DiscordNotifier::new(mock_server.uri());
+        notifier.notify(&failed_event()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_discord_retries_once_when_rate_limited() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .set_body_json(serde_json::json!({"retry_after": 0.01, "global": false})),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = DiscordNotifier::new(mock_server.uri());
+        notifier.notify(&failed_event()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_discord_drops_after_second_rate_limit() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(429).set_body_json(serde_json::json!({"retry_after": 0.01})),
+            )
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = DiscordNotifier::new(mock_server.uri());
+        assert!(notifier.notify(&failed_event()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_events_filter() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                serde_json::json!({"event": "update_failed"}),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config: NotificationConfig = toml::from_str(&format!(
+            r#"
+            type = "webhook"
+            url = "{}"
+            events = ["update_failed"]
+            "#,
+            mock_server.uri()
+        ))
+        .unwrap();
+        assert!(matches!(
+            config.channel,
+            NotificationChannel::Webhook { .. }
+        ));
+
+        let notifications = Notifications::from_config(&Config {
+            notifications: vec![config],
+            ..Config::default()
+        });
+        let ip_changed = Event::ip_changed(None, "5.6.7.8".parse().unwrap(), Vec::new());
+        notifications.send(&[ip_changed, failed_event()]).await;
+    }
+}
+
+#[cfg(test)]
+mod push_tests {
+    use super::failed_event;
+    use crate::notify::{Event, EventKind, GotifyNotifier, Notifier, NtfyNotifier};
+    use std::collections::BTreeMap;
+    use wiremock::matchers::{body_json, body_string, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_ntfy_headers_and_body() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/home-ddns"))
+            .and(header("X-Title", "Update failed: cloudflare"))
+            .and(header("X-Priority", "4"))
+            .and(header("X-Tags", "update_failed"))
+            .and(header("Authorization", "Bearer tk_secret"))
+            .and(body_string(
+                "cloudflare (vpn.example.com) failed to update to 5.6.7.8: 401 Unauthorized",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = NtfyNotifier::new(
+            format!("{}/", mock_server.uri()),
+            "home-ddns".to_string(),
+            Some("tk_secret".into()),
+            BTreeMap::new(),
+        );
+        notifier.notify(&failed_event()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ntfy_priority_override() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(header("X-Priority", "1"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = NtfyNotifier::new(
+            mock_server.uri(),
+            "home-ddns".to_string(),
+            None,
+            BTreeMap::from([(EventKind::IpChanged, 1)]),
+        );
+        let event = Event::ip_changed(None, "5.6.7.8".parse().unwrap(), Vec::new());
+        notifier.notify(&event).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gotify_message() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/message"))
+            .and(header("X-Gotify-Key", "app-token"))
+            .and(body_json(serde_json::json!({
+                "title": "Update failed: cloudflare",
+                "message": "cloudflare (vpn.example.com) failed to update to 5.6.7.8: 401 Unauthorized",
+                "priority": 8
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = GotifyNotifier::new(mock_server.uri(), "app-token".to_string(), 8);
+        notifier.notify(&failed_event()).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod healthcheck_tests {
+    use crate::config::GroupPolicy;
+    use crate::notify::{
+        CycleSummary, GroupOutcome, HealthcheckNotifier, Notifier, ProviderOutcome,
+    };
+    use wiremock::matchers::{body_string, body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn summary(success: bool) -> CycleSummary {
+        CycleSummary {
+            ipv4: Some("5.6.7.8".parse().unwrap()),
+            ipv6: None,
+            providers: vec![ProviderOutcome {
+                provider: "cloudflare".to_string(),
+                domain: "vpn.example.com".to_string(),
+                success,
+                error: (!success).then(|| "401 Unauthorized".to_string()),
+                reason: Default::default(),
+                group: None,
+            }],
+            groups: Vec::new(),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_success_ping() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/ping/abc"))
+            .and(body_string(
+                "IPv4: 5.6.7.8\ncloudflare (vpn.example.com): OK",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = HealthcheckNotifier::new(format!("{}/ping/abc/", mock_server.uri()));
+        notifier.on_cycle(&summary(true)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_fail_and_start() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/ping/abc/fail"))
+            .and(body_string_contains("401 Unauthorized"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/ping/abc/start"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notifier = HealthcheckNotifier::new(format!("{}/ping/abc", mock_server.uri()));
+        notifier.on_start().await.unwrap();
+        notifier.on_cycle(&summary(false)).await.unwrap();
+
+        let failed = CycleSummary::failed("All IP detection services failed: 401 Unauthorized");
+        notifier.on_cycle(&failed).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_group_policy() {
+        let mock_server = MockServer::start().await;
+
+        // The failed fallback is reported, but the cycle succeeded
+        Mock::given(method("POST"))
+            .and(path("/ping/abc"))
+            .and(body_string(
+                "IPv4: 5.6.7.8\n\
+                 cloudflare (vpn.example.com): OK\n\
+                 duckdns (home.duckdns.org): FAILED - 401 Unauthorized\n\
+                 Group home (primary-failover): OK",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut summary = summary(true);
+        summary.providers.push(ProviderOutcome {
+            provider: "duckdns".to_string(),
+            domain: "home.duckdns.org".to_string(),
+            ..summary.providers[0].clone()
+        });
+        summary.providers[1].success = false;
+        summary.providers[1].error = Some("401 Unauthorized".to_string());
+        for outcome in &mut summary.providers {
+            outcome.group = Some("home".to_string());
+        }
+        summary.groups.push(GroupOutcome {
+            name: "home".to_string(),
+            policy: GroupPolicy::PrimaryFailover,
+            success: true,
+        });
+        assert!(summary.success());
+
+        let notifier = HealthcheckNotifier::new(format!("{}/ping/abc", mock_server.uri()));
+        notifier.on_cycle(&summary).await.unwrap();
+
+        summary.groups[0].success = false;
+        assert!(!summary.success());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod exec_tests {
+    use super::failed_event;
+    use crate::notify::{ExecNotifier, Notifier};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    fn script(dir: &Path, body: &str) -> ExecNotifier {
+        std::fs::create_dir_all(dir).unwrap();
+        ExecNotifier::new(
+            "/bin/sh".to_string(),
+            vec!["-c".to_string(), body.to_string()],
+            Duration::from_secs(5),
+        )
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rusty-dns-exec-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_exec_payload_and_env() {
+        let dir = test_dir("payload");
+        let notifier = script(
+            &dir,
+            &format!(
+                r#"cat > "{0}/stdin.json"; echo "$DDNS_EVENT $DDNS_OLD_IP $DDNS_NEW_IP $DDNS_PROVIDER" > "{0}/env""#,
+                dir.display()
+            ),
+        );
+
+        notifier.notify(&failed_event()).await.unwrap();
+
+        let payload: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("stdin.json")).unwrap())
+                .unwrap();
+        assert_eq!(payload["event"], "update_failed");
+        assert_eq!(payload["domain"], "vpn.example.com");
+        assert_eq!(payload["error"], "401 Unauthorized");
+
+        let env = std::fs::read_to_string(dir.join("env")).unwrap();
+        assert_eq!(env.trim(), "update_failed 1.2.3.4 5.6.7.8 cloudflare");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_exec_failure_and_timeout() {
+        let dir = test_dir("failure");
+
+        let notifier = script(&dir, "echo 'peer not found' >&2; exit 3");
+        let err = notifier.notify(&failed_event()).await.unwrap_err();
+        assert!(err.to_string().contains("peer not found"));
+
+        let notifier = ExecNotifier::new(
+            "/bin/sh".to_string(),
+            vec!["-c".to_string(), "sleep 10".to_string()],
+            Duration::from_millis(100),
+        );
+        let err = notifier.notify(&failed_event()).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
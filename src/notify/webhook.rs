@@ -0,0 +1,45 @@
+//! Generic JSON webhook notifier.
+
+use super::{send_request, Event, Notifier};
+use crate::error::Result;
+use async_trait::async_trait;
+
+/// Posts each event as JSON to a URL.
+pub struct WebhookNotifier {
+    client: crate::http::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl WebhookNotifier {
+    /// Create a new webhook notifier.
+    pub fn new(url: String, headers: Vec<(String, String)>) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            url,
+            headers,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let mut request = self.client.post(&self.url).json(event);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        send_request(request, "Webhook").await
+    }
+}
@@ -1,21 +1,45 @@
 //! Cloudflare DDNS provider.
 
-use super::{DdnsProvider, UpdateResult};
+use super::{
+    cname_message, retry_after, DdnsProvider, Failure, ProviderCapabilities, RecordInfo,
+    RecordType, UpdateErrorKind, UpdateResult, WriteProbe,
+};
 use crate::error::{DdnsError, Result};
+use crate::names::RecordName;
+use crate::secret::Secret;
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
-const DEFAULT_BASE_URL: &str = "https://api.cloudflare.com";
+pub(super) const DEFAULT_BASE_URL: &str = "https://api.cloudflare.com";
+
+/// TTL Cloudflare reports for records on automatic TTL.
+const AUTO_TTL: u32 = 1;
+
+/// Seconds an automatic TTL stands for.
+const AUTO_TTL_SECS: u32 = 300;
 
 /// Cloudflare DDNS provider.
 pub struct CloudflareProvider {
-    client: reqwest::Client,
-    api_token: String,
+    client: crate::http::Client,
+    api_token: Secret,
     zone_id: String,
-    record_name: String,
-    proxied: bool,
+    /// `record_name`, then the additional records.
+    record_names: Vec<RecordName>,
+    /// Whether records are proxied; `None` keeps each record's setting.
+    proxied: Option<bool>,
+    ttl: Option<u32>,
+    create_if_missing: bool,
     base_url: String,
+    /// A and AAAA records found or written so far, by name, so updates
+    /// needn't look them up again.
+    records: Mutex<HashMap<(RecordName, RecordType), DnsRecord>>,
+    /// Whether the batch endpoint turned out not to exist.
+    batch_unsupported: AtomicBool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,27 +51,139 @@ struct CloudflareResponse<T> {
 
 #[derive(Debug, Deserialize)]
 struct CloudflareError {
+    #[serde(default)]
+    code: u32,
     message: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl CloudflareError {
+    /// Classify the error by its code, if it is one of the codes telling
+    /// more than the response status.
+    fn kind(&self) -> Option<UpdateErrorKind> {
+        match self.code {
+            // Invalid or missing token, or missing permissions
+            6003 | 6111 | 9103 | 9106 | 9109 | 10000 => Some(UpdateErrorKind::Auth),
+            971 => Some(UpdateErrorKind::RateLimited { retry_after: None }),
+            // Unknown zone, or record deleted meanwhile
+            7003 | 81044 => Some(UpdateErrorKind::RecordNotFound),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 struct DnsRecord {
     id: String,
+    #[serde(default)]
     content: String,
+    #[serde(default, rename = "type")]
+    record_type: String,
+    #[serde(default)]
+    ttl: Option<u32>,
+    #[serde(default)]
+    proxied: Option<bool>,
+    #[serde(default)]
+    comment: Option<String>,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+impl DnsRecord {
+    /// Get the address the record points at, if of `record_type`.
+    fn address(&self, record_type: RecordType) -> Option<IpAddr> {
+        self.content
+            .parse()
+            .ok()
+            .filter(|ip| RecordType::for_ip(ip) == record_type)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    name: String,
+}
+
+/// Value of a zone setting, e.g. `cname_flattening`.
+#[derive(Debug, Deserialize)]
+struct ZoneSetting {
+    value: String,
 }
 
+/// `cname_flattening` value flattening CNAMEs anywhere in the zone, not
+/// only at the apex.
+const FLATTEN_ALL: &str = "flatten_all";
+
 #[derive(Debug, Serialize)]
 struct UpdateRequest {
     #[serde(rename = "type")]
     record_type: String,
     name: String,
     content: String,
-    proxied: bool,
+    /// The record's own setting unless configured, omitted for new records
+    /// (not proxied).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+    /// Omitted to keep the record's existing TTL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    /// The record's own comment and tags, sent back so they're kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+}
+
+/// Change of a record's content alone, leaving its other settings.
+#[derive(Debug, Serialize)]
+struct ContentRequest<'a> {
+    content: &'a str,
+}
+
+/// Changes applied at once by the batch endpoint.
+#[derive(Debug, Default, Serialize)]
+struct BatchRequest {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    patches: Vec<BatchPatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    posts: Vec<UpdateRequest>,
+}
+
+/// Change of an existing record in a [`BatchRequest`].
+#[derive(Debug, Serialize)]
+struct BatchPatch {
+    id: String,
+    #[serde(flatten)]
+    record: UpdateRequest,
+}
+
+/// Records written by a [`BatchRequest`], in the order of the request.
+#[derive(Debug, Default, Deserialize)]
+struct BatchResult {
+    #[serde(default)]
+    patches: Vec<DnsRecord>,
+    #[serde(default)]
+    posts: Vec<DnsRecord>,
 }
 
 impl CloudflareProvider {
-    /// Create a new Cloudflare provider.
-    pub fn new(api_token: String, zone_id: String, record_name: String, proxied: bool) -> Self {
+    /// What the provider type can do. Records are only created with
+    /// [`CloudflareProvider::with_create_if_missing`].
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: true,
+        supports_ipv6: true,
+        supports_ttl: true,
+        supports_txt: false,
+        supports_create: true,
+    };
+
+    /// Create a new Cloudflare provider, setting whether its records are
+    /// proxied if `proxied` is given (`None` keeps each record's setting).
+    pub fn new(
+        api_token: impl Into<Secret>,
+        zone_id: String,
+        record_name: RecordName,
+        proxied: Option<bool>,
+    ) -> Self {
         Self::with_base_url(
             api_token,
             zone_id,
@@ -57,60 +193,466 @@ impl CloudflareProvider {
         )
     }
 
-    /// Create with custom base URL (for testing).
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
     pub fn with_base_url(
-        api_token: String,
+        api_token: impl Into<Secret>,
         zone_id: String,
-        record_name: String,
-        proxied: bool,
+        record_name: RecordName,
+        proxied: Option<bool>,
         base_url: String,
     ) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            api_token,
+            client: crate::http::default_client(),
+            api_token: api_token.into(),
             zone_id,
-            record_name,
+            record_names: vec![record_name],
             proxied,
+            ttl: None,
+            create_if_missing: false,
             base_url,
+            records: Mutex::new(HashMap::new()),
+            batch_unsupported: AtomicBool::new(false),
         }
     }
 
-    /// Get the DNS record ID.
-    async fn get_record_id(&self) -> Result<(String, String)> {
-        let url = format!(
-            "{}/client/v4/zones/{}/dns_records?name={}",
-            self.base_url, self.zone_id, self.record_name
-        );
+    /// Also point these records of the zone at the address, in one batch
+    /// request with `record_name` (see [`CloudflareProvider::update_records`]).
+    pub fn with_additional_records(mut self, names: Vec<RecordName>) -> Self {
+        for name in names {
+            if !self.record_names.contains(&name) {
+                self.record_names.push(name);
+            }
+        }
+        self
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the TTL written with each update (1 for automatic); by default
+    /// the record's existing TTL is kept.
+    pub fn with_ttl(mut self, ttl: Option<u32>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Create the record of an address family when the name has none
+    /// (default: fail with [`UpdateErrorKind::RecordNotFound`]).
+    pub fn with_create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
 
-        let response: CloudflareResponse<Vec<DnsRecord>> = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
+    /// Send an API request, returning the result of a successful one.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: crate::http::RequestBuilder,
+    ) -> std::result::Result<Option<T>, Failure> {
+        let response = request
+            .header(
+                "Authorization",
+                format!("Bearer {}", self.api_token.expose()),
+            )
             .send()
-            .await?
-            .json()
             .await?;
+        Self::read(response).await
+    }
+
+    /// Read an API response, returning the result of a successful request.
+    async fn read<T: DeserializeOwned>(
+        response: reqwest::Response,
+    ) -> std::result::Result<Option<T>, Failure> {
+        let status = response.status();
+        let status_kind = UpdateErrorKind::for_status(status, retry_after(&response));
 
-        if !response.success {
-            let msg = response
-                .errors
-                .first()
+        let response: CloudflareResponse<T> = match response.json().await {
+            Ok(response) => response,
+            Err(e) if status.is_success() => return Err(e.into()),
+            Err(_) => {
+                return Err(Failure {
+                    kind: status_kind,
+                    message: format!("HTTP {}", status),
+                })
+            }
+        };
+        if response.success {
+            return Ok(response.result);
+        }
+
+        let error = response.errors.first();
+        Err(Failure {
+            kind: match error.and_then(CloudflareError::kind) {
+                // The status carries the delay to wait
+                Some(UpdateErrorKind::RateLimited { .. }) if status.as_u16() == 429 => status_kind,
+                Some(kind) => kind,
+                None if status.is_success() => UpdateErrorKind::ProviderRejected,
+                None => status_kind,
+            },
+            message: error
                 .map(|e| e.message.clone())
-                .unwrap_or_else(|| "Unknown error".to_string());
-            return Err(DdnsError::Provider {
-                provider: "cloudflare".to_string(),
-                message: msg,
-            });
+                .unwrap_or_else(|| "Unknown error".to_string()),
+        })
+    }
+
+    /// Get the main record name.
+    fn record_name(&self) -> &RecordName {
+        &self.record_names[0]
+    }
+
+    /// Get the record of `record_type` named `name`, if there is one,
+    /// remembering it.
+    ///
+    /// Fails if the name is a CNAME instead, as it can't have address
+    /// records then.
+    async fn find_record(
+        &self,
+        name: &RecordName,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<DnsRecord>, Failure> {
+        let url = format!(
+            "{}/client/v4/zones/{}/dns_records?name={}&type={}",
+            self.base_url, self.zone_id, name, record_type
+        );
+
+        let records: Option<Vec<DnsRecord>> = self.send(self.client.get(&url)).await?;
+        let record = records.and_then(|records| records.into_iter().next());
+        match &record {
+            Some(record) => self.cache(name, record_type, Some(record.clone())),
+            None => {
+                if let Some(target) = self.cname_target(name).await {
+                    return Err(Failure {
+                        kind: UpdateErrorKind::ProviderRejected,
+                        message: cname_message(name.as_str(), &target),
+                    });
+                }
+            }
+        }
+        Ok(record)
+    }
+
+    /// Get the address held by the record of `record_type` named `name`.
+    async fn record_ip(
+        &self,
+        name: &RecordName,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<IpAddr>, Failure> {
+        let record = self.find_record(name, record_type).await?;
+        Ok(record.and_then(|r| r.address(record_type)))
+    }
+
+    /// Get the target of `name` if it is a CNAME.
+    ///
+    /// The lookup only explains a missing record, so failing it isn't an
+    /// error.
+    async fn cname_target(&self, name: &RecordName) -> Option<String> {
+        let url = format!(
+            "{}/client/v4/zones/{}/dns_records?name={}&type=CNAME",
+            self.base_url, self.zone_id, name
+        );
+
+        let records: Vec<DnsRecord> = self.send(self.client.get(&url)).await.ok()??;
+        records
+            .into_iter()
+            .find(|r| r.record_type == "CNAME")
+            .map(|r| r.content)
+    }
+
+    /// Get the remembered record of `record_type` named `name`.
+    fn cached(&self, name: &RecordName, record_type: RecordType) -> Option<DnsRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(&(name.clone(), record_type))
+            .cloned()
+    }
+
+    /// Get the record of `record_type` named `name`, looking it up unless
+    /// already known.
+    async fn lookup(
+        &self,
+        name: &RecordName,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<DnsRecord>, Failure> {
+        match self.cached(name, record_type) {
+            Some(record) => Ok(Some(record)),
+            None => self.find_record(name, record_type).await,
+        }
+    }
+
+    /// Remember the record of `record_type` named `name`, or forget it.
+    fn cache(&self, name: &RecordName, record_type: RecordType, record: Option<DnsRecord>) {
+        let mut records = self.records.lock().unwrap();
+        let key = (name.clone(), record_type);
+        match record {
+            Some(record) => records.insert(key, record),
+            None => records.remove(&key),
+        };
+    }
+
+    /// Get the request pointing the record `name` at `ip`, keeping the
+    /// proxy setting (unless configured), comment and tags of `record`, the
+    /// existing record.
+    fn update_request(
+        &self,
+        name: &RecordName,
+        record: Option<&DnsRecord>,
+        ip: IpAddr,
+    ) -> UpdateRequest {
+        UpdateRequest {
+            record_type: RecordType::for_ip(&ip).to_string(),
+            name: name.to_string(),
+            content: ip.to_string(),
+            proxied: self.proxied.or(record.and_then(|r| r.proxied)),
+            ttl: self.ttl,
+            comment: record.and_then(|r| r.comment.clone()),
+            tags: record.and_then(|r| r.tags.clone()),
+        }
+    }
+
+    /// Failure of an update of the missing record `name`.
+    fn not_found(name: &RecordName, record_type: RecordType) -> Failure {
+        Failure {
+            kind: UpdateErrorKind::RecordNotFound,
+            message: format!("DNS record {} ({}) not found", name, record_type),
+        }
+    }
+
+    /// Point `record`, the record `name` of the family of `ip`, at it,
+    /// creating the record if missing and allowed to.
+    async fn patch_record(
+        &self,
+        name: &RecordName,
+        record: Option<&DnsRecord>,
+        ip: IpAddr,
+    ) -> std::result::Result<(), Failure> {
+        let record_type = RecordType::for_ip(&ip);
+        let request = self.update_request(name, record, ip);
+        let records = format!(
+            "{}/client/v4/zones/{}/dns_records",
+            self.base_url, self.zone_id
+        );
+
+        let request = match record {
+            Some(record) => self
+                .client
+                .patch(format!("{}/{}", records, record.id))
+                .json(&request),
+            None if self.create_if_missing => self.client.post(&records).json(&request),
+            None => return Err(Self::not_found(name, record_type)),
+        };
+
+        match self.send::<DnsRecord>(request).await {
+            Ok(record) => {
+                self.cache(name, record_type, record);
+                Ok(())
+            }
+            Err(failure) => {
+                // The record may have been deleted meanwhile
+                self.cache(name, record_type, None);
+                Err(failure)
+            }
+        }
+    }
+
+    /// Point every record of the family of `ip` at it in one batch request,
+    /// so the changes land together.
+    ///
+    /// Records that are missing (and not to be created) or can't be looked
+    /// up fail on their own; the others succeed or fail together. Returns
+    /// `None` if the API has no batch endpoint.
+    async fn batch_update(&self, ip: IpAddr) -> Option<Vec<UpdateResult>> {
+        let mut retried = false;
+        loop {
+            let (results, stale) = self.send_batch(ip).await?;
+            if !stale || retried {
+                return Some(results);
+            }
+            retried = true;
         }
+    }
 
-        response
-            .result
-            .and_then(|records| records.into_iter().next())
-            .map(|r| (r.id, r.content))
-            .ok_or_else(|| DdnsError::Provider {
-                provider: "cloudflare".to_string(),
-                message: format!("DNS record {} not found", self.record_name),
+    /// Send one batch request for [`CloudflareProvider::batch_update`].
+    ///
+    /// Also tells whether the batch failed with records found in the cache,
+    /// which may have been deleted meanwhile: they are forgotten, so
+    /// sending again looks them up.
+    async fn send_batch(&self, ip: IpAddr) -> Option<(Vec<UpdateResult>, bool)> {
+        let record_type = RecordType::for_ip(&ip);
+        let mut batch = BatchRequest::default();
+        let mut patched = Vec::new();
+        let mut posted = Vec::new();
+        let mut cached = false;
+        // Lookup outcome of each record, `None` if in the batch
+        let mut lookups = Vec::new();
+        for name in &self.record_names {
+            cached |= self.cached(name, record_type).is_some();
+            match self.lookup(name, record_type).await {
+                Ok(Some(record)) => {
+                    let previous_ip = record.address(record_type);
+                    batch.patches.push(BatchPatch {
+                        record: self.update_request(name, Some(&record), ip),
+                        id: record.id,
+                    });
+                    patched.push(name);
+                    lookups.push((name, previous_ip, None));
+                }
+                Ok(None) if self.create_if_missing => {
+                    batch.posts.push(self.update_request(name, None, ip));
+                    posted.push(name);
+                    lookups.push((name, None, None));
+                }
+                Ok(None) => lookups.push((name, None, Some(Self::not_found(name, record_type)))),
+                Err(failure) => lookups.push((name, None, Some(failure))),
+            }
+        }
+
+        let outcome = if patched.is_empty() && posted.is_empty() {
+            Ok(None)
+        } else {
+            let url = format!(
+                "{}/client/v4/zones/{}/dns_records/batch",
+                self.base_url, self.zone_id
+            );
+            let response = self
+                .client
+                .post(&url)
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", self.api_token.expose()),
+                )
+                .json(&batch)
+                .send()
+                .await;
+            match response {
+                // Older API; the lookups above found the zone
+                Ok(response) if matches!(response.status().as_u16(), 404 | 405) => {
+                    self.batch_unsupported.store(true, Ordering::Relaxed);
+                    return None;
+                }
+                Ok(response) => Self::read::<BatchResult>(response).await,
+                Err(e) => Err(e.into()),
+            }
+        };
+        let outcome = match outcome {
+            Ok(result) => {
+                let result = result.unwrap_or_default();
+                let written = patched
+                    .iter()
+                    .zip(result.patches)
+                    .chain(posted.iter().zip(result.posts));
+                for (name, record) in written {
+                    self.cache(name, record_type, Some(record));
+                }
+                Ok(())
+            }
+            Err(failure) => {
+                for name in patched.iter().chain(&posted) {
+                    self.cache(name, record_type, None);
+                }
+                Err(failure)
+            }
+        };
+        let stale = cached
+            && matches!(&outcome, Err(failure) if failure.kind == UpdateErrorKind::RecordNotFound);
+        let results = lookups
+            .into_iter()
+            .map(|(name, previous_ip, failure)| {
+                let outcome = match failure {
+                    Some(failure) => Err(failure),
+                    None => outcome.clone(),
+                };
+                self.result(name, ip, previous_ip, outcome)
             })
+            .collect();
+        Some((results, stale))
+    }
+
+    /// Point the record `name` of the family of `ip` at it on its own.
+    ///
+    /// A remembered record that turns out to be gone is looked up again
+    /// once.
+    async fn update_record(&self, name: &RecordName, ip: IpAddr) -> UpdateResult {
+        let record_type = RecordType::for_ip(&ip);
+        let mut retried = false;
+        loop {
+            let cached = self.cached(name, record_type).is_some();
+            let (previous_ip, outcome) = match self.lookup(name, record_type).await {
+                Ok(record) => (
+                    record.as_ref().and_then(|r| r.address(record_type)),
+                    self.patch_record(name, record.as_ref(), ip).await,
+                ),
+                Err(failure) => (None, Err(failure)),
+            };
+            let stale = cached
+                && matches!(&outcome, Err(failure) if failure.kind == UpdateErrorKind::RecordNotFound);
+            if !stale || retried {
+                return self.result(name, ip, previous_ip, outcome);
+            }
+            retried = true;
+        }
+    }
+
+    /// Get the result of an update of the record `name`.
+    fn result(
+        &self,
+        name: &RecordName,
+        ip: IpAddr,
+        previous_ip: Option<IpAddr>,
+        outcome: std::result::Result<(), Failure>,
+    ) -> UpdateResult {
+        let failure = outcome.err();
+        UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: name.to_string(),
+            record_type: RecordType::for_ip(&ip),
+            success: failure.is_none(),
+            ip: failure.is_none().then_some(ip),
+            previous_ip,
+            error_kind: failure.as_ref().map(|failure| failure.kind),
+            error: failure.map(|failure| failure.message),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    /// Point every record of the family of `ip` (`record_name` and the
+    /// additional records) at it, with one result per record.
+    ///
+    /// Several records are changed in one batch request, so they land
+    /// together; if the API has no batch endpoint, each is updated on its
+    /// own. Fails only if the API couldn't be reached for any record.
+    pub async fn update_records(&self, ip: IpAddr) -> Result<Vec<UpdateResult>> {
+        let batched =
+            if self.record_names.len() > 1 && !self.batch_unsupported.load(Ordering::Relaxed) {
+                self.batch_update(ip).await
+            } else {
+                None
+            };
+        let results = match batched {
+            Some(results) => results,
+            None => {
+                let mut results = Vec::new();
+                for name in &self.record_names {
+                    results.push(self.update_record(name, ip).await);
+                }
+                results
+            }
+        };
+
+        // Reported like any other unreachable provider
+        if results
+            .iter()
+            .all(|r| r.error_kind == Some(UpdateErrorKind::Network))
+        {
+            return Err(DdnsError::Network(
+                results[0].error.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(results)
     }
 }
 
@@ -121,75 +663,152 @@ impl DdnsProvider for CloudflareProvider {
     }
 
     fn domain(&self) -> String {
-        self.record_name.clone()
+        self.record_name().to_string()
     }
 
-    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
-        let (_, content) = self.get_record_id().await?;
-        Ok(content.parse().ok())
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            supports_create: self.create_if_missing,
+            ..Self::CAPABILITIES
+        }
     }
 
-    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
-        let previous_ip = self.get_current_ip().await.ok().flatten();
-
-        let (record_id, _) = self.get_record_id().await?;
-
-        let url = format!(
-            "{}/client/v4/zones/{}/dns_records/{}",
-            self.base_url, self.zone_id, record_id
-        );
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        Ok(self.get_current_record().await?.map(|record| record.ip))
+    }
 
-        let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    async fn get_current_record(&self) -> Result<Option<RecordInfo>> {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let record = self
+                .find_record(self.record_name(), record_type)
+                .await
+                .map_err(|f| f.into_error(self.name()))?;
+            if let Some(ip) = record.as_ref().and_then(|r| r.address(record_type)) {
+                let ttl = record.and_then(|r| r.ttl).map(|ttl| match ttl {
+                    AUTO_TTL => AUTO_TTL_SECS,
+                    ttl => ttl,
+                });
+                return Ok(Some(RecordInfo::new(ip, ttl)));
+            }
+        }
 
-        let request = UpdateRequest {
-            record_type: record_type.to_string(),
-            name: self.record_name.clone(),
-            content: ip.to_string(),
-            proxied: self.proxied,
-        };
+        if self.create_if_missing {
+            return Ok(None);
+        }
+        Err(DdnsError::Provider {
+            provider: self.name().to_string(),
+            message: format!("DNS record {} not found", self.record_name()),
+        })
+    }
 
-        let response: CloudflareResponse<DnsRecord> = self
-            .client
-            .patch(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .json(&request)
-            .send()
-            .await?
-            .json()
-            .await?;
+    async fn get_record_ip(&self, record_type: RecordType) -> Result<Option<IpAddr>> {
+        Ok(self
+            .record_ip(self.record_name(), record_type)
+            .await
+            .map_err(|f| f.into_error(self.name()))?)
+    }
 
-        if response.success {
-            Ok(UpdateResult {
-                provider: self.name().to_string(),
-                domain: self.record_name.clone(),
-                success: true,
-                ip: Some(ip),
-                previous_ip,
-                error: None,
-                timestamp: chrono::Utc::now(),
-            })
-        } else {
-            let msg = response
-                .errors
-                .first()
-                .map(|e| e.message.clone())
-                .unwrap_or_else(|| "Unknown error".to_string());
-
-            Ok(UpdateResult {
-                provider: self.name().to_string(),
-                domain: self.record_name.clone(),
-                success: false,
-                ip: None,
-                previous_ip,
-                error: Some(msg),
-                timestamp: chrono::Utc::now(),
-            })
+    /// Update every record with [`CloudflareProvider::update_records`],
+    /// reported as one result naming all of them, successful if every
+    /// record was updated.
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let mut results = self.update_records(ip).await?;
+        if results.len() == 1 {
+            return Ok(results.remove(0));
         }
+
+        let failures: Vec<_> = results.iter().filter(|r| !r.success).collect();
+        Ok(UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: results
+                .iter()
+                .map(|r| r.domain.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            record_type: RecordType::for_ip(&ip),
+            success: failures.is_empty(),
+            ip: failures.is_empty().then_some(ip),
+            previous_ip: results[0].previous_ip,
+            error: (!failures.is_empty()).then(|| {
+                failures
+                    .iter()
+                    .map(|r| format!("{}: {}", r.domain, r.error.as_deref().unwrap_or_default()))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            }),
+            error_kind: failures.first().and_then(|r| r.error_kind),
+            timestamp: chrono::Utc::now(),
+        })
     }
 
     async fn validate(&self) -> Result<()> {
         // Try to get the record to validate credentials
-        self.get_record_id().await?;
+        self.get_current_ip().await?;
         Ok(())
     }
+
+    /// Write the content of the A record of `record_name`, or else its
+    /// AAAA record, back to it.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        let name = self.record_name();
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let Some(record) = self
+                .find_record(name, record_type)
+                .await
+                .map_err(|f| f.into_error(self.name()))?
+            else {
+                continue;
+            };
+            let url = format!(
+                "{}/client/v4/zones/{}/dns_records/{}",
+                self.base_url, self.zone_id, record.id
+            );
+            let request = self.client.patch(&url).json(&ContentRequest {
+                content: &record.content,
+            });
+            self.send::<DnsRecord>(request)
+                .await
+                .map_err(|f| f.into_error(self.name()))?;
+            return Ok(WriteProbe::Written(format!(
+                "wrote {} ({}) back unchanged",
+                record.content, record_type
+            )));
+        }
+
+        if self.create_if_missing {
+            return Ok(WriteProbe::Unsupported(format!(
+                "{} doesn't exist yet, and any write would create it",
+                name
+            )));
+        }
+        Err(Self::not_found(name, RecordType::A).into_error(self.name()))
+    }
+
+    async fn warnings(&self) -> Vec<String> {
+        let zone_url = format!("{}/client/v4/zones/{}", self.base_url, self.zone_id);
+        let Ok(Some(zone)) = self.send::<Zone>(self.client.get(&zone_url)).await else {
+            return Vec::new();
+        };
+        // Only the apex is flattened unless the zone says otherwise
+        if zone.name != self.record_name().as_str() {
+            return Vec::new();
+        }
+
+        let setting_url = format!("{}/settings/cname_flattening", zone_url);
+        match self
+            .send::<ZoneSetting>(self.client.get(&setting_url))
+            .await
+        {
+            Ok(Some(setting)) if setting.value == FLATTEN_ALL => vec![format!(
+                "zone {} flattens every CNAME (CNAME flattening is \"{}\"), so names \
+                 pointing at {} are answered with the addresses Cloudflare cached for it \
+                 and may lag behind updates",
+                zone.name,
+                FLATTEN_ALL,
+                self.record_name()
+            )],
+            _ => Vec::new(),
+        }
+    }
 }
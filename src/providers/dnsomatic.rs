@@ -0,0 +1,229 @@
+//! DNS-O-Matic provider.
+//!
+//! DNS-O-Matic takes one dyndns2-style update and forwards it to the
+//! services set up in the account. Updating `all.dnsomatic.com` updates
+//! every one of them, answered with one line per service.
+
+use super::{
+    retry_after, DdnsProvider, ProviderCapabilities, RecordType, UpdateErrorKind, UpdateResult,
+    WriteProbe,
+};
+use crate::error::{DdnsError, Result};
+use crate::names::DomainName;
+use crate::secret::Secret;
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+pub(super) const DEFAULT_BASE_URL: &str = "https://updates.dnsomatic.com";
+
+/// DNS-O-Matic provider.
+pub struct DnsOMaticProvider {
+    client: crate::http::Client,
+    username: String,
+    password: Secret,
+    hostname: DomainName,
+    base_url: String,
+}
+
+impl DnsOMaticProvider {
+    /// What the provider type can do: DNS-O-Matic only forwards IPv4
+    /// addresses, and can't report what the services hold.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: false,
+        supports_ipv6: false,
+        supports_ttl: false,
+        supports_txt: false,
+        supports_create: false,
+    };
+
+    /// Create a new DNS-O-Matic provider updating `hostname`, or every
+    /// service of the account for `all.dnsomatic.com`.
+    pub fn new(username: String, password: impl Into<Secret>, hostname: DomainName) -> Self {
+        Self::with_base_url(username, password, hostname, DEFAULT_BASE_URL.to_string())
+    }
+
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
+    pub fn with_base_url(
+        username: String,
+        password: impl Into<Secret>,
+        hostname: DomainName,
+        base_url: String,
+    ) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            username,
+            password: password.into(),
+            hostname,
+            base_url,
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn result(&self, ip: IpAddr, error: Option<(UpdateErrorKind, String)>) -> UpdateResult {
+        let success = error.is_none();
+        let (error_kind, error) = error.unzip();
+        UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type: RecordType::for_ip(&ip),
+            success,
+            ip: success.then_some(ip),
+            previous_ip: None,
+            error,
+            error_kind,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Read one line of an update response (`good 192.0.2.1`, `nohost`...):
+/// `None` if the service was updated, otherwise why not.
+fn line_error(line: &str) -> Option<(UpdateErrorKind, String)> {
+    let code = line.split_whitespace().next().unwrap_or_default();
+    let (kind, meaning) = match code {
+        "good" | "nochg" => return None,
+        "badauth" => (UpdateErrorKind::Auth, "wrong username or password"),
+        "nohost" => (
+            UpdateErrorKind::RecordNotFound,
+            "hostname isn't a service of the account",
+        ),
+        "notfqdn" => (
+            UpdateErrorKind::ProviderRejected,
+            "hostname isn't a fully qualified domain name",
+        ),
+        "numhost" => (UpdateErrorKind::ProviderRejected, "too many hostnames"),
+        "badagent" => (UpdateErrorKind::ProviderRejected, "client was blocked"),
+        "abuse" => (
+            UpdateErrorKind::RateLimited { retry_after: None },
+            "updates were blocked for abuse",
+        ),
+        "dnserr" | "911" => (UpdateErrorKind::Network, "failed on the server side"),
+        _ => return Some((UpdateErrorKind::ProviderRejected, line.to_string())),
+    };
+    Some((kind, format!("{} ({})", code, meaning)))
+}
+
+/// Read an update response, with one line per updated service. Failures
+/// of some services are listed by their line, so they aren't mistaken for
+/// a failure of all of them.
+fn response_error(text: &str) -> Option<(UpdateErrorKind, String)> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return Some((
+            UpdateErrorKind::ProviderRejected,
+            "Empty response".to_string(),
+        ));
+    }
+
+    let failed: Vec<(usize, (UpdateErrorKind, String))> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| line_error(line).map(|error| (i + 1, error)))
+        .collect();
+    match failed.as_slice() {
+        [] => None,
+        [(_, error)] if lines.len() == 1 => Some(error.clone()),
+        [(_, (kind, _)), ..] => {
+            let details: Vec<String> = failed
+                .iter()
+                .map(|(n, (_, message))| format!("service {}: {}", n, message))
+                .collect();
+            Some((
+                *kind,
+                format!(
+                    "{} of {} services failed: {}",
+                    failed.len(),
+                    lines.len(),
+                    details.join("; ")
+                ),
+            ))
+        }
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for DnsOMaticProvider {
+    fn name(&self) -> &'static str {
+        "dnsomatic"
+    }
+
+    fn domain(&self) -> String {
+        self.hostname.to_string()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        // DNS-O-Matic only forwards updates
+        Ok(None)
+    }
+
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        if ip.is_ipv6() {
+            return Ok(self.result(
+                ip,
+                Some((
+                    UpdateErrorKind::Other,
+                    "IPv6 is not supported by DNS-O-Matic".to_string(),
+                )),
+            ));
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/nic/update", self.base_url))
+            .basic_auth(&self.username, Some(self.password.expose()))
+            .query(&[
+                ("hostname", self.hostname.to_string()),
+                ("myip", ip.to_string()),
+                ("wildcard", "NOCHG".to_string()),
+                ("mx", "NOCHG".to_string()),
+                ("backmx", "NOCHG".to_string()),
+            ])
+            .send()
+            .await?;
+        let status = response.status();
+        let status_kind = UpdateErrorKind::for_status(status, retry_after(&response));
+        let text = response.text().await?;
+
+        if !status.is_success() {
+            return Ok(self.result(
+                ip,
+                Some((status_kind, format!("DNS-O-Matic returned HTTP {}", status))),
+            ));
+        }
+        Ok(self.result(ip, response_error(&text)))
+    }
+
+    async fn validate(&self) -> Result<()> {
+        // Updating is the only way to check the credentials
+        if self.username.is_empty() || self.password.is_empty() {
+            return Err(DdnsError::Provider {
+                provider: "dnsomatic".to_string(),
+                message: "Username or password is empty".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        Ok(WriteProbe::Unsupported(
+            "DNS-O-Matic can't report the current address, so any write may change it; \
+             the credentials are only checked by the first update"
+                .to_string(),
+        ))
+    }
+}
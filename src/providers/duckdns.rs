@@ -1,41 +1,164 @@
 //! DuckDNS provider.
 
-use super::{DdnsProvider, UpdateResult};
+use super::{
+    retry_after, DdnsProvider, ProviderCapabilities, RecordType, UpdateErrorKind, UpdateResult,
+    WriteProbe,
+};
 use crate::error::{DdnsError, Result};
+use crate::secret::Secret;
 use async_trait::async_trait;
 use std::net::IpAddr;
 
-const DEFAULT_BASE_URL: &str = "https://www.duckdns.org";
+pub(super) const DEFAULT_BASE_URL: &str = "https://www.duckdns.org";
+
+/// Suffix of DuckDNS names, left out of `domains`.
+const SUFFIX: &str = ".duckdns.org";
+
+/// Longest DuckDNS subdomain.
+const MAX_SUBDOMAIN_LEN: usize = 63;
+
+/// Subdomains listed in a `domains` setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuckDnsDomains {
+    /// Subdomains, lowercased and without the `.duckdns.org` suffix.
+    pub subdomains: Vec<String>,
+    /// Fixes made to the setting, e.g. a stripped suffix.
+    pub warnings: Vec<String>,
+}
+
+impl DuckDnsDomains {
+    /// Parse a comma-separated list of subdomains, e.g. `home,office`.
+    ///
+    /// An accidental `.duckdns.org` suffix is stripped with a warning.
+    /// Fails with every malformed entry: empty ones (as left by a stray
+    /// comma) and those with other characters than letters, digits and
+    /// hyphens.
+    pub fn parse(domains: &str) -> std::result::Result<Self, String> {
+        let mut subdomains = Vec::new();
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+
+        for (i, entry) in domains.split(',').map(str::trim).enumerate() {
+            if entry.is_empty() {
+                errors.push(format!("entry {} is empty (stray comma?)", i + 1));
+                continue;
+            }
+
+            let lower = entry.to_ascii_lowercase();
+            let subdomain = match lower.trim_end_matches('.').strip_suffix(SUFFIX) {
+                Some(short) => {
+                    warnings.push(format!(
+                        "`domains` lists subdomains only: \"{}\" is taken as \"{}\"",
+                        entry, short
+                    ));
+                    short.to_string()
+                }
+                None => lower,
+            };
+
+            if subdomain.is_empty() {
+                errors.push(format!("\"{}\" has no subdomain", entry));
+            } else if subdomain.len() > MAX_SUBDOMAIN_LEN {
+                errors.push(format!(
+                    "\"{}\" is longer than {} characters",
+                    entry, MAX_SUBDOMAIN_LEN
+                ));
+            } else if let Some(c) = subdomain
+                .chars()
+                .find(|c| !(c.is_ascii_alphanumeric() || *c == '-'))
+            {
+                errors.push(format!(
+                    "\"{}\" contains '{}' (only letters, digits and hyphens are allowed)",
+                    entry, c
+                ));
+            } else {
+                subdomains.push(subdomain);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(format!("`domains`: {}", errors.join("; ")));
+        }
+        Ok(Self {
+            subdomains,
+            warnings,
+        })
+    }
+
+    /// Get the full names, e.g. `home.duckdns.org, office.duckdns.org`.
+    pub fn full_names(&self) -> String {
+        self.subdomains
+            .iter()
+            .map(|subdomain| format!("{}{}", subdomain, SUFFIX))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
 
 /// DuckDNS provider.
 pub struct DuckDnsProvider {
-    client: reqwest::Client,
-    domains: String,
-    token: String,
+    client: crate::http::Client,
+    /// Parsed `domains`, or why they are malformed.
+    domains: std::result::Result<DuckDnsDomains, String>,
+    /// `domains` as configured, to name the provider if malformed.
+    raw_domains: String,
+    token: Secret,
     base_url: String,
 }
 
 impl DuckDnsProvider {
+    /// What the provider type can do: DuckDNS only updates the addresses
+    /// of existing subdomains, with a fixed TTL.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: false,
+        supports_ipv6: true,
+        supports_ttl: false,
+        supports_txt: false,
+        supports_create: false,
+    };
+
     /// Create a new DuckDNS provider.
-    pub fn new(domains: String, token: String) -> Self {
+    ///
+    /// Malformed `domains` (see [`DuckDnsDomains::parse`]) are reported by
+    /// every operation.
+    pub fn new(domains: String, token: impl Into<Secret>) -> Self {
         Self::with_base_url(domains, token, DEFAULT_BASE_URL.to_string())
     }
 
-    /// Create with custom base URL (for testing).
-    pub fn with_base_url(domains: String, token: String, base_url: String) -> Self {
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
+    pub fn with_base_url(domains: String, token: impl Into<Secret>, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            domains,
-            token,
+            client: crate::http::default_client(),
+            domains: DuckDnsDomains::parse(&domains),
+            raw_domains: domains,
+            token: token.into(),
             base_url,
         }
     }
 
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Get the parsed domains, failing if they are malformed.
+    fn domains(&self) -> Result<&DuckDnsDomains> {
+        self.domains
+            .as_ref()
+            .map_err(|message| DdnsError::Provider {
+                provider: "duckdns".to_string(),
+                message: message.clone(),
+            })
+    }
+
+    /// Get the full name of the first subdomain.
     fn full_domain(&self) -> String {
-        format!(
-            "{}.duckdns.org",
-            self.domains.split(',').next().unwrap_or(&self.domains)
-        )
+        match &self.domains {
+            Ok(domains) => format!("{}{}", domains.subdomains[0], SUFFIX),
+            Err(_) => format!("{}{}", self.raw_domains, SUFFIX),
+        }
     }
 }
 
@@ -49,40 +172,70 @@ impl DdnsProvider for DuckDnsProvider {
         self.full_domain()
     }
 
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
     async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
         // DuckDNS doesn't provide a way to query current IP
         Ok(None)
     }
 
     async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let domains = self.domains()?;
+        // IPv6 addresses go in a separate parameter so the A record is untouched
+        let ip_param = if ip.is_ipv4() { "ip" } else { "ipv6" };
         let url = format!(
-            "{}/update?domains={}&token={}&ip={}",
-            self.base_url, self.domains, self.token, ip
+            "{}/update?domains={}&token={}&{}={}",
+            self.base_url,
+            domains.subdomains.join(","),
+            self.token.expose(),
+            ip_param,
+            ip
         );
 
         let response = self.client.get(&url).send().await?;
+        let status = response.status();
+        let status_kind = UpdateErrorKind::for_status(status, retry_after(&response));
         let text = response.text().await?;
 
-        let success = text.trim() == "OK";
+        let success = status.is_success() && text.trim() == "OK";
 
         if success {
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: domains.full_names(),
+                record_type: RecordType::for_ip(&ip),
                 success: true,
                 ip: Some(ip),
                 previous_ip: None,
                 error: None,
+                error_kind: None,
                 timestamp: chrono::Utc::now(),
             })
         } else {
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: domains.full_names(),
+                record_type: RecordType::for_ip(&ip),
                 success: false,
                 ip: None,
                 previous_ip: None,
-                error: Some(format!("DuckDNS returned: {}", text.trim())),
+                error: Some(if status.is_success() {
+                    format!("DuckDNS returned: {}", text.trim())
+                } else {
+                    format!("DuckDNS returned HTTP {}", status)
+                }),
+                error_kind: Some(if !status.is_success() {
+                    status_kind
+                } else if text.trim() == "KO" {
+                    // Wrong token, or a domain not in the account
+                    UpdateErrorKind::Auth
+                } else {
+                    UpdateErrorKind::ProviderRejected
+                }),
                 timestamp: chrono::Utc::now(),
             })
         }
@@ -95,12 +248,23 @@ impl DdnsProvider for DuckDnsProvider {
                 message: "Token is empty".to_string(),
             });
         }
-        if self.domains.is_empty() {
-            return Err(DdnsError::Provider {
-                provider: "duckdns".to_string(),
-                message: "Domains is empty".to_string(),
-            });
-        }
+        self.domains()?;
         Ok(())
     }
+
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        self.domains()?;
+        Ok(WriteProbe::Unsupported(
+            "DuckDNS can't report the current address, so any write may change it; \
+             the token is only checked by the first update"
+                .to_string(),
+        ))
+    }
+
+    async fn warnings(&self) -> Vec<String> {
+        self.domains
+            .as_ref()
+            .map(|domains| domains.warnings.clone())
+            .unwrap_or_default()
+    }
 }
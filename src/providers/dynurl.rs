@@ -0,0 +1,189 @@
+//! Generic dynamic URL provider.
+//!
+//! Registrars such as ClouDNS and Dynu hand out a URL that updates one
+//! record when fetched, with the token in the URL itself.
+
+use super::{
+    retry_after, DdnsProvider, ProviderCapabilities, RecordType, UpdateErrorKind, UpdateResult,
+    WriteProbe,
+};
+use crate::error::{DdnsError, Result};
+use crate::secret::Secret;
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+/// Longest part of a response quoted in an error.
+const MAX_QUOTED_LEN: usize = 200;
+
+/// Dynamic URL provider: fetching the URL updates the record.
+pub struct DynUrlProvider {
+    client: crate::http::Client,
+    url: Secret,
+    domain: String,
+    /// Query parameter to append the address as, if any; otherwise the
+    /// service takes the address the request comes from.
+    ip_param: Option<String>,
+    /// Text a successful response contains, if the status isn't enough.
+    success_contains: Option<String>,
+}
+
+impl DynUrlProvider {
+    /// What the provider type can do: the URL only sets an address.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: false,
+        supports_ipv6: true,
+        supports_ttl: false,
+        supports_txt: false,
+        supports_create: false,
+    };
+
+    /// Create a new dynamic URL provider fetching `url`, which updates
+    /// `domain` (only used to name the record).
+    pub fn new(url: impl Into<Secret>, domain: String) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            url: url.into(),
+            domain,
+            ip_param: None,
+            success_contains: None,
+        }
+    }
+
+    /// Append the address to the URL as the query parameter `param`, e.g.
+    /// `&ip=192.0.2.1` for `ip`.
+    pub fn with_ip_param(mut self, param: Option<String>) -> Self {
+        self.ip_param = param;
+        self
+    }
+
+    /// Only count a response as successful if it contains `text`.
+    pub fn with_success_contains(mut self, text: Option<String>) -> Self {
+        self.success_contains = text;
+        self
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Parse the URL. Errors never quote it, as it holds the token.
+    fn url(&self) -> Result<reqwest::Url> {
+        let url = reqwest::Url::parse(self.url.expose()).map_err(|e| DdnsError::Provider {
+            provider: "dynurl".to_string(),
+            message: format!("`url` is not a valid URL ({})", e),
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(DdnsError::Provider {
+                provider: "dynurl".to_string(),
+                message: format!("`url` must be http or https, not {}", url.scheme()),
+            });
+        }
+        Ok(url)
+    }
+
+    fn result(&self, ip: IpAddr, error: Option<(UpdateErrorKind, String)>) -> UpdateResult {
+        let success = error.is_none();
+        let (error_kind, error) = error.unzip();
+        UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain.clone(),
+            record_type: RecordType::for_ip(&ip),
+            success,
+            ip: success.then_some(ip),
+            previous_ip: None,
+            error,
+            error_kind,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Get the first line of `text`, shortened, to quote it in an error.
+fn quote(text: &str) -> String {
+    let line = text.trim().lines().next().unwrap_or_default();
+    match line.char_indices().nth(MAX_QUOTED_LEN) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for DynUrlProvider {
+    fn name(&self) -> &'static str {
+        "dynurl"
+    }
+
+    fn domain(&self) -> String {
+        self.domain.clone()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        // The URL only sets the address
+        Ok(None)
+    }
+
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let mut url = self.url()?;
+        if let Some(param) = &self.ip_param {
+            url.query_pairs_mut().append_pair(param, &ip.to_string());
+        }
+
+        // Errors mentioning the URL would leak its token
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| DdnsError::Network(e.without_url().to_string()))?;
+        let status = response.status();
+        let status_kind = UpdateErrorKind::for_status(status, retry_after(&response));
+        let text = response
+            .text()
+            .await
+            .map_err(|e| DdnsError::Network(e.without_url().to_string()))?;
+
+        if !status.is_success() {
+            return Ok(self.result(
+                ip,
+                Some((status_kind, format!("Dynamic URL returned HTTP {}", status))),
+            ));
+        }
+        match &self.success_contains {
+            Some(expected) if !text.contains(expected.as_str()) => Ok(self.result(
+                ip,
+                Some((
+                    UpdateErrorKind::ProviderRejected,
+                    format!(
+                        "Response doesn't contain \"{}\": {}",
+                        expected,
+                        quote(&text)
+                    ),
+                )),
+            )),
+            _ => Ok(self.result(ip, None)),
+        }
+    }
+
+    /// Check the URL. Fetching it would update the record, so it is only
+    /// checked by the first update.
+    async fn validate(&self) -> Result<()> {
+        self.url()?;
+        Ok(())
+    }
+
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        self.url()?;
+        Ok(WriteProbe::Unsupported(
+            "a dynamic URL can't report the current address, so any write may change it; \
+             the URL is only checked by the first update"
+                .to_string(),
+        ))
+    }
+}
@@ -1,27 +1,40 @@
 //! GoDaddy DDNS provider.
 
-use super::{DdnsProvider, UpdateResult};
+use super::{
+    cname_message, retry_after, DdnsProvider, ProviderCapabilities, RecordInfo, RecordType,
+    UpdateErrorKind, UpdateResult, WriteProbe,
+};
 use crate::error::{DdnsError, Result};
+use crate::names::{DomainName, RecordName};
+use crate::secret::Secret;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
 
-const DEFAULT_BASE_URL: &str = "https://api.godaddy.com";
+pub(super) const DEFAULT_BASE_URL: &str = "https://api.godaddy.com";
 
 /// GoDaddy DDNS provider.
 pub struct GoDaddyProvider {
-    client: reqwest::Client,
-    api_key: String,
-    api_secret: String,
-    domain: String,
-    name: String,
+    client: crate::http::Client,
+    api_key: Secret,
+    api_secret: Secret,
+    domain: DomainName,
+    name: RecordName,
     ttl: u32,
     base_url: String,
+    /// Address each record was last set to, so updates needn't look it up
+    /// again.
+    written: Mutex<HashMap<RecordType, IpAddr>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct DnsRecord {
     data: String,
+    #[serde(default)]
+    ttl: Option<u32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,18 +43,109 @@ struct UpdateRecord {
     ttl: u32,
 }
 
-#[derive(Debug, Deserialize)]
+/// Error body, e.g. `{"code": "ACCESS_DENIED", "message": "..."}`, with
+/// `fields` for invalid requests.
+#[derive(Debug, Default, Deserialize)]
 struct GoDaddyError {
-    message: String,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    fields: Vec<GoDaddyFieldError>,
+    /// Seconds to wait, for rate-limited requests.
+    #[serde(default, rename = "retryAfterSec")]
+    retry_after_sec: Option<u64>,
+}
+
+/// Problem with one field of an invalid request.
+#[derive(Debug, Deserialize)]
+struct GoDaddyFieldError {
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Code of the error returned to accounts not allowed to use the API.
+const ACCESS_DENIED: &str = "ACCESS_DENIED";
+
+/// Describe a failed request from its status, `Retry-After` header and body.
+fn describe_error(
+    status: reqwest::StatusCode,
+    header_retry_after: Option<Duration>,
+    body: &str,
+) -> (UpdateErrorKind, String) {
+    let Ok(error) = serde_json::from_str::<GoDaddyError>(body) else {
+        return (
+            UpdateErrorKind::for_status(status, header_retry_after),
+            format!("GoDaddy returned HTTP {}", status),
+        );
+    };
+
+    // The delay in the body is more precise than the header
+    let retry_after = error
+        .retry_after_sec
+        .map(Duration::from_secs)
+        .or(header_retry_after);
+    if error.code.as_deref() == Some(ACCESS_DENIED) {
+        let mut message = "GoDaddy denied API access to this account: its DNS API is only \
+                           open to accounts meeting GoDaddy's eligibility rules (a minimum \
+                           number of domains or a qualifying plan). Check the account's API \
+                           eligibility with GoDaddy, or move the domain's DNS to another \
+                           provider"
+            .to_string();
+        if let Some(detail) = &error.message {
+            message.push_str(&format!(" ({})", detail));
+        }
+        return (UpdateErrorKind::Auth, message);
+    }
+
+    let mut message = error
+        .message
+        .or(error.code)
+        .unwrap_or_else(|| format!("GoDaddy returned HTTP {}", status));
+    let fields: Vec<String> = error
+        .fields
+        .iter()
+        .map(|field| match (&field.path, &field.message) {
+            (Some(path), Some(problem)) => format!("{}: {}", path, problem),
+            (Some(text), None) | (None, Some(text)) => text.clone(),
+            (None, None) => String::new(),
+        })
+        .filter(|field| !field.is_empty())
+        .collect();
+    if !fields.is_empty() {
+        message.push_str(&format!(" ({})", fields.join("; ")));
+    }
+    (UpdateErrorKind::for_status(status, retry_after), message)
+}
+
+/// Read why a request failed from its response.
+async fn read_error(response: reqwest::Response) -> (UpdateErrorKind, String) {
+    let status = response.status();
+    let retry_after = retry_after(&response);
+    let body = response.text().await.unwrap_or_default();
+    describe_error(status, retry_after, &body)
 }
 
 impl GoDaddyProvider {
+    /// What the provider type can do. Writing a record creates it if
+    /// missing.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: true,
+        supports_ipv6: true,
+        supports_ttl: true,
+        supports_txt: false,
+        supports_create: true,
+    };
+
     /// Create a new GoDaddy provider.
     pub fn new(
-        api_key: String,
-        api_secret: String,
-        domain: String,
-        name: String,
+        api_key: impl Into<Secret>,
+        api_secret: impl Into<Secret>,
+        domain: DomainName,
+        name: RecordName,
         ttl: u32,
     ) -> Self {
         Self::with_base_url(
@@ -54,53 +158,47 @@ impl GoDaddyProvider {
         )
     }
 
-    /// Create with custom base URL (for testing).
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
     pub fn with_base_url(
-        api_key: String,
-        api_secret: String,
-        domain: String,
-        name: String,
+        api_key: impl Into<Secret>,
+        api_secret: impl Into<Secret>,
+        domain: DomainName,
+        name: RecordName,
         ttl: u32,
         base_url: String,
     ) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            api_key,
-            api_secret,
+            client: crate::http::default_client(),
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
             domain,
             name,
             ttl,
             base_url,
+            written: Mutex::new(HashMap::new()),
         }
     }
 
-    fn full_domain(&self) -> String {
-        if self.name == "@" {
-            self.domain.clone()
-        } else {
-            format!("{}.{}", self.name, self.domain)
-        }
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
     }
 
     fn auth_header(&self) -> String {
-        format!("sso-key {}:{}", self.api_key, self.api_secret)
-    }
-}
-
-#[async_trait]
-impl DdnsProvider for GoDaddyProvider {
-    fn name(&self) -> &'static str {
-        "godaddy"
-    }
-
-    fn domain(&self) -> String {
-        self.full_domain()
+        format!(
+            "sso-key {}:{}",
+            self.api_key.expose(),
+            self.api_secret.expose()
+        )
     }
 
-    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+    /// Get the records of `record_type` (e.g. `A` or `CNAME`) of the name.
+    async fn get_records(&self, record_type: &str) -> Result<Vec<DnsRecord>> {
         let url = format!(
-            "{}/v1/domains/{}/records/A/{}",
-            self.base_url, self.domain, self.name
+            "{}/v1/domains/{}/records/{}/{}",
+            self.base_url, self.domain, record_type, self.name
         );
 
         let response = self
@@ -111,20 +209,106 @@ impl DdnsProvider for GoDaddyProvider {
             .await?;
 
         if !response.status().is_success() {
-            return Ok(None);
+            let (_, message) = read_error(response).await;
+            return Err(DdnsError::Provider {
+                provider: self.name().to_string(),
+                message,
+            });
         }
 
-        let records: Vec<DnsRecord> = response.json().await?;
-        Ok(records.first().and_then(|r| r.data.parse().ok()))
+        Ok(response.json().await?)
+    }
+
+    /// Get the first record of `record_type` of the name, with its TTL.
+    async fn record(&self, record_type: RecordType) -> Result<Option<RecordInfo>> {
+        let records = self.get_records(&record_type.to_string()).await?;
+        if records.is_empty() {
+            if let Some(target) = self.cname_target().await {
+                return Err(DdnsError::Provider {
+                    provider: self.name().to_string(),
+                    message: cname_message(&self.domain(), &target),
+                });
+            }
+        }
+        Ok(records.first().and_then(|r| {
+            let ip = r.data.parse().ok()?;
+            Some(RecordInfo::new(ip, r.ttl))
+        }))
+    }
+
+    /// Get the target of the name if it is a CNAME.
+    ///
+    /// The lookup only explains a missing record, so failing it isn't an
+    /// error.
+    async fn cname_target(&self) -> Option<String> {
+        let records = self.get_records("CNAME").await.ok()?;
+        records.into_iter().next().map(|r| r.data)
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for GoDaddyProvider {
+    fn name(&self) -> &'static str {
+        "godaddy"
+    }
+
+    fn domain(&self) -> String {
+        self.name.full_domain(&self.domain)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        self.get_record_ip(RecordType::A).await
+    }
+
+    async fn get_current_record(&self) -> Result<Option<RecordInfo>> {
+        self.record(RecordType::A).await
+    }
+
+    async fn get_record_ip(&self, record_type: RecordType) -> Result<Option<IpAddr>> {
+        Ok(self.record(record_type).await?.map(|record| record.ip))
     }
 
     async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
-        let previous_ip = self.get_current_ip().await.ok().flatten();
+        let record_type = RecordType::for_ip(&ip);
+        let written = self.written.lock().unwrap().get(&record_type).copied();
+        let previous_ip = match written {
+            Some(written) => Some(written),
+            None => {
+                let records = self.get_records(&record_type.to_string()).await;
+                // Writing an address record next to a CNAME would be rejected
+                if matches!(&records, Ok(records) if records.is_empty()) {
+                    if let Some(target) = self.cname_target().await {
+                        return Ok(UpdateResult {
+                            provider: self.name().to_string(),
+                            label: None,
+                            domain: self.domain(),
+                            record_type,
+                            success: false,
+                            ip: None,
+                            previous_ip: None,
+                            error: Some(cname_message(&self.domain(), &target)),
+                            error_kind: Some(UpdateErrorKind::ProviderRejected),
+                            timestamp: chrono::Utc::now(),
+                        });
+                    }
+                }
+                match records {
+                    Ok(records) => records.first().and_then(|r| r.data.parse().ok()),
+                    Err(_) => None,
+                }
+            }
+        };
 
-        let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
         let url = format!(
             "{}/v1/domains/{}/records/{}/{}",
-            self.base_url, self.domain, record_type, self.name
+            self.base_url,
+            self.domain,
+            RecordType::for_ip(&ip),
+            self.name
         );
 
         let records = vec![UpdateRecord {
@@ -142,58 +326,95 @@ impl DdnsProvider for GoDaddyProvider {
             .await?;
 
         if response.status().is_success() {
+            self.written.lock().unwrap().insert(record_type, ip);
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: self.domain(),
+                record_type: RecordType::for_ip(&ip),
                 success: true,
                 ip: Some(ip),
                 previous_ip,
                 error: None,
+                error_kind: None,
                 timestamp: chrono::Utc::now(),
             })
         } else {
-            let error: std::result::Result<GoDaddyError, _> = response.json().await;
-            let msg = error
-                .map(|e| e.message)
-                .unwrap_or_else(|_| "Unknown error".to_string());
+            // The record may have been changed meanwhile
+            self.written.lock().unwrap().remove(&record_type);
+            let (kind, message) = read_error(response).await;
 
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: self.domain(),
+                record_type: RecordType::for_ip(&ip),
                 success: false,
                 ip: None,
                 previous_ip,
-                error: Some(msg),
+                error: Some(message),
+                error_kind: Some(kind),
                 timestamp: chrono::Utc::now(),
             })
         }
     }
 
     async fn validate(&self) -> Result<()> {
-        let url = format!(
-            "{}/v1/domains/{}/records/A/{}",
-            self.base_url, self.domain, self.name
-        );
-
-        let response = self
-            .client
-            .get(&url)
-            .header("Authorization", self.auth_header())
-            .send()
-            .await?;
+        self.get_current_ip().await?;
+        Ok(())
+    }
 
-        if !response.status().is_success() {
-            let error: std::result::Result<GoDaddyError, _> = response.json().await;
-            let msg = error
-                .map(|e| e.message)
-                .unwrap_or_else(|_| "Authentication failed".to_string());
+    /// Write the A record, or else the AAAA record, back with its address
+    /// and TTL.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let mut records = self.get_records(&record_type.to_string()).await?;
+            let record = match records.len() {
+                0 => continue,
+                1 => records.remove(0),
+                // An update replaces them all with one
+                count => {
+                    return Ok(WriteProbe::Unsupported(format!(
+                        "{} has {} {} records, and any write would replace them with one",
+                        self.domain(),
+                        count,
+                        record_type
+                    )))
+                }
+            };
 
-            return Err(DdnsError::Provider {
-                provider: "godaddy".to_string(),
-                message: msg,
-            });
+            let url = format!(
+                "{}/v1/domains/{}/records/{}/{}",
+                self.base_url, self.domain, record_type, self.name
+            );
+            let records = vec![UpdateRecord {
+                data: record.data.clone(),
+                ttl: record.ttl.unwrap_or(self.ttl),
+            }];
+            let response = self
+                .client
+                .put(&url)
+                .header("Authorization", self.auth_header())
+                .header("Content-Type", "application/json")
+                .json(&records)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let (_, message) = read_error(response).await;
+                return Err(DdnsError::Provider {
+                    provider: self.name().to_string(),
+                    message,
+                });
+            }
+            return Ok(WriteProbe::Written(format!(
+                "wrote {} ({}) back unchanged",
+                record.data, record_type
+            )));
         }
 
-        Ok(())
+        Ok(WriteProbe::Unsupported(format!(
+            "{} has no A or AAAA record yet, and any write would create one",
+            self.domain()
+        )))
     }
 }
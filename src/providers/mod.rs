@@ -1,30 +1,107 @@
 //! DDNS provider implementations.
 
 mod cloudflare;
+mod dnsomatic;
 mod duckdns;
+mod dynurl;
 mod godaddy;
 mod namecheap;
+mod registry;
 #[cfg(test)]
 mod tests;
+mod vultr;
+mod yandexcloud;
 
 pub use cloudflare::CloudflareProvider;
-pub use duckdns::DuckDnsProvider;
+pub use dnsomatic::DnsOMaticProvider;
+pub use duckdns::{DuckDnsDomains, DuckDnsProvider};
+pub use dynurl::DynUrlProvider;
 pub use godaddy::GoDaddyProvider;
 pub use namecheap::NamecheapProvider;
+pub use registry::{
+    register_provider, registered_types, ProviderConstructor, ProviderContext, ProviderRegistry,
+};
+pub use vultr::VultrProvider;
+pub use yandexcloud::{YandexCloudAuth, YandexCloudDnsProvider};
 
-use crate::config::ProviderConfig;
-use crate::error::Result;
+use crate::config::{read_secret, NetworkConfig, ProviderConfig, ProviderKind, YandexAuthType};
+use crate::error::{DdnsError, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use std::time::Duration;
+
+/// DNS record type for an address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum RecordType {
+    /// IPv4 address record.
+    #[default]
+    A,
+    /// IPv6 address record.
+    #[serde(rename = "AAAA")]
+    Aaaa,
+}
+
+impl RecordType {
+    /// Get the record type holding an address.
+    pub fn for_ip(ip: &IpAddr) -> Self {
+        if ip.is_ipv4() {
+            RecordType::A
+        } else {
+            RecordType::Aaaa
+        }
+    }
+
+    /// Get the record type name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+        }
+    }
+}
+
+impl std::fmt::Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Address record as reported by a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordInfo {
+    /// Address the record holds.
+    pub ip: IpAddr,
+    /// TTL of the record in seconds, if the provider reports it.
+    pub ttl: Option<u32>,
+    /// Record type, following from `ip`.
+    pub record_type: RecordType,
+}
+
+impl RecordInfo {
+    /// Describe a record holding `ip` with the given TTL.
+    pub fn new(ip: IpAddr, ttl: Option<u32>) -> Self {
+        Self {
+            ip,
+            ttl,
+            record_type: RecordType::for_ip(&ip),
+        }
+    }
+}
 
 /// Result of a DNS update operation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateResult {
     /// Provider name.
     pub provider: String,
+    /// Label of the configured provider entry (set by the updater).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
     /// Domain/record that was updated.
     pub domain: String,
+    /// Record type (address family) that was updated.
+    #[serde(default)]
+    pub record_type: RecordType,
     /// Whether the update was successful.
     pub success: bool,
     /// New IP address.
@@ -33,10 +110,270 @@ pub struct UpdateResult {
     pub previous_ip: Option<IpAddr>,
     /// Error message if failed.
     pub error: Option<String>,
+    /// Why the update failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<UpdateErrorKind>,
     /// Timestamp of the update.
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+/// Outcome of a [`DdnsProvider::probe_write`] that didn't fail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "message", rename_all = "snake_case")]
+pub enum WriteProbe {
+    /// A write leaving the record as it was went through, as described.
+    Written(String),
+    /// No write could be made without changing the record, and why.
+    Unsupported(String),
+}
+
+/// Why an update failed, deciding whether and when to retry it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UpdateErrorKind {
+    /// The credentials were rejected.
+    Auth,
+    /// The provider throttled the request, asking to wait `retry_after`.
+    RateLimited {
+        #[serde(
+            rename = "retry_after_secs",
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "secs"
+        )]
+        retry_after: Option<Duration>,
+    },
+    /// The record (or its domain) doesn't exist at the provider.
+    RecordNotFound,
+    /// The provider couldn't be reached, or failed on its side.
+    Network,
+    /// The provider refused the update (e.g. an invalid address).
+    ProviderRejected,
+    /// Anything else.
+    Other,
+}
+
+impl UpdateErrorKind {
+    /// Classify the response status of a failed request, with the delay of
+    /// its `Retry-After` header.
+    ///
+    /// Server errors count as [`UpdateErrorKind::Network`]: like a network
+    /// failure, they are on the provider's side and usually pass.
+    pub fn for_status(status: reqwest::StatusCode, retry_after: Option<Duration>) -> Self {
+        match status.as_u16() {
+            401 | 403 => UpdateErrorKind::Auth,
+            404 => UpdateErrorKind::RecordNotFound,
+            429 => UpdateErrorKind::RateLimited { retry_after },
+            500..=599 => UpdateErrorKind::Network,
+            400..=499 => UpdateErrorKind::ProviderRejected,
+            _ => UpdateErrorKind::Other,
+        }
+    }
+
+    /// Classify an error returned by a provider instead of a result.
+    pub fn for_error(error: &DdnsError) -> Self {
+        match error {
+            DdnsError::Network(_) => UpdateErrorKind::Network,
+            DdnsError::Auth { .. } => UpdateErrorKind::Auth,
+            _ => UpdateErrorKind::Other,
+        }
+    }
+
+    /// Whether retrying can't succeed until the configuration or the
+    /// account at the provider changes.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            UpdateErrorKind::Auth
+                | UpdateErrorKind::RecordNotFound
+                | UpdateErrorKind::ProviderRejected
+        )
+    }
+
+    /// Get the kind name, as serialized.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateErrorKind::Auth => "auth",
+            UpdateErrorKind::RateLimited { .. } => "rate_limited",
+            UpdateErrorKind::RecordNotFound => "record_not_found",
+            UpdateErrorKind::Network => "network",
+            UpdateErrorKind::ProviderRejected => "provider_rejected",
+            UpdateErrorKind::Other => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for UpdateErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Get the delay of a response's `Retry-After` header, given in seconds or
+/// as a date.
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Failed request to a provider API, with its [`UpdateErrorKind`].
+#[derive(Clone)]
+pub(super) struct Failure {
+    pub(super) kind: UpdateErrorKind,
+    pub(super) message: String,
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        Failure {
+            kind: if e.is_decode() {
+                UpdateErrorKind::Other
+            } else {
+                UpdateErrorKind::Network
+            },
+            message: e.to_string(),
+        }
+    }
+}
+
+impl Failure {
+    /// Read why a request failed from its response: the message `parse`
+    /// finds in the body, or else the status returned by `service`.
+    pub(super) async fn read(
+        response: reqwest::Response,
+        service: &str,
+        parse: impl FnOnce(&str) -> Option<String>,
+    ) -> Self {
+        let status = response.status();
+        let kind = UpdateErrorKind::for_status(status, retry_after(&response));
+        let body = response.text().await.unwrap_or_default();
+        let message = parse(&body)
+            .filter(|message| !message.is_empty())
+            .unwrap_or_else(|| format!("{} returned HTTP {}", service, status));
+        Failure { kind, message }
+    }
+
+    /// Turn into an error of provider type `provider`.
+    pub(super) fn into_error(self, provider: &str) -> DdnsError {
+        match self.kind {
+            UpdateErrorKind::Network => DdnsError::Network(self.message),
+            _ => DdnsError::Provider {
+                provider: provider.to_string(),
+                message: self.message,
+            },
+        }
+    }
+}
+
+/// Explain why `name` can't be updated while it is a CNAME to `target`.
+pub(crate) fn cname_message(name: &str, target: &str) -> String {
+    format!(
+        "record {} is a CNAME to {}; DDNS requires an A/AAAA record",
+        name, target
+    )
+}
+
+/// Serialize an optional duration as whole seconds.
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+/// What a provider can do, so callers don't have to find out by trying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderCapabilities {
+    /// Whether [`DdnsProvider::get_current_record`] can report the record.
+    pub can_read_record: bool,
+    /// Whether AAAA records can be updated.
+    pub supports_ipv6: bool,
+    /// Whether the record TTL can be set.
+    pub supports_ttl: bool,
+    /// Whether TXT records can be written.
+    pub supports_txt: bool,
+    /// Whether updating a record that doesn't exist yet creates it.
+    pub supports_create: bool,
+}
+
+impl ProviderCapabilities {
+    /// Get the capabilities of a built-in provider type, `None` for other
+    /// types.
+    pub fn of_type(kind: &str) -> Option<Self> {
+        match kind {
+            "cloudflare" => Some(CloudflareProvider::CAPABILITIES),
+            "namecheap" => Some(NamecheapProvider::CAPABILITIES),
+            "duckdns" => Some(DuckDnsProvider::CAPABILITIES),
+            "godaddy" => Some(GoDaddyProvider::CAPABILITIES),
+            "vultr" => Some(VultrProvider::CAPABILITIES),
+            "yandexcloud" => Some(YandexCloudDnsProvider::CAPABILITIES),
+            "dynurl" => Some(DynUrlProvider::CAPABILITIES),
+            "dnsomatic" => Some(DnsOMaticProvider::CAPABILITIES),
+            _ => None,
+        }
+    }
+
+    /// List the capabilities held, e.g. "read record, IPv6, TTL".
+    pub fn describe(&self) -> String {
+        let held: Vec<&str> = [
+            (self.can_read_record, "read record"),
+            (self.supports_ipv6, "IPv6"),
+            (self.supports_ttl, "TTL"),
+            (self.supports_txt, "TXT"),
+            (self.supports_create, "create"),
+        ]
+        .into_iter()
+        .filter_map(|(held, name)| held.then_some(name))
+        .collect();
+        if held.is_empty() {
+            "update only".to_string()
+        } else {
+            held.join(", ")
+        }
+    }
+}
+
+/// Those assumed of provider types that don't declare theirs: the record
+/// can be read, both families and the TTL can be set, and nothing else.
+impl Default for ProviderCapabilities {
+    fn default() -> Self {
+        Self {
+            can_read_record: true,
+            supports_ipv6: true,
+            supports_ttl: true,
+            supports_txt: false,
+            supports_create: false,
+        }
+    }
+}
+
 /// Trait for DDNS providers.
 #[async_trait]
 pub trait DdnsProvider: Send + Sync {
@@ -46,59 +383,314 @@ pub trait DdnsProvider: Send + Sync {
     /// Get the domain being managed.
     fn domain(&self) -> String;
 
+    /// Get ready for a cycle of calls, e.g. by logging in and keeping the
+    /// session or token for the other methods. The updater calls it once
+    /// per cycle, before the first other method it needs, and skips the
+    /// provider's records for the cycle if it fails.
+    ///
+    /// Sessions may be kept across cycles, but can expire or be revoked at
+    /// any time: the other methods must renew them when the provider
+    /// rejects them, rather than rely on `prepare` having just run.
+    ///
+    /// By default, nothing.
+    async fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Get the current DNS record IP (if available).
     async fn get_current_ip(&self) -> Result<Option<IpAddr>>;
 
+    /// Get the current DNS record (if available), with its TTL if the
+    /// provider reports it.
+    ///
+    /// By default, the address returned by
+    /// [`get_current_ip`](DdnsProvider::get_current_ip), with an unknown TTL.
+    async fn get_current_record(&self) -> Result<Option<RecordInfo>> {
+        Ok(self
+            .get_current_ip()
+            .await?
+            .map(|ip| RecordInfo::new(ip, None)))
+    }
+
+    /// Get the current IP of the record of `record_type` (if available).
+    ///
+    /// By default, the address returned by
+    /// [`get_current_ip`](DdnsProvider::get_current_ip) if it is of that
+    /// type.
+    async fn get_record_ip(&self, record_type: RecordType) -> Result<Option<IpAddr>> {
+        Ok(self
+            .get_current_ip()
+            .await?
+            .filter(|ip| RecordType::for_ip(ip) == record_type))
+    }
+
     /// Update the DNS record to the new IP.
     async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult>;
 
+    /// Get what the provider can do.
+    ///
+    /// By default, [`ProviderCapabilities::default`].
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+
     /// Validate provider configuration/credentials.
     async fn validate(&self) -> Result<()>;
+
+    /// Check for setups updates work with but may not behave as expected,
+    /// e.g. a zone apex answered through CNAME flattening. Called by
+    /// validation once [`validate`](DdnsProvider::validate) passed.
+    ///
+    /// By default, none.
+    async fn warnings(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Check that updates would be accepted by making a write that leaves
+    /// the record as it is, e.g. writing its current address back. Fails if
+    /// the write is rejected.
+    ///
+    /// By default, no such write is made.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        Ok(WriteProbe::Unsupported(format!(
+            "{} has no way to write the record without changing it",
+            self.name()
+        )))
+    }
+}
+
+/// Create a provider from configuration, writing `default_ttl` (the global
+/// `default_ttl`) unless the entry sets its own TTL, and sending requests
+/// with the TLS options of `network` through the entry's proxy or else the
+/// one in `network`.
+///
+/// The provider is made by the constructor of its type in the global
+/// [`ProviderRegistry`], which has the built-in types and those added with
+/// [`register_provider`].
+///
+/// Secrets stored in files are read here. If that fails, the returned
+/// provider reports the error from every operation instead.
+pub fn create_provider(
+    config: &ProviderConfig,
+    default_ttl: Option<u32>,
+    network: &NetworkConfig,
+) -> Box<dyn DdnsProvider> {
+    registry::create(config, default_ttl, network)
 }
 
-/// Create a provider from configuration.
-pub fn create_provider(config: &ProviderConfig) -> Box<dyn DdnsProvider> {
-    match config {
-        ProviderConfig::Cloudflare {
+/// Constructor of the built-in types.
+fn builtin(context: &ProviderContext<'_>) -> Result<Box<dyn DdnsProvider>> {
+    let ttl = context.ttl;
+    let client = context.client.clone();
+    let base_url = |default: &str| {
+        context
+            .config
+            .api_base_url
+            .as_deref()
+            .unwrap_or(default)
+            .trim_end_matches('/')
+            .to_string()
+    };
+    Ok(match &context.config.kind {
+        ProviderKind::Cloudflare {
             api_token,
+            api_token_file,
             zone_id,
             record_name,
             proxied,
-        } => Box::new(CloudflareProvider::new(
-            resolve_env(api_token),
-            zone_id.clone(),
-            record_name.clone(),
-            *proxied,
-        )),
-        ProviderConfig::Namecheap {
+            create_if_missing,
+            additional_records,
+        } => Box::new(
+            CloudflareProvider::with_base_url(
+                read_secret(api_token.expose(), api_token_file.as_deref())?,
+                zone_id.clone(),
+                record_name.clone(),
+                *proxied,
+                base_url(cloudflare::DEFAULT_BASE_URL),
+            )
+            .with_ttl(ttl)
+            .with_create_if_missing(*create_if_missing)
+            .with_additional_records(additional_records.clone())
+            .with_client(client),
+        ),
+        ProviderKind::Namecheap {
             domain,
             host,
             password,
-        } => Box::new(NamecheapProvider::new(
-            domain.clone(),
-            host.clone(),
-            resolve_env(password),
-        )),
-        ProviderConfig::DuckDns { domains, token } => {
-            Box::new(DuckDnsProvider::new(domains.clone(), resolve_env(token)))
-        }
-        ProviderConfig::GoDaddy {
+            password_file,
+        } => Box::new(
+            NamecheapProvider::with_base_url(
+                domain.clone(),
+                host.clone(),
+                read_secret(password.expose(), password_file.as_deref())?,
+                base_url(namecheap::DEFAULT_BASE_URL),
+            )
+            .with_client(client),
+        ),
+        ProviderKind::DuckDns {
+            domains,
+            token,
+            token_file,
+        } => Box::new(
+            DuckDnsProvider::with_base_url(
+                domains.clone(),
+                read_secret(token.expose(), token_file.as_deref())?,
+                base_url(duckdns::DEFAULT_BASE_URL),
+            )
+            .with_client(client),
+        ),
+        ProviderKind::GoDaddy {
             api_key,
+            api_key_file,
             api_secret,
+            api_secret_file,
             domain,
             name,
-            ttl,
-        } => Box::new(GoDaddyProvider::new(
-            resolve_env(api_key),
-            resolve_env(api_secret),
-            domain.clone(),
-            name.clone(),
-            *ttl,
-        )),
+        } => Box::new(
+            GoDaddyProvider::with_base_url(
+                read_secret(api_key.expose(), api_key_file.as_deref())?,
+                read_secret(api_secret.expose(), api_secret_file.as_deref())?,
+                domain.clone(),
+                name.clone(),
+                // Always set: GoDaddy falls back to its own default
+                ttl.unwrap_or_default(),
+                base_url(godaddy::DEFAULT_BASE_URL),
+            )
+            .with_client(client),
+        ),
+        ProviderKind::Vultr {
+            api_key,
+            api_key_file,
+            domain,
+            record_name,
+        } => Box::new(
+            VultrProvider::with_base_url(
+                read_secret(api_key.expose(), api_key_file.as_deref())?,
+                domain.clone(),
+                record_name.clone(),
+                ttl,
+                base_url(vultr::DEFAULT_BASE_URL),
+            )
+            .with_client(client),
+        ),
+        ProviderKind::YandexCloud {
+            auth,
+            auth_file,
+            auth_type,
+            zone_id,
+            record_name,
+        } => {
+            let secret =
+                crate::secret::Secret::new(read_secret(auth.expose(), auth_file.as_deref())?);
+            let auth = match auth_type {
+                YandexAuthType::OAuth => YandexCloudAuth::OAuth(secret),
+                YandexAuthType::Iam => YandexCloudAuth::IamToken(secret),
+                YandexAuthType::ApiKey => YandexCloudAuth::ApiKey(secret),
+            };
+            let provider = match &context.config.api_base_url {
+                // Serves the IAM API too, for OAuth token exchange
+                Some(url) => YandexCloudDnsProvider::with_base_url(
+                    auth,
+                    zone_id.clone(),
+                    record_name.clone(),
+                    ttl,
+                    url.trim_end_matches('/').to_string(),
+                ),
+                None => {
+                    YandexCloudDnsProvider::new(auth, zone_id.clone(), record_name.clone(), ttl)
+                }
+            };
+            Box::new(provider.with_client(client))
+        }
+        ProviderKind::DynUrl {
+            url,
+            url_file,
+            domain,
+            append_ip,
+            ip_param,
+            success_contains,
+            ..
+        } => Box::new(
+            DynUrlProvider::new(
+                read_secret(url.expose(), url_file.as_deref())?,
+                domain.to_string(),
+            )
+            .with_ip_param(append_ip.then(|| ip_param.clone().unwrap_or_else(|| "ip".to_string())))
+            .with_success_contains(success_contains.clone())
+            .with_client(client),
+        ),
+        ProviderKind::DnsOMatic {
+            username,
+            password,
+            password_file,
+            hostname,
+        } => Box::new(
+            DnsOMaticProvider::with_base_url(
+                username.clone(),
+                read_secret(password.expose(), password_file.as_deref())?,
+                hostname.clone(),
+                base_url(dnsomatic::DEFAULT_BASE_URL),
+            )
+            .with_client(client),
+        ),
+        ProviderKind::Other { type_name, .. } => {
+            return Err(DdnsError::Config(format!(
+                "{} is not a built-in provider type",
+                type_name
+            )))
+        }
+    })
+}
+
+/// Stand-in for a provider that could not be created from its
+/// configuration (e.g. an unreadable secret file).
+struct Misconfigured {
+    name: &'static str,
+    domain: String,
+    message: String,
+}
+
+impl Misconfigured {
+    fn error(&self) -> DdnsError {
+        DdnsError::Config(self.message.clone())
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for Misconfigured {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn domain(&self) -> String {
+        self.domain.clone()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::of_type(self.name).unwrap_or_default()
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        Err(self.error())
+    }
+
+    async fn update_ip(&self, _ip: IpAddr) -> Result<UpdateResult> {
+        Err(self.error())
+    }
+
+    async fn validate(&self) -> Result<()> {
+        Err(self.error())
+    }
+
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        Err(self.error())
     }
 }
 
 /// Resolve environment variable references (values starting with $).
+///
+/// Config values are already expanded when loading (see
+/// [`crate::config::interpolate`]); this is for values from other sources.
 pub fn resolve_env(value: &str) -> String {
     if let Some(var_name) = value.strip_prefix('$') {
         std::env::var(var_name).unwrap_or_else(|_| {
@@ -109,3 +701,18 @@ pub fn resolve_env(value: &str) -> String {
         value.to_string()
     }
 }
+
+/// Look up the address a record of `domain` resolves to, through the system
+/// resolver.
+///
+/// Used for providers whose API can't report the current record. The answer
+/// may come from a cache, so it can lag behind the provider.
+pub async fn resolve_record(domain: &str, record_type: RecordType) -> Result<Option<IpAddr>> {
+    let addrs = tokio::net::lookup_host((domain, 0))
+        .await
+        .map_err(|e| DdnsError::Network(format!("Cannot resolve {}: {}", domain, e)))?;
+
+    Ok(addrs
+        .map(|addr| addr.ip())
+        .find(|ip| RecordType::for_ip(ip) == record_type))
+}
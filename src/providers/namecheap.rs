@@ -1,44 +1,79 @@
 //! Namecheap DDNS provider.
 
-use super::{DdnsProvider, UpdateResult};
+use super::{
+    retry_after, DdnsProvider, ProviderCapabilities, RecordType, UpdateErrorKind, UpdateResult,
+    WriteProbe,
+};
 use crate::error::{DdnsError, Result};
+use crate::names::{DomainName, RecordName};
+use crate::secret::Secret;
 use async_trait::async_trait;
 use std::net::IpAddr;
 
-const DEFAULT_BASE_URL: &str = "https://dynamicdns.park-your-domain.com";
+pub(super) const DEFAULT_BASE_URL: &str = "https://dynamicdns.park-your-domain.com";
 
 /// Namecheap DDNS provider.
 pub struct NamecheapProvider {
-    client: reqwest::Client,
-    domain: String,
-    host: String,
-    password: String,
+    client: crate::http::Client,
+    domain: DomainName,
+    host: RecordName,
+    password: Secret,
     base_url: String,
 }
 
 impl NamecheapProvider {
+    /// What the provider type can do: Namecheap Dynamic DNS only updates
+    /// the address of existing A records.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: false,
+        supports_ipv6: false,
+        supports_ttl: false,
+        supports_txt: false,
+        supports_create: false,
+    };
+
     /// Create a new Namecheap provider.
-    pub fn new(domain: String, host: String, password: String) -> Self {
+    pub fn new(domain: DomainName, host: RecordName, password: impl Into<Secret>) -> Self {
         Self::with_base_url(domain, host, password, DEFAULT_BASE_URL.to_string())
     }
 
-    /// Create with custom base URL (for testing).
-    pub fn with_base_url(domain: String, host: String, password: String, base_url: String) -> Self {
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
+    pub fn with_base_url(
+        domain: DomainName,
+        host: RecordName,
+        password: impl Into<Secret>,
+        base_url: String,
+    ) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: crate::http::default_client(),
             domain,
             host,
-            password,
+            password: password.into(),
             base_url,
         }
     }
 
-    fn full_domain(&self) -> String {
-        if self.host == "@" {
-            self.domain.clone()
-        } else {
-            format!("{}.{}", self.host, self.domain)
-        }
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+/// Classify an error message of the update endpoint.
+fn error_kind(message: &str) -> UpdateErrorKind {
+    let message = message.to_lowercase();
+    if message.contains("password") {
+        // "Passwords do not match"
+        UpdateErrorKind::Auth
+    } else if message.contains("not found") || message.contains("no records updated") {
+        // "Domain name not found", "No Records updated. A record not Found;"
+        UpdateErrorKind::RecordNotFound
+    } else if message.contains("too many") {
+        UpdateErrorKind::RateLimited { retry_after: None }
+    } else {
+        UpdateErrorKind::ProviderRejected
     }
 }
 
@@ -49,7 +84,7 @@ impl DdnsProvider for NamecheapProvider {
     }
 
     fn domain(&self) -> String {
-        self.full_domain()
+        self.host.full_domain(&self.domain)
     }
 
     async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
@@ -57,26 +92,54 @@ impl DdnsProvider for NamecheapProvider {
         Ok(None)
     }
 
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
     async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        if ip.is_ipv6() {
+            return Ok(UpdateResult {
+                provider: self.name().to_string(),
+                label: None,
+                domain: self.domain(),
+                record_type: RecordType::Aaaa,
+                success: false,
+                ip: None,
+                previous_ip: None,
+                error: Some("IPv6 is not supported by Namecheap Dynamic DNS".to_string()),
+                error_kind: Some(UpdateErrorKind::Other),
+                timestamp: chrono::Utc::now(),
+            });
+        }
+
         let url = format!(
             "{}/update?host={}&domain={}&password={}&ip={}",
-            self.base_url, self.host, self.domain, self.password, ip
+            self.base_url,
+            self.host,
+            self.domain,
+            self.password.expose(),
+            ip
         );
 
         let response = self.client.get(&url).send().await?;
+        let status = response.status();
+        let status_kind = UpdateErrorKind::for_status(status, retry_after(&response));
         let text = response.text().await?;
 
         // Namecheap returns XML with <ErrCount>0</ErrCount> on success
-        let success = text.contains("<ErrCount>0</ErrCount>");
+        let success = status.is_success() && text.contains("<ErrCount>0</ErrCount>");
 
         if success {
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: self.domain(),
+                record_type: RecordType::for_ip(&ip),
                 success: true,
                 ip: Some(ip),
                 previous_ip: None,
                 error: None,
+                error_kind: None,
                 timestamp: chrono::Utc::now(),
             })
         } else {
@@ -86,17 +149,27 @@ impl DdnsProvider for NamecheapProvider {
                     .nth(1)
                     .and_then(|s| s.split("</Err1>").next())
                     .map(|s| s.to_string())
+            } else if !status.is_success() {
+                Some(format!("HTTP {}", status))
             } else {
                 Some("Unknown error".to_string())
             };
+            let error_kind = if status.is_success() {
+                error_kind(error.as_deref().unwrap_or_default())
+            } else {
+                status_kind
+            };
 
             Ok(UpdateResult {
                 provider: self.name().to_string(),
-                domain: self.full_domain(),
+                label: None,
+                domain: self.domain(),
+                record_type: RecordType::for_ip(&ip),
                 success: false,
                 ip: None,
                 previous_ip: None,
                 error,
+                error_kind: Some(error_kind),
                 timestamp: chrono::Utc::now(),
             })
         }
@@ -113,4 +186,12 @@ impl DdnsProvider for NamecheapProvider {
         }
         Ok(())
     }
+
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        Ok(WriteProbe::Unsupported(
+            "Namecheap can't report the current address, so any write may change it; \
+             the password is only checked by the first update"
+                .to_string(),
+        ))
+    }
 }
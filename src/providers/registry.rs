@@ -0,0 +1,245 @@
+//! Registry of provider types, through which every provider is created from
+//! its configuration.
+
+use super::{builtin, DdnsProvider, Misconfigured};
+use crate::config::{NetworkConfig, ProviderConfig, ProviderKind, BUILTIN_PROVIDER_TYPES};
+use crate::error::{DdnsError, Result};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Function creating a provider from its configuration.
+pub type ProviderConstructor =
+    Arc<dyn Fn(&ProviderContext<'_>) -> Result<Box<dyn DdnsProvider>> + Send + Sync>;
+
+/// What a [`ProviderConstructor`] gets to create a provider.
+pub struct ProviderContext<'a> {
+    /// Configured entry.
+    pub config: &'a ProviderConfig,
+    /// TTL to write (see [`ProviderConfig::ttl`]).
+    pub ttl: Option<u32>,
+    /// HTTP client with the `[network]` settings and the entry's proxy.
+    pub client: crate::http::Client,
+}
+
+impl ProviderContext<'_> {
+    /// Read the settings of an entry of a registered type (every key but
+    /// `type` and the common ones like `label` or `ttl`).
+    pub fn settings<T: DeserializeOwned>(&self) -> Result<T> {
+        let ProviderKind::Other { settings, .. } = &self.config.kind else {
+            return Err(DdnsError::Config(format!(
+                "{} is a built-in provider type",
+                self.config.name()
+            )));
+        };
+        toml::Value::Table(settings.clone())
+            .try_into()
+            .map_err(|e| {
+                DdnsError::Config(format!("Invalid {} settings: {}", self.config.name(), e))
+            })
+    }
+}
+
+/// Constructors of provider types by their `type` name.
+///
+/// [`create_provider`](super::create_provider) uses a global registry, which
+/// starts with the built-in types; add to it with [`register_provider`].
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    constructors: BTreeMap<&'static str, ProviderConstructor>,
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProviderRegistry {
+    /// Create a registry of the built-in types.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            constructors: BTreeMap::new(),
+        };
+        for type_name in BUILTIN_PROVIDER_TYPES {
+            registry.register(type_name, builtin);
+        }
+        registry
+    }
+
+    /// Register a provider type, replacing any constructor it had.
+    pub fn register<F>(&mut self, type_name: &'static str, constructor: F) -> &mut Self
+    where
+        F: Fn(&ProviderContext<'_>) -> Result<Box<dyn DdnsProvider>> + Send + Sync + 'static,
+    {
+        self.constructors.insert(type_name, Arc::new(constructor));
+        self
+    }
+
+    /// Get the registered types.
+    pub fn types(&self) -> Vec<&'static str> {
+        self.constructors.keys().copied().collect()
+    }
+
+    /// Create a provider from configuration (see
+    /// [`create_provider`](super::create_provider)).
+    pub fn create(
+        &self,
+        config: &ProviderConfig,
+        default_ttl: Option<u32>,
+        network: &NetworkConfig,
+    ) -> Box<dyn DdnsProvider> {
+        let (name, constructor) = match self.constructors.get_key_value(config.name()) {
+            Some((name, constructor)) => (*name, Some(constructor)),
+            None => ("unknown", None),
+        };
+        let provider = constructor
+            .ok_or_else(|| DdnsError::Config(format!("Unknown provider type `{}`", config.name())))
+            .and_then(|constructor| {
+                constructor(&ProviderContext {
+                    config,
+                    ttl: config.ttl(default_ttl),
+                    client: network.client_options(config.proxy.as_deref())?.client()?,
+                })
+            });
+
+        provider.unwrap_or_else(|e| {
+            Box::new(Misconfigured {
+                name,
+                domain: config.display_name(),
+                message: match e {
+                    DdnsError::Config(message) => message,
+                    e => e.to_string(),
+                },
+            })
+        })
+    }
+}
+
+/// Registry used by [`create_provider`](super::create_provider).
+fn global() -> &'static RwLock<ProviderRegistry> {
+    static REGISTRY: OnceLock<RwLock<ProviderRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(ProviderRegistry::new()))
+}
+
+/// Register a provider type in the global registry, so that config entries
+/// of that `type` are created by `constructor`.
+///
+/// # Example
+///
+/// ```
+/// use async_trait::async_trait;
+/// use rusty_dns::providers::{
+///     register_provider, DdnsProvider, ProviderCapabilities, RecordType, UpdateResult,
+/// };
+/// use rusty_dns::updater::{DetectedIps, UpdatePolicy, Updater};
+/// use rusty_dns::{Config, IpDetector, Result};
+/// use std::net::IpAddr;
+///
+/// /// Provider of an in-house DNS system.
+/// struct Internal {
+///     zone: String,
+/// }
+///
+/// #[async_trait]
+/// impl DdnsProvider for Internal {
+///     fn name(&self) -> &'static str {
+///         "internal"
+///     }
+///
+///     fn domain(&self) -> String {
+///         self.zone.clone()
+///     }
+///
+///     async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+///         Ok(None)
+///     }
+///
+///     fn capabilities(&self) -> ProviderCapabilities {
+///         ProviderCapabilities {
+///             can_read_record: false,
+///             ..ProviderCapabilities::default()
+///         }
+///     }
+///
+///     async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+///         Ok(UpdateResult {
+///             provider: self.name().to_string(),
+///             label: None,
+///             domain: self.domain(),
+///             record_type: RecordType::for_ip(&ip),
+///             success: true,
+///             ip: Some(ip),
+///             previous_ip: None,
+///             error: None,
+///             error_kind: None,
+///             timestamp: chrono::Utc::now(),
+///         })
+///     }
+///
+///     async fn validate(&self) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// #[derive(serde::Deserialize)]
+/// struct Settings {
+///     zone: String,
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<()> {
+/// register_provider("internal", |context| {
+///     let settings: Settings = context.settings()?;
+///     Ok(Box::new(Internal { zone: settings.zone }))
+/// });
+///
+/// let config: Config = toml::from_str(
+///     r#"
+///     [[providers]]
+///     type = "internal"
+///     zone = "corp.example"
+///     "#,
+/// )
+/// .unwrap();
+/// config.validate()?;
+///
+/// let detector = IpDetector::new();
+/// let ips = DetectedIps {
+///     ipv4: Some("203.0.113.7".parse().unwrap()),
+///     ..Default::default()
+/// };
+/// let updates = Updater::new(&config, &detector)
+///     .update_all(&ips, UpdatePolicy::Force)
+///     .await;
+/// assert_eq!(updates[0].provider, "internal");
+/// assert_eq!(updates[0].domain, "corp.example");
+/// assert!(updates[0].error().is_none());
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_provider<F>(type_name: &'static str, constructor: F)
+where
+    F: Fn(&ProviderContext<'_>) -> Result<Box<dyn DdnsProvider>> + Send + Sync + 'static,
+{
+    global()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .register(type_name, constructor);
+}
+
+/// Get the types of the global registry.
+pub fn registered_types() -> Vec<&'static str> {
+    global().read().unwrap_or_else(|e| e.into_inner()).types()
+}
+
+/// Create a provider with the global registry.
+pub(super) fn create(
+    config: &ProviderConfig,
+    default_ttl: Option<u32>,
+    network: &NetworkConfig,
+) -> Box<dyn DdnsProvider> {
+    // Not holding the lock while constructing
+    let registry = global().read().unwrap_or_else(|e| e.into_inner()).clone();
+    registry.create(config, default_ttl, network)
+}
@@ -2,7 +2,7 @@
 
 #[cfg(test)]
 mod namecheap_tests {
-    use crate::providers::{DdnsProvider, NamecheapProvider};
+    use crate::providers::{DdnsProvider, NamecheapProvider, UpdateErrorKind};
     use std::net::IpAddr;
     use wiremock::matchers::{method, path, query_param};
     use wiremock::{Mock, MockServer, ResponseTemplate};
@@ -30,8 +30,8 @@ mod namecheap_tests {
             .await;
 
         let provider = NamecheapProvider::with_base_url(
-            "example.com".to_string(),
-            "vpn".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
             "secret123".to_string(),
             mock_server.uri(),
         );
@@ -61,8 +61,8 @@ mod namecheap_tests {
             .await;
 
         let provider = NamecheapProvider::with_base_url(
-            "example.com".to_string(),
-            "vpn".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
             "wrong".to_string(),
             mock_server.uri(),
         );
@@ -72,13 +72,69 @@ mod namecheap_tests {
 
         assert!(!result.success);
         assert_eq!(result.error, Some("Passwords do not match".to_string()));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+    }
+
+    #[tokio::test]
+    async fn test_namecheap_error_kinds() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .and(query_param("host", "missing"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<interface-response>
+                    <ErrCount>1</ErrCount>
+                    <Err1>No Records updated. A record not Found;</Err1>
+                </interface-response>"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .and(query_param("host", "invalid"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<interface-response><ErrCount>1</ErrCount><Err1>Invalid IP</Err1></interface-response>",
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .and(query_param("host", "busy"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let update = |host: &str| {
+            NamecheapProvider::with_base_url(
+                "example.com".parse().unwrap(),
+                host.parse().unwrap(),
+                "secret".to_string(),
+                mock_server.uri(),
+            )
+        };
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+
+        let result = update("missing").update_ip(ip).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+
+        let result = update("invalid").update_ip(ip).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+
+        let result = update("busy").update_ip(ip).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("HTTP 503 Service Unavailable")
+        );
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Network));
     }
 
     #[tokio::test]
     async fn test_namecheap_root_domain() {
         let provider = NamecheapProvider::new(
-            "example.com".to_string(),
-            "@".to_string(),
+            "example.com".parse().unwrap(),
+            "@".parse().unwrap(),
             "secret".to_string(),
         );
         assert_eq!(provider.domain(), "example.com");
@@ -87,8 +143,8 @@ mod namecheap_tests {
     #[tokio::test]
     async fn test_namecheap_subdomain() {
         let provider = NamecheapProvider::new(
-            "example.com".to_string(),
-            "vpn".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
             "secret".to_string(),
         );
         assert_eq!(provider.domain(), "vpn.example.com");
@@ -97,7 +153,9 @@ mod namecheap_tests {
 
 #[cfg(test)]
 mod duckdns_tests {
-    use crate::providers::{DdnsProvider, DuckDnsProvider};
+    use crate::providers::{
+        DdnsProvider, DuckDnsDomains, DuckDnsProvider, RecordType, UpdateErrorKind, WriteProbe,
+    };
     use std::net::IpAddr;
     use wiremock::matchers::{method, path, query_param};
     use wiremock::{Mock, MockServer, ResponseTemplate};
@@ -128,6 +186,31 @@ mod duckdns_tests {
         assert_eq!(result.ip, Some(ip));
     }
 
+    #[tokio::test]
+    async fn test_duckdns_update_ipv6() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .and(query_param("domains", "mysubdomain"))
+            .and(query_param("ipv6", "2001:db8::1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .mount(&mock_server)
+            .await;
+
+        let provider = DuckDnsProvider::with_base_url(
+            "mysubdomain".to_string(),
+            "mytoken".to_string(),
+            mock_server.uri(),
+        );
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.record_type, RecordType::Aaaa);
+    }
+
     #[tokio::test]
     async fn test_duckdns_update_failure() {
         let mock_server = MockServer::start().await;
@@ -148,6 +231,40 @@ mod duckdns_tests {
         let result = provider.update_ip(ip).await.unwrap();
 
         assert!(!result.success);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+    }
+
+    #[tokio::test]
+    async fn test_duckdns_rate_limited() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+            .mount(&mock_server)
+            .await;
+
+        let provider = DuckDnsProvider::with_base_url(
+            "mysubdomain".to_string(),
+            "mytoken".to_string(),
+            mock_server.uri(),
+        );
+
+        let result = provider
+            .update_ip("5.6.7.8".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("DuckDNS returned HTTP 429 Too Many Requests")
+        );
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(120))
+            })
+        );
     }
 
     #[tokio::test]
@@ -167,15 +284,140 @@ mod duckdns_tests {
         let provider = DuckDnsProvider::new("".to_string(), "token".to_string());
         assert!(provider.validate().await.is_err());
     }
+    #[test]
+    fn test_duckdns_malformed_domains() {
+        let err = DuckDnsDomains::parse("home,").unwrap_err();
+        assert!(err.contains("entry 2 is empty"));
+        let err = DuckDnsDomains::parse(" , home").unwrap_err();
+        assert!(err.contains("entry 1 is empty"));
+        let err = DuckDnsDomains::parse("my_home").unwrap_err();
+        assert!(err.contains(r#""my_home" contains '_'"#));
+        let err = DuckDnsDomains::parse("home.example.com").unwrap_err();
+        assert!(err.contains(r#""home.example.com" contains '.'"#));
+        let err = DuckDnsDomains::parse(".duckdns.org").unwrap_err();
+        assert!(err.contains("has no subdomain"));
+        assert!(DuckDnsDomains::parse(&"a".repeat(64)).is_err());
+
+        // Every malformed entry is reported at once
+        let err = DuckDnsDomains::parse("a b,,ok").unwrap_err();
+        assert!(err.contains(r#""a b" contains ' '"#));
+        assert!(err.contains("entry 2 is empty"));
+    }
+
+    #[test]
+    fn test_duckdns_domains_suffix_stripped() {
+        let domains = DuckDnsDomains::parse("Home.DuckDNS.org., office").unwrap();
+        assert_eq!(domains.subdomains, vec!["home", "office"]);
+        assert_eq!(domains.warnings.len(), 1);
+        assert!(domains.warnings[0].contains(r#""Home.DuckDNS.org." is taken as "home""#));
+        assert_eq!(domains.full_names(), "home.duckdns.org, office.duckdns.org");
+    }
+
+    #[tokio::test]
+    async fn test_duckdns_malformed_domains_fail_validation() {
+        let provider = DuckDnsProvider::new("home,,office".to_string(), "token".to_string());
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("entry 2 is empty"));
+        assert!(provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duckdns_sends_cleaned_domains() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/update"))
+            .and(query_param("domains", "home,office"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = DuckDnsProvider::with_base_url(
+            " Home.duckdns.org, office ".to_string(),
+            "token".to_string(),
+            mock_server.uri(),
+        );
+        assert_eq!(provider.warnings().await.len(), 1);
+        assert_eq!(provider.domain(), "home.duckdns.org");
+
+        let result = provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(result.domain, "home.duckdns.org, office.duckdns.org");
+    }
+
+    #[tokio::test]
+    async fn test_duckdns_probe_write_unsupported() {
+        // No request is made: without reading the record, any write may
+        // change it
+        let provider = DuckDnsProvider::with_base_url(
+            "home".to_string(),
+            "token".to_string(),
+            "http://127.0.0.1:9".to_string(),
+        );
+        let WriteProbe::Unsupported(message) = provider.probe_write().await.unwrap() else {
+            panic!("record written");
+        };
+        assert!(message.contains("can't report the current address"));
+    }
 }
 
 #[cfg(test)]
 mod cloudflare_tests {
-    use crate::providers::{CloudflareProvider, DdnsProvider};
+    use crate::error::DdnsError;
+    use crate::providers::{
+        CloudflareProvider, DdnsProvider, RecordType, UpdateErrorKind, WriteProbe,
+    };
+    use serde_json::json;
     use std::net::IpAddr;
-    use wiremock::matchers::{header, method, path_regex};
+    use std::time::Duration;
+    use wiremock::matchers::{
+        body_json, body_partial_json, header, method, path, path_regex, query_param,
+    };
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
+    /// Answer lookups of the `record_type` record of vpn.example.com with
+    /// `records`.
+    async fn mock_records(server: &MockServer, record_type: &str, records: serde_json::Value) {
+        mock_named_records(server, "vpn.example.com", record_type, records).await;
+    }
+
+    /// Answer lookups of the `record_type` record of `name` with `records`.
+    async fn mock_named_records(
+        server: &MockServer,
+        name: &str,
+        record_type: &str,
+        records: serde_json::Value,
+    ) {
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone-123/dns_records"))
+            .and(query_param("name", name))
+            .and(query_param("type", record_type))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": records,
+                "errors": []
+            })))
+            .mount(server)
+            .await;
+    }
+
+    fn dual_stack(server: &MockServer) -> CloudflareProvider {
+        CloudflareProvider::with_base_url(
+            "test-token".to_string(),
+            "zone-123".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            None,
+            server.uri(),
+        )
+    }
+
     #[tokio::test]
     async fn test_cloudflare_update_success() {
         let mock_server = MockServer::start().await;
@@ -185,12 +427,13 @@ mod cloudflare_tests {
         let patch_response =
             r#"{"success":true,"result":{"id":"record-123","content":"2.2.2.2"},"errors":[]}"#;
 
-        // Mock GET to find record ID
+        // Mock GET to find record ID, reused for the update
         Mock::given(method("GET"))
             .and(path_regex(r"/client/v4/zones/.*/dns_records.*"))
+            .and(query_param("type", "A"))
             .and(header("Authorization", "Bearer test-token"))
             .respond_with(ResponseTemplate::new(200).set_body_string(get_response))
-            .expect(2)
+            .expect(1)
             .mount(&mock_server)
             .await;
 
@@ -205,8 +448,8 @@ mod cloudflare_tests {
         let provider = CloudflareProvider::with_base_url(
             "test-token".to_string(),
             "zone-123".to_string(),
-            "vpn.example.com".to_string(),
-            false,
+            "vpn.example.com".parse().unwrap(),
+            None,
             mock_server.uri(),
         );
 
@@ -217,6 +460,130 @@ mod cloudflare_tests {
         assert_eq!(result.ip, Some(ip));
     }
 
+    #[tokio::test]
+    async fn test_cloudflare_update_sets_ttl() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":[{"id":"record-123","content":"1.1.1.1"}],"errors":[]}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("PATCH"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records/.*"))
+            .and(body_partial_json(
+                json!({ "content": "2.2.2.2", "ttl": 300 }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":{"id":"record-123","content":"2.2.2.2"},"errors":[]}"#,
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = CloudflareProvider::with_base_url(
+            "test-token".to_string(),
+            "zone-123".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            None,
+            mock_server.uri(),
+        )
+        .with_ttl(Some(300));
+
+        let result = provider
+            .update_ip("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success);
+    }
+
+    /// Mount a proxied record with a comment and tags, and a PATCH expecting
+    /// `body`.
+    async fn mount_proxied_record(server: &MockServer, body: serde_json::Value) {
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": [{
+                    "id": "record-123",
+                    "content": "1.1.1.1",
+                    "proxied": true,
+                    "comment": "home router",
+                    "tags": ["team:ops"]
+                }],
+                "errors": []
+            })))
+            .mount(server)
+            .await;
+
+        Mock::given(method("PATCH"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records/record-123"))
+            .and(body_partial_json(body))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":{"id":"record-123","content":"2.2.2.2"},"errors":[]}"#,
+            ))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_update_keeps_proxied_and_comment() {
+        let mock_server = MockServer::start().await;
+        mount_proxied_record(
+            &mock_server,
+            json!({
+                "content": "2.2.2.2",
+                "proxied": true,
+                "comment": "home router",
+                "tags": ["team:ops"]
+            }),
+        )
+        .await;
+
+        // Without `proxied` in the config the record's setting is kept
+        let provider = CloudflareProvider::with_base_url(
+            "test-token".to_string(),
+            "zone-123".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            None,
+            mock_server.uri(),
+        );
+
+        let result = provider
+            .update_ip("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success);
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_update_configured_proxied() {
+        let mock_server = MockServer::start().await;
+        mount_proxied_record(
+            &mock_server,
+            json!({ "content": "2.2.2.2", "proxied": false, "comment": "home router" }),
+        )
+        .await;
+
+        let provider = CloudflareProvider::with_base_url(
+            "test-token".to_string(),
+            "zone-123".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            Some(false),
+            mock_server.uri(),
+        );
+
+        let result = provider
+            .update_ip("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success);
+    }
+
     #[tokio::test]
     async fn test_cloudflare_record_not_found() {
         let mock_server = MockServer::start().await;
@@ -233,15 +600,17 @@ mod cloudflare_tests {
         let provider = CloudflareProvider::with_base_url(
             "test-token".to_string(),
             "zone-123".to_string(),
-            "nonexistent.example.com".to_string(),
-            false,
+            "nonexistent.example.com".parse().unwrap(),
+            None,
             mock_server.uri(),
         );
 
         let ip: IpAddr = "2.2.2.2".parse().unwrap();
-        let result = provider.update_ip(ip).await;
+        let result = provider.update_ip(ip).await.unwrap();
 
-        assert!(result.is_err());
+        assert!(!result.success);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        assert!(provider.validate().await.is_err());
     }
 
     #[tokio::test]
@@ -259,90 +628,2176 @@ mod cloudflare_tests {
         let provider = CloudflareProvider::with_base_url(
             "bad-token".to_string(),
             "zone-123".to_string(),
-            "vpn.example.com".to_string(),
-            false,
+            "vpn.example.com".parse().unwrap(),
+            None,
             mock_server.uri(),
         );
 
         let ip: IpAddr = "2.2.2.2".parse().unwrap();
-        let result = provider.update_ip(ip).await;
+        let result = provider.update_ip(ip).await.unwrap();
 
-        assert!(result.is_err());
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("Invalid API token"));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+        assert!(provider.validate().await.is_err());
     }
-}
-
-#[cfg(test)]
-mod godaddy_tests {
-    use crate::providers::{DdnsProvider, GoDaddyProvider};
-    use std::net::IpAddr;
-    use wiremock::matchers::{header, method, path_regex};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
-    async fn test_godaddy_update_success() {
+    async fn test_cloudflare_error_kinds() {
         let mock_server = MockServer::start().await;
 
-        // Mock GET current IP
         Mock::given(method("GET"))
-            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
-            .and(header("Authorization", "sso-key api-key:api-secret"))
+            .and(path_regex(r"/client/v4/zones/auth/dns_records.*"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(
+                r#"{"success":false,"result":null,"errors":[{"code":10000,"message":"Authentication error"}]}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/limited/dns_records.*"))
             .respond_with(
-                ResponseTemplate::new(200).set_body_json(serde_json::json!([{"data": "1.1.1.1"}])),
+                ResponseTemplate::new(429)
+                    .insert_header("Retry-After", "30")
+                    .set_body_string("Too Many Requests"),
             )
             .mount(&mock_server)
             .await;
-
-        // Mock PUT update
-        Mock::given(method("PUT"))
-            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
-            .and(header("Authorization", "sso-key api-key:api-secret"))
-            .respond_with(ResponseTemplate::new(200))
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/(down|rejected)/dns_records.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":[{"id":"record-123","content":"1.1.1.1"}],"errors":[]}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path_regex(r"/client/v4/zones/down/dns_records/.*"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path_regex(r"/client/v4/zones/rejected/dns_records/.*"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"success":false,"result":null,"errors":[{"code":9005,"message":"Content for A record is invalid"}]}"#,
+            ))
             .mount(&mock_server)
             .await;
 
-        let provider = GoDaddyProvider::with_base_url(
-            "api-key".to_string(),
-            "api-secret".to_string(),
-            "example.com".to_string(),
-            "vpn".to_string(),
-            600,
-            mock_server.uri(),
+        let update = |zone: &str| {
+            CloudflareProvider::with_base_url(
+                "token".to_string(),
+                zone.to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                mock_server.uri(),
+            )
+        };
+        let ip: IpAddr = "2.2.2.2".parse().unwrap();
+
+        let result = update("auth").update_ip(ip).await.unwrap();
+        assert_eq!(result.error.as_deref(), Some("Authentication error"));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+
+        let result = update("limited").update_ip(ip).await.unwrap();
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(Duration::from_secs(30))
+            })
         );
 
-        let ip: IpAddr = "3.3.3.3".parse().unwrap();
-        let result = provider.update_ip(ip).await.unwrap();
+        let result = update("rejected").update_ip(ip).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
 
-        assert!(result.success);
-        assert_eq!(result.ip, Some(ip));
+        // Server errors are reported like unreachable servers
+        let err = update("down").update_ip(ip).await.unwrap_err();
+        assert!(matches!(err, DdnsError::Network(_)));
+        assert_eq!(UpdateErrorKind::for_error(&err), UpdateErrorKind::Network);
     }
 
     #[tokio::test]
-    async fn test_godaddy_domain_format() {
-        let provider = GoDaddyProvider::new(
-            "key".to_string(),
-            "secret".to_string(),
-            "example.com".to_string(),
-            "vpn".to_string(),
-            600,
+    async fn test_cloudflare_dual_stack() {
+        let mock_server = MockServer::start().await;
+        mock_records(
+            &mock_server,
+            "A",
+            json!([{"id": "record-a", "content": "1.1.1.1"}]),
+        )
+        .await;
+        mock_records(
+            &mock_server,
+            "AAAA",
+            json!([{"id": "record-aaaa", "content": "2001:db8::1"}]),
+        )
+        .await;
+
+        // Each family is written to its own record
+        for (id, content) in [("record-a", "2.2.2.2"), ("record-aaaa", "2001:db8::2")] {
+            Mock::given(method("PATCH"))
+                .and(path(format!(
+                    "/client/v4/zones/zone-123/dns_records/{}",
+                    id
+                )))
+                .and(body_partial_json(json!({ "content": content })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "success": true,
+                    "result": {"id": id, "content": content},
+                    "errors": []
+                })))
+                .expect(2)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let provider = dual_stack(&mock_server);
+        assert_eq!(
+            provider.get_record_ip(RecordType::Aaaa).await.unwrap(),
+            Some("2001:db8::1".parse().unwrap())
         );
-        assert_eq!(provider.domain(), "vpn.example.com");
+        // Then the records as written are remembered
+        for previous in [["2001:db8::1", "1.1.1.1"], ["2001:db8::2", "2.2.2.2"]] {
+            let result = provider
+                .update_ip("2001:db8::2".parse().unwrap())
+                .await
+                .unwrap();
+            assert!(result.success, "{:?}", result.error);
+            assert_eq!(result.record_type, RecordType::Aaaa);
+            assert_eq!(result.previous_ip, Some(previous[0].parse().unwrap()));
+
+            let result = provider
+                .update_ip("2.2.2.2".parse().unwrap())
+                .await
+                .unwrap();
+            assert!(result.success, "{:?}", result.error);
+            assert_eq!(result.record_type, RecordType::A);
+            assert_eq!(result.previous_ip, Some(previous[1].parse().unwrap()));
+        }
     }
 
     #[tokio::test]
-    async fn test_godaddy_root_domain() {
-        let provider = GoDaddyProvider::new(
-            "key".to_string(),
-            "secret".to_string(),
-            "example.com".to_string(),
-            "@".to_string(),
-            600,
+    async fn test_cloudflare_missing_aaaa_record() {
+        let mock_server = MockServer::start().await;
+        mock_records(
+            &mock_server,
+            "A",
+            json!([{"id": "record-a", "content": "1.1.1.1"}]),
+        )
+        .await;
+        mock_records(&mock_server, "AAAA", json!([])).await;
+
+        // The A record is never touched by an IPv6 update
+        Mock::given(method("PATCH"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/client/v4/zones/zone-123/dns_records"))
+            .and(body_partial_json(json!({
+                "type": "AAAA",
+                "name": "vpn.example.com",
+                "content": "2001:db8::2"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": {"id": "record-aaaa", "content": "2001:db8::2"},
+                "errors": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let ip: IpAddr = "2001:db8::2".parse().unwrap();
+        let provider = dual_stack(&mock_server);
+        assert_eq!(
+            provider.get_record_ip(RecordType::Aaaa).await.unwrap(),
+            None
         );
-        assert_eq!(provider.domain(), "example.com");
+        assert_eq!(
+            provider.get_current_ip().await.unwrap(),
+            Some("1.1.1.1".parse().unwrap())
+        );
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("DNS record vpn.example.com (AAAA) not found")
+        );
+
+        let result = dual_stack(&mock_server)
+            .with_create_if_missing(true)
+            .update_ip(ip)
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.previous_ip, None);
     }
-}
 
-#[cfg(test)]
-mod env_resolution_tests {
+    #[tokio::test]
+    async fn test_cloudflare_cname_record() {
+        let mock_server = MockServer::start().await;
+        mock_records(&mock_server, "A", json!([])).await;
+        mock_records(
+            &mock_server,
+            "CNAME",
+            json!([{"id": "record-cname", "type": "CNAME", "content": "home.example.net"}]),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let expected = "record vpn.example.com is a CNAME to home.example.net; \
+                        DDNS requires an A/AAAA record";
+        let provider = dual_stack(&mock_server);
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains(expected), "{}", err);
+
+        // Not even created when allowed to
+        let result = dual_stack(&mock_server)
+            .with_create_if_missing(true)
+            .update_ip("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some(expected));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_apex_flattening() {
+        let mock_server = MockServer::start().await;
+        let respond = |result: serde_json::Value| {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": result,
+                "errors": []
+            }))
+        };
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone-123"))
+            .respond_with(respond(json!({"id": "zone-123", "name": "example.com"})))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone-123/settings/cname_flattening"))
+            .respond_with(respond(
+                json!({"id": "cname_flattening", "value": "flatten_all"}),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let provider = |name: &str| {
+            CloudflareProvider::with_base_url(
+                "test-token".to_string(),
+                "zone-123".to_string(),
+                name.parse().unwrap(),
+                None,
+                mock_server.uri(),
+            )
+        };
+        let warnings = provider("example.com").warnings().await;
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].contains("flattens every CNAME"),
+            "{}",
+            warnings[0]
+        );
+
+        // Only the apex is concerned
+        assert!(provider("vpn.example.com").warnings().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_cached_record() {
+        let mock_server = MockServer::start().await;
+        let respond = |success: bool, result: serde_json::Value| {
+            ResponseTemplate::new(if success { 200 } else { 404 }).set_body_json(json!({
+                "success": success,
+                "result": result,
+                "errors": if success {
+                    json!([])
+                } else {
+                    json!([{"code": 81044, "message": "Record does not exist."}])
+                }
+            }))
+        };
+        // The record is replaced after the second update
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone-123/dns_records"))
+            .respond_with(respond(
+                true,
+                json!([{"id": "record-1", "content": "1.1.1.1"}]),
+            ))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone-123/dns_records"))
+            .respond_with(respond(
+                true,
+                json!([{"id": "record-2", "content": "1.1.1.1"}]),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/client/v4/zones/zone-123/dns_records/record-1"))
+            .respond_with(respond(
+                true,
+                json!({"id": "record-1", "content": "2.2.2.2"}),
+            ))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/client/v4/zones/zone-123/dns_records/record-1"))
+            .respond_with(respond(false, serde_json::Value::Null))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/client/v4/zones/zone-123/dns_records/record-2"))
+            .respond_with(respond(
+                true,
+                json!({"id": "record-2", "content": "2.2.2.2"}),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Looked up once, then patched by its remembered ID, until gone
+        let provider = dual_stack(&mock_server);
+        for _ in 0..3 {
+            let result = provider
+                .update_ip("2.2.2.2".parse().unwrap())
+                .await
+                .unwrap();
+            assert!(result.success, "{:?}", result.error);
+        }
+    }
+
+    /// vpn.example.com plus two additional records, one of them missing.
+    async fn multi_record(server: &MockServer) -> CloudflareProvider {
+        mock_records(
+            server,
+            "A",
+            json!([{"id": "rec-vpn", "content": "1.1.1.1"}]),
+        )
+        .await;
+        mock_named_records(
+            server,
+            "home.example.com",
+            "A",
+            json!([{"id": "rec-home", "content": "1.1.1.1"}]),
+        )
+        .await;
+        mock_named_records(server, "nas.example.com", "A", json!([])).await;
+        mock_named_records(server, "nas.example.com", "CNAME", json!([])).await;
+        dual_stack(server).with_additional_records(vec![
+            "home.example.com".parse().unwrap(),
+            "nas.example.com".parse().unwrap(),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_batch_update() {
+        let mock_server = MockServer::start().await;
+        let provider = multi_record(&mock_server)
+            .await
+            .with_create_if_missing(true);
+
+        Mock::given(method("POST"))
+            .and(path("/client/v4/zones/zone-123/dns_records/batch"))
+            .and(header("Authorization", "Bearer test-token"))
+            .and(body_partial_json(json!({
+                "patches": [
+                    {"id": "rec-vpn", "name": "vpn.example.com", "content": "2.2.2.2"},
+                    {"id": "rec-home", "name": "home.example.com", "content": "2.2.2.2"}
+                ],
+                "posts": [{"type": "A", "name": "nas.example.com", "content": "2.2.2.2"}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": {
+                    "patches": [
+                        {"id": "rec-vpn", "content": "2.2.2.2"},
+                        {"id": "rec-home", "content": "2.2.2.2"}
+                    ],
+                    "posts": [{"id": "rec-nas", "content": "2.2.2.2"}]
+                },
+                "errors": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // The created record is patched from then on
+        Mock::given(method("POST"))
+            .and(path("/client/v4/zones/zone-123/dns_records/batch"))
+            .and(body_partial_json(json!({
+                "patches": [{"id": "rec-vpn"}, {"id": "rec-home"}, {"id": "rec-nas"}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": {
+                    "patches": [
+                        {"id": "rec-vpn", "content": "2.2.2.2"},
+                        {"id": "rec-home", "content": "2.2.2.2"},
+                        {"id": "rec-nas", "content": "2.2.2.2"}
+                    ]
+                },
+                "errors": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // Nothing goes through the per-record endpoints
+        Mock::given(method("PATCH"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let ip: IpAddr = "2.2.2.2".parse().unwrap();
+        let results = provider.update_records(ip).await.unwrap();
+        let domains: Vec<_> = results.iter().map(|r| r.domain.as_str()).collect();
+        assert_eq!(
+            domains,
+            ["vpn.example.com", "home.example.com", "nas.example.com"]
+        );
+        assert!(results.iter().all(|r| r.success && r.ip == Some(ip)));
+        assert_eq!(results[0].previous_ip, Some("1.1.1.1".parse().unwrap()));
+        assert_eq!(results[2].previous_ip, None);
+
+        // Reported as one update of every record
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success);
+        assert_eq!(
+            result.domain,
+            "vpn.example.com, home.example.com, nas.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_batch_failure() {
+        let mock_server = MockServer::start().await;
+        let provider = multi_record(&mock_server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/client/v4/zones/zone-123/dns_records/batch"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "success": false,
+                "result": null,
+                "errors": [{"code": 9005, "message": "Content for A record is invalid"}]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let results = provider
+            .update_records("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        // The batch fails as a whole; the missing record on its own
+        assert!(results.iter().all(|r| !r.success));
+        assert!(results[0].error.as_deref().unwrap().contains("is invalid"));
+        assert!(results[1].error.as_deref().unwrap().contains("is invalid"));
+        assert_eq!(results[2].error_kind, Some(UpdateErrorKind::RecordNotFound));
+
+        let result = provider
+            .update_ip("2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert!(
+            result
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("nas.example.com: "),
+            "{:?}",
+            result.error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_batch_fallback() {
+        let mock_server = MockServer::start().await;
+        let provider = multi_record(&mock_server).await;
+
+        // Older API without the batch endpoint
+        Mock::given(method("POST"))
+            .and(path("/client/v4/zones/zone-123/dns_records/batch"))
+            .respond_with(ResponseTemplate::new(405))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        for id in ["rec-vpn", "rec-home"] {
+            Mock::given(method("PATCH"))
+                .and(path(format!(
+                    "/client/v4/zones/zone-123/dns_records/{}",
+                    id
+                )))
+                .and(body_partial_json(json!({"content": "2.2.2.2"})))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "success": true,
+                    "result": {"id": id, "content": "2.2.2.2"},
+                    "errors": []
+                })))
+                .expect(2)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let ip: IpAddr = "2.2.2.2".parse().unwrap();
+        for _ in 0..2 {
+            // The batch endpoint is tried only once
+            let results = provider.update_records(ip).await.unwrap();
+            assert!(results[0].success && results[1].success);
+            assert_eq!(results[0].domain, "vpn.example.com");
+            assert_eq!(results[1].domain, "home.example.com");
+            assert_eq!(results[2].error_kind, Some(UpdateErrorKind::RecordNotFound));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_probe_write() {
+        let mock_server = MockServer::start().await;
+        mock_records(
+            &mock_server,
+            "A",
+            json!([{"id": "record-123", "content": "1.1.1.1", "type": "A"}]),
+        )
+        .await;
+        // Only the content is sent, so proxying and TTL stay as they are
+        Mock::given(method("PATCH"))
+            .and(path("/client/v4/zones/zone-123/dns_records/record-123"))
+            .and(body_json(json!({"content": "1.1.1.1"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": {"id": "record-123", "content": "1.1.1.1", "type": "A"},
+                "errors": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = dual_stack(&mock_server);
+        assert_eq!(
+            provider.probe_write().await.unwrap(),
+            WriteProbe::Written("wrote 1.1.1.1 (A) back unchanged".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_probe_write_rejected() {
+        let mock_server = MockServer::start().await;
+        mock_records(
+            &mock_server,
+            "A",
+            json!([{"id": "record-123", "content": "1.1.1.1", "type": "A"}]),
+        )
+        .await;
+        Mock::given(method("PATCH"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "success": false,
+                "result": null,
+                "errors": [{"code": 10000, "message": "Authentication error"}]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let error = dual_stack(&mock_server).probe_write().await.unwrap_err();
+        assert!(
+            error.to_string().contains("Authentication error"),
+            "{}",
+            error
+        );
+
+        // Nothing is created to probe a missing record
+        let mock_server = MockServer::start().await;
+        mock_records(&mock_server, "A", json!([])).await;
+        mock_records(&mock_server, "AAAA", json!([])).await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let provider = dual_stack(&mock_server).with_create_if_missing(true);
+        assert!(matches!(
+            provider.probe_write().await.unwrap(),
+            WriteProbe::Unsupported(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_current_record_ttl() {
+        let mock_server = MockServer::start().await;
+        // TTL 1 is Cloudflare's "automatic", i.e. 5 minutes
+        mock_records(
+            &mock_server,
+            "A",
+            json!([{"id": "record-123", "content": "1.1.1.1", "type": "A", "ttl": 1}]),
+        )
+        .await;
+
+        let record = dual_stack(&mock_server)
+            .get_current_record()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+        assert_eq!(record.record_type, RecordType::A);
+        assert_eq!(record.ttl, Some(300));
+    }
+}
+
+#[cfg(test)]
+mod godaddy_tests {
+    use crate::providers::{DdnsProvider, GoDaddyProvider, UpdateErrorKind, WriteProbe};
+    use std::net::IpAddr;
+    use wiremock::matchers::{body_json, header, method, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_godaddy_update_success() {
+        let mock_server = MockServer::start().await;
+
+        // Mock GET current IP, only needed before the first update
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .and(header("Authorization", "sso-key api-key:api-secret"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!([{"data": "1.1.1.1"}])),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Mock PUT update
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .and(header("Authorization", "sso-key api-key:api-secret"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let provider = GoDaddyProvider::with_base_url(
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+            mock_server.uri(),
+        );
+
+        let ip: IpAddr = "3.3.3.3".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.ip, Some(ip));
+        assert_eq!(result.previous_ip, Some("1.1.1.1".parse().unwrap()));
+
+        let ip: IpAddr = "4.4.4.4".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.previous_ip, Some("3.3.3.3".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_domain_format() {
+        let provider = GoDaddyProvider::new(
+            "key".to_string(),
+            "secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+        );
+        assert_eq!(provider.domain(), "vpn.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_root_domain() {
+        let provider = GoDaddyProvider::new(
+            "key".to_string(),
+            "secret".to_string(),
+            "example.com".parse().unwrap(),
+            "@".parse().unwrap(),
+            600,
+        );
+        assert_eq!(provider.domain(), "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_error_kinds() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/example.com/records/A/.*"))
+            .and(header("Authorization", "sso-key bad:secret"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "code": "UNABLE_TO_AUTHENTICATE",
+                "message": "Unauthorized : Could not authenticate API key/secret"
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/example.com/records/A/.*"))
+            .and(header("Authorization", "sso-key busy:secret"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+                "code": "TOO_MANY_REQUESTS",
+                "message": "Too many requests",
+                "retryAfterSec": 42
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/other.example/records/A/.*"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "code": "UNKNOWN_DOMAIN",
+                "message": "The given domain is not registered, or does not have a zone file"
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/example.com/records/A/.*"))
+            .and(header("Authorization", "sso-key invalid:secret"))
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "code": "INVALID_BODY",
+                "message": "Request body doesn't fulfill schema"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let update = |key: &str, domain: &str| {
+            GoDaddyProvider::with_base_url(
+                key.to_string(),
+                "secret".to_string(),
+                domain.parse().unwrap(),
+                "vpn".parse().unwrap(),
+                600,
+                mock_server.uri(),
+            )
+        };
+        let ip: IpAddr = "3.3.3.3".parse().unwrap();
+
+        let result = update("bad", "example.com").update_ip(ip).await.unwrap();
+        assert!(result.error.unwrap().contains("Could not authenticate"));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+
+        // The delay comes from the body
+        let result = update("busy", "example.com").update_ip(ip).await.unwrap();
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(42))
+            })
+        );
+
+        let result = update("key", "other.example").update_ip(ip).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+
+        let result = update("invalid", "example.com")
+            .update_ip(ip)
+            .await
+            .unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_error_shapes() {
+        let mock_server = MockServer::start().await;
+        let respond = |key: &str, response: ResponseTemplate| {
+            Mock::given(path_regex(r"/v1/domains/example.com/records/A/vpn"))
+                .and(header(
+                    "Authorization",
+                    format!("sso-key {}:secret", key).as_str(),
+                ))
+                .respond_with(response)
+        };
+
+        respond(
+            "restricted",
+            ResponseTemplate::new(403).set_body_json(serde_json::json!({
+                "code": "ACCESS_DENIED",
+                "message": "Authenticated user is not allowed access"
+            })),
+        )
+        .mount(&mock_server)
+        .await;
+        respond(
+            "busy",
+            ResponseTemplate::new(429)
+                .insert_header("Retry-After", "30")
+                .set_body_json(serde_json::json!({
+                    "code": "TOO_MANY_REQUESTS",
+                    "message": "Too many requests"
+                })),
+        )
+        .mount(&mock_server)
+        .await;
+        respond(
+            "throttled",
+            ResponseTemplate::new(429)
+                .insert_header("Retry-After", "15")
+                .set_body_string("Too Many Requests"),
+        )
+        .mount(&mock_server)
+        .await;
+        respond(
+            "invalid",
+            ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "code": "INVALID_BODY",
+                "message": "Request body doesn't fulfill schema, see details in `fields`",
+                "fields": [
+                    {"code": "UNEXPECTED_TYPE", "message": "is not a number", "path": "records[0].ttl"},
+                    {"code": "MISSING", "path": "records[0].data"}
+                ]
+            })),
+        )
+        .mount(&mock_server)
+        .await;
+        respond(
+            "terse",
+            ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "code": "DUPLICATE_RECORD"
+            })),
+        )
+        .mount(&mock_server)
+        .await;
+        respond(
+            "broken",
+            ResponseTemplate::new(500).set_body_string("<html>Internal Server Error</html>"),
+        )
+        .mount(&mock_server)
+        .await;
+
+        let provider = |key: &str| {
+            GoDaddyProvider::with_base_url(
+                key.to_string(),
+                "secret".to_string(),
+                "example.com".parse().unwrap(),
+                "vpn".parse().unwrap(),
+                600,
+                mock_server.uri(),
+            )
+        };
+        let ip: IpAddr = "3.3.3.3".parse().unwrap();
+
+        // Accounts without API access get an explanation, not a retry
+        let result = provider("restricted").update_ip(ip).await.unwrap();
+        let error = result.error.unwrap();
+        assert!(error.starts_with("GoDaddy denied API access to this account"));
+        assert!(error.ends_with("(Authenticated user is not allowed access)"));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+        let err = provider("restricted").validate().await.unwrap_err();
+        assert!(err.to_string().contains("GoDaddy denied API access"));
+        assert!(provider("restricted").get_current_ip().await.is_err());
+
+        // Without a delay in the body, Retry-After is honored
+        let result = provider("busy").update_ip(ip).await.unwrap();
+        assert_eq!(result.error.as_deref(), Some("Too many requests"));
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(30))
+            })
+        );
+        let result = provider("throttled").update_ip(ip).await.unwrap();
+        assert_eq!(
+            result.error.as_deref(),
+            Some("GoDaddy returned HTTP 429 Too Many Requests")
+        );
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(15))
+            })
+        );
+
+        let result = provider("invalid").update_ip(ip).await.unwrap();
+        assert_eq!(
+            result.error.as_deref(),
+            Some(
+                "Request body doesn't fulfill schema, see details in `fields` \
+                 (records[0].ttl: is not a number; records[0].data)"
+            )
+        );
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+
+        let result = provider("terse").update_ip(ip).await.unwrap();
+        assert_eq!(result.error.as_deref(), Some("DUPLICATE_RECORD"));
+
+        let result = provider("broken").update_ip(ip).await.unwrap();
+        assert_eq!(
+            result.error.as_deref(),
+            Some("GoDaddy returned HTTP 500 Internal Server Error")
+        );
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Network));
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_cname_record() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/example.com/records/A/vpn"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/example.com/records/CNAME/vpn"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!([{"type": "CNAME", "data": "home.example.net"}]),
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let provider = GoDaddyProvider::with_base_url(
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+            mock_server.uri(),
+        );
+
+        let expected = "record vpn.example.com is a CNAME to home.example.net; \
+                        DDNS requires an A/AAAA record";
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains(expected), "{}", err);
+
+        let result = provider
+            .update_ip("3.3.3.3".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some(expected));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_probe_write() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([{"data": "1.1.1.1", "ttl": 3600}])),
+            )
+            .mount(&mock_server)
+            .await;
+        // Written back with its own TTL rather than the configured one
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .and(body_json(
+                serde_json::json!([{"data": "1.1.1.1", "ttl": 3600}]),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = GoDaddyProvider::with_base_url(
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+            mock_server.uri(),
+        );
+        assert_eq!(
+            provider.probe_write().await.unwrap(),
+            WriteProbe::Written("wrote 1.1.1.1 (A) back unchanged".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_probe_write_several_records() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"data": "1.1.1.1"},
+                {"data": "2.2.2.2"}
+            ])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let provider = GoDaddyProvider::with_base_url(
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+            mock_server.uri(),
+        );
+        let WriteProbe::Unsupported(message) = provider.probe_write().await.unwrap() else {
+            panic!("records written");
+        };
+        assert!(message.contains("2 A records"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_current_record_ttl() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/v1/domains/.*/records/A/.*"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([{"data": "1.1.1.1", "ttl": 3600}])),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let provider = GoDaddyProvider::with_base_url(
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            "example.com".parse().unwrap(),
+            "vpn".parse().unwrap(),
+            600,
+            mock_server.uri(),
+        );
+        let record = provider.get_current_record().await.unwrap().unwrap();
+        assert_eq!(record.ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+        assert_eq!(record.ttl, Some(3600));
+    }
+}
+
+#[cfg(test)]
+mod vultr_tests {
+    use crate::providers::{DdnsProvider, RecordType, UpdateErrorKind, VultrProvider, WriteProbe};
+    use serde_json::json;
+    use std::net::IpAddr;
+    use wiremock::matchers::{
+        body_json, header, method, path, query_param, query_param_is_missing,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const RECORDS: &str = "/v2/domains/example.com/records";
+
+    fn vultr(server: &MockServer, name: &str, ttl: Option<u32>) -> VultrProvider {
+        VultrProvider::with_base_url(
+            "api-key".to_string(),
+            "example.com".parse().unwrap(),
+            name.parse().unwrap(),
+            ttl,
+            server.uri(),
+        )
+    }
+
+    /// Answer the first page of records with `records`, pointing at a
+    /// second page if `next` isn't empty.
+    async fn mock_page(
+        server: &MockServer,
+        cursor: Option<&str>,
+        records: serde_json::Value,
+        next: &str,
+    ) {
+        let mock = Mock::given(method("GET"))
+            .and(path(RECORDS))
+            .and(header("Authorization", "Bearer api-key"));
+        let mock = match cursor {
+            Some(cursor) => mock.and(query_param("cursor", cursor)),
+            None => mock.and(query_param_is_missing("cursor")),
+        };
+        mock.respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "records": records,
+            "meta": {"total": 3, "links": {"next": next, "prev": ""}}
+        })))
+        .mount(server)
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_vultr_update_success() {
+        let mock_server = MockServer::start().await;
+        mock_page(
+            &mock_server,
+            None,
+            json!([
+                {"id": "apex", "type": "A", "name": "", "data": "9.9.9.9", "ttl": 300},
+                {"id": "vpn-a", "type": "A", "name": "vpn", "data": "1.1.1.1", "ttl": 300}
+            ]),
+            "",
+        )
+        .await;
+
+        Mock::given(method("PATCH"))
+            .and(path(format!("{}/vpn-a", RECORDS)))
+            .and(header("Authorization", "Bearer api-key"))
+            .and(body_json(json!({"data": "3.3.3.3", "ttl": 120})))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path(format!("{}/vpn-a", RECORDS)))
+            .and(body_json(json!({"data": "4.4.4.4", "ttl": 120})))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = vultr(&mock_server, "vpn", Some(120));
+        assert_eq!(provider.domain(), "vpn.example.com");
+        let record = provider.get_current_record().await.unwrap().unwrap();
+        assert_eq!(record.ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+        assert_eq!(record.ttl, Some(300));
+
+        let ip: IpAddr = "3.3.3.3".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.ip, Some(ip));
+        assert_eq!(result.previous_ip, Some("1.1.1.1".parse().unwrap()));
+
+        // The record isn't looked up again
+        let result = provider
+            .update_ip("4.4.4.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(result.previous_ip, Some(ip));
+        let lists = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path() == RECORDS)
+            .count();
+        assert_eq!(lists, 1);
+
+        // The apex is the record without a name
+        let apex = vultr(&mock_server, "@", None);
+        assert_eq!(apex.domain(), "example.com");
+        assert_eq!(
+            apex.get_current_ip().await.unwrap(),
+            Some("9.9.9.9".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vultr_pagination() {
+        let mock_server = MockServer::start().await;
+        mock_page(
+            &mock_server,
+            None,
+            json!([{"id": "other", "type": "A", "name": "www", "data": "9.9.9.9"}]),
+            "bmV4dF9fMg==",
+        )
+        .await;
+        mock_page(
+            &mock_server,
+            Some("bmV4dF9fMg=="),
+            json!([
+                {"id": "vpn-a", "type": "A", "name": "vpn", "data": "1.1.1.1", "ttl": 300},
+                {"id": "vpn-aaaa", "type": "AAAA", "name": "vpn", "data": "2001:db8::1", "ttl": 300}
+            ]),
+            "",
+        )
+        .await;
+        Mock::given(method("PATCH"))
+            .and(path(format!("{}/vpn-aaaa", RECORDS)))
+            .and(body_json(json!({"data": "2001:db8::2"})))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = vultr(&mock_server, "vpn", None);
+        assert_eq!(
+            provider.get_record_ip(RecordType::Aaaa).await.unwrap(),
+            Some("2001:db8::1".parse().unwrap())
+        );
+
+        let result = provider
+            .update_ip("2001:db8::2".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.record_type, RecordType::Aaaa);
+        assert_eq!(result.previous_ip, Some("2001:db8::1".parse().unwrap()));
+
+        // A name the domain doesn't have
+        let missing = vultr(&mock_server, "nas", None);
+        let result = missing.update_ip("1.2.3.4".parse().unwrap()).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        assert!(result
+            .error
+            .unwrap()
+            .contains("nas.example.com has no A record"));
+    }
+
+    #[tokio::test]
+    async fn test_vultr_unauthorized() {
+        let mock_server = MockServer::start().await;
+        let unauthorized = ResponseTemplate::new(401)
+            .set_body_json(json!({"error": "Invalid API token.", "status": 401}));
+        Mock::given(method("GET"))
+            .and(path("/v2/account"))
+            .respond_with(unauthorized.clone())
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(RECORDS))
+            .respond_with(unauthorized)
+            .mount(&mock_server)
+            .await;
+
+        let provider = vultr(&mock_server, "vpn", None);
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("Invalid API token."), "{}", err);
+
+        let result = provider
+            .update_ip("3.3.3.3".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("Invalid API token."));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+    }
+
+    #[tokio::test]
+    async fn test_vultr_validate_and_probe_write() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v2/account"))
+            .and(header("Authorization", "Bearer api-key"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"account": {"name": "me"}})),
+            )
+            .mount(&mock_server)
+            .await;
+        mock_page(
+            &mock_server,
+            None,
+            json!([
+                {"id": "vpn", "type": "CNAME", "name": "vpn", "data": "home.example.net"},
+                {"id": "nas-a", "type": "A", "name": "nas", "data": "1.1.1.1", "ttl": 600}
+            ]),
+            "",
+        )
+        .await;
+        Mock::given(method("PATCH"))
+            .and(path(format!("{}/nas-a", RECORDS)))
+            .and(body_json(json!({"data": "1.1.1.1", "ttl": 600})))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let nas = vultr(&mock_server, "nas", Some(120));
+        nas.validate().await.unwrap();
+        assert_eq!(
+            nas.probe_write().await.unwrap(),
+            WriteProbe::Written("wrote 1.1.1.1 (A) back unchanged".to_string())
+        );
+
+        let cname = vultr(&mock_server, "vpn", None);
+        let result = cname.update_ip("3.3.3.3".parse().unwrap()).await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+        assert!(result
+            .error
+            .unwrap()
+            .contains("is a CNAME to home.example.net"));
+    }
+}
+
+#[cfg(test)]
+mod yandexcloud_tests {
+    use crate::providers::{
+        DdnsProvider, RecordType, UpdateErrorKind, YandexCloudAuth, YandexCloudDnsProvider,
+    };
+    use crate::secret::Secret;
+    use chrono::Utc;
+    use serde_json::json;
+    use std::net::IpAddr;
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const GET_RECORD_SET: &str = "/dns/v1/zones/zone-1:getRecordSet";
+    const UPDATE_RECORD_SETS: &str = "/dns/v1/zones/zone-1:updateRecordSets";
+
+    fn yandex(
+        server: &MockServer,
+        auth: YandexCloudAuth,
+        ttl: Option<u32>,
+    ) -> YandexCloudDnsProvider {
+        YandexCloudDnsProvider::with_base_url(
+            auth,
+            "zone-1".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            ttl,
+            server.uri(),
+        )
+    }
+
+    fn oauth() -> YandexCloudAuth {
+        YandexCloudAuth::OAuth(Secret::new("y0_oauth"))
+    }
+
+    /// Answer the OAuth token exchange with `iam_token`, valid for `minutes`,
+    /// at most `times` times.
+    async fn mock_exchange(server: &MockServer, iam_token: &str, minutes: i64, times: u64) {
+        Mock::given(method("POST"))
+            .and(path("/iam/v1/tokens"))
+            .and(body_json(json!({"yandexPassportOauthToken": "y0_oauth"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "iamToken": iam_token,
+                "expiresAt": Utc::now() + chrono::Duration::minutes(minutes)
+            })))
+            .up_to_n_times(times)
+            .expect(times)
+            .mount(server)
+            .await;
+    }
+
+    fn record_set(record_type: &str, data: &str, ttl: &str) -> serde_json::Value {
+        json!({"name": "vpn.example.com.", "type": record_type, "ttl": ttl, "data": [data]})
+    }
+
+    fn done() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({"id": "op-1", "done": true}))
+    }
+
+    #[tokio::test]
+    async fn test_yandexcloud_update_success() {
+        let mock_server = MockServer::start().await;
+        mock_exchange(&mock_server, "t1.first", 720, 1).await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(query_param("name", "vpn.example.com."))
+            .and(query_param("type", "A"))
+            .and(header("Authorization", "Bearer t1.first"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(record_set("A", "1.1.1.1", "300")),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // The existing set is deleted and replaced in one change, keeping
+        // its TTL
+        Mock::given(method("POST"))
+            .and(path(UPDATE_RECORD_SETS))
+            .and(header("Authorization", "Bearer t1.first"))
+            .and(body_json(json!({
+                "deletions": [record_set("A", "1.1.1.1", "300")],
+                "additions": [record_set("A", "3.3.3.3", "300")]
+            })))
+            .respond_with(done())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(UPDATE_RECORD_SETS))
+            .and(body_json(json!({
+                "deletions": [record_set("A", "3.3.3.3", "300")],
+                "additions": [record_set("A", "4.4.4.4", "300")]
+            })))
+            .respond_with(done())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = yandex(&mock_server, oauth(), None);
+        assert_eq!(provider.domain(), "vpn.example.com");
+        let ip: IpAddr = "3.3.3.3".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.ip, Some(ip));
+        assert_eq!(result.previous_ip, Some("1.1.1.1".parse().unwrap()));
+
+        // Neither the record set nor the IAM token is fetched again
+        let result = provider
+            .update_ip("4.4.4.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.previous_ip, Some(ip));
+    }
+
+    #[tokio::test]
+    async fn test_yandexcloud_expired_token_renewed() {
+        let mock_server = MockServer::start().await;
+        // The first token is revoked after one request, the second is
+        // about to expire, and the third lasts
+        mock_exchange(&mock_server, "t1.revoked", 720, 1).await;
+        mock_exchange(&mock_server, "t1.expiring", 1, 1).await;
+        mock_exchange(&mock_server, "t1.valid", 720, 1).await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(header("Authorization", "Bearer t1.revoked"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(record_set("A", "1.1.1.1", "300")),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(header("Authorization", "Bearer t1.revoked"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "code": 16, "message": "The token has expired", "details": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        for token in ["t1.expiring", "t1.valid"] {
+            Mock::given(method("GET"))
+                .and(path(GET_RECORD_SET))
+                .and(header(
+                    "Authorization",
+                    format!("Bearer {}", token).as_str(),
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(record_set("A", "2.2.2.2", "300")),
+                )
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let provider = yandex(&mock_server, oauth(), None);
+        assert_eq!(
+            provider.get_current_ip().await.unwrap(),
+            Some("1.1.1.1".parse().unwrap())
+        );
+        // Rejected with 401: exchanged again and retried
+        assert_eq!(
+            provider.get_current_ip().await.unwrap(),
+            Some("2.2.2.2".parse().unwrap())
+        );
+        // About to expire: exchanged again beforehand
+        assert_eq!(
+            provider.get_current_ip().await.unwrap(),
+            Some("2.2.2.2".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_yandexcloud_unauthorized() {
+        let mock_server = MockServer::start().await;
+        let unauthorized = ResponseTemplate::new(401).set_body_json(json!({
+            "code": 16, "message": "Unknown api key", "details": []
+        }));
+        Mock::given(method("GET"))
+            .and(path("/dns/v1/zones/zone-1"))
+            .and(header("Authorization", "Api-Key bad-key"))
+            .respond_with(unauthorized.clone())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .respond_with(unauthorized)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // An API key can't be renewed: the 401 is final
+        let provider = yandex(
+            &mock_server,
+            YandexCloudAuth::ApiKey(Secret::new("bad-key")),
+            None,
+        );
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("Unknown api key"), "{}", err);
+
+        let result = provider
+            .update_ip("3.3.3.3".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("Unknown api key"));
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+
+        // A rejected OAuth token fails the exchange
+        Mock::given(method("POST"))
+            .and(path("/iam/v1/tokens"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "code": 16, "message": "OAuth token is invalid or expired", "details": []
+            })))
+            .mount(&mock_server)
+            .await;
+        let provider = yandex(&mock_server, oauth(), None);
+        let result = provider
+            .update_ip("3.3.3.3".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("OAuth token exchange failed: OAuth token is invalid or expired")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_yandexcloud_missing_and_rejected() {
+        let mock_server = MockServer::start().await;
+        let not_found = ResponseTemplate::new(404).set_body_json(json!({
+            "code": 5, "message": "Record set not found", "details": []
+        }));
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(query_param("type", "A"))
+            .respond_with(not_found.clone())
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(query_param("type", "CNAME"))
+            .respond_with(not_found)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(GET_RECORD_SET))
+            .and(query_param("type", "AAAA"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(record_set(
+                "AAAA",
+                "2001:db8::1",
+                "60",
+            )))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(UPDATE_RECORD_SETS))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "op-1",
+                "done": true,
+                "error": {"code": 9, "message": "Record set to delete does not match"}
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let provider = yandex(
+            &mock_server,
+            YandexCloudAuth::IamToken(Secret::new("t1.static")),
+            Some(120),
+        );
+        assert_eq!(provider.get_current_ip().await.unwrap(), None);
+        let result = provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        assert!(result
+            .error
+            .unwrap()
+            .contains("vpn.example.com has no A record set"));
+
+        // The operation's error fails the update
+        let result = provider
+            .update_ip("2001:db8::2".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.record_type, RecordType::Aaaa);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Record set to delete does not match")
+        );
+        assert_eq!(result.previous_ip, Some("2001:db8::1".parse().unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod dynurl_tests {
+    use crate::providers::{DdnsProvider, DynUrlProvider, RecordType, UpdateErrorKind};
+    use std::net::IpAddr;
+    use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn dynurl(server: &MockServer) -> DynUrlProvider {
+        DynUrlProvider::new(
+            format!("{}/dynamicURL/?q=secret-token", server.uri()),
+            "vpn.example.com".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dynurl_without_ip() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/dynamicURL/"))
+            .and(query_param("q", "secret-token"))
+            .and(query_param_is_missing("ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = dynurl(&mock_server);
+        assert_eq!(provider.domain(), "vpn.example.com");
+        assert_eq!(provider.get_current_ip().await.unwrap(), None);
+        provider.validate().await.unwrap();
+
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.ip, Some(ip));
+        assert_eq!(result.domain, "vpn.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_dynurl_append_ip() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/dynamicURL/"))
+            .and(query_param("q", "secret-token"))
+            .and(query_param("myip", "2001:db8::1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = dynurl(&mock_server).with_ip_param(Some("myip".to_string()));
+        let result = provider
+            .update_ip("2001:db8::1".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.record_type, RecordType::Aaaa);
+    }
+
+    #[tokio::test]
+    async fn test_dynurl_success_contains() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("ip", "1.2.3.4"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("good 1.2.3.4\n"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("ip", "5.6.7.8"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Invalid request.\nTry again"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("ip", "9.9.9.9"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("good"))
+            .mount(&mock_server)
+            .await;
+
+        let provider = dynurl(&mock_server)
+            .with_ip_param(Some("ip".to_string()))
+            .with_success_contains(Some("good".to_string()));
+        let result = provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+
+        // A success status without the text fails, quoting the response
+        let result = provider
+            .update_ip("5.6.7.8".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.ip, None);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::ProviderRejected));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Response doesn't contain \"good\": Invalid request.")
+        );
+
+        // The text doesn't make up for a failed status
+        let result = provider
+            .update_ip("9.9.9.9".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Dynamic URL returned HTTP 403 Forbidden")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dynurl_errors_hide_url() {
+        let provider = DynUrlProvider::new(
+            "http://127.0.0.1:1/update?token=secret-token".to_string(),
+            "vpn.example.com".to_string(),
+        );
+        let err = provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(!err.contains("secret-token"), "{}", err);
+
+        let provider = DynUrlProvider::new(
+            "ftp://example.com/secret-token".to_string(),
+            "vpn.example.com".to_string(),
+        );
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("must be http or https"), "{}", err);
+        assert!(!err.contains("secret-token"), "{}", err);
+    }
+}
+
+#[cfg(test)]
+mod dnsomatic_tests {
+    use crate::providers::{DdnsProvider, DnsOMaticProvider, UpdateErrorKind};
+    use std::net::IpAddr;
+    use wiremock::matchers::{basic_auth, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn dnsomatic(server: &MockServer, hostname: &str) -> DnsOMaticProvider {
+        DnsOMaticProvider::with_base_url(
+            "user@example.com".to_string(),
+            "secret123".to_string(),
+            hostname.parse().unwrap(),
+            server.uri(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dnsomatic_update_success() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/nic/update"))
+            .and(basic_auth("user@example.com", "secret123"))
+            .and(query_param("hostname", "vpn.example.com"))
+            .and(query_param("myip", "1.2.3.4"))
+            .and(query_param("wildcard", "NOCHG"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("good 1.2.3.4"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = dnsomatic(&mock_server, "vpn.example.com");
+        assert_eq!(provider.get_current_ip().await.unwrap(), None);
+        provider.validate().await.unwrap();
+
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let result = provider.update_ip(ip).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.ip, Some(ip));
+        assert_eq!(result.domain, "vpn.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_dnsomatic_error_codes() {
+        let mock_server = MockServer::start().await;
+        for (ip, body) in [
+            ("1.1.1.1", "badauth"),
+            ("2.2.2.2", "nohost"),
+            ("3.3.3.3", "abuse"),
+        ] {
+            Mock::given(method("GET"))
+                .and(query_param("myip", ip))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let provider = dnsomatic(&mock_server, "vpn.example.com");
+        let update = |ip: &str| provider.update_ip(ip.parse().unwrap());
+        let result = update("1.1.1.1").await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("badauth (wrong username or password)")
+        );
+        let result = update("2.2.2.2").await.unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        let result = update("3.3.3.3").await.unwrap();
+        assert_eq!(
+            result.error_kind,
+            Some(UpdateErrorKind::RateLimited { retry_after: None })
+        );
+
+        let result = update("2001:db8::1").await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("IPv6 is not supported"));
+    }
+
+    #[tokio::test]
+    async fn test_dnsomatic_all_partial_failure() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("hostname", "all.dnsomatic.com"))
+            .and(query_param("myip", "1.2.3.4"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string("good 1.2.3.4\nnohost\nnochg 1.2.3.4\n"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("myip", "5.6.7.8"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string("good 5.6.7.8\r\nnochg 5.6.7.8\r\n"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let provider = dnsomatic(&mock_server, "all.dnsomatic.com");
+        let result = provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.ip, None);
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::RecordNotFound));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("1 of 3 services failed: service 2: nohost (hostname isn't a service of the account)")
+        );
+
+        let result = provider
+            .update_ip("5.6.7.8".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+    }
+
+    #[tokio::test]
+    async fn test_dnsomatic_http_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let result = dnsomatic(&mock_server, "vpn.example.com")
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.error_kind, Some(UpdateErrorKind::Auth));
+        assert_eq!(
+            result.error.as_deref(),
+            Some("DNS-O-Matic returned HTTP 401 Unauthorized")
+        );
+    }
+}
+
+#[cfg(test)]
+mod capabilities_tests {
+    use crate::config::ProviderConfig;
+    use crate::providers::{
+        CloudflareProvider, DdnsProvider, DnsOMaticProvider, DuckDnsProvider, DynUrlProvider,
+        GoDaddyProvider, NamecheapProvider, ProviderCapabilities, VultrProvider, YandexCloudAuth,
+        YandexCloudDnsProvider,
+    };
+    use serde_json::json;
+    use std::net::IpAddr;
+    use wiremock::matchers::{method, path_regex, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// TTL given to the providers that take one.
+    const TTL: u32 = 900;
+
+    /// Create a provider of type `kind` against `server`, set up to use
+    /// every capability its type declares.
+    fn provider(kind: &str, server: &MockServer) -> Box<dyn DdnsProvider> {
+        match kind {
+            "cloudflare" => Box::new(
+                CloudflareProvider::with_base_url(
+                    "test-token".to_string(),
+                    "zone-123".to_string(),
+                    "vpn.example.com".parse().unwrap(),
+                    None,
+                    server.uri(),
+                )
+                .with_ttl(Some(TTL))
+                .with_create_if_missing(true),
+            ),
+            "namecheap" => Box::new(NamecheapProvider::with_base_url(
+                "example.com".parse().unwrap(),
+                "vpn".parse().unwrap(),
+                "secret".to_string(),
+                server.uri(),
+            )),
+            "duckdns" => Box::new(DuckDnsProvider::with_base_url(
+                "vpn".to_string(),
+                "token".to_string(),
+                server.uri(),
+            )),
+            "godaddy" => Box::new(GoDaddyProvider::with_base_url(
+                "api-key".to_string(),
+                "api-secret".to_string(),
+                "example.com".parse().unwrap(),
+                "vpn".parse().unwrap(),
+                TTL,
+                server.uri(),
+            )),
+            "vultr" => Box::new(VultrProvider::with_base_url(
+                "api-key".to_string(),
+                "example.com".parse().unwrap(),
+                "vpn".parse().unwrap(),
+                Some(TTL),
+                server.uri(),
+            )),
+            "yandexcloud" => Box::new(YandexCloudDnsProvider::with_base_url(
+                YandexCloudAuth::ApiKey("api-key".into()),
+                "zone-1".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                Some(TTL),
+                server.uri(),
+            )),
+            "dynurl" => Box::new(
+                DynUrlProvider::new(
+                    format!("{}/update?token=token", server.uri()),
+                    "vpn.example.com".to_string(),
+                )
+                .with_ip_param(Some("ip".to_string()))
+                .with_success_contains(Some("good".to_string())),
+            ),
+            "dnsomatic" => Box::new(DnsOMaticProvider::with_base_url(
+                "user".to_string(),
+                "secret".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                server.uri(),
+            )),
+            _ => unreachable!("{} isn't a built-in type", kind),
+        }
+    }
+
+    /// Answer the API of provider type `kind` as if the A and AAAA records
+    /// existed (holding 192.0.2.1 and 2001:db8::1), or as if neither did.
+    async fn mock_api(kind: &str, server: &MockServer, exists: bool) {
+        let mount = |mock: Mock| mock.mount(server);
+        match kind {
+            "cloudflare" => {
+                for (record_type, content) in [("A", "192.0.2.1"), ("AAAA", "2001:db8::1")] {
+                    let records = if exists {
+                        json!([{"id": record_type, "content": content, "ttl": 300}])
+                    } else {
+                        json!([])
+                    };
+                    mount(
+                        Mock::given(method("GET"))
+                            .and(path_regex(r"/dns_records$"))
+                            .and(query_param("type", record_type))
+                            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                                "success": true, "result": records, "errors": []
+                            }))),
+                    )
+                    .await;
+                }
+                mount(
+                    Mock::given(path_regex(r"/dns_records(/.*)?$")).respond_with(
+                        ResponseTemplate::new(200).set_body_json(json!({
+                            "success": true, "result": {"id": "new"}, "errors": []
+                        })),
+                    ),
+                )
+                .await;
+            }
+            "namecheap" => {
+                let errors = if exists { 0 } else { 1 };
+                mount(Mock::given(method("GET")).respond_with(
+                    ResponseTemplate::new(200).set_body_string(format!(
+                        "<interface-response><ErrCount>{}</ErrCount></interface-response>",
+                        errors
+                    )),
+                ))
+                .await;
+            }
+            "duckdns" => {
+                let body = if exists { "OK" } else { "KO" };
+                mount(
+                    Mock::given(method("GET"))
+                        .respond_with(ResponseTemplate::new(200).set_body_string(body)),
+                )
+                .await;
+            }
+            "godaddy" => {
+                for (record_type, data) in [("A", "192.0.2.1"), ("AAAA", "2001:db8::1")] {
+                    let records = if exists {
+                        json!([{"data": data, "ttl": 600}])
+                    } else {
+                        json!([])
+                    };
+                    mount(
+                        Mock::given(method("GET"))
+                            .and(path_regex(format!("/records/{}/", record_type)))
+                            .respond_with(ResponseTemplate::new(200).set_body_json(records)),
+                    )
+                    .await;
+                }
+                mount(
+                    Mock::given(method("GET"))
+                        .and(path_regex("/records/CNAME/"))
+                        .respond_with(ResponseTemplate::new(200).set_body_json(json!([]))),
+                )
+                .await;
+                mount(Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200))).await;
+            }
+            "vultr" => {
+                let records = if exists {
+                    json!([
+                        {"id": "a", "type": "A", "name": "vpn", "data": "192.0.2.1", "ttl": 300},
+                        {"id": "aaaa", "type": "AAAA", "name": "vpn", "data": "2001:db8::1", "ttl": 300}
+                    ])
+                } else {
+                    json!([])
+                };
+                mount(Mock::given(method("GET")).respond_with(
+                    ResponseTemplate::new(200).set_body_json(json!({"records": records})),
+                ))
+                .await;
+                mount(Mock::given(method("PATCH")).respond_with(ResponseTemplate::new(204))).await;
+            }
+            "yandexcloud" => {
+                for (record_type, data) in [("A", "192.0.2.1"), ("AAAA", "2001:db8::1")] {
+                    let response = if exists {
+                        ResponseTemplate::new(200).set_body_json(json!({
+                            "name": "vpn.example.com.",
+                            "type": record_type,
+                            "ttl": "300",
+                            "data": [data]
+                        }))
+                    } else {
+                        ResponseTemplate::new(404)
+                    };
+                    mount(
+                        Mock::given(method("GET"))
+                            .and(path_regex(r":getRecordSet$"))
+                            .and(query_param("type", record_type))
+                            .respond_with(response),
+                    )
+                    .await;
+                }
+                mount(
+                    Mock::given(method("GET"))
+                        .and(query_param("type", "CNAME"))
+                        .respond_with(ResponseTemplate::new(404)),
+                )
+                .await;
+                mount(
+                    Mock::given(method("POST")).respond_with(
+                        ResponseTemplate::new(200).set_body_json(json!({"done": true})),
+                    ),
+                )
+                .await;
+            }
+            "dynurl" => {
+                let body = if exists { "good" } else { "nohost" };
+                mount(
+                    Mock::given(method("GET"))
+                        .respond_with(ResponseTemplate::new(200).set_body_string(body)),
+                )
+                .await;
+            }
+            "dnsomatic" => {
+                let body = if exists { "good 192.0.2.2" } else { "nohost" };
+                mount(
+                    Mock::given(method("GET"))
+                        .respond_with(ResponseTemplate::new(200).set_body_string(body)),
+                )
+                .await;
+            }
+            _ => unreachable!("{} isn't a built-in type", kind),
+        }
+    }
+
+    /// Whether a request sent to `server` carried the TTL.
+    async fn sent_ttl(server: &MockServer) -> bool {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .any(|request| {
+                let body = String::from_utf8_lossy(&request.body);
+                // Yandex Cloud writes int64 fields as strings
+                body.contains(&format!("\"ttl\":{}", TTL))
+                    || body.contains(&format!("\"ttl\":\"{}\"", TTL))
+                    || request
+                        .url
+                        .query_pairs()
+                        .any(|(key, value)| key == "ttl" && value == TTL.to_string())
+            })
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_match_behavior() {
+        let v4: IpAddr = "192.0.2.2".parse().unwrap();
+        let v6: IpAddr = "2001:db8::2".parse().unwrap();
+
+        for kind in ProviderConfig::TYPES {
+            let server = MockServer::start().await;
+            mock_api(kind, &server, true).await;
+            let existing = provider(kind, &server);
+            let declared = existing.capabilities();
+            assert_eq!(
+                Some(declared),
+                ProviderCapabilities::of_type(kind),
+                "{}",
+                kind
+            );
+
+            let record = existing.get_current_record().await.unwrap();
+            assert_eq!(record.is_some(), declared.can_read_record, "{} reads", kind);
+            if !declared.can_read_record {
+                assert!(server.received_requests().await.unwrap().is_empty());
+            }
+
+            let result = existing.update_ip(v4).await.unwrap();
+            assert!(result.success, "{}: {:?}", kind, result.error);
+            assert_eq!(
+                sent_ttl(&server).await,
+                declared.supports_ttl,
+                "{} TTL",
+                kind
+            );
+
+            let result = existing.update_ip(v6).await.unwrap();
+            assert_eq!(result.success, declared.supports_ipv6, "{} IPv6", kind);
+
+            // No provider writes TXT records
+            assert!(!declared.supports_txt, "{} TXT", kind);
+
+            let server = MockServer::start().await;
+            mock_api(kind, &server, false).await;
+            let result = provider(kind, &server).update_ip(v4).await.unwrap();
+            assert_eq!(result.success, declared.supports_create, "{} creates", kind);
+        }
+    }
+
+    #[test]
+    fn test_capabilities_of_instance_and_type() {
+        let cloudflare = CloudflareProvider::new(
+            "test-token".to_string(),
+            "zone-123".to_string(),
+            "vpn.example.com".parse().unwrap(),
+            None,
+        );
+        assert!(!cloudflare.capabilities().supports_create);
+        assert_eq!(
+            ProviderCapabilities::of_type("namecheap")
+                .unwrap()
+                .describe(),
+            "update only"
+        );
+        assert_eq!(
+            CloudflareProvider::CAPABILITIES.describe(),
+            "read record, IPv6, TTL, create"
+        );
+        assert_eq!(ProviderCapabilities::of_type("internal"), None);
+    }
+}
+
+#[cfg(test)]
+mod env_resolution_tests {
     use crate::providers::resolve_env;
 
     #[test]
@@ -363,3 +2818,370 @@ mod env_resolution_tests {
         assert_eq!(result, "$NONEXISTENT_VAR_12345");
     }
 }
+
+#[cfg(test)]
+mod secret_file_tests {
+    use crate::config::{ProviderConfig, ProviderKind};
+    use crate::providers::create_provider;
+
+    #[tokio::test]
+    async fn test_unreadable_secret_file() {
+        let provider = create_provider(
+            &ProviderConfig::new(ProviderKind::DuckDns {
+                domains: "home".to_string(),
+                token: String::new().into(),
+                token_file: Some("/nonexistent/rusty-dns/token".into()),
+            }),
+            None,
+            &Default::default(),
+        );
+
+        assert_eq!(provider.name(), "duckdns");
+        assert_eq!(provider.domain(), "home.duckdns.org");
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("Cannot read secret file /nonexistent/rusty-dns/token"));
+        assert!(provider
+            .update_ip("1.2.3.4".parse().unwrap())
+            .await
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod proxy_tests {
+    use crate::config::{NetworkConfig, ProviderConfig, ProviderKind};
+    use crate::providers::create_provider;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Start a proxy on localhost that drops the first connection, returning
+    /// its URL and a task finishing once something connected to it.
+    async fn proxy() -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://user:pass@{}", listener.local_addr().unwrap());
+        let connected = tokio::spawn(async move {
+            let _ = listener.accept().await;
+        });
+        (url, connected)
+    }
+
+    async fn assert_used(proxy: JoinHandle<()>) {
+        tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .expect("request didn't go through the proxy")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_provider_proxy() {
+        let (global, global_used) = proxy().await;
+        let (own, own_used) = proxy().await;
+        let network = NetworkConfig {
+            proxy: global,
+            ..Default::default()
+        };
+        let config = ProviderConfig::new(ProviderKind::DuckDns {
+            domains: "home".to_string(),
+            token: "token".into(),
+            token_file: None,
+        });
+        let ip = "1.2.3.4".parse().unwrap();
+
+        let provider = create_provider(&config, None, &network);
+        assert!(provider.update_ip(ip).await.is_err());
+        assert_used(global_used).await;
+
+        // The entry's own proxy replaces the global one
+        let config = ProviderConfig {
+            proxy: Some(own),
+            ..config
+        };
+        let provider = create_provider(&config, None, &network);
+        assert!(provider.update_ip(ip).await.is_err());
+        assert_used(own_used).await;
+    }
+
+    #[tokio::test]
+    async fn test_invalid_proxy() {
+        let config = ProviderConfig {
+            proxy: Some("proxy:3128".to_string()),
+            ..ProviderConfig::new(ProviderKind::DuckDns {
+                domains: "home".to_string(),
+                token: "token".into(),
+                token_file: None,
+            })
+        };
+        let provider = create_provider(&config, None, &NetworkConfig::default());
+        let err = provider.validate().await.unwrap_err().to_string();
+        assert!(err.contains("Unsupported proxy scheme"), "{}", err);
+    }
+}
+
+#[cfg(test)]
+mod api_base_url_tests {
+    use crate::config::Config;
+    use crate::providers::create_provider;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_api_base_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/gateway/client/v4/zones/zone-123/dns_records"))
+            .and(query_param("name", "vpn.example.com"))
+            .and(header("Authorization", "Bearer test-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "result": [{"id": "record-123", "content": "1.1.1.1"}],
+                "errors": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/domains/example.com/records/A/home"))
+            .and(header("Authorization", "sso-key api-key:api-secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"data": "2.2.2.2"}])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [network]
+            allow_insecure_endpoints = true
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "test-token"
+            zone_id = "zone-123"
+            record_name = "vpn.example.com"
+            api_base_url = "{uri}/gateway/"
+
+            [[providers]]
+            type = "godaddy"
+            api_key = "api-key"
+            api_secret = "api-secret"
+            domain = "example.com"
+            name = "home"
+            api_base_url = "{uri}"
+            "#,
+            uri = server.uri()
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        let mut ips = Vec::new();
+        for entry in &config.providers {
+            let provider = create_provider(entry, config.default_ttl, &config.network);
+            ips.push(provider.get_current_ip().await.unwrap());
+        }
+        assert_eq!(
+            ips,
+            [
+                Some("1.1.1.1".parse().unwrap()),
+                Some("2.2.2.2".parse().unwrap())
+            ]
+        );
+        server.verify().await;
+    }
+}
+
+#[cfg(test)]
+mod user_agent_tests {
+    use crate::config::NetworkConfig;
+    use crate::http::DEFAULT_USER_AGENT;
+    use crate::providers::{DdnsProvider, DuckDnsProvider};
+    use wiremock::matchers::{header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        let server = MockServer::start().await;
+        for user_agent in [DEFAULT_USER_AGENT, "rusty-dns-test/1.0"] {
+            Mock::given(method("GET"))
+                .and(header("user-agent", user_agent))
+                .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let ip = "5.6.7.8".parse().unwrap();
+
+        let provider =
+            DuckDnsProvider::with_base_url("home".to_string(), "token".to_string(), server.uri());
+        assert!(provider.update_ip(ip).await.unwrap().success);
+
+        let network = NetworkConfig {
+            user_agent: Some("rusty-dns-test/1.0".to_string()),
+            ..Default::default()
+        };
+        let provider =
+            provider.with_client(network.client_options(None).unwrap().client().unwrap());
+        assert!(provider.update_ip(ip).await.unwrap().success);
+    }
+}
+
+#[cfg(test)]
+mod resolve_record_tests {
+    use crate::providers::{resolve_record, RecordType};
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn test_resolve_record() {
+        let ip = resolve_record("127.0.0.1", RecordType::A).await.unwrap();
+        assert_eq!(ip, Some("127.0.0.1".parse::<IpAddr>().unwrap()));
+
+        // No address of the other family
+        let ip = resolve_record("127.0.0.1", RecordType::Aaaa).await.unwrap();
+        assert_eq!(ip, None);
+
+        assert!(resolve_record("invalid..name", RecordType::A)
+            .await
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use crate::config::{Config, NetworkConfig};
+    use crate::providers::{DdnsProvider, ProviderContext, ProviderRegistry, UpdateResult};
+    use crate::Result;
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use std::net::IpAddr;
+
+    #[derive(Deserialize)]
+    struct Settings {
+        zone: String,
+        ttl_field: Option<u32>,
+    }
+
+    struct Internal {
+        zone: String,
+        ttl: Option<u32>,
+    }
+
+    #[async_trait]
+    impl DdnsProvider for Internal {
+        fn name(&self) -> &'static str {
+            "internal"
+        }
+
+        fn domain(&self) -> String {
+            self.zone.clone()
+        }
+
+        async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+            Ok(None)
+        }
+
+        async fn update_ip(&self, _ip: IpAddr) -> Result<UpdateResult> {
+            unimplemented!()
+        }
+
+        async fn validate(&self) -> Result<()> {
+            match self.ttl {
+                Some(300) => Ok(()),
+                ttl => Err(crate::DdnsError::Config(format!("ttl {:?}", ttl))),
+            }
+        }
+    }
+
+    fn internal(context: &ProviderContext<'_>) -> Result<Box<dyn DdnsProvider>> {
+        let settings: Settings = context.settings()?;
+        assert_eq!(settings.ttl_field, Some(1));
+        Ok(Box::new(Internal {
+            zone: settings.zone,
+            ttl: context.ttl,
+        }))
+    }
+
+    fn parse(entry: &str) -> Config {
+        toml::from_str(&format!("default_ttl = 300\n[[providers]]\n{}", entry)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_registered_type() {
+        let mut registry = ProviderRegistry::new();
+        registry.register("internal", internal);
+        assert!(registry.types().contains(&"internal"));
+        assert!(registry.types().contains(&"cloudflare"));
+
+        let config = parse("type = \"internal\"\nzone = \"corp.example\"\nttl_field = 1\n");
+        let provider = registry.create(&config.providers[0], config.default_ttl, &config.network);
+        assert_eq!(provider.name(), "internal");
+        assert_eq!(provider.domain(), "corp.example");
+        provider.validate().await.unwrap();
+
+        // Built-in types go through the same registry
+        let config = parse("type = \"duckdns\"\ndomains = \"home\"\ntoken = \"token\"\n");
+        let provider = registry.create(&config.providers[0], None, &NetworkConfig::default());
+        assert_eq!(provider.name(), "duckdns");
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_type() {
+        let registry = ProviderRegistry::new();
+        let config = parse("type = \"internal\"\ndomain = \"corp.example\"\n");
+        let provider = registry.create(&config.providers[0], None, &NetworkConfig::default());
+        assert_eq!(provider.domain(), "corp.example");
+        let err = provider.validate().await.unwrap_err();
+        assert!(err.to_string().contains("Unknown provider type `internal`"));
+
+        // Invalid settings are reported by every operation
+        let mut registry = ProviderRegistry::new();
+        registry.register("internal", internal);
+        let config = parse("type = \"internal\"\nzone = 1\n");
+        let provider = registry.create(&config.providers[0], None, &NetworkConfig::default());
+        let err = provider.validate().await.unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid internal settings"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod error_kind_tests {
+    use crate::providers::UpdateErrorKind;
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    #[test]
+    fn test_for_status() {
+        let kind =
+            |status: u16| UpdateErrorKind::for_status(StatusCode::from_u16(status).unwrap(), None);
+        assert_eq!(kind(401), UpdateErrorKind::Auth);
+        assert_eq!(kind(403), UpdateErrorKind::Auth);
+        assert_eq!(kind(404), UpdateErrorKind::RecordNotFound);
+        assert_eq!(kind(422), UpdateErrorKind::ProviderRejected);
+        assert_eq!(kind(503), UpdateErrorKind::Network);
+        assert!(kind(400).is_permanent());
+        assert!(!kind(429).is_permanent());
+    }
+
+    #[test]
+    fn test_serialization() {
+        let limited = UpdateErrorKind::RateLimited {
+            retry_after: Some(Duration::from_secs(90)),
+        };
+        let json = serde_json::to_value(limited).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "rate_limited", "retry_after_secs": 90 })
+        );
+        assert_eq!(
+            serde_json::from_value::<UpdateErrorKind>(json).unwrap(),
+            limited
+        );
+        assert_eq!(
+            serde_json::to_value(UpdateErrorKind::Auth).unwrap(),
+            serde_json::json!({ "kind": "auth" })
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Vultr DNS provider.
+
+use super::{
+    cname_message, DdnsProvider, Failure, ProviderCapabilities, RecordInfo, RecordType,
+    UpdateErrorKind, UpdateResult, WriteProbe,
+};
+use crate::error::Result;
+use crate::names::{DomainName, RecordName};
+use crate::secret::Secret;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+pub(super) const DEFAULT_BASE_URL: &str = "https://api.vultr.com";
+
+/// Records asked for per page when listing a domain's records (the API's
+/// maximum).
+const PAGE_SIZE: u32 = 500;
+
+/// Vultr DNS provider, using the API v2.
+pub struct VultrProvider {
+    client: crate::http::Client,
+    api_key: Secret,
+    domain: DomainName,
+    name: RecordName,
+    ttl: Option<u32>,
+    base_url: String,
+    /// A and AAAA records found or written so far, so updates needn't look
+    /// them up again.
+    records: Mutex<HashMap<RecordType, DnsRecord>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DnsRecord {
+    id: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    /// Name relative to the domain, empty for the apex.
+    name: String,
+    data: String,
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Page of a domain's records.
+#[derive(Debug, Deserialize)]
+struct RecordPage {
+    records: Vec<DnsRecord>,
+    #[serde(default)]
+    meta: Meta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Meta {
+    #[serde(default)]
+    links: Links,
+}
+
+/// Cursors of the neighbouring pages, empty on the first and last page.
+#[derive(Debug, Default, Deserialize)]
+struct Links {
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateRecord<'a> {
+    data: &'a str,
+    /// Omitted to keep the record's existing TTL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+/// Error body, e.g. `{"error": "Invalid API token.", "status": 401}`.
+#[derive(Debug, Deserialize)]
+struct VultrError {
+    error: String,
+}
+
+/// Read why a request failed from its response.
+async fn read_error(response: reqwest::Response) -> Failure {
+    Failure::read(response, "Vultr", |body| {
+        serde_json::from_str::<VultrError>(body)
+            .ok()
+            .map(|error| error.error)
+    })
+    .await
+}
+
+impl VultrProvider {
+    /// What the provider type can do. Records must exist: updates only
+    /// change them.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: true,
+        supports_ipv6: true,
+        supports_ttl: true,
+        supports_txt: false,
+        supports_create: false,
+    };
+
+    /// Create a new Vultr provider for the record `name` (`@` for the apex)
+    /// of `domain`, setting the TTL if given.
+    pub fn new(
+        api_key: impl Into<Secret>,
+        domain: DomainName,
+        name: RecordName,
+        ttl: Option<u32>,
+    ) -> Self {
+        Self::with_base_url(api_key, domain, name, ttl, DEFAULT_BASE_URL.to_string())
+    }
+
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server.
+    pub fn with_base_url(
+        api_key: impl Into<Secret>,
+        domain: DomainName,
+        name: RecordName,
+        ttl: Option<u32>,
+        base_url: String,
+    ) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            api_key: api_key.into(),
+            domain,
+            name,
+            ttl,
+            base_url,
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Get the record name as the API gives it.
+    fn api_name(&self) -> &str {
+        if self.name.is_apex() {
+            ""
+        } else {
+            self.name.as_str()
+        }
+    }
+
+    /// Get every record of the name, going through all pages of the
+    /// domain's records.
+    async fn list_records(&self) -> std::result::Result<Vec<DnsRecord>, Failure> {
+        let url = format!("{}/v2/domains/{}/records", self.base_url, self.domain);
+        let mut records = Vec::new();
+        let mut cursor = String::new();
+        loop {
+            let mut request = self
+                .client
+                .get(&url)
+                .bearer_auth(self.api_key.expose())
+                .query(&[("per_page", PAGE_SIZE.to_string())]);
+            if !cursor.is_empty() {
+                request = request.query(&[("cursor", &cursor)]);
+            }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                return Err(read_error(response).await);
+            }
+
+            let page: RecordPage = response.json().await?;
+            records.extend(
+                page.records
+                    .into_iter()
+                    .filter(|r| r.name.eq_ignore_ascii_case(self.api_name())),
+            );
+            if page.meta.links.next.is_empty() {
+                return Ok(records);
+            }
+            cursor = page.meta.links.next;
+        }
+    }
+
+    /// Look up the record of `record_type` of the name, remembering it.
+    ///
+    /// Fails if there is none because the name is a CNAME.
+    async fn find_record(
+        &self,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<DnsRecord>, Failure> {
+        let records = self.list_records().await?;
+        let found = records
+            .iter()
+            .find(|r| r.record_type == record_type.as_str())
+            .cloned();
+        match &found {
+            Some(record) => {
+                self.records
+                    .lock()
+                    .unwrap()
+                    .insert(record_type, record.clone());
+            }
+            None => {
+                if let Some(cname) = records.iter().find(|r| r.record_type == "CNAME") {
+                    return Err(Failure {
+                        kind: UpdateErrorKind::ProviderRejected,
+                        message: cname_message(&self.domain(), &cname.data),
+                    });
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Get the record of `record_type` found or written before, looking it
+    /// up if there is none.
+    async fn lookup(
+        &self,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<DnsRecord>, Failure> {
+        let cached = self.records.lock().unwrap().get(&record_type).cloned();
+        match cached {
+            Some(record) => Ok(Some(record)),
+            None => self.find_record(record_type).await,
+        }
+    }
+
+    /// Write `data` and the TTL to the record with ID `id`.
+    async fn patch(
+        &self,
+        id: &str,
+        data: &str,
+        ttl: Option<u32>,
+    ) -> std::result::Result<(), Failure> {
+        let url = format!(
+            "{}/v2/domains/{}/records/{}",
+            self.base_url, self.domain, id
+        );
+        let response = self
+            .client
+            .patch(&url)
+            .bearer_auth(self.api_key.expose())
+            .json(&UpdateRecord { data, ttl })
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(read_error(response).await);
+        }
+        Ok(())
+    }
+
+    fn failed(&self, ip: IpAddr, previous_ip: Option<IpAddr>, failure: Failure) -> UpdateResult {
+        UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type: RecordType::for_ip(&ip),
+            success: false,
+            ip: None,
+            previous_ip,
+            error: Some(failure.message),
+            error_kind: Some(failure.kind),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for VultrProvider {
+    fn name(&self) -> &'static str {
+        "vultr"
+    }
+
+    fn domain(&self) -> String {
+        self.name.full_domain(&self.domain)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        self.get_record_ip(RecordType::A).await
+    }
+
+    async fn get_current_record(&self) -> Result<Option<RecordInfo>> {
+        Ok(self
+            .find_record(RecordType::A)
+            .await
+            .map_err(|f| f.into_error(self.name()))?
+            .and_then(|r| Some(RecordInfo::new(r.data.parse().ok()?, r.ttl))))
+    }
+
+    async fn get_record_ip(&self, record_type: RecordType) -> Result<Option<IpAddr>> {
+        Ok(self
+            .find_record(record_type)
+            .await
+            .map_err(|f| f.into_error(self.name()))?
+            .and_then(|r| r.data.parse().ok()))
+    }
+
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let record_type = RecordType::for_ip(&ip);
+        let record = match self.lookup(record_type).await {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                let failure = Failure {
+                    kind: UpdateErrorKind::RecordNotFound,
+                    message: format!(
+                        "{} has no {} record at Vultr; create it first",
+                        self.domain(),
+                        record_type
+                    ),
+                };
+                return Ok(self.failed(ip, None, failure));
+            }
+            Err(failure) => return Ok(self.failed(ip, None, failure)),
+        };
+        let previous_ip = record.data.parse().ok();
+
+        let data = ip.to_string();
+        if let Err(failure) = self.patch(&record.id, &data, self.ttl).await {
+            // The record may have been deleted meanwhile
+            self.records.lock().unwrap().remove(&record_type);
+            return Ok(self.failed(ip, previous_ip, failure));
+        }
+
+        self.records.lock().unwrap().insert(
+            record_type,
+            DnsRecord {
+                data,
+                ttl: self.ttl.or(record.ttl),
+                ..record
+            },
+        );
+        Ok(UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type,
+            success: true,
+            ip: Some(ip),
+            previous_ip,
+            error: None,
+            error_kind: None,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+
+    async fn validate(&self) -> Result<()> {
+        let url = format!("{}/v2/account", self.base_url);
+        let response = self
+            .client
+            .get(&url)
+            .bearer_auth(self.api_key.expose())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(read_error(response).await.into_error(self.name()));
+        }
+        Ok(())
+    }
+
+    /// Write the A record, or else the AAAA record, back with its address
+    /// and TTL.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let Some(record) = self
+                .find_record(record_type)
+                .await
+                .map_err(|f| f.into_error(self.name()))?
+            else {
+                continue;
+            };
+            self.patch(&record.id, &record.data, record.ttl)
+                .await
+                .map_err(|f| f.into_error(self.name()))?;
+            return Ok(WriteProbe::Written(format!(
+                "wrote {} ({}) back unchanged",
+                record.data, record_type
+            )));
+        }
+
+        Ok(WriteProbe::Unsupported(format!(
+            "{} has no A or AAAA record to write back",
+            self.domain()
+        )))
+    }
+}
@@ -0,0 +1,542 @@
+//! Yandex Cloud DNS provider.
+
+use super::{
+    cname_message, DdnsProvider, Failure, ProviderCapabilities, RecordInfo, RecordType,
+    UpdateErrorKind, UpdateResult, WriteProbe,
+};
+use crate::error::{DdnsError, Result};
+use crate::names::RecordName;
+use crate::secret::Secret;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+const DEFAULT_DNS_URL: &str = "https://dns.api.cloud.yandex.net";
+const DEFAULT_IAM_URL: &str = "https://iam.api.cloud.yandex.net";
+
+/// How long before its expiry an IAM token is exchanged for a new one, in
+/// seconds.
+const IAM_TOKEN_MARGIN_SECS: i64 = 300;
+
+/// Credential for the Yandex Cloud API.
+#[derive(Debug, Clone)]
+pub enum YandexCloudAuth {
+    /// OAuth token of a Yandex account, exchanged for IAM tokens as they
+    /// expire.
+    OAuth(Secret),
+    /// IAM token, e.g. from `yc iam create-token`. Valid for 12 hours at
+    /// most, and not renewed.
+    IamToken(Secret),
+    /// API key of a service account.
+    ApiKey(Secret),
+}
+
+/// IAM token exchanged for an OAuth token.
+struct IamToken {
+    token: Secret,
+    expires_at: DateTime<Utc>,
+}
+
+/// Yandex Cloud DNS provider, using the DNS API v1.
+pub struct YandexCloudDnsProvider {
+    client: crate::http::Client,
+    auth: YandexCloudAuth,
+    zone_id: String,
+    record_name: RecordName,
+    ttl: Option<u32>,
+    dns_url: String,
+    iam_url: String,
+    /// IAM token for an OAuth credential, until it expires.
+    iam_token: Mutex<Option<IamToken>>,
+    /// A and AAAA record sets found or written so far, so updates needn't
+    /// look them up again.
+    records: Mutex<HashMap<RecordType, RecordSet>>,
+}
+
+/// Record set: every record of a name and type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordSet {
+    /// Fully qualified name, with the trailing dot.
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(with = "int64")]
+    ttl: u32,
+    data: Vec<String>,
+}
+
+impl RecordSet {
+    /// Get the first address of the set.
+    fn ip(&self) -> Option<IpAddr> {
+        self.data.first()?.parse().ok()
+    }
+}
+
+/// Changes applied atomically: the deletions must match the existing
+/// record sets exactly.
+#[derive(Debug, Serialize)]
+struct UpdateRecordSets<'a> {
+    deletions: [&'a RecordSet; 1],
+    additions: [&'a RecordSet; 1],
+}
+
+/// Operation started by a change, e.g. `{"id": "...", "done": true}`.
+#[derive(Debug, Deserialize)]
+struct Operation {
+    #[serde(default)]
+    error: Option<Status>,
+}
+
+/// Error of a request or operation, e.g. `{"code": 16, "message": "The token
+/// is invalid"}`.
+#[derive(Debug, Deserialize)]
+struct Status {
+    #[serde(default)]
+    code: i32,
+    #[serde(default)]
+    message: String,
+}
+
+impl Status {
+    /// Classify the error by its gRPC status code.
+    fn kind(&self) -> UpdateErrorKind {
+        match self.code {
+            7 | 16 => UpdateErrorKind::Auth,
+            5 => UpdateErrorKind::RecordNotFound,
+            4 | 13 | 14 => UpdateErrorKind::Network,
+            _ => UpdateErrorKind::ProviderRejected,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenRequest<'a> {
+    yandex_passport_oauth_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenResponse {
+    iam_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// (De)serialize an int64 field, which the API writes as a string.
+mod int64 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Int64 {
+            Number(u32),
+            String(String),
+        }
+
+        match Int64::deserialize(deserializer)? {
+            Int64::Number(value) => Ok(value),
+            Int64::String(value) => value.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Turn a failed request into an error, telling rejected credentials
+/// apart.
+fn error(failure: Failure) -> DdnsError {
+    match failure.kind {
+        UpdateErrorKind::Auth => DdnsError::Auth {
+            provider: "yandexcloud".to_string(),
+            message: failure.message,
+        },
+        _ => failure.into_error("yandexcloud"),
+    }
+}
+
+/// Read why a request failed from its response.
+async fn read_error(response: reqwest::Response) -> Failure {
+    Failure::read(response, "Yandex Cloud", |body| {
+        serde_json::from_str::<Status>(body)
+            .ok()
+            .map(|error| error.message)
+    })
+    .await
+}
+
+impl YandexCloudDnsProvider {
+    /// What the provider type can do. Record sets must exist: updates only
+    /// replace them.
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        can_read_record: true,
+        supports_ipv6: true,
+        supports_ttl: true,
+        supports_txt: false,
+        supports_create: false,
+    };
+
+    /// Create a new Yandex Cloud DNS provider for the record set
+    /// `record_name` (a full name) of the zone with ID `zone_id`, setting
+    /// the TTL if given.
+    pub fn new(
+        auth: YandexCloudAuth,
+        zone_id: String,
+        record_name: RecordName,
+        ttl: Option<u32>,
+    ) -> Self {
+        let mut provider =
+            Self::with_base_url(auth, zone_id, record_name, ttl, DEFAULT_DNS_URL.to_string());
+        provider.iam_url = DEFAULT_IAM_URL.to_string();
+        provider
+    }
+
+    /// Create with a custom base URL, e.g. an `api_base_url` or a test
+    /// server, serving both the DNS and the IAM API.
+    pub fn with_base_url(
+        auth: YandexCloudAuth,
+        zone_id: String,
+        record_name: RecordName,
+        ttl: Option<u32>,
+        base_url: String,
+    ) -> Self {
+        Self {
+            client: crate::http::default_client(),
+            auth,
+            zone_id,
+            record_name,
+            ttl,
+            dns_url: base_url.clone(),
+            iam_url: base_url,
+            iam_token: Mutex::new(None),
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send requests with `client`, e.g. one using a proxy.
+    pub fn with_client(mut self, client: crate::http::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Get the record set name as the API gives it.
+    fn fqdn(&self) -> String {
+        format!("{}.", self.record_name)
+    }
+
+    /// Exchange the OAuth token for an IAM token.
+    async fn exchange(&self, oauth_token: &Secret) -> std::result::Result<IamToken, Failure> {
+        let url = format!("{}/iam/v1/tokens", self.iam_url);
+        let response = self
+            .client
+            .post(&url)
+            .json(&TokenRequest {
+                yandex_passport_oauth_token: oauth_token.expose(),
+            })
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let failure = read_error(response).await;
+            return Err(Failure {
+                message: format!("OAuth token exchange failed: {}", failure.message),
+                ..failure
+            });
+        }
+
+        let token: TokenResponse = response.json().await?;
+        Ok(IamToken {
+            token: Secret::new(token.iam_token),
+            expires_at: token.expires_at,
+        })
+    }
+
+    /// Get the `Authorization` header value, exchanging the OAuth token for
+    /// an IAM token if there is none about to stay valid (or `renew`).
+    /// Also tells whether a token was just exchanged.
+    async fn authorization(&self, renew: bool) -> std::result::Result<(String, bool), Failure> {
+        let oauth_token = match &self.auth {
+            YandexCloudAuth::ApiKey(key) => {
+                return Ok((format!("Api-Key {}", key.expose()), false))
+            }
+            YandexCloudAuth::IamToken(token) => {
+                return Ok((format!("Bearer {}", token.expose()), false))
+            }
+            YandexCloudAuth::OAuth(token) => token,
+        };
+
+        if !renew {
+            let cached = self.iam_token.lock().unwrap();
+            if let Some(token) = cached.as_ref().filter(|t| {
+                t.expires_at - chrono::Duration::seconds(IAM_TOKEN_MARGIN_SECS) > Utc::now()
+            }) {
+                return Ok((format!("Bearer {}", token.token.expose()), false));
+            }
+        }
+
+        let token = self.exchange(oauth_token).await?;
+        let header = format!("Bearer {}", token.token.expose());
+        *self.iam_token.lock().unwrap() = Some(token);
+        Ok((header, true))
+    }
+
+    /// Send the request built by `request` with the credential.
+    ///
+    /// An IAM token can stop being valid before its stated expiry (e.g.
+    /// when revoked), so a rejected one is exchanged again and the request
+    /// sent once more.
+    async fn send(
+        &self,
+        request: impl Fn() -> crate::http::RequestBuilder,
+    ) -> std::result::Result<reqwest::Response, Failure> {
+        let (header, fresh) = self.authorization(false).await?;
+        let response = request().header("Authorization", header).send().await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED
+            || fresh
+            || !matches!(self.auth, YandexCloudAuth::OAuth(_))
+        {
+            return Ok(response);
+        }
+
+        tracing::debug!("Yandex Cloud rejected the IAM token, exchanging the OAuth token again");
+        let (header, _) = self.authorization(true).await?;
+        Ok(request().header("Authorization", header).send().await?)
+    }
+
+    /// Get the record set of `record_type`, if the zone has one.
+    async fn get_record_set(
+        &self,
+        record_type: &str,
+    ) -> std::result::Result<Option<RecordSet>, Failure> {
+        let url = format!(
+            "{}/dns/v1/zones/{}:getRecordSet",
+            self.dns_url, self.zone_id
+        );
+        let name = self.fqdn();
+        let response = self
+            .send(|| {
+                self.client
+                    .get(&url)
+                    .query(&[("name", name.as_str()), ("type", record_type)])
+            })
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(read_error(response).await);
+        }
+        Ok(Some(response.json().await?))
+    }
+
+    /// Look up the record set of `record_type`, remembering it.
+    ///
+    /// Fails if there is none because the name is a CNAME.
+    async fn find_record(
+        &self,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<RecordSet>, Failure> {
+        let Some(set) = self.get_record_set(record_type.as_str()).await? else {
+            if let Some(cname) = self.get_record_set("CNAME").await? {
+                return Err(Failure {
+                    kind: UpdateErrorKind::ProviderRejected,
+                    message: cname_message(
+                        &self.domain(),
+                        cname.data.first().map_or("", String::as_str),
+                    ),
+                });
+            }
+            return Ok(None);
+        };
+
+        self.records
+            .lock()
+            .unwrap()
+            .insert(record_type, set.clone());
+        Ok(Some(set))
+    }
+
+    /// Get the record set of `record_type` found or written before, looking
+    /// it up if there is none.
+    async fn lookup(
+        &self,
+        record_type: RecordType,
+    ) -> std::result::Result<Option<RecordSet>, Failure> {
+        let cached = self.records.lock().unwrap().get(&record_type).cloned();
+        match cached {
+            Some(set) => Ok(Some(set)),
+            None => self.find_record(record_type).await,
+        }
+    }
+
+    /// Replace the record set `existing` with `replacement`.
+    async fn replace(
+        &self,
+        existing: &RecordSet,
+        replacement: &RecordSet,
+    ) -> std::result::Result<(), Failure> {
+        let url = format!(
+            "{}/dns/v1/zones/{}:updateRecordSets",
+            self.dns_url, self.zone_id
+        );
+        let body = UpdateRecordSets {
+            deletions: [existing],
+            additions: [replacement],
+        };
+        let response = self.send(|| self.client.post(&url).json(&body)).await?;
+        if !response.status().is_success() {
+            return Err(read_error(response).await);
+        }
+
+        let operation: Operation = response.json().await?;
+        match operation.error {
+            Some(error) => Err(Failure {
+                kind: error.kind(),
+                message: error.message,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn failed(&self, ip: IpAddr, previous_ip: Option<IpAddr>, failure: Failure) -> UpdateResult {
+        UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type: RecordType::for_ip(&ip),
+            success: false,
+            ip: None,
+            previous_ip,
+            error: Some(failure.message),
+            error_kind: Some(failure.kind),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for YandexCloudDnsProvider {
+    fn name(&self) -> &'static str {
+        "yandexcloud"
+    }
+
+    fn domain(&self) -> String {
+        self.record_name.to_string()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+
+    /// Exchange the OAuth token for an IAM token, unless the one kept is
+    /// still valid.
+    async fn prepare(&self) -> Result<()> {
+        self.authorization(false).await.map_err(error)?;
+        Ok(())
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        self.get_record_ip(RecordType::A).await
+    }
+
+    async fn get_current_record(&self) -> Result<Option<RecordInfo>> {
+        Ok(self
+            .find_record(RecordType::A)
+            .await
+            .map_err(error)?
+            .and_then(|set| Some(RecordInfo::new(set.ip()?, Some(set.ttl)))))
+    }
+
+    async fn get_record_ip(&self, record_type: RecordType) -> Result<Option<IpAddr>> {
+        Ok(self
+            .find_record(record_type)
+            .await
+            .map_err(error)?
+            .and_then(|set| set.ip()))
+    }
+
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let record_type = RecordType::for_ip(&ip);
+        let existing = match self.lookup(record_type).await {
+            Ok(Some(set)) => set,
+            Ok(None) => {
+                let failure = Failure {
+                    kind: UpdateErrorKind::RecordNotFound,
+                    message: format!(
+                        "{} has no {} record set in the zone; create it first",
+                        self.domain(),
+                        record_type
+                    ),
+                };
+                return Ok(self.failed(ip, None, failure));
+            }
+            Err(failure) => return Ok(self.failed(ip, None, failure)),
+        };
+        let previous_ip = existing.ip();
+
+        // Keep the set's name and type, replacing its data (and its TTL if
+        // one is configured)
+        let replacement = RecordSet {
+            ttl: self.ttl.unwrap_or(existing.ttl),
+            data: vec![ip.to_string()],
+            ..existing.clone()
+        };
+        if let Err(failure) = self.replace(&existing, &replacement).await {
+            // The set may have changed meanwhile, no longer matching the
+            // deletion
+            self.records.lock().unwrap().remove(&record_type);
+            return Ok(self.failed(ip, previous_ip, failure));
+        }
+
+        self.records
+            .lock()
+            .unwrap()
+            .insert(record_type, replacement);
+        Ok(UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type,
+            success: true,
+            ip: Some(ip),
+            previous_ip,
+            error: None,
+            error_kind: None,
+            timestamp: Utc::now(),
+        })
+    }
+
+    /// Get the zone, which checks both the credential and `zone_id`.
+    async fn validate(&self) -> Result<()> {
+        let url = format!("{}/dns/v1/zones/{}", self.dns_url, self.zone_id);
+        let response = self.send(|| self.client.get(&url)).await.map_err(error)?;
+        if !response.status().is_success() {
+            return Err(error(read_error(response).await));
+        }
+        Ok(())
+    }
+
+    /// Write the A record set, or else the AAAA record set, back unchanged.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let Some(set) = self.find_record(record_type).await.map_err(error)? else {
+                continue;
+            };
+            self.replace(&set, &set).await.map_err(error)?;
+            return Ok(WriteProbe::Written(format!(
+                "wrote {} ({}) back unchanged",
+                set.data.join(", "),
+                record_type
+            )));
+        }
+
+        Ok(WriteProbe::Unsupported(format!(
+            "{} has no A or AAAA record set to write back",
+            self.domain()
+        )))
+    }
+}
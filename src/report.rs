@@ -0,0 +1,2266 @@
+//! Machine-readable reports.
+//!
+//! These are the documents printed by `--output json` and returned by the
+//! MCP server, so both interfaces expose the same shapes.
+
+use crate::config::{Config, GroupPolicy, IpVersion, ProviderConfig, ValidationWarning};
+use crate::detector::{
+    Detection, IpDetector, LastKnownIp, NetworkSituation, ServiceHealth, ServiceProbe,
+};
+use crate::history::{self, HistoryEntry, HistoryStore, UpdateReason};
+use crate::providers::{
+    create_provider, resolve_record, DdnsProvider, ProviderCapabilities, RecordInfo, RecordType,
+    UpdateErrorKind, WriteProbe,
+};
+use crate::state::ProviderState;
+use crate::stats::{self, CycleStats};
+use crate::tunnel::TunnelStatus;
+use crate::updater::{DetectedIps, ProviderUpdate, UpdateOutcome, Updater};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Status of the public addresses and configured providers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    /// Detected public IPv4 address.
+    pub ipv4: Option<IpAddr>,
+    /// Detected public IPv6 address.
+    pub ipv6: Option<IpAddr>,
+    /// Why the public addresses couldn't be detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection_error: Option<String>,
+    /// Last confirmed addresses of the needed families that couldn't be
+    /// detected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub last_known: Vec<LastKnownIp>,
+    /// Whether records pointing to the public IPv4 address can reach this
+    /// network, or it's behind CGNAT or double NAT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_situation: Option<NetworkSituation>,
+    /// Running daemon the status was read from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daemon: Option<DaemonInfo>,
+    /// Running reverse SSH tunnel (`rusty-dns tunnel`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelStatus>,
+    /// Configured providers.
+    pub providers: Vec<ProviderReport>,
+    /// Provider groups and whether their policies are satisfied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupReport>,
+    /// Last successful update of any provider.
+    pub last_update: Option<DateTime<Utc>>,
+    /// Health of the IP detection services queried so far.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ip_services: BTreeMap<String, ServiceHealth>,
+    /// Trends of the daemon's recent cycles (`status --verbose`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<CycleStats>,
+}
+
+/// Daemon that reported a status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    /// Daemon process ID.
+    pub pid: u32,
+    /// When the daemon started.
+    pub started: DateTime<Utc>,
+    /// Whether automatic cycles are paused.
+    #[serde(default)]
+    pub paused: bool,
+    /// Seconds between cycles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+}
+
+/// Status of one configured provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderReport {
+    /// Provider label (its key in persistent state).
+    pub label: String,
+    /// Provider type.
+    pub name: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Whether the provider is enabled.
+    pub enabled: bool,
+    /// Address families the provider is configured for.
+    pub ip_version: IpVersion,
+    /// One entry per configured address family.
+    pub records: Vec<RecordReport>,
+    /// Whether the provider is enabled, its record could be read (or its
+    /// settings validated, if it can't read it) and it has no failure
+    /// streak.
+    pub healthy: bool,
+    /// Number of failed updates since the last success.
+    pub consecutive_failures: u32,
+    /// Why the current record couldn't be queried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the records hold the detected addresses.
+    pub sync: SyncStatus,
+    /// Whether `sync` is [`SyncStatus::InSync`].
+    pub in_sync: bool,
+    /// Last successful update sent, according to history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<DateTime<Utc>>,
+    /// Last time a record was read and found to hold the detected address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_verified_at: Option<DateTime<Utc>>,
+    /// Seconds from `last_verified_at` to when the report was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds_since_verified: Option<i64>,
+    /// Group of the entry (see [`GroupReport`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// What the provider type can do, e.g. whether `records` were read
+    /// from its API.
+    #[serde(default)]
+    pub capabilities: ProviderCapabilities,
+}
+
+/// Status of a provider group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupReport {
+    /// Group name.
+    pub name: String,
+    /// Which members must be healthy.
+    pub policy: GroupPolicy,
+    /// Labels of the enabled members, in config order.
+    pub members: Vec<String>,
+    /// Label of the member that must be healthy under `primary-failover`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary: Option<String>,
+    /// Whether the members the policy requires are healthy.
+    pub healthy: bool,
+}
+
+impl GroupReport {
+    /// Whether the health of the member labeled `label` counts for the
+    /// group.
+    pub fn counts(&self, label: &str) -> bool {
+        match self.policy {
+            GroupPolicy::All => true,
+            GroupPolicy::PrimaryFailover => self.primary.as_deref() == Some(label),
+        }
+    }
+}
+
+/// Whether the records of a provider hold the detected addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    /// Every record of a detected family holds the detected address.
+    InSync,
+    /// Some record holds another address.
+    Stale,
+    /// Some record or the public address couldn't be determined.
+    Unknown,
+    /// The record couldn't be queried.
+    Error,
+}
+
+impl SyncStatus {
+    /// Compare `records`, given the error of querying them, if any.
+    fn of(records: &[RecordReport], error: Option<&str>) -> Self {
+        let detected = || records.iter().filter(|r| r.detected_ip.is_some());
+        if error.is_some() {
+            Self::Error
+        } else if detected().any(|r| r.record_ip.is_some() && !r.matches) {
+            Self::Stale
+        } else if detected().next().is_some() && detected().all(|r| r.matches) {
+            Self::InSync
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl std::fmt::Display for SyncStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::InSync => "IN SYNC",
+            Self::Stale => "STALE",
+            Self::Unknown => "UNKNOWN",
+            Self::Error => "ERROR",
+        })
+    }
+}
+
+/// Published address of one record compared with the detected address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordReport {
+    /// Record type.
+    pub record_type: RecordType,
+    /// Detected public address of this family.
+    pub detected_ip: Option<IpAddr>,
+    /// Address the record holds (or last published, if it can't be queried).
+    pub record_ip: Option<IpAddr>,
+    /// Whether the record holds the detected address.
+    pub matches: bool,
+    /// TTL of the record in seconds, if it was queried and the provider
+    /// reports it.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// Until when resolvers may still answer with the address the record
+    /// held before its last update (see [`stale_until`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_until: Option<DateTime<Utc>>,
+}
+
+/// Overall health of a [`StatusReport`], used as the exit code of
+/// `rusty-dns check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    /// Every enabled provider is [in sync](SyncStatus::InSync).
+    Ok,
+    /// Some enabled provider isn't in sync.
+    Mismatch,
+    /// The public address couldn't be detected.
+    DetectionFailed,
+    /// Some provider couldn't be queried or is failing to update.
+    Unhealthy,
+}
+
+impl Health {
+    /// Get the process exit code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Ok => 0,
+            Self::Mismatch => 1,
+            Self::DetectionFailed => 2,
+            Self::Unhealthy => 3,
+        }
+    }
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "OK",
+            Self::Mismatch => "MISMATCH",
+            Self::DetectionFailed => "DETECTION FAILED",
+            Self::Unhealthy => "UNHEALTHY",
+        })
+    }
+}
+
+impl StatusReport {
+    /// Detect the public addresses and query the record of each enabled
+    /// provider.
+    pub async fn collect(config: &Config, detector: &IpDetector) -> Self {
+        Self::collect_from(config, detector, &create_providers(config)).await
+    }
+
+    /// Like [`StatusReport::collect`], with one already created provider per
+    /// entry of `config.providers`.
+    pub async fn collect_from(
+        config: &Config,
+        detector: &IpDetector,
+        providers: &[Box<dyn DdnsProvider>],
+    ) -> Self {
+        let records = query_records(config, providers).await;
+        Self::with_records(config, detector, &records).await
+    }
+
+    /// Detect the public addresses and report them with records queried
+    /// earlier by [`query_records`], so addresses can be refreshed more often
+    /// than the provider APIs are queried.
+    pub async fn with_records(
+        config: &Config,
+        detector: &IpDetector,
+        records: &[LiveRecord],
+    ) -> Self {
+        let updater = Updater::new(config, detector);
+        let (ips, detection_error) = match updater.detect().await {
+            Ok(ips) => (ips, None),
+            Err(e) => (DetectedIps::default(), Some(e.to_string())),
+        };
+
+        let store = history::open(config).ok();
+        let state = store
+            .as_ref()
+            .and_then(|store| store.load_state().ok())
+            .unwrap_or_default();
+        let history = store
+            .as_ref()
+            .and_then(|store| store.entries().ok())
+            .unwrap_or_default();
+
+        let version = updater.ip_version();
+        let last_known = [
+            (version.includes_v4() && ips.ipv4.is_none(), false),
+            (version.includes_v6() && ips.ipv6.is_none(), true),
+        ]
+        .into_iter()
+        .filter(|(missing, _)| *missing)
+        .filter_map(|(_, ipv6)| LastKnownIp::from_state(&state, ipv6))
+        .collect();
+        let network_situation = if version.includes_v4() {
+            Some(detector.network_situation(ips.ipv4, false).await)
+        } else {
+            None
+        };
+
+        let mut reports = Vec::new();
+        let mut verified = Vec::new();
+        for (provider_config, record) in config.providers.iter().zip(records) {
+            // The live record overrides the last published address of its family
+            let mut published = state
+                .providers
+                .get(&provider_config.label())
+                .cloned()
+                .unwrap_or_default();
+            let mut error = None;
+
+            let live = match record {
+                Some(Ok(live)) => live.as_slice(),
+                Some(Err(e)) => {
+                    error = Some(e.clone());
+                    &[]
+                }
+                None => &[],
+            };
+            live.iter().for_each(|r| published.set_published(r.ip));
+
+            let ips = ips.with_suffix(provider_config.ipv6_suffix, config.ipv6_prefix_length);
+            if holds_detected(live, &ips) {
+                verified.push(provider_config.label());
+            }
+            let mut report =
+                ProviderReport::new(provider_config, config.ip_version, &ips, &published, error);
+            report.last_updated = last_updated(&history, &report.label);
+            for record in &mut report.records {
+                let Some(info) = live.iter().find(|r| r.record_type == record.record_type) else {
+                    continue;
+                };
+                record.ttl = info.ttl;
+                record.stale_until = stale_until(
+                    last_changed(&history, &report.label, record.record_type),
+                    info.ttl,
+                );
+            }
+            reports.push(report);
+        }
+
+        if !verified.is_empty() {
+            let now = mark_verified(store.as_ref(), &verified);
+            for report in reports.iter_mut().filter(|r| verified.contains(&r.label)) {
+                report.set_verified(Some(now), now);
+            }
+        }
+
+        Self {
+            ipv4: ips.ipv4,
+            ipv6: ips.ipv6,
+            detection_error,
+            last_known,
+            network_situation,
+            daemon: None,
+            tunnel: TunnelStatus::load(config),
+            last_update: last_success(state.providers.values()),
+            providers: reports,
+            groups: Vec::new(),
+            ip_services: detector.health(),
+            stats: None,
+        }
+        .with_groups(config)
+    }
+
+    /// Add the trends of the cycles recorded by the daemon, for the
+    /// providers of `config`.
+    pub fn with_stats(mut self, config: &Config) -> Self {
+        let mut stats = stats::load(config).unwrap_or_default();
+        stats
+            .providers
+            .retain(|p| config.providers.iter().any(|c| c.label() == p.label));
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Tag the providers with their groups and report the groups of
+    /// `config`, e.g. for a status read from the daemon.
+    pub fn with_groups(mut self, config: &Config) -> Self {
+        for provider in &mut self.providers {
+            provider.group = config.group_of(&provider.label).map(str::to_string);
+        }
+
+        self.groups = config
+            .group_names()
+            .into_iter()
+            .map(|name| {
+                let policy = config.group_policy(name);
+                let members: Vec<String> = config.group_members(name).map(|p| p.label()).collect();
+                let primary = match policy {
+                    GroupPolicy::All => None,
+                    GroupPolicy::PrimaryFailover => members.first().cloned(),
+                };
+                let mut group = GroupReport {
+                    name: name.to_string(),
+                    policy,
+                    members,
+                    primary,
+                    healthy: true,
+                };
+                group.healthy = self
+                    .providers
+                    .iter()
+                    .filter(|p| group.members.contains(&p.label) && group.counts(&p.label))
+                    .all(|p| p.healthy);
+                group
+            })
+            .collect();
+        self
+    }
+
+    /// Get the overall health.
+    ///
+    /// A failed detection takes precedence over unhealthy providers, which
+    /// take precedence over providers not in sync. Disabled providers and
+    /// the fallbacks of `primary-failover` groups are ignored.
+    pub fn health(&self) -> Health {
+        if self.detection_error.is_some() {
+            return Health::DetectionFailed;
+        }
+
+        let counts = |p: &ProviderReport| {
+            self.groups
+                .iter()
+                .find(|g| p.group.as_ref() == Some(&g.name))
+                .map_or(true, |g| g.counts(&p.label))
+        };
+        let enabled = || self.providers.iter().filter(|p| p.enabled && counts(p));
+        if enabled().any(|p| !p.healthy) {
+            Health::Unhealthy
+        } else if enabled().any(|p| !p.in_sync) {
+            Health::Mismatch
+        } else {
+            Health::Ok
+        }
+    }
+}
+
+/// Records of a provider as reported by its API, or by DNS (`None` for
+/// disabled providers, which aren't queried).
+pub type LiveRecord = Option<std::result::Result<Vec<RecordInfo>, String>>;
+
+/// Create one provider per entry of `config.providers`.
+fn create_providers(config: &Config) -> Vec<Box<dyn DdnsProvider>> {
+    config
+        .providers
+        .iter()
+        .map(|p| create_provider(p, config.default_ttl, &config.network))
+        .collect()
+}
+
+/// Query the current record of each enabled provider, with one already
+/// created provider per entry of `config.providers`.
+///
+/// Each provider is asked once: reading the record also proves the
+/// credentials work, so a failed read is the provider's error. Providers
+/// that [can't read](ProviderCapabilities::can_read_record) their record
+/// are [validated](DdnsProvider::validate) instead, which checks their
+/// settings without sending an update. With `dns_lookup`, the records of
+/// providers whose API doesn't report them are looked up in DNS.
+pub async fn query_records(
+    config: &Config,
+    providers: &[Box<dyn DdnsProvider>],
+) -> Vec<LiveRecord> {
+    let mut records = Vec::new();
+    for (provider_config, provider) in config.providers.iter().zip(providers) {
+        if !provider_config.enabled {
+            records.push(None);
+            continue;
+        }
+
+        let current = if provider.capabilities().can_read_record {
+            provider.get_current_record().await
+        } else {
+            provider.validate().await.map(|()| None)
+        };
+        let record = match current {
+            Ok(Some(record)) => Ok(vec![record]),
+            Ok(None) if config.dns_lookup => {
+                let version = provider_config.ip_version(config.ip_version);
+                let mut ips = Vec::new();
+                for (included, record_type) in [
+                    (version.includes_v4(), RecordType::A),
+                    (version.includes_v6(), RecordType::Aaaa),
+                ] {
+                    if !included {
+                        continue;
+                    }
+                    // The record stays unknown if it can't be resolved either;
+                    // resolvers only tell how long they still cache it
+                    if let Ok(Some(ip)) = resolve_record(&provider.domain(), record_type).await {
+                        ips.push(RecordInfo::new(ip, None));
+                    }
+                }
+                Ok(ips)
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        };
+        records.push(Some(record));
+    }
+    records
+}
+
+/// Get the last successful update of the `record_type` record of the
+/// provider labeled `label` that changed its address (or may have, if the
+/// previous address is unknown).
+fn last_changed(
+    history: &[HistoryEntry],
+    label: &str,
+    record_type: RecordType,
+) -> Option<DateTime<Utc>> {
+    history
+        .iter()
+        .filter(|entry| {
+            !entry.is_skip()
+                && entry.result.success
+                && entry.result.label.as_deref() == Some(label)
+                && entry.result.record_type == record_type
+                && entry.result.previous_ip != entry.result.ip
+        })
+        .map(|entry| entry.result.timestamp)
+        .max()
+}
+
+/// Get until when resolvers may still answer with the address a record
+/// held before it was changed at `changed`: one TTL later, as caches
+/// filled just before the change expire. `None` once that has passed, or
+/// if the TTL is unknown.
+pub fn stale_until(changed: Option<DateTime<Utc>>, ttl: Option<u32>) -> Option<DateTime<Utc>> {
+    let until = changed? + chrono::Duration::seconds(ttl?.into());
+    (until > Utc::now()).then_some(until)
+}
+
+/// Whether every `live` record of a detected family holds the detected
+/// address, and there is at least one.
+fn holds_detected(live: &[RecordInfo], ips: &DetectedIps) -> bool {
+    let mut compared = live
+        .iter()
+        .filter_map(|record| {
+            let detected = match record.record_type {
+                RecordType::A => ips.ipv4,
+                RecordType::Aaaa => ips.ipv6,
+            };
+            Some(detected? == record.ip)
+        })
+        .peekable();
+    compared.peek().is_some() && compared.all(|matches| matches)
+}
+
+/// Record in persistent state that the records of the providers labeled
+/// `labels` were found to hold the detected addresses, returning when.
+///
+/// Failing to save only loses the timestamp, so it is logged and ignored.
+fn mark_verified(store: Option<&Arc<dyn HistoryStore>>, labels: &[String]) -> DateTime<Utc> {
+    let now = Utc::now();
+    let saved = store.map(|store| {
+        store.update_state(|state| {
+            for label in labels {
+                state.provider_mut(label).last_verified_at = Some(now);
+            }
+        })
+    });
+    if let Some(Err(e)) = saved {
+        tracing::warn!("Failed to save verified records: {}", e);
+    }
+    now
+}
+
+/// Get the last successful update sent for the provider labeled `label`.
+fn last_updated(history: &[HistoryEntry], label: &str) -> Option<DateTime<Utc>> {
+    history
+        .iter()
+        .filter(|entry| {
+            !entry.is_skip() && entry.result.success && entry.result.label.as_deref() == Some(label)
+        })
+        .map(|entry| entry.result.timestamp)
+        .max()
+}
+
+#[cfg(unix)]
+impl From<&crate::control::DaemonStatus> for StatusReport {
+    fn from(status: &crate::control::DaemonStatus) -> Self {
+        let ips = DetectedIps {
+            ipv4: status.ipv4,
+            ipv6: status.ipv6,
+            ..Default::default()
+        };
+
+        let providers = status
+            .providers
+            .iter()
+            .map(|p| {
+                let ips = DetectedIps {
+                    ipv6: p.ipv6.or(ips.ipv6),
+                    ..ips.clone()
+                };
+                let records = records(p.ip_version, &ips, &p.state);
+                let sync = SyncStatus::of(&records, None);
+                ProviderReport {
+                    label: p.label.clone(),
+                    name: p.name.clone(),
+                    domain: p.domain.clone(),
+                    enabled: p.enabled,
+                    ip_version: p.ip_version,
+                    records,
+                    healthy: p.enabled && p.state.consecutive_failures == 0,
+                    consecutive_failures: p.state.consecutive_failures,
+                    error: None,
+                    sync,
+                    in_sync: sync == SyncStatus::InSync,
+                    last_updated: None,
+                    last_verified_at: None,
+                    seconds_since_verified: None,
+                    group: None,
+                    capabilities: ProviderCapabilities::of_type(&p.name).unwrap_or_default(),
+                }
+                .with_verified(p.state.last_verified_at, Utc::now())
+            })
+            .collect();
+
+        Self {
+            ipv4: status.ipv4,
+            ipv6: status.ipv6,
+            detection_error: None,
+            last_known: Vec::new(),
+            network_situation: None,
+            daemon: Some(DaemonInfo {
+                pid: status.pid,
+                started: status.started,
+                paused: status.paused,
+                interval_secs: status.interval_secs,
+            }),
+            tunnel: None,
+            providers,
+            groups: Vec::new(),
+            last_update: last_success(status.providers.iter().map(|p| &p.state)),
+            ip_services: status.ip_services.clone(),
+            stats: None,
+        }
+    }
+}
+
+impl ProviderReport {
+    /// Build the report of a provider from its published addresses and the
+    /// error of querying its record, if any.
+    pub fn new(
+        config: &ProviderConfig,
+        global: IpVersion,
+        ips: &DetectedIps,
+        published: &ProviderState,
+        error: Option<String>,
+    ) -> Self {
+        let ip_version = config.ip_version(global);
+        let records = records(ip_version, ips, published);
+        let sync = SyncStatus::of(&records, error.as_deref());
+        Self {
+            label: config.label(),
+            name: config.name().to_string(),
+            domain: config.display_name(),
+            enabled: config.enabled,
+            ip_version,
+            records,
+            healthy: config.enabled && error.is_none() && published.consecutive_failures == 0,
+            consecutive_failures: published.consecutive_failures,
+            error,
+            sync,
+            in_sync: sync == SyncStatus::InSync,
+            last_updated: None,
+            last_verified_at: None,
+            seconds_since_verified: None,
+            group: config.group.clone(),
+            capabilities: config.capabilities(),
+        }
+        .with_verified(published.last_verified_at, Utc::now())
+    }
+
+    /// Set when a record was last found to hold the detected address, and
+    /// how long before `now` that was.
+    fn with_verified(mut self, at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Self {
+        self.set_verified(at, now);
+        self
+    }
+
+    fn set_verified(&mut self, at: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        self.last_verified_at = at;
+        self.seconds_since_verified = at.map(|at| (now - at).num_seconds().max(0));
+    }
+}
+
+impl RecordReport {
+    /// Whether an address was detected and the record doesn't hold it.
+    pub fn is_mismatch(&self) -> bool {
+        self.detected_ip.is_some() && !self.matches
+    }
+}
+
+/// Compare the published address of each configured family with the
+/// detected one.
+fn records(version: IpVersion, ips: &DetectedIps, published: &ProviderState) -> Vec<RecordReport> {
+    [
+        (
+            version.includes_v4(),
+            RecordType::A,
+            ips.ipv4,
+            published.last_ipv4,
+        ),
+        (
+            version.includes_v6(),
+            RecordType::Aaaa,
+            ips.ipv6,
+            published.last_ipv6,
+        ),
+    ]
+    .into_iter()
+    .filter(|(included, ..)| *included)
+    .map(|(_, record_type, detected_ip, record_ip)| RecordReport {
+        record_type,
+        detected_ip,
+        record_ip,
+        matches: detected_ip.is_some() && detected_ip == record_ip,
+        ttl: None,
+        stale_until: None,
+    })
+    .collect()
+}
+
+fn last_success<'a>(states: impl Iterator<Item = &'a ProviderState>) -> Option<DateTime<Utc>> {
+    states.filter_map(|s| s.last_success).max()
+}
+
+/// Live records compared with the detected addresses, printed by
+/// `rusty-dns diff`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffReport {
+    /// Detected public IPv4 address.
+    pub ipv4: Option<IpAddr>,
+    /// Detected public IPv6 address.
+    pub ipv6: Option<IpAddr>,
+    /// Why the public addresses couldn't be detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection_error: Option<String>,
+    /// One entry per configured address family of each enabled provider.
+    pub records: Vec<RecordDiff>,
+}
+
+/// Whether a record would change on the next update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The record holds the detected address.
+    Ok,
+    /// The record holds another address, or doesn't exist.
+    Changed,
+    /// The record or the public address couldn't be determined.
+    Unknown,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "OK",
+            Self::Changed => "CHANGED",
+            Self::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+/// Where the current value of a record was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordSource {
+    /// The provider's API.
+    Provider,
+    /// A DNS lookup (see `dns_lookup`).
+    Dns,
+}
+
+/// Live record of one provider and address family.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordDiff {
+    /// Provider label.
+    pub label: String,
+    /// Provider type.
+    pub provider: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Record type.
+    pub record_type: RecordType,
+    /// Address the record holds.
+    pub record_ip: Option<IpAddr>,
+    /// Where `record_ip` was read from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<RecordSource>,
+    /// TTL of the record in seconds, if the provider reports it.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// Until when resolvers may still answer with the address the record
+    /// held before its last update (see [`stale_until`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_until: Option<DateTime<Utc>>,
+    /// Detected public address of this family.
+    pub detected_ip: Option<IpAddr>,
+    /// Whether the record would change.
+    pub verdict: Verdict,
+    /// Why the record couldn't be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DiffReport {
+    /// Detect the public addresses and read the live record of each enabled
+    /// provider, without updating anything.
+    pub async fn collect(config: &Config, detector: &IpDetector) -> Self {
+        Self::collect_from(config, detector, &create_providers(config)).await
+    }
+
+    /// Like [`DiffReport::collect`], with one already created provider per
+    /// entry of `config.providers`.
+    pub async fn collect_from(
+        config: &Config,
+        detector: &IpDetector,
+        providers: &[Box<dyn DdnsProvider>],
+    ) -> Self {
+        let (ips, detection_error) = match Updater::new(config, detector).detect().await {
+            Ok(ips) => (ips, None),
+            Err(e) => (DetectedIps::default(), Some(e.to_string())),
+        };
+        let store = history::open(config).ok();
+        let history = store
+            .as_ref()
+            .and_then(|store| store.entries().ok())
+            .unwrap_or_default();
+
+        let mut records = Vec::new();
+        let mut verified = Vec::new();
+        for (provider_config, provider) in config.providers.iter().zip(providers) {
+            // Disabled providers aren't updated, so nothing would change
+            if !provider_config.enabled {
+                continue;
+            }
+
+            let current = if provider.capabilities().can_read_record {
+                provider.get_current_record().await
+            } else {
+                Ok(None)
+            };
+            let version = provider_config.ip_version(config.ip_version);
+            let ips = ips.with_suffix(provider_config.ipv6_suffix, config.ipv6_prefix_length);
+            let first = records.len();
+            for (record_type, detected_ip) in
+                [(RecordType::A, ips.ipv4), (RecordType::Aaaa, ips.ipv6)]
+            {
+                let included = match record_type {
+                    RecordType::A => version.includes_v4(),
+                    RecordType::Aaaa => version.includes_v6(),
+                };
+                if !included {
+                    continue;
+                }
+
+                // The API reports a single record, of either family
+                let (record_ip, source, ttl, error) = match &current {
+                    Ok(Some(record)) if record.record_type == record_type => (
+                        Some(record.ip),
+                        Some(RecordSource::Provider),
+                        record.ttl,
+                        None,
+                    ),
+                    Err(e) => (None, None, None, Some(e.to_string())),
+                    Ok(_) if config.dns_lookup => {
+                        match resolve_record(&provider.domain(), record_type).await {
+                            Ok(ip) => (ip, ip.map(|_| RecordSource::Dns), None, None),
+                            Err(e) => (None, None, None, Some(e.to_string())),
+                        }
+                    }
+                    Ok(_) => (None, None, None, None),
+                };
+                let label = provider_config.label();
+                let stale_until = stale_until(last_changed(&history, &label, record_type), ttl);
+
+                let verdict = match (record_ip, detected_ip) {
+                    (Some(record), Some(detected)) if record == detected => Verdict::Ok,
+                    (Some(_), Some(_)) => Verdict::Changed,
+                    _ => Verdict::Unknown,
+                };
+
+                records.push(RecordDiff {
+                    label,
+                    provider: provider_config.name().to_string(),
+                    domain: provider_config.display_name(),
+                    record_type,
+                    record_ip,
+                    source,
+                    ttl,
+                    stale_until,
+                    detected_ip,
+                    verdict,
+                    error,
+                });
+            }
+
+            let compared = &records[first..];
+            if compared.iter().any(|r| r.verdict == Verdict::Ok)
+                && compared.iter().all(|r| r.verdict != Verdict::Changed)
+            {
+                verified.push(provider_config.label());
+            }
+        }
+
+        if !verified.is_empty() {
+            mark_verified(store.as_ref(), &verified);
+        }
+
+        Self {
+            ipv4: ips.ipv4,
+            ipv6: ips.ipv6,
+            detection_error,
+            records,
+        }
+    }
+
+    /// Whether any record would change.
+    pub fn has_changes(&self) -> bool {
+        self.records.iter().any(|r| r.verdict == Verdict::Changed)
+    }
+}
+
+/// Public addresses reported by `rusty-dns ip`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpReport {
+    /// Detected public IPv4 address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<Detection>,
+    /// Detected public IPv6 address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<Detection>,
+    /// Why a requested family couldn't be detected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Answer of every service, when all were queried.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceProbe>,
+    /// Health of the services, including these answers, when all were
+    /// queried.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub health: BTreeMap<String, ServiceHealth>,
+}
+
+impl IpReport {
+    /// Detect the requested families concurrently (see
+    /// [`IpDetector::detect_version`]), or query every service if
+    /// `all_services` is set (the first valid answer in service order is then
+    /// reported).
+    pub async fn collect(detector: &IpDetector, version: IpVersion, all_services: bool) -> Self {
+        if !all_services {
+            let detected = detector.detect_version(version, false).await;
+            return Self {
+                ipv4: detected.source(false).cloned(),
+                ipv6: detected.source(true).cloned(),
+                errors: detected.errors,
+                ..Self::default()
+            };
+        }
+
+        let mut report = Self::default();
+        for (included, ipv6) in [
+            (version.includes_v4(), false),
+            (version.includes_v6(), true),
+        ] {
+            if !included {
+                continue;
+            }
+
+            let probes = detector.probe_all(ipv6).await;
+            let detection = probes.iter().find_map(|p| {
+                p.ip.map(|ip| Detection {
+                    ip,
+                    service: p.service.clone(),
+                    latency_ms: p.latency_ms,
+                    cached: false,
+                    prefix: p.prefix,
+                })
+            });
+            report.services.extend(probes);
+
+            match detection {
+                Some(detection) if ipv6 => report.ipv6 = Some(detection),
+                Some(detection) => report.ipv4 = Some(detection),
+                None => report.errors.push(format!(
+                    "All {} detection services failed",
+                    if ipv6 { "IPv6" } else { "IPv4" }
+                )),
+            }
+        }
+
+        report.health = detector.health();
+        report
+    }
+}
+
+/// What happened to one record in an update run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateStatus {
+    /// The provider accepted the new address.
+    Updated,
+    /// Skipped because the record already holds the address.
+    Unchanged,
+    /// The provider can't manage this record type.
+    Unsupported,
+    /// Not sent, for the reason in [`UpdateReport::reason`].
+    Skipped,
+    /// The provider rejected the update.
+    Failed,
+}
+
+/// Update of one record of a configured provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateReport {
+    /// Label of the configured provider entry.
+    pub label: String,
+    /// Provider type.
+    pub provider: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Record type (address family).
+    pub record_type: RecordType,
+    /// Address that was (or would have been) published.
+    pub ip: IpAddr,
+    /// Why the update was sent, or skipped.
+    pub reason: UpdateReason,
+    /// What happened.
+    pub status: UpdateStatus,
+    /// Address the record held before the update (if known).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_ip: Option<IpAddr>,
+    /// Error message if the update failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why the update failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<UpdateErrorKind>,
+}
+
+impl From<&ProviderUpdate> for UpdateReport {
+    fn from(update: &ProviderUpdate) -> Self {
+        let (status, previous_ip) = match &update.outcome {
+            UpdateOutcome::Unchanged => (UpdateStatus::Unchanged, None),
+            UpdateOutcome::Unsupported => (UpdateStatus::Unsupported, None),
+            UpdateOutcome::Skipped => (UpdateStatus::Skipped, None),
+            UpdateOutcome::Attempted(result) if result.success => {
+                (UpdateStatus::Updated, result.previous_ip)
+            }
+            UpdateOutcome::Attempted(result) => (UpdateStatus::Failed, result.previous_ip),
+        };
+
+        Self {
+            label: update.label.clone(),
+            provider: update.provider.to_string(),
+            domain: update.domain.clone(),
+            record_type: update.record_type(),
+            ip: update.ip,
+            reason: update.reason,
+            status,
+            previous_ip,
+            error: update.error().map(str::to_string),
+            error_kind: update.error_kind(),
+        }
+    }
+}
+
+/// Results of one detection + update cycle of the daemon.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CycleReport {
+    /// Detected public IPv4 address.
+    pub ipv4: Option<IpAddr>,
+    /// Detected public IPv6 address.
+    pub ipv6: Option<IpAddr>,
+    /// Why the cycle failed: the addresses couldn't be detected, or the
+    /// state couldn't be saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Updates of the records that didn't hold the detected addresses.
+    pub updates: Vec<UpdateReport>,
+}
+
+impl CycleReport {
+    /// Create a report for a cycle that failed outright.
+    pub fn failed(error: impl std::fmt::Display) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+/// Result of validating the configuration and provider credentials.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// Whether the configuration and every enabled provider passed.
+    pub valid: bool,
+    /// Why the configuration is invalid (providers aren't checked then).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Suspicious but usable settings, including those providers report.
+    pub warnings: Vec<ValidationWarning>,
+    /// One entry per configured provider.
+    pub providers: Vec<ProviderValidation>,
+}
+
+/// Outcome of checking one provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// The provider accepted the credentials.
+    Passed,
+    /// The provider rejected the credentials or couldn't be reached.
+    Failed,
+    /// The provider is disabled.
+    Skipped,
+    /// The provider can't make the check (live checks only).
+    Unsupported,
+}
+
+/// Outcome of a live check of one provider: reading its record, or
+/// writing it back unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveCheck {
+    /// Outcome of the check.
+    pub status: CheckStatus,
+    /// What was found, or why the check failed or couldn't be made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl LiveCheck {
+    fn new(status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/// Validation of one configured provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderValidation {
+    /// Provider label.
+    pub label: String,
+    /// Provider type.
+    pub name: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// File the entry was loaded from (none for the environment).
+    pub source: Option<PathBuf>,
+    /// TTL written on update, if the provider sets one.
+    pub ttl: Option<u32>,
+    /// Outcome of the check.
+    pub status: CheckStatus,
+    /// Error message if the check failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Reading the record, for live validations of providers whose
+    /// credentials passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read: Option<LiveCheck>,
+    /// Writing the record back unchanged (see
+    /// [`DdnsProvider::probe_write`]), like `read`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<LiveCheck>,
+    /// Round-trip time of the credential check (none for disabled
+    /// providers).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Latency>,
+}
+
+/// Round-trip time of one or more calls to a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Latency {
+    /// Number of calls timed.
+    pub samples: u32,
+    /// Fastest call, in milliseconds.
+    pub min_ms: u64,
+    /// Average call, in milliseconds.
+    pub avg_ms: u64,
+}
+
+impl Latency {
+    /// Summarize the durations of calls, `None` if there are none.
+    pub fn of(durations: &[Duration]) -> Option<Self> {
+        let min = durations.iter().min()?;
+        let total: Duration = durations.iter().sum();
+        Some(Self {
+            samples: durations.len() as u32,
+            min_ms: min.as_millis() as u64,
+            avg_ms: (total / durations.len() as u32).as_millis() as u64,
+        })
+    }
+}
+
+impl std::fmt::Display for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.samples > 1 {
+            write!(f, "min {}ms, avg {}ms", self.min_ms, self.avg_ms)
+        } else {
+            write!(f, "{}ms", self.avg_ms)
+        }
+    }
+}
+
+/// Times the credentials of a provider are checked to time them
+/// (`validate --timing`).
+pub const TIMING_SAMPLES: u32 = 3;
+
+/// Check the credentials of `provider` up to `samples` times, timing each
+/// call. Checks stop at the first failure, which is returned. The provider
+/// is [prepared](DdnsProvider::prepare) first, outside the timing.
+pub async fn timed_validate(
+    provider: &dyn DdnsProvider,
+    samples: u32,
+) -> (crate::error::Result<()>, Option<Latency>) {
+    if let Err(e) = provider.prepare().await {
+        return (Err(e), None);
+    }
+    let mut durations = Vec::new();
+    let mut result = Ok(());
+    for _ in 0..samples.max(1) {
+        let start = Instant::now();
+        result = provider.validate().await;
+        durations.push(start.elapsed());
+        if result.is_err() {
+            break;
+        }
+    }
+    (result, Latency::of(&durations))
+}
+
+/// Read the record of `provider` and write it back unchanged.
+async fn check_live(provider: &dyn DdnsProvider) -> (LiveCheck, LiveCheck) {
+    let read = if provider.capabilities().can_read_record {
+        provider.get_current_ip().await
+    } else {
+        Ok(None)
+    };
+    let read = match read {
+        Ok(Some(ip)) => LiveCheck::new(CheckStatus::Passed, format!("holds {}", ip)),
+        Ok(None) => LiveCheck::new(
+            CheckStatus::Unsupported,
+            format!("{} can't report the current record", provider.name()),
+        ),
+        Err(e) => LiveCheck::new(CheckStatus::Failed, e.to_string()),
+    };
+    let write = match provider.probe_write().await {
+        Ok(WriteProbe::Written(message)) => LiveCheck::new(CheckStatus::Passed, message),
+        Ok(WriteProbe::Unsupported(message)) => LiveCheck::new(CheckStatus::Unsupported, message),
+        Err(e) => LiveCheck::new(CheckStatus::Failed, e.to_string()),
+    };
+    (read, write)
+}
+
+impl ValidationReport {
+    /// Validate the configuration, then the credentials of each enabled
+    /// provider.
+    pub async fn collect(config: &Config) -> Self {
+        Self::collect_with(config, false, 1).await
+    }
+
+    /// [`ValidationReport::collect`], then read the record of each provider
+    /// that passed and write it back unchanged, so a token that can read
+    /// but not write fails here rather than on the first address change.
+    ///
+    /// Providers with no way to write without changing the record report
+    /// [`CheckStatus::Unsupported`], which doesn't fail the validation.
+    pub async fn collect_live(config: &Config) -> Self {
+        Self::collect_with(config, true, 1).await
+    }
+
+    /// [`ValidationReport::collect`], or [`ValidationReport::collect_live`]
+    /// if `live` is set, checking the credentials of each provider `samples`
+    /// times so their [`Latency`] is more than a single call.
+    pub async fn collect_with(config: &Config, live: bool, samples: u32) -> Self {
+        let mut warnings = match config.validate() {
+            Ok(warnings) => warnings,
+            Err(e) => {
+                return Self {
+                    valid: false,
+                    error: Some(e.to_string()),
+                    warnings: Vec::new(),
+                    providers: Vec::new(),
+                }
+            }
+        };
+
+        let mut providers = Vec::new();
+        for provider_config in &config.providers {
+            let provider = create_provider(provider_config, config.default_ttl, &config.network);
+            let (validation, latency) = if provider_config.enabled {
+                timed_validate(provider.as_ref(), samples).await
+            } else {
+                (Ok(()), None)
+            };
+            let (status, message) = if !provider_config.enabled {
+                (CheckStatus::Skipped, None)
+            } else {
+                match validation {
+                    Ok(()) => {
+                        warnings.extend(provider.warnings().await.into_iter().map(|message| {
+                            ValidationWarning {
+                                label: provider_config.label(),
+                                message,
+                            }
+                        }));
+                        (CheckStatus::Passed, None)
+                    }
+                    Err(e) => (CheckStatus::Failed, Some(e.to_string())),
+                }
+            };
+            let (read, write) = if live && status == CheckStatus::Passed {
+                let (read, write) = check_live(provider.as_ref()).await;
+                (Some(read), Some(write))
+            } else {
+                (None, None)
+            };
+
+            providers.push(ProviderValidation {
+                label: provider_config.label(),
+                name: provider.name().to_string(),
+                domain: provider.domain(),
+                source: provider_config.source.clone(),
+                ttl: provider_config.ttl(config.default_ttl),
+                status,
+                message,
+                read,
+                write,
+                latency,
+            });
+        }
+
+        let failed = |check: &Option<LiveCheck>| matches!(check, Some(check) if check.status == CheckStatus::Failed);
+        Self {
+            valid: providers
+                .iter()
+                .all(|p| p.status != CheckStatus::Failed && !failed(&p.read) && !failed(&p.write)),
+            error: None,
+            warnings,
+            providers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{CloudflareProvider, DuckDnsProvider, UpdateResult};
+    use crate::state::State;
+    use crate::testing::{MockCall, MockProvider, MockUpdate};
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn ips() -> DetectedIps {
+        DetectedIps {
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_records_match_detected() {
+        let published = ProviderState {
+            last_ipv4: Some("1.2.3.4".parse().unwrap()),
+            last_ipv6: Some("2001:db8::2".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let both = records(IpVersion::Both, &ips(), &published);
+        assert_eq!(both.len(), 2);
+        assert_eq!(both[0].record_type, RecordType::A);
+        assert!(both[0].matches);
+        assert_eq!(both[1].record_type, RecordType::Aaaa);
+        assert!(!both[1].matches);
+
+        // Nothing detected or published never matches
+        let v4 = records(IpVersion::V4, &DetectedIps::default(), &published);
+        assert_eq!(v4.len(), 1);
+        assert!(!v4[0].matches);
+    }
+
+    #[test]
+    fn test_update_report() {
+        let result = |success: bool| UpdateResult {
+            provider: "duckdns".to_string(),
+            label: None,
+            domain: "home.duckdns.org".to_string(),
+            record_type: RecordType::A,
+            success,
+            ip: Some("1.2.3.4".parse().unwrap()),
+            previous_ip: Some("5.6.7.8".parse().unwrap()),
+            error: (!success).then(|| "KO".to_string()),
+            error_kind: (!success).then_some(UpdateErrorKind::Auth),
+            timestamp: Utc::now(),
+        };
+        let update = |outcome| ProviderUpdate {
+            label: "home".to_string(),
+            provider: "duckdns",
+            domain: "home.duckdns.org".to_string(),
+            ip: "1.2.3.4".parse().unwrap(),
+            reason: UpdateReason::Changed,
+            outcome,
+            verified: false,
+        };
+
+        let report = UpdateReport::from(&update(UpdateOutcome::Attempted(result(true))));
+        assert_eq!(report.status, UpdateStatus::Updated);
+        assert_eq!(report.previous_ip, Some("5.6.7.8".parse().unwrap()));
+
+        let report = UpdateReport::from(&update(UpdateOutcome::Attempted(result(false))));
+        assert_eq!(report.status, UpdateStatus::Failed);
+        assert_eq!(report.error.as_deref(), Some("KO"));
+
+        let json =
+            serde_json::to_value(UpdateReport::from(&update(UpdateOutcome::Unchanged))).unwrap();
+        assert_eq!(json["status"], "unchanged");
+        assert_eq!(json["record_type"], "A");
+        assert_eq!(json["reason"], "changed");
+        assert!(json.get("error").is_none());
+    }
+
+    /// Collect the status of one Cloudflare provider, with the public
+    /// address and the record served by mock servers.
+    async fn check(detected: ResponseTemplate, record: ResponseTemplate) -> StatusReport {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(detected)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records"))
+            .respond_with(record)
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-report-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!(
+            r#"
+            state_dir = "{}"
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+            "#,
+            state_dir.display()
+        ))
+        .unwrap();
+
+        let providers: Vec<Box<dyn DdnsProvider>> =
+            vec![Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                "zone".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                server.uri(),
+            ))];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+        StatusReport::collect_from(&config, &detector, &providers).await
+    }
+
+    fn record(content: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_string(format!(
+            r#"{{"success":true,"result":[{{"id":"record-123","content":"{}"}}],"errors":[]}}"#,
+            content
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_health_ok() {
+        let report = check(
+            ResponseTemplate::new(200).set_body_string("1.2.3.4\n"),
+            record("1.2.3.4"),
+        )
+        .await;
+        assert_eq!(report.health(), Health::Ok);
+        assert_eq!(report.health().exit_code(), 0);
+        assert!(report.providers[0].records[0].matches);
+        assert_eq!(report.providers[0].sync, SyncStatus::InSync);
+        assert!(report.providers[0].in_sync);
+    }
+
+    #[tokio::test]
+    async fn test_health_mismatch() {
+        let report = check(
+            ResponseTemplate::new(200).set_body_string("1.2.3.4"),
+            record("5.6.7.8"),
+        )
+        .await;
+        assert_eq!(report.health(), Health::Mismatch);
+        assert_eq!(report.health().exit_code(), 1);
+        assert_eq!(
+            report.providers[0].records[0].record_ip,
+            Some("5.6.7.8".parse().unwrap())
+        );
+        assert_eq!(report.providers[0].sync, SyncStatus::Stale);
+        assert!(!report.providers[0].in_sync);
+    }
+
+    #[tokio::test]
+    async fn test_health_detection_failed() {
+        let report = check(ResponseTemplate::new(500), record("1.2.3.4")).await;
+        assert_eq!(report.health(), Health::DetectionFailed);
+        assert_eq!(report.health().exit_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_last_known_when_detection_fails() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-last-known-{}", std::process::id()));
+        let config = Config {
+            state_dir: Some(state_dir.clone()),
+            ..Config::default()
+        };
+        let mut state = State::default();
+        state.set_last_ip("1.2.3.4".parse().unwrap());
+        state.save_to(&State::path_in(&state_dir)).unwrap();
+
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+        let report = StatusReport::with_records(&config, &detector, &[]).await;
+        assert!(report.detection_error.is_some());
+        assert_eq!(report.last_known.len(), 1);
+        assert_eq!(
+            report.last_known[0].ip,
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+        assert!(report.last_known[0].confirmed.is_some());
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health_unhealthy() {
+        let report = check(
+            ResponseTemplate::new(200).set_body_string("1.2.3.4"),
+            ResponseTemplate::new(500),
+        )
+        .await;
+        assert_eq!(report.health(), Health::Unhealthy);
+        assert_eq!(report.health().exit_code(), 3);
+        assert!(report.providers[0].error.is_some());
+        assert!(!report.providers[0].healthy);
+        assert_eq!(report.providers[0].sync, SyncStatus::Error);
+    }
+
+    #[test]
+    fn test_group_health() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [groups.home]
+            policy = "primary-failover"
+
+            [[providers]]
+            type = "duckdns"
+            domains = "primary"
+            token = "token"
+            group = "home"
+
+            [[providers]]
+            type = "duckdns"
+            domains = "fallback"
+            token = "token"
+            group = "home"
+            "#,
+        )
+        .unwrap();
+        let ips = DetectedIps {
+            ipv6: None,
+            ..ips()
+        };
+        let report = |config: &Config, failing: &str| {
+            StatusReport {
+                ipv4: ips.ipv4,
+                ipv6: None,
+                detection_error: None,
+                last_known: Vec::new(),
+                network_situation: None,
+                daemon: None,
+                tunnel: None,
+                providers: config
+                    .providers
+                    .iter()
+                    .map(|p| {
+                        let published = ProviderState {
+                            last_ipv4: ips.ipv4,
+                            consecutive_failures: u32::from(p.label().contains(failing)),
+                            ..Default::default()
+                        };
+                        ProviderReport::new(p, config.ip_version, &ips, &published, None)
+                    })
+                    .collect(),
+                groups: Vec::new(),
+                last_update: None,
+                ip_services: BTreeMap::new(),
+                stats: None,
+            }
+            .with_groups(config)
+        };
+
+        // A failing fallback leaves the group healthy
+        let status = report(&config, "fallback");
+        assert_eq!(status.providers[1].group.as_deref(), Some("home"));
+        let group = &status.groups[0];
+        assert_eq!(group.members.len(), 2);
+        assert_eq!(
+            group.primary.as_deref(),
+            Some("duckdns-primary.duckdns.org")
+        );
+        assert!(group.healthy);
+        assert_eq!(status.health(), Health::Ok);
+
+        let status = report(&config, "primary");
+        assert!(!status.groups[0].healthy);
+        assert_eq!(status.health(), Health::Unhealthy);
+
+        // Every member must be healthy by default
+        config.groups.clear();
+        let status = report(&config, "fallback");
+        assert_eq!(status.groups[0].policy, GroupPolicy::All);
+        assert_eq!(status.groups[0].primary, None);
+        assert!(!status.groups[0].healthy);
+        assert_eq!(status.health(), Health::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_with_records_reuses_records() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records"))
+            .respond_with(record("1.2.3.4"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-watch-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!(
+            r#"
+            state_dir = "{}"
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "home"
+            enabled = false
+            "#,
+            state_dir.display()
+        ))
+        .unwrap();
+        let providers: Vec<Box<dyn DdnsProvider>> = vec![
+            Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                "zone".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                server.uri(),
+            )),
+            Box::new(Blind),
+        ];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        let records = query_records(&config, &providers).await;
+        assert_eq!(
+            records[0],
+            Some(Ok(vec![RecordInfo::new("1.2.3.4".parse().unwrap(), None)]))
+        );
+        assert_eq!(records[1], None);
+
+        // The addresses are detected again, the records aren't queried
+        for _ in 0..2 {
+            let report = StatusReport::with_records(&config, &detector, &records).await;
+            assert_eq!(report.health(), Health::Ok);
+            assert!(report.providers[0].records[0].matches);
+        }
+    }
+
+    #[test]
+    fn test_sync_status() {
+        let record = |detected: Option<&str>, published: Option<&str>| {
+            let detected_ip = detected.map(|ip| ip.parse().unwrap());
+            let record_ip = published.map(|ip| ip.parse().unwrap());
+            RecordReport {
+                record_type: RecordType::A,
+                detected_ip,
+                record_ip,
+                matches: detected_ip.is_some() && detected_ip == record_ip,
+                ttl: None,
+                stale_until: None,
+            }
+        };
+        let in_sync = record(Some("1.2.3.4"), Some("1.2.3.4"));
+
+        assert_eq!(
+            SyncStatus::of(std::slice::from_ref(&in_sync), None),
+            SyncStatus::InSync
+        );
+        // A family that wasn't detected can't be compared
+        assert_eq!(
+            SyncStatus::of(&[in_sync.clone(), record(None, Some("2001:db8::1"))], None),
+            SyncStatus::InSync
+        );
+        assert_eq!(
+            SyncStatus::of(
+                &[in_sync.clone(), record(Some("1.2.3.4"), Some("5.6.7.8"))],
+                None
+            ),
+            SyncStatus::Stale
+        );
+        assert_eq!(
+            SyncStatus::of(&[record(Some("1.2.3.4"), None)], None),
+            SyncStatus::Unknown
+        );
+        assert_eq!(
+            SyncStatus::of(&[record(None, None)], None),
+            SyncStatus::Unknown
+        );
+        assert_eq!(
+            SyncStatus::of(&[in_sync], Some("HTTP 500")),
+            SyncStatus::Error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_dns_fallback_and_history() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-status-sync-{}", std::process::id()));
+        let mut config: Config = toml::from_str(&format!(
+            r#"
+            state_dir = "{}"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "home"
+            "#,
+            state_dir.display()
+        ))
+        .unwrap();
+        let providers: Vec<Box<dyn DdnsProvider>> = vec![Box::new(Blind)];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        // Without a DNS lookup, the record of a blind provider is unknown
+        let report = StatusReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(report.providers[0].sync, SyncStatus::Unknown);
+        assert_eq!(report.health(), Health::Mismatch);
+        assert_eq!(report.providers[0].last_updated, None);
+        assert_eq!(report.providers[0].last_verified_at, None);
+
+        let label = config.providers[0].label();
+        let updated = Utc::now() - chrono::Duration::hours(2);
+        let entry = |success: bool, timestamp: DateTime<Utc>| HistoryEntry {
+            result: UpdateResult {
+                provider: "duckdns".to_string(),
+                label: Some(label.clone()),
+                domain: "home.duckdns.org".to_string(),
+                record_type: RecordType::A,
+                success,
+                ip: Some("127.0.0.1".parse().unwrap()),
+                previous_ip: None,
+                error: None,
+                error_kind: None,
+                timestamp,
+            },
+            reason: UpdateReason::default(),
+            source: Default::default(),
+        };
+        history::append(
+            &history::path_in(&state_dir),
+            &[entry(true, updated), entry(false, Utc::now())],
+            100,
+        )
+        .unwrap();
+
+        config.dns_lookup = true;
+        let report = StatusReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(
+            report.providers[0].records[0].record_ip,
+            Some("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(report.providers[0].sync, SyncStatus::Stale);
+        assert_eq!(report.health(), Health::Mismatch);
+        // Failed updates don't count
+        assert_eq!(report.providers[0].last_updated, Some(updated));
+        assert_eq!(report.providers[0].last_verified_at, None);
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_caches_after_update() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":[{"id":"record-123","content":"1.2.3.4","ttl":600}],"errors":[]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-stale-caches-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!(
+            r#"
+            state_dir = "{}"
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+            "#,
+            state_dir.display()
+        ))
+        .unwrap();
+        let providers: Vec<Box<dyn DdnsProvider>> =
+            vec![Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                "zone".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                server.uri(),
+            ))];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        // Not updated yet: the TTL is known, but no cache holds an old address
+        let report = StatusReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(report.providers[0].records[0].ttl, Some(600));
+        assert_eq!(report.providers[0].records[0].stale_until, None);
+
+        // The record was read holding the detected address, which is saved
+        let label = config.providers[0].label();
+        let verified = report.providers[0].last_verified_at.unwrap();
+        assert_eq!(report.providers[0].seconds_since_verified, Some(0));
+        let verified_at = || {
+            history::open(&config)
+                .unwrap()
+                .load_state()
+                .unwrap()
+                .providers[&label]
+                .last_verified_at
+        };
+        assert_eq!(verified_at(), Some(verified));
+        let entry = |previous_ip: &str, timestamp: DateTime<Utc>| HistoryEntry {
+            result: UpdateResult {
+                provider: "cloudflare".to_string(),
+                label: Some(label.clone()),
+                domain: "vpn.example.com".to_string(),
+                record_type: RecordType::A,
+                success: true,
+                ip: Some("1.2.3.4".parse().unwrap()),
+                previous_ip: Some(previous_ip.parse().unwrap()),
+                error: None,
+                error_kind: None,
+                timestamp,
+            },
+            reason: UpdateReason::default(),
+            source: Default::default(),
+        };
+        let changed = Utc::now() - chrono::Duration::minutes(2);
+        history::append(
+            &history::path_in(&state_dir),
+            // A forced update to the same address changes nothing
+            &[
+                entry("5.6.7.8", changed),
+                entry("1.2.3.4", Utc::now() - chrono::Duration::minutes(1)),
+            ],
+            100,
+        )
+        .unwrap();
+
+        let until = Some(changed + chrono::Duration::seconds(600));
+        let report = StatusReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(report.providers[0].records[0].stale_until, until);
+        let diff = DiffReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(diff.records[0].ttl, Some(600));
+        assert_eq!(diff.records[0].stale_until, until);
+        assert!(verified_at().unwrap() > verified);
+
+        // Caches have expired one TTL after the change
+        assert_eq!(stale_until(Some(changed), Some(60)), None);
+        assert_eq!(stale_until(Some(changed), None), None);
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_status_queries_each_provider_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/zone/dns_records"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"success":true,"result":[{"id":"record-123","content":"1.2.3.4","ttl":600}],"errors":[]}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/client/v4/zones/denied/dns_records"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(
+                r#"{"success":false,"result":null,"errors":[{"code":10000,"message":"Authentication error"}]}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Validating DuckDNS only checks its settings
+        Mock::given(path("/update"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let state_dir =
+            std::env::temp_dir().join(format!("rusty-dns-status-once-{}", std::process::id()));
+        let config: Config = toml::from_str(&format!(
+            r#"
+            state_dir = "{}"
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "denied"
+            record_name = "nas.example.com"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "home"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "cabin"
+            "#,
+            state_dir.display()
+        ))
+        .unwrap();
+        let cloudflare = |zone: &str, name: &str| {
+            Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                zone.to_string(),
+                name.parse().unwrap(),
+                None,
+                server.uri(),
+            ))
+        };
+        let providers: Vec<Box<dyn DdnsProvider>> = vec![
+            cloudflare("zone", "vpn.example.com"),
+            cloudflare("denied", "nas.example.com"),
+            Box::new(DuckDnsProvider::with_base_url(
+                "home".to_string(),
+                "token",
+                server.uri(),
+            )),
+            Box::new(DuckDnsProvider::with_base_url(
+                "cabin".to_string(),
+                "",
+                server.uri(),
+            )),
+        ];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        // Reading the record is enough to know the credentials work
+        let report = StatusReport::collect_from(&config, &detector, &providers).await;
+        let healthy: Vec<_> = report.providers.iter().map(|p| p.healthy).collect();
+        assert_eq!(healthy, [true, false, true, false]);
+        assert!(report.providers[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Authentication error"));
+        assert!(report.providers[3]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Token is empty"));
+
+        server.verify().await;
+        std::fs::remove_dir_all(&state_dir).ok();
+    }
+
+    /// Provider whose API can't report the current record.
+    struct Blind;
+
+    #[async_trait::async_trait]
+    impl DdnsProvider for Blind {
+        fn name(&self) -> &'static str {
+            "duckdns"
+        }
+
+        fn domain(&self) -> String {
+            "127.0.0.1".to_string()
+        }
+
+        async fn get_current_ip(&self) -> crate::error::Result<Option<IpAddr>> {
+            Ok(None)
+        }
+
+        async fn update_ip(&self, _ip: IpAddr) -> crate::error::Result<UpdateResult> {
+            unreachable!("diff never updates")
+        }
+
+        async fn validate(&self) -> crate::error::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff_report() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/client/v4/zones/.*/dns_records"))
+            .respond_with(record("5.6.7.8"))
+            .mount(&server)
+            .await;
+
+        let mut config: Config = toml::from_str(
+            r#"
+            [[providers]]
+            type = "cloudflare"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "vpn.example.com"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "home"
+
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "cabin"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let providers: Vec<Box<dyn DdnsProvider>> = vec![
+            Box::new(CloudflareProvider::with_base_url(
+                "token".to_string(),
+                "zone".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                server.uri(),
+            )),
+            Box::new(Blind),
+            Box::new(Blind),
+        ];
+        let detector = IpDetector::with_services(vec![format!("{}/ip", server.uri())]);
+
+        let report = DiffReport::collect_from(&config, &detector, &providers).await;
+        assert!(report.has_changes());
+        assert_eq!(report.records.len(), 2);
+        assert_eq!(report.records[0].verdict, Verdict::Changed);
+        assert_eq!(report.records[0].source, Some(RecordSource::Provider));
+        assert_eq!(
+            report.records[0].record_ip,
+            Some("5.6.7.8".parse().unwrap())
+        );
+        assert_eq!(report.records[1].domain, "home.duckdns.org");
+        assert_eq!(report.records[1].verdict, Verdict::Unknown);
+        assert_eq!(report.records[1].record_ip, None);
+
+        // Looked up through DNS when enabled
+        config.dns_lookup = true;
+        let report = DiffReport::collect_from(&config, &detector, &providers).await;
+        assert_eq!(report.records[1].source, Some(RecordSource::Dns));
+        assert_eq!(
+            report.records[1].record_ip,
+            Some("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(report.records[1].verdict, Verdict::Changed);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["records"][1]["verdict"], "changed");
+        assert_eq!(json["records"][1]["source"], "dns");
+    }
+
+    #[tokio::test]
+    async fn test_validation_report() {
+        let config: Config = toml::from_str(
+            r#"
+            [[providers]]
+            type = "duckdns"
+            token = "token"
+            domains = "home"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+
+        let report = ValidationReport::collect(&config).await;
+        assert!(report.valid);
+        assert_eq!(report.providers[0].status, CheckStatus::Skipped);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["providers"][0]["status"], "skipped");
+        assert_eq!(json["providers"][0]["domain"], "home.duckdns.org");
+
+        let mut invalid = config.clone();
+        invalid.providers[0].enabled = true;
+        if let crate::config::ProviderKind::DuckDns { token, .. } = &mut invalid.providers[0].kind {
+            token.expose_mut().clear();
+        }
+        let report = ValidationReport::collect(&invalid).await;
+        assert!(!report.valid);
+        assert!(report.error.unwrap().contains("Invalid provider settings"));
+        assert!(report.providers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validation_latency() {
+        let mock = MockProvider::new("timed.example");
+        let mut disabled = MockProvider::new("off.example").register("mock-latency-off");
+        disabled.enabled = false;
+        let config = Config {
+            providers: vec![mock.register("mock-latency"), disabled],
+            ..Config::default()
+        };
+
+        let report = ValidationReport::collect(&config).await;
+        assert_eq!(report.providers[0].latency.unwrap().samples, 1);
+        assert!(report.providers[1].latency.is_none());
+
+        let report = ValidationReport::collect_with(&config, false, TIMING_SAMPLES).await;
+        let latency = report.providers[0].latency.unwrap();
+        assert_eq!(latency.samples, 3);
+        assert!(latency.min_ms <= latency.avg_ms);
+        let validations = mock
+            .calls()
+            .iter()
+            .filter(|c| **c == MockCall::Validate)
+            .count();
+        assert_eq!(validations, 4);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["providers"][0]["latency"]["samples"], 3);
+        assert!(json["providers"][1].get("latency").is_none());
+
+        let durations = [100, 300, 200].map(Duration::from_millis);
+        let latency = Latency::of(&durations).unwrap();
+        assert_eq!((latency.min_ms, latency.avg_ms), (100, 200));
+        assert_eq!(latency.to_string(), "min 100ms, avg 200ms");
+        assert_eq!(
+            Latency::of(&[Duration::from_millis(42)])
+                .unwrap()
+                .to_string(),
+            "42ms"
+        );
+        assert_eq!(Latency::of(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_validation_report_live() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let writable = MockProvider::new("a.example").with_record(ip);
+        let read_only = MockProvider::new("b.example").with_record(ip);
+        read_only.push_update(MockUpdate::Fail("token can't edit DNS".to_string()));
+        let missing = MockProvider::new("c.example");
+        let config = Config {
+            providers: vec![
+                writable.register("mock-live-writable"),
+                read_only.register("mock-live-read-only"),
+                missing.register("mock-live-missing"),
+            ],
+            ..Config::default()
+        };
+
+        // Nothing is written without --live
+        let report = ValidationReport::collect(&config).await;
+        assert!(report.valid);
+        assert!(report.providers[0].write.is_none());
+        assert!(!writable.calls().contains(&MockCall::ProbeWrite));
+
+        let report = ValidationReport::collect_live(&config).await;
+        assert!(!report.valid);
+        let read = report.providers[0].read.as_ref().unwrap();
+        assert_eq!(read.status, CheckStatus::Passed);
+        assert_eq!(read.message.as_deref(), Some("holds 192.0.2.1"));
+        let write = report.providers[0].write.as_ref().unwrap();
+        assert_eq!(write.status, CheckStatus::Passed);
+        // Read fine, but the write was rejected
+        assert_eq!(
+            report.providers[1].read.as_ref().unwrap().status,
+            CheckStatus::Passed
+        );
+        let write = report.providers[1].write.as_ref().unwrap();
+        assert_eq!(write.status, CheckStatus::Failed);
+        assert!(write.message.as_deref().unwrap().contains("can't edit DNS"));
+        // Nothing to write back doesn't fail the validation
+        let write = report.providers[2].write.as_ref().unwrap();
+        assert_eq!(write.status, CheckStatus::Unsupported);
+        assert_eq!(writable.record(), Some(ip));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["providers"][1]["write"]["status"], "failed");
+        assert_eq!(json["providers"][2]["write"]["status"], "unsupported");
+    }
+}
@@ -0,0 +1,268 @@
+//! JSON Schema of the config file (`rusty-dns schema`).
+//!
+//! The schema is derived from the config types, so it follows them without
+//! being maintained by hand; `config/rusty-dns.schema.json` is a copy kept
+//! in sync by a test, for editors to point at. It describes a single file
+//! as written, before environment references are expanded: checking a file
+//! against it needs neither secrets nor provider APIs.
+
+use crate::config::{self, Config};
+use crate::error::{DdnsError, Result};
+use jsonschema::error::ValidationErrorKind;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Get the JSON Schema of the config file.
+pub fn config_schema() -> serde_json::Value {
+    let mut schema =
+        serde_json::to_value(schemars::schema_for!(Config)).expect("schema serializes to JSON");
+    schema["title"] = "rusty-dns configuration".into();
+    // Read before the rest of the config, so not a field of it
+    schema["properties"]["profiles"] = serde_json::json!({
+        "description": "Named sets of settings overriding the rest of the config when selected \
+                        with `--profile` or `RUSTY_DNS_PROFILE`.",
+        "type": "object",
+        "additionalProperties": { "type": "object" }
+    });
+    schema
+}
+
+/// A place where a config file doesn't match the schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaError {
+    /// Location of the offending value, as a JSON pointer (e.g.
+    /// `/providers/0/zone_id`; empty for the whole file).
+    pub path: String,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.path.as_str() {
+            "" => f.write_str(&self.message),
+            path => write!(f, "{}: {}", path, self.message),
+        }
+    }
+}
+
+/// Result of checking a config file against the schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaReport {
+    /// File checked.
+    pub file: PathBuf,
+    /// Whether the file matches the schema.
+    pub valid: bool,
+    /// Values not matching the schema.
+    pub errors: Vec<SchemaError>,
+    /// Keys the schema doesn't know, which are ignored when loading.
+    pub warnings: Vec<String>,
+}
+
+/// Check the config file at `path` against the schema, without expanding
+/// environment references, reading drop-ins or creating providers.
+pub fn check_file(path: &Path) -> Result<SchemaReport> {
+    let (value, warnings) = config::read_raw_value(path)?;
+    let errors = check_value(&value)?;
+    Ok(SchemaReport {
+        file: path.to_path_buf(),
+        valid: errors.is_empty(),
+        errors,
+        warnings,
+    })
+}
+
+/// Check a parsed config file against the schema.
+pub fn check_value(value: &serde_json::Value) -> Result<Vec<SchemaError>> {
+    check_against(&config_schema(), value, "")
+}
+
+/// Check `value`, found at `path`, against `schema`.
+fn check_against(
+    schema: &serde_json::Value,
+    value: &serde_json::Value,
+    path: &str,
+) -> Result<Vec<SchemaError>> {
+    let validator = jsonschema::JSONSchema::options()
+        .with_draft(jsonschema::Draft::Draft7)
+        .compile(schema)
+        .map_err(|e| DdnsError::Config(format!("Invalid config schema: {}", e)))?;
+    let Err(errors) = validator.validate(value) else {
+        return Ok(Vec::new());
+    };
+
+    let mut found = Vec::new();
+    for error in errors {
+        let at = format!("{}{}", path, error.instance_path);
+        if matches!(error.kind, ValidationErrorKind::OneOfNotValid) {
+            let alternatives = keyword(schema, &error.schema_path.to_string());
+            let alternatives = alternatives.and_then(|a| a.as_array());
+            if let Some(alternatives) = alternatives {
+                found.extend(explain_one_of(schema, alternatives, &error.instance, &at)?);
+                continue;
+            }
+        }
+
+        // Entries may hold credentials, so only scalars are quoted
+        let mut message = error.to_string();
+        if error.instance.is_object() || error.instance.is_array() {
+            let shown = error.instance.to_string();
+            if let Some(rest) = message.strip_prefix(&shown) {
+                message = format!("value{}", rest);
+            }
+        }
+        found.push(SchemaError { path: at, message });
+    }
+    Ok(found)
+}
+
+/// Explain why `value` at `path` matches none of the `alternatives` of a
+/// `oneOf`: which values are allowed, or for entries tagged by `type`
+/// (providers, notifications), what is wrong for their type.
+fn explain_one_of(
+    schema: &serde_json::Value,
+    alternatives: &[serde_json::Value],
+    value: &serde_json::Value,
+    path: &str,
+) -> Result<Vec<SchemaError>> {
+    let error = |message: String| {
+        Ok(vec![SchemaError {
+            path: path.to_string(),
+            message,
+        }])
+    };
+    let tag = |alternative: &serde_json::Value| {
+        alternative["properties"]["type"]["enum"][0]
+            .as_str()
+            .map(str::to_string)
+    };
+
+    let values: Option<Vec<String>> = alternatives
+        .iter()
+        .map(|a| a["enum"][0].as_str().map(str::to_string))
+        .collect();
+    if let Some(values) = values {
+        return error(format!("{} is not one of: {}", value, values.join(", ")));
+    }
+
+    let types: Option<Vec<String>> = alternatives.iter().map(tag).collect();
+    let (Some(types), Some(kind)) = (types, value.get("type").and_then(|t| t.as_str())) else {
+        return error("doesn't match any of the allowed forms".to_string());
+    };
+    let Some(alternative) = alternatives
+        .iter()
+        .find(|a| tag(a).as_deref() == Some(kind))
+    else {
+        return error(format!(
+            "unknown type \"{}\" (expected one of: {})",
+            kind,
+            types.join(", ")
+        ));
+    };
+
+    // Checked alone, with the definitions it may refer to
+    let mut alternative = alternative.clone();
+    alternative["definitions"] = schema["definitions"].clone();
+    check_against(&alternative, value, path)
+}
+
+/// Get the part of `schema` at a keyword location reported by the
+/// validator, which leaves out the `$ref`s followed on the way.
+fn keyword<'a>(schema: &'a serde_json::Value, location: &str) -> Option<&'a serde_json::Value> {
+    let resolve = |node: &'a serde_json::Value| {
+        node.get("$ref")
+            .and_then(|r| r.as_str())
+            .and_then(|r| r.strip_prefix('#'))
+            .and_then(|pointer| schema.pointer(pointer))
+            .unwrap_or(node)
+    };
+
+    let mut node = schema;
+    for segment in location.split('/').skip(1) {
+        node = resolve(node);
+        node = match node {
+            serde_json::Value::Object(map) => map.get(segment)?,
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copy of the schema shipped for editors.
+    const SNAPSHOT: &str = "config/rusty-dns.schema.json";
+
+    #[test]
+    fn test_schema_snapshot() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT);
+        let schema = format!(
+            "{}\n",
+            serde_json::to_string_pretty(&config_schema()).unwrap()
+        );
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(&path, &schema).unwrap();
+        }
+        let snapshot = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            snapshot == schema,
+            "The config schema changed: review the change and update {} with \
+             `UPDATE_SCHEMA=1 cargo test schema` (or `rusty-dns schema > {}`)",
+            SNAPSHOT,
+            SNAPSHOT
+        );
+    }
+
+    #[test]
+    fn test_example_matches_schema() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config/rusty-dns.example.toml");
+        let report = check_file(&path).unwrap();
+        assert!(report.valid, "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn test_check_value() {
+        let value = serde_json::json!({
+            "check_interval_secs": "often",
+            "ip_version": "v5",
+            "providers": [
+                {
+                    "type": "cloudflare",
+                    "api_token": "$CF_API_TOKEN",
+                    "zone_id": "zone",
+                    "record_name": "home.example.com"
+                },
+                { "type": "duckdns", "token": "secret-token" },
+                { "type": "dyndns", "domain": "home.example.com" },
+                {
+                    "type": "yandexcloud",
+                    "auth": "secret-token",
+                    "auth_type": "password",
+                    "zone_id": "zone",
+                    "record_name": "home.example.com"
+                }
+            ],
+            "notifications": [{ "type": "ntfy", "topic": "ddns", "events": ["ip_changed"] }]
+        });
+        let errors: Vec<String> = check_value(&value)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "/check_interval_secs: \"often\" is not of type \"integer\"",
+                "/ip_version: \"v5\" is not one of: v4, v6, both",
+                "/providers/1: \"domains\" is a required property",
+                "/providers/2: unknown type \"dyndns\" (expected one of: cloudflare, namecheap, \
+                 duckdns, godaddy, vultr, yandexcloud, dynurl, dnsomatic)",
+                "/providers/3/auth_type: \"password\" is not one of: oauth, iam, api_key",
+            ]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Credentials: kept out of logs by [`Secret`], or stored in the OS
+//! keyring.
+//!
+//! A credential value of the form `keyring:<service>/<entry>` is looked up
+//! in the platform secret store (Keychain, Windows Credential Manager or the
+//! Secret Service) when the provider is created.
+
+use crate::config::REDACTED;
+use crate::error::{DdnsError, Result};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// A credential (or a reference to one, such as `${VAR}` or a keyring
+/// entry) that never ends up in logs: it is formatted as [`REDACTED`] by
+/// both `Debug` and `Display`, and its memory is zeroed when dropped.
+///
+/// It is (de)serialized as the plain string, so config files keep their
+/// shape.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wrap a credential.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Get the credential itself, e.g. to send it to the provider.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the credential itself for changing it in place, e.g. when
+    /// resolving references.
+    pub fn expose_mut(&mut self) -> &mut String {
+        &mut self.0
+    }
+
+    /// Whether the credential is empty (unset).
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl PartialEq<str> for Secret {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Secret {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl schemars::JsonSchema for Secret {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "Secret".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Prefix marking a credential value as a keyring reference.
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// Keyring service used by `rusty-dns secret set`.
+pub const DEFAULT_SERVICE: &str = "rusty-dns";
+
+/// A reference to a keyring entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyringRef {
+    /// Keyring service name.
+    pub service: String,
+    /// Entry name within the service.
+    pub entry: String,
+}
+
+impl KeyringRef {
+    /// Parse a credential value, returning `None` if it is not a keyring
+    /// reference.
+    pub fn parse(value: &str) -> Option<Result<Self>> {
+        let reference = value.strip_prefix(KEYRING_PREFIX)?;
+        Some(Self::from_name(reference))
+    }
+
+    /// Parse `<service>/<entry>`, or a bare `<entry>` in the default service.
+    pub fn from_name(name: &str) -> Result<Self> {
+        let (service, entry) = name.split_once('/').unwrap_or((DEFAULT_SERVICE, name));
+        if service.is_empty() || entry.is_empty() {
+            return Err(DdnsError::Config(format!(
+                "Invalid keyring reference \"{}\" (expected {}<service>/<entry>)",
+                name, KEYRING_PREFIX
+            )));
+        }
+
+        Ok(Self {
+            service: service.to_string(),
+            entry: entry.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for KeyringRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.service, self.entry)
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringRef {
+    fn keyring_entry(&self) -> Result<keyring::Entry> {
+        keyring::Entry::new(&self.service, &self.entry).map_err(|e| self.error(e))
+    }
+
+    /// Command that stores this entry.
+    fn set_hint(&self) -> String {
+        if self.service == DEFAULT_SERVICE {
+            format!("rusty-dns secret set {}", self.entry)
+        } else {
+            format!("rusty-dns secret set {}/{}", self.service, self.entry)
+        }
+    }
+
+    fn error(&self, e: keyring::Error) -> DdnsError {
+        match e {
+            keyring::Error::NoEntry => DdnsError::Config(format!(
+                "Keyring entry {} not found; set it with `{}`",
+                self,
+                self.set_hint()
+            )),
+            e => DdnsError::Config(format!("Keyring entry {}: {}", self, e)),
+        }
+    }
+
+    /// Look up the secret.
+    pub fn get(&self) -> Result<String> {
+        self.keyring_entry()?
+            .get_password()
+            .map_err(|e| self.error(e))
+    }
+
+    /// Store a secret, replacing any existing value.
+    pub fn set(&self, secret: &str) -> Result<()> {
+        self.keyring_entry()?
+            .set_password(secret)
+            .map_err(|e| self.error(e))
+    }
+
+    /// Delete the entry.
+    pub fn delete(&self) -> Result<()> {
+        self.keyring_entry()?
+            .delete_credential()
+            .map_err(|e| self.error(e))
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+impl KeyringRef {
+    /// Look up the secret (unavailable: built without keyring support).
+    pub fn get(&self) -> Result<String> {
+        Err(DdnsError::Config(format!(
+            "Keyring entry {} cannot be read: rusty-dns was built without the `keyring` feature",
+            self
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_formatting() {
+        let secret = Secret::from("tk_0123456789");
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(secret.to_string(), REDACTED);
+        assert_eq!(secret.expose(), "tk_0123456789");
+        assert_eq!(secret, "tk_0123456789");
+
+        // Serialized as the plain string
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(json, "\"tk_0123456789\"");
+        assert_eq!(serde_json::from_str::<Secret>(&json).unwrap(), secret);
+        assert!(Secret::default().is_empty());
+    }
+
+    #[test]
+    fn test_parse_reference() {
+        assert!(KeyringRef::parse("plain-token").is_none());
+
+        let reference = KeyringRef::parse("keyring:rusty-dns/cloudflare-home")
+            .unwrap()
+            .unwrap();
+        assert_eq!(reference.service, "rusty-dns");
+        assert_eq!(reference.entry, "cloudflare-home");
+        assert_eq!(reference.to_string(), "rusty-dns/cloudflare-home");
+
+        let bare = KeyringRef::from_name("duckdns").unwrap();
+        assert_eq!(bare.service, DEFAULT_SERVICE);
+        assert_eq!(bare.entry, "duckdns");
+
+        assert!(KeyringRef::parse("keyring:").unwrap().is_err());
+        assert!(KeyringRef::parse("keyring:rusty-dns/").unwrap().is_err());
+    }
+
+    #[cfg(feature = "keyring")]
+    #[test]
+    fn test_mock_keyring() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let reference = KeyringRef::from_name("other/cloudflare-home").unwrap();
+
+        // The mock store keeps secrets per entry object, so only the lookup
+        // error can be checked end to end
+        let err = reference.get().unwrap_err().to_string();
+        assert!(err.contains("Keyring entry other/cloudflare-home not found"));
+        assert!(err.contains("`rusty-dns secret set other/cloudflare-home`"));
+        assert_eq!(
+            KeyringRef::from_name("duckdns").unwrap().set_hint(),
+            "rusty-dns secret set duckdns"
+        );
+    }
+}
@@ -0,0 +1,535 @@
+//! Running the daemon as a system service (`rusty-dns service`): a launchd
+//! job on macOS, or a Windows service on Windows (`windows-service`
+//! feature). On Linux the systemd unit in `systemd/` is used instead.
+//!
+//! Either way the service manager stops the daemon through the same
+//! [`CancellationToken`](crate::daemon::CancellationToken) as SIGINT/SIGTERM
+//! do, so a cycle in progress is finished first.
+
+use crate::error::Result;
+use std::path::{Path, PathBuf};
+
+/// Name of the Windows service.
+pub const SERVICE_NAME: &str = "rusty-dns";
+
+/// Command line the service manager runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceCommand {
+    /// The rusty-dns executable.
+    pub program: PathBuf,
+    /// Config file, passed with `--config`.
+    pub config: PathBuf,
+    /// Config profile, passed with `--profile`.
+    pub profile: Option<String>,
+}
+
+impl ServiceCommand {
+    /// Run the current executable with `config` (made absolute, as services
+    /// don't start in the current directory) and `profile`.
+    pub fn current(config: &Path, profile: Option<&str>) -> Result<Self> {
+        let config = if config.is_absolute() {
+            config.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(config)
+        };
+        Ok(Self {
+            program: std::env::current_exe()?,
+            config,
+            profile: profile.map(str::to_string),
+        })
+    }
+
+    /// Get the arguments running `subcommand` with the config and profile.
+    pub fn arguments(&self, subcommand: &[&str]) -> Vec<String> {
+        let mut arguments = vec![
+            "--config".to_string(),
+            self.config.to_string_lossy().into_owned(),
+        ];
+        if let Some(profile) = &self.profile {
+            arguments.extend(["--profile".to_string(), profile.clone()]);
+        }
+        arguments.extend(subcommand.iter().map(|arg| arg.to_string()));
+        arguments
+    }
+}
+
+/// Register the daemon with the service manager and start it.
+pub fn install(command: &ServiceCommand) -> Result<()> {
+    platform::install(command)
+}
+
+/// Stop the daemon and remove it from the service manager.
+pub fn uninstall() -> Result<()> {
+    platform::uninstall()
+}
+
+/// Start the installed service.
+pub fn start() -> Result<()> {
+    platform::start()
+}
+
+/// Stop the installed service.
+pub fn stop() -> Result<()> {
+    platform::stop()
+}
+
+#[cfg(target_os = "macos")]
+use launchd as platform;
+
+#[cfg(all(windows, feature = "windows-service"))]
+use windows as platform;
+#[cfg(all(windows, feature = "windows-service"))]
+pub use windows::run;
+
+#[cfg(not(any(target_os = "macos", all(windows, feature = "windows-service"))))]
+mod platform {
+    use super::ServiceCommand;
+    use crate::error::{DdnsError, Result};
+
+    fn unsupported() -> DdnsError {
+        DdnsError::Config(if cfg!(windows) {
+            "Windows services require rusty-dns built with the `windows-service` feature"
+                .to_string()
+        } else {
+            "`rusty-dns service` manages launchd jobs (macOS) and Windows services; \
+             on Linux install systemd/rusty-dns.service instead"
+                .to_string()
+        })
+    }
+
+    pub fn install(_command: &ServiceCommand) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn start() -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn stop() -> Result<()> {
+        Err(unsupported())
+    }
+}
+
+/// Run the daemon under the Windows service manager: `daemon` is called with
+/// a token cancelled when the service is stopped or the system shuts down.
+///
+/// Only the Windows service manager starts rusty-dns this way (`rusty-dns
+/// service run`); elsewhere this fails.
+#[cfg(not(all(windows, feature = "windows-service")))]
+pub fn run(
+    _daemon: impl FnOnce(crate::daemon::CancellationToken) -> Result<()> + Send + 'static,
+) -> Result<()> {
+    Err(crate::error::DdnsError::Config(
+        "`rusty-dns service run` is only started by the Windows service manager".to_string(),
+    ))
+}
+
+/// launchd jobs (macOS).
+///
+/// As root the job is a system daemon started at boot
+/// (`/Library/LaunchDaemons`), otherwise a user agent started at login
+/// (`~/Library/LaunchAgents`), like the plists shipped in `launchd/`.
+pub mod launchd {
+    use super::ServiceCommand;
+    use crate::error::{DdnsError, Result};
+    use std::path::{Path, PathBuf};
+
+    /// Label of the system daemon.
+    pub const DAEMON_LABEL: &str = "com.rusty-dns.daemon";
+
+    /// Label of the user agent.
+    pub const AGENT_LABEL: &str = "com.rusty-dns.agent";
+
+    /// Where a launchd job is installed and logs.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Job {
+        /// Job label.
+        pub label: &'static str,
+        /// The plist file.
+        pub plist: PathBuf,
+        /// File receiving stdout and stderr.
+        pub log: PathBuf,
+        /// Working directory, for the system daemon.
+        pub working_dir: Option<PathBuf>,
+    }
+
+    impl Job {
+        /// Get the system daemon job.
+        pub fn system() -> Self {
+            Self {
+                label: DAEMON_LABEL,
+                plist: Path::new("/Library/LaunchDaemons").join(format!("{}.plist", DAEMON_LABEL)),
+                log: PathBuf::from("/var/log/rusty-dns.log"),
+                working_dir: Some(PathBuf::from("/var/lib/rusty-dns")),
+            }
+        }
+
+        /// Get the user agent job of the user whose home is `home`.
+        pub fn user(home: &Path) -> Self {
+            Self {
+                label: AGENT_LABEL,
+                plist: home
+                    .join("Library/LaunchAgents")
+                    .join(format!("{}.plist", AGENT_LABEL)),
+                log: home.join("Library/Logs/rusty-dns.log"),
+                working_dir: None,
+            }
+        }
+
+        /// Get the job for the current user: the system daemon as root.
+        pub fn current() -> Result<Self> {
+            if crate::lock::is_root() {
+                return Ok(Self::system());
+            }
+            dirs::home_dir()
+                .map(|home| Self::user(&home))
+                .ok_or_else(|| DdnsError::Config("Cannot determine the home directory".to_string()))
+        }
+
+        /// Render the plist running `command`'s daemon, restarted unless it
+        /// exits cleanly.
+        pub fn render(&self, command: &ServiceCommand) -> String {
+            let mut arguments = vec![command.program.to_string_lossy().into_owned()];
+            arguments.extend(command.arguments(&["daemon"]));
+            let arguments: String = arguments
+                .iter()
+                .map(|arg| format!("        <string>{}</string>\n", escape(arg)))
+                .collect();
+            let working_dir = match &self.working_dir {
+                Some(dir) => format!(
+                    "    <key>WorkingDirectory</key>\n    <string>{}</string>\n\n",
+                    escape(&dir.to_string_lossy())
+                ),
+                None => String::new(),
+            };
+            let log = escape(&self.log.to_string_lossy());
+
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+
+    <key>RunAtLoad</key>
+    <true/>
+
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+
+    <key>ThrottleInterval</key>
+    <integer>5</integer>
+
+{working_dir}    <key>StandardOutPath</key>
+    <string>{log}</string>
+
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>RUST_LOG</key>
+        <string>info</string>
+    </dict>
+</dict>
+</plist>
+"#,
+                label = self.label,
+            )
+        }
+    }
+
+    /// Escape text for an XML element.
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    #[cfg(target_os = "macos")]
+    fn launchctl(args: &[&str]) -> Result<()> {
+        let output = std::process::Command::new("launchctl")
+            .args(args)
+            .output()?;
+        // launchctl reports some failures on stderr with a zero exit status
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !stderr.trim().is_empty() {
+            return Err(DdnsError::Config(format!(
+                "launchctl {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    pub(super) fn install(command: &ServiceCommand) -> Result<()> {
+        let job = Job::current()?;
+        if let Some(dir) = &job.working_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        if let Some(dir) = job.log.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        crate::storage::write_atomic(&job.plist, job.render(command).as_bytes())?;
+        launchctl(&["load", "-w", &job.plist.to_string_lossy()])
+    }
+
+    #[cfg(target_os = "macos")]
+    pub(super) fn uninstall() -> Result<()> {
+        let job = Job::current()?;
+        if !job.plist.exists() {
+            return Err(DdnsError::Config(format!(
+                "{} is not installed",
+                job.plist.display()
+            )));
+        }
+        // Not loaded is fine, the plist is removed either way
+        if let Err(e) = launchctl(&["unload", "-w", &job.plist.to_string_lossy()]) {
+            tracing::warn!("{}", e);
+        }
+        std::fs::remove_file(&job.plist)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    pub(super) fn start() -> Result<()> {
+        launchctl(&["start", Job::current()?.label])
+    }
+
+    #[cfg(target_os = "macos")]
+    pub(super) fn stop() -> Result<()> {
+        launchctl(&["stop", Job::current()?.label])
+    }
+}
+
+/// Windows service.
+#[cfg(all(windows, feature = "windows-service"))]
+mod windows {
+    use super::{ServiceCommand, SERVICE_NAME};
+    use crate::daemon::CancellationToken;
+    use crate::error::Result;
+    use std::ffi::{OsStr, OsString};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_dispatcher;
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    type ServiceMain = Box<dyn FnOnce(CancellationToken) -> Result<()> + Send>;
+
+    /// Daemon run by [`service_main`], set by [`run`].
+    static DAEMON: Mutex<Option<ServiceMain>> = Mutex::new(None);
+
+    fn manager(access: ServiceManagerAccess) -> Result<ServiceManager> {
+        Ok(ServiceManager::local_computer(None::<&str>, access)?)
+    }
+
+    pub(super) fn install(command: &ServiceCommand) -> Result<()> {
+        let info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from("rusty-dns"),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: command.program.clone(),
+            launch_arguments: command
+                .arguments(&["service", "run"])
+                .into_iter()
+                .map(OsString::from)
+                .collect(),
+            dependencies: Vec::new(),
+            // LocalSystem
+            account_name: None,
+            account_password: None,
+        };
+        let service =
+            manager(ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?
+                .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+        service
+            .set_description("Dynamic DNS client keeping DNS records at this host's public IP")?;
+        service.start::<&OsStr>(&[])?;
+        Ok(())
+    }
+
+    pub(super) fn uninstall() -> Result<()> {
+        let service = manager(ServiceManagerAccess::CONNECT)?.open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )?;
+        // Removed once stopped and no longer open
+        service.delete()?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn start() -> Result<()> {
+        manager(ServiceManagerAccess::CONNECT)?
+            .open_service(SERVICE_NAME, ServiceAccess::START)?
+            .start::<&OsStr>(&[])?;
+        Ok(())
+    }
+
+    pub(super) fn stop() -> Result<()> {
+        manager(ServiceManagerAccess::CONNECT)?
+            .open_service(SERVICE_NAME, ServiceAccess::STOP)?
+            .stop()?;
+        Ok(())
+    }
+
+    /// Run the daemon under the Windows service manager: `daemon` is called
+    /// with a token cancelled when the service is stopped or the system
+    /// shuts down. Blocks until the service has stopped.
+    pub fn run(
+        daemon: impl FnOnce(CancellationToken) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        *DAEMON.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(daemon));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    windows_service::define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            tracing::error!("rusty-dns service failed: {}", e);
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let cancel = CancellationToken::new();
+        let handler = {
+            let cancel = cancel.clone();
+            move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    cancel.cancel();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            }
+        };
+        let status = service_control_handler::register(SERVICE_NAME, handler)?;
+        let set_state = |state, accepted, exit_code| {
+            status.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: accepted,
+                exit_code: ServiceExitCode::Win32(exit_code),
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+        };
+
+        set_state(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            0,
+        )?;
+        let daemon = DAEMON.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let result = daemon.map_or(Ok(()), |daemon| daemon(cancel));
+        set_state(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            u32::from(result.is_err()),
+        )?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::launchd::Job;
+    use super::*;
+
+    fn command() -> ServiceCommand {
+        ServiceCommand {
+            program: PathBuf::from("/usr/local/bin/rusty-dns"),
+            config: PathBuf::from("/Users/me/rusty dns/config.toml"),
+            profile: Some("r&d".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_arguments() {
+        assert_eq!(
+            command().arguments(&["service", "run"]),
+            [
+                "--config",
+                "/Users/me/rusty dns/config.toml",
+                "--profile",
+                "r&d",
+                "service",
+                "run"
+            ]
+        );
+
+        let current = ServiceCommand::current(Path::new("config.toml"), None).unwrap();
+        assert!(current.config.is_absolute());
+        assert_eq!(current.arguments(&["daemon"]).len(), 3);
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let user = Job::user(Path::new("/Users/me"));
+        assert_eq!(
+            user.plist,
+            Path::new("/Users/me/Library/LaunchAgents/com.rusty-dns.agent.plist")
+        );
+        let plist = user.render(&command());
+        assert!(plist.contains("<string>com.rusty-dns.agent</string>"));
+        assert!(plist.contains(
+            "        <string>/usr/local/bin/rusty-dns</string>\n\
+             \x20       <string>--config</string>\n\
+             \x20       <string>/Users/me/rusty dns/config.toml</string>\n\
+             \x20       <string>--profile</string>\n\
+             \x20       <string>r&amp;d</string>\n\
+             \x20       <string>daemon</string>\n    </array>"
+        ));
+        assert!(plist.contains("<string>/Users/me/Library/Logs/rusty-dns.log</string>"));
+        assert!(!plist.contains("WorkingDirectory"));
+
+        let system = Job::system().render(&command());
+        assert!(system.contains("<string>com.rusty-dns.daemon</string>"));
+        assert!(
+            system.contains("<key>WorkingDirectory</key>\n    <string>/var/lib/rusty-dns</string>")
+        );
+        // Well-formed enough for launchd: every opened element is closed
+        for tag in ["dict", "array", "plist"] {
+            assert_eq!(
+                system.matches(&format!("<{}", tag)).count(),
+                system.matches(&format!("</{}>", tag)).count(),
+                "{}",
+                tag
+            );
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", all(windows, feature = "windows-service"))))]
+    #[test]
+    fn test_unsupported() {
+        let err = install(&command()).unwrap_err();
+        assert!(err.to_string().contains("systemd") || cfg!(windows));
+        assert!(run(|_| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,678 @@
+//! First-run setup for `rusty-dns setup`.
+//!
+//! The wizard detects the public address, asks for provider entries one at
+//! a time, optionally checks each against its provider, and appends it to
+//! the config file with the comment-preserving writer (see [`crate::edit`]).
+//! Credentials can be kept out of the file as environment variable
+//! references or keyring entries. Questions go through a [`Prompt`], so the
+//! same steps run on the terminal and in tests.
+
+use crate::config::{Config, ProviderConfig};
+use crate::detector::IpDetector;
+use crate::error::{DdnsError, Result};
+use crate::providers::{create_provider, RecordType};
+use crate::secret::{KeyringRef, KEYRING_PREFIX};
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Asks the user questions.
+pub trait Prompt {
+    /// Show a message.
+    fn say(&mut self, message: &str);
+
+    /// Ask for a line of text. An empty answer gives `default`, or an empty
+    /// string without one.
+    fn text(&mut self, question: &str, default: Option<&str>) -> Result<String>;
+
+    /// Ask for a secret without echoing it.
+    fn secret(&mut self, question: &str) -> Result<String>;
+
+    /// Ask a yes/no question.
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool>;
+
+    /// Pick one of `options`, returning its index.
+    fn select(&mut self, question: &str, options: &[&str], default: usize) -> Result<usize>;
+}
+
+/// [`Prompt`] on stdin and stdout.
+pub struct Terminal;
+
+impl Terminal {
+    fn read_line(&self, question: &str) -> Result<String> {
+        print!("{}", question);
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)? == 0 {
+            return Err(DdnsError::Config(
+                "Setup cancelled: no more input".to_string(),
+            ));
+        }
+        Ok(line.trim().to_string())
+    }
+}
+
+impl Prompt for Terminal {
+    fn say(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn text(&mut self, question: &str, default: Option<&str>) -> Result<String> {
+        let answer = match default {
+            Some(default) => self.read_line(&format!("{} [{}]: ", question, default))?,
+            None => self.read_line(&format!("{}: ", question))?,
+        };
+        Ok(match default {
+            Some(default) if answer.is_empty() => default.to_string(),
+            _ => answer,
+        })
+    }
+
+    fn secret(&mut self, question: &str) -> Result<String> {
+        if !std::io::stdin().is_terminal() {
+            return self.read_line(&format!("{}: ", question));
+        }
+        let secret = rpassword::prompt_password(format!("{} (hidden): ", question))?;
+        Ok(secret.trim().to_string())
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+        loop {
+            let answer = self.read_line(&format!("{} {} ", question, hint))?;
+            match answer.to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("Please answer y or n."),
+            }
+        }
+    }
+
+    fn select(&mut self, question: &str, options: &[&str], default: usize) -> Result<usize> {
+        self.say(question);
+        for (i, option) in options.iter().enumerate() {
+            self.say(&format!("  {}) {}", i + 1, option));
+        }
+        loop {
+            let answer = self.text("Choice", Some(&(default + 1).to_string()))?;
+            let chosen = match answer.parse::<usize>() {
+                Ok(number) => number.checked_sub(1),
+                Err(_) => options.iter().position(|o| o.eq_ignore_ascii_case(&answer)),
+            };
+            match chosen {
+                Some(index) if index < options.len() => return Ok(index),
+                _ => self.say(&format!(
+                    "Please enter a number from 1 to {}.",
+                    options.len()
+                )),
+            }
+        }
+    }
+}
+
+/// Where the wizard keeps the credentials it is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretStorage {
+    /// In environment variables, referenced as `$NAME` in the config and
+    /// left for the user to set.
+    Env,
+    /// In the OS keyring, referenced as `keyring:rusty-dns/<entry>`
+    /// (requires the `keyring` feature).
+    Keyring,
+    /// In the config file itself.
+    Plaintext,
+}
+
+impl SecretStorage {
+    /// Get the storages usable in this build, most private first.
+    pub fn available() -> Vec<Self> {
+        let keyring = cfg!(feature = "keyring").then_some(Self::Keyring);
+        keyring
+            .into_iter()
+            .chain([Self::Env, Self::Plaintext])
+            .collect()
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Env => "environment variables (you set them for rusty-dns)",
+            Self::Keyring => "the OS keyring",
+            Self::Plaintext => "the config file, in plain text",
+        }
+    }
+}
+
+/// Choices of a setup run made up front, instead of asking.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SetupOptions {
+    /// Where to keep credentials (default: asked, or as given without
+    /// prompts).
+    pub secrets: Option<SecretStorage>,
+    /// Test the credentials against the provider before saving (default:
+    /// asked, or not without prompts).
+    pub check: Option<bool>,
+    /// Show what the first update would change (default: asked, or not
+    /// without prompts).
+    pub dry_run: Option<bool>,
+}
+
+/// What a setup run did.
+#[derive(Debug, Clone, Default)]
+pub struct SetupOutcome {
+    /// Public address detected.
+    pub ip: Option<IpAddr>,
+    /// Labels of the entries added.
+    pub added: Vec<String>,
+    /// Environment variables the added entries read their credentials
+    /// from, which rusty-dns needs set.
+    pub env_vars: Vec<String>,
+}
+
+/// Where to find the settings of each provider type.
+fn provider_help(kind: &str) -> &'static str {
+    match kind {
+        "cloudflare" => {
+            "Needs an API token with Zone:DNS:Edit permission (My Profile > API Tokens) \
+             and the zone ID (shown on the domain's Overview page)."
+        }
+        "namecheap" => {
+            "Needs the Dynamic DNS password from Domain List > Manage > Advanced DNS, \
+             with Dynamic DNS turned on."
+        }
+        "duckdns" => {
+            "Needs the token shown on duckdns.org once logged in, and your \
+             subdomains without .duckdns.org (comma-separated)."
+        }
+        "godaddy" => "Needs a production API key and secret from developer.godaddy.com/keys.",
+        "vultr" => {
+            "Needs an API key from Account > API (allowing this host's address), and \
+             the domain as added to Vultr DNS with an existing A/AAAA record."
+        }
+        "dynurl" => {
+            "Needs the dynamic URL from the registrar (e.g. ClouDNS or Dynu) and the domain \
+             it updates; set append_ip = true if the URL takes the address as a parameter."
+        }
+        "dnsomatic" => {
+            "Needs the dnsomatic.com username and password, and the hostname of a service \
+             set up there, or all.dnsomatic.com to update every service."
+        }
+        "yandexcloud" => {
+            "Needs an OAuth token (or an API key with auth_type = api_key) allowed to edit \
+             the zone, the zone ID from Cloud DNS, and the full record name."
+        }
+        _ => "",
+    }
+}
+
+/// Whether `field` of a provider type is a credential, i.e. can be read
+/// from a file instead.
+fn is_secret(fields: &[&str], field: &str) -> bool {
+    fields.contains(&format!("{}_file", field).as_str())
+}
+
+/// Whether a credential value already points elsewhere (an environment
+/// variable or keyring entry) rather than being the secret itself.
+fn is_reference(value: &str) -> bool {
+    value.starts_with('$') || value.starts_with(KEYRING_PREFIX)
+}
+
+/// Get a default environment variable name for `field` of the entry
+/// labeled `label`, e.g. `CLOUDFLARE_VPN_EXAMPLE_COM_API_TOKEN`.
+fn env_name(label: &str, field: &str) -> String {
+    let name: String = format!("{}_{}", label, field)
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Provider entry being set up.
+struct Draft {
+    /// Entry as written to the config file.
+    written: ProviderConfig,
+    /// Entry with the credentials themselves, to check it.
+    resolved: ProviderConfig,
+    /// Keyring entries to store on saving.
+    keyring: Vec<(KeyringRef, String)>,
+    /// Environment variables referenced by `written`, with their values.
+    env_vars: Vec<(String, String)>,
+}
+
+impl Draft {
+    /// Build an entry of type `kind` from its settings, keeping literal
+    /// credentials in `storage`. Environment variable names are asked
+    /// through `prompt`, if given.
+    fn new(
+        kind: &str,
+        fields: BTreeMap<String, String>,
+        storage: SecretStorage,
+        mut prompt: Option<&mut dyn Prompt>,
+    ) -> Result<Self> {
+        let resolved = ProviderConfig::from_fields(kind, &fields)?;
+        let label = resolved.label();
+        let known = ProviderConfig::fields(kind);
+
+        let mut written = fields;
+        let mut keyring = Vec::new();
+        let mut env_vars = Vec::new();
+        for (field, value) in written.iter_mut() {
+            if !is_secret(&known, field) || is_reference(value) {
+                continue;
+            }
+            match storage {
+                SecretStorage::Plaintext => {}
+                SecretStorage::Env => {
+                    let default = env_name(&label, field);
+                    let name = match prompt.as_deref_mut() {
+                        Some(prompt) => prompt.text(
+                            &format!("Environment variable for {}", field),
+                            Some(&default),
+                        )?,
+                        None => default,
+                    };
+                    let secret = std::mem::replace(value, format!("${}", name));
+                    env_vars.push((name, secret));
+                }
+                SecretStorage::Keyring => {
+                    let reference = KeyringRef::from_name(&format!("{}-{}", label, field))?;
+                    let secret =
+                        std::mem::replace(value, format!("{}{}", KEYRING_PREFIX, reference));
+                    keyring.push((reference, secret));
+                }
+            }
+        }
+
+        Ok(Self {
+            written: ProviderConfig::from_fields(kind, &written)?,
+            resolved,
+            keyring,
+            env_vars,
+        })
+    }
+
+    /// Test the credentials against the provider.
+    async fn check(&self, config: &Config) -> Result<()> {
+        create_provider(
+            &self.resolved.interpolated()?,
+            config.default_ttl,
+            &config.network,
+        )
+        .validate()
+        .await
+    }
+
+    /// Describe what an update to `ip` would change.
+    async fn dry_run(&self, config: &Config, ip: IpAddr) -> Result<String> {
+        let provider = create_provider(
+            &self.resolved.interpolated()?,
+            config.default_ttl,
+            &config.network,
+        );
+        let record_type = RecordType::for_ip(&ip);
+        let domain = provider.domain();
+        Ok(match provider.get_record_ip(record_type).await? {
+            Some(current) if current == ip => {
+                format!("{} ({}) already points at {}", domain, record_type, ip)
+            }
+            Some(current) => format!(
+                "An update would change {} ({}) from {} to {}",
+                domain, record_type, current, ip
+            ),
+            None => format!(
+                "An update would point {} ({}) at {}",
+                domain, record_type, ip
+            ),
+        })
+    }
+
+    /// Store the keyring entries and append the entry to the config file.
+    fn save(self, path: &Path, prompt: &mut dyn Prompt, outcome: &mut SetupOutcome) -> Result<()> {
+        for (reference, secret) in &self.keyring {
+            store_in_keyring(reference, secret)?;
+        }
+        let label = self.written.label();
+        for warning in Config::add_resolved_provider_to(path, &self.written, self.resolved)? {
+            prompt.say(&format!("warning: {}", warning));
+        }
+        prompt.say(&format!("Added {} to {}", label, path.display()));
+        outcome.added.push(label);
+        for (name, secret) in self.env_vars {
+            // Entries added later in this run are validated with the whole
+            // file, before the user has had a chance to set the variable
+            std::env::set_var(&name, secret);
+            outcome.env_vars.push(name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn store_in_keyring(reference: &KeyringRef, secret: &str) -> Result<()> {
+    reference.set(secret)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn store_in_keyring(reference: &KeyringRef, _secret: &str) -> Result<()> {
+    Err(DdnsError::Config(format!(
+        "Cannot store keyring entry {}: rusty-dns was built without the `keyring` feature",
+        reference
+    )))
+}
+
+/// Detect the public address, IPv4 first.
+async fn detect(detector: &IpDetector) -> Result<IpAddr> {
+    match detector.detect_ipv4().await {
+        Ok(ip) => Ok(ip),
+        Err(e) => detector.detect_ipv6().await.map_err(|_| e),
+    }
+}
+
+/// Ask for the settings of a provider of type `kind`.
+fn ask_fields(prompt: &mut dyn Prompt, kind: &str) -> Result<BTreeMap<String, String>> {
+    prompt.say(provider_help(kind));
+    prompt.say("Leave a setting empty to skip it.");
+    let known = ProviderConfig::fields(kind);
+    let mut fields = BTreeMap::new();
+    for field in &known {
+        if field.ends_with("_file") {
+            continue;
+        }
+        let value = if is_secret(&known, field) {
+            prompt.secret(&format!("  {}", field))?
+        } else {
+            prompt.text(&format!("  {}", field), None)?
+        };
+        if !value.is_empty() {
+            fields.insert(field.to_string(), value);
+        }
+    }
+    Ok(fields)
+}
+
+/// Run the wizard, adding entries to the config file at `path` until the
+/// user is done.
+///
+/// Entries failing their check are only saved if the user insists; entries
+/// the config rejects are reported and can be entered again.
+pub async fn run(
+    prompt: &mut dyn Prompt,
+    path: &Path,
+    detector: &IpDetector,
+    options: SetupOptions,
+) -> Result<SetupOutcome> {
+    let config = Config::load_unchecked(path, false, None)?;
+    let mut outcome = SetupOutcome::default();
+
+    prompt.say("This sets up the DNS records rusty-dns keeps pointed at this machine.");
+    if path.exists() {
+        prompt.say(&format!(
+            "Adding to {} ({} provider(s) configured).",
+            path.display(),
+            config.providers.len()
+        ));
+    } else {
+        prompt.say(&format!("Creating {}.", path.display()));
+    }
+
+    match detect(detector).await {
+        Ok(ip) => {
+            prompt.say(&format!("Your public IP address is {}.", ip));
+            outcome.ip = Some(ip);
+        }
+        Err(e) => prompt.say(&format!(
+            "Could not detect your public IP address ({}); the records are updated once \
+             it can be.",
+            e
+        )),
+    }
+
+    let storage = match options.secrets {
+        Some(storage) => storage,
+        None => {
+            let storages = SecretStorage::available();
+            let names: Vec<_> = storages.iter().map(|s| s.describe()).collect();
+            storages[prompt.select("Where should credentials be kept?", &names, 0)?]
+        }
+    };
+    let check = match options.check {
+        Some(check) => check,
+        None => prompt.confirm(
+            "Test the credentials with each provider before saving?",
+            true,
+        )?,
+    };
+    let dry_run = match (options.dry_run, outcome.ip) {
+        (_, None) => false,
+        (Some(dry_run), _) => dry_run,
+        (None, Some(_)) => prompt.confirm("Show what the first update would change?", true)?,
+    };
+
+    loop {
+        let kind = ProviderConfig::TYPES[prompt.select(
+            "Which provider manages the DNS record?",
+            &ProviderConfig::TYPES,
+            0,
+        )?];
+        let fields = ask_fields(prompt, kind)?;
+        let draft = match Draft::new(kind, fields, storage, Some(&mut *prompt)) {
+            Ok(draft) => draft,
+            Err(e) => {
+                prompt.say(&format!("{}", e));
+                if prompt.confirm("Enter this provider again?", true)? {
+                    continue;
+                }
+                break;
+            }
+        };
+
+        let mut save = true;
+        if check {
+            match draft.check(&config).await {
+                Ok(()) => prompt.say("Credentials OK."),
+                Err(e) => {
+                    prompt.say(&format!("Check failed: {}", e));
+                    save = prompt.confirm("Save this provider anyway?", false)?;
+                }
+            }
+        }
+        if let (true, true, Some(ip)) = (save, dry_run, outcome.ip) {
+            match draft.dry_run(&config, ip).await {
+                Ok(message) => prompt.say(&message),
+                Err(e) => prompt.say(&format!("Could not read the current record: {}", e)),
+            }
+        }
+        if save {
+            if let Err(e) = draft.save(path, prompt, &mut outcome) {
+                prompt.say(&format!("Not saved: {}", e));
+            }
+        }
+
+        if !prompt.confirm("Add another provider?", false)? {
+            break;
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Add one entry of type `kind` from `fields` without asking anything,
+/// failing instead where the wizard would ask.
+///
+/// Credentials already given as references are kept; others go to
+/// `options.secrets` (default: the config file), with environment variables
+/// named after the entry.
+pub async fn run_non_interactive(
+    prompt: &mut dyn Prompt,
+    path: &Path,
+    detector: &IpDetector,
+    kind: &str,
+    fields: BTreeMap<String, String>,
+    options: SetupOptions,
+) -> Result<SetupOutcome> {
+    let config = Config::load_unchecked(path, false, None)?;
+    let mut outcome = SetupOutcome::default();
+    let storage = options.secrets.unwrap_or(SecretStorage::Plaintext);
+    let draft = Draft::new(kind, fields, storage, None)?;
+
+    if options.check == Some(true) {
+        draft.check(&config).await?;
+        prompt.say("Credentials OK.");
+    }
+    if options.dry_run == Some(true) {
+        let ip = detect(detector).await?;
+        outcome.ip = Some(ip);
+        prompt.say(&draft.dry_run(&config, ip).await?);
+    }
+    draft.save(path, prompt, &mut outcome)?;
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// [`Prompt`] giving scripted answers, in order, and keeping what is
+    /// said.
+    #[derive(Default)]
+    struct Script {
+        answers: VecDeque<&'static str>,
+        said: Vec<String>,
+    }
+
+    impl Script {
+        fn new(answers: &[&'static str]) -> Self {
+            Self {
+                answers: answers.iter().copied().collect(),
+                said: Vec::new(),
+            }
+        }
+
+        fn answer(&mut self, question: &str) -> Result<String> {
+            self.said.push(question.to_string());
+            self.answers
+                .pop_front()
+                .map(str::to_string)
+                .ok_or_else(|| DdnsError::Config(format!("Unexpected question: {}", question)))
+        }
+    }
+
+    impl Prompt for Script {
+        fn say(&mut self, message: &str) {
+            self.said.push(message.to_string());
+        }
+
+        fn text(&mut self, question: &str, default: Option<&str>) -> Result<String> {
+            let answer = self.answer(question)?;
+            Ok(match default {
+                Some(default) if answer.is_empty() => default.to_string(),
+                _ => answer,
+            })
+        }
+
+        fn secret(&mut self, question: &str) -> Result<String> {
+            self.answer(question)
+        }
+
+        fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+            Ok(match self.answer(question)?.as_str() {
+                "" => default,
+                answer => answer == "y",
+            })
+        }
+
+        fn select(&mut self, question: &str, options: &[&str], _default: usize) -> Result<usize> {
+            let answer = self.answer(question)?;
+            options
+                .iter()
+                .position(|o| *o == answer)
+                .ok_or_else(|| DdnsError::Config(format!("No option {}", answer)))
+        }
+    }
+
+    #[test]
+    fn test_env_name() {
+        assert_eq!(
+            env_name("cloudflare-vpn.example.com", "api_token"),
+            "CLOUDFLARE_VPN_EXAMPLE_COM_API_TOKEN"
+        );
+        assert_eq!(env_name("1st", "token"), "_1ST_TOKEN");
+    }
+
+    #[tokio::test]
+    async fn test_wizard() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-setup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "# My settings\ncheck_interval_secs = 600\n").unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4"))
+            .mount(&server)
+            .await;
+        let detector = IpDetector::with_services(vec![server.uri()]);
+
+        let mut script = Script::new(&[
+            "environment variables (you set them for rusty-dns)",
+            "n", // no credential check
+            "n", // no dry run
+            "duckdns",
+            "",     // label
+            "home", // domains
+            "secret-token",
+            "", // default variable name
+            "n",
+        ]);
+        let outcome = run(&mut script, &path, &detector, SetupOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.ip, Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(outcome.added, ["duckdns-home.duckdns.org"]);
+        assert_eq!(outcome.env_vars, ["DUCKDNS_HOME_DUCKDNS_ORG_TOKEN"]);
+        // The secret stays out of the file, whose comments are kept
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("# My settings\n"), "{}", written);
+        assert!(written.contains("token = \"$DUCKDNS_HOME_DUCKDNS_ORG_TOKEN\""));
+        assert!(!written.contains("secret-token"));
+
+        // Scripted, with a reference given as is
+        let fields = [("domains", "office"), ("token", "$DUCKDNS_OFFICE_TOKEN")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        std::env::set_var("DUCKDNS_OFFICE_TOKEN", "office-token");
+        let outcome = run_non_interactive(
+            &mut Script::default(),
+            &path,
+            &detector,
+            "duckdns",
+            fields,
+            SetupOptions {
+                secrets: Some(SecretStorage::Env),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.added, ["duckdns-office.duckdns.org"]);
+        assert!(outcome.env_vars.is_empty());
+        let config = Config::load_unchecked(&path, false, None).unwrap();
+        assert_eq!(config.providers.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
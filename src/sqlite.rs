@@ -0,0 +1,504 @@
+//! SQLite history backend (`history.backend = "sqlite"`).
+//!
+//! Keeps the update history, IP change log and persistent state in
+//! `history.sqlite3` in the state directory, so a long history can be
+//! queried, e.g. for graphs:
+//!
+//! - `updates`: one row per history entry, with the entry's fields as
+//!   columns and the whole entry as JSON in `entry`.
+//! - `ip_changes`: one row per detected address change, likewise with the
+//!   whole change in `change`.
+//! - `provider_state`: the state of each provider entry, by label.
+//! - `state`: the rest of the persistent state (last detected addresses,
+//!   IP service health).
+//!
+//! Timestamps are RFC 3339 UTC strings, which sort in time order. SQLite
+//! locks the database itself, so concurrent processes can share it.
+
+use crate::config::{HistoryBackend, HistoryConfig};
+use crate::error::Result;
+use crate::history::{HistoryEntry, HistoryStore, IpChange};
+use crate::state::State;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// File name of the database inside the state directory.
+pub const SQLITE_FILE: &str = "history.sqlite3";
+
+/// Schema version, kept in `PRAGMA user_version`.
+const SCHEMA_VERSION: i32 = 1;
+
+/// How long to wait for another process holding the database lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS updates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT NOT NULL,
+    label TEXT,
+    provider TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    previous_ip TEXT,
+    ip TEXT,
+    success INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    source TEXT NOT NULL,
+    error TEXT,
+    error_kind TEXT,
+    entry TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS updates_timestamp ON updates (timestamp);
+
+CREATE TABLE IF NOT EXISTS ip_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    old_ip TEXT,
+    new_ip TEXT NOT NULL,
+    service TEXT,
+    change TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ip_changes_timestamp ON ip_changes (timestamp);
+
+CREATE TABLE IF NOT EXISTS provider_state (
+    label TEXT PRIMARY KEY,
+    state TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// Key of the `state` row holding everything but the provider states.
+const STATE_KEY: &str = "state";
+
+/// History kept in a SQLite database.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    max_entries: usize,
+    retention: Option<chrono::Duration>,
+}
+
+impl SqliteStore {
+    /// Open (creating if needed) the database in `dir`, trimmed as set in
+    /// `config`.
+    pub fn open(dir: &Path, config: &HistoryConfig) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let conn = Connection::open(dir.join(SQLITE_FILE))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Readers don't wait for writers
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            max_entries: config.max_entries,
+            retention: config.retention(),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Format a timestamp so that text order is time order.
+fn timestamp(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn load_state(conn: &Connection) -> Result<State> {
+    let mut state: State = match conn
+        .query_row(
+            "SELECT value FROM state WHERE key = ?1",
+            [STATE_KEY],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+    {
+        Some(value) => serde_json::from_str(&value)?,
+        None => State::default(),
+    };
+
+    let mut statement = conn.prepare("SELECT label, state FROM provider_state")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (label, value) = row?;
+        state.providers.insert(label, serde_json::from_str(&value)?);
+    }
+    Ok(state)
+}
+
+impl HistoryStore for SqliteStore {
+    fn backend(&self) -> HistoryBackend {
+        HistoryBackend::Sqlite
+    }
+
+    fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT entry FROM updates ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut entries = Vec::new();
+        for row in rows {
+            match serde_json::from_str(&row?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!("Skipping unreadable history entry: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn append(&self, entries: &[HistoryEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO updates (timestamp, label, provider, domain, record_type, \
+                 previous_ip, ip, success, reason, source, error, error_kind, entry) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?;
+            for entry in entries {
+                let r = &entry.result;
+                insert.execute(params![
+                    timestamp(&r.timestamp),
+                    r.label,
+                    r.provider,
+                    r.domain,
+                    r.record_type.to_string(),
+                    r.previous_ip.map(|ip| ip.to_string()),
+                    r.ip.map(|ip| ip.to_string()),
+                    r.success,
+                    entry.reason.as_str(),
+                    entry.source.as_str(),
+                    r.error,
+                    r.error_kind.map(|kind| kind.to_string()),
+                    serde_json::to_string(entry)?,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn ip_changes(&self) -> Result<Vec<IpChange>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT change FROM ip_changes ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut changes = Vec::new();
+        for row in rows {
+            match serde_json::from_str(&row?) {
+                Ok(change) => changes.push(change),
+                Err(e) => tracing::warn!("Skipping unreadable IP change: {}", e),
+            }
+        }
+        Ok(changes)
+    }
+
+    fn append_ip_changes(&self, changes: &[IpChange]) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO ip_changes (timestamp, record_type, old_ip, new_ip, service, change) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for change in changes {
+                insert.execute(params![
+                    timestamp(&change.timestamp),
+                    change.record_type.to_string(),
+                    change.old_ip.map(|ip| ip.to_string()),
+                    change.new_ip.to_string(),
+                    change.service,
+                    serde_json::to_string(change)?,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_state(&self) -> Result<State> {
+        load_state(&self.conn())
+    }
+
+    fn modify_state(&self, f: &mut dyn FnMut(&mut State)) -> Result<()> {
+        let mut conn = self.conn();
+        // Taking the write lock before reading keeps other processes from
+        // changing the state in between
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut state = load_state(&tx)?;
+        f(&mut state);
+
+        let providers = std::mem::take(&mut state.providers);
+        tx.execute(
+            "INSERT OR REPLACE INTO state (key, value) VALUES (?1, ?2)",
+            params![STATE_KEY, serde_json::to_string(&state)?],
+        )?;
+        tx.execute("DELETE FROM provider_state", [])?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO provider_state (label, state) VALUES (?1, ?2)")?;
+            for (label, provider) in &providers {
+                insert.execute(params![label, serde_json::to_string(provider)?])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn prune(&self) -> Result<usize> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut dropped = 0;
+
+        if let Some(retention) = self.retention {
+            let cutoff = timestamp(&(Utc::now() - retention));
+            dropped += tx.execute("DELETE FROM updates WHERE timestamp < ?1", [&cutoff])?;
+            dropped += tx.execute("DELETE FROM ip_changes WHERE timestamp < ?1", [&cutoff])?;
+        }
+        dropped += tx.execute(
+            "DELETE FROM updates WHERE id NOT IN \
+             (SELECT id FROM updates ORDER BY id DESC LIMIT ?1)",
+            [self.max_entries as i64],
+        )?;
+
+        tx.commit()?;
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{self, JsonStore, UpdateReason};
+    use crate::providers::{RecordType, UpdateResult};
+    use std::sync::Arc;
+
+    fn sqlite() -> HistoryConfig {
+        HistoryConfig {
+            backend: HistoryBackend::Sqlite,
+            ..HistoryConfig::default()
+        }
+    }
+
+    fn entry(domain: &str, age_days: i64) -> HistoryEntry {
+        HistoryEntry {
+            result: UpdateResult {
+                provider: "duckdns".to_string(),
+                label: Some(format!("duckdns-{}", domain)),
+                domain: domain.to_string(),
+                record_type: RecordType::A,
+                success: true,
+                ip: Some("1.2.3.4".parse().unwrap()),
+                previous_ip: None,
+                error: None,
+                error_kind: None,
+                timestamp: Utc::now() - chrono::Duration::days(age_days),
+            },
+            reason: UpdateReason::Changed,
+            source: Default::default(),
+        }
+    }
+
+    fn change(age_days: i64) -> IpChange {
+        IpChange {
+            timestamp: Utc::now() - chrono::Duration::days(age_days),
+            record_type: RecordType::A,
+            old_ip: Some("1.2.3.4".parse().unwrap()),
+            new_ip: "5.6.7.8".parse().unwrap(),
+            service: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!(
+            "rusty-dns-sqlite-round-trip-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = history::open_in(&dir, &sqlite()).unwrap();
+        assert_eq!(store.backend(), HistoryBackend::Sqlite);
+        assert!(store.entries().unwrap().is_empty());
+
+        store.append(&[entry("a", 0), entry("b", 0)]).unwrap();
+        store.append_ip_changes(&[change(0)]).unwrap();
+        let domains: Vec<_> = store
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.result.domain)
+            .collect();
+        assert_eq!(domains, ["a", "b"]);
+        assert_eq!(store.ip_changes().unwrap()[0].new_ip.to_string(), "5.6.7.8");
+
+        let ip = "1.2.3.4".parse().unwrap();
+        let failures = store
+            .update_state(|state| {
+                state.set_last_ip(ip);
+                let provider = state.provider_mut("duckdns-a");
+                provider.record_failure("KO".to_string(), None, 3);
+                provider.consecutive_failures
+            })
+            .unwrap();
+        assert_eq!(failures, 1);
+
+        // Another process sees the same database
+        let other = SqliteStore::open(&dir, &sqlite()).unwrap();
+        let state = other.load_state().unwrap();
+        assert_eq!(state.last_ipv4, Some(ip));
+        assert_eq!(state.consecutive_failures("duckdns-a"), 1);
+        assert_eq!(other.entries().unwrap().len(), 2);
+
+        // The columns can be queried directly
+        let conn = Connection::open(dir.join(SQLITE_FILE)).unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM updates WHERE provider = 'duckdns' AND success",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-sqlite-prune-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = HistoryConfig {
+            max_entries: 2,
+            retention_days: 30,
+            ..sqlite()
+        };
+        let store = SqliteStore::open(&dir, &config).unwrap();
+
+        store
+            .append(&[
+                entry("old", 60),
+                entry("a", 2),
+                entry("b", 1),
+                entry("c", 0),
+            ])
+            .unwrap();
+        store.append_ip_changes(&[change(60), change(0)]).unwrap();
+        // Kept until pruned
+        assert_eq!(store.entries().unwrap().len(), 4);
+
+        assert_eq!(store.prune().unwrap(), 3);
+        let domains: Vec<_> = store
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.result.domain)
+            .collect();
+        assert_eq!(domains, ["b", "c"]);
+        assert_eq!(store.ip_changes().unwrap().len(), 1);
+        assert_eq!(store.prune().unwrap(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_from_json() {
+        let dir =
+            std::env::temp_dir().join(format!("rusty-dns-sqlite-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = JsonStore::new(dir.clone(), &HistoryConfig::default());
+        json.append(&[entry("a", 1), entry("b", 0)]).unwrap();
+        json.append_ip_changes(&[change(1)]).unwrap();
+        (&json as &dyn HistoryStore)
+            .update_state(|state| {
+                state.provider_mut("duckdns-a").record_success();
+            })
+            .unwrap();
+
+        let migration = history::migrate(&dir, &sqlite(), HistoryBackend::Sqlite).unwrap();
+        assert_eq!(
+            migration,
+            history::Migration {
+                entries: 2,
+                ip_changes: 1,
+                providers: 1,
+            }
+        );
+        let store = history::open_in(&dir, &sqlite()).unwrap();
+        assert_eq!(store.entries().unwrap().len(), 2);
+        assert_eq!(store.ip_changes().unwrap().len(), 1);
+        assert!(store.load_state().unwrap().providers["duckdns-a"]
+            .last_success
+            .is_some());
+
+        // Not imported twice; the JSON files are left alone
+        let err = history::migrate(&dir, &sqlite(), HistoryBackend::Sqlite).unwrap_err();
+        assert!(err.to_string().contains("already holds 3 entries"));
+        assert_eq!(json.entries().unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_appends_are_not_lost() {
+        let dir = std::env::temp_dir().join(format!(
+            "rusty-dns-sqlite-concurrent-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let shared: Arc<dyn HistoryStore> = Arc::new(SqliteStore::open(&dir, &sqlite()).unwrap());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                // Half share a connection, half open their own like separate
+                // processes would
+                let store = if task % 2 == 0 {
+                    shared.clone()
+                } else {
+                    history::open_in(&dir, &sqlite()).unwrap()
+                };
+                tokio::task::spawn_blocking(move || {
+                    for i in 0..10 {
+                        store
+                            .append(&[entry(&format!("{}-{}", task, i), 0)])
+                            .unwrap();
+                        store
+                            .update_state(|state| {
+                                state.provider_mut("shared").consecutive_failures += 1
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(shared.entries().unwrap().len(), 80);
+        assert_eq!(
+            shared.load_state().unwrap().consecutive_failures("shared"),
+            80
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
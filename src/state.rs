@@ -0,0 +1,462 @@
+//! Persistent daemon state shared between processes.
+//!
+//! Stored through [`crate::storage`], which locks, versions and atomically
+//! writes the file.
+
+use crate::detector::{Ipv6Prefix, ServiceHealth};
+use crate::error::Result;
+use crate::providers::UpdateErrorKind;
+use crate::stats::CycleRecord;
+use crate::storage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// File name of the state file inside the state directory.
+pub const STATE_FILE: &str = "state.json";
+
+/// Kind named in the state file's header.
+const STATE_KIND: &str = "state";
+
+/// Persistent state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Last detected public IPv4 address.
+    #[serde(default)]
+    pub last_ipv4: Option<IpAddr>,
+
+    /// Last detected public IPv6 address.
+    #[serde(default)]
+    pub last_ipv6: Option<IpAddr>,
+
+    /// When the public IPv4 address was last detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ipv4_confirmed: Option<DateTime<Utc>>,
+
+    /// When the public IPv6 address was last detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ipv6_confirmed: Option<DateTime<Utc>>,
+
+    /// Last IPv6 prefix of the network, which addresses of entries with
+    /// `ipv6_suffix` are composed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ipv6_prefix: Option<Ipv6Prefix>,
+
+    /// Per-provider state, keyed by `ProviderConfig::label()`.
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderState>,
+
+    /// Health of the IP detection services, keyed by service.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ip_services: BTreeMap<String, ServiceHealth>,
+
+    /// Aggregates of the last daemon cycles, oldest first (see
+    /// [`State::record_cycle`]).
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub cycles: VecDeque<CycleRecord>,
+}
+
+/// Per-provider state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProviderState {
+    /// Number of failed updates since the last success.
+    #[serde(default)]
+    pub consecutive_failures: u32,
+
+    /// Whether the current failure streak has already been escalated.
+    #[serde(default)]
+    pub alerted: bool,
+
+    /// Last successful update.
+    #[serde(default)]
+    pub last_success: Option<DateTime<Utc>>,
+
+    /// Last failed update.
+    #[serde(default)]
+    pub last_failure: Option<DateTime<Utc>>,
+
+    /// Error message of the last failed update.
+    #[serde(default)]
+    pub last_error: Option<String>,
+
+    /// Kind of the last failed update's error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error_kind: Option<UpdateErrorKind>,
+
+    /// Time before which the provider asked not to be sent updates, after
+    /// rate limiting them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+
+    /// Last IPv4 address successfully published (A record).
+    #[serde(default)]
+    pub last_ipv4: Option<IpAddr>,
+
+    /// Last IPv6 address successfully published (AAAA record).
+    #[serde(default)]
+    pub last_ipv6: Option<IpAddr>,
+
+    /// Last time an update was successfully pushed to the provider.
+    #[serde(default)]
+    pub last_refresh: Option<DateTime<Utc>>,
+
+    /// Last time a record of the provider was read (from its API or DNS)
+    /// and found to hold the detected address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_verified_at: Option<DateTime<Utc>>,
+}
+
+impl ProviderState {
+    /// Get the last address successfully published in the same family as `ip`.
+    pub fn published(&self, ip: &IpAddr) -> Option<IpAddr> {
+        if ip.is_ipv4() {
+            self.last_ipv4
+        } else {
+            self.last_ipv6
+        }
+    }
+
+    /// Remember an address as successfully published.
+    pub fn set_published(&mut self, ip: IpAddr) {
+        if ip.is_ipv4() {
+            self.last_ipv4 = Some(ip);
+        } else {
+            self.last_ipv6 = Some(ip);
+        }
+    }
+
+    /// Check whether the record must be pushed again to keep it from expiring.
+    pub fn refresh_due(&self, interval: Option<chrono::Duration>, now: DateTime<Utc>) -> bool {
+        match (interval, self.last_refresh) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(interval), Some(last)) => now - last >= interval,
+        }
+    }
+
+    /// Check whether `ip` was successfully pushed less than `interval`
+    /// before `now`, so sending it again would only hammer the provider.
+    pub fn updated_within(
+        &self,
+        ip: &IpAddr,
+        interval: Option<chrono::Duration>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match (interval, self.last_refresh) {
+            (Some(interval), Some(last)) => {
+                self.published(ip) == Some(*ip) && now - last < interval
+            }
+            _ => false,
+        }
+    }
+
+    /// Record a successful update, ending any failure streak.
+    ///
+    /// Returns true if this ends a failure streak.
+    pub fn record_success(&mut self) -> bool {
+        let recovered = self.consecutive_failures > 0;
+        self.consecutive_failures = 0;
+        self.alerted = false;
+        self.last_success = Some(Utc::now());
+        self.last_error = None;
+        self.last_error_kind = None;
+        self.retry_at = None;
+        recovered
+    }
+
+    /// Record a failed update.
+    ///
+    /// Returns true exactly once per streak, when the streak is escalated:
+    /// once the failure count reaches `threshold` (0 disables escalation),
+    /// or right away for permanent errors, as retrying won't fix them.
+    /// Rate-limited failures aren't escalated; they set when to retry.
+    pub fn record_failure(
+        &mut self,
+        error: String,
+        kind: Option<UpdateErrorKind>,
+        threshold: u32,
+    ) -> bool {
+        let now = Utc::now();
+        self.consecutive_failures += 1;
+        self.last_failure = Some(now);
+        self.last_error = Some(error);
+        self.last_error_kind = kind;
+        self.retry_at = match kind {
+            Some(UpdateErrorKind::RateLimited {
+                retry_after: Some(delay),
+            }) => chrono::Duration::from_std(delay)
+                .ok()
+                .map(|delay| now + delay),
+            _ => None,
+        };
+
+        let escalate = match kind {
+            Some(UpdateErrorKind::RateLimited { .. }) => false,
+            Some(kind) if kind.is_permanent() => true,
+            _ => self.consecutive_failures >= threshold,
+        };
+        if threshold > 0 && escalate && !self.alerted {
+            self.alerted = true;
+            return true;
+        }
+        false
+    }
+
+    /// Check whether the provider asked not to be sent updates before
+    /// `now`.
+    pub fn retry_pending(&self, now: DateTime<Utc>) -> bool {
+        self.retry_at.is_some_and(|at| now < at)
+    }
+}
+
+impl State {
+    /// Get the state file path inside a state directory.
+    pub fn path_in(dir: &Path) -> PathBuf {
+        dir.join(STATE_FILE)
+    }
+
+    /// Load state from a specific path (missing or corrupt file yields
+    /// empty state).
+    pub fn load_from(path: &Path) -> Result<Self> {
+        storage::load(path, STATE_KIND)
+    }
+
+    /// Save state to a specific path.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        storage::save(path, STATE_KIND, self)
+    }
+
+    /// Atomically read, modify, and write the state file.
+    ///
+    /// Holds an exclusive lock on the state directory's lock file so
+    /// concurrent processes (daemon, `update`, MCP) don't lose writes. A
+    /// corrupt file is moved aside and `f` is given empty state.
+    pub fn update<T>(path: &Path, f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        storage::update(path, STATE_KIND, f)
+    }
+
+    /// Get the state for a provider, creating it if missing.
+    pub fn provider_mut(&mut self, key: &str) -> &mut ProviderState {
+        self.providers.entry(key.to_string()).or_default()
+    }
+
+    /// Get the last detected public address of the same family as `ip`.
+    pub fn last_ip(&self, ip: &IpAddr) -> Option<IpAddr> {
+        if ip.is_ipv4() {
+            self.last_ipv4
+        } else {
+            self.last_ipv6
+        }
+    }
+
+    /// Remember a detected public address, confirmed now.
+    pub fn set_last_ip(&mut self, ip: IpAddr) {
+        if ip.is_ipv4() {
+            self.last_ipv4 = Some(ip);
+            self.last_ipv4_confirmed = Some(Utc::now());
+        } else {
+            self.last_ipv6 = Some(ip);
+            self.last_ipv6_confirmed = Some(Utc::now());
+        }
+    }
+
+    /// Remember the aggregates of a daemon cycle, keeping the last `max`
+    /// cycles (`history.max_cycles`; 0 keeps none).
+    pub fn record_cycle(&mut self, cycle: CycleRecord, max: usize) {
+        self.cycles.push_back(cycle);
+        while self.cycles.len() > max {
+            self.cycles.pop_front();
+        }
+    }
+
+    /// Get the consecutive failure count for a provider.
+    pub fn consecutive_failures(&self, key: &str) -> u32 {
+        self.providers
+            .get(key)
+            .map(|p| p.consecutive_failures)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_once_per_streak() {
+        let mut state = ProviderState::default();
+        assert!(!state.record_failure("e1".to_string(), None, 3));
+        assert!(!state.record_failure("e2".to_string(), None, 3));
+        assert!(state.record_failure("e3".to_string(), None, 3));
+        assert!(!state.record_failure("e4".to_string(), None, 3));
+        assert_eq!(state.consecutive_failures, 4);
+
+        assert!(state.record_success());
+        assert_eq!(state.consecutive_failures, 0);
+
+        // Re-armed after success
+        assert!(!state.record_failure("e1".to_string(), None, 3));
+        assert!(!state.record_failure("e2".to_string(), None, 3));
+        assert!(state.record_failure("e3".to_string(), None, 3));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let auth = Some(UpdateErrorKind::Auth);
+        let limited = Some(UpdateErrorKind::RateLimited {
+            retry_after: Some(std::time::Duration::from_secs(60)),
+        });
+
+        // Retrying won't fix a permanent error, so it is escalated at once
+        let mut state = ProviderState::default();
+        assert!(state.record_failure("403".to_string(), auth, 3));
+        assert!(!state.record_failure("403".to_string(), auth, 3));
+        assert!(!ProviderState::default().record_failure("403".to_string(), auth, 0));
+
+        // Rate limiting is waited out, not escalated
+        let mut state = ProviderState::default();
+        for _ in 0..5 {
+            assert!(!state.record_failure("429".to_string(), limited, 3));
+        }
+        let now = Utc::now();
+        assert!(state.retry_pending(now));
+        assert!(!state.retry_pending(now + chrono::Duration::seconds(61)));
+        assert_eq!(state.last_error_kind, limited);
+
+        // ...but still counts toward the streak
+        assert!(state.record_failure("timeout".to_string(), None, 3));
+        assert!(!state.retry_pending(now));
+
+        state.record_success();
+        assert_eq!(state.last_error_kind, None);
+    }
+
+    #[test]
+    fn test_zero_threshold_never_alerts() {
+        let mut state = ProviderState::default();
+        for _ in 0..10 {
+            assert!(!state.record_failure("error".to_string(), None, 0));
+        }
+    }
+
+    #[test]
+    fn test_record_cycle_keeps_last() {
+        let cycle = |detection_ms| CycleRecord {
+            timestamp: Utc::now(),
+            ipv4: None,
+            ipv6: None,
+            detection_ms,
+            providers: BTreeMap::new(),
+        };
+        let mut state = State::default();
+        for ms in 0..5 {
+            state.record_cycle(cycle(ms), 3);
+        }
+        let kept: Vec<u64> = state.cycles.iter().map(|c| c.detection_ms).collect();
+        assert_eq!(kept, [2, 3, 4]);
+
+        state.record_cycle(cycle(5), 0);
+        assert!(state.cycles.is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-state-{}", std::process::id()));
+        let path = State::path_in(&dir);
+
+        let mut state = State::default();
+        state
+            .provider_mut("cloudflare-vpn.example.com")
+            .record_failure("401".to_string(), None, 3);
+        state.save_to(&path).unwrap();
+
+        let loaded = State::load_from(&path).unwrap();
+        assert_eq!(loaded.consecutive_failures("cloudflare-vpn.example.com"), 1);
+        assert_eq!(loaded.consecutive_failures("missing"), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_published_per_family() {
+        let mut state = ProviderState::default();
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        state.set_published(v4);
+        assert_eq!(state.published(&v4), Some(v4));
+        assert_eq!(state.published(&v6), None);
+
+        state.set_published(v6);
+        assert_eq!(state.published(&v4), Some(v4));
+        assert_eq!(state.published(&v6), Some(v6));
+    }
+
+    #[test]
+    fn test_refresh_due() {
+        let now = Utc::now();
+        let day = Some(chrono::Duration::hours(24));
+        let mut state = ProviderState::default();
+
+        assert!(!state.refresh_due(None, now));
+        assert!(state.refresh_due(day, now));
+
+        state.last_refresh = Some(now - chrono::Duration::hours(23));
+        assert!(!state.refresh_due(day, now));
+
+        state.last_refresh = Some(now - chrono::Duration::hours(24));
+        assert!(state.refresh_due(day, now));
+    }
+
+    #[test]
+    fn test_updated_within() {
+        let now = Utc::now();
+        let minute = Some(chrono::Duration::seconds(60));
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let other: IpAddr = "5.6.7.8".parse().unwrap();
+        let mut state = ProviderState::default();
+        assert!(!state.updated_within(&ip, minute, now));
+
+        state.set_published(ip);
+        state.last_refresh = Some(now - chrono::Duration::seconds(30));
+        assert!(state.updated_within(&ip, minute, now));
+        assert!(!state.updated_within(&other, minute, now));
+        assert!(!state.updated_within(&ip, None, now));
+
+        state.last_refresh = Some(now - chrono::Duration::seconds(60));
+        assert!(!state.updated_within(&ip, minute, now));
+    }
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-update-{}", std::process::id()));
+        let path = State::path_in(&dir);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5 {
+                        State::update(&path, |state| {
+                            state
+                                .provider_mut("duckdns-home.duckdns.org")
+                                .record_failure("KO".to_string(), None, 0);
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let state = State::load_from(&path).unwrap();
+        assert_eq!(state.consecutive_failures("duckdns-home.duckdns.org"), 40);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+//! Trends of the daemon's update cycles (`status --verbose`, `ddns_stats`).
+//!
+//! The daemon keeps a [`CycleRecord`] of each cycle in the persistent state,
+//! the last `history.max_cycles` of them; the CLI and MCP server only read
+//! them, and summarize them with [`summarize`].
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::history;
+use crate::updater::{DetectedIps, ProviderUpdate, UpdateOutcome};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Days over which changes of the detected addresses are counted.
+pub const IP_CHANGE_DAYS: i64 = 7;
+
+/// Number of most recent cycles the latency and failure rates are taken
+/// over.
+pub const RECENT_CYCLES: usize = 100;
+
+/// What happened to a provider entry in a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CycleOutcome {
+    /// Nothing needed sending.
+    Unchanged,
+    /// Every update sent succeeded.
+    Updated,
+    /// An update sent failed.
+    Failed,
+}
+
+/// Aggregates of one daemon cycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CycleRecord {
+    /// When the cycle ran.
+    pub timestamp: DateTime<Utc>,
+    /// Detected public IPv4 address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<IpAddr>,
+    /// Detected public IPv6 address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<IpAddr>,
+    /// Time taken to detect the addresses, in milliseconds.
+    pub detection_ms: u64,
+    /// Outcome of each provider entry checked, by label.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, CycleOutcome>,
+}
+
+impl CycleRecord {
+    /// Record a cycle that detected `ips` in `detection` and made
+    /// `updates`; a cycle that detected nothing has default `ips` and no
+    /// updates.
+    pub fn new(ips: &DetectedIps, detection: Duration, updates: &[ProviderUpdate]) -> Self {
+        let mut providers = BTreeMap::new();
+        for update in updates {
+            let outcome = match &update.outcome {
+                UpdateOutcome::Attempted(result) if result.success => CycleOutcome::Updated,
+                UpdateOutcome::Attempted(_) => CycleOutcome::Failed,
+                UpdateOutcome::Unchanged => CycleOutcome::Unchanged,
+                UpdateOutcome::Unsupported | UpdateOutcome::Skipped => continue,
+            };
+            // The worst outcome of the entry's records
+            let entry = providers
+                .entry(update.label.clone())
+                .or_insert(CycleOutcome::Unchanged);
+            *entry = match (*entry, outcome) {
+                (CycleOutcome::Failed, _) | (_, CycleOutcome::Failed) => CycleOutcome::Failed,
+                (CycleOutcome::Updated, _) | (_, CycleOutcome::Updated) => CycleOutcome::Updated,
+                _ => CycleOutcome::Unchanged,
+            };
+        }
+
+        Self {
+            timestamp: Utc::now(),
+            ipv4: ips.ipv4,
+            ipv6: ips.ipv6,
+            detection_ms: detection.as_millis().try_into().unwrap_or(u64::MAX),
+            providers,
+        }
+    }
+
+    /// Whether the cycle detected an address.
+    pub fn detected(&self) -> bool {
+        self.ipv4.is_some() || self.ipv6.is_some()
+    }
+}
+
+/// Trends of the recorded cycles.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CycleStats {
+    /// Number of cycles recorded.
+    pub cycles: usize,
+    /// When the oldest recorded cycle ran.
+    pub since: Option<DateTime<Utc>>,
+    /// Changes of the detected addresses over the last [`IP_CHANGE_DAYS`]
+    /// days.
+    pub ip_changes_7d: usize,
+    /// Number of cycles the figures below are taken over: the last
+    /// [`RECENT_CYCLES`].
+    pub recent_cycles: usize,
+    /// Average time taken to detect the addresses, in milliseconds.
+    pub avg_detection_ms: Option<u64>,
+    /// Cycles that detected no address.
+    pub detection_failures: usize,
+    /// Per provider entry, by label.
+    pub providers: Vec<ProviderStats>,
+}
+
+/// Trends of one provider entry over the recent cycles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderStats {
+    /// Provider label.
+    pub label: String,
+    /// Cycles that checked the entry.
+    pub cycles: usize,
+    /// Cycles that sent it updates.
+    pub updates: usize,
+    /// Cycles in which an update failed.
+    pub failures: usize,
+    /// Share of the cycles sending updates in which one failed.
+    pub failure_rate: Option<f64>,
+}
+
+/// Summarize `cycles`, oldest first, as of `now`.
+pub fn summarize(cycles: &VecDeque<CycleRecord>, now: DateTime<Utc>) -> CycleStats {
+    let window_start = now - chrono::Duration::days(IP_CHANGE_DAYS);
+    let mut ip_changes_7d = 0;
+    let (mut last_ipv4, mut last_ipv6) = (None, None);
+    for cycle in cycles {
+        // Cycles that missed a family don't end its address
+        for (ip, last) in [(cycle.ipv4, &mut last_ipv4), (cycle.ipv6, &mut last_ipv6)] {
+            let Some(ip) = ip else { continue };
+            if last.is_some_and(|last| last != ip) && cycle.timestamp >= window_start {
+                ip_changes_7d += 1;
+            }
+            *last = Some(ip);
+        }
+    }
+
+    let recent: Vec<&CycleRecord> = cycles
+        .iter()
+        .skip(cycles.len().saturating_sub(RECENT_CYCLES))
+        .collect();
+    let avg_detection_ms = match recent.len() as u64 {
+        0 => None,
+        count => Some(recent.iter().map(|c| c.detection_ms).sum::<u64>() / count),
+    };
+
+    let mut providers: BTreeMap<&str, ProviderStats> = BTreeMap::new();
+    for (label, outcome) in recent.iter().flat_map(|c| &c.providers) {
+        let stats = providers.entry(label).or_insert_with(|| ProviderStats {
+            label: label.clone(),
+            cycles: 0,
+            updates: 0,
+            failures: 0,
+            failure_rate: None,
+        });
+        stats.cycles += 1;
+        if *outcome != CycleOutcome::Unchanged {
+            stats.updates += 1;
+        }
+        if *outcome == CycleOutcome::Failed {
+            stats.failures += 1;
+        }
+    }
+    for stats in providers.values_mut() {
+        stats.failure_rate =
+            (stats.updates > 0).then(|| stats.failures as f64 / stats.updates as f64);
+    }
+
+    CycleStats {
+        cycles: cycles.len(),
+        since: cycles.front().map(|c| c.timestamp),
+        ip_changes_7d,
+        recent_cycles: recent.len(),
+        avg_detection_ms,
+        detection_failures: recent.iter().filter(|c| !c.detected()).count(),
+        providers: providers.into_values().collect(),
+    }
+}
+
+/// Summarize the cycles recorded in the state of `config`.
+pub fn load(config: &Config) -> Result<CycleStats> {
+    let state = history::open(config)?.load_state()?;
+    Ok(summarize(&state.cycles, Utc::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::UpdateReason;
+    use crate::providers::{RecordType, UpdateResult};
+
+    fn at(minutes_ago: i64) -> DateTime<Utc> {
+        "2024-05-08T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+            - chrono::Duration::minutes(minutes_ago)
+    }
+
+    fn cycle(
+        minutes_ago: i64,
+        ipv4: Option<&str>,
+        providers: &[(&str, CycleOutcome)],
+    ) -> CycleRecord {
+        CycleRecord {
+            timestamp: at(minutes_ago),
+            ipv4: ipv4.map(|ip| ip.parse().unwrap()),
+            ipv6: None,
+            detection_ms: if ipv4.is_some() { 200 } else { 5000 },
+            providers: providers
+                .iter()
+                .map(|(label, outcome)| (label.to_string(), *outcome))
+                .collect(),
+        }
+    }
+
+    fn update(label: &str, ip: &str, outcome: UpdateOutcome) -> ProviderUpdate {
+        ProviderUpdate {
+            label: label.to_string(),
+            provider: "cloudflare",
+            domain: "home.example.com".to_string(),
+            ip: ip.parse().unwrap(),
+            reason: UpdateReason::Changed,
+            outcome,
+            verified: false,
+        }
+    }
+
+    fn attempted(ip: &str, success: bool) -> UpdateOutcome {
+        let ip: IpAddr = ip.parse().unwrap();
+        UpdateOutcome::Attempted(UpdateResult {
+            provider: "cloudflare".to_string(),
+            label: None,
+            domain: "home.example.com".to_string(),
+            record_type: RecordType::for_ip(&ip),
+            success,
+            ip: success.then_some(ip),
+            previous_ip: None,
+            error: (!success).then(|| "HTTP 500".to_string()),
+            error_kind: None,
+            timestamp: Utc::now(),
+        })
+    }
+
+    #[test]
+    fn test_record_of_cycle() {
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let updates = [
+            update("home", "192.0.2.1", attempted("192.0.2.1", true)),
+            update("home", "2001:db8::1", attempted("2001:db8::1", false)),
+            update("backup", "192.0.2.1", UpdateOutcome::Unchanged),
+            update("backup", "2001:db8::1", attempted("2001:db8::1", true)),
+            update("off", "192.0.2.1", UpdateOutcome::Skipped),
+        ];
+        let record = CycleRecord::new(&ips, Duration::from_millis(1234), &updates);
+        assert!(record.detected());
+        assert_eq!(record.detection_ms, 1234);
+        assert_eq!(
+            record.providers,
+            BTreeMap::from([
+                ("backup".to_string(), CycleOutcome::Updated),
+                ("home".to_string(), CycleOutcome::Failed),
+            ])
+        );
+
+        let failed = CycleRecord::new(&DetectedIps::default(), Duration::from_secs(5), &[]);
+        assert!(!failed.detected());
+        assert!(failed.providers.is_empty());
+    }
+
+    #[test]
+    fn test_summarize() {
+        use CycleOutcome::*;
+        let day = 24 * 60;
+        let cycles = VecDeque::from([
+            // Changes before the window aren't counted, but set the address
+            cycle(9 * day, Some("192.0.2.1"), &[("home", Updated)]),
+            cycle(8 * day, Some("192.0.2.2"), &[("home", Updated)]),
+            cycle(
+                3 * day,
+                Some("192.0.2.3"),
+                &[("home", Failed), ("backup", Updated)],
+            ),
+            cycle(2 * day, None, &[]),
+            cycle(day, Some("192.0.2.3"), &[("home", Updated)]),
+            cycle(
+                10,
+                Some("192.0.2.4"),
+                &[("home", Unchanged), ("backup", Unchanged)],
+            ),
+        ]);
+        let stats = summarize(&cycles, at(0));
+        assert_eq!(stats.cycles, 6);
+        assert_eq!(stats.since, Some(at(9 * day)));
+        assert_eq!(stats.ip_changes_7d, 2);
+        assert_eq!(stats.recent_cycles, 6);
+        assert_eq!(stats.avg_detection_ms, Some((5 * 200 + 5000) / 6));
+        assert_eq!(stats.detection_failures, 1);
+        assert_eq!(
+            stats.providers,
+            [
+                ProviderStats {
+                    label: "backup".to_string(),
+                    cycles: 2,
+                    updates: 1,
+                    failures: 0,
+                    failure_rate: Some(0.0),
+                },
+                ProviderStats {
+                    label: "home".to_string(),
+                    cycles: 5,
+                    updates: 4,
+                    failures: 1,
+                    failure_rate: Some(0.25),
+                },
+            ]
+        );
+
+        assert_eq!(summarize(&VecDeque::new(), at(0)), CycleStats::default());
+    }
+
+    #[test]
+    fn test_summarize_recent_cycles() {
+        let cycles: VecDeque<CycleRecord> = (0..RECENT_CYCLES as i64 + 50)
+            .rev()
+            .map(|i| {
+                // The older cycles all failed
+                let outcome = if i >= RECENT_CYCLES as i64 {
+                    CycleOutcome::Failed
+                } else {
+                    CycleOutcome::Unchanged
+                };
+                cycle(i, Some("192.0.2.1"), &[("home", outcome)])
+            })
+            .collect();
+        let stats = summarize(&cycles, at(0));
+        assert_eq!(stats.cycles, RECENT_CYCLES + 50);
+        assert_eq!(stats.recent_cycles, RECENT_CYCLES);
+        assert_eq!(stats.providers[0].cycles, RECENT_CYCLES);
+        assert_eq!(stats.providers[0].failures, 0);
+        assert_eq!(stats.providers[0].failure_rate, None);
+    }
+}
@@ -0,0 +1,428 @@
+//! Locked, atomic, versioned files in the state directory.
+//!
+//! The state file, update history and IP change log are shared by the
+//! daemon, `update` and the MCP server, so every write goes through here:
+//!
+//! - Files start with a header line naming what they hold and the format
+//!   version, e.g. `{"rusty_dns":"state","version":1}`. Files written
+//!   before headers existed are read as version 1.
+//! - Writes go to a temporary file renamed over the original, so readers
+//!   never see a half-written file and need no lock.
+//! - Read-modify-write cycles hold the state directory's lock file, so
+//!   concurrent processes don't lose each other's writes.
+//! - A file that can't be read is moved aside (`<file>.corrupt-<time>`)
+//!   with an error logged, and started afresh.
+
+use crate::error::{DdnsError, Result};
+use crate::lock::FileLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// File name of the lock guarding the files in the state directory.
+pub const LOCK_FILE: &str = "state.lock";
+
+/// Format version written in file headers.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// First line of every file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    /// What the file holds, e.g. `state` or `history`.
+    rusty_dns: String,
+    /// Format version.
+    version: u32,
+}
+
+/// Get the lock file path guarding a file in the state directory.
+pub fn lock_path(path: &Path) -> PathBuf {
+    path.with_file_name(LOCK_FILE)
+}
+
+/// Acquire the lock guarding a file in the state directory, blocking until
+/// other processes release it.
+pub fn lock(path: &Path) -> Result<FileLock> {
+    FileLock::exclusive(&lock_path(path))
+}
+
+/// Write a file atomically: to a temporary file in the same directory,
+/// flushed to disk and renamed over `path`.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = std::fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| std::fs::rename(&tmp, path)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+
+    // Persist the rename itself; not possible on every platform
+    #[cfg(unix)]
+    if let Ok(dir) = std::fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Load a JSON document written by [`save`] (missing file yields the
+/// default).
+///
+/// An unreadable file is reported and read as the default, but left in
+/// place for the next locked write to move aside.
+pub fn load<T: DeserializeOwned + Default>(path: &Path, kind: &str) -> Result<T> {
+    match read_document(path, kind)? {
+        Read::Missing => Ok(T::default()),
+        Read::Ok(value) => Ok(value),
+        Read::Corrupt(reason) => {
+            tracing::error!(
+                "{} is corrupt ({}); ignoring it until the next write replaces it",
+                path.display(),
+                reason
+            );
+            Ok(T::default())
+        }
+    }
+}
+
+/// Save a JSON document atomically, holding the lock.
+pub fn save<T: Serialize>(path: &Path, kind: &str, value: &T) -> Result<()> {
+    let _lock = lock(path)?;
+    write_document(path, kind, value)
+}
+
+/// Atomically read, modify, and write a JSON document, holding the lock.
+///
+/// A corrupt file is moved aside and `f` is given the default.
+pub fn update<T, R>(path: &Path, kind: &str, f: impl FnOnce(&mut T) -> R) -> Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let _lock = lock(path)?;
+
+    let mut value = match read_document(path, kind)? {
+        Read::Missing => T::default(),
+        Read::Ok(value) => value,
+        Read::Corrupt(reason) => {
+            quarantine(path, &reason)?;
+            T::default()
+        }
+    };
+    let result = f(&mut value);
+    write_document(path, kind, &value)?;
+    Ok(result)
+}
+
+/// Load the lines of a JSON lines file (missing file yields no lines).
+///
+/// Lines that fail to parse, such as one cut short by a crash, are skipped.
+pub fn load_lines<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<Vec<T>> {
+    match read_lines(path, kind)? {
+        Read::Missing => Ok(Vec::new()),
+        Read::Ok(lines) => Ok(lines),
+        Read::Corrupt(reason) => {
+            tracing::error!(
+                "{} is corrupt ({}); ignoring it until the next write replaces it",
+                path.display(),
+                reason
+            );
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Atomically read, modify, and write a JSON lines file, holding the lock.
+///
+/// A corrupt file is moved aside and `f` is given no lines.
+pub fn update_lines<T, R>(path: &Path, kind: &str, f: impl FnOnce(&mut Vec<T>) -> R) -> Result<R>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let _lock = lock(path)?;
+
+    let mut lines = match read_lines(path, kind)? {
+        Read::Missing => Vec::new(),
+        Read::Ok(lines) => lines,
+        Read::Corrupt(reason) => {
+            quarantine(path, &reason)?;
+            Vec::new()
+        }
+    };
+    let result = f(&mut lines);
+
+    let mut content = header(kind)?;
+    for line in &lines {
+        content.push_str(&serde_json::to_string(line)?);
+        content.push('\n');
+    }
+    write_atomic(path, content.as_bytes())?;
+    Ok(result)
+}
+
+/// Outcome of reading a file.
+enum Read<T> {
+    Missing,
+    Ok(T),
+    Corrupt(String),
+}
+
+fn header(kind: &str) -> Result<String> {
+    let header = Header {
+        rusty_dns: kind.to_string(),
+        version: FORMAT_VERSION,
+    };
+    Ok(format!("{}\n", serde_json::to_string(&header)?))
+}
+
+fn write_document<T: Serialize>(path: &Path, kind: &str, value: &T) -> Result<()> {
+    let mut content = header(kind)?;
+    content.push_str(&serde_json::to_string_pretty(value)?);
+    content.push('\n');
+    write_atomic(path, content.as_bytes())
+}
+
+/// Read a file's content after its header.
+///
+/// Fails if the file was written by a newer format version, so it isn't
+/// overwritten with less than it holds.
+fn read_body(path: &Path, kind: &str) -> Result<Read<String>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Read::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    let Ok(content) = String::from_utf8(bytes) else {
+        return Ok(Read::Corrupt("not UTF-8".to_string()));
+    };
+
+    let (first, rest) = content.split_once('\n').unwrap_or((&content, ""));
+    let Ok(header) = serde_json::from_str::<Header>(first) else {
+        // Written before headers existed
+        return Ok(Read::Ok(content));
+    };
+
+    if header.rusty_dns != kind {
+        return Ok(Read::Corrupt(format!(
+            "holds {}, expected {}",
+            header.rusty_dns, kind
+        )));
+    }
+    if header.version > FORMAT_VERSION {
+        return Err(DdnsError::Serialization(format!(
+            "{} was written by a newer version of rusty-dns (format {}, this version reads up to {})",
+            path.display(),
+            header.version,
+            FORMAT_VERSION
+        )));
+    }
+    Ok(Read::Ok(rest.to_string()))
+}
+
+fn read_document<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<Read<T>> {
+    Ok(match read_body(path, kind)? {
+        Read::Ok(body) if body.trim().is_empty() => Read::Corrupt("empty".to_string()),
+        Read::Ok(body) => match serde_json::from_str(&body) {
+            Ok(value) => Read::Ok(value),
+            Err(e) => Read::Corrupt(e.to_string()),
+        },
+        Read::Missing => Read::Missing,
+        Read::Corrupt(reason) => Read::Corrupt(reason),
+    })
+}
+
+fn read_lines<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<Read<Vec<T>>> {
+    Ok(match read_body(path, kind)? {
+        Read::Ok(body) => Read::Ok(
+            body.lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str(line) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable line in {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+        ),
+        Read::Missing => Read::Missing,
+        Read::Corrupt(reason) => Read::Corrupt(reason),
+    })
+}
+
+/// Move a corrupt file aside, so it can be inspected, and log it.
+fn quarantine(path: &Path, reason: &str) -> Result<()> {
+    let backup = path.with_file_name(format!(
+        "{}.corrupt-{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    std::fs::rename(path, &backup)?;
+    tracing::error!(
+        "{} is corrupt ({}); moved it to {} and starting fresh",
+        path.display(),
+        reason,
+        backup.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn backups(dir: &Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".corrupt-"))
+            .collect()
+    }
+
+    #[test]
+    fn test_versioned_header() {
+        let dir = temp_dir("storage-header");
+        let path = dir.join("doc.json");
+
+        let value = BTreeMap::from([("a".to_string(), 1)]);
+        save(&path, "doc", &value).unwrap();
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(raw.starts_with("{\"rusty_dns\":\"doc\",\"version\":1}\n"));
+        assert_eq!(load::<BTreeMap<String, i32>>(&path, "doc").unwrap(), value);
+
+        // Files written before headers are read as they are
+        std::fs::write(&path, "{\"b\": 2}").unwrap();
+        let legacy: BTreeMap<String, i32> = load(&path, "doc").unwrap();
+        assert_eq!(legacy["b"], 2);
+
+        // A newer format isn't overwritten with less than it holds
+        let newer = "{\"rusty_dns\":\"doc\",\"version\":99}\n{}\n";
+        std::fs::write(&path, newer).unwrap();
+        let err = update(&path, "doc", |_: &mut BTreeMap<String, i32>| {}).unwrap_err();
+        assert!(err.to_string().contains("newer version"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), newer);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_document_is_moved_aside() {
+        let dir = temp_dir("storage-corrupt");
+        let path = dir.join("doc.json");
+
+        for garbage in [
+            &b"{\"rusty_dns\":\"doc\",\"version\":1}\n{\"a\": "[..],
+            b"",
+            b"\xff\xfe",
+        ] {
+            std::fs::write(&path, garbage).unwrap();
+
+            // Reads alone leave the file for inspection
+            assert!(load::<BTreeMap<String, i32>>(&path, "doc")
+                .unwrap()
+                .is_empty());
+            assert!(backups(&dir).is_empty());
+
+            let seen = update(&path, "doc", |value: &mut BTreeMap<String, i32>| {
+                let seen = value.len();
+                value.insert("fresh".to_string(), 1);
+                seen
+            })
+            .unwrap();
+            assert_eq!(seen, 0);
+            assert_eq!(backups(&dir).len(), 1);
+            let loaded: BTreeMap<String, i32> = load(&path, "doc").unwrap();
+            assert_eq!(loaded["fresh"], 1);
+
+            for backup in backups(&dir) {
+                assert_eq!(std::fs::read(dir.join(backup.as_str())).unwrap(), garbage);
+                std::fs::remove_file(dir.join(backup)).unwrap();
+            }
+        }
+
+        // Another kind of file isn't taken for this one
+        save(&path, "other", &BTreeMap::<String, i32>::new()).unwrap();
+        update(&path, "doc", |_: &mut BTreeMap<String, i32>| {}).unwrap();
+        assert_eq!(backups(&dir).len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_line_is_skipped() {
+        let dir = temp_dir("storage-lines");
+        let path = dir.join("log.jsonl");
+
+        update_lines(&path, "log", |lines: &mut Vec<u32>| lines.extend([1, 2])).unwrap();
+        let mut raw = std::fs::read_to_string(&path).unwrap();
+        raw.push_str("{\"cut");
+        std::fs::write(&path, raw).unwrap();
+
+        assert_eq!(load_lines::<u32>(&path, "log").unwrap(), vec![1, 2]);
+        update_lines(&path, "log", |lines: &mut Vec<u32>| lines.push(3)).unwrap();
+        assert_eq!(load_lines::<u32>(&path, "log").unwrap(), vec![1, 2, 3]);
+        assert!(backups(&dir).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_appends_are_not_lost() {
+        let dir = temp_dir("storage-concurrent");
+        let path = dir.join("log.jsonl");
+
+        let tasks: Vec<_> = (0..8u32)
+            .map(|task| {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    for i in 0..10 {
+                        update_lines(&path, "log", |lines: &mut Vec<u32>| {
+                            lines.push(task * 100 + i)
+                        })
+                        .unwrap();
+                        // Readers never see a half-written file
+                        assert!(load_lines::<u32>(&path, "log").unwrap().len() > i as usize);
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let mut lines = load_lines::<u32>(&path, "log").unwrap();
+        assert_eq!(lines.len(), 80);
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 80);
+        assert!(backups(&dir).is_empty());
+        // No temporary files are left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
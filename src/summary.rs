@@ -0,0 +1,204 @@
+//! Machine-readable summary of the last update cycle (`daemon.summary_file`).
+//!
+//! Written atomically after every cycle that detected an address, by the
+//! daemon and `rusty-dns update`, for scripts that want the published
+//! addresses without parsing logs or calling provider APIs. The layout is
+//! versioned by [`SUMMARY_VERSION`]: fields may be added within a version,
+//! but are never renamed or removed.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::state::State;
+use crate::storage;
+use crate::updater::DetectedIps;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Version of the summary layout, bumped on incompatible changes.
+pub const SUMMARY_VERSION: u32 = 1;
+
+/// Summary of the last update cycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    /// Layout version ([`SUMMARY_VERSION`]).
+    pub version: u32,
+    /// When the summary was written.
+    pub generated_at: DateTime<Utc>,
+    /// Public IPv4 address detected by the cycle.
+    pub current_ipv4: Option<IpAddr>,
+    /// Public IPv6 address detected by the cycle.
+    pub current_ipv6: Option<IpAddr>,
+    /// One entry per enabled provider, in config order.
+    pub providers: Vec<ProviderSummary>,
+}
+
+/// Published addresses of one provider entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderSummary {
+    /// Provider label (its key in persistent state).
+    pub label: String,
+    /// Provider type.
+    pub provider: String,
+    /// Domain being managed.
+    pub domain: String,
+    /// Address last published to the record of the provider's first
+    /// address family (A, or AAAA for IPv6-only entries).
+    pub ip: Option<IpAddr>,
+    /// Address last published to the AAAA record, for entries publishing
+    /// both families.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<IpAddr>,
+    /// Whether every record holds the detected address of its family.
+    pub in_sync: bool,
+    /// Last successful update sent to the provider.
+    pub last_update: Option<DateTime<Utc>>,
+}
+
+impl Summary {
+    /// Summarize the state saved after a cycle that detected `ips`.
+    pub fn new(config: &Config, ips: &DetectedIps, state: &State) -> Self {
+        let providers = config
+            .enabled_providers()
+            .map(|provider_config| {
+                let label = provider_config.label();
+                let published = state.providers.get(&label).cloned().unwrap_or_default();
+                let version = provider_config.ip_version(config.ip_version);
+                let detected = ips
+                    .with_suffix(provider_config.ipv6_suffix, config.ipv6_prefix_length)
+                    .for_version(version);
+                let (ip, ipv6) = match (version.includes_v4(), version.includes_v6()) {
+                    (true, true) => (published.last_ipv4, published.last_ipv6),
+                    (true, false) => (published.last_ipv4, None),
+                    (false, _) => (published.last_ipv6, None),
+                };
+
+                ProviderSummary {
+                    provider: provider_config.name().to_string(),
+                    domain: provider_config.display_name(),
+                    ip,
+                    ipv6,
+                    in_sync: !detected.is_empty()
+                        && detected
+                            .iter()
+                            .all(|ip| published.published(ip) == Some(*ip)),
+                    last_update: published.last_success,
+                    label,
+                }
+            })
+            .collect();
+
+        Self {
+            version: SUMMARY_VERSION,
+            generated_at: Utc::now(),
+            current_ipv4: ips.ipv4,
+            current_ipv6: ips.ipv6,
+            providers,
+        }
+    }
+}
+
+/// Write `summary` to `path` as JSON, atomically so readers (or inotify
+/// watchers) never see a partial file.
+pub fn write(path: &Path, summary: &Summary) -> Result<()> {
+    let mut json = serde_json::to_vec_pretty(summary)?;
+    json.push(b'\n');
+    storage::write_atomic(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            [[providers]]
+            type = "cloudflare"
+            label = "home"
+            api_token = "token"
+            zone_id = "zone"
+            record_name = "home.example.com"
+            ip_version = "both"
+
+            [[providers]]
+            type = "duckdns"
+            label = "backup"
+            token = "token"
+            domains = "myhome"
+
+            [[providers]]
+            type = "duckdns"
+            label = "off"
+            token = "token"
+            domains = "old"
+            enabled = false
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_summary_layout() {
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        let home = state.provider_mut("home");
+        home.last_ipv4 = ips.ipv4;
+        home.last_ipv6 = ips.ipv6;
+        home.last_success = Some("2024-05-01T12:00:00Z".parse().unwrap());
+        state.provider_mut("backup").last_ipv4 = Some("192.0.2.9".parse().unwrap());
+
+        let summary = Summary {
+            generated_at: "2024-05-01T12:05:00Z".parse().unwrap(),
+            ..Summary::new(&config(), &ips, &state)
+        };
+        assert_eq!(
+            serde_json::to_value(&summary).unwrap(),
+            serde_json::json!({
+                "version": 1,
+                "generated_at": "2024-05-01T12:05:00Z",
+                "current_ipv4": "192.0.2.1",
+                "current_ipv6": "2001:db8::1",
+                "providers": [
+                    {
+                        "label": "home",
+                        "provider": "cloudflare",
+                        "domain": "home.example.com",
+                        "ip": "192.0.2.1",
+                        "ipv6": "2001:db8::1",
+                        "in_sync": true,
+                        "last_update": "2024-05-01T12:00:00Z"
+                    },
+                    {
+                        "label": "backup",
+                        "provider": "duckdns",
+                        "domain": "myhome.duckdns.org",
+                        "ip": "192.0.2.9",
+                        "in_sync": false,
+                        "last_update": null
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-summary-{}", std::process::id()));
+        let path = dir.join("summary.json");
+        let summary = Summary::new(&config(), &DetectedIps::default(), &State::default());
+
+        write(&path, &summary).unwrap();
+        let written: Summary =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, summary);
+        assert!(summary.providers.iter().all(|p| !p.in_sync));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
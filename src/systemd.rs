@@ -0,0 +1,45 @@
+//! Minimal systemd service notification (sd_notify) support.
+
+/// Send a notification message (e.g. `"STATUS=..."`) to systemd.
+///
+/// Does nothing when not running under systemd (`NOTIFY_SOCKET` unset).
+#[cfg(unix)]
+pub fn notify(message: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let bytes = socket_path.as_encoded_bytes();
+        if let Some(name) = bytes.strip_prefix(b"@") {
+            // Abstract socket namespace
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                return socket.send_to_addr(message.as_bytes(), &addr);
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = name;
+                return Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+            }
+        }
+        socket.send_to(message.as_bytes(), &socket_path)
+    });
+
+    if let Err(e) = result {
+        tracing::debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Send a notification message to systemd (no-op on this platform).
+#[cfg(not(unix))]
+pub fn notify(_message: &str) {}
+
+/// Update the service status line shown by `systemctl status`.
+pub fn notify_status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
@@ -0,0 +1,354 @@
+//! Aligned, optionally colored tables for command output.
+
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+/// Columns are never narrowed below this.
+const MIN_COLUMN_WIDTH: usize = 10;
+
+/// Space between columns.
+const SEPARATOR: &str = "  ";
+
+/// Color of a cell's text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    /// The terminal's default color.
+    #[default]
+    Default,
+    /// Success.
+    Green,
+    /// Skipped or unknown.
+    Yellow,
+    /// Failure.
+    Red,
+}
+
+impl Color {
+    fn code(self) -> Option<&'static str> {
+        match self {
+            Color::Default => None,
+            Color::Green => Some("32"),
+            Color::Yellow => Some("33"),
+            Color::Red => Some("31"),
+        }
+    }
+}
+
+/// A table cell.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cell {
+    text: String,
+    color: Color,
+}
+
+impl Cell {
+    /// Create a cell with colored text.
+    pub fn colored(text: impl Into<String>, color: Color) -> Self {
+        Self {
+            text: text.into(),
+            color,
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Self::colored(text, Color::Default)
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Self::colored(text, Color::Default)
+    }
+}
+
+/// A table with a header row, rendered with aligned columns.
+///
+/// When the table is wider than its maximum width, the widest columns are
+/// narrowed and their cells wrapped onto several lines.
+#[derive(Debug, Clone)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    width: Option<usize>,
+    color: bool,
+}
+
+impl Table {
+    /// Create a table without color or width limit.
+    pub fn new(header: &[&str]) -> Self {
+        Self {
+            header: header.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            width: None,
+            color: false,
+        }
+    }
+
+    /// Create a table for stdout, colored and as wide as the terminal when
+    /// stdout is one (color is disabled by a non-empty `NO_COLOR`).
+    pub fn for_stdout(header: &[&str]) -> Self {
+        let terminal = std::io::stdout().is_terminal();
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Self::new(header)
+            .with_width(terminal.then(terminal_width))
+            .with_color(terminal && !no_color)
+    }
+
+    /// Set the maximum width of a line.
+    pub fn with_width(mut self, width: Option<usize>) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Set whether cells are colored with ANSI escape codes.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Add a row; missing cells are left empty.
+    pub fn push<C: Into<Cell>>(&mut self, row: impl IntoIterator<Item = C>) {
+        self.rows.push(row.into_iter().map(Into::into).collect());
+    }
+
+    /// Width of each column, narrowed to fit the maximum width if possible.
+    ///
+    /// The widest column is narrowed first. Words are only split once every
+    /// column is as narrow as its longest word.
+    fn column_widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.header.iter().map(|h| len(h)).collect();
+        let mut words = widths.clone();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(widths.len()) {
+                widths[i] = widths[i].max(len(&cell.text));
+                let longest = cell.text.split_whitespace().map(len).max();
+                words[i] = words[i].max(longest.unwrap_or_default());
+            }
+        }
+
+        let Some(max) = self.width else {
+            return widths;
+        };
+        let available = max.saturating_sub(SEPARATOR.len() * widths.len().saturating_sub(1));
+        let floors: [Vec<usize>; 2] = [
+            words.iter().map(|&w| w.max(MIN_COLUMN_WIDTH)).collect(),
+            vec![MIN_COLUMN_WIDTH; widths.len()],
+        ];
+        for floor in &floors {
+            while widths.iter().sum::<usize>() > available {
+                let widest = widths
+                    .iter_mut()
+                    .zip(floor)
+                    .filter(|(w, floor)| **w > **floor)
+                    .map(|(w, _)| w)
+                    .max();
+                match widest {
+                    Some(widest) => *widest -= 1,
+                    None => break,
+                }
+            }
+        }
+        widths
+    }
+
+    /// Render the table, one line per row (or more for wrapped cells).
+    pub fn render(&self) -> String {
+        let widths = self.column_widths();
+        let header: Vec<Cell> = self.header.iter().map(|h| Cell::from(h.as_str())).collect();
+
+        let mut out = String::new();
+        for row in std::iter::once(&header).chain(&self.rows) {
+            let cells: Vec<(Vec<String>, Color)> = widths
+                .iter()
+                .enumerate()
+                .map(|(i, &width)| {
+                    let cell = row.get(i).cloned().unwrap_or_default();
+                    (wrap(&cell.text, width), cell.color)
+                })
+                .collect();
+            let lines = cells.iter().map(|(l, _)| l.len()).max().unwrap_or(1);
+
+            for line in 0..lines {
+                let mut text = String::new();
+                for (i, ((cell_lines, color), width)) in cells.iter().zip(&widths).enumerate() {
+                    let part = cell_lines.get(line).map(String::as_str).unwrap_or_default();
+                    if i > 0 {
+                        text.push_str(SEPARATOR);
+                    }
+                    match color.code().filter(|_| self.color && !part.is_empty()) {
+                        Some(code) => {
+                            let _ = write!(text, "\x1b[{}m{}\x1b[0m", code, part);
+                        }
+                        None => text.push_str(part),
+                    }
+                    text.extend(std::iter::repeat(' ').take(width.saturating_sub(len(part))));
+                }
+                out.push_str(text.trim_end());
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render())
+    }
+}
+
+fn len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Wrap text at spaces to lines of at most `width` characters, splitting
+/// longer words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && len(&line) + 1 + len(word) > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+
+        let mut chars: Vec<char> = word.chars().collect();
+        while chars.len() > width {
+            let rest = chars.split_off(width);
+            lines.push(chars.into_iter().collect());
+            chars = rest;
+        }
+        line.extend(chars);
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Width of the terminal on stdout, from `COLUMNS` or the terminal itself
+/// (default: 80).
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .or_else(terminal_columns)
+        .filter(|&c| c > 0)
+        .unwrap_or(80)
+}
+
+#[cfg(unix)]
+fn terminal_columns() -> Option<usize> {
+    // SAFETY: winsize is plain data and TIOCGWINSZ only writes to it.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (ret == 0).then_some(usize::from(size.ws_col))
+}
+
+#[cfg(not(unix))]
+fn terminal_columns() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&["PROVIDER", "DOMAIN", "RESULT"]);
+        table.push([
+            Cell::from("home"),
+            Cell::from("vpn.example.com"),
+            Cell::colored("OK", Color::Green),
+        ]);
+        table.push([
+            Cell::from("cloudflare-nas.example.com"),
+            Cell::from("nas.example.com"),
+            Cell::colored(
+                "FAILED: Provider error (cloudflare): Invalid API token",
+                Color::Red,
+            ),
+        ]);
+        table.push([Cell::from("duckdns"), Cell::from("home.duckdns.org")]);
+        table
+    }
+
+    #[test]
+    fn test_render_aligned() {
+        assert_eq!(
+            table().render(),
+            "\
+PROVIDER                    DOMAIN            RESULT
+home                        vpn.example.com   OK
+cloudflare-nas.example.com  nas.example.com   FAILED: Provider error (cloudflare): Invalid API token
+duckdns                     home.duckdns.org
+"
+        );
+    }
+
+    #[test]
+    fn test_render_wrapped() {
+        assert_eq!(
+            table().with_width(Some(60)).render(),
+            "\
+PROVIDER                    DOMAIN            RESULT
+home                        vpn.example.com   OK
+cloudflare-nas.example.com  nas.example.com   FAILED:
+                                              Provider error
+                                              (cloudflare):
+                                              Invalid API
+                                              token
+duckdns                     home.duckdns.org
+"
+        );
+
+        // Words are split once nothing else fits
+        assert_eq!(
+            table().with_width(Some(40)).render(),
+            "\
+PROVIDER      DOMAIN        RESULT
+home          vpn.example.  OK
+              com
+cloudflare-n  nas.example.  FAILED:
+as.example.c  com           Provider
+om                          error
+                            (cloudflare)
+                            : Invalid
+                            API token
+duckdns       home.duckdns
+              .org
+"
+        );
+    }
+
+    #[test]
+    fn test_render_colored() {
+        let rendered = table().with_color(true).render();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(
+            lines[0],
+            "PROVIDER                    DOMAIN            RESULT"
+        );
+        assert_eq!(
+            lines[1],
+            "home                        vpn.example.com   \x1b[32mOK\x1b[0m"
+        );
+        assert!(lines[2]
+            .ends_with("\x1b[31mFAILED: Provider error (cloudflare): Invalid API token\x1b[0m"));
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("", 10), vec![""]);
+        assert_eq!(wrap("a b c", 3), vec!["a b", "c"]);
+        assert_eq!(wrap("abcdefgh ij", 3), vec!["abc", "def", "gh", "ij"]);
+        assert_eq!(wrap("ab cdefgh", 4), vec!["ab", "cdef", "gh"]);
+    }
+}
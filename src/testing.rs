@@ -0,0 +1,278 @@
+//! Test doubles for code built on the library, enabled by the `test-util`
+//! feature.
+
+use crate::config::{ProviderConfig, ProviderKind};
+use crate::error::{DdnsError, Result};
+use crate::providers::{
+    register_provider, DdnsProvider, ProviderCapabilities, RecordType, UpdateErrorKind,
+    UpdateResult, WriteProbe,
+};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Scripted outcome of a [`MockProvider`] update.
+#[derive(Debug)]
+pub enum MockUpdate {
+    /// The record is set to the address.
+    Succeed,
+    /// The provider reports a failure with this message.
+    Fail(String),
+    /// The provider reports a failure of this kind with this message.
+    FailWith(UpdateErrorKind, String),
+    /// The call returns this error.
+    Error(DdnsError),
+}
+
+/// Call made to a [`MockProvider`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+    Prepare,
+    GetCurrentIp,
+    UpdateIp(IpAddr),
+    Validate,
+    ProbeWrite,
+}
+
+/// Provider keeping its record in memory, with scripted responses and a
+/// log of the calls made to it.
+///
+/// Clones share the record, script and log, so a test can keep one while
+/// the updater creates providers from the registered type (see
+/// [`MockProvider::register`]).
+#[derive(Clone)]
+pub struct MockProvider {
+    domain: String,
+    supports_ipv6: bool,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    record: Option<IpAddr>,
+    prepares: VecDeque<Result<()>>,
+    current_ips: VecDeque<Result<Option<IpAddr>>>,
+    updates: VecDeque<MockUpdate>,
+    delay: Duration,
+    calls: Vec<MockCall>,
+}
+
+impl MockProvider {
+    /// Create a provider managing `domain`, whose record holds no address
+    /// and whose updates succeed.
+    pub fn new(domain: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            supports_ipv6: true,
+            inner: Arc::default(),
+        }
+    }
+
+    /// Start with the record holding `ip`.
+    pub fn with_record(self, ip: IpAddr) -> Self {
+        self.lock().record = Some(ip);
+        self
+    }
+
+    /// Only manage A records.
+    pub fn without_ipv6(mut self) -> Self {
+        self.supports_ipv6 = false;
+        self
+    }
+
+    /// Register the provider under `type_name` in the global registry,
+    /// returning a config entry of that type.
+    pub fn register(&self, type_name: &'static str) -> ProviderConfig {
+        let provider = self.clone();
+        register_provider(type_name, move |_| Ok(Box::new(provider.clone())));
+
+        let mut settings = toml::Table::new();
+        settings.insert("domain".to_string(), self.domain.clone().into());
+        ProviderConfig::new(ProviderKind::Other {
+            type_name: type_name.to_string(),
+            settings,
+        })
+    }
+
+    /// Queue a response of `prepare`. Once the queue is empty, it
+    /// succeeds.
+    pub fn push_prepare(&self, response: Result<()>) {
+        self.lock().prepares.push_back(response);
+    }
+
+    /// Queue a response of `get_current_ip`. Once the queue is empty, it
+    /// returns the record.
+    pub fn push_current_ip(&self, response: Result<Option<IpAddr>>) {
+        self.lock().current_ips.push_back(response);
+    }
+
+    /// Queue the outcome of an update. Once the queue is empty, updates
+    /// succeed.
+    pub fn push_update(&self, outcome: MockUpdate) {
+        self.lock().updates.push_back(outcome);
+    }
+
+    /// Make every update take `delay`.
+    pub fn set_delay(&self, delay: Duration) {
+        self.lock().delay = delay;
+    }
+
+    /// Get the address the record holds.
+    pub fn record(&self) -> Option<IpAddr> {
+        self.lock().record
+    }
+
+    /// Get the calls made so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    /// Get the addresses updates were attempted with, oldest first.
+    pub fn updated_ips(&self) -> Vec<IpAddr> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                MockCall::UpdateIp(ip) => Some(ip),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl DdnsProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn domain(&self) -> String {
+        self.domain.clone()
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        let mut inner = self.lock();
+        inner.calls.push(MockCall::Prepare);
+        inner.prepares.pop_front().unwrap_or(Ok(()))
+    }
+
+    async fn get_current_ip(&self) -> Result<Option<IpAddr>> {
+        let mut inner = self.lock();
+        inner.calls.push(MockCall::GetCurrentIp);
+        let record = inner.record;
+        inner.current_ips.pop_front().unwrap_or(Ok(record))
+    }
+
+    async fn update_ip(&self, ip: IpAddr) -> Result<UpdateResult> {
+        let (outcome, delay) = {
+            let mut inner = self.lock();
+            inner.calls.push(MockCall::UpdateIp(ip));
+            let outcome = inner.updates.pop_front().unwrap_or(MockUpdate::Succeed);
+            (outcome, inner.delay)
+        };
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        let (error, error_kind) = match outcome {
+            MockUpdate::Succeed => (None, None),
+            MockUpdate::Fail(message) => (Some(message), Some(UpdateErrorKind::Other)),
+            MockUpdate::FailWith(kind, message) => (Some(message), Some(kind)),
+            MockUpdate::Error(e) => return Err(e),
+        };
+        let previous_ip = if error.is_none() {
+            self.lock().record.replace(ip)
+        } else {
+            self.record()
+        };
+        Ok(UpdateResult {
+            provider: self.name().to_string(),
+            label: None,
+            domain: self.domain(),
+            record_type: RecordType::for_ip(&ip),
+            success: error.is_none(),
+            ip: Some(ip),
+            previous_ip,
+            error,
+            error_kind,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            supports_ipv6: self.supports_ipv6,
+            ..ProviderCapabilities::default()
+        }
+    }
+
+    async fn validate(&self) -> Result<()> {
+        self.lock().calls.push(MockCall::Validate);
+        Ok(())
+    }
+
+    /// Write the record back, with the next scripted update outcome.
+    async fn probe_write(&self) -> Result<WriteProbe> {
+        let mut inner = self.lock();
+        inner.calls.push(MockCall::ProbeWrite);
+        let Some(record) = inner.record else {
+            return Ok(WriteProbe::Unsupported(
+                "no record to write back".to_string(),
+            ));
+        };
+        match inner.updates.pop_front().unwrap_or(MockUpdate::Succeed) {
+            MockUpdate::Succeed => Ok(WriteProbe::Written(format!(
+                "wrote {} back unchanged",
+                record
+            ))),
+            MockUpdate::Fail(message) | MockUpdate::FailWith(_, message) => {
+                Err(DdnsError::Provider {
+                    provider: self.name().to_string(),
+                    message,
+                })
+            }
+            MockUpdate::Error(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_script() {
+        let old: IpAddr = "192.0.2.1".parse().unwrap();
+        let new: IpAddr = "192.0.2.2".parse().unwrap();
+        let mock = MockProvider::new("home.example").with_record(old);
+        mock.push_current_ip(Err(DdnsError::Network("timeout".to_string())));
+        mock.push_update(MockUpdate::Fail("KO".to_string()));
+        mock.set_delay(Duration::from_secs(5));
+
+        assert!(mock.get_current_ip().await.is_err());
+        assert_eq!(mock.get_current_ip().await.unwrap(), Some(old));
+
+        let started = tokio::time::Instant::now();
+        let result = mock.update_ip(new).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("KO"));
+        assert_eq!(mock.record(), Some(old));
+
+        // Clones share the record and log
+        let result = mock.clone().update_ip(new).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.previous_ip, Some(old));
+        assert_eq!(mock.record(), Some(new));
+        assert_eq!(mock.updated_ips(), vec![new, new]);
+        assert_eq!(
+            mock.calls()[..2],
+            [MockCall::GetCurrentIp, MockCall::GetCurrentIp]
+        );
+    }
+}
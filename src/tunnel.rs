@@ -0,0 +1,502 @@
+//! Reverse SSH tunnel making the MCP server or the daemon's control socket
+//! reachable from a remote host, by supervising the system `ssh` binary.
+//!
+//! The tunnel process writes its status to `tunnel.json` in the state
+//! directory, where `rusty-dns status` and the MCP server read it.
+
+use crate::config::{Config, TunnelConfig, TunnelTarget};
+use crate::error::Result;
+use crate::lock::PidFile;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the status file in the state directory.
+pub const STATUS_FILE: &str = "tunnel.json";
+
+/// Name of the tunnel's PID file in the state directory.
+const PID_FILE: &str = "tunnel.pid";
+
+/// Status of a running tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelStatus {
+    /// Tunnel process ID.
+    pub pid: u32,
+    /// Remote endpoint, e.g. `me@vps.example.com:7000`.
+    pub remote: String,
+    /// What the tunnel exposes.
+    pub exposes: TunnelTarget,
+    /// Whether ssh is connected and forwarding.
+    pub connected: bool,
+    /// When the current connection was established.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connected_since: Option<DateTime<Utc>>,
+    /// When ssh was last started again after exiting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_reconnect: Option<DateTime<Utc>>,
+    /// Number of times ssh was started again after exiting.
+    #[serde(default)]
+    pub reconnects: u32,
+    /// Why the connection last dropped or couldn't be established.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl TunnelStatus {
+    /// Read the status of the tunnel of `config`, if one is running.
+    pub fn load(config: &Config) -> Option<Self> {
+        let state_dir = config.state_dir().ok()?;
+        // A status left behind by a crashed tunnel is stale
+        PidFile::holder(&state_dir.join(PID_FILE))?;
+        let content = std::fs::read_to_string(state_dir.join(STATUS_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Write the status to the state directory, atomically so readers
+    /// never see it half-written.
+    fn save(&self, state_dir: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        crate::storage::write_atomic(&state_dir.join(STATUS_FILE), content.as_bytes())
+    }
+}
+
+/// Build the arguments making ssh forward `remote_bind` on the remote host
+/// to the Unix socket at `local`.
+///
+/// ssh never prompts, exits when the forward can't be set up, and notices a
+/// dead connection within 45 seconds.
+pub fn ssh_args(tunnel: &TunnelConfig, local: &Path) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-N",
+        "-T",
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        "ExitOnForwardFailure=yes",
+        "-o",
+        "ServerAliveInterval=15",
+        "-o",
+        "ServerAliveCountMax=3",
+        "-o",
+        "StreamLocalBindUnlink=yes",
+        "-p",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    args.push(tunnel.port.to_string());
+
+    if let Some(key) = &tunnel.identity_file {
+        args.push("-i".to_string());
+        args.push(key.display().to_string());
+        args.push("-o".to_string());
+        args.push("IdentitiesOnly=yes".to_string());
+    }
+
+    args.push("-R".to_string());
+    args.push(format!("{}:{}", tunnel.remote_bind, local.display()));
+    args.push("--".to_string());
+    args.push(tunnel.destination());
+    args
+}
+
+#[cfg(unix)]
+pub use supervisor::{Tunnel, TunnelEvent};
+
+#[cfg(unix)]
+mod supervisor {
+    use super::*;
+    use crate::control;
+    use crate::daemon::CancellationToken;
+    use crate::error::DdnsError;
+    use crate::lock::runtime_path;
+    use crate::mcp::McpServer;
+    use std::process::{Output, Stdio};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::net::UnixListener;
+    use tokio::process::Command;
+    use tokio::task::JoinHandle;
+
+    /// How long ssh must stay up to count as connected. With
+    /// `ExitOnForwardFailure` it exits as soon as the login or the remote
+    /// forward fails.
+    const CONNECT_GRACE: Duration = Duration::from_secs(5);
+
+    /// Delay before the first reconnection, doubled after each failure.
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+    /// Longest delay between reconnections.
+    const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+    /// A connection up for this long resets the delay.
+    const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+    /// Something the tunnel did or ran into, reported to its event handler.
+    #[derive(Debug, Clone)]
+    pub enum TunnelEvent {
+        /// The local socket at this path is forwarded to the remote host.
+        Forwarding(PathBuf),
+        /// ssh was started, for the `attempt`th time.
+        Connecting { attempt: u32 },
+        /// ssh is connected to this remote endpoint.
+        Connected(String),
+        /// ssh exited, and is started again after `retry_in`.
+        Disconnected { error: String, retry_in: Duration },
+        /// A problem the tunnel carries on despite.
+        Warning(String),
+        /// The tunnel stopped after being cancelled.
+        Stopped,
+    }
+
+    type EventHandler = Box<dyn Fn(&TunnelEvent) + Send + Sync>;
+
+    /// Keeps a reverse SSH tunnel open until cancelled, reconnecting with
+    /// exponential backoff when it drops.
+    pub struct Tunnel {
+        config: Config,
+        config_path: Option<PathBuf>,
+        cancel: CancellationToken,
+        ssh: PathBuf,
+        on_event: EventHandler,
+    }
+
+    impl Tunnel {
+        /// Create a tunnel with the `[tunnel]` settings of `config`, kept
+        /// open until `cancel` is cancelled.
+        pub fn new(config: Config, cancel: CancellationToken) -> Self {
+            Self {
+                config,
+                config_path: None,
+                cancel,
+                ssh: PathBuf::from("ssh"),
+                on_event: Box::new(|_| {}),
+            }
+        }
+
+        /// Set the config file edited by the exposed MCP server's add/remove
+        /// provider tools.
+        pub fn with_config_path(mut self, path: PathBuf) -> Self {
+            self.config_path = Some(path);
+            self
+        }
+
+        /// Run this program instead of `ssh` from `PATH`.
+        pub fn with_ssh_command(mut self, program: impl Into<PathBuf>) -> Self {
+            self.ssh = program.into();
+            self
+        }
+
+        /// Report events to `handler` (default: ignore them).
+        pub fn on_event(mut self, handler: impl Fn(&TunnelEvent) + Send + Sync + 'static) -> Self {
+            self.on_event = Box::new(handler);
+            self
+        }
+
+        /// Keep the tunnel open until cancelled.
+        ///
+        /// Fails without a `[tunnel]` section, if another tunnel of the
+        /// config is running, or if ssh can't be run at all.
+        pub async fn run(self) -> Result<()> {
+            let tunnel = self.config.tunnel.clone().ok_or_else(|| {
+                DdnsError::Config("No [tunnel] section in the config".to_string())
+            })?;
+            let state_dir = self.config.state_dir()?;
+            std::fs::create_dir_all(&state_dir)?;
+            let _pid_file = PidFile::acquire(&state_dir.join(PID_FILE))?;
+
+            let (local, _mcp) = match tunnel.expose {
+                TunnelTarget::Mcp => {
+                    let path = runtime_path("rusty-dns-mcp.sock", &state_dir);
+                    let mut server = McpServer::new(self.config.clone());
+                    if let Some(config_path) = &self.config_path {
+                        server = server.with_config_path(config_path.clone());
+                    }
+                    let listener = McpListener::bind(&path, server)?;
+                    (path, Some(listener))
+                }
+                TunnelTarget::Control => (control::socket_path(&self.config)?, None),
+            };
+            self.emit(TunnelEvent::Forwarding(local.clone()));
+
+            let args = ssh_args(&tunnel, &local);
+            let mut status = TunnelStatus {
+                pid: std::process::id(),
+                remote: tunnel.remote_endpoint(),
+                exposes: tunnel.expose,
+                connected: false,
+                connected_since: None,
+                last_reconnect: None,
+                reconnects: 0,
+                last_error: None,
+            };
+            let mut backoff = INITIAL_BACKOFF;
+
+            for attempt in 1.. {
+                if attempt > 1 {
+                    status.reconnects += 1;
+                    status.last_reconnect = Some(Utc::now());
+                }
+                self.save(&status, &state_dir);
+                self.emit(TunnelEvent::Connecting { attempt });
+
+                let started = Instant::now();
+                let child = Command::new(&self.ssh)
+                    .args(&args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| {
+                        DdnsError::Config(format!("Failed to run `{}`: {}", self.ssh.display(), e))
+                    })?;
+                let output = child.wait_with_output();
+                tokio::pin!(output);
+                let grace = tokio::time::sleep(CONNECT_GRACE);
+                tokio::pin!(grace);
+
+                // Dropping `output` kills ssh
+                let output = loop {
+                    tokio::select! {
+                        _ = self.cancel.cancelled() => break None,
+                        _ = &mut grace, if !status.connected => {
+                            status.connected = true;
+                            status.connected_since = Some(Utc::now());
+                            self.save(&status, &state_dir);
+                            self.emit(TunnelEvent::Connected(status.remote.clone()));
+                        }
+                        output = &mut output => break Some(output),
+                    }
+                };
+                let Some(output) = output else {
+                    break;
+                };
+
+                if started.elapsed() >= STABLE_AFTER {
+                    backoff = INITIAL_BACKOFF;
+                }
+                let retry_in = backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+
+                let error = match output {
+                    Ok(output) => exit_error(&output),
+                    Err(e) => e.to_string(),
+                };
+                status.connected = false;
+                status.connected_since = None;
+                status.last_error = Some(error.clone());
+                self.save(&status, &state_dir);
+                self.emit(TunnelEvent::Disconnected { error, retry_in });
+
+                tokio::select! {
+                    _ = self.cancel.cancelled() => break,
+                    _ = tokio::time::sleep(retry_in) => {}
+                }
+            }
+
+            let _ = std::fs::remove_file(state_dir.join(STATUS_FILE));
+            self.emit(TunnelEvent::Stopped);
+            Ok(())
+        }
+
+        fn save(&self, status: &TunnelStatus, state_dir: &Path) {
+            if let Err(e) = status.save(state_dir) {
+                self.emit(TunnelEvent::Warning(format!(
+                    "Failed to save tunnel status: {}",
+                    e
+                )));
+            }
+        }
+
+        fn emit(&self, event: TunnelEvent) {
+            (self.on_event)(&event);
+        }
+    }
+
+    /// Describe why ssh exited: its last message, or its exit status.
+    pub(super) fn exit_error(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .map_or_else(|| format!("ssh {}", output.status), str::to_string)
+    }
+
+    /// MCP server listening on a Unix socket, one session per connection.
+    /// The socket file is removed when dropped.
+    struct McpListener {
+        path: PathBuf,
+        task: JoinHandle<()>,
+    }
+
+    impl McpListener {
+        /// Bind the socket and start accepting connections.
+        ///
+        /// The caller must hold the tunnel's PID file, so any existing
+        /// socket file is stale and gets replaced.
+        fn bind(path: &Path, server: McpServer) -> Result<Self> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+
+            let listener = UnixListener::bind(path)?;
+
+            // Only the tunnel's user (and ssh running as that user) may connect
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+            let server = Arc::new(server);
+            let task = tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            let server = server.clone();
+                            tokio::spawn(async move {
+                                let (input, output) = stream.into_split();
+                                if let Err(e) = server.run(input, output).await {
+                                    tracing::debug!("MCP connection failed: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            tracing::warn!("MCP socket error: {}", e);
+                            return;
+                        }
+                    }
+                }
+            });
+
+            Ok(Self {
+                path: path.to_path_buf(),
+                task,
+            })
+        }
+    }
+
+    impl Drop for McpListener {
+        fn drop(&mut self) {
+            self.task.abort();
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel() -> TunnelConfig {
+        TunnelConfig {
+            host: "vps.example.com".to_string(),
+            port: 22,
+            user: None,
+            identity_file: None,
+            remote_bind: "7000".to_string(),
+            expose: TunnelTarget::Mcp,
+        }
+    }
+
+    #[test]
+    fn test_ssh_args() {
+        let local = Path::new("/run/user/1000/rusty-dns-mcp.sock");
+        let args = ssh_args(&tunnel(), local).join(" ");
+        assert!(args.starts_with("-N -T -o BatchMode=yes -o ExitOnForwardFailure=yes"));
+        assert!(
+            args.ends_with("-p 22 -R 7000:/run/user/1000/rusty-dns-mcp.sock -- vps.example.com")
+        );
+        assert!(!args.contains("-i "));
+
+        let config = TunnelConfig {
+            port: 2222,
+            user: Some("me".to_string()),
+            identity_file: Some(PathBuf::from("/home/me/.ssh/tunnel")),
+            remote_bind: "/tmp/rusty-dns.sock".to_string(),
+            ..tunnel()
+        };
+        let args = ssh_args(&config, local).join(" ");
+        assert!(args.ends_with(
+            "-p 2222 -i /home/me/.ssh/tunnel -o IdentitiesOnly=yes \
+             -R /tmp/rusty-dns.sock:/run/user/1000/rusty-dns-mcp.sock -- me@vps.example.com"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_error() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let output = |stderr: &str| std::process::Output {
+            status: std::process::ExitStatus::from_raw(255 << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        assert_eq!(
+            supervisor::exit_error(&output(
+                "Warning: Permanently added 'vps' to the list of known hosts.\r\n\
+                 me@vps: Permission denied (publickey).\r\n\n"
+            )),
+            "me@vps: Permission denied (publickey)."
+        );
+        assert_eq!(supervisor::exit_error(&output("")), "ssh exit status: 255");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reconnect_status() {
+        use crate::daemon::CancellationToken;
+        use std::sync::{Arc, Mutex};
+
+        let dir = std::env::temp_dir().join(format!("rusty-dns-tunnel-{}", std::process::id()));
+        let config = Config {
+            state_dir: Some(dir.clone()),
+            tunnel: Some(TunnelConfig {
+                expose: TunnelTarget::Control,
+                ..tunnel()
+            }),
+            ..Default::default()
+        };
+        assert!(TunnelStatus::load(&config).is_none());
+
+        // An ssh that fails right away is started again after a delay
+        let cancel = CancellationToken::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        Tunnel::new(config.clone(), cancel.clone())
+            .with_ssh_command("false")
+            .on_event({
+                let (events, statuses, config) = (events.clone(), statuses.clone(), config.clone());
+                move |event| {
+                    events.lock().unwrap().push(format!("{:?}", event));
+                    if let TunnelEvent::Disconnected { .. } = event {
+                        statuses.lock().unwrap().push(TunnelStatus::load(&config));
+                        cancel.cancel();
+                    }
+                }
+            })
+            .run()
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert!(events[0].starts_with("Forwarding("), "{:?}", events);
+        assert_eq!(events[1], "Connecting { attempt: 1 }");
+        assert!(events[2].contains("exit status: 1"), "{:?}", events);
+        assert!(events[2].contains("retry_in: 1s"), "{:?}", events);
+        assert_eq!(events[3], "Stopped");
+
+        let status = statuses.lock().unwrap()[0].clone().unwrap();
+        assert_eq!(status.pid, std::process::id());
+        assert_eq!(status.remote, "vps.example.com:7000");
+        assert!(!status.connected);
+        assert_eq!(status.reconnects, 0);
+        assert_eq!(status.last_error.as_deref(), Some("ssh exit status: 1"));
+
+        // Nothing is reported once the tunnel stopped
+        assert!(TunnelStatus::load(&config).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1978 @@
+//! Update logic shared by the CLI, daemon, and MCP server.
+
+use crate::config::{Config, IpVersion, ProviderConfig};
+use crate::detector::{DetectionReport, IpDetector, Ipv6Prefix};
+use crate::error::{DdnsError, Result};
+use crate::history::{self, HistoryEntry, IpChange, IpSource, UpdateReason};
+use crate::notify::{Event, EventKind, GroupOutcome, Notifications, ProviderOutcome};
+use crate::providers::{create_provider, DdnsProvider, RecordType, UpdateErrorKind, UpdateResult};
+use crate::state::{ProviderState, State};
+use crate::summary::{self, Summary};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Public addresses detected for each family.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetectedIps {
+    /// Public IPv4 address, if detected.
+    pub ipv4: Option<IpAddr>,
+    /// Public IPv6 address, if detected.
+    pub ipv6: Option<IpAddr>,
+    /// Detection service that answered for IPv4.
+    pub ipv4_service: Option<String>,
+    /// Detection service that answered for IPv6.
+    pub ipv6_service: Option<String>,
+    /// IPv6 prefix delegated to the network, if the IPv6 service reports it.
+    pub ipv6_prefix: Option<Ipv6Prefix>,
+}
+
+impl DetectedIps {
+    /// Get the detected addresses a provider configured for `version` should publish.
+    pub fn for_version(&self, version: IpVersion) -> Vec<IpAddr> {
+        let mut ips = Vec::new();
+        if version.includes_v4() {
+            ips.extend(self.ipv4);
+        }
+        if version.includes_v6() {
+            ips.extend(self.ipv6);
+        }
+        ips
+    }
+
+    /// Get the network's IPv6 prefix: the delegated one if the service
+    /// reported it, otherwise the detected address cut to `length` bits.
+    pub fn prefix(&self, length: u8) -> Option<Ipv6Prefix> {
+        self.ipv6_prefix.or(match self.ipv6 {
+            Some(IpAddr::V6(ip)) => Some(Ipv6Prefix::of(ip, length)),
+            _ => None,
+        })
+    }
+
+    /// Get the addresses of an entry with the `ipv6_suffix` `suffix`: its
+    /// IPv6 address is the prefix (see [`DetectedIps::prefix`]) followed by
+    /// the suffix, and there's none without a prefix.
+    pub fn with_suffix(&self, suffix: Option<Ipv6Addr>, prefix_length: u8) -> Self {
+        let Some(suffix) = suffix else {
+            return self.clone();
+        };
+        Self {
+            ipv6: self
+                .prefix(prefix_length)
+                .map(|prefix| IpAddr::V6(prefix.with_suffix(suffix))),
+            ..self.clone()
+        }
+    }
+
+    /// Get the detection service that answered for the family of `ip`.
+    pub fn service(&self, ip: &IpAddr) -> Option<&str> {
+        if ip.is_ipv4() {
+            self.ipv4_service.as_deref()
+        } else {
+            self.ipv6_service.as_deref()
+        }
+    }
+}
+
+impl From<DetectionReport> for DetectedIps {
+    fn from(report: DetectionReport) -> Self {
+        Self {
+            ipv4: report.ipv4,
+            ipv6: report.ipv6,
+            ipv4_service: report.source(false).map(|d| d.service.clone()),
+            ipv6_service: report.source(true).map(|d| d.service.clone()),
+            ipv6_prefix: report.ipv6_prefix,
+        }
+    }
+}
+
+/// Outcome of updating one record.
+#[derive(Debug, Clone)]
+pub enum UpdateOutcome {
+    /// The provider was called; the result tells whether it succeeded.
+    Attempted(UpdateResult),
+    /// Skipped because the record already holds the address.
+    Unchanged,
+    /// The provider can't manage this record type.
+    Unsupported,
+    /// Not sent, for the reason in [`ProviderUpdate::reason`].
+    Skipped,
+}
+
+/// Update of one record of a configured provider.
+#[derive(Debug, Clone)]
+pub struct ProviderUpdate {
+    /// Label of the configured provider entry (its key in persistent state).
+    pub label: String,
+    /// Provider type.
+    pub provider: &'static str,
+    /// Domain being managed.
+    pub domain: String,
+    /// Address that was (or would have been) published.
+    pub ip: IpAddr,
+    /// Why the update was sent, or skipped.
+    pub reason: UpdateReason,
+    /// What happened.
+    pub outcome: UpdateOutcome,
+    /// Whether the record was read and found to hold `ip`.
+    pub verified: bool,
+}
+
+impl ProviderUpdate {
+    /// Get the record type of this update.
+    pub fn record_type(&self) -> RecordType {
+        RecordType::for_ip(&self.ip)
+    }
+
+    /// Get the error message if the update was attempted and failed.
+    pub fn error(&self) -> Option<&str> {
+        match &self.outcome {
+            UpdateOutcome::Attempted(result) if !result.success => {
+                Some(result.error.as_deref().unwrap_or("Unknown error"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Get why the update failed, if it was attempted and failed.
+    pub fn error_kind(&self) -> Option<UpdateErrorKind> {
+        match &self.outcome {
+            UpdateOutcome::Attempted(result) if !result.success => {
+                Some(result.error_kind.unwrap_or(UpdateErrorKind::Other))
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the notification outcome if the update was attempted.
+    pub fn provider_outcome(&self) -> Option<ProviderOutcome> {
+        match &self.outcome {
+            UpdateOutcome::Attempted(result) => Some(ProviderOutcome {
+                provider: self.provider.to_string(),
+                domain: self.domain.clone(),
+                success: result.success,
+                error: result.error.clone(),
+                reason: self.reason,
+                group: None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the history entry of this update, unless it was unsupported.
+    ///
+    /// Skipped updates are recorded as successful results carrying the
+    /// address that wasn't sent, so the history tells when a record was
+    /// last confirmed.
+    fn history_entry(&self, source: IpSource) -> Option<HistoryEntry> {
+        let result = match &self.outcome {
+            UpdateOutcome::Attempted(result) => result.clone(),
+            UpdateOutcome::Unchanged | UpdateOutcome::Skipped => UpdateResult {
+                provider: self.provider.to_string(),
+                label: Some(self.label.clone()),
+                domain: self.domain.clone(),
+                record_type: self.record_type(),
+                success: true,
+                ip: Some(self.ip),
+                previous_ip: None,
+                error: None,
+                error_kind: None,
+                timestamp: chrono::Utc::now(),
+            },
+            UpdateOutcome::Unsupported => return None,
+        };
+        Some(HistoryEntry {
+            result,
+            reason: self.reason,
+            source,
+        })
+    }
+}
+
+/// Result of recording a cycle's updates in persistent state.
+#[derive(Debug, Clone, Default)]
+pub struct RecordedOutcomes {
+    /// State after recording.
+    pub state: State,
+    /// Providers whose failure streak just reached the escalation threshold.
+    pub escalated: Vec<String>,
+    /// Providers that succeeded after a failure streak.
+    pub recovered: Vec<String>,
+    /// Notification events raised by this cycle.
+    pub events: Vec<Event>,
+    /// Changes of the detected addresses.
+    pub ip_changes: Vec<IpChange>,
+}
+
+/// When a record is sent the address it should hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdatePolicy {
+    /// Send addresses that differ from the last one published.
+    OnlyIfChanged,
+    /// Send every address, whatever the record holds.
+    Force,
+    /// Like [`UpdatePolicy::OnlyIfChanged`], and also resend an unchanged
+    /// address once this long has passed since the last successful update,
+    /// so free hostnames don't expire (`daemon.refresh_interval_hours`).
+    RefreshIfOlderThan(chrono::Duration),
+    /// Read the record from the provider and send the address if it holds
+    /// another one, setting back records changed outside rusty-dns.
+    DriftCorrect,
+}
+
+impl UpdatePolicy {
+    /// Whether the record is read from the provider before deciding.
+    pub fn reads_record(&self) -> bool {
+        matches!(self, Self::DriftCorrect)
+    }
+}
+
+/// Whether to send an address to a record, and why (see [`should_update`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Send it.
+    Update(UpdateReason),
+    /// Don't send it.
+    Skip(UpdateReason),
+}
+
+impl Decision {
+    /// Get the reason of the decision.
+    pub fn reason(&self) -> UpdateReason {
+        match self {
+            Self::Update(reason) | Self::Skip(reason) => *reason,
+        }
+    }
+}
+
+/// What is known about a record besides its provider's state, for
+/// [`should_update`].
+#[derive(Debug, Clone, Copy)]
+pub struct RecordCheck {
+    /// Address the record holds, if read from the provider (see
+    /// [`UpdatePolicy::reads_record`]).
+    pub record: Option<IpAddr>,
+    /// Whether the address is given by the user and was published to the
+    /// record before, making the update a rollback.
+    pub published_before: bool,
+    /// Rate guard: the entry's `min_update_interval_secs`, unless bypassed.
+    pub min_interval: Option<chrono::Duration>,
+    /// Time of the decision.
+    pub now: chrono::DateTime<chrono::Utc>,
+}
+
+/// Decide whether to send `ip` to a record under `policy`.
+///
+/// Providers that asked not to be sent updates for a while are left alone
+/// unless forced, and so are records updated to the same address less than
+/// the rate guard's interval ago, even when forced. A record read from the
+/// provider that holds another address last published by rusty-dns is a
+/// [drift](UpdateReason::Drift) correction.
+pub fn should_update(
+    state: &ProviderState,
+    ip: IpAddr,
+    policy: UpdatePolicy,
+    check: &RecordCheck,
+) -> Decision {
+    if policy != UpdatePolicy::Force && state.retry_pending(check.now) {
+        return Decision::Skip(UpdateReason::NotEligibleInterval);
+    }
+
+    let published = state.published(&ip) == Some(ip);
+    let changed = if check.published_before {
+        UpdateReason::Rollback
+    } else {
+        UpdateReason::Changed
+    };
+    let reason = match policy {
+        UpdatePolicy::Force => UpdateReason::Forced,
+        UpdatePolicy::OnlyIfChanged if published => {
+            return Decision::Skip(UpdateReason::Unchanged);
+        }
+        UpdatePolicy::RefreshIfOlderThan(interval) if published => {
+            if !state.refresh_due(Some(interval), check.now) {
+                return Decision::Skip(UpdateReason::Unchanged);
+            }
+            UpdateReason::Refresh
+        }
+        UpdatePolicy::OnlyIfChanged | UpdatePolicy::RefreshIfOlderThan(_) => changed,
+        UpdatePolicy::DriftCorrect => match check.record {
+            Some(record) if record == ip => return Decision::Skip(UpdateReason::Unchanged),
+            Some(_) if published => UpdateReason::Drift,
+            _ => changed,
+        },
+    };
+
+    if state.updated_within(&ip, check.min_interval, check.now) {
+        return Decision::Skip(UpdateReason::RateGuard);
+    }
+    Decision::Update(reason)
+}
+
+/// Spacing of the provider updates of a cycle (see `daemon.stagger_secs`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stagger {
+    /// Time between the starts of two providers' updates.
+    pub spacing: Duration,
+    /// Time within which the last provider's update must start.
+    pub within: Duration,
+}
+
+impl Stagger {
+    /// Get the time between the starts of `count` providers' updates:
+    /// `spacing`, shortened if needed for the last one to start within
+    /// `within`.
+    pub fn spacing_for(&self, count: usize) -> Duration {
+        match count {
+            0 | 1 => self.spacing,
+            count => self.spacing.min(self.within / (count as u32 - 1)),
+        }
+    }
+}
+
+/// Start times of staggered provider updates.
+struct Slots {
+    spacing: Duration,
+    /// Earliest start of the next provider's update.
+    next: Option<Instant>,
+    /// Label of the provider being updated.
+    current: Option<String>,
+}
+
+impl Slots {
+    fn new(spacing: Duration) -> Self {
+        Self {
+            spacing,
+            next: None,
+            current: None,
+        }
+    }
+
+    /// Wait for the turn of the provider labeled `label`; its other
+    /// records go right after the first.
+    async fn wait(&mut self, label: &str) {
+        if self.current.as_deref() == Some(label) {
+            return;
+        }
+        if let Some(next) = self.next {
+            tokio::time::sleep_until(next).await;
+        }
+        self.next = Some(Instant::now() + self.spacing);
+        self.current = Some(label.to_string());
+    }
+}
+
+/// Shuffle `items` into an unpredictable order.
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        items.swap(i, (random % (i as u64 + 1)) as usize);
+    }
+}
+
+/// Detects public addresses and pushes them to the configured providers.
+pub struct Updater<'a> {
+    config: &'a Config,
+    detector: &'a IpDetector,
+    notifications: Option<&'a Notifications>,
+    progress: Option<&'a (dyn Fn(&ProviderUpdate) + Sync)>,
+    source: IpSource,
+    consensus: bool,
+    bypass_guard: bool,
+    stagger: Option<Stagger>,
+}
+
+impl<'a> Updater<'a> {
+    /// Create a new updater.
+    pub fn new(config: &'a Config, detector: &'a IpDetector) -> Self {
+        Self {
+            config,
+            detector,
+            notifications: None,
+            progress: None,
+            source: IpSource::Detected,
+            consensus: false,
+            bypass_guard: false,
+            stagger: None,
+        }
+    }
+
+    /// Send notifications for events raised while recording.
+    pub fn with_notifications(mut self, notifications: &'a Notifications) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    /// Set where the addresses passed to [`Updater::record`] come from.
+    ///
+    /// Manual addresses are flagged in the history and, as they need not be
+    /// this host's, aren't recorded as the last detected address.
+    pub fn with_source(mut self, source: IpSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Detect addresses by querying all services concurrently and requiring
+    /// them to agree (see [`IpDetector::detect_consensus`]), instead of
+    /// trying them in turn.
+    pub fn with_consensus(mut self, consensus: bool) -> Self {
+        self.consensus = consensus;
+        self
+    }
+
+    /// Send updates even to records updated to the same address less than
+    /// `min_update_interval_secs` ago.
+    pub fn with_guard_bypass(mut self, bypass: bool) -> Self {
+        self.bypass_guard = bypass;
+        self
+    }
+
+    /// Report each update to `handler` as soon as it is done, instead of
+    /// only returning them all at the end.
+    pub fn with_progress(mut self, handler: &'a (dyn Fn(&ProviderUpdate) + Sync)) -> Self {
+        self.progress = Some(handler);
+        self
+    }
+
+    /// Space out the providers' updates; [`Updater::update_changed`] also
+    /// takes them in random order.
+    pub fn with_stagger(mut self, stagger: Stagger) -> Self {
+        self.stagger = Some(stagger);
+        self
+    }
+
+    /// Get the notification outcomes of the attempted `updates`, tagged
+    /// with their providers' groups, and the outcomes of those groups.
+    ///
+    /// A group fails when a member whose failures count (see
+    /// [`Config::counts_failures`]) does.
+    pub fn outcomes<'u>(
+        &self,
+        updates: impl IntoIterator<Item = &'u ProviderUpdate>,
+    ) -> (Vec<ProviderOutcome>, Vec<GroupOutcome>) {
+        let mut outcomes = Vec::new();
+        let mut groups: Vec<GroupOutcome> = Vec::new();
+        for update in updates {
+            let Some(mut outcome) = update.provider_outcome() else {
+                continue;
+            };
+            if let Some(name) = self.config.group_of(&update.label) {
+                let index = match groups.iter().position(|g| g.name == name) {
+                    Some(index) => index,
+                    None => {
+                        groups.push(GroupOutcome {
+                            name: name.to_string(),
+                            policy: self.config.group_policy(name),
+                            success: true,
+                        });
+                        groups.len() - 1
+                    }
+                };
+                if !outcome.success && self.config.counts_failures(&update.label) {
+                    groups[index].success = false;
+                }
+                outcome.group = Some(name.to_string());
+            }
+            outcomes.push(outcome);
+        }
+        (outcomes, groups)
+    }
+
+    /// Add `update` to `updates`, reporting it right away.
+    fn push(&self, updates: &mut Vec<ProviderUpdate>, update: ProviderUpdate) {
+        if let Some(progress) = self.progress {
+            progress(&update);
+        }
+        updates.push(update);
+    }
+
+    /// Get the address families needed by the configured providers.
+    pub fn ip_version(&self) -> IpVersion {
+        self.config
+            .enabled_providers()
+            .map(|p| p.ip_version(self.config.ip_version))
+            .reduce(IpVersion::union)
+            .unwrap_or(self.config.ip_version)
+    }
+
+    /// Detect the public addresses for all needed families concurrently.
+    ///
+    /// Fails only if no needed family could be detected.
+    pub async fn detect(&self) -> Result<DetectedIps> {
+        let report = self
+            .detector
+            .detect_version(self.ip_version(), self.consensus)
+            .await;
+
+        if !report.is_detected() {
+            return Err(DdnsError::IpDetection(if report.errors.is_empty() {
+                "No address family to detect".to_string()
+            } else {
+                report.errors.join("; ")
+            }));
+        }
+
+        for e in &report.errors {
+            tracing::warn!("{}", e);
+        }
+
+        Ok(DetectedIps::from(report))
+    }
+
+    /// Update every provider whose records `policy` says to (see
+    /// [`should_update`]), reporting skipped records too.
+    ///
+    /// Disabled providers are reported as skipped, as are records updated
+    /// to the same address less than `min_update_interval_secs` ago, even
+    /// when forced (see [`Updater::with_guard_bypass`]). A manual address
+    /// published earlier is a [rollback](UpdateReason::Rollback).
+    pub async fn update_all(&self, ips: &DetectedIps, policy: UpdatePolicy) -> Vec<ProviderUpdate> {
+        let store = history::open(self.config).ok();
+        let state = store
+            .as_ref()
+            .and_then(|store| store.load_state().ok())
+            .unwrap_or_default();
+        let history = match (&store, self.source) {
+            (Some(store), IpSource::Manual) => store.entries().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        let providers = self.config.providers.iter().collect();
+        self.update(providers, ips, &state, &history, policy, false)
+            .await
+    }
+
+    /// Update the enabled providers whose records `policy` says to (see
+    /// [`should_update`]), given the persistent `state`.
+    ///
+    /// Only updates that were sent, or held back by the rate guard or a
+    /// provider's rate limiting, are reported: records already up to date
+    /// and families a provider doesn't support are left out.
+    ///
+    /// With [`Updater::with_stagger`], providers are taken in random order
+    /// and the updates of two of them start at least the spacing apart.
+    pub async fn update_changed(
+        &self,
+        ips: &DetectedIps,
+        state: &State,
+        policy: UpdatePolicy,
+    ) -> Vec<ProviderUpdate> {
+        let mut providers: Vec<_> = self.config.enabled_providers().collect();
+        if self.stagger.is_some() {
+            shuffle(&mut providers);
+        }
+        self.update(providers, ips, state, &[], policy, true).await
+    }
+
+    /// Update the records of `providers` under `policy`; `quiet` leaves out
+    /// records that are up to date or unsupported.
+    async fn update(
+        &self,
+        providers: Vec<&ProviderConfig>,
+        ips: &DetectedIps,
+        state: &State,
+        history: &[HistoryEntry],
+        policy: UpdatePolicy,
+        quiet: bool,
+    ) -> Vec<ProviderUpdate> {
+        let mut updates = Vec::new();
+        let now = chrono::Utc::now();
+        // Spaced for all, so it holds however many are up to date
+        let mut slots = self
+            .stagger
+            .map(|stagger| Slots::new(stagger.spacing_for(providers.len())));
+
+        for provider_config in providers {
+            let label = provider_config.label();
+            let provider = create_provider(
+                provider_config,
+                self.config.default_ttl,
+                &self.config.network,
+            );
+            let version = provider_config.ip_version(self.config.ip_version);
+            let ips = ips.with_suffix(provider_config.ipv6_suffix, self.config.ipv6_prefix_length);
+            let capabilities = provider.capabilities();
+            let provider_state = state.providers.get(&label).cloned().unwrap_or_default();
+            let mut prepared = None;
+            let min_interval = if self.bypass_guard {
+                None
+            } else {
+                provider_config.min_update_interval(self.config.min_update_interval_secs)
+            };
+
+            for ip in ips.for_version(version) {
+                let mut check = RecordCheck {
+                    record: None,
+                    published_before: self.source == IpSource::Manual
+                        && published_before(history, &label, ip),
+                    min_interval,
+                    now,
+                };
+                if provider_config.enabled && ip.is_ipv6() && !capabilities.supports_ipv6 {
+                    tracing::debug!("{} does not support AAAA records, skipping", label);
+                    if !quiet {
+                        self.push(
+                            &mut updates,
+                            ProviderUpdate {
+                                label: label.clone(),
+                                provider: provider.name(),
+                                domain: provider.domain(),
+                                ip,
+                                reason: UpdateReason::Changed,
+                                outcome: UpdateOutcome::Unsupported,
+                                verified: false,
+                            },
+                        );
+                    }
+                    continue;
+                }
+
+                let decision = if !provider_config.enabled {
+                    Decision::Skip(UpdateReason::Disabled)
+                } else {
+                    match should_update(&provider_state, ip, policy, &check) {
+                        // Only read when that can change the decision; providers
+                        // that can't read the record would only answer `None`
+                        Decision::Update(_)
+                            if policy.reads_record() && capabilities.can_read_record =>
+                        {
+                            if prepare(provider.as_ref(), &label, &mut prepared)
+                                .await
+                                .is_ok()
+                            {
+                                check.record = provider
+                                    .get_record_ip(RecordType::for_ip(&ip))
+                                    .await
+                                    .ok()
+                                    .flatten();
+                            }
+                            should_update(&provider_state, ip, policy, &check)
+                        }
+                        decision => decision,
+                    }
+                };
+
+                let outcome = match decision {
+                    Decision::Skip(UpdateReason::Unchanged) if quiet => continue,
+                    Decision::Skip(UpdateReason::Unchanged) => UpdateOutcome::Unchanged,
+                    Decision::Skip(reason) => {
+                        if reason == UpdateReason::NotEligibleInterval {
+                            tracing::info!(
+                                "{} is rate limited, not retrying before {}",
+                                label,
+                                provider_state.retry_at.unwrap_or(now).to_rfc3339()
+                            );
+                        }
+                        UpdateOutcome::Skipped
+                    }
+                    Decision::Update(_) => {
+                        if let Some(slots) = &mut slots {
+                            slots.wait(&label).await;
+                        }
+                        let result = match prepare(provider.as_ref(), &label, &mut prepared).await {
+                            Ok(()) => attempt(provider.as_ref(), &label, ip).await,
+                            Err((kind, message)) => {
+                                failed(provider.as_ref(), &label, ip, kind, message)
+                            }
+                        };
+                        UpdateOutcome::Attempted(result)
+                    }
+                };
+                self.push(
+                    &mut updates,
+                    ProviderUpdate {
+                        label: label.clone(),
+                        provider: provider.name(),
+                        domain: provider.domain(),
+                        ip,
+                        reason: decision.reason(),
+                        outcome,
+                        verified: check.record == Some(ip),
+                    },
+                );
+            }
+        }
+
+        updates
+    }
+
+    /// Record detected addresses and update outcomes in persistent state,
+    /// append attempted and skipped updates to the history and address
+    /// changes to the IP change log, write `daemon.summary_file`, and send
+    /// notifications.
+    ///
+    /// Failure streaks reaching `daemon.max_consecutive_failures`, or
+    /// starting with a permanent error (see [`UpdateErrorKind::is_permanent`]),
+    /// are escalated once with an error event and an `update_failed`
+    /// notification, except for the fallbacks of a `primary-failover` group.
+    pub async fn record(
+        &self,
+        ips: &DetectedIps,
+        updates: &[ProviderUpdate],
+    ) -> Result<RecordedOutcomes> {
+        let store = history::open(self.config)?;
+        let threshold = self.config.daemon.max_consecutive_failures;
+
+        let recorded = store.update_state(|state| {
+            let mut recorded = RecordedOutcomes::default();
+
+            let detected = match self.source {
+                IpSource::Detected => [ips.ipv4, ips.ipv6],
+                IpSource::Manual => [None, None],
+            };
+            for ip in detected.into_iter().flatten() {
+                let old_ip = state.last_ip(&ip);
+                if old_ip.is_some() && old_ip != Some(ip) {
+                    // Including records composed from the prefix
+                    let (providers, groups) =
+                        self.outcomes(updates.iter().filter(|u| u.ip.is_ipv6() == ip.is_ipv6()));
+                    recorded
+                        .events
+                        .push(Event::ip_changed(old_ip, ip, providers).with_groups(groups));
+                    recorded.ip_changes.push(IpChange {
+                        timestamp: chrono::Utc::now(),
+                        record_type: RecordType::for_ip(&ip),
+                        old_ip,
+                        new_ip: ip,
+                        service: ips.service(&ip).map(str::to_string),
+                    });
+                }
+                state.set_last_ip(ip);
+            }
+            if self.source == IpSource::Detected && ips.ipv6.is_some() {
+                state.last_ipv6_prefix = ips.prefix(self.config.ipv6_prefix_length);
+            }
+
+            let mut keys: Vec<&str> = updates
+                .iter()
+                .filter(|u| !matches!(u.outcome, UpdateOutcome::Skipped))
+                .map(|u| u.label.as_str())
+                .collect();
+            keys.dedup();
+
+            for key in keys {
+                let provider_updates: Vec<_> = updates.iter().filter(|u| u.label == key).collect();
+                let provider_state = state.provider_mut(key);
+                let was_alerted = provider_state.alerted;
+                let mut errors = Vec::new();
+                let mut failed = None;
+                let mut succeeded = None;
+
+                for update in &provider_updates {
+                    if update.verified {
+                        provider_state.last_verified_at = Some(chrono::Utc::now());
+                    }
+                    let published = provider_state.published(&update.ip);
+                    match &update.outcome {
+                        UpdateOutcome::Attempted(result) if !result.success => {
+                            errors.push(format!(
+                                "{}: {}",
+                                update.record_type(),
+                                update.error().unwrap_or_default()
+                            ));
+                            failed.get_or_insert((*update, published));
+                        }
+                        UpdateOutcome::Attempted(result) => {
+                            provider_state.set_published(update.ip);
+                            provider_state.last_refresh = Some(result.timestamp);
+                            succeeded.get_or_insert((*update, published));
+                        }
+                        UpdateOutcome::Unchanged => {
+                            provider_state.set_published(update.ip);
+                            succeeded.get_or_insert((*update, published));
+                        }
+                        UpdateOutcome::Unsupported | UpdateOutcome::Skipped => {}
+                    }
+                }
+
+                if let Some((update, published)) = failed {
+                    let error = errors.join("; ");
+                    // A threshold of 0 never escalates
+                    let threshold = if self.config.counts_failures(key) {
+                        threshold
+                    } else {
+                        0
+                    };
+                    if provider_state.record_failure(error.clone(), update.error_kind(), threshold)
+                    {
+                        tracing::error!(
+                            provider = key,
+                            consecutive_failures = provider_state.consecutive_failures,
+                            "Provider has failed {} consecutive updates: {}",
+                            provider_state.consecutive_failures,
+                            error
+                        );
+                        recorded.escalated.push(key.to_string());
+                        recorded.events.push(Event::provider(
+                            EventKind::UpdateFailed,
+                            update.provider,
+                            &update.domain,
+                            published,
+                            update.ip,
+                            Some(error),
+                        ));
+                    }
+                } else if let Some((update, published)) = succeeded {
+                    if provider_state.record_success() {
+                        recorded.recovered.push(key.to_string());
+                        if was_alerted {
+                            recorded.events.push(Event::provider(
+                                EventKind::UpdateRecovered,
+                                update.provider,
+                                &update.domain,
+                                published,
+                                update.ip,
+                                None,
+                            ));
+                        }
+                    }
+                }
+            }
+
+            recorded.state = state.clone();
+            recorded
+        })?;
+
+        if self.config.history.enabled {
+            let entries: Vec<_> = updates
+                .iter()
+                .filter_map(|update| update.history_entry(self.source))
+                .collect();
+            store.append(&entries)?;
+            store.append_ip_changes(&recorded.ip_changes)?;
+        }
+
+        if let Some(path) = &self.config.daemon.summary_file {
+            // Manual addresses aren't this host's, so the summary keeps the
+            // last detected ones
+            let current = match self.source {
+                IpSource::Detected => ips.clone(),
+                IpSource::Manual => DetectedIps {
+                    ipv4: recorded.state.last_ipv4,
+                    ipv6: recorded.state.last_ipv6,
+                    ..Default::default()
+                },
+            };
+            let summary = Summary::new(self.config, &current, &recorded.state);
+            if let Err(e) = summary::write(path, &summary) {
+                tracing::warn!("Failed to write summary to {}: {}", path.display(), e);
+            }
+        }
+
+        if let Some(notifications) = self.notifications {
+            notifications.send(&recorded.events).await;
+        }
+
+        Ok(recorded)
+    }
+}
+
+/// Whether the history shows `ip` successfully published to the provider
+/// labeled `label`.
+fn published_before(history: &[HistoryEntry], label: &str, ip: IpAddr) -> bool {
+    history.iter().any(|entry| {
+        !entry.is_skip()
+            && entry.result.success
+            && entry.result.ip == Some(ip)
+            && entry.result.label.as_deref() == Some(label)
+    })
+}
+
+/// Prepare a provider for the cycle (see [`DdnsProvider::prepare`]) the
+/// first time `prepared` is asked for, keeping how it went there.
+async fn prepare(
+    provider: &dyn DdnsProvider,
+    label: &str,
+    prepared: &mut Option<std::result::Result<(), (UpdateErrorKind, String)>>,
+) -> std::result::Result<(), (UpdateErrorKind, String)> {
+    if prepared.is_none() {
+        *prepared = Some(provider.prepare().await.map_err(|e| {
+            tracing::warn!("Failed to prepare {}: {}", label, e);
+            (UpdateErrorKind::for_error(&e), e.to_string())
+        }));
+    }
+    prepared.clone().unwrap_or(Ok(()))
+}
+
+/// Call a provider, turning errors into a failed result, and tag the result
+/// with the entry label.
+async fn attempt(provider: &dyn DdnsProvider, label: &str, ip: IpAddr) -> UpdateResult {
+    match provider.update_ip(ip).await {
+        Ok(mut result) => {
+            result.label = Some(label.to_string());
+            result
+        }
+        Err(e) => failed(
+            provider,
+            label,
+            ip,
+            UpdateErrorKind::for_error(&e),
+            e.to_string(),
+        ),
+    }
+}
+
+/// Get the result of an update of `ip` that failed before reaching the
+/// provider's answer.
+fn failed(
+    provider: &dyn DdnsProvider,
+    label: &str,
+    ip: IpAddr,
+    kind: UpdateErrorKind,
+    message: String,
+) -> UpdateResult {
+    UpdateResult {
+        provider: provider.name().to_string(),
+        label: Some(label.to_string()),
+        domain: provider.domain(),
+        record_type: RecordType::for_ip(&ip),
+        success: false,
+        ip: None,
+        previous_ip: None,
+        error: Some(message),
+        error_kind: Some(kind),
+        timestamp: chrono::Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderKind;
+    use crate::testing::{MockCall, MockProvider, MockUpdate};
+
+    fn duckdns(ip_version: Option<IpVersion>) -> ProviderConfig {
+        ProviderConfig {
+            ip_version,
+            ..ProviderConfig::new(ProviderKind::DuckDns {
+                domains: "home".to_string(),
+                token: "token".into(),
+                token_file: None,
+            })
+        }
+    }
+
+    #[test]
+    fn test_for_version() {
+        let ips = DetectedIps {
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: None,
+            ..Default::default()
+        };
+        assert_eq!(ips.for_version(IpVersion::Both).len(), 1);
+        assert!(ips.for_version(IpVersion::V6).is_empty());
+    }
+
+    #[test]
+    fn test_should_update() {
+        use Decision::{Skip, Update};
+        use UpdatePolicy::*;
+
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = chrono::Utc::now();
+        let day = chrono::Duration::days(1);
+        let check = RecordCheck {
+            record: None,
+            published_before: false,
+            min_interval: Some(chrono::Duration::seconds(60)),
+            now,
+        };
+        let published = |at: chrono::Duration| ProviderState {
+            last_ipv4: Some(ip),
+            last_refresh: Some(now - at),
+            ..Default::default()
+        };
+        let policies = [OnlyIfChanged, Force, RefreshIfOlderThan(day), DriftCorrect];
+        let decide = |state: &ProviderState, check: &RecordCheck| {
+            policies.map(|policy| should_update(state, ip, policy, check))
+        };
+
+        // Never published, or published in another address
+        let changed = [
+            Update(UpdateReason::Changed),
+            Update(UpdateReason::Forced),
+            Update(UpdateReason::Changed),
+            Update(UpdateReason::Changed),
+        ];
+        assert_eq!(decide(&ProviderState::default(), &check), changed);
+        let moved = ProviderState {
+            last_ipv4: Some(other),
+            last_refresh: Some(now),
+            ..Default::default()
+        };
+        assert_eq!(decide(&moved, &check), changed);
+        // A manual address sent before
+        let rollback = RecordCheck {
+            published_before: true,
+            ..check
+        };
+        assert_eq!(
+            decide(&moved, &rollback),
+            [
+                Update(UpdateReason::Rollback),
+                Update(UpdateReason::Forced),
+                Update(UpdateReason::Rollback),
+                Update(UpdateReason::Rollback),
+            ]
+        );
+
+        // Published an hour ago, and two days ago
+        let hour = published(chrono::Duration::hours(1));
+        assert_eq!(
+            decide(&hour, &check),
+            [
+                Skip(UpdateReason::Unchanged),
+                Update(UpdateReason::Forced),
+                Skip(UpdateReason::Unchanged),
+                Update(UpdateReason::Changed),
+            ]
+        );
+        assert_eq!(
+            decide(&published(day * 2), &check),
+            [
+                Skip(UpdateReason::Unchanged),
+                Update(UpdateReason::Forced),
+                Update(UpdateReason::Refresh),
+                Update(UpdateReason::Changed),
+            ]
+        );
+
+        // The record holds the address, or was changed outside rusty-dns
+        let holds = RecordCheck {
+            record: Some(ip),
+            ..check
+        };
+        assert_eq!(
+            should_update(&hour, ip, DriftCorrect, &holds),
+            Skip(UpdateReason::Unchanged)
+        );
+        assert_eq!(
+            should_update(&hour, ip, Force, &holds),
+            Update(UpdateReason::Forced)
+        );
+        let drifted = RecordCheck {
+            record: Some(other),
+            ..check
+        };
+        assert_eq!(
+            should_update(&hour, ip, DriftCorrect, &drifted),
+            Update(UpdateReason::Drift)
+        );
+        assert_eq!(
+            should_update(&moved, ip, DriftCorrect, &drifted),
+            Update(UpdateReason::Changed)
+        );
+
+        // Updated seconds ago: held back by the guard, even when forced,
+        // unless it's bypassed
+        let recent = published(chrono::Duration::seconds(10));
+        assert_eq!(
+            decide(&recent, &check),
+            [
+                Skip(UpdateReason::Unchanged),
+                Skip(UpdateReason::RateGuard),
+                Skip(UpdateReason::Unchanged),
+                Skip(UpdateReason::RateGuard),
+            ]
+        );
+        assert_eq!(
+            should_update(
+                &recent,
+                ip,
+                RefreshIfOlderThan(chrono::Duration::zero()),
+                &check
+            ),
+            Skip(UpdateReason::RateGuard)
+        );
+        let bypass = RecordCheck {
+            min_interval: None,
+            ..check
+        };
+        assert_eq!(
+            should_update(&recent, ip, Force, &bypass),
+            Update(UpdateReason::Forced)
+        );
+        assert_eq!(
+            should_update(&recent, ip, DriftCorrect, &bypass),
+            Update(UpdateReason::Changed)
+        );
+
+        // Rate limited by the provider: only forced updates go through
+        let limited = ProviderState {
+            retry_at: Some(now + chrono::Duration::minutes(5)),
+            ..moved.clone()
+        };
+        assert_eq!(
+            decide(&limited, &check),
+            [
+                Skip(UpdateReason::NotEligibleInterval),
+                Update(UpdateReason::Forced),
+                Skip(UpdateReason::NotEligibleInterval),
+                Skip(UpdateReason::NotEligibleInterval),
+            ]
+        );
+        let expired = ProviderState {
+            retry_at: Some(now - chrono::Duration::minutes(5)),
+            ..moved
+        };
+        assert_eq!(decide(&expired, &check), changed);
+
+        assert!(DriftCorrect.reads_record());
+        assert!(!policies[..3].iter().any(UpdatePolicy::reads_record));
+        assert_eq!(
+            Skip(UpdateReason::RateGuard).reason(),
+            UpdateReason::RateGuard
+        );
+    }
+
+    #[test]
+    fn test_ip_version_covers_all_providers() {
+        let detector = IpDetector::new();
+
+        let mut config = Config::default();
+        assert_eq!(Updater::new(&config, &detector).ip_version(), IpVersion::V4);
+
+        config.providers = vec![duckdns(None), duckdns(Some(IpVersion::V6))];
+        assert_eq!(
+            Updater::new(&config, &detector).ip_version(),
+            IpVersion::Both
+        );
+
+        config.providers = vec![duckdns(Some(IpVersion::V6))];
+        assert_eq!(Updater::new(&config, &detector).ip_version(), IpVersion::V6);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_provider_is_skipped() {
+        let config = Config {
+            providers: vec![ProviderConfig {
+                enabled: false,
+                ..duckdns(None)
+            }],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let ips = DetectedIps {
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: None,
+            ..Default::default()
+        };
+
+        let updater = Updater::new(&config, &detector);
+        let updates = updater.update_all(&ips, UpdatePolicy::Force).await;
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Skipped));
+        assert_eq!(updates[0].reason, UpdateReason::Disabled);
+        assert!(updater
+            .update_changed(&ips, &State::default(), UpdatePolicy::OnlyIfChanged)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_family_is_not_attempted() {
+        let config = Config {
+            providers: vec![ProviderConfig {
+                ip_version: Some(IpVersion::V6),
+                ..ProviderConfig::new(ProviderKind::Namecheap {
+                    domain: "example.com".parse().unwrap(),
+                    host: "vpn".parse().unwrap(),
+                    password: "secret".into(),
+                    password_file: None,
+                })
+            }],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let ips = DetectedIps {
+            ipv4: None,
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let updates = Updater::new(&config, &detector)
+            .update_all(&ips, UpdatePolicy::Force)
+            .await;
+
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Unsupported));
+        assert_eq!(updates[0].record_type(), RecordType::Aaaa);
+    }
+
+    fn attempted(ip: IpAddr, error: Option<&str>) -> ProviderUpdate {
+        ProviderUpdate {
+            label: "duckdns-home.duckdns.org".to_string(),
+            provider: "duckdns",
+            domain: "home.duckdns.org".to_string(),
+            ip,
+            reason: UpdateReason::Changed,
+            outcome: UpdateOutcome::Attempted(UpdateResult {
+                provider: "duckdns".to_string(),
+                label: Some("duckdns-home.duckdns.org".to_string()),
+                domain: "home.duckdns.org".to_string(),
+                record_type: RecordType::for_ip(&ip),
+                success: error.is_none(),
+                ip: Some(ip),
+                previous_ip: None,
+                error: error.map(str::to_string),
+                error_kind: error.map(|_| UpdateErrorKind::Other),
+                timestamp: chrono::Utc::now(),
+            }),
+            verified: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_raises_events() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-events-{}", std::process::id()));
+        let mut config = Config {
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        config.daemon.max_consecutive_failures = 2;
+        config.daemon.summary_file = Some(dir.join("summary.json"));
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+
+        let old: IpAddr = "1.2.3.4".parse().unwrap();
+        let new: IpAddr = "5.6.7.8".parse().unwrap();
+        let at = |ip| DetectedIps {
+            ipv4: Some(ip),
+            ipv6: None,
+            ..Default::default()
+        };
+
+        // First detection is not a change
+        let recorded = updater
+            .record(&at(old), &[attempted(old, None)])
+            .await
+            .unwrap();
+        assert!(recorded.events.is_empty());
+        let summary: Summary =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("summary.json")).unwrap())
+                .unwrap();
+        assert_eq!(summary.current_ipv4, Some(old));
+
+        let recorded = updater
+            .record(&at(new), &[attempted(new, Some("KO"))])
+            .await
+            .unwrap();
+        assert_eq!(recorded.events.len(), 1);
+        assert_eq!(recorded.events[0].event, EventKind::IpChanged);
+        assert_eq!(recorded.events[0].old_ip, Some(old));
+        assert!(!recorded.events[0].providers[0].success);
+
+        // Logged whatever the provider outcome
+        let changes = history::load_ip_changes(&history::ip_changes_path_in(&dir)).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old_ip, Some(old));
+        assert_eq!(changes[0].new_ip, new);
+
+        let recorded = updater
+            .record(&at(new), &[attempted(new, Some("KO"))])
+            .await
+            .unwrap();
+        assert_eq!(recorded.events.len(), 1);
+        assert_eq!(recorded.events[0].event, EventKind::UpdateFailed);
+        assert_eq!(recorded.events[0].old_ip, Some(old));
+        assert_eq!(recorded.events[0].error.as_deref(), Some("A: KO"));
+
+        let recorded = updater
+            .record(&at(new), &[attempted(new, None)])
+            .await
+            .unwrap();
+        assert_eq!(recorded.events.len(), 1);
+        assert_eq!(recorded.events[0].event, EventKind::UpdateRecovered);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prepare_once_per_cycle() {
+        let mock = MockProvider::new("home.example").with_record("192.0.2.1".parse().unwrap());
+        let config = Config {
+            ip_version: IpVersion::Both,
+            providers: vec![mock.register("mock-prepare")],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.2".parse().unwrap()),
+            ipv6: Some("2001:db8::2".parse().unwrap()),
+            ..Default::default()
+        };
+        let prepares = || {
+            mock.calls()
+                .iter()
+                .filter(|c| **c == MockCall::Prepare)
+                .count()
+        };
+
+        // Prepared before the first read, and not again for the AAAA record
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert_eq!(updates.len(), 2);
+        assert!(updates.iter().all(|u| u.error().is_none()));
+        assert_eq!(
+            mock.calls()[..2],
+            [MockCall::Prepare, MockCall::GetCurrentIp]
+        );
+        assert_eq!(prepares(), 1);
+        updater.update_all(&ips, UpdatePolicy::Force).await;
+        assert_eq!(prepares(), 2);
+
+        // A failure skips the provider's calls for the cycle
+        mock.push_prepare(Err(DdnsError::Auth {
+            provider: "mock".to_string(),
+            message: "Session expired".to_string(),
+        }));
+        let before = mock.calls().len();
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert_eq!(mock.calls()[before..], [MockCall::Prepare]);
+        for update in &updates {
+            assert!(matches!(update.outcome, UpdateOutcome::Attempted(_)));
+            assert_eq!(update.error_kind(), Some(UpdateErrorKind::Auth));
+            assert_eq!(
+                update.error(),
+                Some("Authentication failed (mock): Session expired")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_login_once_per_cycle() {
+        use crate::providers::{register_provider, YandexCloudAuth, YandexCloudDnsProvider};
+        use serde_json::json;
+        use wiremock::matchers::{method, path, path_regex, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/iam/v1/tokens"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "iamToken": "t1.token",
+                "expiresAt": chrono::Utc::now() + chrono::Duration::hours(12)
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        for (record_type, data) in [("A", "192.0.2.1"), ("AAAA", "2001:db8::1")] {
+            Mock::given(method("GET"))
+                .and(path_regex(":getRecordSet$"))
+                .and(query_param("type", record_type))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "name": "vpn.example.com.", "type": record_type, "ttl": "300", "data": [data]
+                })))
+                .expect(2)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .and(path_regex(":updateRecordSets$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"done": true})))
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        register_provider("yandex-session", move |_| {
+            Ok(Box::new(YandexCloudDnsProvider::with_base_url(
+                YandexCloudAuth::OAuth("y0_oauth".into()),
+                "zone-1".to_string(),
+                "vpn.example.com".parse().unwrap(),
+                None,
+                uri.clone(),
+            )))
+        });
+        let config = Config {
+            ip_version: IpVersion::Both,
+            providers: vec![ProviderConfig::new(ProviderKind::Other {
+                type_name: "yandex-session".to_string(),
+                settings: toml::Table::new(),
+            })],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.2".parse().unwrap()),
+            ipv6: Some("2001:db8::2".parse().unwrap()),
+            ..Default::default()
+        };
+
+        // Two reads and two updates per cycle, on one login
+        for _ in 0..2 {
+            let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+            assert_eq!(updates.len(), 2);
+            assert!(updates.iter().all(|u| u.error().is_none()), "{:?}", updates);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_all_skips_unchanged() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let mock = MockProvider::new("home.example").with_record(ip);
+        let config = Config {
+            providers: vec![mock.register("mock-unchanged")],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ..Default::default()
+        };
+
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Unchanged));
+        assert!(updates[0].verified);
+        assert_eq!(updates[0].label, "mock-unchanged-home.example");
+        assert_eq!(
+            mock.calls(),
+            vec![MockCall::Prepare, MockCall::GetCurrentIp]
+        );
+
+        // Forced updates don't look at the record
+        let updates = updater.update_all(&ips, UpdatePolicy::Force).await;
+        assert_eq!(updates[0].reason, UpdateReason::Forced);
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Attempted(_)));
+        assert!(!updates[0].verified);
+        assert_eq!(updates[0].error(), None);
+        assert_eq!(mock.updated_ips(), vec![ip]);
+
+        // A record that can't be read is updated
+        mock.push_current_ip(Err(DdnsError::Network("timeout".to_string())));
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Attempted(_)));
+        assert_eq!(mock.updated_ips(), vec![ip, ip]);
+    }
+
+    #[tokio::test]
+    async fn test_record_verified() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-verified-{}", std::process::id()));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let mock = MockProvider::new("home.example").with_record(ip);
+        let config = Config {
+            providers: vec![mock.register("mock-verified")],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector).with_guard_bypass(true);
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ..Default::default()
+        };
+        let verified_at = || {
+            history::open(&config)
+                .unwrap()
+                .load_state()
+                .unwrap()
+                .providers["mock-verified-home.example"]
+                .last_verified_at
+        };
+
+        // Sending the address doesn't verify the record...
+        let updates = updater.update_all(&ips, UpdatePolicy::Force).await;
+        updater.record(&ips, &updates).await.unwrap();
+        assert_eq!(verified_at(), None);
+
+        // ...reading it does
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        updater.record(&ips, &updates).await.unwrap();
+        let verified = verified_at().unwrap();
+
+        // A record holding another address isn't verified
+        let moved = DetectedIps {
+            ipv4: Some("192.0.2.2".parse().unwrap()),
+            ..Default::default()
+        };
+        let updates = updater.update_all(&moved, UpdatePolicy::DriftCorrect).await;
+        updater.record(&moved, &updates).await.unwrap();
+        assert_eq!(verified_at(), Some(verified));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_suffix_composed_records() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-suffix-{}", std::process::id()));
+        let router = MockProvider::new("router.example");
+        let nas = MockProvider::new("nas.example");
+        let config = Config {
+            ip_version: IpVersion::V6,
+            providers: vec![
+                router.register("mock-router"),
+                ProviderConfig {
+                    ipv6_suffix: Some("::1234:5678:9abc:def0".parse().unwrap()),
+                    ..nas.register("mock-nas")
+                },
+            ],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+
+        // The delegated prefix wins over the detected address's /64
+        let ips = DetectedIps {
+            ipv6: Some("2001:db8:1200:10::1".parse().unwrap()),
+            ipv6_prefix: Some(Ipv6Prefix::of("2001:db8:1200::".parse().unwrap(), 56)),
+            ..Default::default()
+        };
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert_eq!(router.updated_ips(), vec![ips.ipv6.unwrap()]);
+        assert_eq!(
+            nas.updated_ips(),
+            vec!["2001:db8:1200:0:1234:5678:9abc:def0"
+                .parse::<IpAddr>()
+                .unwrap()]
+        );
+        updater.record(&ips, &updates).await.unwrap();
+        let state = State::load_from(&State::path_in(&dir)).unwrap();
+        assert_eq!(state.last_ipv6_prefix, ips.ipv6_prefix);
+
+        // A new prefix moves every composed record
+        let ips = DetectedIps {
+            ipv6: Some("2001:db8:3400:10::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+        assert_eq!(
+            nas.updated_ips()[1],
+            "2001:db8:3400:10:1234:5678:9abc:def0"
+                .parse::<IpAddr>()
+                .unwrap()
+        );
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+        assert_eq!(recorded.events[0].event, EventKind::IpChanged);
+        assert_eq!(recorded.events[0].providers.len(), 2);
+
+        // Without IPv6 there's no prefix to compose from
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ips.with_suffix(config.providers[1].ipv6_suffix, 64).ipv6,
+            None
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_reasons() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-reasons-{}", std::process::id()));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let mock = MockProvider::new("home.example").with_record(ip);
+        let config = Config {
+            providers: vec![mock.register("mock-reasons")],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let detected = Updater::new(&config, &detector);
+        let manual = Updater::new(&config, &detector).with_source(IpSource::Manual);
+        async fn run(updater: &Updater<'_>, ip: IpAddr) -> UpdateReason {
+            let ips = DetectedIps {
+                ipv4: Some(ip),
+                ..Default::default()
+            };
+            let updates = updater.update_all(&ips, UpdatePolicy::DriftCorrect).await;
+            updater.record(&ips, &updates).await.unwrap();
+            updates[0].reason
+        }
+
+        assert_eq!(run(&detected, ip).await, UpdateReason::Unchanged);
+        // The record was changed elsewhere after being confirmed
+        mock.push_current_ip(Ok(Some(other)));
+        assert_eq!(run(&detected, ip).await, UpdateReason::Drift);
+        assert_eq!(run(&manual, other).await, UpdateReason::Changed);
+        // Back to an address published before
+        assert_eq!(run(&manual, ip).await, UpdateReason::Rollback);
+
+        let entries = history::load_from(&history::path_in(&dir)).unwrap();
+        let reasons: Vec<_> = entries.iter().map(|e| e.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                UpdateReason::Unchanged,
+                UpdateReason::Drift,
+                UpdateReason::Changed,
+                UpdateReason::Rollback
+            ]
+        );
+        assert!(entries[0].is_skip() && entries[0].result.success);
+        assert_eq!(entries[0].result.ip, Some(ip));
+        assert_eq!(entries[0].outcome(), "SKIPPED (unchanged)");
+        assert_eq!(entries[3].outcome(), "OK (rollback, manual)");
+        assert_eq!(mock.updated_ips(), vec![ip, other, ip]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rate_guard() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-guard-{}", std::process::id()));
+        let guarded = MockProvider::new("a.example");
+        let unguarded = MockProvider::new("b.example");
+        let config = Config {
+            providers: vec![
+                guarded.register("mock-guard-on"),
+                ProviderConfig {
+                    min_update_interval_secs: Some(0),
+                    ..unguarded.register("mock-guard-off")
+                },
+            ],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ..Default::default()
+        };
+        async fn run(updater: &Updater<'_>, ips: &DetectedIps) -> Vec<ProviderUpdate> {
+            let updates = updater.update_all(ips, UpdatePolicy::Force).await;
+            updater.record(ips, &updates).await.unwrap();
+            updates
+        }
+
+        let updater = Updater::new(&config, &detector);
+        let updates = run(&updater, &ips).await;
+        assert!(updates
+            .iter()
+            .all(|u| matches!(u.outcome, UpdateOutcome::Attempted(_))));
+
+        // Sent again right away only where the guard is disabled
+        let updates = run(&updater, &ips).await;
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Skipped));
+        assert_eq!(updates[0].reason, UpdateReason::RateGuard);
+        assert_eq!(updates[1].reason, UpdateReason::Forced);
+        assert_eq!(guarded.updated_ips(), vec![ip]);
+        assert_eq!(unguarded.updated_ips(), vec![ip, ip]);
+
+        // Another address isn't held back
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let updates = run(
+            &updater,
+            &DetectedIps {
+                ipv4: Some(other),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(updates[0].reason, UpdateReason::Forced);
+
+        let bypass = Updater::new(&config, &detector).with_guard_bypass(true);
+        run(&bypass, &ips).await;
+        run(&bypass, &ips).await;
+        assert_eq!(guarded.updated_ips(), vec![ip, other, ip, ip]);
+
+        let entries = history::load_from(&history::path_in(&dir)).unwrap();
+        assert_eq!(entries[2].outcome(), "SKIPPED (rate_guard)");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stagger_spacing() {
+        let stagger = Stagger {
+            spacing: Duration::from_secs(10),
+            within: Duration::from_secs(60),
+        };
+        assert_eq!(stagger.spacing_for(1), Duration::from_secs(10));
+        assert_eq!(stagger.spacing_for(4), Duration::from_secs(10));
+        // Squeezed to start the last one in time
+        assert_eq!(stagger.spacing_for(9), Duration::from_millis(7500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_staggered_updates() {
+        let mocks: Vec<_> = ["a.example", "b.example", "c.example"]
+            .into_iter()
+            .map(MockProvider::new)
+            .collect();
+        for mock in &mocks {
+            mock.set_delay(Duration::from_secs(1));
+        }
+        let config = Config {
+            providers: vec![
+                mocks[0].register("mock-stagger-a"),
+                mocks[1].register("mock-stagger-b"),
+                mocks[2].register("mock-stagger-c"),
+            ],
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let reported = std::sync::Mutex::new(Vec::new());
+        let progress = |update: &ProviderUpdate| {
+            reported
+                .lock()
+                .unwrap()
+                .push((update.label.clone(), Instant::now()));
+        };
+        let updater = Updater::new(&config, &detector)
+            .with_progress(&progress)
+            .with_stagger(Stagger {
+                spacing: Duration::from_secs(30),
+                within: Duration::from_secs(40),
+            });
+        let ips = DetectedIps {
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let updates = updater
+            .update_changed(&ips, &State::default(), UpdatePolicy::OnlyIfChanged)
+            .await;
+        assert_eq!(updates.len(), 3);
+
+        // Each reported when done, spaced so the last starts within 40s
+        let reported = reported.into_inner().unwrap();
+        let times: Vec<_> = reported.iter().map(|(_, at)| *at - started).collect();
+        assert_eq!(
+            times,
+            [1, 21, 41].map(Duration::from_secs),
+            "{:?}",
+            reported
+        );
+        let mut labels: Vec<_> = reported.into_iter().map(|(label, _)| label).collect();
+        labels.sort();
+        assert_eq!(
+            labels,
+            [
+                "mock-stagger-a-a.example",
+                "mock-stagger-b-b.example",
+                "mock-stagger-c-c.example"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-partial-{}", std::process::id()));
+        let working = MockProvider::new("a.example");
+        let failing = MockProvider::new("b.example");
+        failing.push_update(MockUpdate::Fail("KO".to_string()));
+        failing.push_update(MockUpdate::Error(DdnsError::Network("timeout".to_string())));
+        let config = Config {
+            providers: vec![
+                working.register("mock-partial-ok"),
+                failing.register("mock-partial-ko"),
+            ],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ..Default::default()
+        };
+
+        let updates = updater
+            .update_changed(&ips, &State::default(), UpdatePolicy::OnlyIfChanged)
+            .await;
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].error(), None);
+        assert_eq!(updates[1].error(), Some("KO"));
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+        assert_eq!(working.record(), Some(ip));
+        assert_eq!(failing.record(), None);
+
+        // Only the failed record is retried
+        let updates = updater
+            .update_changed(&ips, &recorded.state, UpdatePolicy::OnlyIfChanged)
+            .await;
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].label, "mock-partial-ko-b.example");
+        assert_eq!(
+            updates[0].error(),
+            Some("Network error: timeout"),
+            "{:?}",
+            updates[0]
+        );
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+        assert_eq!(
+            recorded.state.providers["mock-partial-ko-b.example"].consecutive_failures,
+            2
+        );
+        assert_eq!(working.updated_ips(), vec![ip]);
+        assert_eq!(failing.updated_ips(), vec![ip, ip]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_failover() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-group-{}", std::process::id()));
+        let primary = MockProvider::new("a.example");
+        let fallback = MockProvider::new("b.example");
+        let grouped = |mock: &MockProvider, type_name| ProviderConfig {
+            group: Some("home".to_string()),
+            ..mock.register(type_name)
+        };
+        let mut config = Config {
+            providers: vec![
+                grouped(&primary, "mock-group-primary"),
+                grouped(&fallback, "mock-group-fallback"),
+            ],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        config.groups.insert(
+            "home".to_string(),
+            crate::config::GroupConfig {
+                policy: crate::config::GroupPolicy::PrimaryFailover,
+            },
+        );
+        config.daemon.max_consecutive_failures = 1;
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let at = |ip: &str| DetectedIps {
+            ipv4: Some(ip.parse().unwrap()),
+            ..Default::default()
+        };
+
+        // A failing fallback is updated but not alerted on
+        fallback.push_update(MockUpdate::Fail("KO".to_string()));
+        let ips = at("192.0.2.1");
+        let updates = updater
+            .update_changed(&ips, &State::default(), UpdatePolicy::OnlyIfChanged)
+            .await;
+        let (outcomes, groups) = updater.outcomes(&updates);
+        assert_eq!(outcomes[1].group.as_deref(), Some("home"));
+        assert!(!outcomes[1].success);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].success);
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+        assert!(recorded.escalated.is_empty());
+        assert!(recorded.events.is_empty());
+        let state = &recorded.state.providers["mock-group-fallback-b.example"];
+        assert_eq!(state.consecutive_failures, 1);
+        assert!(!state.alerted);
+
+        // A failing primary fails the group and is alerted on
+        primary.push_update(MockUpdate::Fail("KO".to_string()));
+        let ips = at("192.0.2.2");
+        let updates = updater
+            .update_changed(&ips, &recorded.state, UpdatePolicy::OnlyIfChanged)
+            .await;
+        let (_, groups) = updater.outcomes(&updates);
+        assert!(!groups[0].success);
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+        assert_eq!(recorded.escalated, vec!["mock-group-primary-a.example"]);
+        let changed = &recorded.events[0];
+        assert_eq!(changed.event, EventKind::IpChanged);
+        assert!(!changed.success());
+        assert!(changed
+            .message()
+            .ends_with("Group home (primary-failover): FAILED"));
+        assert_eq!(recorded.events[1].event, EventKind::UpdateFailed);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_kinds() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-kinds-{}", std::process::id()));
+        let denied = MockProvider::new("a.example");
+        let limited = MockProvider::new("b.example");
+        denied.push_update(MockUpdate::FailWith(
+            UpdateErrorKind::Auth,
+            "Invalid token".to_string(),
+        ));
+        limited.push_update(MockUpdate::FailWith(
+            UpdateErrorKind::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(3600)),
+            },
+            "Slow down".to_string(),
+        ));
+        let mut config = Config {
+            providers: vec![
+                denied.register("mock-kinds-denied"),
+                limited.register("mock-kinds-limited"),
+            ],
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        config.daemon.max_consecutive_failures = 3;
+        let detector = IpDetector::new();
+        let updater = Updater::new(&config, &detector);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ..Default::default()
+        };
+
+        let updates = updater
+            .update_changed(&ips, &State::default(), UpdatePolicy::OnlyIfChanged)
+            .await;
+        assert_eq!(updates[0].error_kind(), Some(UpdateErrorKind::Auth));
+        let recorded = updater.record(&ips, &updates).await.unwrap();
+
+        // Rejected credentials are escalated without waiting for a streak
+        assert_eq!(recorded.escalated, vec!["mock-kinds-denied-a.example"]);
+        assert_eq!(recorded.events[0].event, EventKind::UpdateFailed);
+
+        // The rate-limited provider is left alone until the delay passes
+        let updates = updater
+            .update_changed(&ips, &recorded.state, UpdatePolicy::OnlyIfChanged)
+            .await;
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].label, "mock-kinds-denied-a.example");
+        assert!(matches!(updates[0].outcome, UpdateOutcome::Attempted(_)));
+        assert_eq!(updates[1].label, "mock-kinds-limited-b.example");
+        assert!(matches!(updates[1].outcome, UpdateOutcome::Skipped));
+        assert_eq!(updates[1].reason, UpdateReason::NotEligibleInterval);
+        assert_eq!(limited.updated_ips(), vec![ip]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_manual_source() {
+        let dir = std::env::temp_dir().join(format!("rusty-dns-manual-{}", std::process::id()));
+        let config = Config {
+            state_dir: Some(dir.clone()),
+            ..Config::default()
+        };
+        let detector = IpDetector::new();
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let ips = DetectedIps {
+            ipv4: Some(ip),
+            ipv6: None,
+            ..Default::default()
+        };
+
+        let recorded = Updater::new(&config, &detector)
+            .with_source(IpSource::Manual)
+            .record(&ips, &[attempted(ip, None)])
+            .await
+            .unwrap();
+
+        // Published, but not taken as this host's address
+        assert_eq!(recorded.state.last_ip(&ip), None);
+        let published = &recorded.state.providers["duckdns-home.duckdns.org"];
+        assert_eq!(published.last_ipv4, Some(ip));
+
+        let entries = history::load_from(&history::path_in(&dir)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, IpSource::Manual);
+        let line = std::fs::read_to_string(history::path_in(&dir)).unwrap();
+        assert!(line.contains(r#""source":"manual""#));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
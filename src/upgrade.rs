@@ -0,0 +1,264 @@
+//! Restarting the daemon into a new binary (`daemon --reexec-on-upgrade`).
+//!
+//! The daemon polls its own executable between cycles. Once a changed
+//! binary has stayed the same for a poll, it is executed in place of the
+//! running process with the same arguments, so deployments don't have to
+//! restart the service. Windows can't replace a running process, so it
+//! only warns that a restart is required.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time between checks of the binary.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shortest time between two re-executions, so a binary that keeps
+/// changing can't restart the daemon in a loop.
+pub const MIN_REEXEC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Environment variable telling a re-executed daemon when it was
+/// re-executed, in Unix seconds, so the rate limit holds across processes.
+pub const REEXEC_AT_ENV: &str = "RUSTY_DNS_REEXEC_AT";
+
+/// What identifies a version of the binary on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryStamp {
+    /// Modification time.
+    pub modified: Option<SystemTime>,
+    /// Size in bytes.
+    pub len: u64,
+    /// Inode number, changed when the file is replaced rather than
+    /// rewritten (Unix only).
+    pub inode: Option<u64>,
+}
+
+impl BinaryStamp {
+    /// Read the stamp of the file at `path`, following symlinks; `None` if
+    /// it can't be read, e.g. while it is being replaced.
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            Some(metadata.ino())
+        };
+        #[cfg(not(unix))]
+        let inode = None;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode,
+        })
+    }
+}
+
+/// Outcome of a check of the binary (see [`BinaryWatch::check`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeCheck {
+    /// The binary is the one running.
+    Unchanged,
+    /// The binary changed since the last check; it may still be being
+    /// written.
+    Settling,
+    /// The binary changed, but the daemon was re-executed too recently;
+    /// it is re-executed once this time has passed.
+    Deferred(SystemTime),
+    /// The binary changed and has settled: re-execute it.
+    Reexec,
+}
+
+/// Watches the binary of the running process for upgrades.
+#[derive(Debug, Clone)]
+pub struct BinaryWatch {
+    path: PathBuf,
+    /// Stamp of the running binary.
+    running: Option<BinaryStamp>,
+    /// Stamp of a changed binary seen at the last check.
+    pending: Option<BinaryStamp>,
+    last_reexec: Option<SystemTime>,
+}
+
+impl BinaryWatch {
+    /// Watch the binary at `path`, whose current version is the one running
+    /// and which was last re-executed at `last_reexec`, if ever.
+    pub fn new(path: PathBuf, last_reexec: Option<SystemTime>) -> Self {
+        Self {
+            running: BinaryStamp::of(&path),
+            path,
+            pending: None,
+            last_reexec,
+        }
+    }
+
+    /// Watch the binary of the running process, taking when it was
+    /// re-executed from [`REEXEC_AT_ENV`].
+    pub fn current() -> std::io::Result<Self> {
+        let last_reexec = std::env::var(REEXEC_AT_ENV)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        Ok(Self::new(std::env::current_exe()?, last_reexec))
+    }
+
+    /// Get the path of the watched binary.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Decide what to do given the binary's `current` stamp at `now`.
+    ///
+    /// A changed binary is only re-executed once it has the same stamp at
+    /// two checks in a row, so a file still being written is left alone,
+    /// and at most once per [`MIN_REEXEC_INTERVAL`]. A binary that can't be
+    /// read is waited for.
+    pub fn check(&mut self, current: Option<BinaryStamp>, now: SystemTime) -> UpgradeCheck {
+        let Some(current) = current else {
+            return UpgradeCheck::Settling;
+        };
+        if Some(current) == self.running {
+            self.pending = None;
+            return UpgradeCheck::Unchanged;
+        }
+        if self.pending != Some(current) {
+            self.pending = Some(current);
+            return UpgradeCheck::Settling;
+        }
+
+        if let Some(last) = self.last_reexec {
+            let allowed = last + MIN_REEXEC_INTERVAL;
+            if now < allowed {
+                return UpgradeCheck::Deferred(allowed);
+            }
+        }
+        // Once per version, even if re-executing it fails
+        self.running = Some(current);
+        self.pending = None;
+        self.last_reexec = Some(now);
+        UpgradeCheck::Reexec
+    }
+
+    /// Check the binary on disk now (see [`BinaryWatch::check`]).
+    pub fn poll(&mut self) -> UpgradeCheck {
+        let current = BinaryStamp::of(&self.path);
+        self.check(current, SystemTime::now())
+    }
+}
+
+/// Replace the running process with the binary at `path`, passing the
+/// arguments of this process. Only returns if that fails.
+#[cfg(unix)]
+pub fn reexec(path: &Path) -> std::io::Error {
+    use std::io::Write;
+    use std::os::unix::process::CommandExt;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Nothing buffered is written once the process is replaced
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+
+    let mut args = std::env::args_os();
+    let mut command = std::process::Command::new(path);
+    if let Some(arg0) = args.next() {
+        command.arg0(arg0);
+    }
+    command
+        .args(args)
+        .env(REEXEC_AT_ENV, now.to_string())
+        .exec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(modified: u64, inode: u64) -> Option<BinaryStamp> {
+        Some(BinaryStamp {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+            len: 1024,
+            inode: Some(inode),
+        })
+    }
+
+    fn watch(last_reexec: Option<SystemTime>) -> BinaryWatch {
+        BinaryWatch {
+            path: PathBuf::from("/usr/bin/rusty-dns"),
+            running: stamp(100, 1),
+            pending: None,
+            last_reexec,
+        }
+    }
+
+    #[test]
+    fn test_reexec_once_settled() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let mut watch = watch(None);
+        assert_eq!(watch.check(stamp(100, 1), now), UpgradeCheck::Unchanged);
+
+        // Still being written: the stamp changes between checks
+        assert_eq!(watch.check(stamp(200, 1), now), UpgradeCheck::Settling);
+        assert_eq!(watch.check(stamp(210, 1), now), UpgradeCheck::Settling);
+        // Missing while replaced
+        assert_eq!(watch.check(None, now), UpgradeCheck::Settling);
+        assert_eq!(watch.check(stamp(210, 2), now), UpgradeCheck::Settling);
+        assert_eq!(watch.check(stamp(210, 2), now), UpgradeCheck::Reexec);
+
+        // Not again for the same binary, even if re-executing it failed
+        assert_eq!(watch.check(stamp(210, 2), now), UpgradeCheck::Unchanged);
+    }
+
+    #[test]
+    fn test_reverted_binary_is_unchanged() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let mut watch = watch(None);
+        assert_eq!(watch.check(stamp(200, 2), now), UpgradeCheck::Settling);
+        assert_eq!(watch.check(stamp(100, 1), now), UpgradeCheck::Unchanged);
+        assert_eq!(watch.check(stamp(200, 2), now), UpgradeCheck::Settling);
+    }
+
+    #[test]
+    fn test_reexec_rate_limited() {
+        let reexec = UNIX_EPOCH + Duration::from_secs(10_000);
+        let allowed = reexec + MIN_REEXEC_INTERVAL;
+        let mut watch = watch(Some(reexec));
+
+        let soon = reexec + Duration::from_secs(60);
+        assert_eq!(watch.check(stamp(200, 2), soon), UpgradeCheck::Settling);
+        assert_eq!(
+            watch.check(stamp(200, 2), soon),
+            UpgradeCheck::Deferred(allowed)
+        );
+        assert_eq!(watch.check(stamp(200, 2), allowed), UpgradeCheck::Reexec);
+
+        // The next upgrade waits for the interval again
+        let later = allowed + Duration::from_secs(10);
+        assert_eq!(watch.check(stamp(300, 3), later), UpgradeCheck::Settling);
+        assert_eq!(
+            watch.check(stamp(300, 3), later),
+            UpgradeCheck::Deferred(allowed + MIN_REEXEC_INTERVAL)
+        );
+    }
+
+    #[test]
+    fn test_stamp_of_file() {
+        let path = std::env::temp_dir().join(format!("rusty-dns-binary-{}", std::process::id()));
+        assert_eq!(BinaryStamp::of(&path), None);
+
+        std::fs::write(&path, b"v1").unwrap();
+        let v1 = BinaryStamp::of(&path).unwrap();
+        assert_eq!(v1.len, 2);
+
+        // Replaced by a new file, as package managers do
+        let new = path.with_extension("new");
+        std::fs::write(&new, b"v2").unwrap();
+        std::fs::rename(&new, &path).unwrap();
+        let v2 = BinaryStamp::of(&path).unwrap();
+        if cfg!(unix) {
+            assert_ne!(v1, v2);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}